@@ -11,7 +11,8 @@ CREATE TABLE IF NOT EXISTS sources (
     description TEXT,
     tags TEXT NOT NULL,
     created_at TEXT NOT NULL,
-    updated_at TEXT NOT NULL
+    updated_at TEXT NOT NULL,
+    owner TEXT -- principal that created the source; see auth.enforce_ownership
 );
 
 -- Flows table  
@@ -36,7 +37,11 @@ CREATE TABLE IF NOT EXISTS flows (
     available_timerange TEXT,
     created_at TEXT NOT NULL,
     updated_at TEXT NOT NULL,
-    FOREIGN KEY (source_id) REFERENCES sources (id) ON DELETE SET NULL
+    retention_seconds INTEGER, -- segments ending longer ago than this are expired
+    notify_url TEXT, -- callback for this flow's own events, signed with notify_secret
+    notify_secret TEXT,
+    owner TEXT, -- principal that created the flow, or was given it; see auth.enforce_ownership
+    FOREIGN KEY (source_id) REFERENCES sources (id) ON DELETE RESTRICT
 );
 
 -- Flow segments table
@@ -51,6 +56,9 @@ CREATE TABLE IF NOT EXISTS flow_segments (
     key_frame_count INTEGER,
     get_urls TEXT,
     created_at TEXT NOT NULL,
+    essence_parameters TEXT,
+    start_ns INTEGER, -- timerange bounds in nanoseconds since the epoch, for range queries
+    end_ns INTEGER,
     PRIMARY KEY (flow_id, object_id, timerange),
     FOREIGN KEY (flow_id) REFERENCES flows (id) ON DELETE CASCADE
 );
@@ -62,7 +70,24 @@ CREATE TABLE IF NOT EXISTS media_objects (
     size_bytes INTEGER,
     mime_type TEXT,
     flow_references TEXT NOT NULL,
-    created_at TEXT NOT NULL
+    created_at TEXT NOT NULL,
+    metadata TEXT,
+    storage_class TEXT,
+    format TEXT, -- JSON-encoded format of the flow the bytes were uploaded for
+    content_hash TEXT -- hex SHA-256 of the stored bytes, served as their ETag
+);
+
+-- Flow stats table
+-- Per-flow segment statistics, kept up to date by triggers on flow_segments and
+-- media_objects (see Database::migrate) so reading them doesn't scan segments.
+-- total_bytes sums the size of each segment's object, once per segment.
+CREATE TABLE IF NOT EXISTS flow_stats (
+    flow_id TEXT PRIMARY KEY,
+    segment_count INTEGER NOT NULL DEFAULT 0,
+    total_bytes INTEGER NOT NULL DEFAULT 0,
+    first_start_ns INTEGER, -- earliest segment start, nanoseconds since the epoch
+    last_end_ns INTEGER, -- latest segment end
+    FOREIGN KEY (flow_id) REFERENCES flows (id) ON DELETE CASCADE
 );
 
 -- Webhooks table
@@ -71,7 +96,11 @@ CREATE TABLE IF NOT EXISTS webhooks (
     url TEXT PRIMARY KEY,
     api_key_name TEXT,
     api_key_value TEXT,
-    events TEXT NOT NULL
+    events TEXT NOT NULL,
+    id TEXT,
+    previous_api_key_value TEXT,
+    previous_key_expires_at TEXT,
+    owner TEXT -- principal whose flows' events it hears, when registered under auth.enforce_ownership
 );
 
 -- Deletion requests table
@@ -83,6 +112,62 @@ CREATE TABLE IF NOT EXISTS deletion_requests (
     status TEXT NOT NULL,
     progress TEXT,
     created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    owner TEXT -- owner of the flow, kept once the flow is deleted; see auth.enforce_ownership
+);
+
+-- Ingest pauses table
+-- One row per paused scope: "service" for the global switch, otherwise a flow id
+CREATE TABLE IF NOT EXISTS ingest_pauses (
+    scope TEXT PRIMARY KEY,
+    paused_at TEXT NOT NULL
+);
+
+-- Storage allocations table
+-- One row per allocated object id; the primary key makes concurrent allocations
+-- of the same explicit object_id resolve to a single winner
+CREATE TABLE IF NOT EXISTS storage_allocations (
+    object_id TEXT PRIMARY KEY,
+    flow_id TEXT,
+    put_url TEXT NOT NULL,
+    media_store TEXT,
+    storage_class TEXT,
+    expires_at TEXT,
+    created_at TEXT NOT NULL
+);
+
+-- Jobs table
+-- Durable background work. Claiming a job moves run_at to the end of its lease,
+-- so a job whose worker died before completing it becomes due again.
+CREATE TABLE IF NOT EXISTS jobs (
+    id TEXT PRIMARY KEY,
+    kind TEXT NOT NULL,
+    target TEXT,
+    payload TEXT NOT NULL,
+    status TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    run_at TEXT NOT NULL,
+    last_error TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+-- Schema migrations table
+-- One row per schema version a binary has migrated the database to. The
+-- newest row's min_compatible_version is the oldest schema version whose
+-- binaries may still run against the database.
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version INTEGER PRIMARY KEY,
+    min_compatible_version INTEGER NOT NULL,
+    binary_version TEXT NOT NULL, -- version of the binary that applied it
+    applied_at TEXT NOT NULL
+);
+
+-- Service settings table
+-- Runtime overrides made through the admin API, one JSON value per setting
+CREATE TABLE IF NOT EXISTS service_settings (
+    name TEXT PRIMARY KEY,
+    value TEXT NOT NULL,
     updated_at TEXT NOT NULL
 );
 
@@ -91,11 +176,14 @@ CREATE TABLE IF NOT EXISTS deletion_requests (
 -- Sources indexes
 CREATE INDEX IF NOT EXISTS idx_sources_format ON sources(format);
 CREATE INDEX IF NOT EXISTS idx_sources_created_at ON sources(created_at);
+CREATE INDEX IF NOT EXISTS idx_sources_page ON sources(created_at, id);
 
 -- Flows indexes  
 CREATE INDEX IF NOT EXISTS idx_flows_source_id ON flows(source_id);
 CREATE INDEX IF NOT EXISTS idx_flows_format ON flows(format);
 CREATE INDEX IF NOT EXISTS idx_flows_created_at ON flows(created_at);
+CREATE INDEX IF NOT EXISTS idx_flows_page ON flows(created_at, id);
+CREATE INDEX IF NOT EXISTS idx_flows_source_page ON flows(source_id, created_at, id);
 CREATE INDEX IF NOT EXISTS idx_flows_codec ON flows(codec);
 
 -- Flow segments indexes
@@ -112,6 +200,9 @@ CREATE INDEX IF NOT EXISTS idx_deletion_requests_status ON deletion_requests(sta
 CREATE INDEX IF NOT EXISTS idx_deletion_requests_flow_id ON deletion_requests(flow_id);
 CREATE INDEX IF NOT EXISTS idx_deletion_requests_created_at ON deletion_requests(created_at);
 
+-- Jobs indexes
+CREATE INDEX IF NOT EXISTS idx_jobs_due ON jobs(kind, status, run_at);
+
 -- Insert default service information (optional)
 -- You can uncomment and modify these if you want to pre-populate data
 
@@ -0,0 +1,155 @@
+//! Tar archives of a flow's segment objects, for `GET /flows/:id/segments/archive`.
+//!
+//! Only the pieces the handler streams are built here: a header per entry,
+//! the padding after its bytes, and the end-of-archive marker. Object bytes
+//! are never held in memory; the handler streams them from storage between
+//! the header and the padding.
+
+use crate::error::{TamsError, TamsResult};
+use serde::Serialize;
+use uuid::Uuid;
+
+const BLOCK_SIZE: u64 = 512;
+/// Longest name a ustar header holds without a PAX extended header
+const USTAR_NAME_LEN: usize = 100;
+/// Largest entry an 11-digit octal size field can describe
+const MAX_ENTRY_SIZE: u64 = 0o77777777777;
+/// Name of the entry describing the archive's segments, always written first
+pub const MANIFEST_ENTRY: &str = "manifest.json";
+/// Two zero blocks end an archive
+pub const END_OF_ARCHIVE: [u8; 2 * BLOCK_SIZE as usize] = [0; 2 * BLOCK_SIZE as usize];
+
+/// The `manifest.json` entry, listing the segments in timeline order
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveManifest {
+    pub flow_id: Uuid,
+    pub segments: Vec<ArchiveManifestSegment>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveManifestSegment {
+    pub object_id: String,
+    pub timerange: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ts_offset: Option<String>,
+    /// Name of the object's entry; None when the object is missing
+    pub entry: Option<String>,
+    /// Set when the object was missing and `allow_missing=true` let the archive go ahead
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub missing: bool,
+}
+
+/// Entry name for a segment's object, `<start_ts>_<object_id>`
+pub fn entry_name(start: &str, object_id: &str) -> String {
+    format!("{}_{}", start, object_id)
+}
+
+/// Zero bytes that round an entry of `size` bytes up to a whole block
+pub fn padding(size: u64) -> Vec<u8> {
+    vec![0; ((BLOCK_SIZE - size % BLOCK_SIZE) % BLOCK_SIZE) as usize]
+}
+
+/// Bytes an entry of `size` bytes named `name` takes, headers and padding included
+pub fn entry_len(name: &str, size: u64) -> u64 {
+    let header_len = header(name, size, 0).map(|header| header.len() as u64).unwrap_or(BLOCK_SIZE);
+    header_len + size + padding(size).len() as u64
+}
+
+/// Header block(s) for a regular file entry. Names too long for ustar are
+/// carried in a PAX extended header ahead of it.
+pub fn header(name: &str, size: u64, mtime: i64) -> TamsResult<Vec<u8>> {
+    if size > MAX_ENTRY_SIZE {
+        return Err(TamsError::Internal(format!("Archive entry {} is too large for tar ({} bytes)", name, size)));
+    }
+    if name.len() <= USTAR_NAME_LEN {
+        return Ok(ustar_block(name, size, mtime, b'0').to_vec());
+    }
+
+    // A PAX record is "<length> path=<name>\n", its length counting its own digits
+    let record_len = |digits: usize| digits + " path=\n".len() + name.len();
+    let mut digits = record_len(0).to_string().len();
+    while record_len(digits).to_string().len() != digits {
+        digits += 1;
+    }
+    let record = format!("{} path={}\n", record_len(digits), name);
+
+    let short_name: String = name.chars().take(USTAR_NAME_LEN / 2).collect();
+    let mut blocks = ustar_block(&format!("PaxHeader/{}", short_name), record.len() as u64, mtime, b'x').to_vec();
+    blocks.extend_from_slice(record.as_bytes());
+    blocks.extend(padding(record.len() as u64));
+    blocks.extend_from_slice(&ustar_block(&short_name, size, mtime, b'0'));
+    Ok(blocks)
+}
+
+fn ustar_block(name: &str, size: u64, mtime: i64, typeflag: u8) -> [u8; BLOCK_SIZE as usize] {
+    let mut block = [0u8; BLOCK_SIZE as usize];
+    let mut put = |offset: usize, value: &[u8]| block[offset..offset + value.len()].copy_from_slice(value);
+    let name = &name.as_bytes()[..name.len().min(USTAR_NAME_LEN)];
+    put(0, name);
+    put(100, b"0000644\0");
+    put(108, b"0000000\0");
+    put(116, b"0000000\0");
+    put(124, format!("{:011o}\0", size).as_bytes());
+    put(136, format!("{:011o}\0", mtime.max(0)).as_bytes());
+    put(148, b"        ");
+    put(156, &[typeflag]);
+    put(257, b"ustar\0");
+    put(263, b"00");
+
+    let checksum: u32 = block.iter().map(|&b| b as u32).sum();
+    block[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    block
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// (name, bytes) of each file in a tar archive, following PAX path records
+    pub(crate) fn read_entries(archive: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let octal = |field: &[u8]| {
+            let text = std::str::from_utf8(field).unwrap().trim_matches(|c: char| c == '\0' || c == ' ');
+            u64::from_str_radix(text, 8).unwrap()
+        };
+        let mut entries = Vec::new();
+        let mut long_name = None;
+        let mut position = 0;
+        while archive[position..position + 512].iter().any(|&b| b != 0) {
+            let block = &archive[position..position + 512];
+            let stored: u32 = octal(&block[148..156]) as u32;
+            let computed: u32 = block.iter().enumerate().map(|(i, &b)| if (148..156).contains(&i) { 32 } else { b as u32 }).sum();
+            assert_eq!(stored, computed, "header checksum");
+            let size = octal(&block[124..136]) as usize;
+            let data = archive[position + 512..position + 512 + size].to_vec();
+            position += 512 + size.div_ceil(512) * 512;
+            if block[156] == b'x' {
+                let record = String::from_utf8(data).unwrap();
+                long_name = Some(record.split_once("path=").unwrap().1.trim_end_matches('\n').to_string());
+                continue;
+            }
+            let name = String::from_utf8(block[..100].iter().copied().take_while(|&b| b != 0).collect()).unwrap();
+            entries.push((long_name.take().unwrap_or(name), data));
+        }
+        assert_eq!(archive.len(), position + 1024);
+        entries
+    }
+
+    #[test]
+    fn test_headers_round_trip_short_and_long_names() {
+        let long_name = entry_name("1694429247:40000000", &"x".repeat(150));
+        let mut archive = Vec::new();
+        for (name, data) in [("manifest.json", b"{}".as_slice()), (long_name.as_str(), b"object bytes".as_slice())] {
+            let header = header(name, data.len() as u64, 1_700_000_000).unwrap();
+            assert_eq!(header.len() as u64 + data.len() as u64 + padding(data.len() as u64).len() as u64, entry_len(name, data.len() as u64));
+            archive.extend(header);
+            archive.extend_from_slice(data);
+            archive.extend(padding(data.len() as u64));
+        }
+        archive.extend_from_slice(&END_OF_ARCHIVE);
+
+        let entries = read_entries(&archive);
+        assert_eq!(entries[0], ("manifest.json".to_string(), b"{}".to_vec()));
+        assert_eq!(entries[1], (long_name, b"object bytes".to_vec()));
+        assert!(padding(1024).is_empty());
+    }
+}
@@ -1,14 +1,18 @@
-use crate::{config::AuthConfig, error::TamsError};
+use crate::{
+    config::{AuthConfig, CorsConfig},
+    error::{TamsError, TamsResult},
+};
 use axum::{
     extract::{Request, State},
-    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    http::{header::AUTHORIZATION, HeaderMap, HeaderName, HeaderValue, Method},
     middleware::Next,
     response::Response,
 };
 use base64::prelude::*;
 use jsonwebtoken::{decode, DecodingKey, Validation};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+use tower_http::cors::{AllowHeaders, AllowOrigin, Any, CorsLayer};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -17,6 +21,41 @@ pub struct Claims {
     pub iat: usize,  // Issued at
 }
 
+/// Who made an authenticated request: the Basic auth username or the JWT
+/// subject. `auth_middleware` adds it to the request's extensions; requests
+/// let through without credentials carry none.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principal {
+    pub name: String,
+    /// Listed in `auth.admin_principals`, so exempt from ownership
+    pub admin: bool,
+}
+
+impl Principal {
+    pub fn new(name: &str, config: &AuthConfig) -> Self {
+        Principal {
+            name: name.to_string(),
+            admin: config.admin_principals.iter().any(|admin| admin == name),
+        }
+    }
+
+    /// Whether this principal may see and change a resource with the given
+    /// owner. Resources without one predate ownership and are left to admins.
+    pub fn can_access(&self, owner: Option<&str>) -> bool {
+        self.admin || owner == Some(self.name.as_str())
+    }
+}
+
+/// Refuse admin-only routes to principals not in `auth.admin_principals` with
+/// 403. Without `auth.require_auth` requests carry no principal and pass, as
+/// they do everywhere else.
+pub async fn require_admin_middleware(request: Request, next: Next) -> Result<Response, TamsError> {
+    if let Some(principal) = request.extensions().get::<Principal>().filter(|principal| !principal.admin) {
+        return Err(TamsError::Forbidden(format!("{} is not an admin", principal.name)));
+    }
+    Ok(next.run(request).await)
+}
+
 pub struct AuthState {
     pub config: AuthConfig,
     pub decoding_key: DecodingKey,
@@ -35,7 +74,7 @@ impl AuthState {
 pub async fn auth_middleware(
     State(auth_state): State<Arc<AuthState>>,
     headers: HeaderMap,
-    request: Request,
+    mut request: Request,
     next: Next,
 ) -> Result<Response, TamsError> {
     // Skip authentication if not required
@@ -43,18 +82,29 @@ pub async fn auth_middleware(
         return Ok(next.run(request).await);
     }
 
+    // Browsers send CORS preflights without credentials; the CORS layer answers them
+    if request.method() == Method::OPTIONS {
+        return Ok(next.run(request).await);
+    }
+
+    // Health reports stay reachable for probes unless configured otherwise
+    if !auth_state.config.health_requires_auth && request.uri().path().starts_with("/service/health") {
+        return Ok(next.run(request).await);
+    }
+
     let auth_header = headers
         .get(AUTHORIZATION)
         .and_then(|header| header.to_str().ok())
         .ok_or_else(|| TamsError::Unauthorized("Missing Authorization header".to_string()))?;
 
     // Try JWT Bearer token first
-    if auth_header.starts_with("Bearer ") {
+    let principal = if auth_header.starts_with("Bearer ") {
         let token = auth_header
             .strip_prefix("Bearer ")
             .ok_or_else(|| TamsError::Unauthorized("Invalid Bearer token format".to_string()))?;
 
-        validate_jwt_token(token, &auth_state.decoding_key)?;
+        let claims = validate_jwt_token(token, &auth_state.decoding_key)?;
+        Principal::new(&claims.sub, &auth_state.config)
     }
     // Try Basic auth
     else if auth_header.starts_with("Basic ") {
@@ -62,13 +112,15 @@ pub async fn auth_middleware(
             .strip_prefix("Basic ")
             .ok_or_else(|| TamsError::Unauthorized("Invalid Basic auth format".to_string()))?;
 
-        validate_basic_auth(encoded, &auth_state.config)?;
+        let username = validate_basic_auth(encoded, &auth_state.config)?;
+        Principal::new(&username, &auth_state.config)
     } else {
         return Err(TamsError::Unauthorized(
             "Unsupported authentication method".to_string(),
         ));
-    }
+    };
 
+    request.extensions_mut().insert(principal);
     Ok(next.run(request).await)
 }
 
@@ -81,7 +133,8 @@ fn validate_jwt_token(token: &str, decoding_key: &DecodingKey) -> Result<Claims,
     }
 }
 
-fn validate_basic_auth(encoded: &str, config: &AuthConfig) -> Result<(), TamsError> {
+/// The username of valid Basic credentials
+fn validate_basic_auth(encoded: &str, config: &AuthConfig) -> Result<String, TamsError> {
     let decoded = BASE64_STANDARD.decode(encoded)
         .map_err(|_| TamsError::Unauthorized("Invalid Base64 encoding".to_string()))?;
 
@@ -99,14 +152,14 @@ fn validate_basic_auth(encoded: &str, config: &AuthConfig) -> Result<(), TamsErr
         return Err(TamsError::Unauthorized("Invalid credentials".to_string()));
     }
 
-    Ok(())
+    Ok(username.to_string())
 }
 
-// Helper function to create JWT tokens (for testing or admin tools)
-pub fn create_jwt_token(user_id: &str, secret: &str) -> Result<String, TamsError> {
+// Helper function to create JWT tokens (for testing or admin tools), issued at `issued_at`
+pub fn create_jwt_token(user_id: &str, secret: &str, issued_at: chrono::DateTime<chrono::Utc>) -> Result<String, TamsError> {
     use jsonwebtoken::{encode, EncodingKey, Header};
     
-    let now = chrono::Utc::now().timestamp() as usize;
+    let now = issued_at.timestamp() as usize;
     let claims = Claims {
         sub: user_id.to_string(),
         exp: now + 3600, // 1 hour
@@ -118,6 +171,45 @@ pub fn create_jwt_token(user_id: &str, secret: &str) -> Result<String, TamsError
         .map_err(|e| TamsError::Internal(format!("Failed to create JWT token: {}", e)))
 }
 
+/// CORS layer for the configured origins, methods and headers. It sits outside
+/// `auth_middleware`, so preflights are answered before authentication.
+pub fn cors_layer(config: &CorsConfig) -> TamsResult<CorsLayer> {
+    let invalid = |what: &str, value: &str| TamsError::Internal(format!("Invalid CORS {} '{}'", what, value));
+    let origins = if config.allowed_origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            config
+                .allowed_origins
+                .iter()
+                .map(|origin| HeaderValue::from_str(origin).map_err(|_| invalid("origin", origin)))
+                .collect::<TamsResult<Vec<_>>>()?,
+        )
+    };
+    let methods = config
+        .allowed_methods
+        .iter()
+        .map(|method| Method::from_bytes(method.as_bytes()).map_err(|_| invalid("method", method)))
+        .collect::<TamsResult<Vec<_>>>()?;
+    let headers = if config.allowed_headers.iter().any(|header| header == "*") {
+        AllowHeaders::from(Any)
+    } else {
+        AllowHeaders::list(
+            config
+                .allowed_headers
+                .iter()
+                .map(|header| HeaderName::from_bytes(header.as_bytes()).map_err(|_| invalid("header", header)))
+                .collect::<TamsResult<Vec<_>>>()?,
+        )
+    };
+
+    let mut layer = CorsLayer::new().allow_origin(origins).allow_methods(methods).allow_headers(headers);
+    if let Some(max_age) = config.max_age_seconds {
+        layer = layer.max_age(Duration::from_secs(max_age));
+    }
+    Ok(layer)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -128,7 +220,7 @@ mod tests {
         let user_id = "test-user";
 
         // Create token
-        let token = create_jwt_token(user_id, secret).unwrap();
+        let token = create_jwt_token(user_id, secret, chrono::Utc::now()).unwrap();
         assert!(!token.is_empty());
 
         // Validate token
@@ -144,6 +236,10 @@ mod tests {
             jwt_secret: "secret".to_string(),
             basic_auth_username: "admin".to_string(),
             basic_auth_password: "password".to_string(),
+            health_requires_auth: false,
+            enforce_ownership: false,
+            admin_principals: Vec::new(),
+            allow_read_only_unlock: false,
         };
 
         // Valid credentials
@@ -158,4 +254,51 @@ mod tests {
         let encoded = BASE64_STANDARD.encode("invalid");
         assert!(validate_basic_auth(&encoded, &config).is_err());
     }
+
+    #[tokio::test]
+    async fn test_preflight_bypasses_authentication() {
+        use axum::{http::{header, StatusCode}, routing::get, Router};
+        use tower::ServiceExt;
+
+        let auth_state = Arc::new(AuthState::new(AuthConfig {
+            require_auth: true,
+            jwt_secret: "secret".to_string(),
+            basic_auth_username: "admin".to_string(),
+            basic_auth_password: "password".to_string(),
+            health_requires_auth: false,
+            enforce_ownership: false,
+            admin_principals: Vec::new(),
+            allow_read_only_unlock: false,
+        }));
+        let cors = CorsConfig {
+            allowed_origins: vec!["https://ui.example.com".to_string()],
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            allowed_headers: vec!["*".to_string()],
+            max_age_seconds: Some(600),
+        };
+        let app = Router::new()
+            .route("/flows", get(|| async { "flows" }))
+            .layer(axum::middleware::from_fn_with_state(auth_state, auth_middleware))
+            .layer(cors_layer(&cors).unwrap());
+
+        let preflight = Request::options("/flows")
+            .header(header::ORIGIN, "https://ui.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(preflight).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://ui.example.com");
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+        assert!(headers[header::ACCESS_CONTROL_ALLOW_METHODS].to_str().unwrap().contains("GET"));
+
+        // A bare OPTIONS isn't a preflight, but still carries no credentials
+        let options = Request::options("/flows").body(axum::body::Body::empty()).unwrap();
+        assert_ne!(app.clone().oneshot(options).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+        let get = Request::get("/flows").body(axum::body::Body::empty()).unwrap();
+        assert_eq!(app.oneshot(get).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    }
 } 
\ No newline at end of file
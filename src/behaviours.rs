@@ -0,0 +1,172 @@
+//! The `behaviours` object of `GET /service`: each optional behaviour this
+//! server can be configured with, named after the setting that controls it,
+//! with its current value, so clients can detect features without knowing
+//! the server's version.
+//!
+//! Every `[validation]` setting is listed. [`service_behaviours`] destructures
+//! `ValidationConfig`, and each other section it reads a behaviour from, in
+//! full, so a new setting in one of them doesn't compile until it is listed
+//! here or marked as not a behaviour. Settings from other sections are listed
+//! when they change what a client sees from the API.
+
+use crate::{
+    config::{
+        AppConfig, AuthConfig, DatabaseConfig, DeletionConfig, MediaStorageConfig, ServerConfig, ServiceConfig,
+        TimerangeParsing, ValidationConfig,
+    },
+    error::TamsResult,
+};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+pub type Behaviours = BTreeMap<String, Value>;
+
+/// `(name, value)` entries, with each value serialized as in the config file
+macro_rules! behaviours {
+    ($($name:ident: $value:expr),* $(,)?) => {
+        Behaviours::from([$((stringify!($name).to_string(), serde_json::to_value($value)?)),*])
+    };
+}
+
+/// The behaviours `config` turns on, plus whether ingest is paused service-wide
+pub fn service_behaviours(config: &AppConfig, ingest_paused: bool) -> TamsResult<Behaviours> {
+    let ValidationConfig {
+        allowed_containers,
+        allowed_codecs,
+        max_query_duration_seconds,
+        max_flow_collection_bytes,
+        max_tags_bytes,
+        require_segment_objects,
+        max_future_skew_seconds,
+        future_skew_policy,
+        allow_private_notify_urls,
+        require_flow_format,
+        immutable_fields_with_segments,
+        codec_inference,
+        container_codecs,
+    } = &config.validation;
+    let AuthConfig {
+        require_auth,
+        health_requires_auth,
+        enforce_ownership,
+        allow_read_only_unlock,
+        jwt_secret: _,
+        basic_auth_username: _,
+        basic_auth_password: _,
+        admin_principals: _,
+    } = &config.auth;
+    let DeletionConfig {
+        on_flow_delete_with_active_requests,
+        writes_during_deletion,
+        worker: _,
+    } = &config.deletion;
+    let MediaStorageConfig {
+        concurrent_upload_policy,
+        object_overwrite_policy,
+        base_path: _,
+        max_file_size: _,
+        temp_path: _,
+        object_path_template: _,
+        stats_refresh_interval_seconds: _,
+        max_compression_ratio: _,
+        max_archive_bytes: _,
+        max_allocation_objects: _,
+        allocation_expiry_seconds: _,
+        storage_classes: _,
+        format_base_paths: _,
+    } = &config.media_storage;
+    // Which stores serve presigned URLs is read through effective_media_stores
+    let ServiceConfig {
+        serve_unprefixed,
+        media_stores: _,
+        media_store_type: _,
+        public_url_base: _,
+        name: _,
+        description: _,
+        version: _,
+        service_type: _,
+        api_prefix: _,
+        media_store_read_priority: _,
+        default_tags: _,
+        required_tags: _,
+        summary_cache_seconds: _,
+    } = &config.service;
+    let ServerConfig {
+        trailing_slash_equivalent,
+        host: _,
+        port: _,
+        workers: _,
+    } = &config.server;
+    let DatabaseConfig {
+        timerange_parsing,
+        url: _,
+        max_connections: _,
+        connection_timeout_seconds: _,
+        busy_timeout_ms: _,
+        busy_retries: _,
+        busy_retry_base_ms: _,
+        journal_mode: _,
+        synchronous: _,
+        foreign_keys: _,
+        bounds_backfill_batch_size: _,
+        bounds_backfill_interval_ms: _,
+        flow_stats_reconcile_interval_seconds: _,
+    } = &config.database;
+    let presigned_media_urls = config.service.effective_media_stores().iter().any(|store| store.presigned_urls);
+
+    Ok(behaviours! {
+        allowed_containers: allowed_containers,
+        allowed_codecs: allowed_codecs,
+        max_query_duration_seconds: max_query_duration_seconds,
+        max_flow_collection_bytes: max_flow_collection_bytes,
+        max_tags_bytes: max_tags_bytes,
+        require_segment_objects: require_segment_objects,
+        max_future_skew_seconds: max_future_skew_seconds,
+        future_skew_policy: future_skew_policy,
+        allow_private_notify_urls: allow_private_notify_urls,
+        require_flow_format: require_flow_format,
+        immutable_fields_with_segments: immutable_fields_with_segments,
+        codec_inference: codec_inference,
+        container_codecs: container_codecs,
+        strict_timeranges: *timerange_parsing == TimerangeParsing::Strict,
+        require_auth: require_auth,
+        health_requires_auth: health_requires_auth,
+        ownership: enforce_ownership,
+        read_only_unlock: allow_read_only_unlock,
+        ingest_paused: ingest_paused,
+        serve_unprefixed: serve_unprefixed,
+        trailing_slash_equivalent: trailing_slash_equivalent,
+        presigned_media_urls: presigned_media_urls,
+        concurrent_upload_policy: concurrent_upload_policy,
+        object_overwrite_policy: object_overwrite_policy,
+        on_flow_delete_with_active_requests: on_flow_delete_with_active_requests,
+        writes_during_deletion: writes_during_deletion,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::tests::TestApp;
+    use axum::http::{Method, StatusCode};
+
+    #[tokio::test]
+    async fn test_service_lists_every_validation_setting() {
+        let app = TestApp::with_config(|config| {
+            config.validation.require_flow_format = true;
+            config.auth.enforce_ownership = true;
+        })
+        .await;
+        let (status, info) = app.call(Method::GET, "/service", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let behaviours = info["behaviours"].as_object().unwrap();
+
+        let validation = serde_json::to_value(&app.state.config.validation).unwrap();
+        for (name, value) in validation.as_object().unwrap() {
+            assert_eq!(behaviours.get(name), Some(value), "validation.{}", name);
+        }
+        assert_eq!(behaviours["require_flow_format"], true);
+        assert_eq!(behaviours["ownership"], true);
+        assert_eq!(behaviours["strict_timeranges"], false);
+        assert_eq!(behaviours["ingest_paused"], false);
+    }
+}
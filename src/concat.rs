@@ -0,0 +1,191 @@
+use crate::{
+    error::{TamsError, TamsResult},
+    models::{ContentFormat, Flow, FlowSegment, MediaObject},
+};
+
+/// Containers whose objects can be joined by appending their bytes
+pub const CONCATENATABLE_CONTAINERS: &[&str] = &["video/mp2t", "audio/aac", "audio/mpeg"];
+
+/// A byte range of one object within a concatenated stream
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConcatPart {
+    pub object_id: String,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Fail with `Unprocessable` unless the segments' objects can be streamed back
+/// to back: the flow must use a concatenatable container, and every object must
+/// share one media type and one set of essence parameters.
+pub fn check_concatenatable(flow: &Flow, segments: &[FlowSegment], objects: &[Option<MediaObject>]) -> TamsResult<()> {
+    if matches!(flow.format, ContentFormat::Image | ContentFormat::Multi) {
+        return Err(TamsError::Unprocessable(format!(
+            "Flow {} has a format whose segments cannot be concatenated",
+            flow.id
+        )));
+    }
+
+    let container = flow.container.as_deref().unwrap_or_default();
+    if !CONCATENATABLE_CONTAINERS.iter().any(|c| c.eq_ignore_ascii_case(container)) {
+        return Err(TamsError::Unprocessable(format!(
+            "Container '{}' is not concatenatable; supported containers are {}",
+            container,
+            CONCATENATABLE_CONTAINERS.join(", ")
+        )));
+    }
+
+    let mut mime_types = objects.iter().flatten().filter_map(|o| o.mime_type.as_deref());
+    if let Some(first) = mime_types.next() {
+        if let Some(other) = mime_types.find(|m| !m.eq_ignore_ascii_case(first)) {
+            return Err(TamsError::Unprocessable(format!(
+                "Segments mix media types '{}' and '{}'",
+                first, other
+            )));
+        }
+    }
+
+    if let Some((first, rest)) = segments.split_first() {
+        if let Some(other) = rest.iter().find(|s| s.essence_parameters != first.essence_parameters) {
+            return Err(TamsError::Unprocessable(format!(
+                "Segments {} and {} have different essence parameters",
+                first.object_id, other.object_id
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse a single `bytes=` range against the total length, returning the
+/// inclusive (first, last) byte positions. `Ok(None)` means no usable range was
+/// requested and the whole stream should be sent; `Err(())` means unsatisfiable.
+#[allow(clippy::result_unit_err)]
+pub fn parse_range_header(value: &str, total: u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    // Multiple ranges would need a multipart response; serve the whole stream
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((first, last)) = spec.split_once('-') else {
+        return Ok(None);
+    };
+
+    let (first, last) = match (first.trim(), last.trim()) {
+        ("", "") => return Ok(None),
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().map_err(|_| ())?;
+            if suffix == 0 || total == 0 {
+                return Err(());
+            }
+            (total.saturating_sub(suffix), total - 1)
+        }
+        (first, last) => {
+            let first: u64 = first.parse().map_err(|_| ())?;
+            let last = if last.is_empty() {
+                total.saturating_sub(1)
+            } else {
+                last.parse::<u64>().map_err(|_| ())?.min(total.saturating_sub(1))
+            };
+            (first, last)
+        }
+    };
+
+    if first >= total || first > last {
+        return Err(());
+    }
+    Ok(Some((first, last)))
+}
+
+/// Map an inclusive byte range of the concatenated stream onto the objects
+pub fn plan_parts(sizes: &[(String, u64)], range: Option<(u64, u64)>) -> Vec<ConcatPart> {
+    let total: u64 = sizes.iter().map(|(_, size)| size).sum();
+    let (first, last) = match range {
+        Some(range) => range,
+        None if total == 0 => return Vec::new(),
+        None => (0, total - 1),
+    };
+
+    let mut parts = Vec::new();
+    let mut position = 0u64;
+    for (object_id, size) in sizes {
+        let object_end = position + size;
+        if *size > 0 && object_end > first && position <= last {
+            let offset = first.saturating_sub(position);
+            let end = (last + 1).min(object_end) - position;
+            parts.push(ConcatPart {
+                object_id: object_id.clone(),
+                offset,
+                len: end - offset,
+            });
+        }
+        position = object_end;
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{CreateSegmentRequest, SegmentEssenceParameters};
+    use uuid::Uuid;
+
+    fn sizes() -> Vec<(String, u64)> {
+        vec![("a".to_string(), 10), ("b".to_string(), 5), ("c".to_string(), 10)]
+    }
+
+    #[test]
+    fn test_parse_range_header() {
+        assert_eq!(parse_range_header("bytes=0-9", 25), Ok(Some((0, 9))));
+        assert_eq!(parse_range_header("bytes=20-", 25), Ok(Some((20, 24))));
+        assert_eq!(parse_range_header("bytes=-5", 25), Ok(Some((20, 24))));
+        assert_eq!(parse_range_header("bytes=10-100", 25), Ok(Some((10, 24))));
+        assert_eq!(parse_range_header("bytes=0-1,4-5", 25), Ok(None));
+        assert_eq!(parse_range_header("items=0-1", 25), Ok(None));
+        assert_eq!(parse_range_header("bytes=25-", 25), Err(()));
+        assert_eq!(parse_range_header("bytes=5-2", 25), Err(()));
+    }
+
+    #[test]
+    fn test_plan_parts_spans_objects() {
+        assert_eq!(plan_parts(&sizes(), None).len(), 3);
+        assert_eq!(
+            plan_parts(&sizes(), Some((8, 16))),
+            vec![
+                ConcatPart { object_id: "a".to_string(), offset: 8, len: 2 },
+                ConcatPart { object_id: "b".to_string(), offset: 0, len: 5 },
+                ConcatPart { object_id: "c".to_string(), offset: 0, len: 2 },
+            ]
+        );
+        assert_eq!(
+            plan_parts(&sizes(), Some((11, 12))),
+            vec![ConcatPart { object_id: "b".to_string(), offset: 1, len: 2 }]
+        );
+    }
+
+    #[test]
+    fn test_mixed_essence_parameters_are_not_concatenatable() {
+        let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        flow.container = Some("video/mp2t".to_string());
+        let segment = |object_id: &str, width: Option<u32>| {
+            CreateSegmentRequest {
+                essence_parameters: width.map(|w| SegmentEssenceParameters {
+                    frame_width: Some(w),
+                    frame_height: None,
+                }),
+                ..CreateSegmentRequest::new(object_id, "0:0", "1:0")
+            }
+            .into_segment(flow.id)
+        };
+
+        assert!(check_concatenatable(&flow, &[segment("a", None), segment("b", None)], &[]).is_ok());
+        assert!(matches!(
+            check_concatenatable(&flow, &[segment("a", None), segment("b", Some(1280))], &[]),
+            Err(TamsError::Unprocessable(_))
+        ));
+
+        flow.container = Some("video/mp4".to_string());
+        assert!(check_concatenatable(&flow, &[segment("a", None)], &[]).is_err());
+    }
+}
@@ -1,7 +1,10 @@
 use config::{Config, ConfigError, File};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 
+use crate::models::ContentFormat;
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AppConfig {
     pub server: ServerConfig,
@@ -13,6 +16,20 @@ pub struct AppConfig {
     pub logging: LoggingConfig,
     pub pagination: PaginationConfig,
     pub cleanup: CleanupConfig,
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    #[serde(default)]
+    pub caching: CachingConfig,
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+    #[serde(default)]
+    pub deletion: DeletionConfig,
+    #[serde(default)]
+    pub jobs: JobsConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub events: EventStreamConfig,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -20,6 +37,9 @@ pub struct ServerConfig {
     pub host: String,
     pub port: u16,
     pub workers: usize,
+    /// Serve `/path/` as `/path` instead of answering 404
+    #[serde(default)]
+    pub trailing_slash_equivalent: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -27,6 +47,99 @@ pub struct DatabaseConfig {
     pub url: String,
     pub max_connections: u32,
     pub connection_timeout_seconds: u64,
+    /// How long SQLite itself waits on a locked database before reporting it busy
+    #[serde(default = "default_busy_timeout_ms")]
+    pub busy_timeout_ms: u64,
+    /// Further attempts at a write that still found the database busy
+    #[serde(default = "default_busy_retries")]
+    pub busy_retries: u32,
+    /// Delay before the first retry; doubles with each further attempt
+    #[serde(default = "default_busy_retry_base_ms")]
+    pub busy_retry_base_ms: u64,
+    #[serde(default)]
+    pub journal_mode: JournalMode,
+    #[serde(default)]
+    pub synchronous: Synchronous,
+    /// Enforce foreign keys, so deleting a flow removes its segments
+    #[serde(default = "default_foreign_keys")]
+    pub foreign_keys: bool,
+    #[serde(default)]
+    pub timerange_parsing: TimerangeParsing,
+    /// Segments given numeric bounds per backfill batch
+    #[serde(default = "default_bounds_backfill_batch_size")]
+    pub bounds_backfill_batch_size: u32,
+    /// Pause between backfill batches, so the backfill leaves room for other writers
+    #[serde(default = "default_bounds_backfill_interval_ms")]
+    pub bounds_backfill_interval_ms: u64,
+    /// How often per-flow segment stats are checked against the segments and
+    /// drift is corrected; 0 leaves it to POST /service/maintenance/reconcile-flow-stats
+    #[serde(default = "default_flow_stats_reconcile_interval_seconds")]
+    pub flow_stats_reconcile_interval_seconds: u64,
+}
+
+/// What segment readers do with a stored timerange that doesn't parse
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimerangeParsing {
+    /// Log a warning and treat the segment as un-timeranged, leaving it out of range queries
+    #[default]
+    Lenient,
+    /// Fail the read, to surface bad data
+    Strict,
+}
+
+/// SQLite `journal_mode` pragma
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalMode {
+    /// Write-ahead log: readers don't block the writer
+    #[default]
+    Wal,
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Off,
+}
+
+/// SQLite `synchronous` pragma
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Synchronous {
+    Off,
+    /// Durable across application crashes; in WAL mode a power loss may drop the last commits
+    #[default]
+    Normal,
+    Full,
+    Extra,
+}
+
+fn default_foreign_keys() -> bool {
+    true
+}
+
+fn default_busy_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_busy_retries() -> u32 {
+    5
+}
+
+fn default_busy_retry_base_ms() -> u64 {
+    50
+}
+
+fn default_bounds_backfill_batch_size() -> u32 {
+    1000
+}
+
+fn default_bounds_backfill_interval_ms() -> u64 {
+    200
+}
+
+fn default_flow_stats_reconcile_interval_seconds() -> u64 {
+    86400
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -34,6 +147,85 @@ pub struct MediaStorageConfig {
     pub base_path: PathBuf,
     pub max_file_size: u64,
     pub temp_path: PathBuf,
+    /// Layout of objects under base_path; see `storage::ObjectPathTemplate` for placeholders
+    #[serde(default = "default_object_path_template")]
+    pub object_path_template: String,
+    /// How often cached storage stats are recomputed in the background
+    #[serde(default = "default_stats_refresh_interval_seconds")]
+    pub stats_refresh_interval_seconds: u64,
+    /// What a PUT does while another upload of the same object id is in progress
+    #[serde(default)]
+    pub concurrent_upload_policy: ConcurrentUploadPolicy,
+    /// What a PUT of an object id that already has stored bytes does
+    #[serde(default)]
+    pub object_overwrite_policy: ObjectOverwritePolicy,
+    /// Most a gzip-encoded upload may expand by; its decompressed size is also
+    /// held to max_file_size
+    #[serde(default = "default_max_compression_ratio")]
+    pub max_compression_ratio: u64,
+    /// Largest segment archive served, in bytes; larger requests get 413
+    #[serde(default = "default_max_archive_bytes")]
+    pub max_archive_bytes: u64,
+    /// Most objects one storage allocation request may ask for, by limit or object_ids
+    #[serde(default = "default_max_allocation_objects")]
+    pub max_allocation_objects: u32,
+    /// How long an allocation's put_url stays valid
+    #[serde(default = "default_allocation_expiry_seconds")]
+    pub allocation_expiry_seconds: u64,
+    /// Named storage locations, class name to base path. A flow's
+    /// `storage_class` tag places its objects in one; others go under base_path.
+    #[serde(default)]
+    pub storage_classes: HashMap<String, PathBuf>,
+    /// Base path for the objects of flows of each format; formats not listed
+    /// use base_path
+    #[serde(default)]
+    pub format_base_paths: HashMap<ContentFormat, PathBuf>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConcurrentUploadPolicy {
+    /// Queue behind the in-progress upload
+    #[default]
+    Wait,
+    /// Fail with 409 Conflict
+    Reject,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ObjectOverwritePolicy {
+    /// Accept a re-upload of identical bytes with 200; different bytes fail with 409 Conflict
+    #[default]
+    IdenticalOnly,
+    /// Replace the stored bytes and answer 200
+    Allow,
+    /// Write-once: any re-upload fails with 409 Conflict
+    Fail,
+}
+
+pub fn default_max_archive_bytes() -> u64 {
+    4 * 1024 * 1024 * 1024
+}
+
+pub fn default_max_allocation_objects() -> u32 {
+    100
+}
+
+pub fn default_allocation_expiry_seconds() -> u64 {
+    3600
+}
+
+pub fn default_max_compression_ratio() -> u64 {
+    100
+}
+
+pub fn default_stats_refresh_interval_seconds() -> u64 {
+    300
+}
+
+pub fn default_object_path_template() -> String {
+    "{shard2}/{shard2}/{id}".to_string()
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -41,8 +233,137 @@ pub struct ServiceConfig {
     pub name: String,
     pub description: String,
     pub version: String,
+    /// URN identifying the kind of service, reported as `type` by GET /service
+    #[serde(default = "default_service_type")]
+    pub service_type: String,
+    /// Path the API is mounted under, e.g. `/x-tams/v6.0`; empty mounts it at the root
+    #[serde(default = "default_api_prefix")]
+    pub api_prefix: String,
+    /// Keep answering at the root as well as under `api_prefix` while clients
+    /// move over; those responses carry `Deprecation: true`
+    #[serde(default = "default_serve_unprefixed")]
+    pub serve_unprefixed: bool,
     pub media_store_type: String,
     pub public_url_base: String,
+    /// Configured media store backends; when empty a single primary store is
+    /// derived from `media_store_type` and `public_url_base`
+    #[serde(default)]
+    pub media_stores: Vec<MediaStoreConfig>,
+    /// Store names in the order the download/get_urls logic should prefer them
+    #[serde(default)]
+    pub media_store_read_priority: Vec<String>,
+    /// Tags merged into newly created sources and flows; client values win
+    #[serde(default)]
+    pub default_tags: HashMap<String, String>,
+    /// Tag keys that new sources and flows must carry after defaults are merged
+    #[serde(default)]
+    pub required_tags: Vec<String>,
+    /// How long GET /service/summary serves the same document before recomputing it
+    #[serde(default = "default_summary_cache_seconds")]
+    pub summary_cache_seconds: u64,
+}
+
+pub fn default_service_type() -> String {
+    "urn:x-tams:service.tams-rust".to_string()
+}
+
+pub fn default_api_prefix() -> String {
+    "/x-tams/v6.0".to_string()
+}
+
+pub fn default_serve_unprefixed() -> bool {
+    true
+}
+
+pub fn default_summary_cache_seconds() -> u64 {
+    5
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaStoreRole {
+    ReadWrite,
+    ReadOnly,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MediaStoreConfig {
+    pub name: String,
+    pub store_type: String,
+    pub url_base: String,
+    /// Whether the backend issues presigned URLs natively (e.g. S3)
+    #[serde(default)]
+    pub presigned_urls: bool,
+    pub role: MediaStoreRole,
+    /// How `/objects/:id/download` serves objects when this is the preferred store
+    #[serde(default)]
+    pub download_mode: DownloadMode,
+    /// Base URL redirects point at, e.g. a CDN fronting the media host; defaults to `url_base`
+    #[serde(default)]
+    pub redirect_url_base: Option<String>,
+    /// Let clients force proxying in redirect mode with `?proxy=true`
+    #[serde(default)]
+    pub allow_proxy_override: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadMode {
+    /// Stream object bytes through the API
+    #[default]
+    Proxy,
+    /// Answer with a 302 to the object's URL under the store's redirect base
+    Redirect,
+}
+
+impl ServiceConfig {
+    /// `path` under the API prefix, as clients must request it
+    pub fn api_path(&self, path: &str) -> String {
+        format!("{}{}", self.api_prefix, path)
+    }
+
+    /// Absolute URL of `path` under the API prefix
+    pub fn api_url(&self, path: &str) -> String {
+        format!("{}{}", self.public_url_base.trim_end_matches('/'), self.api_path(path))
+    }
+
+    /// The configured media stores, or a single read/write store describing the
+    /// legacy scalar settings when none are configured
+    pub fn effective_media_stores(&self) -> Vec<MediaStoreConfig> {
+        if !self.media_stores.is_empty() {
+            return self.media_stores.clone();
+        }
+        vec![MediaStoreConfig {
+            name: "primary".to_string(),
+            store_type: self.media_store_type.clone(),
+            url_base: self.api_url(""),
+            presigned_urls: false,
+            role: MediaStoreRole::ReadWrite,
+            download_mode: DownloadMode::default(),
+            redirect_url_base: None,
+            allow_proxy_override: false,
+        }]
+    }
+
+    /// The store new objects are written to: the first read/write store
+    pub fn primary_media_store(&self) -> Option<MediaStoreConfig> {
+        self.effective_media_stores()
+            .into_iter()
+            .find(|store| store.role == MediaStoreRole::ReadWrite)
+    }
+
+    /// Stores in read-preference order: those named in `media_store_read_priority`
+    /// first, then the remainder in declaration order
+    pub fn media_stores_by_read_priority(&self) -> Vec<MediaStoreConfig> {
+        let mut stores = self.effective_media_stores();
+        stores.sort_by_key(|store| {
+            self.media_store_read_priority
+                .iter()
+                .position(|name| name == &store.name)
+                .unwrap_or(usize::MAX)
+        });
+        stores
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -51,13 +372,30 @@ pub struct AuthConfig {
     pub jwt_secret: String,
     pub basic_auth_username: String,
     pub basic_auth_password: String,
+    /// Require credentials for the detailed health endpoints
+    #[serde(default)]
+    pub health_requires_auth: bool,
+    /// Limit each principal to the sources and flows it owns; admins see all
+    #[serde(default)]
+    pub enforce_ownership: bool,
+    /// Principals (Basic auth usernames or JWT subjects) exempt from ownership
+    #[serde(default)]
+    pub admin_principals: Vec<String>,
+    /// Let any client clear a flow's read_only, not only admin_principals
+    #[serde(default)]
+    pub allow_read_only_unlock: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct CorsConfig {
+    /// Origins allowed to call the API; "*" allows any
     pub allowed_origins: Vec<String>,
     pub allowed_methods: Vec<String>,
+    /// Request headers a browser may send; "*" allows any
     pub allowed_headers: Vec<String>,
+    /// How long browsers may cache a preflight response; unset leaves it to the browser
+    #[serde(default)]
+    pub max_age_seconds: Option<u64>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -78,6 +416,293 @@ pub struct CleanupConfig {
     pub orphaned_object_retention_days: u64,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ValidationConfig {
+    /// Canonical container values (e.g. "video/mp2t"); unset accepts any value
+    pub allowed_containers: Option<Vec<String>>,
+    /// Canonical codec values (e.g. "video/h264"); unset accepts any value
+    pub allowed_codecs: Option<Vec<String>>,
+    /// Longest timerange span a segment or flow query may request, which then must
+    /// give both bounds; unset means unlimited
+    pub max_query_duration_seconds: Option<u64>,
+    /// Largest serialized flow_collection accepted on flow create/update, in bytes
+    pub max_flow_collection_bytes: Option<usize>,
+    /// Largest serialized tags map accepted on flow create/update, in bytes
+    pub max_tags_bytes: Option<usize>,
+    /// Reject segments whose object has not been uploaded yet
+    #[serde(default)]
+    pub require_segment_objects: bool,
+    /// How far ahead of server time a new segment may start, in seconds
+    #[serde(default = "default_max_future_skew_seconds")]
+    pub max_future_skew_seconds: u64,
+    /// What happens to a segment starting further ahead than that
+    #[serde(default)]
+    pub future_skew_policy: FutureSkewPolicy,
+    /// Let a flow's notify_url name loopback, private or link-local addresses
+    #[serde(default)]
+    pub allow_private_notify_urls: bool,
+    /// Refuse new flows without a format rather than storing them as data flows
+    #[serde(default)]
+    pub require_flow_format: bool,
+    /// Refuse updates that change the format or source_id of a flow with segments
+    #[serde(default = "default_immutable_fields_with_segments")]
+    pub immutable_fields_with_segments: bool,
+    /// Whether a flow's missing container or codec is filled in from container_codecs
+    #[serde(default)]
+    pub codec_inference: CodecInferencePolicy,
+    /// Codecs each container usually carries, most typical first; replaces the
+    /// built-in table when set
+    #[serde(default = "default_container_codecs")]
+    pub container_codecs: Vec<ContainerCodecs>,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        ValidationConfig {
+            allowed_containers: None,
+            allowed_codecs: None,
+            max_query_duration_seconds: None,
+            max_flow_collection_bytes: None,
+            max_tags_bytes: None,
+            require_segment_objects: false,
+            max_future_skew_seconds: default_max_future_skew_seconds(),
+            future_skew_policy: FutureSkewPolicy::default(),
+            allow_private_notify_urls: false,
+            require_flow_format: false,
+            immutable_fields_with_segments: default_immutable_fields_with_segments(),
+            codec_inference: CodecInferencePolicy::default(),
+            container_codecs: default_container_codecs(),
+        }
+    }
+}
+
+fn default_max_future_skew_seconds() -> u64 {
+    300
+}
+
+fn default_immutable_fields_with_segments() -> bool {
+    true
+}
+
+/// One row of the container/codec table used for inference
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ContainerCodecs {
+    pub container: String,
+    pub codecs: Vec<String>,
+}
+
+fn default_container_codecs() -> Vec<ContainerCodecs> {
+    let row = |container: &str, codecs: &[&str]| ContainerCodecs {
+        container: container.to_string(),
+        codecs: codecs.iter().map(|codec| codec.to_string()).collect(),
+    };
+    vec![
+        row("video/mp2t", &["video/h264", "video/h265", "video/mpeg2", "audio/aac", "audio/mpeg", "audio/ac3"]),
+        row("video/mp4", &["video/h264", "video/h265", "video/av1", "audio/aac", "audio/opus"]),
+        row("video/webm", &["video/vp9", "video/vp8", "video/av1", "audio/opus", "audio/vorbis"]),
+        row("application/mxf", &["video/jpeg2000", "video/h264", "audio/L24", "audio/L16"]),
+        row("audio/wav", &["audio/L24", "audio/L16"]),
+    ]
+}
+
+/// Inference of a flow's container from its codec and the other way round
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CodecInferencePolicy {
+    /// Store container and codec as sent
+    #[default]
+    Off,
+    /// Fill in whichever is missing, and warn when a pair contradicts the table
+    Infer,
+    /// As `infer`, but refuse a contradicting pair with 400
+    Strict,
+}
+
+/// Handling of segments timestamped further in the future than the allowed skew
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FutureSkewPolicy {
+    /// Refuse the segment with 400 Bad Request
+    #[default]
+    Reject,
+    /// Store the segment and return a `future_segment` response warning
+    Warn,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct CachingConfig {
+    /// Cache-Control for object content; object bytes never change once written
+    pub object_cache_control: String,
+    /// Cache-Control for object metadata, whose flow references can change
+    pub metadata_cache_control: String,
+    /// Cache-Control for download redirects, kept short so a store can be repointed
+    pub redirect_cache_control: String,
+}
+
+impl Default for CachingConfig {
+    fn default() -> Self {
+        CachingConfig {
+            object_cache_control: "public, max-age=31536000, immutable".to_string(),
+            metadata_cache_control: "public, max-age=60".to_string(),
+            redirect_cache_control: "private, max-age=60".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// How long a rotated-out api_key_value keeps signing deliveries alongside the new one
+    pub secret_rotation_overlap_seconds: u64,
+    /// 32-byte key, hex encoded, used to encrypt stored api_key_values
+    pub encryption_key: Option<String>,
+    /// Derive the encryption key from auth.jwt_secret when no explicit key is set
+    pub derive_encryption_key_from_jwt_secret: bool,
+    /// Largest delivery body in bytes; larger notifications are sent as a summary
+    /// of ids, counts and timeranges marked `truncated`. Zero disables the limit.
+    pub max_body_bytes: u64,
+    /// Longest a delivery may take, from connecting until the receiver's
+    /// response has been read
+    pub delivery_timeout_seconds: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            secret_rotation_overlap_seconds: 86400,
+            encryption_key: None,
+            derive_encryption_key_from_jwt_secret: false,
+            max_body_bytes: 1024 * 1024,
+            delivery_timeout_seconds: 30,
+        }
+    }
+}
+
+/// `GET /service/events`, the server-sent event stream of notifications
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct EventStreamConfig {
+    /// Notifications held for subscribers that haven't read them yet; a
+    /// subscriber further behind than this is disconnected with a `reconnect` event
+    pub buffer_events: usize,
+    /// Interval between heartbeat comments on an idle stream
+    pub heartbeat_seconds: u64,
+}
+
+impl Default for EventStreamConfig {
+    fn default() -> Self {
+        EventStreamConfig {
+            buffer_events: 256,
+            heartbeat_seconds: 15,
+        }
+    }
+}
+
+/// What DELETE /flows/:id does when deletion requests for the flow are still active
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ActiveDeletionRequestPolicy {
+    /// Mark the requests cancelled and delete the flow
+    #[default]
+    Cancel,
+    /// Refuse the delete with 409 Conflict
+    Reject,
+}
+
+/// What segment writes and storage allocations for a flow do while one of its
+/// deletion requests is pending or in progress
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WritesDuringDeletionPolicy {
+    /// Refuse every write to the flow with 409 Conflict
+    #[default]
+    Reject,
+    /// Refuse only segments overlapping the timerange being deleted, and
+    /// allocations while the whole flow is being emptied
+    OutsideTimerange,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DeletionConfig {
+    pub on_flow_delete_with_active_requests: ActiveDeletionRequestPolicy,
+    pub writes_during_deletion: WritesDuringDeletionPolicy,
+    pub worker: DeletionWorkerSettings,
+}
+
+/// Pacing of the deletion worker, so large deletions don't starve live ingest.
+/// Overrides set through the admin API are persisted and take precedence.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct DeletionWorkerSettings {
+    /// Segments deleted per transaction; progress and cancellation are checked between batches
+    pub batch_size: u32,
+    /// Pause between batches
+    pub batch_pause_ms: u64,
+    /// Upper bound on segment objects deleted per second; 0 for no limit
+    pub max_objects_per_second: u32,
+    /// UTC time of day during which deletions run, e.g. "22:00-06:00"; always when unset
+    pub active_window: Option<String>,
+}
+
+impl Default for DeletionWorkerSettings {
+    fn default() -> Self {
+        DeletionWorkerSettings {
+            batch_size: 500,
+            batch_pause_ms: 50,
+            max_objects_per_second: 0,
+            active_window: None,
+        }
+    }
+}
+
+/// Expiry of segments from flows that set `retention_seconds`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// How often each flow with a retention policy is checked for expired segments
+    pub sweep_interval_seconds: u64,
+    /// Segments deleted per transaction
+    pub batch_size: u32,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            sweep_interval_seconds: 300,
+            batch_size: 500,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct JobsConfig {
+    /// How often background workers look for due jobs
+    pub poll_interval_seconds: u64,
+    /// How long a claimed job is reserved before it may be claimed again
+    pub lease_seconds: u64,
+    /// Delay before the first retry of a failed job; doubles with each failure
+    pub retry_base_seconds: u64,
+    /// Longest delay between retries
+    pub retry_max_seconds: u64,
+    /// Attempts after which a job is marked failed
+    pub max_attempts: u32,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        JobsConfig {
+            poll_interval_seconds: 5,
+            lease_seconds: 300,
+            retry_base_seconds: 10,
+            retry_max_seconds: 3600,
+            max_attempts: 8,
+        }
+    }
+}
+
 impl AppConfig {
     pub fn new() -> Result<Self, ConfigError> {
         let config = Config::builder()
@@ -0,0 +1,129 @@
+use crate::{
+    config::WebhookConfig,
+    error::{TamsError, TamsResult},
+};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use config::ConfigError;
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+/// Prefix marking a stored value as sealed by [`SecretCipher`]
+pub const SEALED_PREFIX: &str = "enc:v1:";
+
+const NONCE_LEN: usize = 24;
+const HKDF_INFO: &[u8] = b"tams-rust webhook api_key_value";
+
+/// Encrypts secrets stored in the database with XChaCha20-Poly1305.
+///
+/// Sealed values are `enc:v1:` followed by the hex encoded nonce and ciphertext.
+#[derive(Clone)]
+pub struct SecretCipher {
+    cipher: XChaCha20Poly1305,
+}
+
+impl SecretCipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        SecretCipher {
+            cipher: XChaCha20Poly1305::new(key.into()),
+        }
+    }
+
+    /// Build the cipher for webhook keys: an explicit hex key takes precedence,
+    /// otherwise one is derived from the JWT secret when enabled
+    pub fn from_config(config: &WebhookConfig, jwt_secret: &str) -> TamsResult<Option<Self>> {
+        if let Some(hex_key) = &config.encryption_key {
+            let bytes = hex::decode(hex_key.trim()).map_err(|e| {
+                TamsError::Config(ConfigError::Message(format!("webhooks.encryption_key is not valid hex: {}", e)))
+            })?;
+            let key: [u8; 32] = bytes.try_into().map_err(|_| {
+                TamsError::Config(ConfigError::Message(
+                    "webhooks.encryption_key must be 32 bytes (64 hex characters)".to_string(),
+                ))
+            })?;
+            return Ok(Some(Self::new(&key)));
+        }
+
+        if config.derive_encryption_key_from_jwt_secret {
+            let mut key = [0u8; 32];
+            Hkdf::<Sha256>::new(None, jwt_secret.as_bytes())
+                .expand(HKDF_INFO, &mut key)
+                .map_err(|e| TamsError::Internal(format!("Failed to derive webhook encryption key: {}", e)))?;
+            return Ok(Some(Self::new(&key)));
+        }
+
+        Ok(None)
+    }
+
+    pub fn seal(&self, plaintext: &str) -> TamsResult<String> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| TamsError::Internal("Failed to encrypt secret".to_string()))?;
+        Ok(format!("{}{}{}", SEALED_PREFIX, hex::encode(nonce), hex::encode(ciphertext)))
+    }
+
+    pub fn open(&self, sealed: &str) -> TamsResult<String> {
+        let encoded = sealed
+            .strip_prefix(SEALED_PREFIX)
+            .ok_or_else(|| TamsError::Internal("Secret is not sealed".to_string()))?;
+        let bytes = hex::decode(encoded)
+            .map_err(|_| TamsError::Internal("Sealed secret is not valid hex".to_string()))?;
+        if bytes.len() < NONCE_LEN {
+            return Err(TamsError::Internal("Sealed secret is truncated".to_string()));
+        }
+
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| TamsError::Internal("Failed to decrypt secret; wrong encryption key?".to_string()))?;
+        String::from_utf8(plaintext)
+            .map_err(|_| TamsError::Internal("Decrypted secret is not UTF-8".to_string()))
+    }
+}
+
+pub fn is_sealed(value: &str) -> bool {
+    value.starts_with(SEALED_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open_round_trip() {
+        let cipher = SecretCipher::new(&[7u8; 32]);
+        let sealed = cipher.seal("Bearer downstream-token").unwrap();
+
+        assert!(is_sealed(&sealed));
+        assert!(!sealed.contains("downstream-token"));
+        assert_ne!(sealed, cipher.seal("Bearer downstream-token").unwrap());
+        assert_eq!(cipher.open(&sealed).unwrap(), "Bearer downstream-token");
+    }
+
+    #[test]
+    fn test_open_with_wrong_key_fails() {
+        let sealed = SecretCipher::new(&[1u8; 32]).seal("secret").unwrap();
+        assert!(SecretCipher::new(&[2u8; 32]).open(&sealed).is_err());
+    }
+
+    #[test]
+    fn test_cipher_from_config() {
+        let mut config = WebhookConfig::default();
+        assert!(SecretCipher::from_config(&config, "jwt").unwrap().is_none());
+
+        config.derive_encryption_key_from_jwt_secret = true;
+        let derived = SecretCipher::from_config(&config, "jwt").unwrap().unwrap();
+        let again = SecretCipher::from_config(&config, "jwt").unwrap().unwrap();
+        assert_eq!(again.open(&derived.seal("x").unwrap()).unwrap(), "x");
+
+        config.encryption_key = Some("ab".repeat(32));
+        assert!(SecretCipher::from_config(&config, "jwt").unwrap().is_some());
+        config.encryption_key = Some("abcd".to_string());
+        assert!(SecretCipher::from_config(&config, "jwt").is_err());
+    }
+}
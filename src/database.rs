@@ -1,40 +1,633 @@
+use crate::clock::{system_clock, SharedClock};
 use crate::models::*;
-use crate::error::{TamsError, TamsResult};
+use crate::config::{ActiveDeletionRequestPolicy, DatabaseConfig, JournalMode, Synchronous, TimerangeParsing};
+use crate::error::{is_busy_error, TamsError, TamsResult};
+use crate::metrics::metrics;
+use crate::time_utils::{
+    covering_timerange, format_rfc3339, format_tams_timerange, format_tams_timestamp, parse_segment_timerange,
+    nanos_to_timestamp, segment_bounds_nanos, timestamp_to_nanos, OPEN_END_NANOS,
+};
 use chrono::{DateTime, Utc};
-use sqlx::{Pool, Sqlite, SqlitePool, Row};
-use std::collections::HashMap;
+use sqlx::pool::PoolConnection;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous};
+use sqlx::{Connection, Pool, QueryBuilder, Row, Sqlite};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use uuid::Uuid;
 use serde_json;
+use std::future::Future;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use futures_util::StreamExt;
+use base64::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+/// Until every segment has start_ns/end_ns, range queries read segments
+/// through `flow_segment_bounds`, which takes a segment's bounds from those
+/// columns where they are set and otherwise parses them out of the legacy
+/// `start_s:start_ns:end_s:end_ns` string (or `start_s:start_ns:`, which runs
+/// to [`OPEN_END_NANOS`]), so rows written before the numeric columns existed
+/// are found while they are backfilled. Other strings get no bounds. The view
+/// is dropped once the backfill is complete, and queries then read
+/// flow_segments and its (flow_id, start_ns) index directly.
+const SEGMENT_BOUNDS_VIEW: &str = r#"
+DROP VIEW IF EXISTS flow_segment_bounds;
+CREATE VIEW flow_segment_bounds AS
+SELECT rowid AS rowid, flow_id, object_id, timerange, start_ns, end_ns FROM flow_segments WHERE start_ns IS NOT NULL
+UNION ALL
+SELECT segment_rowid, flow_id, object_id, timerange,
+    CASE WHEN colons = 3 OR open_end THEN CAST(substr(timerange, 1, instr(timerange, ':') - 1) AS INTEGER) * 1000000000
+        + CAST(substr(rest, 1, instr(rest, ':') - 1) AS INTEGER) END,
+    CASE WHEN colons = 3 THEN CAST(substr(tail, 1, instr(tail, ':') - 1) AS INTEGER) * 1000000000
+        + CAST(substr(tail, instr(tail, ':') + 1) AS INTEGER)
+        WHEN open_end THEN 9223372036854775807 END
+FROM (
+    SELECT segment_rowid, flow_id, object_id, timerange, colons, colons = 2 AND timerange LIKE '%:' AS open_end,
+        rest, substr(rest, instr(rest, ':') + 1) AS tail
+    FROM (
+        SELECT rowid AS segment_rowid, flow_id, object_id, timerange,
+            length(timerange) - length(replace(timerange, ':', '')) AS colons,
+            substr(timerange, instr(timerange, ':') + 1) AS rest
+        FROM flow_segments WHERE start_ns IS NULL
+    )
+);
+"#;
+
+/// Keep `flow_stats` in step with every write to flow_segments and media_objects,
+/// in the writing statement's own transaction. Adding a segment only adjusts
+/// the counters; removing one re-reads the flow's earliest start or latest end
+/// through the (flow_id, start_ns) and (flow_id, end_ns) indexes, and only when
+/// the segment held it. Changes to an object's size are applied to every flow
+/// with a segment of that object.
+const FLOW_STATS_TRIGGERS: &str = r#"
+DROP TRIGGER IF EXISTS flow_stats_flow_insert;
+CREATE TRIGGER flow_stats_flow_insert AFTER INSERT ON flows
+BEGIN
+    INSERT OR IGNORE INTO flow_stats (flow_id) VALUES (NEW.id);
+END;
+
+DROP TRIGGER IF EXISTS flow_stats_segment_insert;
+CREATE TRIGGER flow_stats_segment_insert AFTER INSERT ON flow_segments
+BEGIN
+    INSERT INTO flow_stats (flow_id, segment_count, total_bytes, first_start_ns, last_end_ns)
+    VALUES (
+        NEW.flow_id, 1,
+        COALESCE((SELECT size_bytes FROM media_objects WHERE object_id = NEW.object_id), 0),
+        NEW.start_ns, NEW.end_ns
+    )
+    ON CONFLICT(flow_id) DO UPDATE SET
+        segment_count = segment_count + 1,
+        total_bytes = total_bytes + excluded.total_bytes,
+        first_start_ns = COALESCE(min(first_start_ns, excluded.first_start_ns), first_start_ns, excluded.first_start_ns),
+        last_end_ns = COALESCE(max(last_end_ns, excluded.last_end_ns), last_end_ns, excluded.last_end_ns);
+END;
+
+DROP TRIGGER IF EXISTS flow_stats_segment_delete;
+CREATE TRIGGER flow_stats_segment_delete AFTER DELETE ON flow_segments
+BEGIN
+    UPDATE flow_stats SET
+        segment_count = segment_count - 1,
+        total_bytes = total_bytes - COALESCE((SELECT size_bytes FROM media_objects WHERE object_id = OLD.object_id), 0),
+        first_start_ns = CASE WHEN OLD.start_ns <= first_start_ns
+            THEN (SELECT MIN(start_ns) FROM flow_segments WHERE flow_id = OLD.flow_id) ELSE first_start_ns END,
+        last_end_ns = CASE WHEN OLD.end_ns >= last_end_ns
+            THEN (SELECT MAX(end_ns) FROM flow_segments WHERE flow_id = OLD.flow_id) ELSE last_end_ns END
+    WHERE flow_id = OLD.flow_id;
+END;
+
+DROP TRIGGER IF EXISTS flow_stats_segment_bounds;
+CREATE TRIGGER flow_stats_segment_bounds AFTER UPDATE OF start_ns, end_ns ON flow_segments
+BEGIN
+    UPDATE flow_stats SET
+        first_start_ns = (SELECT MIN(start_ns) FROM flow_segments WHERE flow_id = NEW.flow_id),
+        last_end_ns = (SELECT MAX(end_ns) FROM flow_segments WHERE flow_id = NEW.flow_id)
+    WHERE flow_id = NEW.flow_id;
+END;
+
+DROP TRIGGER IF EXISTS flow_stats_object_insert;
+CREATE TRIGGER flow_stats_object_insert AFTER INSERT ON media_objects WHEN NEW.size_bytes IS NOT NULL
+BEGIN
+    UPDATE flow_stats SET total_bytes = total_bytes + NEW.size_bytes
+        * (SELECT COUNT(*) FROM flow_segments WHERE flow_id = flow_stats.flow_id AND object_id = NEW.object_id)
+    WHERE flow_id IN (SELECT flow_id FROM flow_segments WHERE object_id = NEW.object_id);
+END;
+
+DROP TRIGGER IF EXISTS flow_stats_object_resize;
+CREATE TRIGGER flow_stats_object_resize AFTER UPDATE OF size_bytes ON media_objects
+WHEN OLD.size_bytes IS NOT NEW.size_bytes
+BEGIN
+    UPDATE flow_stats SET total_bytes = total_bytes + (COALESCE(NEW.size_bytes, 0) - COALESCE(OLD.size_bytes, 0))
+        * (SELECT COUNT(*) FROM flow_segments WHERE flow_id = flow_stats.flow_id AND object_id = NEW.object_id)
+    WHERE flow_id IN (SELECT flow_id FROM flow_segments WHERE object_id = NEW.object_id);
+END;
+
+DROP TRIGGER IF EXISTS flow_stats_object_delete;
+CREATE TRIGGER flow_stats_object_delete AFTER DELETE ON media_objects WHEN OLD.size_bytes IS NOT NULL
+BEGIN
+    UPDATE flow_stats SET total_bytes = total_bytes - OLD.size_bytes
+        * (SELECT COUNT(*) FROM flow_segments WHERE flow_id = flow_stats.flow_id AND object_id = OLD.object_id)
+    WHERE flow_id IN (SELECT flow_id FROM flow_segments WHERE object_id = OLD.object_id);
+END;
+"#;
+
+/// `flow_stats` columns of flow `f` computed from its segments, for filling in
+/// and reconciling the stored ones
+const FLOW_STATS_COMPUTED: &str = "f.id,
+    (SELECT COUNT(*) FROM flow_segments s WHERE s.flow_id = f.id),
+    (SELECT COALESCE(SUM(m.size_bytes), 0) FROM flow_segments s JOIN media_objects m ON m.object_id = s.object_id WHERE s.flow_id = f.id),
+    (SELECT MIN(start_ns) FROM flow_segments s WHERE s.flow_id = f.id),
+    (SELECT MAX(end_ns) FROM flow_segments s WHERE s.flow_id = f.id)";
+
+/// Flow ids buffered ahead of a slow `/flows/ids` client
+const FLOW_ID_STREAM_BUFFER: usize = 256;
+/// Schema version this binary migrates databases to. Bump it with every change
+/// to create_db.sql or `Database::migrate`.
+pub const SCHEMA_VERSION: i64 = 12;
+/// Oldest schema version whose binaries can still run against a database this
+/// binary has migrated. Raise it to SCHEMA_VERSION when a change would break
+/// them, e.g. a column they would leave unset that this binary relies on.
+/// Version 11 retires the legacy bounds view once backfilled, after which range
+/// queries only find segments whose start_ns/end_ns are set. Version 12 serves
+/// content_hash as the ETag, which older binaries would leave stale when they
+/// overwrite an object.
+pub const MIN_COMPATIBLE_SCHEMA_VERSION: i64 = 12;
+
+/// How the schema version recorded in a database compares with this binary's
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaSkew {
+    /// Nothing recorded: a new database, or one from before versions were kept
+    Unversioned,
+    Current,
+    /// Migrated by an older binary; this one brings it up to date
+    Older { found: i64 },
+    /// Migrated by a newer binary that declared this one still compatible
+    NewerCompatible { found: i64 },
+}
+/// Columns bound per row by a segment INSERT
+const SEGMENT_INSERT_COLUMNS: usize = 12;
+/// Rows per multi-row segment INSERT, keeping under SQLite's historical limit
+/// of 999 bound parameters per statement
+const SEGMENT_INSERT_CHUNK_ROWS: usize = 999 / SEGMENT_INSERT_COLUMNS;
 
 #[derive(Clone)]
 pub struct Database {
     pool: Pool<Sqlite>,
+    busy_retries: u32,
+    busy_retry_base: Duration,
+    timerange_parsing: TimerangeParsing,
+    /// Whether range queries still read through `flow_segment_bounds`
+    legacy_bounds: Arc<AtomicBool>,
+    clock: SharedClock,
 }
 
 impl Database {
-    pub async fn new(database_url: &str, _max_connections: u32) -> TamsResult<Self> {
+    pub async fn new(config: &DatabaseConfig) -> TamsResult<Self> {
         // Extract the file path from the sqlite:// URL
+        let database_url = config.url.as_str();
         let file_path = if database_url.starts_with("sqlite:") {
             database_url.strip_prefix("sqlite:").unwrap_or(database_url)
         } else {
             database_url
         };
 
-        let pool = SqlitePool::connect_with(
-            sqlx::sqlite::SqliteConnectOptions::new()
-                .filename(file_path)
-                .create_if_missing(true)
+        let journal_mode = match config.journal_mode {
+            JournalMode::Wal => SqliteJournalMode::Wal,
+            JournalMode::Delete => SqliteJournalMode::Delete,
+            JournalMode::Truncate => SqliteJournalMode::Truncate,
+            JournalMode::Persist => SqliteJournalMode::Persist,
+            JournalMode::Memory => SqliteJournalMode::Memory,
+            JournalMode::Off => SqliteJournalMode::Off,
+        };
+        let synchronous = match config.synchronous {
+            Synchronous::Off => SqliteSynchronous::Off,
+            Synchronous::Normal => SqliteSynchronous::Normal,
+            Synchronous::Full => SqliteSynchronous::Full,
+            Synchronous::Extra => SqliteSynchronous::Extra,
+        };
+
+        // WAL (the default) lets readers carry on while a write is in progress, and
+        // the busy timeout has SQLite wait for a lock instead of failing straight away
+        let pool = SqlitePoolOptions::new()
+            .max_connections(config.max_connections)
+            .acquire_timeout(Duration::from_secs(config.connection_timeout_seconds))
+            .connect_with(
+                SqliteConnectOptions::new()
+                    .filename(file_path)
+                    .create_if_missing(true)
+                    .journal_mode(journal_mode)
+                    .synchronous(synchronous)
+                    .foreign_keys(config.foreign_keys)
+                    .busy_timeout(Duration::from_millis(config.busy_timeout_ms)),
+            )
+            .await?;
+
+        Ok(Database {
+            pool,
+            busy_retries: config.busy_retries,
+            busy_retry_base: Duration::from_millis(config.busy_retry_base_ms),
+            timerange_parsing: config.timerange_parsing,
+            legacy_bounds: Arc::new(AtomicBool::new(true)),
+            clock: system_clock(),
+        })
+    }
+
+    /// Read the time for `updated_at`, `applied_at` and pause stamps from `clock`
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Where range queries read segment bounds from: the legacy bounds view
+    /// until it is retired, then flow_segments itself
+    fn segment_bounds(&self) -> &'static str {
+        if self.legacy_bounds.load(Ordering::Relaxed) {
+            "flow_segment_bounds"
+        } else {
+            "flow_segments"
+        }
+    }
+
+    /// Run a write, retrying with exponential backoff while SQLite reports the
+    /// database busy or locked. Other errors, and the last busy error once the
+    /// retries run out, are returned as they are.
+    async fn retry_busy<T, F, Fut>(&self, mut write: F) -> TamsResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, sqlx::Error>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match write().await {
+                Err(e) if is_busy_error(&e) && attempt < self.busy_retries => {
+                    let delay = self.busy_retry_base * 2u32.saturating_pow(attempt);
+                    attempt += 1;
+                    tracing::warn!("Database busy, retrying write in {:?} (attempt {}): {}", delay, attempt, e);
+                    tokio::time::sleep(delay).await;
+                }
+                result => return Ok(result?),
+            }
+        }
+    }
+
+    /// Open a write transaction on its own pooled connection. See
+    /// [`DatabaseTransaction`] for the isolation it gives.
+    pub async fn begin_transaction(&self) -> TamsResult<DatabaseTransaction> {
+        let conn = self
+            .retry_busy(|| async {
+                let mut conn = self.pool.acquire().await?;
+                sqlx::query("BEGIN IMMEDIATE").execute(&mut *conn).await?;
+                Ok::<_, sqlx::Error>(conn)
+            })
+            .await?;
+        Ok(DatabaseTransaction {
+            conn: Some(conn),
+            segment_bounds: self.segment_bounds(),
+        })
+    }
+
+    /// Compare the database's schema version with this binary's, refusing a
+    /// database migrated by a newer binary that this one isn't compatible with
+    pub async fn check_schema_version(&self) -> TamsResult<SchemaSkew> {
+        let versioned: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations')",
         )
+        .fetch_one(&self.pool)
         .await?;
+        if !versioned {
+            return Ok(SchemaSkew::Unversioned);
+        }
+        let newest: Option<(i64, i64, String)> = sqlx::query_as(
+            "SELECT version, min_compatible_version, binary_version FROM schema_migrations ORDER BY version DESC LIMIT 1",
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some((found, min_compatible, migrated_by)) = newest else {
+            return Ok(SchemaSkew::Unversioned);
+        };
+        match found.cmp(&SCHEMA_VERSION) {
+            std::cmp::Ordering::Equal => Ok(SchemaSkew::Current),
+            std::cmp::Ordering::Less => Ok(SchemaSkew::Older { found }),
+            std::cmp::Ordering::Greater if min_compatible <= SCHEMA_VERSION => Ok(SchemaSkew::NewerCompatible { found }),
+            std::cmp::Ordering::Greater => Err(TamsError::SchemaTooNew(format!(
+                "the database is at schema version {} (migrated by v{}), which needs schema version {} or later; \
+                 this binary (v{}) supports schema version {}. Deploy the newer binary instead",
+                found,
+                migrated_by,
+                min_compatible,
+                env!("CARGO_PKG_VERSION"),
+                SCHEMA_VERSION
+            ))),
+        }
+    }
 
-        Ok(Database { pool })
+    /// The newest schema version recorded in the database
+    pub async fn schema_version(&self) -> TamsResult<Option<i64>> {
+        Ok(sqlx::query_scalar("SELECT MAX(version) FROM schema_migrations")
+            .fetch_one(&self.pool)
+            .await?)
     }
 
     pub async fn migrate(&self) -> TamsResult<()> {
+        let skew = self.check_schema_version().await?;
+        match skew {
+            SchemaSkew::Older { found } => tracing::warn!(
+                "DATABASE SCHEMA UPGRADE: migrating schema version {} to {}; binaries supporting schema versions \
+                 older than {} must not be run against this database afterwards",
+                found,
+                SCHEMA_VERSION,
+                MIN_COMPATIBLE_SCHEMA_VERSION
+            ),
+            SchemaSkew::NewerCompatible { found } => tracing::warn!(
+                "Database schema version {} is newer than this binary's {}; running in compatibility mode, \
+                 upgrade this binary",
+                found,
+                SCHEMA_VERSION
+            ),
+            SchemaSkew::Unversioned | SchemaSkew::Current => {}
+        }
+        // Steps gated on this are skipped by databases already past their version
+        let migrated_from = match skew {
+            SchemaSkew::Unversioned => 0,
+            SchemaSkew::Current => SCHEMA_VERSION,
+            SchemaSkew::Older { found } | SchemaSkew::NewerCompatible { found } => found,
+        };
+
         // Read and execute the schema
         let schema = std::fs::read_to_string("create_db.sql")?;
         sqlx::raw_sql(&schema).execute(&self.pool).await?;
+
+        // Databases created before these columns existed
+        self.ensure_column("webhooks", "id", "TEXT").await?;
+        self.ensure_column("webhooks", "previous_api_key_value", "TEXT").await?;
+        self.ensure_column("webhooks", "previous_key_expires_at", "TEXT").await?;
+        self.ensure_column("media_objects", "metadata", "TEXT").await?;
+        self.ensure_column("flow_segments", "essence_parameters", "TEXT").await?;
+        self.ensure_column("jobs", "target", "TEXT").await?;
+        self.ensure_column("flow_segments", "start_ns", "INTEGER").await?;
+        self.ensure_column("flow_segments", "end_ns", "INTEGER").await?;
+        self.ensure_column("flows", "retention_seconds", "INTEGER").await?;
+        self.ensure_column("flows", "notify_url", "TEXT").await?;
+        self.ensure_column("flows", "notify_secret", "TEXT").await?;
+        self.ensure_column("flows", "owner", "TEXT").await?;
+        self.ensure_column("media_objects", "storage_class", "TEXT").await?;
+        self.ensure_column("media_objects", "format", "TEXT").await?;
+        self.ensure_column("storage_allocations", "storage_class", "TEXT").await?;
+        self.ensure_column("webhooks", "owner", "TEXT").await?;
+        self.ensure_column("deletion_requests", "owner", "TEXT").await?;
+        sqlx::query(
+            "UPDATE deletion_requests SET owner = (SELECT owner FROM flows WHERE flows.id = deletion_requests.flow_id) \
+             WHERE owner IS NULL",
+        )
+        .execute(&self.pool)
+        .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_flows_owner ON flows(owner)")
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_flow_segments_flow_start ON flow_segments(flow_id, start_ns)")
+            .execute(&self.pool)
+            .await?;
+        // Version 10 records the owner of each source
+        if migrated_from < 10 {
+            self.ensure_column("sources", "owner", "TEXT").await?;
+            sqlx::query("CREATE INDEX IF NOT EXISTS idx_sources_owner ON sources(owner)")
+                .execute(&self.pool)
+                .await?;
+        }
+        // Version 12 records a hash of each uploaded object's bytes
+        if migrated_from < 12 {
+            self.ensure_column("media_objects", "content_hash", "TEXT").await?;
+        }
+
+        // Rows left dangling by deletes made while foreign keys weren't enforced.
+        // Orphan segments are only reported, for operators to follow up; a
+        // missing source is cleared from its flows after being logged
+        let orphaned: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT flow_id, COUNT(*) FROM flow_segments WHERE flow_id NOT IN (SELECT id FROM flows) GROUP BY flow_id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        for (flow_id, count) in &orphaned {
+            tracing::warn!("{} segments refer to flow {}, which no longer exists", count, flow_id);
+        }
+        let dangling: Vec<(String, String)> =
+            sqlx::query_as("SELECT id, source_id FROM flows WHERE source_id IS NOT NULL AND source_id NOT IN (SELECT id FROM sources)")
+                .fetch_all(&self.pool)
+                .await?;
+        for (flow_id, source_id) in &dangling {
+            tracing::warn!("Clearing source_id of flow {}: source {} no longer exists", flow_id, source_id);
+        }
+        if !dangling.is_empty() {
+            sqlx::query("UPDATE flows SET source_id = NULL WHERE source_id IS NOT NULL AND source_id NOT IN (SELECT id FROM sources)")
+                .execute(&self.pool)
+                .await?;
+        }
+        self.restrict_source_deletes().await?;
+
+        // Segments stored before their bounds were kept as numbers are read
+        // through this view until the backfill has reached them all. Version 11
+        // indexes those segments and retires the view once they are done
+        if migrated_from < 11 {
+            sqlx::query("CREATE INDEX IF NOT EXISTS idx_flow_segments_unbounded ON flow_segments(start_ns) WHERE start_ns IS NULL")
+                .execute(&self.pool)
+                .await?;
+        }
+        let unbounded: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM flow_segments WHERE start_ns IS NULL)")
+            .fetch_one(&self.pool)
+            .await?;
+        if unbounded {
+            sqlx::raw_sql(SEGMENT_BOUNDS_VIEW).execute(&self.pool).await?;
+        } else {
+            sqlx::query("DROP VIEW IF EXISTS flow_segment_bounds").execute(&self.pool).await?;
+        }
+        self.legacy_bounds.store(unbounded, Ordering::Relaxed);
+
+        // Flows stored before their stats were kept get them computed once here
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_flow_segments_flow_end ON flow_segments(flow_id, end_ns)")
+            .execute(&self.pool)
+            .await?;
+        sqlx::raw_sql(FLOW_STATS_TRIGGERS).execute(&self.pool).await?;
+        let filled = sqlx::query(&format!(
+            "INSERT INTO flow_stats (flow_id, segment_count, total_bytes, first_start_ns, last_end_ns)
+            SELECT {} FROM flows f WHERE NOT EXISTS (SELECT 1 FROM flow_stats WHERE flow_id = f.id)",
+            FLOW_STATS_COMPUTED
+        ))
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        if filled > 0 {
+            tracing::info!("Computed segment stats of {} flows", filled);
+        }
+
+        let unassigned: Vec<String> = sqlx::query_scalar("SELECT url FROM webhooks WHERE id IS NULL")
+            .fetch_all(&self.pool)
+            .await?;
+        for url in unassigned {
+            sqlx::query("UPDATE webhooks SET id = ?1 WHERE url = ?2")
+                .bind(Uuid::new_v4().to_string())
+                .bind(url)
+                .execute(&self.pool)
+                .await?;
+        }
+        sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_webhooks_id ON webhooks(id)")
+            .execute(&self.pool)
+            .await?;
+
+        // flow_references used to be a bare list of flow ids
+        let legacy: Vec<(String, String)> =
+            sqlx::query_as("SELECT object_id, flow_references FROM media_objects WHERE flow_references LIKE '[\"%'")
+                .fetch_all(&self.pool)
+                .await?;
+        for (object_id, stored) in legacy {
+            let references = self
+                .flow_references_with_segments(&object_id, parse_flow_references(&stored))
+                .await?;
+            sqlx::query("UPDATE media_objects SET flow_references = ?1 WHERE object_id = ?2")
+                .bind(serde_json::to_string(&references)?)
+                .bind(object_id)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Text that isn't UTF-8, such as CESU-8 surrogates written by other tools, fails every read of its row
+        for (table, column) in [
+            ("sources", "label"),
+            ("sources", "description"),
+            ("sources", "tags"),
+            ("flows", "label"),
+            ("flows", "description"),
+            ("flows", "tags"),
+        ] {
+            let rows: Vec<(String, Vec<u8>)> =
+                sqlx::query_as(&format!("SELECT id, CAST({} AS BLOB) FROM {} WHERE {} IS NOT NULL", column, table, column))
+                    .fetch_all(&self.pool)
+                    .await?;
+            for (id, stored) in rows {
+                let Cow::Owned(repaired) = String::from_utf8_lossy(&stored) else {
+                    continue;
+                };
+                tracing::warn!("Replacing invalid UTF-8 in {}.{} of row {}", table, column, id);
+                sqlx::query(&format!("UPDATE {} SET {} = ?1 WHERE id = ?2", table, column))
+                    .bind(repaired)
+                    .bind(id)
+                    .execute(&self.pool)
+                    .await?;
+            }
+        }
+
+        // Webhooks used to be stored with dotted event names such as `flow.created`
+        let legacy: Vec<(String, String)> = sqlx::query_as("SELECT url, events FROM webhooks WHERE events LIKE '%.%'")
+            .fetch_all(&self.pool)
+            .await?;
+        for (url, stored) in legacy {
+            let events: Vec<String> = stored
+                .split(',')
+                .map(|event| event.parse::<EventType>().map(|e| e.to_string()).unwrap_or_else(|_| event.to_string()))
+                .collect();
+            sqlx::query("UPDATE webhooks SET events = ?1 WHERE url = ?2")
+                .bind(events.join(","))
+                .bind(url)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // A newer compatible binary's row stays the newest
+        sqlx::query(
+            r#"
+            INSERT INTO schema_migrations (version, min_compatible_version, binary_version, applied_at)
+            VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT(version) DO NOTHING
+            "#,
+        )
+        .bind(SCHEMA_VERSION)
+        .bind(MIN_COMPATIBLE_SCHEMA_VERSION)
+        .bind(env!("CARGO_PKG_VERSION"))
+        .bind(format_rfc3339(&self.clock.now()))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Add a column to an existing table if an older schema lacks it
+    async fn ensure_column(&self, table: &str, column: &str, definition: &str) -> TamsResult<()> {
+        let columns = sqlx::query(&format!("PRAGMA table_info({})", table))
+            .fetch_all(&self.pool)
+            .await?;
+        let exists = columns
+            .iter()
+            .any(|row| row.get::<String, _>("name") == column);
+        if !exists {
+            sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+                .execute(&self.pool)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Sources used to be deletable out from under their flows, whose
+    /// source_id was then set to NULL. SQLite can't alter a foreign key, so
+    /// flows tables from then are rebuilt with ON DELETE RESTRICT instead.
+    async fn restrict_source_deletes(&self) -> TamsResult<()> {
+        let on_delete: Option<String> =
+            sqlx::query_scalar("SELECT on_delete FROM pragma_foreign_key_list('flows') WHERE \"table\" = 'sources'")
+                .fetch_optional(&self.pool)
+                .await?;
+        if on_delete.as_deref() != Some("SET NULL") {
+            return Ok(());
+        }
+        tracing::info!("Rebuilding the flows table so sources with flows can't be deleted");
+
+        // The stored definition includes columns added since by ensure_column
+        let definition: String = sqlx::query_scalar("SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'flows'")
+            .fetch_one(&self.pool)
+            .await?;
+        let columns = definition
+            .find('(')
+            .map(|start| &definition[start..])
+            .ok_or_else(|| TamsError::Internal("Unreadable flows table definition".to_string()))?;
+        let indexes: Vec<String> =
+            sqlx::query_scalar("SELECT sql FROM sqlite_master WHERE type = 'index' AND tbl_name = 'flows' AND sql IS NOT NULL")
+                .fetch_all(&self.pool)
+                .await?;
+
+        // Foreign keys can only be switched off outside a transaction, and only
+        // for one connection, which is closed afterwards rather than returned
+        let mut conn = self.pool.acquire().await?.detach();
+        sqlx::query("PRAGMA foreign_keys = OFF").execute(&mut conn).await?;
+        let rebuilt = async {
+            let mut tx = conn.begin().await?;
+            sqlx::query(&format!("CREATE TABLE flows_rebuild {}", columns.replace("ON DELETE SET NULL", "ON DELETE RESTRICT")))
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("INSERT INTO flows_rebuild SELECT * FROM flows").execute(&mut *tx).await?;
+            sqlx::query("DROP TABLE flows").execute(&mut *tx).await?;
+            sqlx::query("ALTER TABLE flows_rebuild RENAME TO flows").execute(&mut *tx).await?;
+            for index in &indexes {
+                sqlx::query(index).execute(&mut *tx).await?;
+            }
+            let violations = sqlx::query("PRAGMA foreign_key_check").fetch_all(&mut *tx).await?;
+            if !violations.is_empty() {
+                return Err(TamsError::Internal(format!(
+                    "{} rows break foreign keys after rebuilding flows",
+                    violations.len()
+                )));
+            }
+            tx.commit().await?;
+            Ok(())
+        }
+        .await;
+        // Whether or not the rebuild went through, the connection is left
+        // enforcing foreign keys, and closed
+        sqlx::query("PRAGMA foreign_keys = ON").execute(&mut conn).await?;
+        conn.close().await?;
+        rebuilt
+    }
+
+    /// Round-trip a trivial query to confirm the database is reachable
+    pub async fn ping(&self) -> TamsResult<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
         Ok(())
     }
 
@@ -43,23 +636,26 @@ impl Database {
         let source_id = source.id.to_string();
         let format_str = serde_json::to_string(&source.format)?;
         let tags_str = serde_json::to_string(&source.tags)?;
-        let created_at = source.created_at.to_rfc3339();
-        let updated_at = source.updated_at.to_rfc3339();
+        let created_at = format_rfc3339(&source.created_at);
+        let updated_at = format_rfc3339(&source.updated_at);
 
-        sqlx::query!(
-            r#"
-            INSERT INTO sources (id, format, label, description, tags, created_at, updated_at)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
-            "#,
-            source_id,
-            format_str,
-            source.label,
-            source.description,
-            tags_str,
-            created_at,
-            updated_at
-        )
-        .execute(&self.pool)
+        self.retry_busy(|| {
+            sqlx::query!(
+                r#"
+                INSERT INTO sources (id, format, label, description, tags, created_at, updated_at, owner)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                "#,
+                source_id,
+                format_str,
+                source.label,
+                source.description,
+                tags_str,
+                created_at,
+                updated_at,
+                source.owner
+            )
+            .execute(&self.pool)
+        })
         .await?;
 
         Ok(())
@@ -68,7 +664,7 @@ impl Database {
     pub async fn get_source(&self, id: &Uuid) -> TamsResult<Option<Source>> {
         let id_str = id.to_string();
         let rows = sqlx::query!(
-            "SELECT id, format, label, description, tags, created_at, updated_at FROM sources WHERE id = ?1",
+            "SELECT id, format, label, description, tags, created_at, updated_at, owner FROM sources WHERE id = ?1",
             id_str
         )
         .fetch_all(&self.pool)
@@ -83,6 +679,7 @@ impl Database {
                 tags: serde_json::from_str(&row.tags)?,
                 created_at: DateTime::parse_from_rfc3339(&row.created_at)?.with_timezone(&Utc),
                 updated_at: DateTime::parse_from_rfc3339(&row.updated_at)?.with_timezone(&Utc),
+                owner: row.owner.clone(),
             }))
         } else {
             Ok(None)
@@ -93,59 +690,97 @@ impl Database {
         self.get_source(id).await?.ok_or_else(|| TamsError::NotFound("Source not found".to_string()))
     }
 
-    pub async fn list_sources(&self) -> TamsResult<Vec<Source>> {
-        let rows = sqlx::query!(
-            "SELECT id, format, label, description, tags, created_at, updated_at FROM sources"
-        )
-        .fetch_all(&self.pool)
-        .await?;
+    pub async fn list_sources(&self) -> TamsResult<Listing<Source>> {
+        Ok(self.fetch_sources(&SourceFilters::default(), None, None, None).await?.0)
+    }
 
-        let mut sources = Vec::new();
-        for row in rows {
-            sources.push(Source {
-                id: Uuid::parse_str(row.id.as_ref().ok_or_else(|| TamsError::InvalidInput("Missing id".to_string()))?)?,
-                format: serde_json::from_str(&row.format)?,
-                label: row.label,
-                description: row.description,
-                tags: serde_json::from_str(&row.tags)?,
-                created_at: DateTime::parse_from_rfc3339(&row.created_at)?.with_timezone(&Utc),
-                updated_at: DateTime::parse_from_rfc3339(&row.updated_at)?.with_timezone(&Utc),
-            });
+    /// Sources matching `filters` in `(created_at, id)` order after `after`, at
+    /// most `limit` of them, with the key of the next page when there are more
+    async fn fetch_sources(
+        &self,
+        filters: &SourceFilters,
+        owned_by: Option<&str>,
+        after: Option<&PageCursor>,
+        limit: Option<u32>,
+    ) -> TamsResult<(Listing<Source>, Option<String>)> {
+        let mut query = QueryBuilder::new(
+            "SELECT id, format, label, description, tags, created_at, updated_at, owner FROM sources WHERE 1 = 1",
+        );
+        if let Some(owner) = owned_by {
+            query.push(" AND owner = ").push_bind(owner.to_string());
+        }
+        filters.push_conditions(&mut query)?;
+        push_page_conditions(&mut query, after, limit);
+        let rows = query.build().fetch_all(&self.pool).await?;
+        let (rows, next_key) = split_page(rows, limit)?;
+
+        let mut listing = Listing::default();
+        for row in &rows {
+            let parsed = (|| -> TamsResult<Source> {
+                let timestamp = |column: &str| -> TamsResult<DateTime<Utc>> {
+                    Ok(DateTime::parse_from_rfc3339(&row.try_get::<String, _>(column)?)?.with_timezone(&Utc))
+                };
+                Ok(Source {
+                    id: Uuid::parse_str(&row.try_get::<String, _>("id")?)?,
+                    format: serde_json::from_str(&row.try_get::<String, _>("format")?)?,
+                    label: row.try_get("label")?,
+                    description: row.try_get("description")?,
+                    tags: serde_json::from_str(&row.try_get::<String, _>("tags")?)?,
+                    created_at: timestamp("created_at")?,
+                    updated_at: timestamp("updated_at")?,
+                    owner: row.try_get("owner")?,
+                })
+            })();
+            listing.push_parsed(parsed, || format!("sources.id={:?}", row.try_get::<String, _>("id").ok()));
         }
-        Ok(sources)
+        Ok((listing, next_key))
     }
 
     pub async fn update_source(&self, source: &Source) -> TamsResult<()> {
         let source_id = source.id.to_string();
         let format_str = serde_json::to_string(&source.format)?;
         let tags_str = serde_json::to_string(&source.tags)?;
-        let updated_at = source.updated_at.to_rfc3339();
+        let updated_at = format_rfc3339(&source.updated_at);
 
-        sqlx::query!(
-            r#"
-            UPDATE sources 
-            SET format = ?2, label = ?3, description = ?4, tags = ?5, updated_at = ?6
-            WHERE id = ?1
-            "#,
-            source_id,
-            format_str,
-            source.label,
-            source.description,
-            tags_str,
-            updated_at
-        )
-        .execute(&self.pool)
+        self.retry_busy(|| {
+            sqlx::query!(
+                r#"
+                UPDATE sources 
+                SET format = ?2, label = ?3, description = ?4, tags = ?5, updated_at = ?6
+                WHERE id = ?1
+                "#,
+                source_id,
+                format_str,
+                source.label,
+                source.description,
+                tags_str,
+                updated_at
+            )
+            .execute(&self.pool)
+        })
         .await?;
 
         Ok(())
     }
 
-    pub async fn delete_source(&self, id: &Uuid) -> TamsResult<()> {
+    /// Delete a source that no flow refers to; Conflict while any still does
+    pub async fn delete_source(&self, id: &Uuid) -> TamsResult<u64> {
         let id_str = id.to_string();
-        sqlx::query!("DELETE FROM sources WHERE id = ?1", id_str)
-            .execute(&self.pool)
+        let mut tx = self.begin_transaction().await?;
+        // flows.source_id is ON DELETE RESTRICT; checking first gives a clearer error
+        let has_flows: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM flows WHERE source_id = ?1)")
+            .bind(&id_str)
+            .fetch_one(tx.conn())
             .await?;
-        Ok(())
+        if has_flows {
+            tx.rollback().await?;
+            return Err(TamsError::Conflict(format!("Source {} still has flows; delete them first", id)));
+        }
+        let result = sqlx::query!("DELETE FROM sources WHERE id = ?1", id_str)
+            .execute(tx.conn())
+            .await?;
+        tx.commit().await?;
+        Ok(result.rows_affected())
     }
 
     // Flow operations
@@ -162,281 +797,695 @@ impl Database {
         let frame_height = flow.frame_height.map(|v| v as i64);
         let sample_rate = flow.sample_rate.map(|v| v as i64);
         let channels = flow.channels.map(|v| v as i64);
-        let created_at = flow.created_at.to_rfc3339();
-        let updated_at = flow.updated_at.to_rfc3339();
+        let created_at = format_rfc3339(&flow.created_at);
+        let updated_at = format_rfc3339(&flow.updated_at);
+        let retention_seconds = flow.retention_seconds.map(|v| v as i64);
 
-        sqlx::query!(
-            r#"
-            INSERT INTO flows (
-                id, source_id, format, label, description, tags, read_only,
-                max_bit_rate, avg_bit_rate, container, codec, frame_width,
-                frame_height, sample_rate, channels, flow_collection,
-                available_timerange, created_at, updated_at
+        self.retry_busy(|| {
+            sqlx::query!(
+                r#"
+                INSERT INTO flows (
+                    id, source_id, format, label, description, tags, read_only,
+                    max_bit_rate, avg_bit_rate, container, codec, frame_width,
+                    frame_height, sample_rate, channels, flow_collection,
+                    available_timerange, created_at, updated_at, retention_seconds,
+                    notify_url, notify_secret, owner
+                )
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23)
+                "#,
+                flow_id,
+                source_id,
+                format_str,
+                flow.label,
+                flow.description,
+                tags_str,
+                flow.read_only,
+                max_bit_rate,
+                avg_bit_rate,
+                flow.container,
+                flow.codec,
+                frame_width,
+                frame_height,
+                sample_rate,
+                channels,
+                flow_collection_str,
+                available_timerange_str,
+                created_at,
+                updated_at,
+                retention_seconds,
+                flow.notify_url,
+                flow.notify_secret,
+                flow.owner
             )
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)
-            "#,
-            flow_id,
-            source_id,
-            format_str,
-            flow.label,
-            flow.description,
-            tags_str,
-            flow.read_only,
-            max_bit_rate,
-            avg_bit_rate,
-            flow.container,
-            flow.codec,
-            frame_width,
-            frame_height,
-            sample_rate,
-            channels,
-            flow_collection_str,
-            available_timerange_str,
-            created_at,
-            updated_at
-        )
-        .execute(&self.pool)
+            .execute(&self.pool)
+        })
         .await?;
 
         Ok(())
     }
 
     pub async fn get_flow(&self, id: &Uuid) -> TamsResult<Option<Flow>> {
-        let id_str = id.to_string();
-        let rows = sqlx::query!(
-            "SELECT * FROM flows WHERE id = ?1",
-            id_str
-        )
-        .fetch_all(&self.pool)
-        .await?;
-
-        if let Some(row) = rows.first() {
-            let flow_collection = row.flow_collection.as_ref()
-                .and_then(|fc| serde_json::from_str(fc).ok());
-            let available_timerange = row.available_timerange.as_ref()
-                .and_then(|tr| serde_json::from_str(tr).ok());
-                
-            Ok(Some(Flow {
-                id: Uuid::parse_str(row.id.as_ref().ok_or_else(|| TamsError::InvalidInput("Missing id".to_string()))?)?,
-                source_id: row.source_id.as_ref().map(|s| Uuid::parse_str(s)).transpose()?,
-                format: serde_json::from_str(&row.format)?,
-                label: row.label.clone(),
-                description: row.description.clone(),
-                tags: serde_json::from_str(&row.tags)?,
-                read_only: row.read_only.map(|v| v != 0),
-                max_bit_rate: row.max_bit_rate.map(|v| v as u64),
-                avg_bit_rate: row.avg_bit_rate.map(|v| v as u64),
-                container: row.container.clone(),
-                codec: row.codec.clone(),
-                frame_width: row.frame_width.map(|v| v as u32),
-                frame_height: row.frame_height.map(|v| v as u32),
-                sample_rate: row.sample_rate.map(|v| v as u32),
-                channels: row.channels.map(|v| v as u32),
-                flow_collection,
-                available_timerange,
-                created_at: DateTime::parse_from_rfc3339(&row.created_at)?.with_timezone(&Utc),
-                updated_at: DateTime::parse_from_rfc3339(&row.updated_at)?.with_timezone(&Utc),
-            }))
-        } else {
-            Ok(None)
-        }
+        fetch_flow(&self.pool, id).await
     }
 
     pub async fn get_flow_required(&self, id: &Uuid) -> TamsResult<Flow> {
         self.get_flow(id).await?.ok_or_else(|| TamsError::NotFound("Flow not found".to_string()))
     }
 
-    pub async fn list_flows(&self) -> TamsResult<Vec<Flow>> {
-        let rows = sqlx::query!("SELECT * FROM flows")
+    /// List all flows, or only those of `owned_by`. `flow_collection` blobs can
+    /// be large, so they are only deserialized when `include_flow_collection` is set.
+    pub async fn list_flows(&self, include_flow_collection: bool, owned_by: Option<&str>) -> TamsResult<Listing<Flow>> {
+        let rows = sqlx::query!("SELECT * FROM flows WHERE ?1 IS NULL OR owner = ?1", owned_by)
             .fetch_all(&self.pool)
             .await?;
 
-        let mut flows = Vec::new();
+        let mut listing = Listing::default();
         for row in rows {
-            let flow_collection = row.flow_collection.as_ref()
-                .map(|fc| serde_json::from_str(fc).unwrap_or_default());
-            let available_timerange = row.available_timerange.as_ref()
-                .map(|tr| serde_json::from_str(tr).unwrap_or_default());
-                
-            flows.push(Flow {
-                id: Uuid::parse_str(row.id.as_ref().ok_or_else(|| TamsError::InvalidInput("Missing id".to_string()))?)?,
-                source_id: row.source_id.as_ref().map(|s| Uuid::parse_str(s)).transpose()?,
-                format: serde_json::from_str(&row.format)?,
-                label: row.label,
-                description: row.description,
-                tags: serde_json::from_str(&row.tags)?,
-                read_only: row.read_only.map(|v| v != 0),
-                max_bit_rate: row.max_bit_rate.map(|v| v as u64),
-                avg_bit_rate: row.avg_bit_rate.map(|v| v as u64),
-                container: row.container,
-                codec: row.codec,
-                frame_width: row.frame_width.map(|v| v as u32),
-                frame_height: row.frame_height.map(|v| v as u32),
-                sample_rate: row.sample_rate.map(|v| v as u32),
-                channels: row.channels.map(|v| v as u32),
-                flow_collection,
-                available_timerange,
-                created_at: DateTime::parse_from_rfc3339(&row.created_at)?.with_timezone(&Utc),
-                updated_at: DateTime::parse_from_rfc3339(&row.updated_at)?.with_timezone(&Utc),
-            });
+            let parsed = (|| -> TamsResult<Flow> {
+                let flow_collection = row.flow_collection.as_deref()
+                    .filter(|_| include_flow_collection)
+                    .map(serde_json::from_str)
+                    .transpose()?;
+                let available_timerange = row.available_timerange.as_deref().map(serde_json::from_str).transpose()?;
+
+                Ok(Flow {
+                    id: Uuid::parse_str(row.id.as_ref().ok_or_else(|| TamsError::InvalidInput("Missing id".to_string()))?)?,
+                    source_id: row.source_id.as_ref().map(|s| Uuid::parse_str(s)).transpose()?,
+                    format: serde_json::from_str(&row.format)?,
+                    label: row.label.clone(),
+                    description: row.description.clone(),
+                    tags: serde_json::from_str(&row.tags)?,
+                    read_only: row.read_only.map(|v| v != 0),
+                    max_bit_rate: row.max_bit_rate.map(|v| v as u64),
+                    avg_bit_rate: row.avg_bit_rate.map(|v| v as u64),
+                    container: row.container.clone(),
+                    codec: row.codec.clone(),
+                    frame_width: row.frame_width.map(|v| v as u32),
+                    frame_height: row.frame_height.map(|v| v as u32),
+                    sample_rate: row.sample_rate.map(|v| v as u32),
+                    channels: row.channels.map(|v| v as u32),
+                    flow_collection,
+                    available_timerange,
+                    retention_seconds: row.retention_seconds.map(|v| v as u64),
+                    notify_url: row.notify_url.clone(),
+                    notify_secret: row.notify_secret.clone(),
+                    owner: row.owner.clone(),
+                    created_at: DateTime::parse_from_rfc3339(&row.created_at)?.with_timezone(&Utc),
+                    updated_at: DateTime::parse_from_rfc3339(&row.updated_at)?.with_timezone(&Utc),
+                })
+            })();
+            listing.push_parsed(parsed, || format!("flows.id={:?}", row.id));
         }
-        Ok(flows)
+        Ok(listing)
     }
 
-    pub async fn update_flow(&self, flow: &Flow) -> TamsResult<()> {
-        let flow_id = flow.id.to_string();
-        let source_id = flow.source_id.map(|id| id.to_string());
-        let format_str = serde_json::to_string(&flow.format)?;
-        let tags_str = serde_json::to_string(&flow.tags)?;
-        let flow_collection_str = flow.flow_collection.as_ref().map(|fc| serde_json::to_string(fc).unwrap_or_default());
-        let available_timerange_str = flow.available_timerange.as_ref().map(|tr| serde_json::to_string(tr).unwrap_or_default());
-        let max_bit_rate = flow.max_bit_rate.map(|v| v as i64);
-        let avg_bit_rate = flow.avg_bit_rate.map(|v| v as i64);
-        let frame_width = flow.frame_width.map(|v| v as i64);
-        let frame_height = flow.frame_height.map(|v| v as i64);
-        let sample_rate = flow.sample_rate.map(|v| v as i64);
-        let channels = flow.channels.map(|v| v as i64);
-        let updated_at = flow.updated_at.to_rfc3339();
-
-        sqlx::query!(
+    /// The first `per_source` flows of each source by created_at, in one query.
+    /// A source with more flows than that gets `per_source + 1` of them so the
+    /// caller can tell there are more. Flows come grouped by source, and only
+    /// those of `owned_by` count when it is given.
+    pub async fn list_flows_for_sources(
+        &self,
+        source_ids: &[Uuid],
+        per_source: u32,
+        owned_by: Option<&str>,
+    ) -> TamsResult<Listing<Flow>> {
+        let source_ids: Vec<String> = source_ids.iter().map(Uuid::to_string).collect();
+        let rows = sqlx::query(
             r#"
-            UPDATE flows SET
-                source_id = ?2, format = ?3, label = ?4, description = ?5,
-                tags = ?6, read_only = ?7, max_bit_rate = ?8, avg_bit_rate = ?9,
-                container = ?10, codec = ?11, frame_width = ?12, frame_height = ?13,
-                sample_rate = ?14, channels = ?15, flow_collection = ?16,
-                available_timerange = ?17, updated_at = ?18
-            WHERE id = ?1
+            SELECT * FROM (
+                SELECT flows.*, ROW_NUMBER() OVER (PARTITION BY source_id ORDER BY created_at, id) AS source_rank
+                FROM flows
+                WHERE source_id IN (SELECT value FROM json_each(?1)) AND (?3 IS NULL OR owner = ?3)
+            )
+            WHERE source_rank <= ?2
+            ORDER BY source_id, source_rank
             "#,
-            flow_id,
-            source_id,
-            format_str,
-            flow.label,
-            flow.description,
-            tags_str,
-            flow.read_only,
-            max_bit_rate,
-            avg_bit_rate,
-            flow.container,
-            flow.codec,
-            frame_width,
-            frame_height,
-            sample_rate,
-            channels,
-            flow_collection_str,
-            available_timerange_str,
-            updated_at
         )
-        .execute(&self.pool)
+        .bind(serde_json::to_string(&source_ids)?)
+        .bind(per_source as i64 + 1)
+        .bind(owned_by)
+        .fetch_all(&self.pool)
         .await?;
 
-        Ok(())
+        let mut listing = Listing::default();
+        for row in &rows {
+            listing.push_parsed(flow_from_row(row), || format!("flows.id={:?}", row.try_get::<String, _>("id").ok()));
+        }
+        Ok(listing)
     }
 
-    pub async fn delete_flow_segments(&self, id: &Uuid) -> TamsResult<()> {
-        let id_str = id.to_string();
-        sqlx::query!("DELETE FROM flow_segments WHERE flow_id = ?1", id_str)
-            .execute(&self.pool)
-            .await?;
-        Ok(())
+    /// Stream the ids of all flows (optionally only those of one source) in id
+    /// order without loading full rows. Ids arrive on the returned channel; an
+    /// error ends the stream.
+    pub fn stream_flow_ids(&self, source_id: Option<Uuid>, owned_by: Option<String>) -> mpsc::Receiver<TamsResult<String>> {
+        let (tx, rx) = mpsc::channel(FLOW_ID_STREAM_BUFFER);
+        let pool = self.pool.clone();
+        tokio::spawn(async move {
+            let source_id = source_id.map(|id| id.to_string());
+            let mut rows = sqlx::query!(
+                "SELECT id FROM flows WHERE (?1 IS NULL OR source_id = ?1) AND (?2 IS NULL OR owner = ?2) ORDER BY id",
+                source_id,
+                owned_by
+            )
+            .fetch(&pool);
+            while let Some(row) = rows.next().await {
+                let item = row
+                    .map_err(TamsError::from)
+                    .and_then(|row| row.id.ok_or_else(|| TamsError::InvalidInput("Missing id".to_string())));
+                let failed = item.is_err();
+                if tx.send(item).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        rx
     }
 
-    pub async fn delete_flow(&self, id: &Uuid) -> TamsResult<()> {
-        let id_str = id.to_string();
-        sqlx::query!("DELETE FROM flows WHERE id = ?1", id_str)
-            .execute(&self.pool)
-            .await?;
-        Ok(())
+    /// Delete a flow's segments referencing one object, returning the number of rows removed
+    pub async fn delete_flow_segments_for_object(
+        conn: &mut sqlx::SqliteConnection,
+        flow_id: &Uuid,
+        object_id: &str,
+    ) -> TamsResult<u64> {
+        let flow_id_str = flow_id.to_string();
+        let result = sqlx::query!(
+            "DELETE FROM flow_segments WHERE flow_id = ?1 AND object_id = ?2",
+            flow_id_str,
+            object_id
+        )
+        .execute(&mut *conn)
+        .await?;
+        Ok(result.rows_affected())
     }
 
-    // Flow segment operations
-    pub async fn add_flow_segment(&self, segment: &FlowSegment) -> TamsResult<()> {
-        let flow_id = segment.flow_id.to_string();
-        let get_urls_json = serde_json::to_string(&segment.get_urls).unwrap_or_default();
-        let sample_offset = segment.sample_offset.map(|v| v as i64);
-        let sample_count = segment.sample_count.map(|v| v as i64);
-        let key_frame_count = segment.key_frame_count.map(|v| v as i64);
-        let created_at = segment.created_at.to_rfc3339();
+    /// Delete up to `limit` of a flow's segments overlapping `timerange` (all of
+    /// them when unset), earliest first, in one transaction and recompute the
+    /// flow's available_timerange from the rest
+    pub async fn delete_flow_segments_in_timerange(
+        &self,
+        flow_id: &Uuid,
+        timerange: Option<&TimeRange>,
+        limit: u32,
+    ) -> TamsResult<SegmentObjectDeletion> {
+        let flow_id_str = flow_id.to_string();
+        let (window_start, window_end) = match timerange {
+            Some(window) => (Some(timestamp_to_nanos(&window.start)?), window.end.as_deref().map(timestamp_to_nanos).transpose()?),
+            None => (None, None),
+        };
+        let mut tx = self.begin_transaction().await?;
 
-        sqlx::query!(
-            r#"
-            INSERT INTO flow_segments (
-                flow_id, object_id, timerange, ts_offset, sample_offset,
-                sample_count, key_frame_count, get_urls, created_at
-            )
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
-            "#,
-            flow_id,
-            segment.object_id,
-            segment.timerange,
-            segment.ts_offset,
-            sample_offset,
-            sample_count,
-            key_frame_count,
-            get_urls_json,
-            created_at
+        // A segment whose range can't be read has no bounds, so can't be shown to overlap a window
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            &format!(
+                r#"
+                SELECT object_id, timerange FROM {}
+                WHERE flow_id = ?1 AND (?2 IS NULL OR ((?3 IS NULL OR start_ns < ?3) AND end_ns > ?2))
+                ORDER BY start_ns
+                LIMIT ?4
+                "#,
+                self.segment_bounds()
+            ),
         )
-        .execute(&self.pool)
+        .bind(&flow_id_str)
+        .bind(window_start)
+        .bind(window_end)
+        .bind(limit)
+        .fetch_all(tx.conn())
         .await?;
 
-        Ok(())
+        let deletion = self.delete_segment_rows(tx.conn(), &flow_id_str, rows).await?;
+        tx.commit().await?;
+        Ok(deletion)
     }
 
-    pub async fn get_flow_segments(&self, flow_id: &Uuid) -> TamsResult<Vec<FlowSegment>> {
+    /// Delete up to `limit` of a flow's segments that ended at or before
+    /// `cutoff`, oldest first, in one transaction and recompute the flow's
+    /// available_timerange from the rest
+    pub async fn delete_flow_segments_ending_before(
+        &self,
+        flow_id: &Uuid,
+        cutoff: DateTime<Utc>,
+        limit: u32,
+    ) -> TamsResult<SegmentObjectDeletion> {
         let flow_id_str = flow_id.to_string();
-        let rows = sqlx::query!(
-            "SELECT * FROM flow_segments WHERE flow_id = ?1 ORDER BY ts_offset",
-            flow_id_str
+        let cutoff_ns = timestamp_to_nanos(&format_tams_timestamp(&cutoff))?;
+        let mut tx = self.begin_transaction().await?;
+
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            &format!(
+                r#"
+                SELECT object_id, timerange FROM {}
+                WHERE flow_id = ?1 AND end_ns <= ?2
+                ORDER BY end_ns
+                LIMIT ?3
+                "#,
+                self.segment_bounds()
+            ),
         )
-        .fetch_all(&self.pool)
+        .bind(&flow_id_str)
+        .bind(cutoff_ns)
+        .bind(limit)
+        .fetch_all(tx.conn())
         .await?;
 
-        let mut segments = Vec::new();
-        for row in rows {
-            let default_urls = "{}".to_string();
-            let get_urls_str = row.get_urls.as_ref().unwrap_or(&default_urls);
-            let get_urls: HashMap<String, String> = 
-                serde_json::from_str(get_urls_str).unwrap_or_default();
-
-            segments.push(FlowSegment {
-                flow_id: Uuid::parse_str(&row.flow_id)?,
-                object_id: row.object_id,
-                timerange: row.timerange,
-                ts_offset: row.ts_offset,
-                sample_offset: row.sample_offset.map(|v| v as u64),
-                sample_count: row.sample_count.map(|v| v as u64),
-                key_frame_count: row.key_frame_count.map(|v| v as u32),
-                get_urls,
-                created_at: DateTime::parse_from_rfc3339(&row.created_at)?.with_timezone(&Utc),
-            });
-        }
-        Ok(segments)
+        let deletion = self.delete_segment_rows(tx.conn(), &flow_id_str, rows).await?;
+        tx.commit().await?;
+        Ok(deletion)
     }
 
-    // Media object operations
-    pub async fn create_media_object(&self, object: &MediaObject) -> TamsResult<()> {
-        let flow_references_json = serde_json::to_string(&object.flow_references).unwrap_or_default();
-        let size_bytes = object.size_bytes.map(|v| v as i64);
-        let created_at = object.created_at.to_rfc3339();
+    /// Recompute a flow's available_timerange after segments were deleted, from
+    /// the remaining segments that start first and end last
+    async fn update_available_timerange(
+        &self,
+        conn: &mut SqliteConnection,
+        flow_id_str: &str,
+    ) -> TamsResult<Option<TimeRange>> {
+        let first: Option<String> = sqlx::query_scalar(
+            &format!("SELECT timerange FROM {} WHERE flow_id = ?1 AND start_ns IS NOT NULL ORDER BY start_ns LIMIT 1", self.segment_bounds()),
+        )
+        .bind(flow_id_str)
+        .fetch_optional(&mut *conn)
+        .await?;
+        let last: Option<String> = sqlx::query_scalar(
+            &format!("SELECT timerange FROM {} WHERE flow_id = ?1 AND end_ns IS NOT NULL ORDER BY end_ns DESC LIMIT 1", self.segment_bounds()),
+        )
+        .bind(flow_id_str)
+        .fetch_optional(&mut *conn)
+        .await?;
+        let remaining_ranges = first
+            .iter()
+            .chain(&last)
+            .map(|stored| parse_segment_timerange(stored))
+            .collect::<TamsResult<Vec<_>>>()?;
 
+        let available_timerange = covering_timerange(&remaining_ranges)?;
+        let available_timerange_str = available_timerange
+            .as_ref()
+            .map(|tr| serde_json::to_string(tr).unwrap_or_default());
+        let updated_at = format_rfc3339(&self.clock.now());
         sqlx::query!(
-            r#"
-            INSERT INTO media_objects (object_id, size_bytes, mime_type, flow_references, created_at)
-            VALUES (?1, ?2, ?3, ?4, ?5)
-            "#,
-            object.object_id,
-            size_bytes,
-            object.mime_type,
-            flow_references_json,
-            created_at
+            "UPDATE flows SET available_timerange = ?1, updated_at = ?2 WHERE id = ?3",
+            available_timerange_str,
+            updated_at,
+            flow_id_str
         )
-        .execute(&self.pool)
+        .execute(&mut *conn)
         .await?;
+        Ok(available_timerange)
+    }
 
-        Ok(())
+    /// Delete the given `(object_id, timerange)` segment rows of a flow and
+    /// store its available_timerange as covered by the segments left
+    async fn delete_segment_rows(
+        &self,
+        conn: &mut SqliteConnection,
+        flow_id_str: &str,
+        rows: Vec<(String, String)>,
+    ) -> TamsResult<SegmentObjectDeletion> {
+        let mut deleted_ranges = Vec::new();
+        let mut counts: Vec<(String, u64)> = Vec::new();
+        for (object_id, stored) in rows {
+            sqlx::query!(
+                "DELETE FROM flow_segments WHERE flow_id = ?1 AND object_id = ?2 AND timerange = ?3",
+                flow_id_str,
+                object_id,
+                stored
+            )
+            .execute(&mut *conn)
+            .await?;
+            deleted_ranges.extend(parse_segment_timerange(&stored).ok());
+            match counts.iter_mut().find(|(counted, _)| *counted == object_id) {
+                Some((_, deleted)) => *deleted += 1,
+                None => counts.push((object_id, 1)),
+            }
+        }
+
+        let available_timerange = self.update_available_timerange(conn, flow_id_str).await?;
+
+        Ok(SegmentObjectDeletion {
+            counts,
+            deleted_timerange: covering_timerange(&deleted_ranges)?,
+            available_timerange,
+        })
     }
 
-    pub async fn get_media_object(&self, object_id: &str) -> TamsResult<Option<MediaObject>> {
+    /// Number of a flow's segments overlapping `timerange`, or all of them when unset
+    pub async fn count_flow_segments_in_timerange(&self, flow_id: &Uuid, timerange: Option<&TimeRange>) -> TamsResult<u64> {
+        let (window_start, window_end) = match timerange {
+            Some(window) => (Some(timestamp_to_nanos(&window.start)?), window.end.as_deref().map(timestamp_to_nanos).transpose()?),
+            None => (None, None),
+        };
+        let count: i64 = sqlx::query_scalar(
+            &format!("SELECT COUNT(*) FROM {} WHERE flow_id = ?1 AND (?2 IS NULL OR ((?3 IS NULL OR start_ns < ?3) AND end_ns > ?2))", self.segment_bounds()),
+        )
+        .bind(flow_id.to_string())
+        .bind(window_start)
+        .bind(window_end)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(count as u64)
+    }
+
+    /// Delete a flow's segments for each of the given objects in one transaction and
+    /// recompute the flow's available_timerange from the segments that remain
+    pub async fn delete_flow_segments_by_objects(
+        &self,
+        flow_id: &Uuid,
+        object_ids: &[String],
+    ) -> TamsResult<SegmentObjectDeletion> {
+        let flow_id_str = flow_id.to_string();
+        let mut tx = self.begin_transaction().await?;
+        tx.check_flow_writable(flow_id).await?;
+
+        let mut deleted_ranges = Vec::new();
+        let mut counts = Vec::with_capacity(object_ids.len());
+        for object_id in object_ids {
+            let rows = sqlx::query!(
+                "SELECT timerange FROM flow_segments WHERE flow_id = ?1 AND object_id = ?2",
+                flow_id_str,
+                object_id
+            )
+            .fetch_all(tx.conn())
+            .await?;
+            for row in rows {
+                // Unparseable ranges still get deleted, they just can't widen the event range
+                if let Ok(range) = parse_segment_timerange(&row.timerange) {
+                    deleted_ranges.push(range);
+                }
+            }
+
+            let deleted = Self::delete_flow_segments_for_object(tx.conn(), flow_id, object_id).await?;
+            counts.push((object_id.clone(), deleted));
+        }
+
+        let available_timerange = self.update_available_timerange(tx.conn(), &flow_id_str).await?;
+        tx.commit().await?;
+
+        Ok(SegmentObjectDeletion {
+            counts,
+            deleted_timerange: covering_timerange(&deleted_ranges)?,
+            available_timerange,
+        })
+    }
+
+    /// One page of (flow id, stored available_timerange JSON), ordered by id and
+    /// starting after `after_id`
+    pub async fn get_flow_timerange_page(
+        &self,
+        after_id: Option<&str>,
+        limit: u32,
+    ) -> TamsResult<Vec<(String, Option<String>)>> {
+        let after_id = after_id.unwrap_or("");
+        let limit = limit as i64;
+        let rows = sqlx::query!(
+            "SELECT id, available_timerange FROM flows WHERE id > ?1 ORDER BY id LIMIT ?2",
+            after_id,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| row.id.map(|id| (id, row.available_timerange)))
+            .collect())
+    }
+
+    /// Next page of flows' `(id, container, codec)`, in id order after `after_id`
+    pub async fn get_flow_vocabulary_page(
+        &self,
+        after_id: Option<&str>,
+        limit: u32,
+    ) -> TamsResult<Vec<(String, Option<String>, Option<String>)>> {
+        Ok(sqlx::query_as("SELECT id, container, codec FROM flows WHERE id > ?1 ORDER BY id LIMIT ?2")
+            .bind(after_id.unwrap_or(""))
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    /// Rewrite a flow's container and codec, e.g. into their normalized form
+    pub async fn set_flow_vocabulary(&self, flow_id: &str, container: Option<&str>, codec: Option<&str>) -> TamsResult<()> {
+        let updated_at = format_rfc3339(&self.clock.now());
+        self.retry_busy(|| {
+            sqlx::query("UPDATE flows SET container = ?1, codec = ?2, updated_at = ?3 WHERE id = ?4")
+                .bind(container)
+                .bind(codec)
+                .bind(&updated_at)
+                .bind(flow_id)
+                .execute(&self.pool)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Segment stats of a flow, as kept by the `flow_stats` triggers
+    pub async fn get_flow_stats(&self, flow_id: &Uuid) -> TamsResult<FlowStats> {
+        let row: Option<(i64, i64, Option<i64>, Option<i64>)> = sqlx::query_as(
+            "SELECT segment_count, total_bytes, first_start_ns, last_end_ns FROM flow_stats WHERE flow_id = ?1",
+        )
+        .bind(flow_id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some((segment_count, total_bytes, first_start_ns, last_end_ns)) = row else {
+            return Ok(FlowStats::default());
+        };
+        Ok(FlowStats {
+            segment_count: segment_count.max(0) as u64,
+            total_bytes: total_bytes.max(0) as u64,
+            first_timestamp: first_start_ns.map(nanos_to_timestamp),
+            last_timestamp: last_end_ns.map(nanos_to_timestamp),
+        })
+    }
+
+    /// Recompute the stats of up to `limit` flows after `after_id` by id and
+    /// rewrite those that have drifted from their segments. Each batch is one
+    /// statement, so a segment written meanwhile is counted either by the
+    /// recompute or by its trigger, never both. Returns the last flow id of the
+    /// batch with the numbers of flows checked and corrected, or None past the last flow.
+    pub async fn reconcile_flow_stats(&self, after_id: &str, limit: u32) -> TamsResult<Option<(String, u64, u64)>> {
+        let ids: Vec<String> = sqlx::query_scalar("SELECT id FROM flows WHERE id > ?1 ORDER BY id LIMIT ?2")
+            .bind(after_id)
+            .bind(limit.max(1) as i64)
+            .fetch_all(&self.pool)
+            .await?;
+        let Some(last_id) = ids.last() else {
+            return Ok(None);
+        };
+        let sql = format!(
+            "INSERT INTO flow_stats (flow_id, segment_count, total_bytes, first_start_ns, last_end_ns)
+            SELECT {} FROM flows f WHERE f.id > ?1 AND f.id <= ?2
+            ON CONFLICT(flow_id) DO UPDATE SET
+                segment_count = excluded.segment_count,
+                total_bytes = excluded.total_bytes,
+                first_start_ns = excluded.first_start_ns,
+                last_end_ns = excluded.last_end_ns
+            WHERE segment_count != excluded.segment_count
+                OR total_bytes != excluded.total_bytes
+                OR first_start_ns IS NOT excluded.first_start_ns
+                OR last_end_ns IS NOT excluded.last_end_ns",
+            FLOW_STATS_COMPUTED
+        );
+        let corrected = self
+            .retry_busy(|| {
+                sqlx::query(&sql).bind(after_id).bind(last_id).execute(&self.pool)
+            })
+            .await?
+            .rows_affected();
+        Ok(Some((last_id.clone(), ids.len() as u64, corrected)))
+    }
+
+    /// Stored timerange strings of every segment in a flow
+    pub async fn get_segment_timeranges(&self, flow_id: &str) -> TamsResult<Vec<String>> {
+        fetch_segment_timeranges(&self.pool, flow_id).await
+    }
+
+    pub async fn set_flow_available_timerange(&self, flow_id: &str, timerange: Option<&TimeRange>) -> TamsResult<()> {
+        let timerange_str = timerange.map(|tr| serde_json::to_string(tr).unwrap_or_default());
+        let updated_at = format_rfc3339(&self.clock.now());
+        self.retry_busy(|| {
+            sqlx::query!(
+                "UPDATE flows SET available_timerange = ?1, updated_at = ?2 WHERE id = ?3",
+                timerange_str,
+                updated_at,
+                flow_id
+            )
+            .execute(&self.pool)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Delete a flow, returning the number of rows removed. Its segments go with
+    /// it through the `flow_segments` foreign key's ON DELETE CASCADE.
+    pub async fn delete_flow(&self, id: &Uuid) -> TamsResult<u64> {
+        let id_str = id.to_string();
+        let result = self.retry_busy(|| {
+            sqlx::query!("DELETE FROM flows WHERE id = ?1", id_str)
+                .execute(&self.pool)
+        })
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    // Flow segment operations
+    pub async fn add_flow_segment(&self, segment: &FlowSegment) -> TamsResult<()> {
+        let essence_parameters_json = segment.essence_parameters.as_ref().map(serde_json::to_string).transpose()?;
+        self.retry_busy(|| insert_flow_segment(&self.pool, segment, essence_parameters_json.as_deref()))
+            .await
+    }
+
+    /// Insert many segments in one transaction; see [`DatabaseTransaction::add_flow_segments`].
+    /// Everything that isn't returned as a failure is stored, and counted as ingested.
+    pub async fn add_flow_segments(&self, segments: &[FlowSegment]) -> TamsResult<Vec<FlowSegmentFailure>> {
+        if segments.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut tx = self.begin_transaction().await?;
+        let failures = tx.add_flow_segments(segments).await?;
+        tx.commit().await?;
+        metrics().record_segments_ingested(self.clock.now(), (segments.len() - failures.len()) as u64);
+        Ok(failures)
+    }
+
+    pub async fn get_flow_segments(&self, flow_id: &Uuid) -> TamsResult<Listing<FlowSegment>> {
+        let flow_id_str = flow_id.to_string();
+        let rows = sqlx::query!(
+            "SELECT * FROM flow_segments WHERE flow_id = ?1 ORDER BY ts_offset",
+            flow_id_str
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut listing = Listing::default();
+        for row in rows {
+            let parsed = (|| -> TamsResult<FlowSegment> {
+                Ok(FlowSegment {
+                    flow_id: Uuid::parse_str(&row.flow_id)?,
+                    object_id: row.object_id.clone(),
+                    timerange: row.timerange.clone(),
+                    ts_offset: row.ts_offset.clone(),
+                    sample_offset: row.sample_offset.map(|v| v as u64),
+                    sample_count: row.sample_count.map(|v| v as u64),
+                    key_frame_count: row.key_frame_count.map(|v| v as u32),
+                    get_urls: row.get_urls.as_deref().map(serde_json::from_str).transpose()?.unwrap_or_default(),
+                    created_at: DateTime::parse_from_rfc3339(&row.created_at)?.with_timezone(&Utc),
+                    essence_parameters: row
+                        .essence_parameters
+                        .as_deref()
+                        .map(serde_json::from_str)
+                        .transpose()?,
+                })
+            })();
+            listing.push_parsed(parsed, || {
+                format!(
+                    "flow_segments.(flow_id, object_id, timerange)=({}, {}, {})",
+                    row.flow_id, row.object_id, row.timerange
+                )
+            });
+        }
+        Ok(listing)
+    }
+
+    // Media object operations
+    pub async fn create_media_object(&self, object: &MediaObject) -> TamsResult<()> {
+        let flow_references_json = serde_json::to_string(&object.flow_references).unwrap_or_default();
+        let size_bytes = object.size_bytes.map(|v| v as i64);
+        let created_at = format_rfc3339(&object.created_at);
+        let metadata_json = serde_json::to_string(&object.metadata)?;
+        let format_json = object.format.as_ref().map(serde_json::to_string).transpose()?;
+
+        self.retry_busy(|| {
+            sqlx::query!(
+                r#"
+                INSERT INTO media_objects (object_id, size_bytes, mime_type, flow_references, created_at, metadata, storage_class, format, content_hash)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                "#,
+                object.object_id,
+                size_bytes,
+                object.mime_type,
+                flow_references_json,
+                created_at,
+                metadata_json,
+                object.storage_class,
+                format_json,
+                object.content_hash
+            )
+            .execute(&self.pool)
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Record an uploaded object, or refresh the size, storage class and content
+    /// hash of the existing record when its bytes were uploaded again; the rest
+    /// of an existing record is kept
+    pub async fn record_uploaded_object(&self, object: &MediaObject) -> TamsResult<()> {
+        let flow_references_json = serde_json::to_string(&object.flow_references)?;
+        let size_bytes = object.size_bytes.map(|v| v as i64);
+        let created_at = format_rfc3339(&object.created_at);
+        let metadata_json = serde_json::to_string(&object.metadata)?;
+        let format_json = object.format.as_ref().map(serde_json::to_string).transpose()?;
+
+        self.retry_busy(|| {
+            sqlx::query(
+                r#"
+                INSERT INTO media_objects (object_id, size_bytes, mime_type, flow_references, created_at, metadata, storage_class, format, content_hash)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                ON CONFLICT(object_id) DO UPDATE SET size_bytes = excluded.size_bytes, storage_class = excluded.storage_class,
+                    content_hash = excluded.content_hash
+                "#,
+            )
+            .bind(&object.object_id)
+            .bind(size_bytes)
+            .bind(&object.mime_type)
+            .bind(&flow_references_json)
+            .bind(&created_at)
+            .bind(&metadata_json)
+            .bind(&object.storage_class)
+            .bind(&format_json)
+            .bind(&object.content_hash)
+            .execute(&self.pool)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Up to `limit` objects of the flow's segments that aren't recorded in
+    /// `storage_class` (None for the default storage)
+    pub async fn get_flow_objects_outside_class(
+        &self,
+        flow_id: &Uuid,
+        storage_class: Option<&str>,
+        limit: u32,
+    ) -> TamsResult<Vec<String>> {
+        Ok(sqlx::query_scalar(
+            r#"
+            SELECT DISTINCT m.object_id FROM flow_segments s JOIN media_objects m ON m.object_id = s.object_id
+            WHERE s.flow_id = ?1 AND m.storage_class IS NOT ?2
+            LIMIT ?3
+            "#,
+        )
+        .bind(flow_id.to_string())
+        .bind(storage_class)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Record that an object now lives in `storage_class`
+    pub async fn set_media_object_storage_class(&self, object_id: &str, storage_class: Option<&str>) -> TamsResult<()> {
+        self.retry_busy(|| {
+            sqlx::query("UPDATE media_objects SET storage_class = ?1 WHERE object_id = ?2")
+                .bind(storage_class)
+                .bind(object_id)
+                .execute(&self.pool)
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_media_object(&self, object_id: &str) -> TamsResult<Option<MediaObject>> {
         let rows = sqlx::query!(
             "SELECT * FROM media_objects WHERE object_id = ?1",
             object_id
@@ -445,7 +1494,13 @@ impl Database {
         .await?;
 
         if let Some(row) = rows.first() {
-            let flow_references: Vec<Uuid> = serde_json::from_str(&row.flow_references).unwrap_or_default();
+            let flow_references = self
+                .flow_references_with_segments(object_id, parse_flow_references(&row.flow_references))
+                .await?;
+            let metadata: HashMap<String, String> = row.metadata.as_deref()
+                .map(serde_json::from_str)
+                .transpose()?
+                .unwrap_or_default();
 
             Ok(Some(MediaObject {
                 object_id: row.object_id.as_ref().ok_or_else(|| TamsError::InvalidInput("Missing object_id".to_string()))?.clone(),
@@ -453,31 +1508,129 @@ impl Database {
                 mime_type: row.mime_type.clone(),
                 flow_references,
                 created_at: DateTime::parse_from_rfc3339(&row.created_at)?.with_timezone(&Utc),
+                metadata,
+                storage_class: row.storage_class.clone(),
+                format: row.format.as_deref().map(serde_json::from_str).transpose()?,
+                content_hash: row.content_hash.clone(),
             }))
         } else {
             Ok(None)
         }
     }
 
+    /// Fill in each flow reference's timerange from the segments that use the
+    /// object, adding references for flows whose segments use it
+    async fn flow_references_with_segments(
+        &self,
+        object_id: &str,
+        mut references: Vec<FlowReference>,
+    ) -> TamsResult<Vec<FlowReference>> {
+        let rows = sqlx::query!(
+            "SELECT flow_id, timerange FROM flow_segments WHERE object_id = ?1 ORDER BY flow_id",
+            object_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut ranges_by_flow: Vec<(Uuid, Vec<TimeRange>)> = Vec::new();
+        for row in rows {
+            let (Ok(flow_id), Ok(range)) = (Uuid::parse_str(&row.flow_id), parse_segment_timerange(&row.timerange)) else {
+                tracing::warn!("Skipping unparseable segment of object {} in flow {}", object_id, row.flow_id);
+                continue;
+            };
+            match ranges_by_flow.last_mut() {
+                Some((last, ranges)) if *last == flow_id => ranges.push(range),
+                _ => ranges_by_flow.push((flow_id, vec![range])),
+            }
+        }
+
+        for (flow_id, ranges) in ranges_by_flow {
+            let timerange = covering_timerange(&ranges)?;
+            match references.iter_mut().find(|reference| reference.flow_id == flow_id) {
+                Some(reference) => reference.timerange = timerange,
+                None => references.push(FlowReference { flow_id, timerange }),
+            }
+        }
+        Ok(references)
+    }
+
+    /// Merge entries into an object's user metadata, returning the number of rows updated
+    pub async fn merge_media_object_metadata(&self, object_id: &str, entries: &HashMap<String, String>) -> TamsResult<u64> {
+        let mut tx = self.begin_transaction().await?;
+        let Some(row) = sqlx::query!("SELECT metadata FROM media_objects WHERE object_id = ?1", object_id)
+            .fetch_optional(tx.conn())
+            .await?
+        else {
+            return Ok(0);
+        };
+
+        let mut metadata: HashMap<String, String> = row.metadata.as_deref()
+            .map(serde_json::from_str)
+            .transpose()?
+            .unwrap_or_default();
+        metadata.extend(entries.iter().map(|(k, v)| (k.clone(), v.clone())));
+        let metadata_json = serde_json::to_string(&metadata)?;
+
+        let result = sqlx::query!(
+            "UPDATE media_objects SET metadata = ?1 WHERE object_id = ?2",
+            metadata_json,
+            object_id
+        )
+        .execute(tx.conn())
+        .await?;
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
     pub async fn get_media_object_required(&self, object_id: &str) -> TamsResult<MediaObject> {
         self.get_media_object(object_id).await?.ok_or_else(|| TamsError::NotFound("Media object not found".to_string()))
     }
 
-    // Webhook operations
-    pub async fn create_webhook(&self, webhook: &Webhook) -> TamsResult<()> {
-        let events_str = webhook.events.join(",");
-        
-        sqlx::query!(
+    /// Those of `object_ids` that exist, read in one query and keyed by id. Flow
+    /// references are the stored ones, or the first flow with a segment using the
+    /// object when none were stored, so `primary_flow_id` agrees with
+    /// `get_media_object`; their timeranges aren't filled in.
+    pub async fn get_media_objects(&self, object_ids: &[String]) -> TamsResult<HashMap<String, MediaObject>> {
+        let rows = sqlx::query(
             r#"
-            INSERT INTO webhooks (url, api_key_name, api_key_value, events)
-            VALUES (?1, ?2, ?3, ?4)
+            SELECT m.*, (SELECT MIN(s.flow_id) FROM flow_segments s WHERE s.object_id = m.object_id) AS segment_flow_id
+            FROM media_objects m
+            WHERE m.object_id IN (SELECT value FROM json_each(?1))
             "#,
-            webhook.url,
-            webhook.api_key_name,
-            webhook.api_key_value,
-            events_str
         )
-        .execute(&self.pool)
+        .bind(serde_json::to_string(object_ids)?)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut objects = HashMap::with_capacity(rows.len());
+        for row in &rows {
+            let object = media_object_from_row(row)?;
+            objects.insert(object.object_id.clone(), object);
+        }
+        Ok(objects)
+    }
+
+    // Webhook operations
+    /// Store a webhook; `owner` limits it to events about that principal's flows
+    pub async fn create_webhook(&self, webhook: &Webhook, owner: Option<&str>) -> TamsResult<()> {
+        let events_str = webhook.events.join(",");
+        let id_str = webhook.id.map(|id| id.to_string());
+        
+        self.retry_busy(|| {
+            sqlx::query!(
+                r#"
+                INSERT INTO webhooks (url, api_key_name, api_key_value, events, id, owner)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                "#,
+                webhook.url,
+                webhook.api_key_name,
+                webhook.api_key_value,
+                events_str,
+                id_str,
+                owner
+            )
+            .execute(&self.pool)
+        })
         .await?;
 
         Ok(())
@@ -495,6 +1648,7 @@ impl Database {
         let mut webhooks = Vec::new();
         for row in rows {
             webhooks.push(Webhook {
+                id: row.id.as_deref().map(Uuid::parse_str).transpose()?,
                 url: row.url.ok_or_else(|| TamsError::InvalidInput("Missing url".to_string()))?,
                 api_key_name: row.api_key_name,
                 api_key_value: row.api_key_value,
@@ -512,6 +1666,7 @@ impl Database {
         let mut webhooks = Vec::new();
         for row in rows {
             webhooks.push(Webhook {
+                id: row.id.as_deref().map(Uuid::parse_str).transpose()?,
                 url: row.url.ok_or_else(|| TamsError::InvalidInput("Missing url".to_string()))?,
                 api_key_name: row.api_key_name,
                 api_key_value: None, // Don't return the actual key value for security
@@ -521,52 +1676,218 @@ impl Database {
         Ok(webhooks)
     }
 
-    // Deletion request operations
-    pub async fn create_deletion_request(&self, request: &DeletionRequest) -> TamsResult<()> {
-        let flow_id_str = request.flow_id.to_string();
-        let created_at = request.created_at.to_rfc3339();
-        let updated_at = request.updated_at.to_rfc3339();
+    /// `(flow_id, notify_url, notify_secret)` of each flow with its own callback
+    pub async fn get_flow_webhooks(&self) -> TamsResult<Vec<(Uuid, String, String)>> {
+        let rows: Vec<(String, String, Option<String>)> =
+            sqlx::query_as("SELECT id, notify_url, notify_secret FROM flows WHERE notify_url IS NOT NULL")
+                .fetch_all(&self.pool)
+                .await?;
+        rows.into_iter()
+            .map(|(id, url, secret)| Ok((Uuid::parse_str(&id)?, url, secret.unwrap_or_default())))
+            .collect()
+    }
 
-        sqlx::query!(
-            r#"
-            INSERT INTO deletion_requests (id, flow_id, timerange, status, progress, created_at, updated_at)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
-            "#,
-            request.id,
-            flow_id_str,
-            request.timerange,
-            request.status,
-            request.progress,
-            created_at,
-            updated_at
-        )
-        .execute(&self.pool)
+    /// Load every webhook with its delivery keys for the dispatcher
+    pub async fn get_stored_webhooks(&self) -> TamsResult<Vec<StoredWebhook>> {
+        let rows = sqlx::query!("SELECT * FROM webhooks")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut webhooks = Vec::new();
+        for row in rows {
+            let previous_key = match (row.previous_api_key_value, row.previous_key_expires_at) {
+                (Some(api_key_value), Some(expires_at)) => Some(PreviousWebhookKey {
+                    api_key_value,
+                    expires_at: DateTime::parse_from_rfc3339(&expires_at)?.with_timezone(&Utc),
+                }),
+                _ => None,
+            };
+            webhooks.push(StoredWebhook {
+                webhook: Webhook {
+                    id: row.id.as_deref().map(Uuid::parse_str).transpose()?,
+                    url: row.url.ok_or_else(|| TamsError::InvalidInput("Missing url".to_string()))?,
+                    api_key_name: row.api_key_name,
+                    api_key_value: None,
+                    events: row.events.split(',').map(|s| s.to_string()).collect(),
+                },
+                api_key_value: row.api_key_value.unwrap_or_default(),
+                previous_key,
+                owner: row.owner,
+            });
+        }
+        Ok(webhooks)
+    }
+
+    /// Replace a webhook's api_key_value, keeping the old value as the previous key
+    /// until `previous_expires_at`. Returns None when no webhook has the given id,
+    /// or when `owned_by` is given and the webhook isn't theirs.
+    pub async fn rotate_webhook_secret(
+        &self,
+        id: &Uuid,
+        new_api_key_value: &str,
+        previous_expires_at: DateTime<Utc>,
+        owned_by: Option<&str>,
+    ) -> TamsResult<Option<StoredWebhook>> {
+        let id_str = id.to_string();
+        let expires_at_str = format_rfc3339(&previous_expires_at);
+        let result = self.retry_busy(|| {
+            sqlx::query!(
+                r#"
+                UPDATE webhooks
+                SET previous_api_key_value = api_key_value,
+                    previous_key_expires_at = ?1,
+                    api_key_value = ?2
+                WHERE id = ?3 AND (?4 IS NULL OR owner = ?4)
+                "#,
+                expires_at_str,
+                new_api_key_value,
+                id_str,
+                owned_by
+            )
+            .execute(&self.pool)
+        })
         .await?;
 
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+        Ok(self
+            .get_stored_webhooks()
+            .await?
+            .into_iter()
+            .find(|stored| stored.webhook.id == Some(*id)))
+    }
+
+    /// Stored (url, api_key_value, previous_api_key_value) for every webhook
+    pub async fn get_webhook_key_values(&self) -> TamsResult<Vec<(String, Option<String>, Option<String>)>> {
+        let rows = sqlx::query!("SELECT url, api_key_value, previous_api_key_value FROM webhooks")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                let url = row.url.ok_or_else(|| TamsError::InvalidInput("Missing url".to_string()))?;
+                Ok((url, row.api_key_value, row.previous_api_key_value))
+            })
+            .collect()
+    }
+
+    pub async fn set_webhook_key_values(
+        &self,
+        url: &str,
+        api_key_value: Option<&str>,
+        previous_api_key_value: Option<&str>,
+    ) -> TamsResult<()> {
+        self.retry_busy(|| {
+            sqlx::query!(
+                "UPDATE webhooks SET api_key_value = ?1, previous_api_key_value = ?2 WHERE url = ?3",
+                api_key_value,
+                previous_api_key_value,
+                url
+            )
+            .execute(&self.pool)
+        })
+        .await?;
         Ok(())
     }
 
-    pub async fn get_deletion_requests(&self) -> TamsResult<Vec<DeletionRequest>> {
-        let rows = sqlx::query!("SELECT * FROM deletion_requests ORDER BY created_at DESC")
+    /// Drop previous webhook keys whose overlap window has ended
+    pub async fn purge_expired_webhook_keys(&self, now: DateTime<Utc>) -> TamsResult<u64> {
+        let now_str = format_rfc3339(&now);
+        let result = self.retry_busy(|| {
+            sqlx::query!(
+                r#"
+                UPDATE webhooks
+                SET previous_api_key_value = NULL, previous_key_expires_at = NULL
+                WHERE previous_key_expires_at IS NOT NULL AND previous_key_expires_at <= ?1
+                "#,
+                now_str
+            )
+            .execute(&self.pool)
+        })
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    // Ingest pause operations
+    pub async fn get_ingest_pauses(&self) -> TamsResult<Vec<String>> {
+        let rows = sqlx::query!("SELECT scope FROM ingest_pauses")
             .fetch_all(&self.pool)
             .await?;
+        Ok(rows.into_iter().filter_map(|row| row.scope).collect())
+    }
+
+    pub async fn set_ingest_pause(&self, scope: &str, paused: bool) -> TamsResult<()> {
+        if paused {
+            let paused_at = format_rfc3339(&self.clock.now());
+            self.retry_busy(|| {
+                sqlx::query!(
+                    "INSERT INTO ingest_pauses (scope, paused_at) VALUES (?1, ?2) ON CONFLICT(scope) DO NOTHING",
+                    scope,
+                    paused_at
+                )
+                .execute(&self.pool)
+            })
+            .await?;
+        } else {
+            self.retry_busy(|| {
+                sqlx::query!("DELETE FROM ingest_pauses WHERE scope = ?1", scope)
+                    .execute(&self.pool)
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    // Storage allocation operations
 
-        let mut requests = Vec::new();
+    /// Forget allocations that expired before `expired_before`, returning how many
+    pub async fn purge_expired_storage_allocations(&self, expired_before: DateTime<Utc>) -> TamsResult<u64> {
+        let expired_before = format_rfc3339(&expired_before);
+        let result = self.retry_busy(|| {
+            sqlx::query!(
+                "DELETE FROM storage_allocations WHERE expires_at IS NOT NULL AND expires_at <= ?1",
+                expired_before
+            )
+            .execute(&self.pool)
+        })
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// The recorded allocation for an object id, flagged as already allocated
+    pub async fn get_storage_allocation(&self, object_id: &str) -> TamsResult<Option<StorageObject>> {
+        fetch_storage_allocation(&self.pool, object_id).await
+    }
+
+    // Deletion request operations
+    /// All deletion requests, newest first, or only those for flows of `owned_by`
+    pub async fn get_deletion_requests(&self, owned_by: Option<&str>) -> TamsResult<Listing<DeletionRequest>> {
+        let rows = sqlx::query!(
+            "SELECT * FROM deletion_requests WHERE ?1 IS NULL OR owner = ?1 ORDER BY created_at DESC",
+            owned_by
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut listing = Listing::default();
         for row in rows {
-            let flow_id_str = row.flow_id.as_ref().ok_or_else(|| TamsError::InvalidInput("Missing flow_id".to_string()))?;
-            let progress = row.progress.as_ref().and_then(|p| p.parse::<i32>().ok());
-            
-            requests.push(DeletionRequest {
-                id: row.id.ok_or_else(|| TamsError::InvalidInput("Missing id".to_string()))?,
-                flow_id: Uuid::parse_str(flow_id_str)?,
-                timerange: row.timerange,
-                status: row.status,
-                progress,
-                created_at: DateTime::parse_from_rfc3339(&row.created_at)?.with_timezone(&Utc),
-                updated_at: DateTime::parse_from_rfc3339(&row.updated_at)?.with_timezone(&Utc),
-            });
+            let parsed = (|| -> TamsResult<DeletionRequest> {
+                let flow_id_str = row.flow_id.as_ref().ok_or_else(|| TamsError::InvalidInput("Missing flow_id".to_string()))?;
+                let progress = row.progress.as_ref().and_then(|p| p.parse::<i32>().ok());
+
+                Ok(DeletionRequest {
+                    id: row.id.clone().ok_or_else(|| TamsError::InvalidInput("Missing id".to_string()))?,
+                    flow_id: Uuid::parse_str(flow_id_str)?,
+                    timerange: row.timerange.clone(),
+                    status: row.status.clone(),
+                    progress,
+                    created_at: DateTime::parse_from_rfc3339(&row.created_at)?.with_timezone(&Utc),
+                    updated_at: DateTime::parse_from_rfc3339(&row.updated_at)?.with_timezone(&Utc),
+                })
+            })();
+            listing.push_parsed(parsed, || format!("deletion_requests.id={:?}", row.id));
         }
-        Ok(requests)
+        Ok(listing)
     }
 
     pub async fn get_deletion_request(&self, id: &str) -> TamsResult<Option<DeletionRequest>> {
@@ -595,58 +1916,2779 @@ impl Database {
         }
     }
 
-    pub async fn get_deletion_request_required(&self, id: &str) -> TamsResult<DeletionRequest> {
-        self.get_deletion_request(id).await?.ok_or_else(|| TamsError::NotFound("Deletion request not found".to_string()))
+    /// Owner of the flow a deletion request is for, as recorded with the request
+    pub async fn get_deletion_request_owner(&self, id: &str) -> TamsResult<Option<String>> {
+        let owner: Option<Option<String>> = sqlx::query_scalar("SELECT owner FROM deletion_requests WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(owner.flatten())
     }
 
-    // Helper methods for handlers
-    pub async fn get_sources(&self, _limit: u32, _page: Option<&str>) -> TamsResult<Vec<Source>> {
-        self.list_sources().await
+    pub async fn get_deletion_requests_for_flow(&self, flow_id: &Uuid) -> TamsResult<Listing<DeletionRequest>> {
+        fetch_deletion_requests_for_flow(&self.pool, flow_id).await
     }
 
-    pub async fn get_flows(&self, _limit: u32, _page: Option<&str>) -> TamsResult<Vec<Flow>> {
-        self.list_flows().await
+    /// Replace a deletion request's timerange if it is still pending, returning the
+    /// number of rows updated
+    pub async fn update_pending_deletion_request_timerange(&self, id: &str, timerange: &str) -> TamsResult<u64> {
+        let updated_at = format_rfc3339(&self.clock.now());
+        let result = self.retry_busy(|| {
+            sqlx::query!(
+                "UPDATE deletion_requests SET timerange = ?1, updated_at = ?2 WHERE id = ?3 AND status = 'pending'",
+                timerange,
+                updated_at,
+                id
+            )
+            .execute(&self.pool)
+        })
+        .await?;
+        Ok(result.rows_affected())
     }
 
-    pub async fn delete_flow_segments_by_timerange(&self, flow_id: &Uuid, _timerange: &TimeRange) -> TamsResult<()> {
-        // For now, delete all segments for the flow
-        // In a real implementation, you'd filter by timerange
-        self.delete_flow_segments(flow_id).await
-    }
+    /// Delete a flow and cancel its active deletion requests in one transaction,
+    /// returning (flows deleted, requests cancelled). Under the `Reject` policy
+    /// active requests instead roll the delete back with 409 Conflict; they are
+    /// looked for once the delete holds the write lock, so none can be created
+    /// in between.
+    pub async fn delete_flow_cancelling_deletion_requests(
+        &self,
+        id: &Uuid,
+        policy: ActiveDeletionRequestPolicy,
+    ) -> TamsResult<(u64, u64)> {
+        let id_str = id.to_string();
+        let cancelled_status = DeletionRequest::CANCELLED;
+        let updated_at = format_rfc3339(&self.clock.now());
+        let mut tx = self.begin_transaction().await?;
 
-    pub async fn get_flow_segments_by_timerange(
-        &self, 
-        flow_id: &Uuid, 
-        _timerange: Option<&TimeRange>, 
-        _limit: u32
-    ) -> TamsResult<Vec<FlowSegment>> {
-        // For now, return all segments for the flow
-        // In a real implementation, you'd filter by timerange and limit
-        self.get_flow_segments(flow_id).await
-    }
-}
+        let deleted = sqlx::query!("DELETE FROM flows WHERE id = ?1", id_str)
+            .execute(tx.conn())
+            .await?
+            .rows_affected();
+        if deleted > 0 && policy == ActiveDeletionRequestPolicy::Reject {
+            let active: Vec<String> = sqlx::query_scalar(
+                "SELECT id FROM deletion_requests WHERE flow_id = ?1 AND status IN ('pending', 'in_progress') ORDER BY created_at",
+            )
+            .bind(&id_str)
+            .fetch_all(tx.conn())
+            .await?;
+            if !active.is_empty() {
+                tx.rollback().await?;
+                return Err(TamsError::Conflict(format!(
+                    "Flow {} has active deletion requests: {}",
+                    id,
+                    active.join(", ")
+                )));
+            }
+        }
+        let cancelled = if deleted == 0 {
+            0
+        } else {
+            sqlx::query!(
+                r#"
+                UPDATE deletion_requests SET status = ?1, updated_at = ?2
+                WHERE flow_id = ?3 AND status IN ('pending', 'in_progress')
+                "#,
+                cancelled_status,
+                updated_at,
+                id_str
+            )
+            .execute(tx.conn())
+            .await?
+            .rows_affected()
+        };
 
-// Filter structs for queries
-#[derive(Debug, Default)]
-pub struct SourceFilters {
-    pub format: Option<ContentFormat>,
-    pub label: Option<String>,
-}
+        tx.commit().await?;
+        Ok((deleted, cancelled))
+    }
 
-#[derive(Debug, Default)]
-pub struct FlowFilters {
-    pub source_id: Option<Uuid>,
-    pub format: Option<ContentFormat>,
-    pub label: Option<String>,
-    pub codec: Option<String>,
-    pub frame_width: Option<u32>,
-    pub frame_height: Option<u32>,
-    pub timerange: Option<TimeRange>,
-}
+    /// Record a deletion request together with the job that will carry it out, so
+    /// neither exists without the other
+    pub async fn create_deletion_request_with_job(&self, request: &DeletionRequest, job: &Job) -> TamsResult<()> {
+        let flow_id_str = request.flow_id.to_string();
+        let created_at = format_rfc3339(&request.created_at);
+        let updated_at = format_rfc3339(&request.updated_at);
+        let mut tx = self.begin_transaction().await?;
 
-#[derive(Debug, Default)]
-pub struct FlowSegmentFilters {
-    pub object_id: Option<String>,
-    pub timerange: Option<TimeRange>,
-    pub reverse_order: Option<bool>,
-} 
\ No newline at end of file
+        sqlx::query!(
+            r#"
+            INSERT INTO deletion_requests (id, flow_id, timerange, status, progress, created_at, updated_at, owner)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, (SELECT owner FROM flows WHERE id = ?2))
+            "#,
+            request.id,
+            flow_id_str,
+            request.timerange,
+            request.status,
+            request.progress,
+            created_at,
+            updated_at
+        )
+        .execute(tx.conn())
+        .await?;
+        Self::insert_job(tx.conn(), job).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Move a deletion request to a new status unless it was cancelled meanwhile,
+    /// returning whether it was updated
+    pub async fn set_deletion_request_status(&self, id: &str, status: &str, progress: Option<i32>) -> TamsResult<bool> {
+        let updated_at = format_rfc3339(&self.clock.now());
+        let cancelled_status = DeletionRequest::CANCELLED;
+        let result = self.retry_busy(|| {
+            sqlx::query!(
+                "UPDATE deletion_requests SET status = ?1, progress = ?2, updated_at = ?3 WHERE id = ?4 AND status != ?5",
+                status,
+                progress,
+                updated_at,
+                id,
+                cancelled_status
+            )
+            .execute(&self.pool)
+        })
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Active deletion requests with no live job of `job_kind` carrying them out
+    pub async fn get_unqueued_deletion_request_ids(&self, job_kind: &str) -> TamsResult<Vec<String>> {
+        let ids = sqlx::query_scalar(
+            r#"
+            SELECT id FROM deletion_requests
+            WHERE status IN (?1, ?2) AND NOT EXISTS (
+                SELECT 1 FROM jobs
+                WHERE kind = ?3 AND status IN (?4, ?5)
+                AND json_extract(payload, '$.deletion_request_id') = deletion_requests.id
+            )
+            "#,
+        )
+        .bind(DeletionRequest::PENDING)
+        .bind(DeletionRequest::IN_PROGRESS)
+        .bind(job_kind)
+        .bind(Job::PENDING)
+        .bind(Job::RUNNING)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(ids)
+    }
+
+    /// Flows with a retention policy and no pending or running `job_kind` job
+    pub async fn get_unqueued_retention_flow_ids(&self, job_kind: &str) -> TamsResult<Vec<String>> {
+        let ids = sqlx::query_scalar(
+            r#"
+            SELECT id FROM flows
+            WHERE retention_seconds IS NOT NULL AND NOT EXISTS (
+                SELECT 1 FROM jobs
+                WHERE kind = ?1 AND status IN (?2, ?3) AND target = flows.id
+            )
+            "#,
+        )
+        .bind(job_kind)
+        .bind(Job::PENDING)
+        .bind(Job::RUNNING)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(ids)
+    }
+
+    /// Up to `limit` objects that name the flow among their references, no
+    /// longer back any segment and were registered before `created_before`,
+    /// each with the storage class and format that place its bytes
+    pub async fn list_orphaned_flow_objects(
+        &self,
+        flow_id: &Uuid,
+        created_before: DateTime<Utc>,
+        limit: u32,
+    ) -> TamsResult<Vec<(String, Option<String>, Option<ContentFormat>)>> {
+        let rows: Vec<(String, Option<String>, Option<String>)> = sqlx::query_as(
+            r#"
+            SELECT m.object_id, m.storage_class, m.format FROM media_objects m
+            WHERE m.created_at < ?2
+            AND EXISTS (
+                SELECT 1 FROM json_each(m.flow_references) r
+                WHERE json_extract(r.value, '$.flow_id') = ?1
+            )
+            AND NOT EXISTS (SELECT 1 FROM flow_segments s WHERE s.object_id = m.object_id)
+            ORDER BY m.created_at
+            LIMIT ?3
+            "#,
+        )
+        .bind(flow_id.to_string())
+        .bind(format_rfc3339(&created_before))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter()
+            .map(|(object_id, storage_class, format)| {
+                Ok((object_id, storage_class, format.as_deref().map(serde_json::from_str).transpose()?))
+            })
+            .collect()
+    }
+
+    // Job queue operations
+    pub async fn create_job(&self, job: &Job) -> TamsResult<()> {
+        let mut conn = self.pool.acquire().await?;
+        Self::insert_job(&mut conn, job).await
+    }
+
+    async fn insert_job(conn: &mut sqlx::SqliteConnection, job: &Job) -> TamsResult<()> {
+        let payload = serde_json::to_string(&job.payload)?;
+        let run_at = format_rfc3339(&job.run_at);
+        let created_at = format_rfc3339(&job.created_at);
+        let updated_at = format_rfc3339(&job.updated_at);
+        sqlx::query!(
+            r#"
+            INSERT INTO jobs (id, kind, target, payload, status, attempts, run_at, last_error, created_at, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+            "#,
+            job.id,
+            job.kind,
+            job.target,
+            payload,
+            job.status,
+            job.attempts,
+            run_at,
+            job.last_error,
+            created_at,
+            updated_at
+        )
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Atomically claim up to `limit` due jobs of a kind: pending jobs whose run_at
+    /// has passed, and running jobs whose lease has expired. Claimed jobs are
+    /// marked running with run_at moved to `lease_until` and their attempt counted.
+    pub async fn claim_due_jobs(
+        &self,
+        kind: &str,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: u32,
+    ) -> TamsResult<Vec<Job>> {
+        let rows = self.retry_busy(|| {
+            sqlx::query(
+                r#"
+                UPDATE jobs SET status = ?1, attempts = attempts + 1, run_at = ?2, updated_at = ?3
+                WHERE id IN (
+                    SELECT id FROM jobs
+                    WHERE kind = ?4 AND status IN (?5, ?1) AND run_at <= ?3
+                    ORDER BY run_at
+                    LIMIT ?6
+                )
+                RETURNING *
+                "#,
+            )
+            .bind(Job::RUNNING)
+            .bind(format_rfc3339(&lease_until))
+            .bind(format_rfc3339(&now))
+            .bind(kind)
+            .bind(Job::PENDING)
+            .bind(limit)
+            .fetch_all(&self.pool)
+        })
+        .await?;
+        rows.iter().map(job_from_row).collect()
+    }
+
+    /// Set a job's status, next run time and last error after an attempt
+    pub async fn finish_job_attempt(
+        &self,
+        id: &str,
+        status: &str,
+        run_at: DateTime<Utc>,
+        last_error: Option<&str>,
+    ) -> TamsResult<()> {
+        let run_at = format_rfc3339(&run_at);
+        let updated_at = format_rfc3339(&self.clock.now());
+        self.retry_busy(|| {
+            sqlx::query!(
+                "UPDATE jobs SET status = ?1, run_at = ?2, last_error = ?3, updated_at = ?4 WHERE id = ?5",
+                status,
+                run_at,
+                last_error,
+                updated_at,
+                id
+            )
+            .execute(&self.pool)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Make a claimed job due again at `run_at` without counting the attempt,
+    /// replacing its payload when one is given
+    pub async fn defer_job(&self, id: &str, run_at: DateTime<Utc>, payload: Option<&serde_json::Value>) -> TamsResult<()> {
+        let run_at = format_rfc3339(&run_at);
+        let updated_at = format_rfc3339(&self.clock.now());
+        let payload = payload.map(serde_json::to_string).transpose()?;
+        self.retry_busy(|| {
+            sqlx::query(
+                "UPDATE jobs SET status = ?1, run_at = ?2, attempts = MAX(attempts - 1, 0), updated_at = ?3, \
+                 payload = COALESCE(?5, payload) WHERE id = ?4",
+            )
+            .bind(Job::PENDING)
+            .bind(&run_at)
+            .bind(&updated_at)
+            .bind(id)
+            .bind(&payload)
+            .execute(&self.pool)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// A persisted runtime setting, stored as JSON
+    pub async fn get_service_setting(&self, name: &str) -> TamsResult<Option<serde_json::Value>> {
+        let value: Option<String> = sqlx::query_scalar("SELECT value FROM service_settings WHERE name = ?1")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(value.as_deref().map(serde_json::from_str).transpose()?)
+    }
+
+    pub async fn set_service_setting(&self, name: &str, value: &serde_json::Value) -> TamsResult<()> {
+        let value = serde_json::to_string(value)?;
+        let updated_at = format_rfc3339(&self.clock.now());
+        self.retry_busy(|| {
+            sqlx::query(
+                r#"
+                INSERT INTO service_settings (name, value, updated_at) VALUES (?1, ?2, ?3)
+                ON CONFLICT(name) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at
+                "#,
+            )
+            .bind(name)
+            .bind(&value)
+            .bind(&updated_at)
+            .execute(&self.pool)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Most recently updated jobs, optionally restricted to one kind and to a set of statuses
+    pub async fn list_jobs(&self, kind: Option<&str>, statuses: Option<&[&str]>, limit: u32) -> TamsResult<Vec<Job>> {
+        let statuses = statuses.map(serde_json::to_string).transpose()?;
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM jobs
+            WHERE (?1 IS NULL OR kind = ?1)
+            AND (?2 IS NULL OR status IN (SELECT value FROM json_each(?2)))
+            ORDER BY updated_at DESC
+            LIMIT ?3
+            "#,
+        )
+        .bind(kind)
+        .bind(statuses)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter().map(job_from_row).collect()
+    }
+
+    /// Number of jobs in each status, optionally for one kind
+    pub async fn count_jobs_by_status(&self, kind: Option<&str>) -> TamsResult<BTreeMap<String, u64>> {
+        let rows: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT status, COUNT(*) FROM jobs WHERE (?1 IS NULL OR kind = ?1) GROUP BY status",
+        )
+        .bind(kind)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|(status, count)| (status, count as u64)).collect())
+    }
+
+    pub async fn get_deletion_request_required(&self, id: &str) -> TamsResult<DeletionRequest> {
+        self.get_deletion_request(id).await?.ok_or_else(|| TamsError::NotFound("Deletion request not found".to_string()))
+    }
+
+    // Helper methods for handlers
+    /// A page of sources in `(created_at, id)` order, starting after the
+    /// `page` key of the one before, and the key of the next when there are
+    /// more, optionally only those of one owner
+    pub async fn get_sources(
+        &self,
+        limit: u32,
+        page: Option<&str>,
+        owned_by: Option<&str>,
+        filters: &SourceFilters,
+    ) -> TamsResult<(Listing<Source>, Option<String>)> {
+        let after = page.map(PageCursor::decode).transpose()?;
+        self.fetch_sources(filters, owned_by, after.as_ref(), Some(limit)).await
+    }
+
+    /// A page of the flows matching `filters`, paged like
+    /// [`Database::get_sources`], optionally only those of one owner
+    pub async fn get_flows(
+        &self,
+        limit: u32,
+        page: Option<&str>,
+        include_flow_collection: bool,
+        owned_by: Option<&str>,
+        filters: &FlowFilters,
+    ) -> TamsResult<(Listing<Flow>, Option<String>)> {
+        let after = page.map(PageCursor::decode).transpose()?;
+        let columns = if include_flow_collection { "*" } else { FLOW_COLUMNS_WITHOUT_COLLECTION };
+        let mut query = QueryBuilder::new(format!("SELECT {} FROM flows WHERE 1 = 1", columns));
+        if let Some(owner) = owned_by {
+            query.push(" AND owner = ").push_bind(owner.to_string());
+        }
+        filters.push_conditions(&mut query, self.segment_bounds())?;
+        push_page_conditions(&mut query, after.as_ref(), Some(limit));
+        let rows = query.build().fetch_all(&self.pool).await?;
+        let (rows, next_key) = split_page(rows, Some(limit))?;
+
+        let mut listing = Listing::default();
+        for row in &rows {
+            listing.push_parsed(flow_from_row(row), || format!("flows.id={:?}", row.try_get::<String, _>("id").ok()));
+        }
+        Ok((listing, next_key))
+    }
+
+    /// Number of sources matching `filters`, for listings asked to include a
+    /// total, optionally only those of one owner
+    pub async fn count_sources(&self, filters: &SourceFilters, owned_by: Option<&str>) -> TamsResult<u64> {
+        let mut query = QueryBuilder::new("SELECT COUNT(*) FROM sources WHERE 1 = 1");
+        if let Some(owner) = owned_by {
+            query.push(" AND owner = ").push_bind(owner.to_string());
+        }
+        filters.push_conditions(&mut query)?;
+        let count: i64 = query.build_query_scalar().fetch_one(&self.pool).await?;
+        Ok(count as u64)
+    }
+
+    /// Rows counted for the service summary, with the number of active deletion
+    /// requests, in one query
+    pub async fn get_summary_counts(&self) -> TamsResult<(SummaryCounts, u64)> {
+        let (sources, flows, segments, objects, active_deletions): (i64, i64, i64, i64, i64) = sqlx::query_as(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM sources),
+                (SELECT COUNT(*) FROM flows),
+                (SELECT COUNT(*) FROM flow_segments),
+                (SELECT COUNT(*) FROM media_objects),
+                (SELECT COUNT(*) FROM deletion_requests WHERE status IN (?1, ?2))
+            "#,
+        )
+        .bind(DeletionRequest::PENDING)
+        .bind(DeletionRequest::IN_PROGRESS)
+        .fetch_one(&self.pool)
+        .await?;
+        let counts = SummaryCounts {
+            sources: sources as u64,
+            flows: flows as u64,
+            segments: segments as u64,
+            objects: objects as u64,
+        };
+        Ok((counts, active_deletions as u64))
+    }
+
+    /// Number of flows matching `filters`, optionally only those of one owner
+    pub async fn count_flows(&self, filters: &FlowFilters, owned_by: Option<&str>) -> TamsResult<u64> {
+        let mut query = QueryBuilder::new("SELECT COUNT(*) FROM flows WHERE 1 = 1");
+        if let Some(owner) = owned_by {
+            query.push(" AND owner = ").push_bind(owner.to_string());
+        }
+        filters.push_conditions(&mut query, self.segment_bounds())?;
+        let count: i64 = query.build_query_scalar().fetch_one(&self.pool).await?;
+        Ok(count as u64)
+    }
+
+    /// Give a flow a new owner, and its deletion requests with it, returning
+    /// false when there is no such flow
+    pub async fn set_flow_owner(&self, id: &Uuid, owner: &str, updated_at: DateTime<Utc>) -> TamsResult<bool> {
+        let mut tx = self.begin_transaction().await?;
+        let result = sqlx::query("UPDATE flows SET owner = ?1, updated_at = ?2 WHERE id = ?3")
+            .bind(owner)
+            .bind(format_rfc3339(&updated_at))
+            .bind(id.to_string())
+            .execute(tx.conn())
+            .await?;
+        sqlx::query("UPDATE deletion_requests SET owner = ?1 WHERE flow_id = ?2")
+            .bind(owner)
+            .bind(id.to_string())
+            .execute(tx.conn())
+            .await?;
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Number of a flow's segments matching `filters`
+    pub async fn count_flow_segments(&self, flow_id: &Uuid, filters: &FlowSegmentFilters) -> TamsResult<u64> {
+        let count: i64 = sqlx::query_scalar(
+            &format!(
+                r#"
+                SELECT COUNT(*) FROM {} WHERE flow_id = ?1
+                AND (?2 IS NULL OR end_ns > ?2) AND (?3 IS NULL OR start_ns < ?3)
+                AND (?4 IS NULL OR object_id = ?4)
+                "#,
+                self.segment_bounds()
+            ),
+        )
+        .bind(flow_id.to_string())
+        .bind(filters.start_ns)
+        .bind(filters.end_ns)
+        .bind(&filters.object_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(count as u64)
+    }
+
+    /// Delete a flow's segments lying wholly within `start_ns..end_ns` (either
+    /// bound open; with both open, every segment) in one transaction and
+    /// recompute the flow's available_timerange from the rest. Segments only
+    /// partly inside are kept, or under `strict` refuse the whole deletion.
+    pub async fn delete_flow_segments_by_timerange(
+        &self,
+        flow_id: &Uuid,
+        start_ns: Option<i64>,
+        end_ns: Option<i64>,
+        strict: bool,
+    ) -> TamsResult<SegmentObjectDeletion> {
+        let flow_id_str = flow_id.to_string();
+        let mut tx = self.begin_transaction().await?;
+        tx.check_flow_writable(flow_id).await?;
+
+        if strict {
+            let straddling: Option<(String, String)> = sqlx::query_as(
+                &format!(
+                    r#"
+                    SELECT object_id, timerange FROM {}
+                    WHERE flow_id = ?1 AND (?3 IS NULL OR start_ns < ?3) AND (?2 IS NULL OR end_ns > ?2)
+                        AND NOT ((?2 IS NULL OR start_ns >= ?2) AND (?3 IS NULL OR end_ns <= ?3))
+                    ORDER BY start_ns
+                    LIMIT 1
+                    "#,
+                    self.segment_bounds()
+                ),
+            )
+            .bind(&flow_id_str)
+            .bind(start_ns)
+            .bind(end_ns)
+            .fetch_optional(tx.conn())
+            .await?;
+            if let Some((object_id, timerange)) = straddling {
+                let timerange = parse_segment_timerange(&timerange).map(|range| format_tams_timerange(&range)).unwrap_or(timerange);
+                return Err(TamsError::SegmentOverlap(format!(
+                    "Segment {} of object {} lies only partly within the range to delete",
+                    timerange, object_id
+                )));
+            }
+        }
+
+        // A segment whose range can't be read has no bounds, so is only deleted with every other
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            &format!(
+                r#"
+                SELECT object_id, timerange FROM {}
+                WHERE flow_id = ?1 AND ((?2 IS NULL AND ?3 IS NULL) OR ((?2 IS NULL OR start_ns >= ?2) AND (?3 IS NULL OR end_ns <= ?3)))
+                ORDER BY start_ns
+                "#,
+                self.segment_bounds()
+            ),
+        )
+        .bind(&flow_id_str)
+        .bind(start_ns)
+        .bind(end_ns)
+        .fetch_all(tx.conn())
+        .await?;
+
+        let deletion = self.delete_segment_rows(tx.conn(), &flow_id_str, rows).await?;
+        tx.commit().await?;
+        Ok(deletion)
+    }
+
+    /// Which of the given objects are referenced by segments of the flow
+    pub async fn get_flow_segment_object_ids(
+        &self,
+        flow_id: &Uuid,
+        object_ids: &[String],
+    ) -> TamsResult<HashSet<String>> {
+        let object_ids: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT DISTINCT object_id FROM flow_segments
+            WHERE flow_id = ?1 AND object_id IN (SELECT value FROM json_each(?2))
+            "#,
+        )
+        .bind(flow_id.to_string())
+        .bind(serde_json::to_string(object_ids)?)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(object_ids.into_iter().collect())
+    }
+
+    /// Objects referenced by a flow's segments, in the order they first appear
+    /// on its timeline, at most `limit` of them, continuing after the `page`
+    /// key of an earlier page. Returns the key of the next page when there is one.
+    pub async fn list_flow_object_ids(
+        &self,
+        flow_id: &Uuid,
+        limit: u32,
+        page: Option<&str>,
+    ) -> TamsResult<(Vec<String>, Option<String>)> {
+        let limit = limit.max(1);
+        let after = page.map(parse_object_page_key).transpose()?;
+        let mut rows: Vec<(String, i64)> = sqlx::query_as(&format!(
+            r#"
+            SELECT object_id, COALESCE(MIN(start_ns), ?3) AS first_start FROM {} WHERE flow_id = ?1
+            GROUP BY object_id
+            HAVING ?4 IS NULL OR (first_start, object_id) > (?4, ?5)
+            ORDER BY first_start, object_id
+            LIMIT ?2
+            "#,
+            self.segment_bounds()
+        ))
+        .bind(flow_id.to_string())
+        .bind(limit as i64 + 1)
+        .bind(i64::MIN)
+        .bind(after.as_ref().map(|(start, _)| *start))
+        .bind(after.as_ref().map(|(_, object_id)| object_id.as_str()))
+        .fetch_all(&self.pool)
+        .await?;
+
+        let next_key = match rows.len() > limit as usize {
+            true => {
+                rows.truncate(limit as usize);
+                rows.last().map(|(object_id, first_start)| format!("{}_{}", first_start, object_id))
+            }
+            false => None,
+        };
+        Ok((rows.into_iter().map(|(object_id, _)| object_id).collect(), next_key))
+    }
+
+    /// Number of distinct objects referenced by a flow's segments
+    pub async fn count_flow_object_ids(&self, flow_id: &Uuid) -> TamsResult<u64> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(DISTINCT object_id) FROM flow_segments WHERE flow_id = ?1")
+            .bind(flow_id.to_string())
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count as u64)
+    }
+
+    /// Fill in start_ns/end_ns for up to `limit` segments stored without them,
+    /// taking those after `after_rowid` in rowid order, in one transaction.
+    /// Returns the last rowid examined and how many segments were filled in,
+    /// or None once no segment without bounds is left past `after_rowid`.
+    /// Segments whose stored string doesn't parse are passed over; they count
+    /// as unparseable in [`Self::get_segment_bounds_progress`] once examined.
+    pub async fn backfill_segment_bounds(&self, after_rowid: i64, limit: u32) -> TamsResult<Option<(i64, u64)>> {
+        let rows: Vec<(i64, String)> = sqlx::query_as(
+            "SELECT rowid, timerange FROM flow_segments WHERE start_ns IS NULL AND rowid > ?1 ORDER BY rowid LIMIT ?2",
+        )
+        .bind(after_rowid)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        let Some(&(last_rowid, _)) = rows.last() else {
+            return Ok(None);
+        };
+
+        let mut tx = self.begin_transaction().await?;
+        let mut filled = 0;
+        for (rowid, stored) in rows {
+            let Ok((start_ns, end_ns)) = segment_bounds_nanos(&stored) else {
+                tracing::warn!("Leaving segment timerange '{}' without bounds: it doesn't parse", stored);
+                continue;
+            };
+            sqlx::query("UPDATE flow_segments SET start_ns = ?1, end_ns = ?2 WHERE rowid = ?3")
+                .bind(start_ns)
+                .bind(end_ns)
+                .bind(rowid)
+                .execute(tx.conn())
+                .await?;
+            filled += 1;
+        }
+        tx.commit().await?;
+        Ok(Some((last_rowid, filled)))
+    }
+
+    /// How many segments have start_ns/end_ns filled in, and how many of those
+    /// without them, up to `examined_through` (the last rowid the backfill has
+    /// examined), don't parse, out of all segments
+    pub async fn get_segment_bounds_progress(&self, examined_through: i64) -> TamsResult<SegmentBoundsProgress> {
+        let (segments_backfilled, segments_unparseable, segments_total): (i64, i64, i64) = sqlx::query_as(
+            "SELECT COUNT(start_ns), COUNT(CASE WHEN start_ns IS NULL AND rowid <= ?1 THEN 1 END), COUNT(*) FROM flow_segments",
+        )
+        .bind(examined_through)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(SegmentBoundsProgress {
+            segments_backfilled: segments_backfilled as u64,
+            segments_unparseable: segments_unparseable as u64,
+            segments_total: segments_total as u64,
+            complete: segments_backfilled + segments_unparseable == segments_total,
+        })
+    }
+
+    /// Drop the legacy bounds view, so range queries read flow_segments and
+    /// its indexes directly. Refused until the backfill has examined every
+    /// segment without bounds, as the view is then the only way to find them.
+    pub async fn retire_segment_bounds_view(&self, examined_through: i64) -> TamsResult<()> {
+        let progress = self.get_segment_bounds_progress(examined_through).await?;
+        if !progress.complete {
+            return Err(TamsError::Conflict(format!(
+                "The segment bounds backfill has examined {} of {} segments",
+                progress.segments_backfilled + progress.segments_unparseable,
+                progress.segments_total
+            )));
+        }
+        self.legacy_bounds.store(false, Ordering::Relaxed);
+        sqlx::query("DROP VIEW IF EXISTS flow_segment_bounds").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    /// Whether range queries still read through the legacy bounds view
+    pub fn reads_legacy_bounds(&self) -> bool {
+        self.legacy_bounds.load(Ordering::Relaxed)
+    }
+
+    /// Earliest start and latest end of a flow's segments, in nanoseconds
+    pub async fn get_segment_extent_nanos(&self, flow_id: &Uuid) -> TamsResult<Option<(i64, i64)>> {
+        let (start, end): (Option<i64>, Option<i64>) =
+            sqlx::query_as(&format!("SELECT MIN(start_ns), MAX(end_ns) FROM {} WHERE flow_id = ?1", self.segment_bounds()))
+                .bind(flow_id.to_string())
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(start.zip(end))
+    }
+
+    /// Stored timeranges of a flow's segments overlapping `[window_start, window_end)`
+    pub async fn get_segment_timeranges_in_window(
+        &self,
+        flow_id: &Uuid,
+        window_start: i64,
+        window_end: i64,
+    ) -> TamsResult<Vec<String>> {
+        let rows: Vec<(String,)> = sqlx::query_as(
+            &format!("SELECT timerange FROM {} WHERE flow_id = ?1 AND start_ns < ?3 AND end_ns > ?2 ORDER BY start_ns", self.segment_bounds()),
+        )
+        .bind(flow_id.to_string())
+        .bind(window_start)
+        .bind(window_end)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|(timerange,)| timerange).collect())
+    }
+
+    /// Per-bucket segment coverage of `[window_start, window_end)`, with buckets
+    /// of `bucket` nanoseconds counted from `origin`. Each row is (bucket index,
+    /// covered nanoseconds, overlapping segments, estimated bytes); buckets with no
+    /// segments are omitted.
+    pub async fn get_segment_rollup(
+        &self,
+        flow_id: &Uuid,
+        origin: i64,
+        bucket: i64,
+        window_start: i64,
+        window_end: i64,
+    ) -> TamsResult<Vec<(i64, i64, i64, i64)>> {
+        // Each segment is clipped to the window and split at bucket boundaries
+        let rows = sqlx::query_as(
+            &format!(
+                r#"
+                WITH RECURSIVE pieces(bucket, piece_start, piece_end, duration, size) AS (
+                    SELECT (MAX(s.start_ns, ?4) - ?2) / ?3,
+                           MAX(s.start_ns, ?4),
+                           MIN(s.end_ns, ?5),
+                           s.end_ns - s.start_ns,
+                           COALESCE(m.size_bytes, 0)
+                    FROM {} s
+                    LEFT JOIN media_objects m ON m.object_id = s.object_id
+                    WHERE s.flow_id = ?1 AND s.start_ns < ?5 AND s.end_ns > ?4
+                    UNION ALL
+                    SELECT bucket + 1, ?2 + (bucket + 1) * ?3, piece_end, duration, size
+                    FROM pieces
+                    WHERE ?2 + (bucket + 1) * ?3 < piece_end
+                ),
+                clipped AS (
+                    SELECT bucket, MIN(piece_end, ?2 + (bucket + 1) * ?3) - piece_start AS covered, duration, size
+                    FROM pieces
+                )
+                SELECT bucket,
+                       MIN(SUM(covered), ?3),
+                       COUNT(*),
+                       CAST(COALESCE(SUM(size * CAST(covered AS REAL) / NULLIF(duration, 0)), 0) AS INTEGER)
+                FROM clipped
+                GROUP BY bucket
+                ORDER BY bucket
+                "#,
+                self.segment_bounds()
+            ),
+        )
+        .bind(flow_id.to_string())
+        .bind(origin)
+        .bind(bucket)
+        .bind(window_start)
+        .bind(window_end)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    /// Parse a segment timerange read from `flow_id`'s segments. One that doesn't
+    /// parse fails in strict mode; in lenient mode it is logged and the segment
+    /// counts as un-timeranged.
+    pub fn stored_timerange(&self, flow_id: &Uuid, stored: &str) -> TamsResult<Option<TimeRange>> {
+        match parse_segment_timerange(stored) {
+            Ok(range) => Ok(Some(range)),
+            Err(e) if self.timerange_parsing == TimerangeParsing::Strict => Err(e),
+            Err(e) => {
+                tracing::warn!("Segment of flow {} is excluded from range queries: {}", flow_id, e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// A page of up to `limit` of a flow's segments matching `filters`, in
+    /// timeline order (or reversed), continuing after the `page` key of an
+    /// earlier page. Returns the key of the next page when there is one.
+    /// Un-timeranged segments only appear when no window is given, first in
+    /// timeline order; with a window they fail the query in strict mode, and
+    /// are otherwise left out and counted in `skipped_corrupt`.
+    pub async fn get_flow_segments_by_timerange(
+        &self,
+        flow_id: &Uuid,
+        filters: &FlowSegmentFilters,
+        limit: u32,
+        page: Option<&str>,
+    ) -> TamsResult<(Listing<FlowSegment>, Option<String>)> {
+        let limit = limit.max(1);
+        let flow_id_str = flow_id.to_string();
+        let mut excluded = 0;
+        if filters.start_ns.is_some() || filters.end_ns.is_some() {
+            let untimeranged: i64 =
+                sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {} WHERE flow_id = ?1 AND start_ns IS NULL", self.segment_bounds()))
+                    .bind(&flow_id_str)
+                    .fetch_one(&self.pool)
+                    .await?;
+            if untimeranged > 0 {
+                let problem = format!("{} segments of flow {} have timeranges that don't parse", untimeranged, flow_id);
+                if self.timerange_parsing == TimerangeParsing::Strict {
+                    return Err(TamsError::InvalidTimerange(problem));
+                }
+                tracing::warn!("{}; they are excluded from range queries", problem);
+                excluded = untimeranged as u64;
+            }
+        }
+
+        // Pages are keyed on (start, rowid); un-timeranged segments sort first
+        let after = page.map(parse_segment_page_key).transpose()?;
+        let (order, beyond) = if filters.reverse_order.unwrap_or(false) { ("DESC", "<") } else { ("ASC", ">") };
+        let rows = sqlx::query(&format!(
+            r#"
+            SELECT s.*, COALESCE(b.start_ns, ?5) AS sort_start, b.rowid AS segment_rowid
+            FROM {bounds} b JOIN flow_segments s ON s.rowid = b.rowid
+            WHERE b.flow_id = ?1
+            AND (?2 IS NULL OR b.end_ns > ?2) AND (?3 IS NULL OR b.start_ns < ?3)
+            AND (?4 IS NULL OR b.object_id = ?4)
+            AND (?6 IS NULL OR (COALESCE(b.start_ns, ?5), b.rowid) {beyond} (?6, ?7))
+            ORDER BY sort_start {order}, b.rowid {order}
+            LIMIT ?8
+            "#,
+            bounds = self.segment_bounds(),
+        ))
+        .bind(&flow_id_str)
+        .bind(filters.start_ns)
+        .bind(filters.end_ns)
+        .bind(&filters.object_id)
+        .bind(i64::MIN)
+        .bind(after.map(|(start, _)| start))
+        .bind(after.map(|(_, rowid)| rowid))
+        .bind(limit as i64 + 1)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let next_key = match rows.get(limit as usize) {
+            Some(_) => {
+                let last = &rows[limit as usize - 1];
+                Some(format!("{}_{}", last.try_get::<i64, _>("sort_start")?, last.try_get::<i64, _>("segment_rowid")?))
+            }
+            None => None,
+        };
+        let mut listing = Listing {
+            skipped_corrupt: excluded,
+            ..Listing::default()
+        };
+        for row in rows.iter().take(limit as usize) {
+            listing.push_parsed(segment_from_row(row), || {
+                format!("flow_segments.rowid={:?}", row.try_get::<i64, _>("segment_rowid").ok())
+            });
+        }
+        Ok((listing, next_key))
+    }
+}
+
+/// A write transaction held on one pooled connection for the length of a
+/// request. SQLite is the only backend: the transaction opens with `BEGIN
+/// IMMEDIATE`, taking the database's single write lock up front, so it runs
+/// serializable. Concurrent transactions queue on the lock (within the busy
+/// timeout and retries) instead of failing when they first write, and readers
+/// outside it keep seeing the last committed state under WAL.
+pub struct DatabaseTransaction {
+    conn: Option<PoolConnection<Sqlite>>,
+    segment_bounds: &'static str,
+}
+
+impl DatabaseTransaction {
+    fn conn(&mut self) -> &mut SqliteConnection {
+        self.conn.as_mut().expect("transaction connection is held until commit or rollback")
+    }
+
+    pub async fn commit(mut self) -> TamsResult<()> {
+        sqlx::query("COMMIT").execute(self.conn()).await?;
+        self.conn.take();
+        Ok(())
+    }
+
+    pub async fn rollback(mut self) -> TamsResult<()> {
+        sqlx::query("ROLLBACK").execute(self.conn()).await?;
+        self.conn.take();
+        Ok(())
+    }
+
+    pub async fn get_flow(&mut self, id: &Uuid) -> TamsResult<Option<Flow>> {
+        fetch_flow(self.conn(), id).await
+    }
+
+    pub async fn get_flow_required(&mut self, id: &Uuid) -> TamsResult<Flow> {
+        self.get_flow(id).await?.ok_or_else(|| TamsError::NotFound("Flow not found".to_string()))
+    }
+
+    /// Refuse a segment change to a flow that is missing or read-only. Checked
+    /// in the transaction making the change, so a finalize can't slip between
+    pub async fn check_flow_writable(&mut self, id: &Uuid) -> TamsResult<()> {
+        if self.get_flow_required(id).await?.is_read_only() {
+            return Err(TamsError::ReadOnlyFlow { flow_id: id.to_string() });
+        }
+        Ok(())
+    }
+
+    /// Span from the earliest start to the latest end of the flow's segments
+    pub async fn get_segment_coverage(&mut self, flow_id: &Uuid) -> TamsResult<Option<TimeRange>> {
+        let (start, end): (Option<i64>, Option<i64>) =
+            sqlx::query_as(&format!("SELECT MIN(start_ns), MAX(end_ns) FROM {} WHERE flow_id = ?1", self.segment_bounds))
+                .bind(flow_id.to_string())
+                .fetch_one(self.conn())
+                .await?;
+        Ok(start.zip(end).map(|(start, end)| TimeRange {
+            start: nanos_to_timestamp(start),
+            end: (end != OPEN_END_NANOS).then(|| nanos_to_timestamp(end)),
+        }))
+    }
+
+    /// Timeranges of the flow's segments that reach outside `start_ns..end_ns`,
+    /// where an open end keeps everything from the start on
+    pub async fn get_segment_ranges_outside(
+        &mut self,
+        flow_id: &Uuid,
+        start_ns: i64,
+        end_ns: Option<i64>,
+    ) -> TamsResult<Vec<TimeRange>> {
+        let rows: Vec<(i64, i64)> = sqlx::query_as(&format!(
+            "SELECT start_ns, end_ns FROM {} WHERE flow_id = ?1 AND (start_ns < ?2 OR end_ns > ?3)",
+            self.segment_bounds
+        ))
+        .bind(flow_id.to_string())
+        .bind(start_ns)
+        .bind(end_ns.unwrap_or(OPEN_END_NANOS))
+        .fetch_all(self.conn())
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(start, end)| TimeRange {
+                start: nanos_to_timestamp(start),
+                end: (end != OPEN_END_NANOS).then(|| nanos_to_timestamp(end)),
+            })
+            .collect())
+    }
+
+    /// Whether the flow has at least one segment, without counting them
+    pub async fn flow_has_segments(&mut self, flow_id: &Uuid) -> TamsResult<bool> {
+        let has_segments: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM flow_segments WHERE flow_id = ?1)")
+            .bind(flow_id.to_string())
+            .fetch_one(self.conn())
+            .await?;
+        Ok(has_segments)
+    }
+
+    pub async fn get_deletion_requests_for_flow(&mut self, flow_id: &Uuid) -> TamsResult<Listing<DeletionRequest>> {
+        fetch_deletion_requests_for_flow(self.conn(), flow_id).await
+    }
+
+    pub async fn update_flow(&mut self, flow: &Flow) -> TamsResult<()> {
+        write_flow(self.conn(), flow).await
+    }
+
+    /// Whether the object has been uploaded, or registered, and not reclaimed
+    pub async fn media_object_exists(&mut self, object_id: &str) -> TamsResult<bool> {
+        let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM media_objects WHERE object_id = ?1)")
+            .bind(object_id)
+            .fetch_one(self.conn())
+            .await?;
+        Ok(exists)
+    }
+
+    /// Forget an object no segment references any more. Returns false if it
+    /// was unknown or has been referenced again since.
+    pub async fn delete_orphaned_media_object(&mut self, object_id: &str) -> TamsResult<bool> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM media_objects WHERE object_id = ?1
+            AND NOT EXISTS (SELECT 1 FROM flow_segments WHERE object_id = ?1)
+            "#,
+        )
+        .bind(object_id)
+        .execute(self.conn())
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Record an allocation, first write wins. If the object id is already
+    /// allocated the existing record is returned with `already_allocated` set,
+    /// unless that allocation expired by `now`, in which case it is replaced.
+    pub async fn claim_storage_allocation(
+        &mut self,
+        flow_id: Option<&Uuid>,
+        allocation: &StorageObject,
+        now: DateTime<Utc>,
+    ) -> TamsResult<StorageObject> {
+        let flow_id = flow_id.map(|id| id.to_string());
+        let expires_at = allocation.expires_at.as_ref().map(format_rfc3339);
+        let created_at = format_rfc3339(&now);
+
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO storage_allocations (object_id, flow_id, put_url, media_store, storage_class, expires_at, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            ON CONFLICT(object_id) DO UPDATE SET
+                flow_id = excluded.flow_id,
+                put_url = excluded.put_url,
+                media_store = excluded.media_store,
+                storage_class = excluded.storage_class,
+                expires_at = excluded.expires_at,
+                created_at = excluded.created_at
+            WHERE storage_allocations.expires_at <= ?7
+            "#,
+            allocation.object_id,
+            flow_id,
+            allocation.put_url,
+            allocation.media_store,
+            allocation.storage_class,
+            expires_at,
+            created_at
+        )
+        .execute(self.conn())
+        .await?
+        .rows_affected();
+        if inserted == 1 {
+            return Ok(allocation.clone());
+        }
+
+        fetch_storage_allocation(self.conn(), &allocation.object_id)
+            .await?
+            .ok_or_else(|| TamsError::Internal(format!("Allocation for {} vanished", allocation.object_id)))
+    }
+
+    pub async fn add_flow_segment(&mut self, segment: &FlowSegment) -> TamsResult<()> {
+        let essence_parameters_json = segment.essence_parameters.as_ref().map(serde_json::to_string).transpose()?;
+        Ok(insert_flow_segment(self.conn(), segment, essence_parameters_json.as_deref()).await?)
+    }
+
+    /// Insert segments with a multi-row INSERT per chunk. A chunk refused by a
+    /// constraint (a duplicate segment, or one of a flow that doesn't exist) is
+    /// retried a row at a time; the rows that still fail are returned and
+    /// everything else is inserted.
+    pub async fn add_flow_segments(&mut self, segments: &[FlowSegment]) -> TamsResult<Vec<FlowSegmentFailure>> {
+        let mut failures = Vec::new();
+        for chunk in segments.chunks(SEGMENT_INSERT_CHUNK_ROWS) {
+            let rows = chunk
+                .iter()
+                .map(|segment| {
+                    let essence_parameters_json = segment.essence_parameters.as_ref().map(serde_json::to_string).transpose()?;
+                    let get_urls_json = serde_json::to_string(&segment.get_urls)?;
+                    Ok((segment, essence_parameters_json, get_urls_json))
+                })
+                .collect::<TamsResult<Vec<_>>>()?;
+
+            let mut insert = sqlx::QueryBuilder::<Sqlite>::new(
+                "INSERT INTO flow_segments (flow_id, object_id, timerange, ts_offset, sample_offset, sample_count, \
+                 key_frame_count, get_urls, created_at, essence_parameters, start_ns, end_ns) ",
+            );
+            insert.push_values(&rows, |mut row, (segment, essence_parameters_json, get_urls_json)| {
+                let (start_ns, end_ns) = segment_bounds_nanos(&segment.timerange).ok().unzip();
+                row.push_bind(segment.flow_id.to_string())
+                    .push_bind(&segment.object_id)
+                    .push_bind(&segment.timerange)
+                    .push_bind(&segment.ts_offset)
+                    .push_bind(segment.sample_offset.map(|v| v as i64))
+                    .push_bind(segment.sample_count.map(|v| v as i64))
+                    .push_bind(segment.key_frame_count.map(|v| v as i64))
+                    .push_bind(get_urls_json)
+                    .push_bind(format_rfc3339(&segment.created_at))
+                    .push_bind(essence_parameters_json)
+                    .push_bind(start_ns)
+                    .push_bind(end_ns);
+            });
+            match insert.build().execute(self.conn()).await {
+                Ok(_) => {}
+                Err(e) if is_constraint_violation(&e) => {
+                    // A failed statement leaves the transaction open with nothing of it applied
+                    for (segment, essence_parameters_json, _) in &rows {
+                        if let Err(e) = insert_flow_segment(self.conn(), segment, essence_parameters_json.as_deref()).await {
+                            if !is_constraint_violation(&e) {
+                                return Err(e.into());
+                            }
+                            failures.push(FlowSegmentFailure {
+                                segment: (*segment).clone(),
+                                error: e.to_string(),
+                            });
+                        }
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(failures)
+    }
+}
+
+impl Drop for DatabaseTransaction {
+    /// A transaction dropped without commit or rollback (a panicking or
+    /// cancelled handler) closes its connection, which rolls it back, rather
+    /// than returning it to the pool mid-transaction
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            drop(conn.detach());
+        }
+    }
+}
+
+async fn fetch_storage_allocation<'c, E>(executor: E, object_id: &str) -> TamsResult<Option<StorageObject>>
+where
+    E: sqlx::Executor<'c, Database = Sqlite>,
+{
+    let row = sqlx::query!(
+        "SELECT put_url, media_store, storage_class, expires_at FROM storage_allocations WHERE object_id = ?1",
+        object_id
+    )
+    .fetch_optional(executor)
+    .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    Ok(Some(StorageObject {
+        object_id: object_id.to_string(),
+        put_url: row.put_url,
+        put_headers: None,
+        expires_at: row
+            .expires_at
+            .as_deref()
+            .map(|t| DateTime::parse_from_rfc3339(t).map(|t| t.with_timezone(&Utc)))
+            .transpose()?,
+        media_store: row.media_store,
+        storage_class: row.storage_class,
+        already_allocated: true,
+    }))
+}
+
+async fn fetch_flow<'c, E>(executor: E, id: &Uuid) -> TamsResult<Option<Flow>>
+where
+    E: sqlx::Executor<'c, Database = Sqlite>,
+{
+    let id_str = id.to_string();
+    let rows = sqlx::query!(
+        "SELECT * FROM flows WHERE id = ?1",
+        id_str
+    )
+    .fetch_all(executor)
+    .await?;
+
+    if let Some(row) = rows.first() {
+        let flow_collection = row.flow_collection.as_ref()
+            .and_then(|fc| serde_json::from_str(fc).ok());
+        let available_timerange = row.available_timerange.as_ref()
+            .and_then(|tr| serde_json::from_str(tr).ok());
+            
+        Ok(Some(Flow {
+            id: Uuid::parse_str(row.id.as_ref().ok_or_else(|| TamsError::InvalidInput("Missing id".to_string()))?)?,
+            source_id: row.source_id.as_ref().map(|s| Uuid::parse_str(s)).transpose()?,
+            format: serde_json::from_str(&row.format)?,
+            label: row.label.clone(),
+            description: row.description.clone(),
+            tags: serde_json::from_str(&row.tags)?,
+            read_only: row.read_only.map(|v| v != 0),
+            max_bit_rate: row.max_bit_rate.map(|v| v as u64),
+            avg_bit_rate: row.avg_bit_rate.map(|v| v as u64),
+            container: row.container.clone(),
+            codec: row.codec.clone(),
+            frame_width: row.frame_width.map(|v| v as u32),
+            frame_height: row.frame_height.map(|v| v as u32),
+            sample_rate: row.sample_rate.map(|v| v as u32),
+            channels: row.channels.map(|v| v as u32),
+            flow_collection,
+            available_timerange,
+            retention_seconds: row.retention_seconds.map(|v| v as u64),
+            notify_url: row.notify_url.clone(),
+            notify_secret: row.notify_secret.clone(),
+            owner: row.owner.clone(),
+            created_at: DateTime::parse_from_rfc3339(&row.created_at)?.with_timezone(&Utc),
+            updated_at: DateTime::parse_from_rfc3339(&row.updated_at)?.with_timezone(&Utc),
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+async fn write_flow<'c, E>(executor: E, flow: &Flow) -> TamsResult<()>
+where
+    E: sqlx::Executor<'c, Database = Sqlite>,
+{
+    let flow_id = flow.id.to_string();
+    let source_id = flow.source_id.map(|id| id.to_string());
+    let format_str = serde_json::to_string(&flow.format)?;
+    let tags_str = serde_json::to_string(&flow.tags)?;
+    let flow_collection_str = flow.flow_collection.as_ref().map(|fc| serde_json::to_string(fc).unwrap_or_default());
+    let available_timerange_str = flow.available_timerange.as_ref().map(|tr| serde_json::to_string(tr).unwrap_or_default());
+    let max_bit_rate = flow.max_bit_rate.map(|v| v as i64);
+    let avg_bit_rate = flow.avg_bit_rate.map(|v| v as i64);
+    let frame_width = flow.frame_width.map(|v| v as i64);
+    let frame_height = flow.frame_height.map(|v| v as i64);
+    let sample_rate = flow.sample_rate.map(|v| v as i64);
+    let channels = flow.channels.map(|v| v as i64);
+    let retention_seconds = flow.retention_seconds.map(|v| v as i64);
+    let updated_at = format_rfc3339(&flow.updated_at);
+
+    sqlx::query!(
+        r#"
+        UPDATE flows SET
+            source_id = ?2, format = ?3, label = ?4, description = ?5,
+            tags = ?6, read_only = ?7, max_bit_rate = ?8, avg_bit_rate = ?9,
+            container = ?10, codec = ?11, frame_width = ?12, frame_height = ?13,
+            sample_rate = ?14, channels = ?15, flow_collection = ?16,
+            available_timerange = ?17, updated_at = ?18, retention_seconds = ?19,
+            notify_url = ?20, notify_secret = ?21
+        WHERE id = ?1
+        "#,
+        flow_id,
+        source_id,
+        format_str,
+        flow.label,
+        flow.description,
+        tags_str,
+        flow.read_only,
+        max_bit_rate,
+        avg_bit_rate,
+        flow.container,
+        flow.codec,
+        frame_width,
+        frame_height,
+        sample_rate,
+        channels,
+        flow_collection_str,
+        available_timerange_str,
+        updated_at,
+        retention_seconds,
+        flow.notify_url,
+        flow.notify_secret
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+async fn fetch_segment_timeranges<'c, E>(executor: E, flow_id: &str) -> TamsResult<Vec<String>>
+where
+    E: sqlx::Executor<'c, Database = Sqlite>,
+{
+    let rows = sqlx::query!("SELECT timerange FROM flow_segments WHERE flow_id = ?1", flow_id)
+        .fetch_all(executor)
+        .await?;
+    Ok(rows.into_iter().map(|row| row.timerange).collect())
+}
+
+fn is_constraint_violation(error: &sqlx::Error) -> bool {
+    matches!(error, sqlx::Error::Database(e)
+        if e.is_unique_violation() || e.is_foreign_key_violation() || e.is_check_violation())
+}
+
+/// Insert a segment whose essence_parameters the caller has already serialized
+async fn insert_flow_segment<'c, E>(executor: E, segment: &FlowSegment, essence_parameters_json: Option<&str>) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'c, Database = Sqlite>,
+{
+    let flow_id = segment.flow_id.to_string();
+    let get_urls_json = serde_json::to_string(&segment.get_urls).unwrap_or_default();
+    let sample_offset = segment.sample_offset.map(|v| v as i64);
+    let sample_count = segment.sample_count.map(|v| v as i64);
+    let key_frame_count = segment.key_frame_count.map(|v| v as i64);
+    let created_at = format_rfc3339(&segment.created_at);
+    let (start_ns, end_ns) = segment_bounds_nanos(&segment.timerange).ok().unzip();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO flow_segments (
+            flow_id, object_id, timerange, ts_offset, sample_offset,
+            sample_count, key_frame_count, get_urls, created_at, essence_parameters,
+            start_ns, end_ns
+        )
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+        "#,
+        flow_id,
+        segment.object_id,
+        segment.timerange,
+        segment.ts_offset,
+        sample_offset,
+        sample_count,
+        key_frame_count,
+        get_urls_json,
+        created_at,
+        essence_parameters_json,
+        start_ns,
+        end_ns
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+async fn fetch_deletion_requests_for_flow<'c, E>(executor: E, flow_id: &Uuid) -> TamsResult<Listing<DeletionRequest>>
+where
+    E: sqlx::Executor<'c, Database = Sqlite>,
+{
+    let flow_id_str = flow_id.to_string();
+    let rows = sqlx::query!(
+        "SELECT * FROM deletion_requests WHERE flow_id = ?1 ORDER BY created_at DESC",
+        flow_id_str
+    )
+    .fetch_all(executor)
+    .await?;
+
+    let mut listing = Listing::default();
+    for row in rows {
+        let parsed = (|| -> TamsResult<DeletionRequest> {
+            let progress = row.progress.as_ref().and_then(|p| p.parse::<i32>().ok());
+
+            Ok(DeletionRequest {
+                id: row.id.clone().ok_or_else(|| TamsError::InvalidInput("Missing id".to_string()))?,
+                flow_id: *flow_id,
+                timerange: row.timerange.clone(),
+                status: row.status.clone(),
+                progress,
+                created_at: DateTime::parse_from_rfc3339(&row.created_at)?.with_timezone(&Utc),
+                updated_at: DateTime::parse_from_rfc3339(&row.updated_at)?.with_timezone(&Utc),
+            })
+        })();
+        listing.push_parsed(parsed, || format!("deletion_requests.id={:?}", row.id));
+    }
+    Ok(listing)
+}
+
+fn job_from_row(row: &sqlx::sqlite::SqliteRow) -> TamsResult<Job> {
+    let timestamp = |column: &str| -> TamsResult<DateTime<Utc>> {
+        Ok(DateTime::parse_from_rfc3339(&row.try_get::<String, _>(column)?)?.with_timezone(&Utc))
+    };
+    Ok(Job {
+        id: row.try_get("id")?,
+        kind: row.try_get("kind")?,
+        target: row.try_get("target")?,
+        payload: serde_json::from_str(&row.try_get::<String, _>("payload")?)?,
+        status: row.try_get("status")?,
+        attempts: row.try_get("attempts")?,
+        run_at: timestamp("run_at")?,
+        last_error: row.try_get("last_error")?,
+        created_at: timestamp("created_at")?,
+        updated_at: timestamp("updated_at")?,
+    })
+}
+
+fn segment_from_row(row: &sqlx::sqlite::SqliteRow) -> TamsResult<FlowSegment> {
+    let integer = |column: &str| -> TamsResult<Option<i64>> { Ok(row.try_get(column)?) };
+    let get_urls: Option<String> = row.try_get("get_urls")?;
+    let essence_parameters: Option<String> = row.try_get("essence_parameters")?;
+    Ok(FlowSegment {
+        flow_id: Uuid::parse_str(&row.try_get::<String, _>("flow_id")?)?,
+        object_id: row.try_get("object_id")?,
+        timerange: row.try_get("timerange")?,
+        ts_offset: row.try_get("ts_offset")?,
+        sample_offset: integer("sample_offset")?.map(|v| v as u64),
+        sample_count: integer("sample_count")?.map(|v| v as u64),
+        key_frame_count: integer("key_frame_count")?.map(|v| v as u32),
+        get_urls: get_urls.as_deref().map(serde_json::from_str).transpose()?.unwrap_or_default(),
+        created_at: DateTime::parse_from_rfc3339(&row.try_get::<String, _>("created_at")?)?.with_timezone(&Utc),
+        essence_parameters: essence_parameters.as_deref().map(serde_json::from_str).transpose()?,
+    })
+}
+
+/// Where a page of sources or flows left off in their `(created_at, id)`
+/// order. Clients get it as an opaque `next_key` and pass it back as `page`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PageCursor {
+    pub created_at: String,
+    pub id: String,
+}
+
+impl PageCursor {
+    pub fn encode(&self) -> String {
+        BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(key: &str) -> TamsResult<Self> {
+        BASE64_URL_SAFE_NO_PAD
+            .decode(key)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| TamsError::BadRequest(format!("Invalid page key '{}'", key)))
+    }
+}
+
+/// Rows to fetch for a page of `limit`: one more, which tells whether there is
+/// another page. No limit is SQLite's -1.
+fn page_fetch_limit(limit: Option<u32>) -> i64 {
+    limit.map_or(-1, |limit| limit as i64 + 1)
+}
+
+/// The rows of a page fetched with [`page_fetch_limit`], and the key of the
+/// next page if the extra row came back
+fn split_page(mut rows: Vec<sqlx::sqlite::SqliteRow>, limit: Option<u32>) -> TamsResult<(Vec<sqlx::sqlite::SqliteRow>, Option<String>)> {
+    let Some(limit) = limit.filter(|&limit| rows.len() > limit as usize) else {
+        return Ok((rows, None));
+    };
+    rows.truncate(limit as usize);
+    let next_key = match rows.last() {
+        Some(last) => Some(PageCursor { created_at: last.try_get("created_at")?, id: last.try_get("id")? }.encode()),
+        None => None,
+    };
+    Ok((rows, next_key))
+}
+
+/// `<first_start_ns>_<object_id>` of the last object of a flow storage page
+fn parse_object_page_key(key: &str) -> TamsResult<(i64, String)> {
+    key.split_once('_')
+        .and_then(|(start, object_id)| Some((start.parse().ok()?, object_id.to_string())))
+        .ok_or_else(|| TamsError::BadRequest(format!("Invalid page key '{}'", key)))
+}
+
+/// `<start_ns>_<rowid>` of the last segment on a page, as returned in `next_key`
+fn parse_segment_page_key(key: &str) -> TamsResult<(i64, i64)> {
+    key.split_once('_')
+        .and_then(|(start, rowid)| Some((start.parse().ok()?, rowid.parse().ok()?)))
+        .ok_or_else(|| TamsError::BadRequest(format!("Invalid page key '{}'", key)))
+}
+
+/// Every flows column but flow_collection, which listings only read when asked
+/// to include it; [`flow_from_row`] still finds it, as NULL
+const FLOW_COLUMNS_WITHOUT_COLLECTION: &str = "id, source_id, format, label, description, tags, read_only, \
+     max_bit_rate, avg_bit_rate, container, codec, frame_width, frame_height, sample_rate, channels, \
+     NULL AS flow_collection, available_timerange, created_at, updated_at, retention_seconds, notify_url, \
+     notify_secret, owner";
+
+fn flow_from_row(row: &sqlx::sqlite::SqliteRow) -> TamsResult<Flow> {
+    let timestamp = |column: &str| -> TamsResult<DateTime<Utc>> {
+        Ok(DateTime::parse_from_rfc3339(&row.try_get::<String, _>(column)?)?.with_timezone(&Utc))
+    };
+    let integer = |column: &str| -> TamsResult<Option<i64>> { Ok(row.try_get(column)?) };
+    let flow_collection: Option<String> = row.try_get("flow_collection")?;
+    let available_timerange: Option<String> = row.try_get("available_timerange")?;
+    Ok(Flow {
+        id: Uuid::parse_str(&row.try_get::<String, _>("id")?)?,
+        source_id: row.try_get::<Option<String>, _>("source_id")?.map(|s| Uuid::parse_str(&s)).transpose()?,
+        format: serde_json::from_str(&row.try_get::<String, _>("format")?)?,
+        label: row.try_get("label")?,
+        description: row.try_get("description")?,
+        tags: serde_json::from_str(&row.try_get::<String, _>("tags")?)?,
+        read_only: integer("read_only")?.map(|v| v != 0),
+        max_bit_rate: integer("max_bit_rate")?.map(|v| v as u64),
+        avg_bit_rate: integer("avg_bit_rate")?.map(|v| v as u64),
+        container: row.try_get("container")?,
+        codec: row.try_get("codec")?,
+        frame_width: integer("frame_width")?.map(|v| v as u32),
+        frame_height: integer("frame_height")?.map(|v| v as u32),
+        sample_rate: integer("sample_rate")?.map(|v| v as u32),
+        channels: integer("channels")?.map(|v| v as u32),
+        flow_collection: flow_collection.as_deref().map(serde_json::from_str).transpose()?,
+        available_timerange: available_timerange.as_deref().map(serde_json::from_str).transpose()?,
+        retention_seconds: integer("retention_seconds")?.map(|v| v as u64),
+        notify_url: row.try_get("notify_url")?,
+        notify_secret: row.try_get("notify_secret")?,
+        owner: row.try_get("owner")?,
+        created_at: timestamp("created_at")?,
+        updated_at: timestamp("updated_at")?,
+    })
+}
+
+/// Stored flow references, accepting the legacy list of bare flow ids
+fn media_object_from_row(row: &sqlx::sqlite::SqliteRow) -> TamsResult<MediaObject> {
+    let mut flow_references = parse_flow_references(&row.try_get::<String, _>("flow_references")?);
+    if flow_references.is_empty() {
+        if let Some(flow_id) = row.try_get::<Option<String>, _>("segment_flow_id")? {
+            flow_references.push(FlowReference::new(Uuid::parse_str(&flow_id)?));
+        }
+    }
+    let metadata: Option<String> = row.try_get("metadata")?;
+    let format: Option<String> = row.try_get("format")?;
+    Ok(MediaObject {
+        object_id: row.try_get("object_id")?,
+        size_bytes: row.try_get::<Option<i64>, _>("size_bytes")?.map(|v| v as u64),
+        mime_type: row.try_get("mime_type")?,
+        flow_references,
+        created_at: DateTime::parse_from_rfc3339(&row.try_get::<String, _>("created_at")?)?.with_timezone(&Utc),
+        metadata: metadata.as_deref().map(serde_json::from_str).transpose()?.unwrap_or_default(),
+        storage_class: row.try_get("storage_class")?,
+        format: format.as_deref().map(serde_json::from_str).transpose()?,
+        content_hash: row.try_get("content_hash")?,
+    })
+}
+
+fn parse_flow_references(stored: &str) -> Vec<FlowReference> {
+    serde_json::from_str::<Vec<FlowReference>>(stored)
+        .or_else(|_| {
+            serde_json::from_str::<Vec<Uuid>>(stored)
+                .map(|ids| ids.into_iter().map(FlowReference::new).collect())
+        })
+        .unwrap_or_default()
+}
+
+/// Rows returned by a listing query. Rows that fail to parse are skipped and
+/// counted rather than failing the whole listing.
+#[derive(Debug)]
+pub struct Listing<T> {
+    pub items: Vec<T>,
+    pub skipped_corrupt: u64,
+}
+
+impl<T> Default for Listing<T> {
+    fn default() -> Self {
+        Listing {
+            items: Vec::new(),
+            skipped_corrupt: 0,
+        }
+    }
+}
+
+impl<T> Listing<T> {
+    fn push_parsed(&mut self, parsed: TamsResult<T>, row_key: impl FnOnce() -> String) {
+        match parsed {
+            Ok(item) => self.items.push(item),
+            Err(e) => {
+                tracing::error!("Skipping corrupt row {}: {}", row_key(), e);
+                metrics().record_corrupt_row();
+                self.skipped_corrupt += 1;
+            }
+        }
+    }
+}
+
+/// Outcome of deleting a flow's segments by object id
+#[derive(Debug)]
+pub struct SegmentObjectDeletion {
+    /// Segments removed per object id, in request (or segment) order
+    pub counts: Vec<(String, u64)>,
+    /// Range covering the removed segments, if any were removed
+    pub deleted_timerange: Option<TimeRange>,
+    /// The flow's available_timerange after the deletion
+    pub available_timerange: Option<TimeRange>,
+}
+
+/// `AND (created_at, id) > cursor`, then the order and limit of a listing page
+fn push_page_conditions(query: &mut QueryBuilder<'_, Sqlite>, after: Option<&PageCursor>, limit: Option<u32>) {
+    if let Some(cursor) = after {
+        query
+            .push(" AND (created_at, id) > (")
+            .push_bind(cursor.created_at.clone())
+            .push(", ")
+            .push_bind(cursor.id.clone())
+            .push(")");
+    }
+    query.push(" ORDER BY created_at, id LIMIT ").push_bind(page_fetch_limit(limit));
+}
+
+// Filter structs for queries. Each filter that is set narrows the listing
+// with an exact match; filters that contradict each other match nothing.
+//
+// A format filter is served by idx_sources_format / idx_flows_format, a flow
+// source_id filter by idx_flows_source_page, which also keeps that source's
+// flows in page order, and codec by idx_flows_codec. Labels and frame
+// dimensions have no index of their own and are checked row by row among
+// whatever the other filters (or the page order) select.
+#[derive(Debug, Default)]
+pub struct SourceFilters {
+    pub format: Option<ContentFormat>,
+    pub label: Option<String>,
+}
+
+impl SourceFilters {
+    /// Append an `AND` condition for each filter set, after a `WHERE`
+    fn push_conditions(&self, query: &mut QueryBuilder<'_, Sqlite>) -> TamsResult<()> {
+        if let Some(format) = &self.format {
+            query.push(" AND format = ").push_bind(serde_json::to_string(format)?);
+        }
+        if let Some(label) = &self.label {
+            query.push(" AND label = ").push_bind(label.clone());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FlowFilters {
+    pub source_id: Option<Uuid>,
+    pub format: Option<ContentFormat>,
+    pub label: Option<String>,
+    pub codec: Option<String>,
+    pub frame_width: Option<u32>,
+    pub frame_height: Option<u32>,
+    /// Only flows with a segment overlapping `[start_ns, end_ns)`, in
+    /// nanoseconds since the epoch; either bound may be left open. Found
+    /// through idx_flow_segments_flow_start for each candidate flow.
+    pub start_ns: Option<i64>,
+    pub end_ns: Option<i64>,
+}
+
+impl FlowFilters {
+    /// Append an `AND` condition for each filter set, after a `WHERE`, reading
+    /// segment bounds from the `segment_bounds` relation
+    fn push_conditions(&self, query: &mut QueryBuilder<'_, Sqlite>, segment_bounds: &str) -> TamsResult<()> {
+        if let Some(source_id) = self.source_id {
+            query.push(" AND source_id = ").push_bind(source_id.to_string());
+        }
+        if let Some(format) = &self.format {
+            query.push(" AND format = ").push_bind(serde_json::to_string(format)?);
+        }
+        if let Some(label) = &self.label {
+            query.push(" AND label = ").push_bind(label.clone());
+        }
+        if let Some(codec) = &self.codec {
+            query.push(" AND codec = ").push_bind(codec.clone());
+        }
+        if let Some(width) = self.frame_width {
+            query.push(" AND frame_width = ").push_bind(width as i64);
+        }
+        if let Some(height) = self.frame_height {
+            query.push(" AND frame_height = ").push_bind(height as i64);
+        }
+        if self.start_ns.is_some() || self.end_ns.is_some() {
+            query.push(format!(" AND EXISTS (SELECT 1 FROM {} WHERE flow_id = flows.id", segment_bounds));
+            if let Some(start_ns) = self.start_ns {
+                query.push(" AND end_ns > ").push_bind(start_ns);
+            }
+            if let Some(end_ns) = self.end_ns {
+                query.push(" AND start_ns < ").push_bind(end_ns);
+            }
+            query.push(")");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FlowSegmentFilters {
+    pub object_id: Option<String>,
+    /// Only segments overlapping `[start_ns, end_ns)`, in nanoseconds since the
+    /// epoch; either bound may be left open
+    pub start_ns: Option<i64>,
+    pub end_ns: Option<i64>,
+    pub reverse_order: Option<bool>,
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::clock::{Clock, FakeClock};
+    use std::sync::atomic::Ordering;
+    use tempfile::TempDir;
+
+    pub(crate) async fn create_test_database() -> (Database, TempDir) {
+        create_test_database_with(|_| {}).await
+    }
+
+    async fn create_test_database_with(configure: impl FnOnce(&mut DatabaseConfig)) -> (Database, TempDir) {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = crate::config::AppConfig::from_file("config").unwrap().database;
+        config.url = format!("sqlite:{}", temp_dir.path().join("tams.db").display());
+        configure(&mut config);
+        let database = Database::new(&config).await.unwrap();
+        database.migrate().await.unwrap();
+        (database, temp_dir)
+    }
+
+    #[tokio::test]
+    async fn test_connection_pragmas_follow_config() {
+        let pragmas = |database: Database| async move {
+            let mut conn = database.pool.acquire().await.unwrap();
+            let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode").fetch_one(&mut *conn).await.unwrap();
+            let synchronous: i64 = sqlx::query_scalar("PRAGMA synchronous").fetch_one(&mut *conn).await.unwrap();
+            let foreign_keys: i64 = sqlx::query_scalar("PRAGMA foreign_keys").fetch_one(&mut *conn).await.unwrap();
+            (journal_mode, synchronous, foreign_keys)
+        };
+
+        let (database, _temp_dir) = create_test_database().await;
+        assert_eq!(pragmas(database).await, ("wal".to_string(), 1, 1));
+
+        let (database, _temp_dir) = create_test_database_with(|config| {
+            config.journal_mode = JournalMode::Delete;
+            config.synchronous = Synchronous::Full;
+            config.foreign_keys = false;
+        })
+        .await;
+        assert_eq!(pragmas(database).await, ("delete".to_string(), 2, 0));
+    }
+
+    /// Take the write lock on a separate connection, as a concurrent writer would
+    async fn hold_write_lock(database: &Database) -> sqlx::pool::PoolConnection<Sqlite> {
+        let mut holder = database.pool.acquire().await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut *holder).await.unwrap();
+        holder
+    }
+
+    #[tokio::test]
+    async fn test_busy_writes_are_retried_until_the_lock_clears() {
+        let (database, _temp_dir) = create_test_database_with(|config| {
+            config.busy_timeout_ms = 0;
+            config.busy_retries = 8;
+            config.busy_retry_base_ms = 10;
+        })
+        .await;
+
+        let mut holder = hold_write_lock(&database).await;
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            sqlx::query("COMMIT").execute(&mut *holder).await.unwrap();
+        });
+
+        let source = Source::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_source(&source).await.unwrap();
+        release.await.unwrap();
+        assert!(database.get_source(&source.id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_busy_transactional_writes_are_retried_until_the_lock_clears() {
+        let (database, _temp_dir) = create_test_database_with(|config| {
+            config.busy_timeout_ms = 0;
+            config.busy_retries = 8;
+            config.busy_retry_base_ms = 10;
+        })
+        .await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_flow(&flow).await.unwrap();
+        database.add_flow_segment(&CreateSegmentRequest::new("obj", "0:0", "10:0").into_segment(flow.id)).await.unwrap();
+
+        // A deferred transaction failed at its first write to the held lock rather than waiting
+        let mut holder = hold_write_lock(&database).await;
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            sqlx::query("COMMIT").execute(&mut *holder).await.unwrap();
+        });
+        assert!(database.set_flow_owner(&flow.id, "team-a", Utc::now()).await.unwrap());
+        let deletion = database.delete_flow_segments_by_objects(&flow.id, &["obj".to_string()]).await.unwrap();
+        release.await.unwrap();
+
+        assert_eq!(deletion.counts, vec![("obj".to_string(), 1)]);
+        assert_eq!(database.get_flow(&flow.id).await.unwrap().unwrap().owner.as_deref(), Some("team-a"));
+    }
+
+    #[tokio::test]
+    async fn test_busy_write_gives_up_with_service_unavailable() {
+        use axum::response::IntoResponse;
+
+        let (database, _temp_dir) = create_test_database_with(|config| {
+            config.busy_timeout_ms = 0;
+            config.busy_retries = 2;
+            config.busy_retry_base_ms = 1;
+        })
+        .await;
+
+        let mut holder = hold_write_lock(&database).await;
+        let err = database
+            .create_source(&Source::new(Uuid::new_v4(), ContentFormat::Video))
+            .await
+            .unwrap_err();
+        sqlx::query("ROLLBACK").execute(&mut *holder).await.unwrap();
+
+        assert!(matches!(&err, TamsError::Database(e) if is_busy_error(e)));
+        let response = err.into_response();
+        assert_eq!(response.status(), axum::http::StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key(axum::http::header::RETRY_AFTER));
+    }
+
+    #[tokio::test]
+    async fn test_transactions_serialize_read_modify_write() {
+        let (database, _temp_dir) = create_test_database().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_flow(&flow).await.unwrap();
+
+        let mut first = database.begin_transaction().await.unwrap();
+        let mut read = first.get_flow_required(&flow.id).await.unwrap();
+        read.label = Some("first".to_string());
+        first.update_flow(&read).await.unwrap();
+
+        // A second transaction can't start, and so can't read the stale flow,
+        // until the first commits
+        let second = tokio::spawn({
+            let database = database.clone();
+            let id = flow.id;
+            async move {
+                let mut second = database.begin_transaction().await.unwrap();
+                let label = second.get_flow_required(&id).await.unwrap().label;
+                second.rollback().await.unwrap();
+                label
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!second.is_finished());
+
+        first.commit().await.unwrap();
+        assert_eq!(second.await.unwrap().as_deref(), Some("first"));
+    }
+
+    #[tokio::test]
+    async fn test_transaction_rollback_discards_writes() {
+        let (database, _temp_dir) = create_test_database().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_flow(&flow).await.unwrap();
+
+        let mut changed = flow.clone();
+        changed.label = Some("discarded".to_string());
+
+        let mut tx = database.begin_transaction().await.unwrap();
+        tx.update_flow(&changed).await.unwrap();
+        tx.rollback().await.unwrap();
+        assert_eq!(database.get_flow_required(&flow.id).await.unwrap().label, None);
+
+        // Dropping an unsettled transaction rolls it back too
+        let mut tx = database.begin_transaction().await.unwrap();
+        tx.update_flow(&changed).await.unwrap();
+        drop(tx);
+        assert_eq!(database.get_flow_required(&flow.id).await.unwrap().label, None);
+        database.begin_transaction().await.unwrap().commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unparseable_segment_timeranges_by_parsing_mode() {
+        let (lenient, _temp_dir) = create_test_database().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        lenient.create_flow(&flow).await.unwrap();
+        let segment = CreateSegmentRequest::new("good", "0:0", "10:0");
+        lenient.add_flow_segment(&segment.into_segment(flow.id)).await.unwrap();
+        sqlx::query("INSERT INTO flow_segments (flow_id, object_id, timerange, created_at) VALUES (?1, 'bad', '[0:0_10:0)', ?2)")
+            .bind(flow.id.to_string())
+            .bind(format_rfc3339(&Utc::now()))
+            .execute(&lenient.pool)
+            .await
+            .unwrap();
+        let all = FlowSegmentFilters::default();
+        let window = FlowSegmentFilters {
+            start_ns: Some(5_000_000_000),
+            end_ns: Some(6_000_000_000),
+            ..Default::default()
+        };
+
+        // Lenient: listed, but left out of range queries and reported as skipped
+        let (listed, _) = lenient.get_flow_segments_by_timerange(&flow.id, &all, 100, None).await.unwrap();
+        assert_eq!((listed.items.len(), listed.skipped_corrupt), (2, 0));
+        let (in_window, _) = lenient.get_flow_segments_by_timerange(&flow.id, &window, 100, None).await.unwrap();
+        assert_eq!(in_window.items.len(), 1);
+        assert_eq!(in_window.items[0].object_id, "good");
+        assert_eq!(in_window.skipped_corrupt, 1);
+
+        let strict = Database {
+            timerange_parsing: TimerangeParsing::Strict,
+            ..lenient.clone()
+        };
+        assert_eq!(strict.get_flow_segments_by_timerange(&flow.id, &all, 100, None).await.unwrap().0.items.len(), 2);
+        assert!(matches!(
+            strict.get_flow_segments_by_timerange(&flow.id, &window, 100, None).await,
+            Err(TamsError::InvalidTimerange(_))
+        ));
+
+        // Corrupt get_urls fail the segment's row rather than reading as none
+        sqlx::query("UPDATE flow_segments SET get_urls = '{bad json' WHERE object_id = 'good'")
+            .execute(&lenient.pool)
+            .await
+            .unwrap();
+        let (listed, _) = lenient.get_flow_segments_by_timerange(&flow.id, &all, 100, None).await.unwrap();
+        assert_eq!((listed.items.len(), listed.skipped_corrupt), (1, 1));
+        let listed = lenient.get_flow_segments(&flow.id).await.unwrap();
+        assert_eq!((listed.items.len(), listed.skipped_corrupt), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_segments_by_timerange_window_order_and_pages() {
+        let (database, _temp_dir) = create_test_database().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_flow(&flow).await.unwrap();
+        for (object_id, start, end) in [("b", "10:0", "20:0"), ("a", "0:0", "10:0"), ("c", "20:0", "30:0")] {
+            let segment = CreateSegmentRequest::new(object_id, start, end);
+            database.add_flow_segment(&segment.into_segment(flow.id)).await.unwrap();
+        }
+
+        let second = 1_000_000_000;
+        let objects = |filters: FlowSegmentFilters, limit: u32, page: Option<String>| {
+            let database = database.clone();
+            async move {
+                let (listing, next_key) =
+                    database.get_flow_segments_by_timerange(&flow.id, &filters, limit, page.as_deref()).await.unwrap();
+                (listing.items.into_iter().map(|segment| segment.object_id).collect::<Vec<_>>(), next_key)
+            }
+        };
+        let window = |start: Option<i64>, end: Option<i64>| FlowSegmentFilters {
+            start_ns: start.map(|s| s * second),
+            end_ns: end.map(|e| e * second),
+            ..Default::default()
+        };
+
+        assert_eq!(objects(window(None, None), 100, None).await.0, ["a", "b", "c"]);
+        // Half-open: a window ending where a segment starts, or starting where one ends, misses it
+        assert_eq!(objects(window(Some(10), Some(20)), 100, None).await.0, ["b"]);
+        assert_eq!(objects(window(Some(5), Some(15)), 100, None).await.0, ["a", "b"]);
+        assert_eq!(objects(window(Some(29), Some(31)), 100, None).await.0, ["c"]);
+        assert!(objects(window(Some(30), Some(40)), 100, None).await.0.is_empty());
+        assert!(objects(window(Some(-5), Some(0)), 100, None).await.0.is_empty());
+        // Open-ended
+        assert_eq!(objects(window(Some(15), None), 100, None).await.0, ["b", "c"]);
+        assert_eq!(objects(window(None, Some(10)), 100, None).await.0, ["a"]);
+
+        // Pages follow on from the key, forwards and reversed
+        let (first, next_key) = objects(window(None, None), 2, None).await;
+        assert_eq!(first, ["a", "b"]);
+        assert_eq!(objects(window(None, None), 2, next_key).await, (vec!["c".to_string()], None));
+        let reversed = || FlowSegmentFilters { reverse_order: Some(true), ..window(Some(5), None) };
+        let (first, next_key) = objects(reversed(), 2, None).await;
+        assert_eq!(first, ["c", "b"]);
+        assert_eq!(objects(reversed(), 2, next_key).await, (vec!["a".to_string()], None));
+        assert_eq!(objects(window(None, None), 3, None).await.1, None);
+
+        let invalid = database.get_flow_segments_by_timerange(&flow.id, &window(None, None), 2, Some("next")).await;
+        assert!(matches!(invalid, Err(TamsError::BadRequest(_))));
+        let count = database.count_flow_segments(&flow.id, &window(Some(5), None)).await.unwrap();
+        assert_eq!(count, 3);
+    }
+
+    #[tokio::test]
+    async fn test_list_flows_skips_corrupt_rows() {
+        let (database, _temp_dir) = create_test_database().await;
+
+        let healthy = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_flow(&healthy).await.unwrap();
+
+        sqlx::raw_sql(
+            r#"INSERT INTO flows (id, format, tags, created_at, updated_at)
+               VALUES ('not-a-uuid', '"urn:x-nmos:format:video"', '{bad json', 'yesterday', 'today')"#,
+        )
+        .execute(&database.pool)
+        .await
+        .unwrap();
+
+        let before = metrics().corrupt_rows_skipped.load(Ordering::Relaxed);
+        let listing = database.list_flows(false, None).await.unwrap();
+
+        assert_eq!(listing.items.len(), 1);
+        assert_eq!(listing.items[0].id, healthy.id);
+        assert_eq!(listing.skipped_corrupt, 1);
+        assert!(metrics().corrupt_rows_skipped.load(Ordering::Relaxed) > before);
+
+        // Corrupt JSON in an optional column fails its row too
+        for column in ["available_timerange", "flow_collection"] {
+            let damaged = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+            database.create_flow(&damaged).await.unwrap();
+            sqlx::query(&format!("UPDATE flows SET {} = '{{bad json' WHERE id = ?1", column))
+                .bind(damaged.id.to_string())
+                .execute(&database.pool)
+                .await
+                .unwrap();
+        }
+        let listing = database.list_flows(true, None).await.unwrap();
+        assert_eq!((listing.items.len(), listing.skipped_corrupt), (1, 3));
+        let (listing, _) = database.get_flows(100, None, true, None, &FlowFilters::default()).await.unwrap();
+        assert_eq!((listing.items.len(), listing.skipped_corrupt), (1, 3));
+    }
+
+    #[tokio::test]
+    async fn test_list_sources_skips_corrupt_rows() {
+        let (database, _temp_dir) = create_test_database().await;
+
+        let healthy = Source::new(Uuid::new_v4(), ContentFormat::Audio);
+        database.create_source(&healthy).await.unwrap();
+
+        sqlx::raw_sql(
+            r#"INSERT INTO sources (id, format, tags, created_at, updated_at)
+               VALUES ('00000000-0000-0000-0000-000000000001', 'garbage', '{}', 'now', 'now')"#,
+        )
+        .execute(&database.pool)
+        .await
+        .unwrap();
+
+        let listing = database.list_sources().await.unwrap();
+        assert_eq!(listing.items.len(), 1);
+        assert_eq!(listing.skipped_corrupt, 1);
+    }
+
+    /// Paging through flows as `GET /flows` does, with and without
+    /// `?include=flow_collection`: `cargo test --release -- --ignored bench_flow_pages`
+    #[tokio::test]
+    #[ignore]
+    async fn bench_flow_pages_with_large_collections() {
+        let (database, _temp_dir) = create_test_database().await;
+
+        let large_collection = FlowCollection {
+            flows: (0..2_000)
+                .map(|i| FlowCollectionItem {
+                    flow_id: Uuid::new_v4(),
+                    role: Some(format!("role-{}", i)),
+                    container_map: None,
+                })
+                .collect(),
+        };
+        for i in 0..10_000 {
+            let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Multi);
+            if i % 2 == 0 {
+                flow.flow_collection = Some(large_collection.clone());
+            }
+            database.create_flow(&flow).await.unwrap();
+        }
+
+        let filters = FlowFilters::default();
+        let page_through = |include| {
+            let (database, filters) = (&database, &filters);
+            async move {
+                let started = std::time::Instant::now();
+                let (mut listed, mut page) = (0, None);
+                loop {
+                    let (flows, next_key) = database.get_flows(100, page.as_deref(), include, None, filters).await.unwrap();
+                    listed += flows.items.len();
+                    match next_key {
+                        Some(key) => page = Some(key),
+                        None => return (listed, started.elapsed()),
+                    }
+                }
+            }
+        };
+        let (with_collections, parsing) = page_through(true).await;
+        let (without_collections, skipping) = page_through(false).await;
+        assert_eq!((with_collections, without_collections), (10_000, 10_000));
+        // Collections are only parsed when asked for, which is most of the work
+        assert!(skipping * 2 < parsing, "{:?} without collections, {:?} with", skipping, parsing);
+    }
+
+    #[tokio::test]
+    async fn test_flow_pages_only_read_collections_when_asked() {
+        let (database, _temp_dir) = create_test_database().await;
+        let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Multi);
+        flow.flow_collection = Some(FlowCollection {
+            flows: vec![FlowCollectionItem { flow_id: Uuid::new_v4(), role: Some("video".to_string()), container_map: None }],
+        });
+        database.create_flow(&flow).await.unwrap();
+
+        let filters = FlowFilters::default();
+        let page = |include| database.get_flows(10, None, include, None, &filters);
+        let (without, _) = page(false).await.unwrap();
+        assert_eq!(without.items[0].id, flow.id);
+        assert!(without.items[0].flow_collection.is_none());
+        let (with, _) = page(true).await.unwrap();
+        assert_eq!(with.items[0].flow_collection.as_ref().unwrap().flows.len(), 1);
+    }
+
+    /// Claim an allocation in a transaction of its own
+    async fn claim(database: &Database, allocation: &StorageObject, now: DateTime<Utc>) -> TamsResult<StorageObject> {
+        let mut tx = database.begin_transaction().await?;
+        let claimed = tx.claim_storage_allocation(None, allocation, now).await?;
+        tx.commit().await?;
+        Ok(claimed)
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_allocations_have_one_winner() {
+        let (database, _temp_dir) = create_test_database().await;
+        let allocation = |put_url: &str| StorageObject {
+            object_id: "shared-object".to_string(),
+            put_url: put_url.to_string(),
+            put_headers: None,
+            expires_at: Some(Utc::now() + chrono::Duration::hours(1)),
+            media_store: None,
+            storage_class: None,
+            already_allocated: false,
+        };
+
+        let first = {
+            let database = database.clone();
+            let allocation = allocation("http://a");
+            tokio::spawn(async move { claim(&database, &allocation, Utc::now()).await })
+        };
+        let second = {
+            let database = database.clone();
+            let allocation = allocation("http://b");
+            tokio::spawn(async move { claim(&database, &allocation, Utc::now()).await })
+        };
+        let first = first.await.unwrap().unwrap();
+        let second = second.await.unwrap().unwrap();
+
+        assert_ne!(first.already_allocated, second.already_allocated);
+        assert_eq!(first.put_url, second.put_url);
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM storage_allocations WHERE object_id = ?1")
+            .bind("shared-object")
+            .fetch_one(&database.pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, 1);
+    }
+
+    #[tokio::test]
+    async fn test_expired_allocation_is_replaced() {
+        let (database, _temp_dir) = create_test_database().await;
+        let now = Utc::now();
+        let allocation = |put_url: &str, expires_at: DateTime<Utc>| StorageObject {
+            object_id: "reused-object".to_string(),
+            put_url: put_url.to_string(),
+            put_headers: None,
+            expires_at: Some(expires_at),
+            media_store: None,
+            storage_class: None,
+            already_allocated: false,
+        };
+
+        let first = allocation("http://a", now + chrono::Duration::hours(1));
+        claim(&database, &first, now).await.unwrap();
+        let again = claim(&database, &allocation("http://b", now + chrono::Duration::hours(2)), now).await.unwrap();
+        assert!(again.already_allocated);
+        assert_eq!(again.put_url, "http://a");
+
+        // Once the first allocation has expired the object id is handed out afresh
+        let later = now + chrono::Duration::hours(1);
+        let renewed = allocation("http://c", later + chrono::Duration::hours(1));
+        let claimed = claim(&database, &renewed, later).await.unwrap();
+        assert!(!claimed.already_allocated);
+        assert_eq!(claimed.put_url, "http://c");
+        let stored = database.get_storage_allocation("reused-object").await.unwrap().unwrap();
+        assert_eq!((stored.put_url.as_str(), stored.expires_at), ("http://c", renewed.expires_at));
+
+        assert_eq!(database.purge_expired_storage_allocations(later).await.unwrap(), 0);
+        assert_eq!(database.purge_expired_storage_allocations(later + chrono::Duration::hours(1)).await.unwrap(), 1);
+        assert!(database.get_storage_allocation("reused-object").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_legacy_flow_references_migrated_with_segment_timeranges() {
+        let (database, _temp_dir) = create_test_database().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        let allocated_only = Uuid::new_v4();
+        database.create_flow(&flow).await.unwrap();
+        for (start, end) in [("10:0", "20:0"), ("20:0", "30:0")] {
+            let request = CreateSegmentRequest::new("legacy-object", start, end);
+            database.add_flow_segment(&request.into_segment(flow.id)).await.unwrap();
+        }
+        sqlx::query("INSERT INTO media_objects (object_id, flow_references, created_at) VALUES (?1, ?2, ?3)")
+            .bind("legacy-object")
+            .bind(serde_json::to_string(&vec![allocated_only]).unwrap())
+            .bind(format_rfc3339(&Utc::now()))
+            .execute(&database.pool)
+            .await
+            .unwrap();
+
+        database.migrate().await.unwrap();
+        let stored: String = sqlx::query_scalar("SELECT flow_references FROM media_objects WHERE object_id = ?1")
+            .bind("legacy-object")
+            .fetch_one(&database.pool)
+            .await
+            .unwrap();
+        let stored: Vec<FlowReference> = serde_json::from_str(&stored).unwrap();
+        assert_eq!(stored.len(), 2);
+
+        let object = database.get_media_object_required("legacy-object").await.unwrap();
+        assert_eq!(object.primary_flow_id(), Some(allocated_only));
+        assert!(object.flow_references[0].timerange.is_none());
+        let covered = object.flow_references[1].timerange.as_ref().unwrap();
+        assert_eq!((object.flow_references[1].flow_id, covered.start.as_str(), covered.end.as_deref().unwrap()), (flow.id, "10:0", "30:0"));
+    }
+
+    #[tokio::test]
+    async fn test_legacy_webhook_event_names_migrated() {
+        let (database, _temp_dir) = create_test_database().await;
+        sqlx::query("INSERT INTO webhooks (url, events) VALUES (?1, ?2)")
+            .bind("https://example.com/legacy")
+            .bind("flow.created,flow.segments_added,custom.thing")
+            .execute(&database.pool)
+            .await
+            .unwrap();
+
+        database.migrate().await.unwrap();
+        let webhooks = database.get_webhooks_list().await.unwrap();
+        let webhook = webhooks.iter().find(|w| w.url == "https://example.com/legacy").unwrap();
+        assert_eq!(webhook.events, vec!["flows/created", "flows/segments_added", "custom.thing"]);
+    }
+
+    #[tokio::test]
+    async fn test_deletion_requests_without_jobs_are_found() {
+        let (database, _temp_dir) = create_test_database().await;
+        let now = format_rfc3339(&Utc::now());
+        for (id, status) in [("legacy", "pending"), ("finished", "done")] {
+            sqlx::query("INSERT INTO deletion_requests (id, flow_id, status, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?4)")
+                .bind(id)
+                .bind(Uuid::new_v4().to_string())
+                .bind(status)
+                .bind(&now)
+                .execute(&database.pool)
+                .await
+                .unwrap();
+        }
+        assert_eq!(database.get_unqueued_deletion_request_ids("flow_deletion").await.unwrap(), vec!["legacy"]);
+
+        database
+            .create_job(&Job::new("flow_deletion", Some("legacy"), serde_json::json!({ "deletion_request_id": "legacy" }), Utc::now()))
+            .await
+            .unwrap();
+        assert!(database.get_unqueued_deletion_request_ids("flow_deletion").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_reports_affected_rows() {
+        let (database, _temp_dir) = create_test_database().await;
+
+        assert_eq!(database.delete_source(&Uuid::new_v4()).await.unwrap(), 0);
+        assert_eq!(database.delete_flow(&Uuid::new_v4()).await.unwrap(), 0);
+
+        let source = Source::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_source(&source).await.unwrap();
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_flow(&flow).await.unwrap();
+
+        assert_eq!(database.delete_source(&source.id).await.unwrap(), 1);
+        assert_eq!(database.delete_flow(&flow.id).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_deletes_cascade_through_foreign_keys() {
+        let (database, _temp_dir) = create_test_database().await;
+        let source = Source::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_source(&source).await.unwrap();
+        let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        flow.source_id = Some(source.id);
+        database.create_flow(&flow).await.unwrap();
+        let segment = CreateSegmentRequest::new("obj-1", "0:0", "10:0");
+        database.add_flow_segment(&segment.clone().into_segment(flow.id)).await.unwrap();
+
+        // A segment can't point at a flow that doesn't exist
+        assert!(database.add_flow_segment(&segment.clone().into_segment(Uuid::new_v4())).await.is_err());
+
+        // Nor can rows written by SQL alone, bypassing every application check
+        let foreign_key_error = |result: Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error>| {
+            matches!(result, Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation())
+        };
+        let orphan_segment = sqlx::query(
+            "INSERT INTO flow_segments (flow_id, object_id, timerange, created_at) VALUES (?1, 'obj-2', '0:0_1:0', '2026-01-01T00:00:00Z')",
+        )
+        .bind(Uuid::new_v4().to_string())
+        .execute(&database.pool)
+        .await;
+        assert!(foreign_key_error(orphan_segment));
+        let orphan_flow = sqlx::query(
+            "INSERT INTO flows (id, source_id, format, tags, read_only, created_at, updated_at) \
+             VALUES (?1, ?2, '\"urn:x-nmos:format:video\"', '{}', 0, '2026-01-01T00:00:00Z', '2026-01-01T00:00:00Z')",
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(Uuid::new_v4().to_string())
+        .execute(&database.pool)
+        .await;
+        assert!(foreign_key_error(orphan_flow));
+
+        // A source can't be deleted from under its flows
+        assert!(matches!(database.delete_source(&source.id).await, Err(TamsError::Conflict(_))));
+        assert_eq!(database.get_flow(&flow.id).await.unwrap().unwrap().source_id, Some(source.id));
+
+        database.delete_flow(&flow.id).await.unwrap();
+        assert!(database.get_flow_segments(&flow.id).await.unwrap().items.is_empty());
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM flow_segments")
+            .fetch_one(&database.pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 0);
+        assert_eq!(database.delete_source(&source.id).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_reports_rows_orphaned_without_enforcement() {
+        let (database, _temp_dir) = create_test_database_with(|config| config.foreign_keys = false).await;
+        let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        flow.source_id = Some(Uuid::new_v4());
+        database.create_flow(&flow).await.unwrap();
+        let segment = CreateSegmentRequest::new("obj-1", "0:0", "10:0");
+        database.add_flow_segment(&segment.into_segment(Uuid::new_v4())).await.unwrap();
+
+        database.migrate().await.unwrap();
+        assert_eq!(database.get_flow(&flow.id).await.unwrap().unwrap().source_id, None);
+        let orphans: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM flow_segments")
+            .fetch_one(&database.pool)
+            .await
+            .unwrap();
+        assert_eq!(orphans, 1, "orphan segments are reported, not deleted");
+    }
+
+    #[tokio::test]
+    async fn test_migrate_rebuilds_flows_to_restrict_source_deletes() {
+        let (database, _temp_dir) = create_test_database_with(|config| config.foreign_keys = false).await;
+        let on_delete = || {
+            sqlx::query_scalar::<_, String>("SELECT on_delete FROM pragma_foreign_key_list('flows') WHERE \"table\" = 'sources'")
+                .fetch_one(&database.pool)
+        };
+        assert_eq!(on_delete().await.unwrap(), "RESTRICT");
+
+        // Put back the flows table of a database from before sources were protected
+        let definition: String = sqlx::query_scalar("SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'flows'")
+            .fetch_one(&database.pool)
+            .await
+            .unwrap();
+        sqlx::query("DROP TABLE flows").execute(&database.pool).await.unwrap();
+        sqlx::query(&definition.replace("ON DELETE RESTRICT", "ON DELETE SET NULL"))
+            .execute(&database.pool)
+            .await
+            .unwrap();
+        assert_eq!(on_delete().await.unwrap(), "SET NULL");
+        let source = Source::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_source(&source).await.unwrap();
+        let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        flow.source_id = Some(source.id);
+        flow.owner = Some("team-a".to_string());
+        database.create_flow(&flow).await.unwrap();
+
+        database.migrate().await.unwrap();
+        assert_eq!(on_delete().await.unwrap(), "RESTRICT");
+        let kept = database.get_flow(&flow.id).await.unwrap().unwrap();
+        assert_eq!(kept.source_id, Some(source.id));
+        assert_eq!(kept.owner.as_deref(), Some("team-a"));
+        let indexes: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name = 'idx_flows_owner'")
+            .fetch_one(&database.pool)
+            .await
+            .unwrap();
+        assert_eq!(indexes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_flows_rebuild_is_rolled_back() {
+        let (database, _temp_dir) = create_test_database_with(|config| config.foreign_keys = false).await;
+        let on_delete = || {
+            sqlx::query_scalar::<_, String>("SELECT on_delete FROM pragma_foreign_key_list('flows') WHERE \"table\" = 'sources'")
+                .fetch_one(&database.pool)
+        };
+        let definition: String = sqlx::query_scalar("SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'flows'")
+            .fetch_one(&database.pool)
+            .await
+            .unwrap();
+        sqlx::query("DROP TABLE flows").execute(&database.pool).await.unwrap();
+        sqlx::query(&definition.replace("ON DELETE RESTRICT", "ON DELETE SET NULL"))
+            .execute(&database.pool)
+            .await
+            .unwrap();
+        // A flow naming a source that doesn't exist fails the rebuild's check
+        let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        flow.source_id = Some(Uuid::new_v4());
+        database.create_flow(&flow).await.unwrap();
+
+        assert!(matches!(database.restrict_source_deletes().await, Err(TamsError::Internal(_))));
+        assert_eq!(on_delete().await.unwrap(), "SET NULL");
+        assert!(database.get_flow(&flow.id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_schema_version_skew() {
+        let (database, _temp_dir) = create_test_database().await;
+        assert_eq!(database.schema_version().await.unwrap(), Some(SCHEMA_VERSION));
+        assert_eq!(database.check_schema_version().await.unwrap(), SchemaSkew::Current);
+        let record = |version: i64, min_compatible: i64| {
+            sqlx::query("INSERT INTO schema_migrations VALUES (?1, ?2, '9.9.9', '2026-01-01T00:00:00Z')")
+                .bind(version)
+                .bind(min_compatible)
+                .execute(&database.pool)
+        };
+
+        // A database left behind by an older binary is brought up to date
+        sqlx::query("DELETE FROM schema_migrations").execute(&database.pool).await.unwrap();
+        record(SCHEMA_VERSION - 1, SCHEMA_VERSION - 1).await.unwrap();
+        assert_eq!(
+            database.check_schema_version().await.unwrap(),
+            SchemaSkew::Older { found: SCHEMA_VERSION - 1 }
+        );
+        database.migrate().await.unwrap();
+        assert_eq!(database.schema_version().await.unwrap(), Some(SCHEMA_VERSION));
+
+        // A newer binary that still supports this one lets it run, and stays newest
+        record(SCHEMA_VERSION + 1, SCHEMA_VERSION).await.unwrap();
+        database.migrate().await.unwrap();
+        assert_eq!(database.schema_version().await.unwrap(), Some(SCHEMA_VERSION + 1));
+
+        // One that doesn't stops it before anything is changed
+        record(SCHEMA_VERSION + 2, SCHEMA_VERSION + 2).await.unwrap();
+        let err = database.migrate().await.unwrap_err();
+        assert!(matches!(err, TamsError::SchemaTooNew(_)));
+        assert!(err.to_string().contains(&format!("schema version {} or later", SCHEMA_VERSION + 2)));
+    }
+
+    #[tokio::test]
+    async fn test_migrate_adds_source_owners_to_version_9_databases() {
+        let (database, _temp_dir) = create_test_database().await;
+        let source = Source::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_source(&source).await.unwrap();
+        // Put back the sources table of a database from before owners were recorded
+        sqlx::raw_sql("DROP INDEX idx_sources_owner; ALTER TABLE sources DROP COLUMN owner; DELETE FROM schema_migrations")
+            .execute(&database.pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO schema_migrations VALUES (9, 2, '9.9.9', '2026-01-01T00:00:00Z')")
+            .execute(&database.pool)
+            .await
+            .unwrap();
+
+        database.migrate().await.unwrap();
+        assert_eq!(database.schema_version().await.unwrap(), Some(SCHEMA_VERSION));
+        assert_eq!(database.get_source(&source.id).await.unwrap().unwrap().owner, None);
+        let indexes: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name = 'idx_sources_owner'")
+            .fetch_one(&database.pool)
+            .await
+            .unwrap();
+        assert_eq!(indexes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_replaces_invalid_utf8_text() {
+        let (database, _temp_dir) = create_test_database().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_flow(&flow).await.unwrap();
+        // "cam " and a CESU-8 encoded lone surrogate
+        sqlx::query("UPDATE flows SET label = CAST(X'63616D20EDA080' AS TEXT) WHERE id = ?1")
+            .bind(flow.id.to_string())
+            .execute(&database.pool)
+            .await
+            .unwrap();
+        assert!(database.get_flow(&flow.id).await.is_err());
+
+        database.migrate().await.unwrap();
+        let label = database.get_flow(&flow.id).await.unwrap().unwrap().label.unwrap();
+        assert!(label.starts_with("cam \u{FFFD}"));
+        assert!(serde_json::to_string(&label).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_batched_segment_insert_reports_conflicting_rows() {
+        let (database, _temp_dir) = create_test_database().await;
+        // A time of its own, so the ingest count isn't shared with other tests
+        let clock = Arc::new(FakeClock::new(DateTime::from_timestamp(1_000_000_000, 0).unwrap()));
+        let database = database.with_clock(clock.clone());
+        let segments_of = |flow_id: Uuid, count: u32| -> Vec<FlowSegment> {
+            (0..count)
+                .map(|second| {
+                    CreateSegmentRequest {
+                        sample_offset: Some(second as u64),
+                        ..CreateSegmentRequest::new(&format!("obj-{}", second), &format!("{}:0", second), &format!("{}:0", second + 1))
+                    }
+                    .into_segment(flow_id)
+                })
+                .collect()
+        };
+
+        // Enough segments to span several multi-row INSERT chunks
+        let count = 2000;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_flow(&flow).await.unwrap();
+        assert!(database.add_flow_segments(&segments_of(flow.id, count)).await.unwrap().is_empty());
+
+        let stored = database.get_flow_segments(&flow.id).await.unwrap().items;
+        assert_eq!(stored.len(), count as usize);
+        assert_eq!(stored.iter().filter_map(|s| s.sample_offset).sum::<u64>(), (0..count as u64).sum::<u64>());
+        let indexed: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM flow_segments WHERE flow_id = ?1 AND end_ns > start_ns")
+            .bind(flow.id.to_string())
+            .fetch_one(&database.pool)
+            .await
+            .unwrap();
+        assert_eq!(indexed, count as i64);
+
+        // Duplicates of stored segments fail alone; the rest of their chunk is kept
+        let mut retried = segments_of(flow.id, count + 5);
+        retried.drain(..count as usize - 2);
+        let failures = database.add_flow_segments(&retried).await.unwrap();
+        let failed: Vec<&str> = failures.iter().map(|f| f.segment.object_id.as_str()).collect();
+        assert_eq!(failed, vec!["obj-1998", "obj-1999"]);
+        assert_eq!(database.get_flow_segments(&flow.id).await.unwrap().items.len(), count as usize + 5);
+        assert_eq!(metrics().segments_ingested_recently(clock.now()), count as u64 + 5);
+    }
+
+    /// The batched insert against the row-at-a-time loop it replaces, each of
+    /// whose inserts commits on its own: `cargo test --release -- --ignored bench_batched`
+    #[tokio::test]
+    #[ignore]
+    async fn bench_batched_segment_insert_against_loop() {
+        let (database, _temp_dir) = create_test_database().await;
+        let count = 5_000;
+        let segments_of = |flow_id: Uuid| -> Vec<FlowSegment> {
+            (0..count)
+                .map(|second| {
+                    CreateSegmentRequest::new(&format!("obj-{}", second), &format!("{}:0", second), &format!("{}:0", second + 1))
+                        .into_segment(flow_id)
+                })
+                .collect()
+        };
+        let looped_flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        let batched_flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_flow(&looped_flow).await.unwrap();
+        database.create_flow(&batched_flow).await.unwrap();
+
+        let started = std::time::Instant::now();
+        for segment in segments_of(looped_flow.id) {
+            database.add_flow_segment(&segment).await.unwrap();
+        }
+        let looped = started.elapsed();
+        let started = std::time::Instant::now();
+        assert!(database.add_flow_segments(&segments_of(batched_flow.id)).await.unwrap().is_empty());
+        let batched = started.elapsed();
+
+        for flow in [&looped_flow, &batched_flow] {
+            assert_eq!(database.get_flow_segments(&flow.id).await.unwrap().items.len(), count);
+        }
+        assert!(batched * 5 < looped, "{} segments: loop {:?}, batched {:?}", count, looped, batched);
+    }
+
+    #[tokio::test]
+    async fn test_delete_segments_by_objects_recomputes_timerange() {
+        let (database, _temp_dir) = create_test_database().await;
+
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_flow(&flow).await.unwrap();
+        for (object_id, start, end) in [("obj-a", "0:0", "10:0"), ("obj-a", "10:0", "20:0"), ("obj-b", "20:0", "30:0")] {
+            let request = CreateSegmentRequest::new(object_id, start, end);
+            database.add_flow_segment(&request.into_segment(flow.id)).await.unwrap();
+        }
+
+        let deletion = database
+            .delete_flow_segments_by_objects(&flow.id, &["obj-a".to_string(), "obj-missing".to_string()])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            deletion.counts,
+            vec![("obj-a".to_string(), 2), ("obj-missing".to_string(), 0)]
+        );
+        let deleted = deletion.deleted_timerange.unwrap();
+        assert_eq!((deleted.start.as_str(), deleted.end.as_deref().unwrap()), ("0:0", "20:0"));
+
+        let stored = database.get_flow_required(&flow.id).await.unwrap();
+        let available = stored.available_timerange.unwrap();
+        assert_eq!((available.start.as_str(), available.end.as_deref().unwrap()), ("20:0", "30:0"));
+        assert_eq!(database.get_flow_segments(&flow.id).await.unwrap().items.len(), 1);
+
+        // A read-only flow keeps its segments
+        sqlx::query("UPDATE flows SET read_only = 1 WHERE id = ?1")
+            .bind(flow.id.to_string())
+            .execute(&database.pool)
+            .await
+            .unwrap();
+        let err = database.delete_flow_segments_by_objects(&flow.id, &["obj-b".to_string()]).await.unwrap_err();
+        assert!(matches!(err, TamsError::ReadOnlyFlow { .. }));
+        let err = database.delete_flow_segments_by_timerange(&flow.id, None, None, false).await.unwrap_err();
+        assert!(matches!(err, TamsError::ReadOnlyFlow { .. }));
+        assert_eq!(database.get_flow_segments(&flow.id).await.unwrap().items.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_flow_stats_follow_segment_writes_and_reconcile() {
+        let (database, _temp_dir) = create_test_database().await;
+
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_flow(&flow).await.unwrap();
+        assert_eq!(database.get_flow_stats(&flow.id).await.unwrap(), FlowStats::default());
+
+        let upload = |object_id: &str, size_bytes: u64| MediaObject {
+            object_id: object_id.to_string(),
+            size_bytes: Some(size_bytes),
+            mime_type: None,
+            flow_references: vec![],
+            created_at: Utc::now(),
+            metadata: Default::default(),
+            storage_class: None,
+            format: None,
+            content_hash: None,
+        };
+        database.record_uploaded_object(&upload("obj-a", 100)).await.unwrap();
+        for (object_id, start, end) in [("obj-b", "10:0", "20:0"), ("obj-a", "0:0", "10:0"), ("obj-a", "20:0", "30:0")] {
+            let request = CreateSegmentRequest::new(object_id, start, end);
+            database.add_flow_segment(&request.into_segment(flow.id)).await.unwrap();
+        }
+        let stats = database.get_flow_stats(&flow.id).await.unwrap();
+        assert_eq!((stats.segment_count, stats.total_bytes), (3, 200));
+        assert_eq!(stats.first_timestamp.as_deref(), Some("0:000000000"));
+        assert_eq!(stats.last_timestamp.as_deref(), Some("30:000000000"));
+
+        // obj-b arrives after its segment, and obj-a is uploaded again at a new size
+        database.record_uploaded_object(&upload("obj-b", 7)).await.unwrap();
+        database.record_uploaded_object(&upload("obj-a", 50)).await.unwrap();
+        assert_eq!(database.get_flow_stats(&flow.id).await.unwrap().total_bytes, 107);
+
+        let window = TimeRange::new("0:0", Some("10:0"));
+        database.delete_flow_segments_in_timerange(&flow.id, Some(&window), 10).await.unwrap();
+        let stats = database.get_flow_stats(&flow.id).await.unwrap();
+        assert_eq!((stats.segment_count, stats.total_bytes), (2, 57));
+        assert_eq!(stats.first_timestamp.as_deref(), Some("10:000000000"));
+        assert_eq!(stats.last_timestamp.as_deref(), Some("30:000000000"));
+
+        // Drift, as left by writes made without the triggers, is found and corrected
+        let untouched = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_flow(&untouched).await.unwrap();
+        sqlx::query("UPDATE flow_stats SET segment_count = 9, last_end_ns = NULL WHERE flow_id = ?1")
+            .bind(flow.id.to_string())
+            .execute(&database.pool)
+            .await
+            .unwrap();
+        let report = crate::maintenance::reconcile_flow_stats(&database, 1).await.unwrap();
+        assert_eq!((report.flows_checked, report.flows_corrected), (2, 1));
+        assert_eq!(database.get_flow_stats(&flow.id).await.unwrap(), stats);
+
+        database.delete_flow(&flow.id).await.unwrap();
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM flow_stats").fetch_one(&database.pool).await.unwrap();
+        assert_eq!(remaining, 1);
+    }
+
+    #[tokio::test]
+    async fn test_legacy_segment_bounds_read_while_backfilling() {
+        let (database, _temp_dir) = create_test_database().await;
+
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_flow(&flow).await.unwrap();
+        for (object_id, start, end) in [("obj-c", "20:0", "30:0"), ("obj-a", "0:0", "10:0"), ("obj-b", "10:0", "20:0")] {
+            let request = CreateSegmentRequest::new(object_id, start, end);
+            database.add_flow_segment(&request.into_segment(flow.id)).await.unwrap();
+        }
+        // Rows written before the bounds were kept: two of the three, one
+        // without an end and one that doesn't parse
+        sqlx::query("UPDATE flow_segments SET start_ns = NULL, end_ns = NULL WHERE object_id != 'obj-b'")
+            .execute(&database.pool)
+            .await
+            .unwrap();
+        for (object_id, stored) in [("obj-open", "40:0:"), ("obj-bad", "garbage")] {
+            sqlx::query("INSERT INTO flow_segments (flow_id, object_id, timerange, created_at) VALUES (?1, ?2, ?3, '')")
+                .bind(flow.id.to_string())
+                .bind(object_id)
+                .bind(stored)
+                .execute(&database.pool)
+                .await
+                .unwrap();
+        }
+        database.migrate().await.unwrap();
+        assert!(database.reads_legacy_bounds());
+
+        let second = 1_000_000_000;
+        let reads = || async {
+            (
+                database
+                    .count_flow_segments(
+                        &flow.id,
+                        &FlowSegmentFilters { start_ns: Some(5 * second), end_ns: Some(25 * second), ..Default::default() },
+                    )
+                    .await
+                    .unwrap(),
+                database.get_segment_timeranges_in_window(&flow.id, 0, 30 * second).await.unwrap(),
+                database.get_segment_extent_nanos(&flow.id).await.unwrap(),
+                database.list_flow_object_ids(&flow.id, 10, None).await.unwrap().0,
+            )
+        };
+        let open_window = FlowSegmentFilters { start_ns: Some(45 * second), end_ns: Some(50 * second), ..Default::default() };
+        let before = reads().await;
+        assert_eq!(before.0, 3);
+        assert_eq!(before.1, ["0:0:10:0", "10:0:20:0", "20:0:30:0"]);
+        assert_eq!(before.2, Some((0, OPEN_END_NANOS)));
+        assert_eq!(before.3[1..], ["obj-a", "obj-b", "obj-c", "obj-open"]);
+        assert_eq!(database.count_flow_segments(&flow.id, &open_window).await.unwrap(), 1);
+        let progress = database.get_segment_bounds_progress(0).await.unwrap();
+        assert_eq!((progress.segments_backfilled, progress.segments_total, progress.complete), (1, 5, false));
+        assert!(matches!(database.retire_segment_bounds_view(0).await, Err(TamsError::Conflict(_))));
+
+        let (mut after_rowid, mut filled) = (0, 0);
+        while let Some((last_rowid, batch_filled)) = database.backfill_segment_bounds(after_rowid, 1).await.unwrap() {
+            after_rowid = last_rowid;
+            filled += batch_filled;
+        }
+        assert_eq!(filled, 3);
+        let progress = database.get_segment_bounds_progress(after_rowid).await.unwrap();
+        assert_eq!(
+            (progress.segments_backfilled, progress.segments_unparseable, progress.segments_total, progress.complete),
+            (4, 1, 5, true)
+        );
+        assert_eq!(reads().await, before);
+
+        // Retired, the view is gone and reads go to flow_segments directly
+        database.retire_segment_bounds_view(after_rowid).await.unwrap();
+        assert!(!database.reads_legacy_bounds());
+        assert_eq!(reads().await, before);
+        assert_eq!(database.count_flow_segments(&flow.id, &open_window).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_segment_bounds_backfill_job_retires_view() {
+        use crate::maintenance::{self, SEGMENT_BOUNDS_BACKFILL_JOB};
+        let (state, _temp_dir) =
+            crate::handlers::tests::create_test_state_with(|config| config.database.bounds_backfill_batch_size = 1).await;
+        let database = &state.database;
+        assert!(!database.reads_legacy_bounds());
+        assert!(!maintenance::enqueue_segment_bounds_backfill(&state).await.unwrap());
+
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        database.create_flow(&flow).await.unwrap();
+        for (object_id, stored) in [("obj-a", "0:0:10:0"), ("obj-b", "10:0:20:0"), ("obj-bad", "garbage")] {
+            sqlx::query("INSERT INTO flow_segments (flow_id, object_id, timerange, created_at) VALUES (?1, ?2, ?3, '')")
+                .bind(flow.id.to_string())
+                .bind(object_id)
+                .bind(stored)
+                .execute(&database.pool)
+                .await
+                .unwrap();
+        }
+        database.migrate().await.unwrap();
+        assert!(maintenance::enqueue_segment_bounds_backfill(&state).await.unwrap());
+        assert!(!maintenance::enqueue_segment_bounds_backfill(&state).await.unwrap(), "one job at a time");
+
+        let mut filled = 0;
+        for _ in 0..10 {
+            filled += maintenance::run_segment_bounds_backfill(&state).await.unwrap();
+        }
+        assert_eq!(filled, 2);
+        assert!(!database.reads_legacy_bounds());
+        let jobs = state.jobs.list(Some(SEGMENT_BOUNDS_BACKFILL_JOB), None, 10).await.unwrap();
+        assert_eq!(jobs.iter().map(|job| job.status.as_str()).collect::<Vec<_>>(), [Job::COMPLETED]);
+        let progress = maintenance::segment_bounds_progress(&state).await.unwrap();
+        assert_eq!((progress.segments_unparseable, progress.complete), (1, true));
+
+        // A restart reads through the view again until it sees the backfill had finished
+        database.migrate().await.unwrap();
+        assert!(!maintenance::enqueue_segment_bounds_backfill(&state).await.unwrap());
+        assert!(!database.reads_legacy_bounds());
+    }
+
+    #[tokio::test]
+    async fn test_rotate_webhook_secret_keeps_previous_key_until_purged() {
+        let (database, _temp_dir) = create_test_database().await;
+
+        let id = Uuid::new_v4();
+        database.create_webhook(&Webhook {
+            id: Some(id),
+            url: "https://example.com/hook".to_string(),
+            api_key_name: Some("X-Api-Key".to_string()),
+            api_key_value: Some("old-key".to_string()),
+            events: vec!["flows/deleted".to_string()],
+        }, None).await.unwrap();
+
+        assert!(database.rotate_webhook_secret(&Uuid::new_v4(), "x", Utc::now(), None).await.unwrap().is_none());
+
+        let expires_at = Utc::now() + chrono::Duration::hours(1);
+        let stored = database.rotate_webhook_secret(&id, "new-key", expires_at, None).await.unwrap().unwrap();
+        assert_eq!(stored.api_key_value, "new-key");
+        assert_eq!(stored.previous_key.as_ref().unwrap().api_key_value, "old-key");
+
+        // Neither key is exposed through the listing
+        let listed = database.get_webhooks_list().await.unwrap();
+        assert_eq!(listed[0].id, Some(id));
+        assert!(listed[0].api_key_value.is_none());
+
+        assert_eq!(database.purge_expired_webhook_keys(Utc::now()).await.unwrap(), 0);
+        assert_eq!(database.purge_expired_webhook_keys(expires_at).await.unwrap(), 1);
+        let stored = database.get_stored_webhooks().await.unwrap();
+        assert!(stored[0].previous_key.is_none());
+        assert_eq!(stored[0].api_key_value, "new-key");
+    }
+}
@@ -0,0 +1,420 @@
+use crate::{
+    config::{DeletionWorkerSettings, WritesDuringDeletionPolicy},
+    database::Database,
+    error::{TamsError, TamsResult},
+    handlers::AppState,
+    metrics::metrics,
+    models::{DeletionRequest, EventNotification, EventType, SegmentsDeletedEvent, TimeRange},
+    time_utils::{parse_timerange_param, timeranges_overlap, validate_timerange},
+};
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Instant;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+/// Job kind that carries out one flow deletion request
+pub const FLOW_DELETION_JOB: &str = "flow_deletion";
+/// Deletion jobs claimed per poll
+const DELETION_JOBS_PER_POLL: u32 = 10;
+/// Name the worker's pacing overrides are persisted under
+const WORKER_SETTINGS: &str = "deletion_worker";
+/// A batch rate older than this no longer describes what the worker is doing
+const RATE_STALE_AFTER: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowDeletionJob {
+    pub deletion_request_id: String,
+}
+
+/// A deletion request's timerange as sent by a client: a TimeRange object, a
+/// `[start_end)` string, or null for the whole flow. The range must end, so a
+/// deletion never reaches segments written after it was requested.
+pub fn parse_requested_timerange(value: &Value) -> TamsResult<Option<TimeRange>> {
+    let timerange = match value {
+        Value::Null => return Ok(None),
+        Value::String(range) => parse_timerange_param(range)?,
+        Value::Object(_) => serde_json::from_value(value.clone())?,
+        other => {
+            return Err(TamsError::InvalidTimerange(format!("{}: expected a timerange string or object", other)));
+        }
+    };
+    timerange.bounded_end()?;
+    validate_timerange(&timerange)?;
+    Ok(Some(timerange))
+}
+
+/// A deletion request's stored timerange. New requests store the canonical
+/// TimeRange object; older ones may hold any form `parse_requested_timerange` accepts.
+pub fn parse_deletion_timerange(stored: &str) -> TamsResult<Option<TimeRange>> {
+    parse_requested_timerange(&serde_json::from_str(stored)?)
+}
+
+/// Refuse a write to a flow while one of the given deletion requests for it
+/// is active. `written` is the timerange of a new segment, or None for a write
+/// with no timerange of its own such as a storage allocation.
+pub fn check_no_conflicting_deletion(
+    requests: &[DeletionRequest],
+    written: Option<&TimeRange>,
+    policy: WritesDuringDeletionPolicy,
+) -> TamsResult<()> {
+    for request in requests.iter().filter(|r| r.is_active()) {
+        let conflicts = match policy {
+            WritesDuringDeletionPolicy::Reject => true,
+            WritesDuringDeletionPolicy::OutsideTimerange => {
+                match request.timerange.as_deref().map(parse_deletion_timerange).transpose()?.flatten() {
+                    None => true,
+                    Some(deleting) => written.map(|written| timeranges_overlap(written, &deleting)).transpose()?.unwrap_or(false),
+                }
+            }
+        };
+        if conflicts {
+            return Err(TamsError::Conflict(format!(
+                "Flow {} has deletion request {} {}; retry once it completes",
+                request.flow_id, request.id, request.status
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Queue a job for every active deletion request that has none, such as those
+/// created before deletions ran through the job queue. Returns how many were queued.
+pub async fn enqueue_unqueued_requests(state: &AppState) -> TamsResult<usize> {
+    let ids = state.database.get_unqueued_deletion_request_ids(FLOW_DELETION_JOB).await?;
+    for id in &ids {
+        let payload = FlowDeletionJob {
+            deletion_request_id: id.clone(),
+        };
+        state.jobs.enqueue(FLOW_DELETION_JOB, Some(id), &payload).await?;
+    }
+    Ok(ids.len())
+}
+
+/// Poll the job queue for flow deletion jobs until the process exits
+pub fn spawn_deletion_worker(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(state.jobs.poll_interval());
+        loop {
+            interval.tick().await;
+            match run_due_deletions(&state).await {
+                Ok(_) => metrics().record_task_run("deletion", state.clock.now()),
+                Err(e) => warn!("Deletion worker poll failed: {}", e),
+            }
+        }
+    });
+}
+
+/// UTC time of day during which deletions may run, written `HH:MM-HH:MM`. A
+/// window whose end is earlier than its start runs over midnight.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActiveWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl ActiveWindow {
+    pub fn parse(value: &str) -> TamsResult<Self> {
+        let invalid = || TamsError::Validation(format!("Invalid active_window '{}': expected 'HH:MM-HH:MM'", value));
+        let (start, end) = value.split_once('-').ok_or_else(invalid)?;
+        let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| invalid());
+        let window = ActiveWindow {
+            start: parse(start)?,
+            end: parse(end)?,
+        };
+        if window.start == window.end {
+            return Err(TamsError::Validation(format!(
+                "active_window '{}' is empty; leave it unset to run at any time",
+                value
+            )));
+        }
+        Ok(window)
+    }
+
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let time = now.time();
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// The next time the window opens after `now`
+    pub fn next_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let today = now.date_naive().and_time(self.start).and_utc();
+        if today > now {
+            today
+        } else {
+            today + Duration::days(1)
+        }
+    }
+}
+
+fn validate_settings(settings: &DeletionWorkerSettings) -> TamsResult<()> {
+    if settings.batch_size == 0 {
+        return Err(TamsError::Validation("batch_size must be at least 1".to_string()));
+    }
+    settings.active_window.as_deref().map(ActiveWindow::parse).transpose()?;
+    Ok(())
+}
+
+/// Pacing settings and recent throughput of the deletion worker, as shown to operators
+#[derive(Debug, Clone, Serialize)]
+pub struct DeletionWorkerState {
+    pub settings: DeletionWorkerSettings,
+    /// Whether the settings differ from the configured defaults
+    pub overridden: bool,
+    pub in_active_window: bool,
+    /// Segment objects deleted per second over the latest batch; 0 when idle
+    pub objects_per_second: f64,
+}
+
+/// Live pacing settings for the deletion worker. Changes made at runtime are
+/// persisted as `service_settings` overrides and picked up at the next batch.
+pub struct DeletionWorkerControl {
+    defaults: DeletionWorkerSettings,
+    settings: RwLock<DeletionWorkerSettings>,
+    rate: std::sync::Mutex<Option<(Instant, f64)>>,
+}
+
+impl DeletionWorkerControl {
+    pub async fn load(database: &Database, defaults: DeletionWorkerSettings) -> TamsResult<Self> {
+        validate_settings(&defaults)?;
+        let control = DeletionWorkerControl {
+            settings: RwLock::new(defaults.clone()),
+            defaults,
+            rate: std::sync::Mutex::new(None),
+        };
+        if let Some(overrides) = database.get_service_setting(WORKER_SETTINGS).await? {
+            match control.merged(&overrides).await {
+                Ok(settings) => *control.settings.write().await = settings,
+                Err(e) => warn!("Ignoring stored deletion worker settings: {}", e),
+            }
+        }
+        Ok(control)
+    }
+
+    pub async fn settings(&self) -> DeletionWorkerSettings {
+        self.settings.read().await.clone()
+    }
+
+    /// Apply the given fields over the current settings and persist the result
+    pub async fn update(&self, database: &Database, changes: &Value) -> TamsResult<DeletionWorkerSettings> {
+        let settings = self.merged(changes).await?;
+        database.set_service_setting(WORKER_SETTINGS, &serde_json::to_value(&settings)?).await?;
+        *self.settings.write().await = settings.clone();
+        info!("Deletion worker settings changed: {:?}", settings);
+        Ok(settings)
+    }
+
+    async fn merged(&self, changes: &Value) -> TamsResult<DeletionWorkerSettings> {
+        let Value::Object(changes) = changes else {
+            return Err(TamsError::Validation("Deletion worker settings must be a JSON object".to_string()));
+        };
+        let mut merged = serde_json::to_value(&*self.settings.read().await)?;
+        let fields = merged.as_object_mut().expect("settings serialize to an object");
+        for (key, value) in changes {
+            if !fields.contains_key(key) {
+                return Err(TamsError::Validation(format!("Unknown deletion worker setting '{}'", key)));
+            }
+            fields.insert(key.clone(), value.clone());
+        }
+        let settings: DeletionWorkerSettings = serde_json::from_value(merged)
+            .map_err(|e| TamsError::Validation(format!("Invalid deletion worker settings: {}", e)))?;
+        validate_settings(&settings)?;
+        Ok(settings)
+    }
+
+    fn record_batch(&self, deleted: u64, elapsed: std::time::Duration) {
+        let rate = deleted as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        *self.rate.lock().unwrap_or_else(|e| e.into_inner()) = Some((Instant::now(), rate));
+    }
+
+    pub fn objects_per_second(&self) -> f64 {
+        match *self.rate.lock().unwrap_or_else(|e| e.into_inner()) {
+            Some((at, rate)) if at.elapsed() < RATE_STALE_AFTER => rate,
+            _ => 0.0,
+        }
+    }
+
+    pub async fn state(&self, now: DateTime<Utc>) -> DeletionWorkerState {
+        let settings = self.settings().await;
+        DeletionWorkerState {
+            overridden: settings != self.defaults,
+            in_active_window: active_window(&settings).is_none_or(|window| window.contains(now)),
+            objects_per_second: self.objects_per_second(),
+            settings,
+        }
+    }
+}
+
+/// The settings' window; validated whenever settings are changed
+fn active_window(settings: &DeletionWorkerSettings) -> Option<ActiveWindow> {
+    settings.active_window.as_deref().and_then(|window| ActiveWindow::parse(window).ok())
+}
+
+/// What became of a deletion request after a run of the worker
+enum DeletionOutcome {
+    Finished,
+    /// Paused until the given time, by the active window or the job's time budget
+    Deferred(DateTime<Utc>),
+}
+
+/// Claim due flow deletion jobs and carry each one out, returning how many were claimed
+pub async fn run_due_deletions(state: &AppState) -> TamsResult<usize> {
+    let jobs = state.jobs.claim_due(FLOW_DELETION_JOB, DELETION_JOBS_PER_POLL).await?;
+    for job in &jobs {
+        let payload: FlowDeletionJob = match serde_json::from_value(job.payload.clone()) {
+            Ok(payload) => payload,
+            Err(e) => {
+                state.jobs.fail_with_backoff(job, &format!("Invalid job payload: {}", e)).await?;
+                continue;
+            }
+        };
+
+        match process_deletion_request(state, &payload.deletion_request_id).await {
+            Ok(DeletionOutcome::Finished) => state.jobs.complete(job).await?,
+            Ok(DeletionOutcome::Deferred(run_at)) => state.jobs.defer(job, run_at).await?,
+            Err(e) => {
+                warn!("Deletion request {} attempt {} failed: {}", payload.deletion_request_id, job.attempts, e);
+                if !state.jobs.fail_with_backoff(job, &e.to_string()).await? {
+                    state
+                        .database
+                        .set_deletion_request_status(&payload.deletion_request_id, DeletionRequest::ERROR, None)
+                        .await?;
+                }
+            }
+        }
+    }
+    Ok(jobs.len())
+}
+
+/// Delete the segments a request covers in paced batches and mark it done.
+/// Progress and cancellation are checked between batches; outside the active
+/// window, or once half the job's lease is used, the request is left
+/// in_progress and the job deferred. Safe to repeat: a request that is already
+/// finished or cancelled is left alone.
+async fn process_deletion_request(state: &AppState, id: &str) -> TamsResult<DeletionOutcome> {
+    let Some(request) = state.database.get_deletion_request(id).await? else {
+        return Ok(DeletionOutcome::Finished);
+    };
+    if !request.is_active() {
+        return Ok(DeletionOutcome::Finished);
+    }
+
+    let window = request.timerange.as_deref().map(parse_deletion_timerange).transpose()?.flatten();
+    let owner = state.database.get_deletion_request_owner(id).await?;
+    let started = state.clock.now();
+    let budget = state.jobs.lease() / 2;
+    let remaining = state
+        .database
+        .count_flow_segments_in_timerange(&request.flow_id, window.as_ref())
+        .await?;
+    let base_progress = request.progress.unwrap_or(0).clamp(0, 99);
+    let mut progress = base_progress;
+    let mut deleted = 0u64;
+
+    loop {
+        // A cancelled request refuses the status update
+        if !state.database.set_deletion_request_status(id, DeletionRequest::IN_PROGRESS, Some(progress)).await? {
+            return Ok(DeletionOutcome::Finished);
+        }
+        let settings = state.deletion_worker.settings().await;
+        let now = state.clock.now();
+        if let Some(active) = active_window(&settings).filter(|active| !active.contains(now)) {
+            return Ok(DeletionOutcome::Deferred(active.next_start(now)));
+        }
+        if now - started >= budget {
+            return Ok(DeletionOutcome::Deferred(now));
+        }
+
+        let batch_started = Instant::now();
+        let deletion = state
+            .database
+            .delete_flow_segments_in_timerange(&request.flow_id, window.as_ref(), settings.batch_size)
+            .await?;
+        let count: u64 = deletion.counts.iter().map(|(_, count)| count).sum();
+        deleted += count;
+
+        if let Some(timerange) = deletion.deleted_timerange {
+            state.webhook_manager.send_flow_notification(owner.as_deref(), EventNotification {
+                event_timestamp: state.clock.now(),
+                event_type: EventType::FlowsSegmentsDeleted,
+                event: SegmentsDeletedEvent {
+                    flow_id: request.flow_id,
+                    timerange,
+                },
+            }).await;
+        }
+        if count < settings.batch_size as u64 {
+            state.deletion_worker.record_batch(count, batch_started.elapsed());
+            break;
+        }
+
+        // Hold the batch to the configured rate, and pause at least batch_pause_ms
+        let paced = if settings.max_objects_per_second > 0 {
+            std::time::Duration::from_secs_f64(count as f64 / settings.max_objects_per_second as f64)
+        } else {
+            std::time::Duration::ZERO
+        };
+        let pause = std::time::Duration::from_millis(settings.batch_pause_ms).max(paced.saturating_sub(batch_started.elapsed()));
+        tokio::time::sleep(pause).await;
+        state.deletion_worker.record_batch(count, batch_started.elapsed());
+
+        let done = (deleted * (100 - base_progress) as u64) / remaining.max(1);
+        progress = (base_progress + done as i32).min(99);
+    }
+
+    info!("Deletion request {} removed {} segments from flow {}", id, deleted, request.flow_id);
+    state.database.set_deletion_request_status(id, DeletionRequest::DONE, Some(100)).await?;
+    Ok(DeletionOutcome::Finished)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::create_test_database;
+    use serde_json::json;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(&format!("2024-03-01T{}:00Z", time)).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_active_window() {
+        let night = ActiveWindow::parse("22:00-06:00").unwrap();
+        assert!(night.contains(at("23:30")));
+        assert!(night.contains(at("05:59")));
+        assert!(!night.contains(at("06:00")));
+        assert_eq!(night.next_start(at("12:00")), at("22:00"));
+
+        let early = ActiveWindow::parse("01:00-05:00").unwrap();
+        assert!(!early.contains(at("05:30")));
+        assert_eq!(early.next_start(at("05:30")), at("01:00") + Duration::days(1));
+
+        assert!(ActiveWindow::parse("01:00").is_err());
+        assert!(ActiveWindow::parse("25:00-02:00").is_err());
+        assert!(ActiveWindow::parse("02:00-02:00").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_worker_overrides_persist() {
+        let (database, _temp_dir) = create_test_database().await;
+        let control = DeletionWorkerControl::load(&database, DeletionWorkerSettings::default()).await.unwrap();
+        control
+            .update(&database, &json!({ "batch_size": 10, "active_window": "22:00-06:00" }))
+            .await
+            .unwrap();
+        assert!(control.update(&database, &json!({ "batch_sise": 10 })).await.is_err());
+        assert!(control.update(&database, &json!({ "active_window": "soon" })).await.is_err());
+
+        let reloaded = DeletionWorkerControl::load(&database, DeletionWorkerSettings::default()).await.unwrap();
+        let settings = reloaded.settings().await;
+        assert_eq!(settings.batch_size, 10);
+        assert_eq!(settings.active_window.as_deref(), Some("22:00-06:00"));
+        assert!(reloaded.state(at("12:00")).await.overridden);
+        assert!(!reloaded.state(at("12:00")).await.in_active_window);
+    }
+}
@@ -0,0 +1,130 @@
+//! UTF-8 hygiene for JSON bodies.
+//!
+//! `json_encoding_middleware` buffers JSON request bodies and refuses those
+//! that aren't valid UTF-8 with 400, strips a leading byte order mark, and
+//! replaces `\uXXXX` escapes of unpaired surrogates with U+FFFD so that what
+//! gets stored is always well-formed. serde only ever writes valid UTF-8, so
+//! responses need no such care; JSON responses are labelled
+//! `application/json; charset=utf-8` for strict clients.
+//!
+//! Text already stored in another encoding is repaired at startup by
+//! `Database::migrate`.
+
+use crate::error::TamsError;
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// Largest JSON body accepted; the same as axum's default for `Json`
+const MAX_JSON_BODY_BYTES: usize = 2 * 1024 * 1024;
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+pub const JSON_UTF8: HeaderValue = HeaderValue::from_static("application/json; charset=utf-8");
+
+fn is_json(content_type: Option<&HeaderValue>) -> bool {
+    content_type
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| {
+            let media_type = media_type.trim().to_ascii_lowercase();
+            media_type == "application/json" || media_type.ends_with("+json")
+        })
+}
+
+/// The surrogate escaped by a `\uXXXX` at the start of `bytes`, if it is one
+fn surrogate_escape(bytes: &[u8]) -> Option<u16> {
+    let escape = bytes.get(..6)?;
+    if !escape.starts_with(b"\\u") {
+        return None;
+    }
+    let value = u16::from_str_radix(std::str::from_utf8(&escape[2..]).ok()?, 16).ok()?;
+    (0xD800..=0xDFFF).contains(&value).then_some(value)
+}
+
+/// JSON text with each escaped surrogate that isn't half of a pair replaced
+/// by `\uFFFD`, or None when there are none
+fn replace_lone_surrogates(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut replaced = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            i += 1;
+            continue;
+        }
+        match surrogate_escape(&bytes[i..]) {
+            Some(high) if high < 0xDC00 && surrogate_escape(&bytes[i + 6..]).is_some_and(|low| low >= 0xDC00) => i += 12,
+            Some(_) => {
+                replaced.push_str(&text[copied..i]);
+                replaced.push_str("\\uFFFD");
+                i += 6;
+                copied = i;
+            }
+            // Any other escape, including an escaped backslash before a `u`
+            None => i += 2,
+        }
+    }
+    if copied == 0 {
+        return None;
+    }
+    replaced.push_str(&text[copied..]);
+    Some(replaced)
+}
+
+/// Buffer a JSON request body and make it well-formed UTF-8
+async fn normalize_json_body(request: Request) -> Result<Request, TamsError> {
+    if !is_json(request.headers().get(header::CONTENT_TYPE)) {
+        return Ok(request);
+    }
+    let (mut parts, body) = request.into_parts();
+    let bytes = axum::body::to_bytes(body, MAX_JSON_BODY_BYTES).await.map_err(|_| TamsError::FileTooLarge {
+        max_size: MAX_JSON_BODY_BYTES as u64,
+    })?;
+    let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(&bytes);
+    let text = std::str::from_utf8(bytes).map_err(|e| {
+        TamsError::BadRequest(format!("Request body is not valid UTF-8: invalid byte at offset {}", e.valid_up_to()))
+    })?;
+    let text = match replace_lone_surrogates(text) {
+        Some(replaced) => {
+            tracing::warn!("Replaced unpaired surrogate escapes in {} {} body", parts.method, parts.uri.path());
+            replaced
+        }
+        None => text.to_string(),
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Ok(Request::from_parts(parts, Body::from(text)))
+}
+
+/// Normalize JSON request bodies and label JSON responses as UTF-8
+pub async fn json_encoding_middleware(request: Request, next: Next) -> Response {
+    let mut response = match normalize_json_body(request).await {
+        Ok(request) => next.run(request).await,
+        Err(e) => e.into_response(),
+    };
+    if response.headers().get(header::CONTENT_TYPE).is_some_and(|value| value == "application/json") {
+        response.headers_mut().insert(header::CONTENT_TYPE, JSON_UTF8);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_lone_surrogates() {
+        assert_eq!(replace_lone_surrogates(r#"{"label":"ok 🎥"}"#), None);
+        assert_eq!(replace_lone_surrogates(r#"{"label":"a \\ud800"}"#), None);
+        assert_eq!(
+            replace_lone_surrogates(r#"{"label":"a \ud800 b \udc00\ud83c"}"#).as_deref(),
+            Some(r#"{"label":"a \uFFFD b \uFFFD\uFFFD"}"#)
+        );
+        assert!(is_json(Some(&HeaderValue::from_static("Application/JSON; charset=utf-8"))));
+        assert!(!is_json(Some(&HeaderValue::from_static("video/mp2t"))));
+    }
+}
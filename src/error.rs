@@ -1,8 +1,9 @@
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
+use serde::Serialize;
 use serde_json::json;
 use thiserror::Error;
 use chrono;
@@ -51,12 +52,19 @@ pub enum TamsError {
     #[error("Internal server error: {0}")]
     Internal(String),
 
+    /// The database was migrated by a binary this one can't safely run beside
+    #[error("Database schema is too new: {0}")]
+    SchemaTooNew(String),
+
     #[error("Media storage error: {0}")]
     MediaStorage(String),
 
     #[error("File too large: maximum size is {max_size} bytes")]
     FileTooLarge { max_size: u64 },
 
+    #[error("Unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
     #[error("Invalid timerange: {0}")]
     InvalidTimerange(String),
 
@@ -69,6 +77,11 @@ pub enum TamsError {
     #[error("Object not found: {object_id}")]
     ObjectNotFound { object_id: String },
 
+    /// A segment referenced an object that was never uploaded; `extensions`
+    /// tells the client how to allocate and upload it
+    #[error("Segment object has not been uploaded: {object_id}")]
+    SegmentObjectMissing { object_id: String, extensions: serde_json::Value },
+
     #[error("Flow not found: {flow_id}")]
     FlowNotFound { flow_id: String },
 
@@ -83,6 +96,45 @@ pub enum TamsError {
 
     #[error("Invalid input: {0}")]
     InvalidInput(String),
+
+    #[error("Unprocessable: {0}")]
+    Unprocessable(String),
+
+    #[error("{0}")]
+    IngestPaused(String),
+
+    #[error("Validation failed: {}", join_messages(.0))]
+    InvalidFields(Vec<FieldError>),
+}
+
+/// One of the problems reported together by [`TamsError::InvalidFields`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    /// Path of the request field at fault, e.g. `container`
+    pub field: String,
+    pub message: String,
+}
+
+fn join_messages(errors: &[FieldError]) -> String {
+    errors.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join("; ")
+}
+
+/// Retry-After sent with responses refused because ingest is paused
+pub const INGEST_PAUSED_RETRY_AFTER_SECONDS: u64 = 60;
+
+/// Retry-After sent when a write gave up on a busy database
+pub const DATABASE_BUSY_RETRY_AFTER_SECONDS: u64 = 1;
+
+/// Whether SQLite refused the statement because another connection holds the
+/// lock (SQLITE_BUSY or SQLITE_LOCKED, including their extended codes)
+pub fn is_busy_error(error: &sqlx::Error) -> bool {
+    let sqlx::Error::Database(db_error) = error else {
+        return false;
+    };
+    db_error
+        .code()
+        .and_then(|code| code.parse::<i32>().ok())
+        .is_some_and(|code| matches!(code & 0xff, 5 | 6))
 }
 
 impl IntoResponse for TamsError {
@@ -94,7 +146,8 @@ impl IntoResponse for TamsError {
             }
             TamsError::BadRequest(_) | TamsError::Validation(_) | 
             TamsError::InvalidTimerange(_) | TamsError::InvalidFormat { .. } |
-            TamsError::MissingField { .. } | TamsError::Uuid(_) | TamsError::Json(_) => {
+            TamsError::MissingField { .. } | TamsError::Uuid(_) | TamsError::Json(_) |
+            TamsError::SegmentObjectMissing { .. } | TamsError::InvalidFields(_) => {
                 (StatusCode::BAD_REQUEST, self.to_string())
             }
             TamsError::Unauthorized(_) => {
@@ -106,15 +159,74 @@ impl IntoResponse for TamsError {
             TamsError::Conflict(_) | TamsError::SegmentOverlap(_) => {
                 (StatusCode::CONFLICT, self.to_string())
             }
+            TamsError::Unprocessable(_) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, self.to_string())
+            }
             TamsError::FileTooLarge { .. } => {
                 (StatusCode::PAYLOAD_TOO_LARGE, self.to_string())
             }
+            TamsError::UnsupportedMediaType(_) => {
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, self.to_string())
+            }
+            TamsError::IngestPaused(_) => {
+                (StatusCode::SERVICE_UNAVAILABLE, self.to_string())
+            }
+            TamsError::Database(e) if is_busy_error(e) => {
+                tracing::warn!("Database busy: {}", e);
+                (StatusCode::SERVICE_UNAVAILABLE, "Database is busy, retry shortly".to_string())
+            }
             _ => {
                 tracing::error!("Internal server error: {}", self);
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string())
             }
         };
 
+        if let TamsError::IngestPaused(_) = &self {
+            let body = Json(json!({
+                "error": error_message,
+                "code": "ingest_paused",
+                "status": status.as_u16()
+            }));
+            return (
+                status,
+                [(header::RETRY_AFTER, INGEST_PAUSED_RETRY_AFTER_SECONDS.to_string())],
+                body,
+            )
+                .into_response();
+        }
+
+        if let TamsError::Database(e) = &self {
+            if is_busy_error(e) {
+                let body = Json(json!({
+                    "error": error_message,
+                    "code": "database_busy",
+                    "status": status.as_u16()
+                }));
+                return (status, [(header::RETRY_AFTER, DATABASE_BUSY_RETRY_AFTER_SECONDS.to_string())], body)
+                    .into_response();
+            }
+        }
+
+        if let TamsError::SegmentObjectMissing { extensions, .. } = &self {
+            let body = Json(json!({
+                "error": error_message,
+                "code": "object_not_uploaded",
+                "status": status.as_u16(),
+                "extensions": extensions
+            }));
+            return (status, body).into_response();
+        }
+
+        if let TamsError::InvalidFields(errors) = &self {
+            let body = Json(json!({
+                "error": error_message,
+                "code": "validation_failed",
+                "status": status.as_u16(),
+                "errors": errors
+            }));
+            return (status, body).into_response();
+        }
+
         let body = Json(json!({
             "error": error_message,
             "status": status.as_u16()
@@ -0,0 +1,259 @@
+//! `GET /service/events`: notifications as a server-sent event stream.
+//!
+//! Every notification sent to webhooks is also published here, numbered with
+//! a sequence that is sent as the SSE `id`. Subscribers share one broadcast
+//! buffer of `events.buffer_events` notifications, so a client that stops
+//! reading holds at most that many in memory however long it stays connected.
+//! A client that falls further behind than that is sent a final `reconnect`
+//! event, carrying the sequence of the last notification it was given and how
+//! many it missed, and its stream ends: notifications are never skipped
+//! without the client being told. There is no replay endpoint yet, so a
+//! client resuming after `reconnect` re-reads what it needs from the listings.
+//!
+//! Heartbeats are SSE comments added by the response itself every
+//! `events.heartbeat_seconds`; they never pass through the buffer, so an idle
+//! connection can't lag.
+//!
+//! Under `auth.enforce_ownership` a subscriber other than an admin is only
+//! sent events about its own flows, plus those about no flow at all.
+
+use crate::{auth::Principal, handlers::AppState, metrics::metrics, ownership};
+use axum::{
+    extract::State,
+    response::sse::{Event, KeepAlive, Sse},
+    Extension,
+};
+use futures_util::{Stream, StreamExt};
+use serde::Serialize;
+use std::{
+    convert::Infallible,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+/// SSE event name of the last event sent to a subscriber that fell behind
+pub const RECONNECT_EVENT: &str = "reconnect";
+
+/// Who may be told of an event when listeners are limited to their own flows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventAudience {
+    /// The event concerns no flow
+    Everyone,
+    /// The event concerns a flow with this owner, or one without an owner
+    FlowOwner(Option<String>),
+}
+
+impl EventAudience {
+    /// Whether a listener limited to the flows of `owned_by`, if limited at
+    /// all, is told of the event
+    pub fn includes(&self, owned_by: Option<&str>) -> bool {
+        match (self, owned_by) {
+            (EventAudience::Everyone, _) | (_, None) => true,
+            (EventAudience::FlowOwner(owner), Some(owned_by)) => owner.as_deref() == Some(owned_by),
+        }
+    }
+}
+
+/// A notification as published to subscribers
+#[derive(Debug)]
+pub struct StreamedEvent {
+    pub sequence: u64,
+    pub event_type: &'static str,
+    /// The notification as JSON, untruncated
+    pub data: String,
+    pub audience: EventAudience,
+}
+
+/// Data of the `reconnect` event
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReconnectHint {
+    /// Sequence of the last notification delivered on this connection, if any
+    pub last_sequence: Option<u64>,
+    /// Notifications published since then that the connection did not get
+    pub missed: u64,
+}
+
+/// What a subscription yields
+#[derive(Debug)]
+pub enum StreamItem {
+    Event(Arc<StreamedEvent>),
+    /// The subscriber fell behind; nothing follows this
+    Reconnect(ReconnectHint),
+}
+
+impl StreamItem {
+    fn into_sse(self) -> Event {
+        match self {
+            StreamItem::Event(event) => Event::default()
+                .id(event.sequence.to_string())
+                .event(event.event_type)
+                .data(&event.data),
+            StreamItem::Reconnect(hint) => Event::default()
+                .event(RECONNECT_EVENT)
+                .json_data(&hint)
+                .unwrap_or_else(|_| Event::default().event(RECONNECT_EVENT)),
+        }
+    }
+}
+
+/// Publishes notifications to the connected subscribers
+pub struct EventStream {
+    sender: broadcast::Sender<Arc<StreamedEvent>>,
+    /// Sequence of the next notification; held while sending so subscribers
+    /// receive notifications in sequence order
+    next_sequence: Mutex<u64>,
+}
+
+impl EventStream {
+    pub fn new(buffer_events: usize) -> Self {
+        let (sender, _) = broadcast::channel(buffer_events.max(1));
+        EventStream { sender, next_sequence: Mutex::new(1) }
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    /// Publish a notification to the current subscribers, returning its sequence
+    pub fn publish(&self, event_type: &'static str, data: String, audience: EventAudience) -> u64 {
+        let mut next_sequence = self.next_sequence.lock().unwrap_or_else(|e| e.into_inner());
+        let sequence = *next_sequence;
+        *next_sequence += 1;
+        // Fails only when nobody is subscribed
+        let _ = self.sender.send(Arc::new(StreamedEvent { sequence, event_type, data, audience }));
+        sequence
+    }
+
+    /// Notifications published from now on, only those about the flows of
+    /// `owned_by` when given, ending with a [`StreamItem::Reconnect`] if the
+    /// subscriber falls more than the buffer behind
+    pub fn subscribe(&self, owned_by: Option<String>) -> impl Stream<Item = StreamItem> {
+        let subscription = Subscription {
+            receiver: self.sender.subscribe(),
+            last_sequence: None,
+            owned_by,
+            _counted: SubscriberCount::new(),
+        };
+        futures_util::stream::unfold(Some(subscription), |subscription| async move {
+            let mut subscription = subscription?;
+            loop {
+                match subscription.receiver.recv().await {
+                    Ok(event) if !event.audience.includes(subscription.owned_by.as_deref()) => continue,
+                    Ok(event) => {
+                        subscription.last_sequence = Some(event.sequence);
+                        return Some((StreamItem::Event(event), Some(subscription)));
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        metrics().event_stream_drops.fetch_add(1, Ordering::Relaxed);
+                        warn!(
+                            "Dropping event stream subscriber {} notifications behind after sequence {:?}",
+                            missed, subscription.last_sequence
+                        );
+                        let hint = ReconnectHint { last_sequence: subscription.last_sequence, missed };
+                        return Some((StreamItem::Reconnect(hint), None));
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
+
+struct Subscription {
+    receiver: broadcast::Receiver<Arc<StreamedEvent>>,
+    last_sequence: Option<u64>,
+    owned_by: Option<String>,
+    _counted: SubscriberCount,
+}
+
+/// Counts a subscriber in the metrics for as long as it is connected
+struct SubscriberCount;
+
+impl SubscriberCount {
+    fn new() -> Self {
+        metrics().event_stream_subscribers.fetch_add(1, Ordering::Relaxed);
+        SubscriberCount
+    }
+}
+
+impl Drop for SubscriberCount {
+    fn drop(&mut self) {
+        metrics().event_stream_subscribers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub async fn stream_events(
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let heartbeat = Duration::from_secs(state.config.events.heartbeat_seconds.max(1));
+    let owned_by = ownership::listing_owner(&state.config.auth, principal.as_deref()).map(str::to_string);
+    let events = state.webhook_manager.events().subscribe(owned_by).map(|item| Ok(item.into_sse()));
+    Sse::new(events).keep_alive(KeepAlive::new().interval(heartbeat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::tests::TestApp;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    #[tokio::test]
+    async fn test_slow_subscriber_is_dropped_with_resume_hint() {
+        let app = TestApp::with_config(|config| config.events.buffer_events = 4).await;
+        let response = app.send(Request::builder().uri("/service/events").body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body().into_data_stream();
+        let events = app.state.webhook_manager.events();
+        let drops = metrics().event_stream_drops.load(Ordering::Relaxed);
+
+        let first = events.publish("flows/created", r#"{"n":1}"#.to_string(), EventAudience::Everyone);
+        let frame = String::from_utf8(body.next().await.unwrap().unwrap().to_vec()).unwrap();
+        assert!(frame.contains(&format!("id: {}", first)), "{}", frame);
+        assert!(frame.contains("event: flows/created"), "{}", frame);
+
+        // The client stops reading while more than the buffer is published
+        for n in 0..10 {
+            events.publish("flows/updated", format!(r#"{{"n":{}}}"#, n), EventAudience::Everyone);
+        }
+        let mut rest = String::new();
+        while let Some(frame) = body.next().await {
+            rest.push_str(std::str::from_utf8(&frame.unwrap()).unwrap());
+        }
+        assert!(!rest.contains("flows/updated"), "{}", rest);
+        let hint = format!(r#"{{"last_sequence":{},"missed":6}}"#, first);
+        assert!(rest.contains(&format!("event: {}\ndata: {}", RECONNECT_EVENT, hint)), "{}", rest);
+        assert!(metrics().event_stream_drops.load(Ordering::Relaxed) > drops);
+        assert!(!events.has_subscribers());
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_only_get_their_own_flows_events() {
+        let app = TestApp::with_config(|config| config.auth.enforce_ownership = true).await;
+        let request = Request::builder()
+            .uri("/service/events")
+            .extension(Principal { name: "team-a".to_string(), admin: false })
+            .body(Body::empty())
+            .unwrap();
+        let mut body = app.send(request).await.into_body().into_data_stream();
+        let events = app.state.webhook_manager.events();
+
+        let owner = |name: &str| EventAudience::FlowOwner(Some(name.to_string()));
+        events.publish("flows/updated", r#"{"n":1}"#.to_string(), owner("team-b"));
+        events.publish("flows/updated", r#"{"n":2}"#.to_string(), EventAudience::FlowOwner(None));
+        let own = events.publish("flows/updated", r#"{"n":3}"#.to_string(), owner("team-a"));
+        let shared = events.publish("sources/deleted", r#"{"n":4}"#.to_string(), EventAudience::Everyone);
+
+        let mut frames = String::new();
+        while !frames.contains(&format!("id: {}", shared)) {
+            frames.push_str(std::str::from_utf8(&body.next().await.unwrap().unwrap()).unwrap());
+        }
+        assert!(frames.contains(&format!("id: {}\nevent: flows/updated", own)), "{}", frames);
+        assert!(!frames.contains(r#"{"n":1}"#) && !frames.contains(r#"{"n":2}"#), "{}", frames);
+    }
+}
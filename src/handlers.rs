@@ -1,19 +1,44 @@
 use crate::{
-    config::AppConfig,
-    database::Database,
-    error::{TamsError, TamsResult},
+    archive,
+    auth::Principal,
+    behaviours::service_behaviours,
+    clock::SharedClock,
+    concat,
+    config::{AppConfig, AuthConfig, MediaStoreConfig, MediaStoreRole},
+    database::{Database, DatabaseTransaction, FlowSegmentFilters},
+    deletion,
+    error::{FieldError, TamsError, TamsResult},
+    head::Head,
+    ingest::IngestControl,
+    jobs::JobQueue,
+    maintenance,
+    metrics::metrics,
     models::*,
-    storage::MediaStorage,
+    ownership,
+    query::{FlowQueryParams, SourceQueryParams},
+    reload::{ConfigReload, ConfigReloader},
+    summary,
+    storage::{
+        GetUrlTemplate, MediaStorage, ObjectContext, StoreOutcome, EXTERNAL_GET_URL_LABEL, GET_URL_TEMPLATE_TAG, STORAGE_CLASS_TAG,
+    },
+    time_utils,
+    transaction::TransactionSlot,
+    validation,
+    warnings::{ResponseWarning, Warnings},
     webhooks::WebhookManager,
 };
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::{Html, Json},
+    extract::{Path, Query, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Json, Response},
     Extension,
 };
 use serde_json::{json, Value};
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 use uuid::Uuid;
 
 pub type AppState = Arc<AppStateInner>;
@@ -23,6 +48,49 @@ pub struct AppStateInner {
     pub database: Database,
     pub storage: Arc<MediaStorage>,
     pub webhook_manager: Arc<WebhookManager>,
+    pub ingest: Arc<IngestControl>,
+    pub jobs: JobQueue,
+    pub deletion_worker: Arc<deletion::DeletionWorkerControl>,
+    pub reloader: Arc<ConfigReloader>,
+    pub clock: SharedClock,
+    pub summary: summary::SummaryCache,
+}
+
+/// `201 Created` with the new resource as the body and its URL in `Location`
+#[derive(Debug)]
+pub struct Created<T> {
+    pub location: HeaderValue,
+    pub body: T,
+}
+
+impl<T> Created<T> {
+    pub fn new(location: &str, body: T) -> TamsResult<Self> {
+        let location = HeaderValue::from_str(location)
+            .map_err(|e| TamsError::Internal(format!("Invalid Location '{}': {}", location, e)))?;
+        Ok(Created { location, body })
+    }
+}
+
+impl<T: serde::Serialize> IntoResponse for Created<T> {
+    fn into_response(self) -> Response {
+        (StatusCode::CREATED, [(header::LOCATION, self.location)], Json(self.body)).into_response()
+    }
+}
+
+/// Apply `?time_format=tams|rfc3339` to the timestamps serialized in the response
+pub async fn time_format_middleware(request: Request, next: Next) -> Result<Response, TamsError> {
+    let format = request
+        .uri()
+        .query()
+        .and_then(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == "time_format")
+                .map(|(_, value)| value.into_owned())
+        })
+        .map(|value| time_utils::TimeFormat::from_query(&value))
+        .transpose()?
+        .unwrap_or_default();
+    Ok(time_utils::with_time_format(format, next.run(request)).await)
 }
 
 // Root endpoint
@@ -36,43 +104,261 @@ pub async fn get_root() -> Result<Json<Value>, TamsError> {
 
 // Service info endpoint
 pub async fn get_service_info(State(state): State<AppState>) -> Result<Json<ServiceInfo>, TamsError> {
+    let primary = state.config.service.primary_media_store();
+    let media_stores = state
+        .config
+        .service
+        .media_stores_by_read_priority()
+        .into_iter()
+        .map(|store| MediaStoreInfo {
+            primary: primary.as_ref().map(|p| p.name == store.name).unwrap_or(false),
+            name: store.name,
+            store_type: store.store_type,
+            presigned_urls: store.presigned_urls,
+            url_base: store.url_base,
+            role: match store.role {
+                MediaStoreRole::ReadWrite => "read_write".to_string(),
+                MediaStoreRole::ReadOnly => "read_only".to_string(),
+            },
+        })
+        .collect();
+
+    let service = &state.config.service;
+    let ingest_paused = state.ingest.is_service_paused();
+    let media_store_type = primary.map(|store| store.store_type).unwrap_or_else(|| service.media_store_type.clone());
     let info = ServiceInfo {
-        name: "TAMS Rust Server".to_string(),
-        description: "Time-addressable Media Store implementation in Rust".to_string(),
-        version: "0.1.0".to_string(),
-        media_store_type: "file".to_string(),
-        event_stream_mechanisms: vec!["webhooks".to_string()],
+        service_type: service.service_type.clone(),
+        name: service.name.clone(),
+        description: service.description.clone(),
+        api_version: TAMS_API_VERSION.to_string(),
+        service_version: env!("CARGO_PKG_VERSION").to_string(),
+        api_prefix: Some(service.api_prefix.clone()).filter(|prefix| !prefix.is_empty()),
+        media_store: MediaStoreSummary {
+            store_type: media_store_type.clone(),
+        },
+        event_stream_mechanisms: vec![EventStreamMechanism {
+            name: "webhooks".to_string(),
+            url: service.api_url("/service/webhooks"),
+        }],
+        version: service.version.clone(),
+        media_store_type,
+        media_stores,
         capabilities: ServiceCapabilities {
             supports_webhooks: true,
             supports_flow_deletion: true,
             supports_segment_deletion: true,
             supports_read_only_flows: true,
             max_file_size: state.config.media_storage.max_file_size,
+            default_tags: state.config.service.default_tags.clone(),
+            required_tags: state.config.service.required_tags.clone(),
         },
+        behaviours: service_behaviours(&state.config, ingest_paused)?,
+        ingest_paused,
+        binary_version: env!("CARGO_PKG_VERSION").to_string(),
+        schema_version: state.database.schema_version().await?,
     };
 
     Ok(Json(info))
 }
 
+/// Longest a dependency check may take before it counts as failed
+const DEPENDENCY_CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+// Dependency health report endpoint
+pub async fn get_dependency_health(
+    State(state): State<AppState>,
+) -> Result<(StatusCode, Json<DependenciesReport>), TamsError> {
+    // Without configured stores media is served by this API itself
+    let client = reqwest::Client::new();
+    let stores = futures_util::future::join_all(state.config.service.media_stores.iter().map(|store| {
+        check_dependency(format!("media_store:{}", store.name), probe_media_store(&client, store))
+    }));
+    let (database, storage, stores) = tokio::join!(
+        check_dependency("database".to_string(), state.database.ping()),
+        check_dependency("storage".to_string(), state.storage.probe_write()),
+        stores,
+    );
+
+    let mut dependencies = vec![database, storage];
+    dependencies.extend(stores);
+    let healthy = dependencies.iter().all(|d| d.error.is_none());
+    let report = DependenciesReport {
+        status: if healthy { "ok" } else { "degraded" }.to_string(),
+        dependencies,
+    };
+    let status = if healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+
+    Ok((status, Json(report)))
+}
+
+/// Any HTTP response from the store's url_base counts; only failing to reach it doesn't
+async fn probe_media_store(client: &reqwest::Client, store: &MediaStoreConfig) -> TamsResult<()> {
+    client.head(&store.url_base).send().await?;
+    Ok(())
+}
+
+async fn check_dependency(
+    name: String,
+    check: impl std::future::Future<Output = TamsResult<()>>,
+) -> DependencyStatus {
+    let started = std::time::Instant::now();
+    let result = tokio::time::timeout(DEPENDENCY_CHECK_TIMEOUT, check).await.unwrap_or_else(|_| {
+        Err(TamsError::Internal(format!("no answer within {} seconds", DEPENDENCY_CHECK_TIMEOUT.as_secs())))
+    });
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    match result {
+        Ok(()) => DependencyStatus {
+            name,
+            status: "ok".to_string(),
+            latency_ms,
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Dependency check for {} failed: {}", name, e);
+            DependencyStatus {
+                name,
+                status: "error".to_string(),
+                latency_ms,
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
 // Sources endpoints
+/// `?include_total=true` asks a listing for the total matching count, which
+/// costs an extra `COUNT(*)` query
+fn include_total(params: &HashMap<String, String>) -> bool {
+    params.get("include_total").map(|v| v == "true").unwrap_or(false)
+}
+
+/// Flows embedded per source by `GET /sources?include=flows` unless `flows_limit` says otherwise
+const EMBEDDED_FLOWS_DEFAULT: u32 = 10;
+/// Most flows `flows_limit` may embed per source
+const EMBEDDED_FLOWS_MAX: u32 = 100;
+
+/// `include=flows` embeds each source's first flows by created_at, at most
+/// `flows_limit` of them, fetched for the whole page in one query
 pub async fn list_sources(
-    Query(params): Query<HashMap<String, String>>,
+    params: SourceQueryParams,
     State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
 ) -> Result<Json<Value>, TamsError> {
-    let limit = params.get("limit").and_then(|l| l.parse().ok()).unwrap_or(100);
-    let page = params.get("page");
-    
-    let sources = state.database.get_sources(limit, page.map(|s| s.as_str())).await?;
-    
+    let paging = params.paging();
+    let config = state.reloader.pagination();
+    let limit = paging.limit_or(config.default_limit, config.max_limit);
+    let include_flows = params.includes("flows");
+    let filters = params.filters()?;
+    let owned_by = ownership::listing_owner(&state.config.auth, principal.as_deref());
+
+    let (sources, next_key) = state.database.get_sources(limit, paging.page.as_deref(), owned_by, &filters).await?;
+    let total = if params.include_total { Some(state.database.count_sources(&filters, owned_by).await?) } else { None };
+    let mut pagination = PaginationInfo::new(limit, total, sources.skipped_corrupt);
+    pagination.next_key = next_key;
+    if !include_flows {
+        return Ok(Json(json!({
+            "sources": sources.items,
+            "pagination": pagination
+        })));
+    }
+
+    let flows_limit = params.flows_limit.unwrap_or(EMBEDDED_FLOWS_DEFAULT).min(EMBEDDED_FLOWS_MAX);
+    let source_ids: Vec<Uuid> = sources.items.iter().map(|source| source.id).collect();
+    let mut flows_by_source: HashMap<Uuid, Vec<Flow>> = HashMap::new();
+    for mut flow in state.database.list_flows_for_sources(&source_ids, flows_limit, owned_by).await?.items {
+        ownership::hide_notify_url(&mut flow, principal.as_deref());
+        if let Some(source_id) = flow.source_id {
+            flows_by_source.entry(source_id).or_default().push(flow);
+        }
+    }
+    let sources: Vec<SourceWithFlows> = sources
+        .items
+        .into_iter()
+        .map(|source| {
+            let mut flows = flows_by_source.remove(&source.id).unwrap_or_default();
+            let flows_has_more = flows.len() > flows_limit as usize;
+            flows.truncate(flows_limit as usize);
+            SourceWithFlows { source, flows, flows_has_more }
+        })
+        .collect();
+
     Ok(Json(json!({
         "sources": sources,
-        "pagination": {
-            "limit": limit,
-            "count": sources.len()
-        }
+        "pagination": pagination,
+        "flows_limit": flows_limit
     })))
 }
 
+/// Most timeranges returned per category by the coverage diff
+const COVERAGE_DIFF_MAX_RANGES: usize = 1000;
+
+/// Compare the segment coverage of two flows of a source
+pub async fn get_source_coverage_diff(
+    Path(source_id): Path<Uuid>,
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+) -> Result<Json<CoverageDiff>, TamsError> {
+    state.database.get_source_required(&source_id).await?;
+    let limit = params
+        .get("limit")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(COVERAGE_DIFF_MAX_RANGES)
+        .min(COVERAGE_DIFF_MAX_RANGES);
+    let bound = params.get("timerange").map(|tr| time_utils::parse_timerange_param(tr)).transpose()?;
+    if let Some(timerange) = params.get("timerange") {
+        let (start_ns, end_ns) = time_utils::parse_timerange_bounds(timerange)?;
+        validation::check_query_bounds(start_ns, end_ns, &state.config.validation)?;
+    }
+
+    let mut coverage = Vec::with_capacity(2);
+    for param in ["flow_a", "flow_b"] {
+        let flow_id = params
+            .get(param)
+            .ok_or_else(|| TamsError::BadRequest(format!("Missing {} parameter", param)))?;
+        let flow_id = Uuid::parse_str(flow_id)?;
+        let flow = state.database.get_flow_required(&flow_id).await?;
+        // The flows are named in the query, out of the ownership middleware's sight
+        if ownership::hidden_from(&state.config.auth, principal.as_deref(), flow.owner.as_deref()) {
+            return Err(TamsError::NotFound("Flow not found".to_string()));
+        }
+        if flow.source_id != Some(source_id) {
+            return Err(TamsError::BadRequest(format!("Flow {} does not belong to source {}", flow_id, source_id)));
+        }
+
+        let mut ranges = Vec::new();
+        for stored in state.database.get_segment_timeranges(&flow_id.to_string()).await? {
+            ranges.extend(state.database.stored_timerange(&flow_id, &stored)?);
+        }
+        if let Some(bound) = &bound {
+            ranges = time_utils::intersect_timeranges(&ranges, std::slice::from_ref(bound))?;
+        }
+        coverage.push((flow_id, ranges));
+    }
+    let (flow_b, b) = coverage.pop().unwrap_or_default();
+    let (flow_a, a) = coverage.pop().unwrap_or_default();
+
+    let coverage_set = |ranges: Vec<TimeRange>| -> TamsResult<CoverageSet> {
+        Ok(CoverageSet {
+            total_duration: time_utils::total_duration(&ranges)?,
+            count: ranges.len(),
+            truncated: ranges.len() > limit,
+            timeranges: ranges.into_iter().take(limit).collect(),
+        })
+    };
+
+    Ok(Json(CoverageDiff {
+        source_id,
+        flow_a,
+        flow_b,
+        only_a: coverage_set(time_utils::subtract_timeranges(&a, &b)?)?,
+        only_b: coverage_set(time_utils::subtract_timeranges(&b, &a)?)?,
+        both: coverage_set(time_utils::intersect_timeranges(&a, &b)?)?,
+        timerange: bound,
+    }))
+}
+
 pub async fn get_source(
     Path(id): Path<Uuid>,
     State(state): State<AppState>,
@@ -83,11 +369,14 @@ pub async fn get_source(
 
 pub async fn create_source(
     State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
     Json(payload): Json<CreateSourceRequest>,
-) -> Result<Json<Source>, TamsError> {
-    let source = payload.into_source();
+) -> Result<Created<Source>, TamsError> {
+    let mut source = payload.into_source();
+    source.owner = principal.map(|Extension(principal)| principal.name);
+    validation::apply_service_tag_policy(&mut source.tags, &state.config.service)?;
     state.database.create_source(&source).await?;
-    Ok(Json(source))
+    Created::new(&state.config.service.api_path(&format!("/sources/{}", source.id)), source)
 }
 
 pub async fn update_source(
@@ -105,277 +394,4362 @@ pub async fn delete_source(
     Path(id): Path<Uuid>,
     State(state): State<AppState>,
 ) -> Result<StatusCode, TamsError> {
-    state.database.delete_source(&id).await?;
+    if state.database.delete_source(&id).await? == 0 {
+        return Err(TamsError::SourceNotFound { source_id: id.to_string() });
+    }
+
+    state.webhook_manager.send_notification(EventNotification {
+        event_timestamp: state.clock.now(),
+        event_type: EventType::SourcesDeleted,
+        event: SourceDeletedEvent { source_id: id },
+    }).await;
+
     Ok(StatusCode::NO_CONTENT)
 }
 
 // Flows endpoints
+/// Flows matching every filter given, e.g. `?source_id=` for a source's flows
 pub async fn list_flows(
-    Query(params): Query<HashMap<String, String>>,
+    params: FlowQueryParams,
     State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
 ) -> Result<Json<Value>, TamsError> {
-    let limit = params.get("limit").and_then(|l| l.parse().ok()).unwrap_or(100);
-    let page = params.get("page");
-    
-    let flows = state.database.get_flows(limit, page.map(|s| s.as_str())).await?;
-    
+    let paging = params.paging();
+    let config = state.reloader.pagination();
+    let limit = paging.limit_or(config.default_limit, config.max_limit);
+    let include_flow_collection = params.includes("flow_collection");
+    let mut filters = params.filters(&state.config.validation)?;
+    // Codecs are stored normalized, so the filter is normalized the same way
+    filters.codec = filters.codec.map(|codec| {
+        validation::normalize_vocabulary("codec", &codec, state.config.validation.allowed_codecs.as_deref()).unwrap_or(codec)
+    });
+
+    let owned_by = ownership::listing_owner(&state.config.auth, principal.as_deref());
+    let (flows, next_key) = state
+        .database
+        .get_flows(limit, paging.page.as_deref(), include_flow_collection, owned_by, &filters)
+        .await?;
+    let total = if params.include_total {
+        Some(state.database.count_flows(&filters, owned_by).await?)
+    } else {
+        None
+    };
+
+    let mut pagination = PaginationInfo::new(limit, total, flows.skipped_corrupt);
+    pagination.next_key = next_key;
+    let mut flows = flows.items;
+    for flow in &mut flows {
+        ownership::hide_notify_url(flow, principal.as_deref());
+    }
     Ok(Json(json!({
         "flows": flows,
-        "pagination": {
-            "limit": limit,
-            "count": flows.len()
-        }
+        "pagination": pagination
     })))
 }
 
+/// Stream every flow id as a JSON array, for clients that only need to enumerate
+pub async fn list_flow_ids(
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+) -> Result<Response, TamsError> {
+    let source_id = params.get("source_id").map(|id| Uuid::parse_str(id)).transpose()?;
+    let owned_by = ownership::listing_owner(&state.config.auth, principal.as_deref());
+    let ids = state.database.stream_flow_ids(source_id, owned_by.map(str::to_string));
+
+    // Emits "[", then each quoted id with separating commas, then "]"
+    let body = futures_util::stream::unfold((ids, true, false), |(mut ids, first, done)| async move {
+        if done {
+            return None;
+        }
+        match ids.recv().await {
+            Some(Ok(id)) => {
+                let chunk = format!("{}\"{}\"", if first { "[" } else { "," }, id);
+                Some((Ok::<_, TamsError>(chunk), (ids, false, false)))
+            }
+            Some(Err(e)) => {
+                tracing::error!("Flow id stream failed: {}", e);
+                Some((Err(e), (ids, first, true)))
+            }
+            None => {
+                let chunk = if first { "[]" } else { "]" };
+                Some((Ok(chunk.to_string()), (ids, first, true)))
+            }
+        }
+    });
+
+    Ok((
+        [(header::CONTENT_TYPE, crate::encoding::JSON_UTF8)],
+        axum::body::Body::from_stream(body),
+    )
+        .into_response())
+}
+
 pub async fn get_flow(
     Path(id): Path<Uuid>,
     State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+) -> Result<Json<FlowWithStats>, TamsError> {
+    let mut flow = state.database.get_flow_required(&id).await?;
+    ownership::hide_notify_url(&mut flow, principal.as_deref());
+    let stats = state.database.get_flow_stats(&id).await?;
+    Ok(Json(FlowWithStats { flow, stats }))
+}
+
+pub async fn create_flow(
+    State(state): State<AppState>,
+    warnings: Warnings,
+    principal: Option<Extension<Principal>>,
+    Json(payload): Json<CreateFlowRequest>,
+) -> Result<Created<Flow>, TamsError> {
+    validation::check_flow_format_given(payload.format.as_ref(), &state.config.validation, &warnings)?;
+    let mut flow = payload.into_flow();
+    check_source_visible(&state, flow.source_id, principal.as_deref()).await?;
+    flow.owner = principal.map(|Extension(principal)| principal.name);
+    let failures = validation::check_new_flow(&mut flow, &state.config.service, &state.config.validation, &warnings);
+    if let Some((_, e)) = failures.into_iter().next() {
+        return Err(e);
+    }
+    if let Some(notify_url) = &flow.notify_url {
+        validation::check_notify_url_resolves(notify_url, &state.config.validation).await?;
+    }
+    if let Some(secret) = &flow.notify_secret {
+        flow.notify_secret = Some(state.webhook_manager.seal_key(secret)?);
+    }
+    state.database.create_flow(&flow).await?;
+    state.webhook_manager.sync_flow_webhook(&flow).await;
+    Created::new(&state.config.service.api_path(&format!("/flows/{}", flow.id)), flow)
+}
+
+/// Outcome of `POST /flows/validate`
+#[derive(Debug, serde::Serialize)]
+pub struct FlowValidation {
+    pub valid: bool,
+    /// The flow as it would be stored, with default tags and canonical vocabularies
+    pub flow: Flow,
+    pub warnings: Vec<ResponseWarning>,
+}
+
+/// Run the checks create_flow makes without storing anything: 200 with the
+/// normalized flow and any warnings, or 400 listing every problem found
+pub async fn validate_flow(
+    State(state): State<AppState>,
+    warnings: Warnings,
+    Json(payload): Json<CreateFlowRequest>,
+) -> Result<Json<FlowValidation>, TamsError> {
+    let format_given = validation::check_flow_format_given(payload.format.as_ref(), &state.config.validation, &warnings);
+    let mut flow = payload.into_flow();
+    let submitted = (flow.container.clone(), flow.codec.clone());
+    let mut failures = validation::check_new_flow(&mut flow, &state.config.service, &state.config.validation, &warnings);
+    if let Err(e) = format_given {
+        failures.insert(0, ("format", e));
+    }
+    if let (Some(notify_url), true) = (&flow.notify_url, failures.iter().all(|(field, _)| *field != "notify_url")) {
+        if let Err(e) = validation::check_notify_url_resolves(notify_url, &state.config.validation).await {
+            failures.push(("notify_url", e));
+        }
+    }
+    if !failures.is_empty() {
+        return Err(TamsError::InvalidFields(
+            failures
+                .into_iter()
+                .map(|(field, e)| FieldError {
+                    field: field.to_string(),
+                    message: e.to_string(),
+                })
+                .collect(),
+        ));
+    }
+
+    for (field, submitted, stored) in [
+        ("container", submitted.0, &flow.container),
+        ("codec", submitted.1, &flow.codec),
+    ] {
+        if let (Some(submitted), Some(stored)) = (submitted, stored) {
+            if &submitted != stored {
+                warnings.push(
+                    ResponseWarning::new("normalized", format!("{} '{}' will be stored as '{}'", field, submitted, stored))
+                        .with_field(field),
+                );
+            }
+        }
+    }
+    if state.database.get_flow(&flow.id).await?.is_some() {
+        warnings.push(
+            ResponseWarning::new("id_in_use", format!("Flow {} already exists; creating it again would fail", flow.id))
+                .with_field("id"),
+        );
+    }
+    Ok(Json(FlowValidation {
+        valid: true,
+        flow,
+        warnings: warnings.snapshot(),
+    }))
+}
+
+/// Hand a flow to another principal. Only its current owner or an admin may,
+/// unless requests are unauthenticated.
+pub async fn set_flow_owner(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+    Json(payload): Json<SetFlowOwnerRequest>,
 ) -> Result<Json<Flow>, TamsError> {
+    let owner = payload.owner.trim();
+    if owner.is_empty() {
+        return Err(TamsError::Validation("owner must not be empty".to_string()));
+    }
     let flow = state.database.get_flow_required(&id).await?;
+    if let Some(Extension(principal)) = &principal {
+        if !principal.can_access(flow.owner.as_deref()) {
+            return Err(TamsError::Forbidden(format!("Only the owner of flow {} or an admin can transfer it", id)));
+        }
+    }
+    state.database.set_flow_owner(&id, owner, state.clock.now()).await?;
+    tracing::info!(
+        target: "audit",
+        flow_id = %id,
+        previous_owner = ?flow.owner,
+        owner = %owner,
+        "flow ownership transferred"
+    );
+    let mut flow = state.database.get_flow_required(&id).await?;
+    ownership::hide_notify_url(&mut flow, principal.as_deref());
     Ok(Json(flow))
 }
 
-pub async fn create_flow(
+/// Seal a flow once capture completes: in one transaction its
+/// available_timerange becomes the span of its segments and it is made
+/// read-only, after which segment writes are refused. Finalizing a finalized
+/// flow just recomputes the timerange.
+pub async fn finalize_flow(
+    Path(id): Path<Uuid>,
     State(state): State<AppState>,
-    Json(payload): Json<CreateFlowRequest>,
+    principal: Option<Extension<Principal>>,
 ) -> Result<Json<Flow>, TamsError> {
-    let flow = payload.into_flow();
-    state.database.create_flow(&flow).await?;
+    let mut tx = state.database.begin_transaction().await?;
+    let mut flow = tx.get_flow_required(&id).await?;
+    flow.available_timerange = tx.get_segment_coverage(&id).await?;
+    flow.read_only = Some(true);
+    flow.updated_at = state.clock.now();
+    tx.update_flow(&flow).await?;
+    tx.commit().await?;
+
+    state.webhook_manager.send_flow_notification(flow.owner.as_deref(), EventNotification {
+        event_timestamp: state.clock.now(),
+        event_type: EventType::FlowsUpdated,
+        // Subscribers needn't be able to change the flow, so never see its callback
+        event: FlowUpdatedEvent { flow: Flow { notify_url: None, ..flow.clone() } },
+    }).await;
+    ownership::hide_notify_url(&mut flow, principal.as_deref());
     Ok(Json(flow))
 }
 
+/// Refuse segment writes to a read-only flow, e.g. one that has been finalized
+fn check_flow_writable(flow: &Flow) -> TamsResult<()> {
+    if flow.is_read_only() {
+        return Err(TamsError::ReadOnlyFlow { flow_id: flow.id.to_string() });
+    }
+    Ok(())
+}
+
+/// A read-only flow takes no change but to read_only itself, and only an
+/// admin may clear that unless `auth.allow_read_only_unlock` is set
+fn check_read_only_update(
+    flow: &Flow,
+    payload: &UpdateFlowRequest,
+    principal: Option<&Principal>,
+    config: &AuthConfig,
+) -> TamsResult<()> {
+    if !flow.is_read_only() {
+        return Ok(());
+    }
+    if !payload.sets_only_read_only() {
+        return Err(TamsError::ReadOnlyFlow { flow_id: flow.id.to_string() });
+    }
+    let unlocking = payload.read_only == Some(false);
+    if unlocking && !config.allow_read_only_unlock && !principal.is_some_and(|principal| principal.admin) {
+        return Err(TamsError::Forbidden(format!(
+            "Only admins may clear read_only on flow {}",
+            flow.id
+        )));
+    }
+    Ok(())
+}
+
+/// Runs in a request transaction so the read, checks and write of the flow
+/// can't interleave with a concurrent update
 pub async fn update_flow(
     Path(id): Path<Uuid>,
+    Query(params): Query<HashMap<String, String>>,
     State(state): State<AppState>,
+    warnings: Warnings,
+    principal: Option<Extension<Principal>>,
+    Extension(slot): Extension<TransactionSlot>,
     Json(payload): Json<UpdateFlowRequest>,
 ) -> Result<Json<Flow>, TamsError> {
-    let existing_flow = state.database.get_flow_required(&id).await?;
-    let updated_flow = payload.apply_to_flow(existing_flow);
-    state.database.update_flow(&updated_flow).await?;
+    // A new notify_url is resolved before the transaction takes the write lock,
+    // which a slow DNS server would otherwise hold up for every writer
+    if let Some(notify_url) = payload.notify_url.as_deref().filter(|url| !url.is_empty()) {
+        if state.database.get_flow_required(&id).await?.notify_url.as_deref() != Some(notify_url) {
+            validation::check_notify_url_resolves(notify_url, &state.config.validation).await?;
+        }
+    }
+    let mut tx = slot.begin(&state.database).await?;
+    let existing_flow = tx.get_flow_required(&id).await?;
+    let principal = principal.map(|Extension(principal)| principal);
+    check_read_only_update(&existing_flow, &payload, principal.as_ref(), &state.config.auth)?;
+    if payload.source_id != existing_flow.source_id {
+        check_source_visible(&state, payload.source_id, principal.as_ref()).await?;
+    }
+    if state.config.validation.immutable_fields_with_segments {
+        check_immutable_fields_unchanged(&mut tx, &existing_flow, &payload).await?;
+    }
+    if let Some(new_range) = &payload.available_timerange {
+        let force = params.get("force").map(|v| v == "true").unwrap_or(false);
+        // Without auth there's no principal, and anyone may, as on the admin routes
+        if force && principal.as_ref().is_some_and(|principal| !principal.admin) {
+            return Err(TamsError::Forbidden("Only admins may pass force=true".to_string()));
+        }
+        if !force {
+            check_available_timerange_covers_segments(&mut tx, &id, new_range).await?;
+        }
+    }
+    // Only a newly supplied secret needs sealing; a kept one is stored sealed
+    let payload_secret = payload.notify_secret.clone().filter(|_| payload.notify_url.as_deref() != Some(""));
+    let mut updated_flow = payload.apply_to_flow(existing_flow);
+    validation::check_flow_field_sizes(&updated_flow, &state.config.validation)?;
+    validation::infer_container_codec(&mut updated_flow, &state.config.validation, &warnings)?;
+    validation::normalize_flow_vocabularies(&mut updated_flow, &state.config.validation)?;
+    validation::check_flow_format(&updated_flow)?;
+    validation::check_flow_collection(&updated_flow)?;
+    GetUrlTemplate::for_flow(&updated_flow)?;
+    validation::check_flow_notify(&updated_flow, &state.config.validation)?;
+    if let Some(secret) = &payload_secret {
+        updated_flow.notify_secret = Some(state.webhook_manager.seal_key(secret)?);
+    }
+    tx.update_flow(&updated_flow).await?;
+    // The flow's webhook only follows the change once it is stored
+    tx.commit().await?;
+    state.webhook_manager.sync_flow_webhook(&updated_flow).await;
+    ownership::hide_notify_url(&mut updated_flow, principal.as_ref());
     Ok(Json(updated_flow))
 }
 
+/// Refuse a flow's source_id naming a source hidden from the caller, answered
+/// as if the source didn't exist; the body is out of the ownership middleware's sight
+async fn check_source_visible(state: &AppState, source_id: Option<Uuid>, principal: Option<&Principal>) -> TamsResult<()> {
+    let Some(source_id) = source_id else {
+        return Ok(());
+    };
+    if ownership::listing_owner(&state.config.auth, principal).is_none() {
+        return Ok(());
+    }
+    match state.database.get_source(&source_id).await? {
+        Some(source) if ownership::hidden_from(&state.config.auth, principal, source.owner.as_deref()) => {
+            Err(TamsError::NotFound("Source not found".to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Refuse a change of format or source_id once the flow has segments, which
+/// were written for the flow as it was
+async fn check_immutable_fields_unchanged(
+    tx: &mut DatabaseTransaction,
+    flow: &Flow,
+    payload: &UpdateFlowRequest,
+) -> TamsResult<()> {
+    let mut changed = Vec::new();
+    if payload.format.as_ref().is_some_and(|format| *format != flow.format) {
+        changed.push("format");
+    }
+    if payload.source_id.is_some_and(|source_id| flow.source_id != Some(source_id)) {
+        changed.push("source_id");
+    }
+    if changed.is_empty() || !tx.flow_has_segments(&flow.id).await? {
+        return Ok(());
+    }
+    Err(TamsError::Conflict(format!(
+        "Flow {} has segments, so its {} can't be changed",
+        flow.id,
+        changed.join(" and ")
+    )))
+}
+
+/// Refuse an available_timerange that would hide existing segments, unless
+/// deletion requests in progress for the flow cover what it excludes
+async fn check_available_timerange_covers_segments(
+    tx: &mut DatabaseTransaction,
+    flow_id: &Uuid,
+    new_range: &TimeRange,
+) -> TamsResult<()> {
+    let start_ns = time_utils::timestamp_to_nanos(&new_range.start)?;
+    let end_ns = new_range.end.as_deref().map(time_utils::timestamp_to_nanos).transpose()?;
+    let outside = tx.get_segment_ranges_outside(flow_id, start_ns, end_ns).await?;
+    if outside.is_empty() {
+        return Ok(());
+    }
+    let excluded = time_utils::subtract_timeranges(&outside, std::slice::from_ref(new_range))?;
+
+    let mut deleting = Vec::new();
+    for request in tx.get_deletion_requests_for_flow(flow_id).await?.items.iter().filter(|r| r.is_active()) {
+        match request.timerange.as_deref().map(deletion::parse_deletion_timerange).transpose()?.flatten() {
+            Some(timerange) => deleting.push(timerange),
+            // Deleting the whole flow
+            None => return Ok(()),
+        }
+    }
+    let kept = time_utils::subtract_timeranges(&excluded, &deleting)?;
+    if kept.is_empty() {
+        return Ok(());
+    }
+
+    let kept: Vec<String> = kept.iter().map(time_utils::format_tams_timerange).collect();
+    Err(TamsError::Conflict(format!(
+        "available_timerange {} excludes existing segments covering {}; delete the segments first or pass force=true",
+        time_utils::format_tams_timerange(new_range),
+        kept.join(", ")
+    )))
+}
+
 pub async fn delete_flow(
     Path(id): Path<Uuid>,
     State(state): State<AppState>,
 ) -> Result<StatusCode, TamsError> {
-    state.database.delete_flow(&id).await?;
+    let owner = state.database.get_flow(&id).await?.and_then(|flow| flow.owner);
+    // Cancelled requests are terminal, so nothing acts on them once the flow is gone
+    let (deleted, cancelled) = state
+        .database
+        .delete_flow_cancelling_deletion_requests(&id, state.config.deletion.on_flow_delete_with_active_requests)
+        .await?;
+    if deleted == 0 {
+        return Err(TamsError::FlowNotFound { flow_id: id.to_string() });
+    }
+    if cancelled > 0 {
+        tracing::info!("Cancelled {} deletion requests for deleted flow {}", cancelled, id);
+    }
+
+    state.webhook_manager.send_flow_notification(owner.as_deref(), EventNotification {
+        event_timestamp: state.clock.now(),
+        event_type: EventType::FlowsDeleted,
+        event: FlowDeletedEvent { flow_id: id },
+    }).await;
+    state.webhook_manager.remove_flow_webhook(&id).await;
+
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// `timerange` in TAMS notation, or `start` and `end`; either bound may be left
+/// open, and an unparseable or inverted range is refused rather than ignored
+fn query_bounds(params: &HashMap<String, String>) -> TamsResult<(Option<i64>, Option<i64>)> {
+    match params.get("timerange") {
+        Some(timerange) => time_utils::parse_timerange_bounds(timerange),
+        None => {
+            let bound = |name: &str| params.get(name).map(String::as_str).unwrap_or_default();
+            time_utils::parse_timerange_bounds(&format!("{}_{}", bound("start"), bound("end")))
+        }
+    }
+}
+
 // Flow segments endpoints
 pub async fn list_flow_segments(
     Path(flow_id): Path<Uuid>,
     Query(params): Query<HashMap<String, String>>,
     State(state): State<AppState>,
+    head: Head,
 ) -> Result<Json<Value>, TamsError> {
-    let limit = params.get("limit").and_then(|l| l.parse().ok()).unwrap_or(100);
+    let max_limit = state.reloader.pagination().max_limit;
+    let limit = params.get("limit").and_then(|l| l.parse().ok()).unwrap_or(100).clamp(1, max_limit.max(1));
 
-    let timerange = if let (Some(start), Some(end)) = (params.get("start"), params.get("end")) {
-        Some(TimeRange {
-            start: start.clone(),
-            end: end.clone(),
-        })
-    } else {
+    let (start_ns, end_ns) = query_bounds(&params)?;
+    let timerange = start_ns.zip(end_ns).map(|(start, end)| TimeRange {
+        start: time_utils::nanos_to_timestamp(start),
+        end: Some(time_utils::nanos_to_timestamp(end)),
+    });
+    validation::check_query_bounds(start_ns, end_ns, &state.config.validation)?;
+    let filters = FlowSegmentFilters {
+        object_id: params.get("object_id").cloned(),
+        start_ns,
+        end_ns,
+        reverse_order: params.get("reverse_order").map(|v| v == "true"),
+    };
+
+    let (mut segments, next_key) = state
+        .database
+        .get_flow_segments_by_timerange(&flow_id, &filters, limit, params.get("page").map(String::as_str))
+        .await?;
+
+    // get_urls and the total only shape the body, which HEAD throws away
+    let skip_body = head.skip_body();
+
+    // Media in an external store is addressed by the flow's template; nothing here to check
+    let template = match state.database.get_flow(&flow_id).await? {
+        Some(flow) if !skip_body => GetUrlTemplate::for_flow(&flow)?,
+        _ => None,
+    };
+    if let Some(template) = template {
+        for segment in &mut segments.items {
+            let url = template.expand(&segment.object_id, &flow_id);
+            segment.get_urls = HashMap::from([(EXTERNAL_GET_URL_LABEL.to_string(), url)]);
+        }
+    }
+
+    // Always given for segments: without filters it is the flow's kept
+    // segment_count, so only a filtered listing pays for a COUNT(*)
+    let unfiltered = filters.start_ns.is_none() && filters.end_ns.is_none() && filters.object_id.is_none();
+    let total = if skip_body {
         None
+    } else if unfiltered {
+        Some(state.database.get_flow_stats(&flow_id).await?.segment_count)
+    } else {
+        Some(state.database.count_flow_segments(&flow_id, &filters).await?)
     };
 
-    let segments = state.database.get_flow_segments_by_timerange(&flow_id, timerange.as_ref(), limit).await?;
-    
+    let mut pagination = PaginationInfo::new(limit, total, segments.skipped_corrupt);
+    pagination.next_key = next_key;
+    pagination.timerange = timerange;
+    pagination.reverse_order = filters.reverse_order;
     Ok(Json(json!({
-        "segments": segments,
-        "pagination": {
-            "limit": limit,
-            "count": segments.len()
-        }
+        "segments": segments.items,
+        "pagination": pagination
     })))
 }
 
-pub async fn add_flow_segment(
-    Path(flow_id): Path<Uuid>,
-    State(state): State<AppState>,
-    Json(payload): Json<CreateSegmentRequest>,
-) -> Result<Json<FlowSegment>, TamsError> {
-    let segment = payload.into_segment(flow_id);
-    state.database.add_flow_segment(&segment).await?;
-    Ok(Json(segment))
-}
+/// Most gaps returned by the gaps endpoint; the count and total duration still cover all of them
+const GAPS_MAX_RANGES: usize = 1000;
 
-pub async fn delete_flow_segments(
+/// `start` and `end` bound the window searched and default to the extent of
+/// the flow's segments
+pub async fn get_flow_gaps(
     Path(flow_id): Path<Uuid>,
     Query(params): Query<HashMap<String, String>>,
     State(state): State<AppState>,
-) -> Result<StatusCode, TamsError> {
-    let timerange = if let (Some(start), Some(end)) = (params.get("start"), params.get("end")) {
-        Some(TimeRange {
-            start: start.clone(),
-            end: end.clone(),
-        })
-    } else {
-        None
+) -> Result<Json<FlowGaps>, TamsError> {
+    state.database.get_flow_required(&flow_id).await?;
+    let extent = state.database.get_segment_extent_nanos(&flow_id).await?;
+    let start = params.get("start").cloned().or_else(|| extent.map(|(start, _)| time_utils::nanos_to_timestamp(start)));
+    let end = params.get("end").cloned().or_else(|| extent.map(|(_, end)| time_utils::nanos_to_timestamp(end)));
+    let (Some(start), Some(end)) = (start, end) else {
+        return Ok(Json(FlowGaps {
+            flow_id,
+            timerange: None,
+            gaps: CoverageSet {
+                timeranges: Vec::new(),
+                count: 0,
+                truncated: false,
+                total_duration: time_utils::nanos_to_timestamp(0),
+            },
+        }));
     };
+    let window = time_utils::create_timerange(&start, &end)?;
+    validation::check_query_duration(&window, &state.config.validation)?;
 
-    // Delete segments based on timerange
-    if let Some(ref tr) = timerange {
-        state.database.delete_flow_segments_by_timerange(&flow_id, tr).await?;
+    let mut segments = Vec::new();
+    for stored in state
+        .database
+        .get_segment_timeranges_in_window(
+            &flow_id,
+            time_utils::timestamp_to_nanos(&window.start)?,
+            time_utils::timestamp_to_nanos(window.bounded_end()?)?,
+        )
+        .await?
+    {
+        segments.extend(state.database.stored_timerange(&flow_id, &stored)?);
     }
+    let gaps = time_utils::find_gaps(&window, &segments)?;
 
-    Ok(StatusCode::NO_CONTENT)
+    Ok(Json(FlowGaps {
+        flow_id,
+        gaps: CoverageSet {
+            total_duration: time_utils::total_duration(&gaps)?,
+            count: gaps.len(),
+            truncated: gaps.len() > GAPS_MAX_RANGES,
+            timeranges: gaps.into_iter().take(GAPS_MAX_RANGES).collect(),
+        },
+        timerange: Some(window),
+    }))
 }
 
-// Storage endpoints
-pub async fn allocate_storage(
-    Path(_flow_id): Path<Uuid>,
+/// Most buckets a rollup may return
+const ROLLUP_MAX_BUCKETS: i64 = 10_000;
+
+/// Segment coverage in fixed-size buckets, e.g. `?bucket=60:0` for one entry per
+/// minute. Spans the flow's segments unless `timerange` is given; buckets with no
+/// coverage are left out unless `include_empty=true`.
+pub async fn get_flow_segment_rollup(
+    Path(flow_id): Path<Uuid>,
     Query(params): Query<HashMap<String, String>>,
     State(state): State<AppState>,
-) -> Result<Json<FlowStorage>, TamsError> {
-    // Parse limit from query parameters, default to 1
-    let limit = params.get("limit").and_then(|l| l.parse().ok()).unwrap_or(1);
-    
-    // Parse object_ids from query parameters if provided
-    let object_ids = if let Some(object_ids_str) = params.get("object_ids") {
-        Some(object_ids_str.split(',').map(|s| s.to_string()).collect())
+) -> Result<Json<SegmentRollup>, TamsError> {
+    state.database.get_flow_required(&flow_id).await?;
+    let bucket = params
+        .get("bucket")
+        .ok_or_else(|| TamsError::BadRequest("Missing bucket parameter".to_string()))?;
+    let bucket_nanos = time_utils::parse_duration_nanos(bucket)?;
+    let include_empty = params.get("include_empty").map(|v| v == "true").unwrap_or(false);
+
+    let window = match params.get("timerange") {
+        Some(timerange) => {
+            let timerange = time_utils::parse_timerange_param(timerange)?;
+            Some((
+                time_utils::timestamp_to_nanos(&timerange.start)?,
+                time_utils::timestamp_to_nanos(timerange.bounded_end()?)?,
+            ))
+        }
+        None => match state.database.get_segment_extent_nanos(&flow_id).await? {
+            // An open-ended segment has no last instant to count buckets up to
+            Some((_, end)) if end == time_utils::OPEN_END_NANOS => {
+                return Err(TamsError::Validation(
+                    "Flow has an open-ended segment; give a timerange to roll up".to_string(),
+                ));
+            }
+            extent => extent,
+        },
+    };
+    let Some((window_start, window_end)) = window else {
+        return Ok(Json(SegmentRollup {
+            flow_id,
+            bucket: time_utils::nanos_to_timestamp(bucket_nanos),
+            timerange: None,
+            buckets: Vec::new(),
+        }));
+    };
+
+    // Buckets line up on multiples of the bucket size, so a minute bucket starts on the minute
+    let too_large = || TamsError::Validation("Rollup timerange is out of range for the bucket size".to_string());
+    let origin = window_start.checked_sub(window_start.rem_euclid(bucket_nanos)).ok_or_else(too_large)?;
+    let bucket_count = window_end
+        .checked_sub(origin)
+        .and_then(|span| span.checked_add(bucket_nanos - 1))
+        .ok_or_else(too_large)?
+        / bucket_nanos;
+    if bucket_count > ROLLUP_MAX_BUCKETS {
+        return Err(TamsError::Validation(format!(
+            "Rollup would return {} buckets, more than the limit of {}; use a larger bucket or a shorter timerange",
+            bucket_count, ROLLUP_MAX_BUCKETS
+        )));
+    }
+    // Every bucket starts before this, so none of their starts can overflow either
+    let rollup_end = bucket_count
+        .checked_mul(bucket_nanos)
+        .and_then(|span| origin.checked_add(span))
+        .ok_or_else(too_large)?;
+
+    let rows = state
+        .database
+        .get_segment_rollup(&flow_id, origin, bucket_nanos, window_start, window_end)
+        .await?;
+    let to_bucket = |index: i64, covered: i64, count: i64, bytes: i64| RollupBucket {
+        start: time_utils::nanos_to_timestamp(origin + index * bucket_nanos),
+        covered_duration: time_utils::nanos_to_timestamp(covered),
+        segment_count: count as u64,
+        byte_estimate: bytes as u64,
+    };
+    let buckets = if include_empty {
+        let mut rows = rows.into_iter().peekable();
+        (0..bucket_count)
+            .map(|index| match rows.next_if(|row| row.0 == index) {
+                Some((_, covered, count, bytes)) => to_bucket(index, covered, count, bytes),
+                None => to_bucket(index, 0, 0, 0),
+            })
+            .collect()
     } else {
-        None
+        rows.into_iter()
+            .map(|(index, covered, count, bytes)| to_bucket(index, covered, count, bytes))
+            .collect()
     };
-    
-    // Use the storage allocate_storage method which creates proper StorageObjects
-    let objects = state.storage.allocate_storage(limit, object_ids).await?;
-    
-    Ok(Json(FlowStorage { objects }))
-}
 
-// Media object endpoints
-pub async fn get_media_object(
-    Path(object_id): Path<String>,
-    State(state): State<AppState>,
-) -> Result<Json<MediaObject>, TamsError> {
-    let media_object = state.database.get_media_object_required(&object_id).await?;
-    Ok(Json(media_object))
+    Ok(Json(SegmentRollup {
+        flow_id,
+        bucket: time_utils::nanos_to_timestamp(bucket_nanos),
+        timerange: Some(TimeRange {
+            start: time_utils::nanos_to_timestamp(origin),
+            end: Some(time_utils::nanos_to_timestamp(rollup_end)),
+        }),
+        buckets,
+    }))
 }
 
-pub async fn put_media_object(
-    Path(object_id): Path<String>,
-    State(state): State<AppState>,
-    body: axum::body::Bytes,
-) -> Result<StatusCode, TamsError> {
-    // Store the uploaded data
-    state.storage.store_object(&object_id, body.to_vec()).await?;
-    
-    // Create or update media object record in database
-    let media_object = MediaObject {
-        object_id: object_id.clone(),
-        size_bytes: Some(body.len() as u64),
-        mime_type: None, // Could be inferred from content-type header
-        flow_references: Vec::new(),
-        created_at: chrono::Utc::now(),
-    };
-    
-    // Try to create the media object, ignore if it already exists
-    let _ = state.database.create_media_object(&media_object).await;
-    
-    Ok(StatusCode::CREATED)
+/// Segments read per range query by `segments_in_window`
+const WINDOW_PAGE_SIZE: u32 = 1000;
+
+/// Bounds of the window a stream or archive reads, given as `list_flow_segments`
+/// takes them. Without any the window is the whole flow, whose extent is then
+/// held to the maximum query duration like any given window.
+async fn read_window(
+    state: &AppState,
+    flow_id: &Uuid,
+    params: &HashMap<String, String>,
+) -> TamsResult<(Option<i64>, Option<i64>)> {
+    let (start_ns, end_ns) = query_bounds(params)?;
+    if start_ns.is_none() && end_ns.is_none() && state.config.validation.max_query_duration_seconds.is_some() {
+        let stats = state.database.get_flow_stats(flow_id).await?;
+        if let Some(start) = stats.first_timestamp {
+            let extent = TimeRange { start, end: stats.last_timestamp };
+            validation::check_query_duration(&extent, &state.config.validation)?;
+        }
+    } else {
+        validation::check_query_bounds(start_ns, end_ns, &state.config.validation)?;
+    }
+    Ok((start_ns, end_ns))
 }
 
-pub async fn head_media_object(
-    State(state): State<AppState>,
-    Path(object_id): Path<String>,
-) -> TamsResult<StatusCode> {
-    let _media_object = state.database.get_media_object_required(&object_id).await?;
-    Ok(StatusCode::OK)
+/// The flow's segments overlapping `start_ns..end_ns`, with their parsed
+/// timeranges, in timeline order; read through the range query a page at a time
+async fn segments_in_window(
+    state: &AppState,
+    flow_id: &Uuid,
+    start_ns: Option<i64>,
+    end_ns: Option<i64>,
+) -> TamsResult<Vec<(TimeRange, FlowSegment)>> {
+    let filters = FlowSegmentFilters { start_ns, end_ns, ..Default::default() };
+    let mut ordered = Vec::new();
+    let mut page = None;
+    loop {
+        let (segments, next_key) =
+            state.database.get_flow_segments_by_timerange(flow_id, &filters, WINDOW_PAGE_SIZE, page.as_deref()).await?;
+        for segment in segments.items {
+            // Only listed without a window; with no timerange there's no place for it
+            if let Some(timerange) = state.database.stored_timerange(flow_id, &segment.timerange)? {
+                ordered.push((timerange, segment));
+            }
+        }
+        match next_key {
+            Some(key) => page = Some(key),
+            None => return Ok(ordered),
+        }
+    }
 }
 
-// Webhook endpoints
-pub async fn list_webhooks(
+/// Stream the flow's segment objects back to back in timerange order, for
+/// preview. Only flows whose objects concatenate byte-wise are supported.
+pub async fn stream_flow(
+    Path(flow_id): Path<Uuid>,
+    Query(params): Query<HashMap<String, String>>,
     State(state): State<AppState>,
-) -> Result<Json<Value>, TamsError> {
-    let webhooks = state.database.get_webhooks_list().await?;
-    
-    Ok(Json(json!({
-        "webhooks": webhooks
-    })))
-}
+    headers: HeaderMap,
+) -> Result<Response, TamsError> {
+    use futures_util::{StreamExt, TryStreamExt};
 
-pub async fn create_webhook(
+    let flow = state.database.get_flow_required(&flow_id).await?;
+    let (start_ns, end_ns) = read_window(&state, &flow_id, &params).await?;
+
+    let ordered = segments_in_window(&state, &flow_id, start_ns, end_ns).await?;
+    if ordered.is_empty() {
+        return Err(TamsError::NotFound(format!("Flow {} has no segments in the requested timerange", flow_id)));
+    }
+    let segments: Vec<FlowSegment> = ordered.into_iter().map(|(_, segment)| segment).collect();
+
+    let object_ids: Vec<String> = segments.iter().map(|segment| segment.object_id.clone()).collect();
+    let found = state.database.get_media_objects(&object_ids).await?;
+    let objects: Vec<Option<MediaObject>> = segments
+        .iter()
+        .map(|segment| found.get(&segment.object_id).cloned())
+        .collect();
+    concat::check_concatenatable(&flow, &segments, &objects)?;
+
+    let mut sizes = Vec::with_capacity(segments.len());
+    let mut contexts = HashMap::new();
+    for (segment, object) in segments.iter().zip(&objects) {
+        let context = ObjectContext::for_object(object.as_ref(), Some(flow_id));
+        let (size, _) = state.storage.get_object_metadata(&segment.object_id, &context).await?;
+        sizes.push((segment.object_id.clone(), size));
+        contexts.insert(segment.object_id.clone(), context);
+    }
+    let total: u64 = sizes.iter().map(|(_, size)| size).sum();
+
+    let range = match headers.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        Some(value) => match concat::parse_range_header(value, total) {
+            Ok(range) => range,
+            Err(()) => {
+                return Ok((
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    [(header::CONTENT_RANGE, format!("bytes */{}", total))],
+                )
+                    .into_response());
+            }
+        },
+        None => None,
+    };
+
+    let parts = concat::plan_parts(&sizes, range);
+    let content_length: u64 = parts.iter().map(|part| part.len).sum();
+    let storage = state.storage.clone();
+    let body = futures_util::stream::iter(parts)
+        .then(move |part| {
+            let storage = storage.clone();
+            let context = contexts.get(&part.object_id).cloned().unwrap_or_default();
+            async move {
+                let reader = storage.open_object_range(&part.object_id, &context, part.offset, part.len).await?;
+                Ok::<_, TamsError>(tokio_util::io::ReaderStream::new(reader).map_err(TamsError::from))
+            }
+        })
+        .try_flatten();
+
+    let content_type = flow.container.clone().unwrap_or_else(|| "application/octet-stream".to_string());
+    let mut response = (
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+            (header::CONTENT_LENGTH, content_length.to_string()),
+        ],
+        axum::body::Body::from_stream(body),
+    )
+        .into_response();
+    if let Some((first, last)) = range {
+        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+        response.headers_mut().insert(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", first, last, total)
+                .parse()
+                .map_err(|_| TamsError::Internal("Invalid Content-Range".to_string()))?,
+        );
+    }
+    Ok(response)
+}
+
+/// Stream the objects of the flow's segments in a timerange as one tar
+/// archive (gzipped with `format=tar.gz`), led by a `manifest.json` giving each
+/// segment's timerange and ts_offset. A missing object fails the request before
+/// anything is sent, unless `allow_missing=true` records it in the manifest.
+pub async fn archive_flow_segments(
+    Path(flow_id): Path<Uuid>,
+    Query(params): Query<HashMap<String, String>>,
     State(state): State<AppState>,
-    Json(payload): Json<WebhookRequest>,
-) -> Result<Json<Webhook>, TamsError> {
-    let webhook = Webhook {
-        url: payload.url,
-        api_key_name: payload.api_key_name,
-        api_key_value: Some(payload.api_key_value),
-        events: payload.events,
+) -> Result<Response, TamsError> {
+    use futures_util::{stream, StreamExt, TryStreamExt};
+
+    state.database.get_flow_required(&flow_id).await?;
+    let gzip = match params.get("format").map(String::as_str) {
+        None | Some("tar") => false,
+        Some("tar.gz") | Some("tgz") => true,
+        Some(other) => {
+            return Err(TamsError::Validation(format!("Unsupported archive format '{}'; use tar or tar.gz", other)));
+        }
     };
-    
-    state.database.create_webhook(&webhook).await?;
-    
-    // Return webhook without the API key value for security
-    let response_webhook = Webhook {
-        url: webhook.url,
-        api_key_name: webhook.api_key_name,
-        api_key_value: None,
-        events: webhook.events,
+    let allow_missing = params.get("allow_missing").is_some_and(|v| v == "true");
+    let (start_ns, end_ns) = read_window(&state, &flow_id, &params).await?;
+    let segments = segments_in_window(&state, &flow_id, start_ns, end_ns).await?;
+    if segments.is_empty() {
+        return Err(TamsError::NotFound(format!("Flow {} has no segments in the requested timerange", flow_id)));
+    }
+
+    struct Entry {
+        name: String,
+        object_id: String,
+        context: ObjectContext,
+        size: u64,
+        mtime: i64,
+    }
+    let mut manifest = archive::ArchiveManifest { flow_id, segments: Vec::with_capacity(segments.len()) };
+    let mut entries = Vec::with_capacity(segments.len());
+    let object_ids: Vec<String> = segments.iter().map(|(_, segment)| segment.object_id.clone()).collect();
+    let objects = state.database.get_media_objects(&object_ids).await?;
+    for (timerange, segment) in segments {
+        let context = ObjectContext::for_object(objects.get(&segment.object_id), Some(flow_id));
+        let size = match state.storage.get_object_metadata(&segment.object_id, &context).await {
+            Ok((size, _)) => Some(size),
+            Err(TamsError::ObjectNotFound { .. }) if allow_missing => None,
+            Err(e) => return Err(e),
+        };
+        let name = archive::entry_name(&timerange.start, &segment.object_id);
+        manifest.segments.push(archive::ArchiveManifestSegment {
+            object_id: segment.object_id.clone(),
+            timerange: segment.timerange.clone(),
+            ts_offset: segment.ts_offset.clone(),
+            entry: size.map(|_| name.clone()),
+            missing: size.is_none(),
+        });
+        if let Some(size) = size {
+            entries.push(Entry {
+                name,
+                object_id: segment.object_id,
+                context,
+                size,
+                mtime: segment.created_at.timestamp(),
+            });
+        }
+    }
+
+    let manifest = serde_json::to_vec_pretty(&manifest)?;
+    let total = archive::entry_len(archive::MANIFEST_ENTRY, manifest.len() as u64)
+        + entries.iter().map(|entry| archive::entry_len(&entry.name, entry.size)).sum::<u64>()
+        + archive::END_OF_ARCHIVE.len() as u64;
+    let max_size = state.config.media_storage.max_archive_bytes;
+    if total > max_size {
+        return Err(TamsError::FileTooLarge { max_size });
+    }
+
+    let mut head = archive::header(archive::MANIFEST_ENTRY, manifest.len() as u64, state.clock.now().timestamp())?;
+    head.extend_from_slice(&manifest);
+    head.extend(archive::padding(manifest.len() as u64));
+    let storage = state.storage.clone();
+    let objects = stream::iter(entries)
+        .then(move |entry| {
+            let storage = storage.clone();
+            async move {
+                let header = archive::header(&entry.name, entry.size, entry.mtime)?;
+                let reader = storage.open_object_range(&entry.object_id, &entry.context, 0, entry.size).await?;
+                Ok::<_, TamsError>(
+                    stream::once(async move { Ok(axum::body::Bytes::from(header)) })
+                        .chain(tokio_util::io::ReaderStream::new(reader).map_err(TamsError::from))
+                        .chain(stream::once(async move { Ok(axum::body::Bytes::from(archive::padding(entry.size))) })),
+                )
+            }
+        })
+        .try_flatten();
+    let tar = stream::once(async move { Ok(axum::body::Bytes::from(head)) })
+        .chain(objects)
+        .chain(stream::once(async { Ok(axum::body::Bytes::from_static(&archive::END_OF_ARCHIVE)) }));
+
+    if gzip {
+        let reader = tokio_util::io::StreamReader::new(tar.map_err(|e| std::io::Error::other(e.to_string())));
+        let body = tokio_util::io::ReaderStream::new(async_compression::tokio::bufread::GzipEncoder::new(reader));
+        return Ok((
+            [
+                (header::CONTENT_TYPE, "application/gzip".to_string()),
+                (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.tar.gz\"", flow_id)),
+            ],
+            axum::body::Body::from_stream(body),
+        )
+            .into_response());
+    }
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.tar\"", flow_id)),
+            (header::CONTENT_LENGTH, total.to_string()),
+        ],
+        axum::body::Body::from_stream(tar),
+    )
+        .into_response())
+}
+
+pub async fn add_flow_segment(
+    Path(flow_id): Path<Uuid>,
+    State(state): State<AppState>,
+    warnings: Warnings,
+    Json(payload): Json<CreateSegmentRequest>,
+) -> Result<Created<FlowSegment>, TamsError> {
+    state.ingest.check(Some(&flow_id)).await?;
+    // A timerange that doesn't parse would be stored without bounds, out of sight of range queries
+    time_utils::validate_timerange(&payload.timerange)?;
+    validation::check_segment_ts_offset(&payload)?;
+    validation::check_segment_clock_skew(&payload, state.clock.now(), &state.config.validation, &warnings)?;
+    let require_object = state.config.validation.require_segment_objects && !has_external_media(&state, &flow_id).await?;
+    // Segments have no URL of their own; point at the listing of exactly this timerange
+    let location = state.config.service.api_path(&format!(
+        "/flows/{}/segments?{}",
+        flow_id,
+        url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("start", &payload.timerange.start)
+            .append_pair("end", payload.timerange.bounded_end()?)
+            .finish()
+    ));
+    let written = payload.timerange.clone();
+    let segment = payload.into_segment(flow_id);
+    // Checked under the write lock, so no deletion request can start, and
+    // retention can't reclaim the object, between the checks and the insert
+    let mut tx = state.database.begin_transaction().await?;
+    let Some(flow) = tx.get_flow(&flow_id).await? else {
+        tx.rollback().await?;
+        return Err(TamsError::FlowNotFound { flow_id: flow_id.to_string() });
     };
-    
-    Ok(Json(response_webhook))
+    check_flow_writable(&flow)?;
+    if require_object && !tx.media_object_exists(&segment.object_id).await? {
+        tx.rollback().await?;
+        return Err(missing_segment_object(&state, &flow_id, &segment.object_id).await?);
+    }
+    let requests = tx.get_deletion_requests_for_flow(&flow_id).await?;
+    deletion::check_no_conflicting_deletion(&requests.items, Some(&written), state.config.deletion.writes_during_deletion)?;
+    tx.add_flow_segment(&segment).await?;
+    tx.commit().await?;
+    metrics().record_segments_ingested(state.clock.now(), 1);
+    state.webhook_manager.send_flow_notification(flow.owner.as_deref(), EventNotification {
+        event_timestamp: state.clock.now(),
+        event_type: EventType::FlowsSegmentsAdded,
+        event: SegmentsAddedEvent {
+            flow_id,
+            segments: vec![segment.clone()],
+        },
+    }).await;
+    Created::new(&location, segment)
 }
 
-pub async fn delete_webhook(
+/// `POST /flows/:flow_id/segments` takes one segment, or as TAMS allows an
+/// array of them for bulk ingest
+pub async fn post_flow_segments(
+    Path(flow_id): Path<Uuid>,
     State(state): State<AppState>,
-    Path(webhook_url): Path<String>,
-) -> TamsResult<StatusCode> {
-    // TODO: Implement delete_webhook in database
-    // state.database.delete_webhook(&webhook_url).await?;
-    // state.webhook_manager.remove_webhook(&webhook_url).await;
-    Ok(StatusCode::NO_CONTENT)
+    warnings: Warnings,
+    Json(payload): Json<Value>,
+) -> Result<Response, TamsError> {
+    if payload.is_array() {
+        let segments: Vec<CreateSegmentRequest> = serde_json::from_value(payload)?;
+        return add_flow_segments(Path(flow_id), State(state), warnings, Json(segments)).await;
+    }
+    let segment: CreateSegmentRequest = serde_json::from_value(payload)?;
+    Ok(add_flow_segment(Path(flow_id), State(state), warnings, Json(segment)).await?.into_response())
 }
 
-// Flow delete request endpoints
-pub async fn request_flow_deletion(
+/// Add many segments to a flow at once, stored by the batched multi-row
+/// insert. Segments that fail validation or can't be stored (e.g. duplicates)
+/// are left out and the rest kept: all of them stored is 201 with the
+/// segments, otherwise 200 with the `failed_segments` and why.
+pub async fn add_flow_segments(
     Path(flow_id): Path<Uuid>,
     State(state): State<AppState>,
-    Json(payload): Json<HashMap<String, Value>>,
-) -> Result<Json<DeletionRequest>, TamsError> {
-    let request_id = Uuid::new_v4().to_string();
-    let timerange = payload.get("timerange")
-        .and_then(|tr| serde_json::to_string(tr).ok());
+    warnings: Warnings,
+    Json(payload): Json<Vec<CreateSegmentRequest>>,
+) -> Result<Response, TamsError> {
+    state.ingest.check(Some(&flow_id)).await?;
+    if payload.is_empty() {
+        return Err(TamsError::Validation("No segments given".to_string()));
+    }
+    let now = state.clock.now();
+    let mut failed_segments = Vec::new();
+    let mut accepted = Vec::with_capacity(payload.len());
+    for request in payload {
+        let checked = time_utils::validate_timerange(&request.timerange)
+            .and_then(|_| validation::check_segment_ts_offset(&request))
+            .and_then(|_| validation::check_segment_clock_skew(&request, now, &state.config.validation, &warnings));
+        let written = request.timerange.clone();
+        let segment = request.into_segment(flow_id);
+        match checked {
+            Ok(()) => accepted.push((written, segment)),
+            Err(e) => failed_segments.push(FlowSegmentFailure { segment, error: e.to_string() }),
+        }
+    }
+    let require_object = state.config.validation.require_segment_objects && !has_external_media(&state, &flow_id).await?;
 
-    let request = DeletionRequest {
-        id: request_id,
-        flow_id,
-        timerange,
-        status: "pending".to_string(),
-        progress: None,
-        created_at: chrono::Utc::now(),
-        updated_at: chrono::Utc::now(),
+    // Checked under the write lock, as for a single segment
+    let mut tx = state.database.begin_transaction().await?;
+    let Some(flow) = tx.get_flow(&flow_id).await? else {
+        tx.rollback().await?;
+        return Err(TamsError::FlowNotFound { flow_id: flow_id.to_string() });
     };
+    check_flow_writable(&flow)?;
+    let requests = tx.get_deletion_requests_for_flow(&flow_id).await?;
+    let mut segments = Vec::with_capacity(accepted.len());
+    for (written, segment) in accepted {
+        deletion::check_no_conflicting_deletion(&requests.items, Some(&written), state.config.deletion.writes_during_deletion)?;
+        if require_object && !tx.media_object_exists(&segment.object_id).await? {
+            let error = missing_segment_object(&state, &flow_id, &segment.object_id).await?;
+            failed_segments.push(FlowSegmentFailure { segment, error: error.to_string() });
+        } else {
+            segments.push(segment);
+        }
+    }
+    let refused = tx.add_flow_segments(&segments).await?;
+    tx.commit().await?;
+    segments.retain(|segment| {
+        !refused.iter().any(|f| f.segment.object_id == segment.object_id && f.segment.timerange == segment.timerange)
+    });
+    failed_segments.extend(refused);
+    metrics().record_segments_ingested(state.clock.now(), segments.len() as u64);
 
-    state.database.create_deletion_request(&request).await?;
-    
-    Ok(Json(request))
+    if !segments.is_empty() {
+        state.webhook_manager.send_flow_notification(flow.owner.as_deref(), EventNotification {
+            event_timestamp: state.clock.now(),
+            event_type: EventType::FlowsSegmentsAdded,
+            event: SegmentsAddedEvent {
+                flow_id,
+                segments: segments.clone(),
+            },
+        }).await;
+    }
+    if !failed_segments.is_empty() {
+        return Ok((StatusCode::OK, Json(FlowSegmentBulkFailure { failed_segments })).into_response());
+    }
+    let location = state.config.service.api_path(&format!("/flows/{}/segments", flow_id));
+    Ok(Created::new(&location, segments)?.into_response())
 }
 
-pub async fn list_deletion_requests(
+/// Build the error for a segment whose object was never uploaded, telling the
+/// client how to allocate it and whether an earlier allocation has expired
+async fn missing_segment_object(state: &AppState, flow_id: &Uuid, object_id: &str) -> TamsResult<TamsError> {
+    let allocation = state.database.get_storage_allocation(object_id).await?;
+    let allocation_expires_at = allocation.and_then(|a| a.expires_at);
+    let guidance = MissingObjectGuidance {
+        object_id: object_id.to_string(),
+        allocation_hint: AllocationHint {
+            method: "POST".to_string(),
+            path: state.config.service.api_path(&format!("/flows/{}/storage", flow_id)),
+            body: FlowStorageRequest {
+                limit: Some(1),
+                object_ids: Some(vec![object_id.to_string()]),
+            },
+        },
+        expired_allocation: allocation_expires_at.is_some_and(|expires| expires <= state.clock.now()),
+        allocation_expires_at,
+    };
+    Ok(TamsError::SegmentObjectMissing {
+        object_id: object_id.to_string(),
+        extensions: serde_json::to_value(guidance)?,
+    })
+}
+
+/// Delete the segments lying wholly within `timerange` (or `start` and
+/// `end`). Segments only partly inside are left alone, or with `strict=true`
+/// refuse the deletion with 409. Wiping every segment takes an explicit
+/// `all=true` instead of a range.
+pub async fn delete_flow_segments(
+    Path(flow_id): Path<Uuid>,
+    Query(params): Query<HashMap<String, String>>,
     State(state): State<AppState>,
 ) -> Result<Json<Value>, TamsError> {
-    let requests = state.database.get_deletion_requests().await?;
-    
+    let flag = |name: &str| params.get(name).is_some_and(|v| v == "true");
+    let (start_ns, end_ns) = query_bounds(&params)?;
+    if start_ns.is_none() && end_ns.is_none() && !flag("all") {
+        return Err(TamsError::Validation(
+            "Give a timerange, or start and end, of the segments to delete; all=true deletes every segment".to_string(),
+        ));
+    }
+
+    let flow = state.database.get_flow_required(&flow_id).await?;
+    let deletion = state
+        .database
+        .delete_flow_segments_by_timerange(&flow_id, start_ns, end_ns, flag("strict"))
+        .await?;
+
+    if let Some(timerange) = deletion.deleted_timerange.clone() {
+        state.webhook_manager.send_flow_notification(flow.owner.as_deref(), EventNotification {
+            event_timestamp: state.clock.now(),
+            event_type: EventType::FlowsSegmentsDeleted,
+            event: SegmentsDeletedEvent { flow_id, timerange },
+        }).await;
+    }
+
+    let deleted: u64 = deletion.counts.iter().map(|(_, count)| count).sum();
     Ok(Json(json!({
-        "deletion_requests": requests
+        "flow_id": flow_id,
+        "deleted": deleted,
+        "available_timerange": deletion.available_timerange
     })))
 }
 
-pub async fn get_deletion_request(
-    Path(id): Path<String>,
+pub async fn delete_flow_segments_by_object(
+    Path(flow_id): Path<Uuid>,
     State(state): State<AppState>,
-) -> Result<Json<DeletionRequest>, TamsError> {
-    let request = state.database.get_deletion_request_required(&id).await?;
-    Ok(Json(request))
+    Json(payload): Json<DeleteSegmentsByObjectRequest>,
+) -> Result<Json<Value>, TamsError> {
+    if payload.object_ids.is_empty() {
+        return Err(TamsError::Validation("object_ids must not be empty".to_string()));
+    }
+    let flow = state.database.get_flow_required(&flow_id).await?;
+
+    let deletion = state
+        .database
+        .delete_flow_segments_by_objects(&flow_id, &payload.object_ids)
+        .await?;
+
+    if let Some(timerange) = deletion.deleted_timerange.clone() {
+        state.webhook_manager.send_flow_notification(flow.owner.as_deref(), EventNotification {
+            event_timestamp: state.clock.now(),
+            event_type: EventType::FlowsSegmentsDeleted,
+            event: SegmentsDeletedEvent { flow_id, timerange },
+        }).await;
+    }
+
+    let objects: Vec<Value> = deletion
+        .counts
+        .iter()
+        .map(|(object_id, deleted)| json!({ "object_id": object_id, "segments_deleted": deleted }))
+        .collect();
+
+    Ok(Json(json!({
+        "flow_id": flow_id,
+        "objects": objects,
+        "available_timerange": deletion.available_timerange
+    })))
 }
 
-// Test page endpoint
-pub async fn get_test_page() -> Result<Html<String>, TamsError> {
-    let html = include_str!("../test.html");
-    Ok(Html(html.to_string()))
-} 
\ No newline at end of file
+/// Most objects one get_urls refresh may name
+const GET_URLS_REFRESH_MAX_OBJECTS: usize = 100;
+
+/// Generate fresh get_urls for objects of a flow's segments, for clients whose
+/// embedded URLs have expired. `accept_get_urls` limits the URLs to a comma
+/// separated list of labels, as it does for listings.
+pub async fn refresh_segment_get_urls(
+    Path(flow_id): Path<Uuid>,
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+    Json(payload): Json<RefreshGetUrlsRequest>,
+) -> Result<Json<Value>, TamsError> {
+    let mut object_ids = payload.object_ids;
+    object_ids.extend(payload.object_id);
+    let mut seen = HashSet::new();
+    object_ids.retain(|id| seen.insert(id.clone()));
+    if object_ids.is_empty() {
+        return Err(TamsError::Validation("object_id or object_ids is required".to_string()));
+    }
+    if object_ids.len() > GET_URLS_REFRESH_MAX_OBJECTS {
+        return Err(TamsError::Validation(format!(
+            "At most {} objects can be refreshed at once",
+            GET_URLS_REFRESH_MAX_OBJECTS
+        )));
+    }
+    let template = GetUrlTemplate::for_flow(&state.database.get_flow_required(&flow_id).await?)?;
+
+    // Only objects the flow's segments point at, so this can't mint URLs for arbitrary objects
+    let referenced = state.database.get_flow_segment_object_ids(&flow_id, &object_ids).await?;
+    if let Some(unreferenced) = object_ids.iter().find(|id| !referenced.contains(*id)) {
+        return Err(TamsError::Forbidden(format!(
+            "Object {} is not referenced by any segment of flow {}",
+            unreferenced, flow_id
+        )));
+    }
+
+    let objects = objects_with_get_urls(&state, &flow_id, template.as_ref(), object_ids, &params).await?;
+    Ok(Json(json!({
+        "flow_id": flow_id,
+        "objects": objects
+    })))
+}
+
+/// Fresh get_urls for objects of a flow's segments, limited to the labels in
+/// an `accept_get_urls` parameter when there is one
+async fn objects_with_get_urls(
+    state: &AppState,
+    flow_id: &Uuid,
+    template: Option<&GetUrlTemplate>,
+    object_ids: Vec<String>,
+    params: &HashMap<String, String>,
+) -> TamsResult<Vec<ObjectGetUrls>> {
+    let accepted: Option<Vec<&str>> = params
+        .get("accept_get_urls")
+        .map(|labels| labels.split(',').map(str::trim).filter(|l| !l.is_empty()).collect());
+    let mut objects = Vec::with_capacity(object_ids.len());
+    for object_id in object_ids {
+        let mut get_urls = match template {
+            // Templated URLs don't expire, and the bytes aren't ours to check for
+            Some(template) => vec![GetUrl {
+                url: template.expand(&object_id, flow_id),
+                label: Some(EXTERNAL_GET_URL_LABEL.to_string()),
+                expires_at: None,
+            }],
+            None => {
+                let media_object = state.database.get_media_object(&object_id).await?;
+                let context = ObjectContext::for_object(media_object.as_ref(), Some(*flow_id));
+                state.storage.generate_get_urls(&object_id, None, &context).await?
+            }
+        };
+        if let Some(accepted) = &accepted {
+            get_urls.retain(|url| url.label.as_deref().is_some_and(|label| accepted.contains(&label)));
+        }
+        objects.push(ObjectGetUrls { object_id, get_urls });
+    }
+    Ok(objects)
+}
+
+// Storage stats endpoint
+pub async fn get_storage_stats(
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+) -> Result<Response, TamsError> {
+    let refresh = params.get("refresh").map(|v| v == "true").unwrap_or(false);
+    let cached = state.storage.cached_storage_stats().await;
+
+    // Stats come from the background cache; computing them inline can take minutes
+    if refresh || cached.is_none() {
+        let started = state.storage.spawn_storage_stats_refresh();
+        return Ok((
+            StatusCode::ACCEPTED,
+            Json(json!({
+                "refreshing": true,
+                "refresh_started": started,
+                "stats": cached
+            })),
+        )
+            .into_response());
+    }
+
+    Ok(Json(json!({
+        "refreshing": false,
+        "stats": cached
+    }))
+    .into_response())
+}
+
+// Maintenance endpoints
+/// Rebuild the segment stats of every flow whose stored stats have drifted.
+/// Like the other repairs, it runs with notifications suppressed.
+pub async fn reconcile_flow_stats(State(state): State<AppState>) -> Result<Json<FlowStatsReconcileReport>, TamsError> {
+    let repair = maintenance::reconcile_flow_stats(&state.database, maintenance::FLOW_STATS_BATCH_SIZE);
+    let report = state.webhook_manager.suppress_events("reconcile_flow_stats", repair).await?;
+    Ok(Json(report))
+}
+
+/// Normalize stored flow containers and codecs against the configured vocabularies
+pub async fn normalize_vocabularies(State(state): State<AppState>) -> Result<Json<VocabularyRepairReport>, TamsError> {
+    let repair =
+        maintenance::normalize_flow_vocabularies(&state.database, &state.config.validation, maintenance::VOCABULARY_BATCH_SIZE);
+    let report = state.webhook_manager.suppress_events("normalize_vocabularies", repair).await?;
+    Ok(Json(report))
+}
+
+pub async fn recompute_timeranges(State(state): State<AppState>) -> Result<Json<TimeRangeRecomputeReport>, TamsError> {
+    let repair = maintenance::recompute_available_timeranges(&state.database, maintenance::RECOMPUTE_BATCH_SIZE);
+    let report = state.webhook_manager.suppress_events("recompute_timeranges", repair).await?;
+    tracing::info!(
+        "Recomputed available_timerange: {} flows checked, {} corrected",
+        report.flows_checked,
+        report.flows_corrected
+    );
+    Ok(Json(report))
+}
+
+// Ingest pause endpoints
+pub async fn pause_flow_ingest(
+    Path(flow_id): Path<Uuid>,
+    State(state): State<AppState>,
+) -> Result<Json<IngestPauseState>, TamsError> {
+    set_flow_ingest_paused(&state, flow_id, true).await
+}
+
+pub async fn resume_flow_ingest(
+    Path(flow_id): Path<Uuid>,
+    State(state): State<AppState>,
+) -> Result<Json<IngestPauseState>, TamsError> {
+    set_flow_ingest_paused(&state, flow_id, false).await
+}
+
+async fn set_flow_ingest_paused(state: &AppState, flow_id: Uuid, paused: bool) -> Result<Json<IngestPauseState>, TamsError> {
+    state.database.get_flow_required(&flow_id).await?;
+    state.ingest.set_flow_paused(&state.database, &flow_id, paused).await?;
+    tracing::info!("Ingest {} for flow {}", if paused { "paused" } else { "resumed" }, flow_id);
+    Ok(Json(state.ingest.state().await))
+}
+
+pub async fn get_ingest_pause_state(State(state): State<AppState>) -> Result<Json<IngestPauseState>, TamsError> {
+    Ok(Json(state.ingest.state().await))
+}
+
+/// Progress of the background backfill of numeric segment bounds
+pub async fn get_segment_bounds_progress(State(state): State<AppState>) -> Result<Json<SegmentBoundsProgress>, TamsError> {
+    Ok(Json(maintenance::segment_bounds_progress(&state).await?))
+}
+
+pub async fn set_service_ingest_paused(
+    State(state): State<AppState>,
+    Json(payload): Json<IngestPauseRequest>,
+) -> Result<Json<IngestPauseState>, TamsError> {
+    if state.ingest.set_service_paused(&state.database, payload.paused).await? {
+        tracing::info!("Service-wide ingest {}", if payload.paused { "paused" } else { "resumed" });
+        let event_type = if payload.paused { EventType::ServiceIngestPaused } else { EventType::ServiceIngestResumed };
+        state.webhook_manager.send_notification(EventNotification {
+            event_timestamp: state.clock.now(),
+            event_type,
+            event: IngestPauseEvent { paused: payload.paused },
+        }).await;
+    }
+    Ok(Json(state.ingest.state().await))
+}
+
+/// The deletion worker's pacing settings and current rate
+pub async fn get_deletion_worker_config(
+    State(state): State<AppState>,
+) -> Result<Json<deletion::DeletionWorkerState>, TamsError> {
+    Ok(Json(state.deletion_worker.state(state.clock.now()).await))
+}
+
+/// Change some or all of the deletion worker's pacing settings; the result is
+/// persisted and applies from the worker's next batch
+pub async fn update_deletion_worker_config(
+    State(state): State<AppState>,
+    Json(changes): Json<Value>,
+) -> Result<Json<deletion::DeletionWorkerState>, TamsError> {
+    state.deletion_worker.update(&state.database, &changes).await?;
+    Ok(Json(state.deletion_worker.state(state.clock.now()).await))
+}
+
+/// The config in force, secrets redacted, and when it was last reloaded
+pub async fn get_effective_config(State(state): State<AppState>) -> Result<Json<Value>, TamsError> {
+    Ok(Json(json!({
+        "config": state.reloader.effective_json()?,
+        "reloaded_at": state.reloader.reloaded_at(),
+    })))
+}
+
+/// Re-read the config file and apply its hot-reloadable settings, as SIGHUP does
+pub async fn reload_config(State(state): State<AppState>) -> Result<Json<ConfigReload>, TamsError> {
+    Ok(Json(state.reloader.reload(&state.jobs, state.clock.now())?))
+}
+
+// Storage endpoints
+
+/// The objects the flow's segments already reference, in timeline order, with
+/// download URLs, paged by `limit` and the `page` key of the previous page.
+/// New objects are allocated with POST.
+pub async fn list_flow_storage(
+    Path(flow_id): Path<Uuid>,
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+) -> Result<Json<Value>, TamsError> {
+    let config = state.reloader.pagination();
+    let limit = params
+        .get("limit")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(config.default_limit)
+        .clamp(1, config.max_limit.max(1));
+    let template = GetUrlTemplate::for_flow(&state.database.get_flow_required(&flow_id).await?)?;
+    let (object_ids, next_key) = state
+        .database
+        .list_flow_object_ids(&flow_id, limit, params.get("page").map(String::as_str))
+        .await?;
+    let objects = objects_with_get_urls(&state, &flow_id, template.as_ref(), object_ids, &params).await?;
+    let total = if include_total(&params) { Some(state.database.count_flow_object_ids(&flow_id).await?) } else { None };
+    let mut pagination = PaginationInfo::new(limit, total, 0);
+    pagination.next_key = next_key;
+
+    Ok(Json(json!({
+        "flow_id": flow_id,
+        "objects": objects,
+        "pagination": pagination
+    })))
+}
+
+pub async fn allocate_storage(
+    Path(flow_id): Path<Uuid>,
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+    warnings: Warnings,
+    payload: Option<Json<FlowStorageRequest>>,
+) -> Result<Json<FlowStorage>, TamsError> {
+    state.ingest.check(Some(&flow_id)).await?;
+    check_flow_accepts_uploads(&state, &flow_id).await?;
+    let flow = state.database.get_flow_required(&flow_id).await?;
+    let payload = payload.map(|Json(payload)| payload);
+
+    // A JSON body takes precedence over the query parameters; limit defaults to 1
+    let limit = payload
+        .as_ref()
+        .and_then(|p| p.limit)
+        .or_else(|| params.get("limit").and_then(|l| l.parse().ok()))
+        .unwrap_or(1);
+    let object_ids = match payload.and_then(|p| p.object_ids) {
+        Some(object_ids) => Some(object_ids),
+        None => params
+            .get("object_ids")
+            .map(|object_ids_str| object_ids_str.split(',').map(|s| s.to_string()).collect()),
+    };
+    
+    // Use the storage allocate_storage method which creates proper StorageObjects
+    let storage_class = state.storage.storage_class_for(&flow, |class| {
+        warnings.push(
+            ResponseWarning::new(
+                "unknown_storage_class",
+                format!("Storage class '{}' is not configured; objects are placed in the default storage", class),
+            )
+            .with_field(&format!("tags.{}", STORAGE_CLASS_TAG)),
+        )
+    });
+    let context = ObjectContext {
+        flow_id: Some(flow_id),
+        storage_class,
+        format: Some(flow.format.clone()),
+    };
+    let candidates = state.storage.allocate_storage(limit, object_ids, &context).await?;
+
+    // Record each allocation; a caller that loses the race for an explicit id (or
+    // asks for one already uploaded) gets the existing allocation back, flagged.
+    // The deletion check shares their transaction, so no deletion request can
+    // start between the check and the allocations being recorded.
+    let mut tx = state.database.begin_transaction().await?;
+    let requests = tx.get_deletion_requests_for_flow(&flow_id).await?;
+    deletion::check_no_conflicting_deletion(&requests.items, None, state.config.deletion.writes_during_deletion)?;
+    let mut objects = Vec::with_capacity(candidates.len());
+    for candidate in candidates {
+        objects.push(tx.claim_storage_allocation(Some(&flow_id), &candidate, state.clock.now()).await?);
+    }
+    tx.commit().await?;
+    for allocation in &mut objects {
+        if !allocation.already_allocated && state.storage.object_exists(&allocation.object_id, &context).await {
+            allocation.already_allocated = true;
+        }
+    }
+    
+    let expires_at = objects.iter().filter_map(|object| object.expires_at).min();
+    Ok(Json(FlowStorage { expires_at, objects }))
+}
+
+/// Whether the flow's media lives in an external store named by its `get_url_template` tag
+async fn has_external_media(state: &AppState, flow_id: &Uuid) -> TamsResult<bool> {
+    let flow = state.database.get_flow(flow_id).await?;
+    Ok(flow.is_some_and(|flow| flow.tags.contains_key(GET_URL_TEMPLATE_TAG)))
+}
+
+/// Refuse allocations and uploads for flows whose bytes we don't own
+async fn check_flow_accepts_uploads(state: &AppState, flow_id: &Uuid) -> TamsResult<()> {
+    if has_external_media(state, flow_id).await? {
+        return Err(TamsError::Forbidden(format!(
+            "Flow {} serves its media from an external store ({} tag); uploads are not accepted",
+            flow_id, GET_URL_TEMPLATE_TAG
+        )));
+    }
+    Ok(())
+}
+
+// Media object endpoints
+pub async fn get_media_object(
+    Path(object_id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Response, TamsError> {
+    let media_object = state.database.get_media_object_required(&object_id).await?;
+    Ok((
+        [(header::CACHE_CONTROL, state.config.caching.metadata_cache_control.clone())],
+        Json(media_object),
+    )
+        .into_response())
+}
+
+pub async fn download_media_object(
+    Path(object_id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+    head: Head,
+    headers: HeaderMap,
+) -> Result<Response, TamsError> {
+    let media_object = state.database.get_media_object(&object_id).await?;
+    let context = ObjectContext::for_object(media_object.as_ref(), None);
+
+    // Stores fronted by a CDN hand the client off rather than proxying bytes;
+    // any Range header is left for the CDN to honour. HEAD always answers
+    // locally, so probes work in redirect mode too.
+    if let Some(store) = state.storage.download_redirect_store().filter(|_| !head.is_head()) {
+        let force_proxy = params.get("proxy").map(|v| v == "true").unwrap_or(false);
+        if force_proxy && !store.allow_proxy_override {
+            return Err(TamsError::Forbidden(format!(
+                "Media store '{}' does not allow proxied downloads",
+                store.name
+            )));
+        }
+        if !force_proxy {
+            if !state.storage.object_exists(&object_id, &context).await {
+                return Err(TamsError::ObjectNotFound { object_id });
+            }
+            let location = state.storage.redirect_url(store, &object_id, &context)?;
+            return Ok((
+                StatusCode::FOUND,
+                [
+                    (header::LOCATION, location),
+                    (header::CACHE_CONTROL, state.config.caching.redirect_cache_control.clone()),
+                ],
+            )
+                .into_response());
+        }
+    }
+
+    // Validated and probed from the file's metadata; only a GET that sends
+    // the body reads the object
+    let content_hash = media_object.as_ref().and_then(|o| o.content_hash.as_deref());
+    let (size, etag) = state.storage.get_object_etag(&object_id, &context, content_hash).await?;
+    let cache_control = state.config.caching.object_cache_control.clone();
+
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(',').any(|candidate| candidate.trim() == etag || candidate.trim() == "*"))
+        .unwrap_or(false);
+    if not_modified {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [(header::ETAG, etag), (header::CACHE_CONTROL, cache_control)],
+        )
+            .into_response());
+    }
+
+    let content_type = media_object
+        .and_then(|o| o.mime_type)
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    let headers = [
+        (header::CONTENT_TYPE, content_type),
+        (header::ETAG, etag),
+        (header::CACHE_CONTROL, cache_control),
+    ];
+    if head.is_head() {
+        return Ok((headers, [(header::CONTENT_LENGTH, size.to_string())]).into_response());
+    }
+
+    let data = state.storage.get_object(&object_id, &context).await?;
+    Ok((headers, data).into_response())
+}
+
+/// Header prefix for user metadata supplied with an object upload
+const OBJECT_META_HEADER_PREFIX: &str = "x-object-meta-";
+
+pub async fn put_media_object(
+    Path(object_id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: axum::body::Body,
+) -> Result<StatusCode, TamsError> {
+    use futures_util::TryStreamExt;
+
+    // The allocation's put_url carries the flow the object was allocated for
+    let flow_id = params.get("flow_id").map(|id| Uuid::parse_str(id)).transpose()?;
+    state.ingest.check(flow_id.as_ref()).await?;
+    if let Some(flow_id) = &flow_id {
+        check_flow_accepts_uploads(&state, flow_id).await?;
+    }
+    // Bytes go where the allocation placed them, or failing that where the flow's tag
+    // says, under the base path for the flow's format
+    let flow = match &flow_id {
+        Some(flow_id) => state.database.get_flow(flow_id).await?,
+        None => None,
+    };
+    let storage_class = match (state.database.get_storage_allocation(&object_id).await?, &flow) {
+        (Some(allocation), _) => allocation.storage_class,
+        (None, Some(flow)) => state.storage.storage_class_for(flow, |_| {}),
+        (None, None) => None,
+    };
+    let format = flow.map(|flow| flow.format);
+    let context = ObjectContext {
+        flow_id,
+        storage_class: storage_class.clone(),
+        format: format.clone(),
+    };
+    let declared_len = declared_content_length(&headers)?;
+
+    // Stream the uploaded data into the store, counting it against the declared
+    // length; the policy decides whether an existing object may be written again
+    let body = body.into_data_stream().map_err(std::io::Error::other);
+    let (outcome, size, content_hash) = state
+        .storage
+        .store_object_stream(&object_id, headers.get(header::CONTENT_ENCODING), declared_len, body, &context)
+        .await?;
+
+    // Create or update media object record in database
+    let media_object = MediaObject {
+        object_id: object_id.clone(),
+        size_bytes: Some(size),
+        mime_type: None, // Could be inferred from content-type header
+        flow_references: flow_id.into_iter().map(FlowReference::new).collect(),
+        created_at: state.clock.now(),
+        metadata: object_metadata_from_headers(&headers)?,
+        storage_class,
+        format,
+        content_hash: Some(content_hash),
+    };
+    state.database.record_uploaded_object(&media_object).await?;
+
+    Ok(match outcome {
+        StoreOutcome::Created => StatusCode::CREATED,
+        StoreOutcome::Unchanged | StoreOutcome::Overwritten => StatusCode::OK,
+    })
+}
+
+/// An upload's `Content-Length`, which its body is held to as it arrives so
+/// that one cut off, e.g. by a proxy, is refused rather than stored as
+/// complete. The length is that of the body as sent, before any
+/// `Content-Encoding` is undone.
+fn declared_content_length(headers: &HeaderMap) -> TamsResult<Option<u64>> {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .map(|declared| {
+            declared
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .ok_or_else(|| TamsError::BadRequest("Invalid Content-Length header".to_string()))
+        })
+        .transpose()
+}
+
+/// Collect `X-Object-Meta-<key>` headers; keys are lowercased as HTTP headers are case-insensitive
+fn object_metadata_from_headers(headers: &HeaderMap) -> TamsResult<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+    for (name, value) in headers {
+        let Some(key) = name.as_str().strip_prefix(OBJECT_META_HEADER_PREFIX) else {
+            continue;
+        };
+        if key.is_empty() {
+            return Err(TamsError::Validation(format!("{} header needs a key suffix", OBJECT_META_HEADER_PREFIX)));
+        }
+        let value = value
+            .to_str()
+            .map_err(|_| TamsError::Validation(format!("Metadata value for '{}' is not valid text", key)))?;
+        metadata.insert(key.to_string(), value.to_string());
+    }
+    Ok(metadata)
+}
+
+pub async fn update_media_object_metadata(
+    Path(object_id): Path<String>,
+    State(state): State<AppState>,
+    Json(entries): Json<HashMap<String, String>>,
+) -> Result<Json<MediaObject>, TamsError> {
+    if entries.keys().any(|k| k.is_empty()) {
+        return Err(TamsError::Validation("Metadata keys must not be empty".to_string()));
+    }
+    if state.database.merge_media_object_metadata(&object_id, &entries).await? == 0 {
+        return Err(TamsError::ObjectNotFound { object_id });
+    }
+    Ok(Json(state.database.get_media_object_required(&object_id).await?))
+}
+
+pub async fn head_media_object(
+    State(state): State<AppState>,
+    Path(object_id): Path<String>,
+) -> TamsResult<StatusCode> {
+    let _media_object = state.database.get_media_object_required(&object_id).await?;
+    Ok(StatusCode::OK)
+}
+
+// Webhook endpoints
+pub async fn list_webhooks(
+    State(state): State<AppState>,
+) -> Result<Json<Value>, TamsError> {
+    let webhooks = state.database.get_webhooks_list().await?;
+    
+    Ok(Json(json!({
+        "webhooks": webhooks
+    })))
+}
+
+/// Under `auth.enforce_ownership` a webhook registered by a principal other
+/// than an admin only hears of events about that principal's flows
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+    Json(payload): Json<WebhookRequest>,
+) -> Result<Json<Webhook>, TamsError> {
+    let events = validation::normalize_webhook_events(&payload.events)?;
+    let sealed_key = state.webhook_manager.seal_key(&payload.api_key_value)?;
+    let webhook = Webhook {
+        id: Some(Uuid::new_v4()),
+        url: payload.url,
+        api_key_name: payload.api_key_name,
+        api_key_value: Some(sealed_key.clone()),
+        events,
+    };
+    
+    let owner = ownership::listing_owner(&state.config.auth, principal.as_deref()).map(str::to_string);
+    state.database.create_webhook(&webhook, owner.as_deref()).await?;
+    state.webhook_manager.upsert_stored_webhook(StoredWebhook {
+        webhook: Webhook { api_key_value: None, ..webhook.clone() },
+        api_key_value: sealed_key,
+        previous_key: None,
+        owner,
+    }).await;
+    
+    // Return webhook without the API key value for security
+    let response_webhook = Webhook {
+        id: webhook.id,
+        url: webhook.url,
+        api_key_name: webhook.api_key_name,
+        api_key_value: None,
+        events: webhook.events,
+    };
+    
+    Ok(Json(response_webhook))
+}
+
+/// Only the principal that registered a webhook under `auth.enforce_ownership`,
+/// or an admin, may rotate its secret; to others it doesn't exist
+pub async fn rotate_webhook_secret(
+    Path(webhook_id): Path<Uuid>,
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+    Json(payload): Json<WebhookSecretRequest>,
+) -> Result<StatusCode, TamsError> {
+    if payload.api_key_value.is_empty() {
+        return Err(TamsError::Validation("api_key_value must not be empty".to_string()));
+    }
+
+    let overlap = chrono::Duration::seconds(state.config.webhooks.secret_rotation_overlap_seconds as i64);
+    let previous_expires_at = state.clock.now() + overlap;
+    let sealed_key = state.webhook_manager.seal_key(&payload.api_key_value)?;
+    let owned_by = ownership::listing_owner(&state.config.auth, principal.as_deref());
+    let stored = state
+        .database
+        .rotate_webhook_secret(&webhook_id, &sealed_key, previous_expires_at, owned_by)
+        .await?
+        .ok_or_else(|| TamsError::NotFound(format!("Webhook not found: {}", webhook_id)))?;
+
+    tracing::info!(
+        target: "audit",
+        webhook_id = %webhook_id,
+        url = %stored.webhook.url,
+        previous_key_expires_at = %time_utils::format_rfc3339(&previous_expires_at),
+        "webhook secret rotated"
+    );
+    state.webhook_manager.upsert_stored_webhook(stored).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    Path(webhook_url): Path<String>,
+) -> TamsResult<StatusCode> {
+    // TODO: Implement delete_webhook in database
+    // state.database.delete_webhook(&webhook_url).await?;
+    // state.webhook_manager.remove_webhook(&webhook_url).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Flow delete request endpoints
+pub async fn request_flow_deletion(
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+    Json(payload): Json<CreateDeletionRequest>,
+) -> Result<Json<DeletionRequest>, TamsError> {
+    let flow_id = payload.flow_id;
+    let flow = state.database.get_flow_required(&flow_id).await?;
+    if ownership::hidden_from(&state.config.auth, principal.as_deref(), flow.owner.as_deref()) {
+        return Err(TamsError::NotFound("Flow not found".to_string()));
+    }
+    let request_id = Uuid::new_v4().to_string();
+    // Stored in the canonical form update_deletion_request also writes
+    let timerange = payload
+        .timerange
+        .as_ref()
+        .map(deletion::parse_requested_timerange)
+        .transpose()
+        .map_err(|e| TamsError::Validation(format!("Invalid deletion timerange: {}", e)))?
+        .flatten();
+    let timerange = match timerange {
+        Some(timerange) => Some(serde_json::to_string(&timerange)?),
+        None => None,
+    };
+
+    let request = DeletionRequest {
+        id: request_id,
+        flow_id,
+        timerange,
+        status: DeletionRequest::PENDING.to_string(),
+        progress: None,
+        created_at: state.clock.now(),
+        updated_at: state.clock.now(),
+    };
+
+    // The worker picks the request up from the job queue
+    let job = Job::new(
+        deletion::FLOW_DELETION_JOB,
+        Some(&request.id),
+        serde_json::to_value(deletion::FlowDeletionJob { deletion_request_id: request.id.clone() })?,
+        state.clock.now(),
+    );
+    state.database.create_deletion_request_with_job(&request, &job).await?;
+    
+    Ok(Json(request))
+}
+
+/// Answer 404 for a deletion request of another principal's flow under
+/// `auth.enforce_ownership`, as for the flow itself
+async fn check_deletion_request_visible(state: &AppState, id: &str, principal: Option<&Principal>) -> TamsResult<()> {
+    let Some(owner) = ownership::listing_owner(&state.config.auth, principal) else {
+        return Ok(());
+    };
+    if state.database.get_deletion_request_owner(id).await?.as_deref() != Some(owner) {
+        return Err(TamsError::NotFound("Deletion request not found".to_string()));
+    }
+    Ok(())
+}
+
+pub async fn update_deletion_request(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+    Json(payload): Json<UpdateDeletionRequest>,
+) -> Result<Json<DeletionRequest>, TamsError> {
+    check_deletion_request_visible(&state, &id, principal.as_deref()).await?;
+    let timerange = deletion::parse_requested_timerange(&payload.timerange)
+        .map_err(|e| TamsError::Validation(format!("Invalid deletion timerange: {}", e)))?
+        .ok_or_else(|| TamsError::Validation("Invalid deletion timerange: it can be amended but not removed".to_string()))?;
+    let timerange = serde_json::to_string(&timerange)?;
+
+    // The status check is part of the UPDATE so a worker picking the request up
+    // concurrently can't have its window changed underneath it
+    if state.database.update_pending_deletion_request_timerange(&id, &timerange).await? == 0 {
+        let existing = state.database.get_deletion_request_required(&id).await?;
+        return Err(TamsError::Conflict(format!(
+            "Deletion request {} is {}; only pending requests can be amended",
+            id, existing.status
+        )));
+    }
+
+    Ok(Json(state.database.get_deletion_request_required(&id).await?))
+}
+
+pub async fn list_deletion_requests(
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+) -> Result<Json<Value>, TamsError> {
+    let owned_by = ownership::listing_owner(&state.config.auth, principal.as_deref());
+    let requests = state.database.get_deletion_requests(owned_by).await?;
+    
+    Ok(Json(json!({
+        "deletion_requests": requests.items,
+        "pagination": {
+            "count": requests.items.len(),
+            "skipped_corrupt": requests.skipped_corrupt
+        }
+    })))
+}
+
+pub async fn get_deletion_request(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+) -> Result<Json<DeletionRequest>, TamsError> {
+    check_deletion_request_visible(&state, &id, principal.as_deref()).await?;
+    let request = state.database.get_deletion_request_required(&id).await?;
+    Ok(Json(request))
+}
+
+/// Background jobs with their target, attempts and next run time.
+///
+/// `type` (or `kind`) restricts the listing to one job type and `status` to a
+/// comma separated set of statuses. Without `status` the queued, running and
+/// failed jobs are listed; `status=all` includes completed ones too.
+pub async fn list_jobs(
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+) -> Result<Json<Value>, TamsError> {
+    let pagination = state.reloader.pagination();
+    let limit = params
+        .get("limit")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(pagination.default_limit)
+        .min(pagination.max_limit);
+    let kind = params.get("type").or_else(|| params.get("kind")).map(String::as_str);
+
+    const KNOWN: [&str; 4] = [Job::PENDING, Job::RUNNING, Job::FAILED, Job::COMPLETED];
+    let statuses: Option<Vec<&str>> = match params.get("status").map(String::as_str) {
+        Some("all") => None,
+        Some(requested) => {
+            let statuses: Vec<&str> = requested.split(',').map(str::trim).collect();
+            if let Some(unknown) = statuses.iter().find(|s| !KNOWN.contains(s)) {
+                return Err(TamsError::Validation(format!(
+                    "Unknown job status '{}'; expected 'all' or any of: {}",
+                    unknown,
+                    KNOWN.join(", ")
+                )));
+            }
+            Some(statuses)
+        }
+        None => Some(vec![Job::PENDING, Job::RUNNING, Job::FAILED]),
+    };
+
+    let jobs = state.jobs.list(kind, statuses.as_deref(), limit).await?;
+    let counts = state.database.count_jobs_by_status(kind).await?;
+
+    Ok(Json(json!({
+        "jobs": jobs,
+        "counts": counts,
+        "pagination": {
+            "count": jobs.len(),
+            "limit": limit
+        }
+    })))
+}
+
+// Test page endpoint
+pub async fn get_test_page() -> Result<Html<String>, TamsError> {
+    let html = include_str!("../test.html");
+    Ok(Html(html.to_string()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::clock::{system_clock, FakeClock};
+    use crate::config::{ActiveDeletionRequestPolicy, DownloadMode};
+    use tempfile::TempDir;
+
+    pub(crate) async fn create_test_state() -> (AppState, TempDir) {
+        create_test_state_with(|_| {}).await
+    }
+
+    pub(crate) async fn create_test_state_with(configure: impl FnOnce(&mut AppConfig)) -> (AppState, TempDir) {
+        create_test_state_with_clock(configure, system_clock()).await
+    }
+
+    /// Typed listing parameters parsed from `(name, value)` pairs, as the extractor would
+    fn listing_query<T: serde::de::DeserializeOwned>(pairs: &[(&str, &str)]) -> T {
+        let query: Vec<String> = pairs.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        let uri: axum::http::Uri = format!("/?{}", query.join("&")).parse().unwrap();
+        Query::try_from_uri(&uri).unwrap().0
+    }
+
+    pub(crate) async fn create_test_state_with_clock(
+        configure: impl FnOnce(&mut AppConfig),
+        clock: SharedClock,
+    ) -> (AppState, TempDir) {
+        let temp_dir = TempDir::new().unwrap();
+
+        let mut config = AppConfig::from_file("config").unwrap();
+        config.database.url = format!("sqlite:{}", temp_dir.path().join("tams.db").display());
+        config.media_storage.base_path = temp_dir.path().join("objects");
+        config.media_storage.temp_path = temp_dir.path().join("temp");
+        configure(&mut config);
+
+        let database = Database::new(&config.database).await.unwrap().with_clock(clock.clone());
+        database.migrate().await.unwrap();
+        let storage = MediaStorage::new(
+            config.media_storage.clone(),
+            config.service.api_url(""),
+        )
+        .unwrap()
+        .with_media_stores(&config.service)
+        .with_clock(clock.clone());
+        storage.ensure_directories().await.unwrap();
+
+        let ingest = IngestControl::load(&database).await.unwrap();
+        let jobs = JobQueue::new(database.clone(), config.jobs.clone()).with_clock(clock.clone());
+        let deletion_worker = deletion::DeletionWorkerControl::load(&database, config.deletion.worker.clone())
+            .await
+            .unwrap();
+
+        let reloader = ConfigReloader::new("config", config.clone(), None);
+        let webhook_manager =
+            WebhookManager::new().with_event_buffer(config.events.buffer_events).with_clock(clock.clone());
+        let state = Arc::new(AppStateInner {
+            config,
+            database,
+            storage: Arc::new(storage),
+            webhook_manager: Arc::new(webhook_manager),
+            ingest: Arc::new(ingest),
+            jobs,
+            deletion_worker: Arc::new(deletion_worker),
+            reloader: Arc::new(reloader),
+            clock,
+            summary: Default::default(),
+        });
+        (state, temp_dir)
+    }
+
+    /// Call `update_flow` the way the router does, settling its transaction by the outcome
+    async fn call_update_flow(
+        state: &AppState,
+        id: Uuid,
+        params: HashMap<String, String>,
+        payload: UpdateFlowRequest,
+    ) -> Result<Json<Flow>, TamsError> {
+        call_update_flow_as(state, id, params, payload, None).await
+    }
+
+    async fn call_update_flow_as(
+        state: &AppState,
+        id: Uuid,
+        params: HashMap<String, String>,
+        payload: UpdateFlowRequest,
+        principal: Option<Principal>,
+    ) -> Result<Json<Flow>, TamsError> {
+        let slot = TransactionSlot::default();
+        let principal = principal.map(Extension);
+        let warnings = Warnings::default();
+        let result =
+            update_flow(Path(id), Query(params), State(state.clone()), warnings, principal, Extension(slot.clone()), Json(payload))
+                .await;
+        let status = if result.is_ok() { StatusCode::OK } else { StatusCode::CONFLICT };
+        slot.finish(status).await?;
+        result
+    }
+
+    #[tokio::test]
+    async fn test_object_upload_status_follows_overwrite_policy() {
+        use crate::config::ObjectOverwritePolicy;
+
+        for (policy, same_again, different) in [
+            (ObjectOverwritePolicy::IdenticalOnly, StatusCode::OK, StatusCode::CONFLICT),
+            (ObjectOverwritePolicy::Allow, StatusCode::OK, StatusCode::OK),
+            (ObjectOverwritePolicy::Fail, StatusCode::CONFLICT, StatusCode::CONFLICT),
+        ] {
+            let (state, _temp_dir) = create_test_state_with(|config| config.media_storage.object_overwrite_policy = policy).await;
+            let put = |bytes: &'static [u8]| {
+                put_media_object(
+                    Path("rewritten".to_string()),
+                    Query(HashMap::new()),
+                    State(state.clone()),
+                    HeaderMap::new(),
+                    axum::body::Body::from(bytes),
+                )
+            };
+            let status = |result: Result<StatusCode, TamsError>| result.unwrap_or_else(|e| e.into_response().status());
+
+            assert_eq!(status(put(b"first").await), StatusCode::CREATED, "{:?}", policy);
+            assert_eq!(status(put(b"first").await), same_again, "{:?}", policy);
+            assert_eq!(status(put(b"second!").await), different, "{:?}", policy);
+
+            let expected: &[u8] = if policy == ObjectOverwritePolicy::Allow { b"second!" } else { b"first" };
+            let stored = state.storage.get_object("rewritten", &ObjectContext::default()).await.unwrap();
+            assert_eq!(stored, expected, "{:?}", policy);
+            let record = state.database.get_media_object_required("rewritten").await.unwrap();
+            assert_eq!(record.size_bytes, Some(expected.len() as u64), "{:?}", policy);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload_not_matching_content_length_is_refused() {
+        use futures_util::stream;
+
+        let (state, _temp_dir) = create_test_state().await;
+        // The body arrives in chunks and, unless `broken`, ends cleanly after them
+        let put = |object_id: &str, content_length: &str, chunks: &[&'static [u8]], broken: bool| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_LENGTH, content_length.parse().unwrap());
+            let mut chunks: Vec<std::io::Result<axum::body::Bytes>> =
+                chunks.iter().map(|chunk| Ok(axum::body::Bytes::from_static(chunk))).collect();
+            if broken {
+                chunks.push(Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "connection reset")));
+            }
+            let body = axum::body::Body::from_stream(stream::iter(chunks));
+            put_media_object(Path(object_id.to_string()), Query(HashMap::new()), State(state.clone()), headers, body)
+        };
+        let incomplete = |result: Result<StatusCode, TamsError>| match result {
+            Err(TamsError::BadRequest(message)) => message.starts_with("Incomplete upload"),
+            _ => false,
+        };
+
+        assert!(incomplete(put("cut", "10", &[b"ab", b"cd"], true).await));
+        assert!(incomplete(put("short", "10", &[b"ab", b"cd"], false).await));
+        assert!(incomplete(put("long", "3", &[b"ab", b"cd"], false).await));
+        for object_id in ["cut", "short", "long"] {
+            assert!(!state.storage.object_exists(object_id, &ObjectContext::default()).await);
+            assert!(state.database.get_media_object(object_id).await.unwrap().is_none());
+        }
+        let temp_files = std::fs::read_dir(&state.config.media_storage.temp_path).unwrap();
+        assert_eq!(temp_files.count(), 0);
+        assert!(put("bogus", "ten", &[b"abcd"], false).await.is_err());
+
+        assert_eq!(put("whole", "4", &[b"ab", b"cd"], false).await.unwrap(), StatusCode::CREATED);
+        let stored = state.storage.get_object("whole", &ObjectContext::default()).await.unwrap();
+        assert_eq!(stored, b"abcd");
+    }
+
+    #[tokio::test]
+    async fn test_gzip_uploads_are_stored_decompressed() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let gzip = |data: &[u8]| {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data).unwrap();
+            axum::body::Bytes::from(encoder.finish().unwrap())
+        };
+        let (state, _temp_dir) = create_test_state().await;
+        let put = |object_id: &str, encoding: &str, body: axum::body::Bytes| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_ENCODING, encoding.parse().unwrap());
+            put_media_object(Path(object_id.to_string()), Query(HashMap::new()), State(state.clone()), headers, body.into())
+        };
+        let status = |result: Result<StatusCode, TamsError>| result.unwrap_or_else(|e| e.into_response().status());
+
+        let text = br#"{"caption": "small data-format object"}"#.repeat(20);
+        assert_eq!(status(put("gzipped", "gzip", gzip(&text)).await), StatusCode::CREATED);
+        assert_eq!(state.storage.get_object("gzipped", &ObjectContext::default()).await.unwrap(), text);
+        let record = state.database.get_media_object_required("gzipped").await.unwrap();
+        assert_eq!(record.size_bytes, Some(text.len() as u64));
+
+        // A megabyte of zeros compresses about a thousandfold, past the ratio cap
+        let bomb = gzip(&vec![0u8; 1024 * 1024]);
+        assert_eq!(status(put("bomb", "gzip", bomb).await), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let compressed = gzip(&text);
+        let truncated = compressed.slice(..compressed.len() / 2);
+        assert_eq!(status(put("truncated", "gzip", truncated).await), StatusCode::BAD_REQUEST);
+        assert_eq!(status(put("brotli", "br", gzip(&text)).await), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        for object_id in ["bomb", "truncated", "brotli"] {
+            assert!(state.database.get_media_object(object_id).await.unwrap().is_none());
+        }
+        // Failed uploads leave neither a temp file nor an object behind
+        let temp_files = std::fs::read_dir(&state.config.media_storage.temp_path).unwrap();
+        assert_eq!(temp_files.count(), 0);
+        for object_id in ["bomb", "truncated"] {
+            assert!(!state.storage.object_exists(object_id, &ObjectContext::default()).await);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_storage_class_tag_places_objects() {
+        let nearline = TempDir::new().unwrap();
+        let nearline_path = nearline.path().to_path_buf();
+        let (state, _temp_dir) = create_test_state_with(|config| {
+            config.media_storage.storage_classes.insert("nearline".to_string(), nearline_path);
+        })
+        .await;
+        let tagged_flow = |class: &str| {
+            let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+            flow.tags.insert(STORAGE_CLASS_TAG.to_string(), class.to_string());
+            flow
+        };
+        let (flow, misnamed) = (tagged_flow("nearline"), tagged_flow("glacier"));
+        state.database.create_flow(&flow).await.unwrap();
+        state.database.create_flow(&misnamed).await.unwrap();
+
+        let Json(allocated) = allocate_storage(Path(flow.id), Query(HashMap::new()), State(state.clone()), Warnings::default(), None)
+            .await
+            .unwrap();
+        let allocation = &allocated.objects[0];
+        assert_eq!(allocation.storage_class.as_deref(), Some("nearline"));
+        let params = HashMap::from([("flow_id".to_string(), flow.id.to_string())]);
+        let body = axum::body::Body::from("nearline bytes");
+        let status = put_media_object(Path(allocation.object_id.clone()), Query(params), State(state.clone()), HeaderMap::new(), body)
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+
+        let record = state.database.get_media_object_required(&allocation.object_id).await.unwrap();
+        assert_eq!(record.storage_class.as_deref(), Some("nearline"));
+        let placed = walkdir(nearline.path());
+        assert_eq!(placed.len(), 1);
+        assert!(placed[0].ends_with(&allocation.object_id));
+        assert!(!state.storage.object_exists(&allocation.object_id, &ObjectContext::default()).await);
+        let response = download_media_object(
+            Path(allocation.object_id.clone()),
+            Query(HashMap::new()),
+            State(state.clone()),
+            Head::default(),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&bytes[..], b"nearline bytes");
+
+        // An unconfigured class is accepted, with a warning, and uses the default storage
+        let warnings = Warnings::default();
+        let Json(allocated) = allocate_storage(Path(misnamed.id), Query(HashMap::new()), State(state.clone()), warnings.clone(), None)
+            .await
+            .unwrap();
+        assert_eq!(allocated.objects[0].storage_class, None);
+        let warnings = warnings.take();
+        assert_eq!(warnings[0].code, "unknown_storage_class");
+        assert_eq!(warnings[0].field.as_deref(), Some("tags.storage_class"));
+    }
+
+    #[tokio::test]
+    async fn test_move_flow_storage_class_follows_retagged_flow() {
+        let nearline = TempDir::new().unwrap();
+        let nearline_path = nearline.path().to_path_buf();
+        let (state, _temp_dir) = create_test_state_with(|config| {
+            config.media_storage.storage_classes.insert("nearline".to_string(), nearline_path);
+        })
+        .await;
+        let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        flow.tags.insert(STORAGE_CLASS_TAG.to_string(), "nearline".to_string());
+        state.database.create_flow(&flow).await.unwrap();
+        let Json(allocated) = allocate_storage(Path(flow.id), Query(HashMap::new()), State(state.clone()), Warnings::default(), None)
+            .await
+            .unwrap();
+        let object_id = allocated.objects[0].object_id.clone();
+        let params = HashMap::from([("flow_id".to_string(), flow.id.to_string())]);
+        let body = axum::body::Body::from("archived bytes");
+        put_media_object(Path(object_id.clone()), Query(params), State(state.clone()), HeaderMap::new(), body)
+            .await
+            .unwrap();
+        let request = CreateSegmentRequest::new(&object_id, "0:0", "1:0");
+        state.database.add_flow_segment(&request.into_segment(flow.id)).await.unwrap();
+
+        // Untagged, the flow's objects belong in the default storage
+        flow.tags.remove(STORAGE_CLASS_TAG);
+        let mut tx = state.database.begin_transaction().await.unwrap();
+        tx.update_flow(&flow).await.unwrap();
+        tx.commit().await.unwrap();
+        let moved = maintenance::move_flow_storage_class(&state.database, &state.storage, &flow.id).await.unwrap();
+        assert_eq!(moved, 1);
+
+        assert!(walkdir(nearline.path()).is_empty());
+        assert_eq!(state.database.get_media_object_required(&object_id).await.unwrap().storage_class, None);
+        let response = download_media_object(
+            Path(object_id.clone()),
+            Query(HashMap::new()),
+            State(state.clone()),
+            Head::default(),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&bytes[..], b"archived bytes");
+        assert_eq!(maintenance::move_flow_storage_class(&state.database, &state.storage, &flow.id).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_format_base_paths_place_objects() {
+        let audio = TempDir::new().unwrap();
+        let audio_path = audio.path().to_path_buf();
+        let (state, _temp_dir) = create_test_state_with(|config| {
+            config.media_storage.format_base_paths.insert(ContentFormat::Audio, audio_path);
+        })
+        .await;
+
+        let mut placed = Vec::new();
+        for format in [ContentFormat::Audio, ContentFormat::Video] {
+            let flow = Flow::new(Uuid::new_v4(), format.clone());
+            state.database.create_flow(&flow).await.unwrap();
+            let Json(allocated) = allocate_storage(Path(flow.id), Query(HashMap::new()), State(state.clone()), Warnings::default(), None)
+                .await
+                .unwrap();
+            let object_id = allocated.objects[0].object_id.clone();
+            let params = HashMap::from([("flow_id".to_string(), flow.id.to_string())]);
+            let body = axum::body::Body::from(object_id.clone());
+            put_media_object(Path(object_id.clone()), Query(params), State(state.clone()), HeaderMap::new(), body)
+                .await
+                .unwrap();
+            let record = state.database.get_media_object_required(&object_id).await.unwrap();
+            assert_eq!(record.format, Some(format));
+            placed.push(object_id);
+        }
+
+        let audio_files = walkdir(audio.path());
+        assert_eq!(audio_files.len(), 1);
+        assert!(audio_files[0].ends_with(&placed[0]));
+        assert!(!state.storage.object_exists(&placed[0], &ObjectContext::default()).await);
+        assert!(state.storage.object_exists(&placed[1], &ObjectContext::default()).await);
+        for object_id in &placed {
+            let response = download_media_object(Path(object_id.clone()), Query(HashMap::new()), State(state.clone()), Head::default(), HeaderMap::new())
+                .await
+                .unwrap();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(&bytes[..], object_id.as_bytes());
+        }
+    }
+
+    /// Every file under `root`
+    fn walkdir(root: &std::path::Path) -> Vec<std::path::PathBuf> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(root).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files.extend(walkdir(&path));
+            } else {
+                files.push(path);
+            }
+        }
+        files
+    }
+
+    #[tokio::test]
+    async fn test_object_download_caching_headers() {
+        let (state, _temp_dir) = create_test_state().await;
+
+        let body = axum::body::Body::from("immutable bytes");
+        put_media_object(
+            Path("cache-test-object".to_string()),
+            Query(HashMap::new()),
+            State(state.clone()),
+            HeaderMap::new(),
+            body,
+        )
+        .await
+        .unwrap();
+
+        let response = download_media_object(
+            Path("cache-test-object".to_string()),
+            Query(HashMap::new()),
+            State(state.clone()),
+            Head::default(),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            "public, max-age=31536000, immutable"
+        );
+        let etag = response.headers()[header::ETAG].clone();
+        // The hash of the bytes, taken as they were uploaded
+        use sha2::{Digest, Sha256};
+        assert_eq!(etag, format!("\"{}\"", hex::encode(Sha256::digest("immutable bytes"))).as_str());
+
+        let mut conditional = HeaderMap::new();
+        conditional.insert(header::IF_NONE_MATCH, etag);
+        let response = download_media_object(
+            Path("cache-test-object".to_string()),
+            Query(HashMap::new()),
+            State(state.clone()),
+            Head::default(),
+            conditional,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = get_media_object(Path("cache-test-object".to_string()), State(state.clone()))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "public, max-age=60");
+    }
+
+    #[tokio::test]
+    async fn test_object_download_redirect_mode() {
+        let (state, _temp_dir) = create_test_state_with(|config| {
+            config.service.media_stores = vec![MediaStoreConfig {
+                name: "cdn".to_string(),
+                store_type: "http_object_store".to_string(),
+                url_base: "http://127.0.0.1:8080".to_string(),
+                presigned_urls: false,
+                role: MediaStoreRole::ReadWrite,
+                download_mode: DownloadMode::Redirect,
+                redirect_url_base: Some("https://cdn.example.com/media/".to_string()),
+                allow_proxy_override: true,
+            }];
+        })
+        .await;
+        put_media_object(
+            Path("cdn-object".to_string()),
+            Query(HashMap::new()),
+            State(state.clone()),
+            HeaderMap::new(),
+            axum::body::Body::from("cdn bytes"),
+        )
+        .await
+        .unwrap();
+
+        let download = |params: &[(&str, &str)], headers: HeaderMap| {
+            let params = params.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            download_media_object(Path("cdn-object".to_string()), Query(params), State(state.clone()), Head::default(), headers)
+        };
+
+        // Range requests are redirected untouched
+        let mut ranged = HeaderMap::new();
+        ranged.insert(header::RANGE, "bytes=0-2".parse().unwrap());
+        let response = download(&[], ranged).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        assert!(location.starts_with("https://cdn.example.com/media/"));
+        assert!(location.ends_with("/cdn-object"));
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "private, max-age=60");
+
+        let response = download(&[("proxy", "true")], HeaderMap::new()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&bytes[..], b"cdn bytes");
+
+        // HEAD answers locally rather than redirecting
+        let head = download_media_object(
+            Path("cdn-object".to_string()),
+            Query(HashMap::new()),
+            State(state.clone()),
+            Head::new(true),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(head.status(), StatusCode::OK);
+        assert!(head.headers().contains_key(header::ETAG));
+        assert_eq!(head.headers()[header::CONTENT_LENGTH], "9");
+        assert!(axum::body::to_bytes(head.into_body(), usize::MAX).await.unwrap().is_empty());
+
+        let missing = download_media_object(
+            Path("missing-object".to_string()),
+            Query(HashMap::new()),
+            State(state.clone()),
+            Head::default(),
+            HeaderMap::new(),
+        )
+        .await;
+        assert!(matches!(missing, Err(TamsError::ObjectNotFound { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_list_flow_storage_returns_referenced_objects_in_timeline_order() {
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        let context = ObjectContext { flow_id: Some(flow.id), ..Default::default() };
+        // obj-b is reused by a later segment; it is listed once, where it first appears
+        for (object_id, start, end) in [("obj-b", "10:0", "20:0"), ("obj-a", "0:0", "10:0"), ("obj-b", "20:0", "30:0")] {
+            state.storage.store_object(object_id, None, b"bytes".to_vec().into(), &context).await.unwrap();
+            let segment = CreateSegmentRequest::new(object_id, start, end);
+            state.database.add_flow_segment(&segment.into_segment(flow.id)).await.unwrap();
+        }
+        let params = |pairs: &[(&str, &str)]| Query(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect());
+
+        let Json(listed) = list_flow_storage(Path(flow.id), params(&[("include_total", "true")]), State(state.clone()))
+            .await
+            .unwrap();
+        let objects = listed["objects"].as_array().unwrap();
+        let ids: Vec<_> = objects.iter().map(|object| object["object_id"].as_str().unwrap()).collect();
+        assert_eq!(ids, ["obj-a", "obj-b"]);
+        assert!(!objects[0]["get_urls"].as_array().unwrap().is_empty());
+        assert_eq!(listed["pagination"]["count"], 2);
+
+        assert!(listed["pagination"]["next_key"].is_null());
+
+        let Json(first) = list_flow_storage(Path(flow.id), params(&[("limit", "1")]), State(state.clone())).await.unwrap();
+        assert_eq!(first["objects"][0]["object_id"], "obj-a");
+        let page = first["pagination"]["next_key"].as_str().unwrap();
+        let Json(second) =
+            list_flow_storage(Path(flow.id), params(&[("limit", "1"), ("page", page)]), State(state.clone())).await.unwrap();
+        assert_eq!(second["objects"][0]["object_id"], "obj-b");
+        assert!(second["pagination"]["next_key"].is_null());
+        let Json(clamped) = list_flow_storage(Path(flow.id), params(&[("limit", "0")]), State(state.clone())).await.unwrap();
+        assert_eq!(clamped["objects"].as_array().unwrap().len(), 1);
+        let err = list_flow_storage(Path(Uuid::new_v4()), params(&[]), State(state.clone())).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_segment_get_urls() {
+        let (state, _temp_dir) = create_test_state_with(|config| {
+            let primary = config.service.effective_media_stores().remove(0);
+            let mut backup = primary.clone();
+            backup.name = "backup".to_string();
+            backup.url_base = "https://backup.example.com".to_string();
+            backup.role = MediaStoreRole::ReadOnly;
+            config.service.media_stores = vec![primary, backup];
+        })
+        .await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        let other = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&other).await.unwrap();
+        for (flow_id, object_id) in [(flow.id, "obj-mine"), (other.id, "obj-theirs")] {
+            let context = ObjectContext { flow_id: Some(flow_id), ..Default::default() };
+            state.storage.store_object(object_id, None, b"bytes".to_vec().into(), &context).await.unwrap();
+            let segment = CreateSegmentRequest::new(object_id, "0:0", "1:0");
+            state.database.add_flow_segment(&segment.into_segment(flow_id)).await.unwrap();
+        }
+        let refresh = |object_ids: &[&str], params: &[(&str, &str)]| {
+            let params = params.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            let payload = RefreshGetUrlsRequest {
+                object_id: None,
+                object_ids: object_ids.iter().map(|id| id.to_string()).collect(),
+            };
+            refresh_segment_get_urls(Path(flow.id), Query(params), State(state.clone()), Json(payload))
+        };
+
+        let Json(refreshed) = refresh(&["obj-mine"], &[]).await.unwrap();
+        let get_urls = refreshed["objects"][0]["get_urls"].as_array().unwrap();
+        assert_eq!(get_urls.len(), 2);
+        assert!(get_urls.iter().all(|url| url["expires_at"].is_string()));
+
+        let Json(filtered) = refresh(&["obj-mine"], &[("accept_get_urls", "backup")]).await.unwrap();
+        let get_urls = filtered["objects"][0]["get_urls"].as_array().unwrap();
+        assert_eq!(get_urls.len(), 1);
+        assert_eq!(get_urls[0]["label"], "backup");
+        assert!(get_urls[0]["url"].as_str().unwrap().starts_with("https://backup.example.com/"));
+
+        // Objects of another flow's segments are off limits
+        let err = refresh(&["obj-mine", "obj-theirs"], &[]).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::FORBIDDEN);
+        let err = refresh(&[], &[]).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_finalized_flow_refuses_segment_writes() {
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        for (object_id, start, end) in [("first", "10:0", "12:0"), ("second", "12:0", "15:0")] {
+            let segment = CreateSegmentRequest::new(object_id, start, end);
+            add_flow_segment(Path(flow.id), State(state.clone()), Warnings::default(), Json(segment))
+                .await
+                .unwrap();
+        }
+
+        let Json(finalized) = finalize_flow(Path(flow.id), State(state.clone()), None).await.unwrap();
+        assert!(finalized.is_read_only());
+        let available = finalized.available_timerange.unwrap();
+        assert_eq!((available.start.as_str(), available.end.as_deref().unwrap()), ("10:000000000", "15:000000000"));
+        assert!(state.database.get_flow_required(&flow.id).await.unwrap().is_read_only());
+
+        let late = CreateSegmentRequest::new("late", "15:0", "16:0");
+        let err = add_flow_segment(Path(flow.id), State(state.clone()), Warnings::default(), Json(late))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TamsError::ReadOnlyFlow { .. }));
+        let request = DeleteSegmentsByObjectRequest {
+            object_ids: vec!["first".to_string()],
+        };
+        let err = delete_flow_segments_by_object(Path(flow.id), State(state.clone()), Json(request)).await.unwrap_err();
+        assert!(matches!(err, TamsError::ReadOnlyFlow { .. }));
+        let range = HashMap::from([("start".to_string(), "10:0".to_string()), ("end".to_string(), "12:0".to_string())]);
+        let err = delete_flow_segments(Path(flow.id), Query(range), State(state.clone())).await.unwrap_err();
+        assert!(matches!(err, TamsError::ReadOnlyFlow { .. }));
+        assert_eq!(state.database.get_flow_segments(&flow.id).await.unwrap().items.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_external_media_flow_uses_get_url_template() {
+        let (state, _temp_dir) = create_test_state_with(|config| config.validation.require_segment_objects = true).await;
+        let payload: CreateFlowRequest = serde_json::from_value(json!({
+            "format": "urn:x-nmos:format:video",
+            "tags": {"get_url_template": "https://store.example.com/{flow_id}/{object_id_urlencoded}"}
+        }))
+        .unwrap();
+        let flow = create_flow(State(state.clone()), Warnings::default(), None, Json(payload)).await.unwrap().body;
+
+        // Segments may name objects that were never uploaded here
+        let segment: CreateSegmentRequest =
+            serde_json::from_value(json!({"object_id": "clip 1/a", "timerange": {"start": "0:0", "end": "1:0"}})).unwrap();
+        let _ = add_flow_segment(Path(flow.id), State(state.clone()), Warnings::default(), Json(segment)).await.unwrap();
+
+        let Json(listed) = list_flow_segments(Path(flow.id), Query(HashMap::new()), State(state.clone()), Head::default()).await.unwrap();
+        let expected = format!("https://store.example.com/{}/clip%201%2Fa", flow.id);
+        assert_eq!(listed["segments"][0]["get_urls"]["external"], expected);
+
+        let payload = RefreshGetUrlsRequest {
+            object_id: Some("clip 1/a".to_string()),
+            object_ids: Vec::new(),
+        };
+        let Json(refreshed) = refresh_segment_get_urls(Path(flow.id), Query(HashMap::new()), State(state.clone()), Json(payload))
+            .await
+            .unwrap();
+        assert_eq!(refreshed["objects"][0]["get_urls"][0]["url"], expected);
+        assert!(refreshed["objects"][0]["get_urls"][0]["expires_at"].is_null());
+
+        // We don't own the bytes, so nothing can be allocated or uploaded for the flow
+        let err = allocate_storage(Path(flow.id), Query(HashMap::new()), State(state.clone()), Warnings::default(), None).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::FORBIDDEN);
+        let params = HashMap::from([("flow_id".to_string(), flow.id.to_string())]);
+        let err = put_media_object(
+            Path("clip".to_string()),
+            Query(params),
+            State(state.clone()),
+            HeaderMap::new(),
+            axum::body::Body::from("bytes"),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::FORBIDDEN);
+
+        let payload: CreateFlowRequest = serde_json::from_value(json!({
+            "format": "urn:x-nmos:format:video",
+            "tags": {"get_url_template": "https://store.example.com/{nope}"}
+        }))
+        .unwrap();
+        assert!(matches!(create_flow(State(state.clone()), Warnings::default(), None, Json(payload)).await, Err(TamsError::Validation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_override_requires_operator_opt_in() {
+        let (state, _temp_dir) = create_test_state_with(|config| {
+            let mut store = config.service.effective_media_stores().remove(0);
+            store.download_mode = DownloadMode::Redirect;
+            config.service.media_stores = vec![store];
+        })
+        .await;
+        let mut params = HashMap::new();
+        params.insert("proxy".to_string(), "true".to_string());
+        let result = download_media_object(Path("any-object".to_string()), Query(params), State(state.clone()), Head::default(), HeaderMap::new()).await;
+        assert_eq!(result.unwrap_err().into_response().status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_delete_unknown_source_and_flow_return_not_found() {
+        let (state, _temp_dir) = create_test_state().await;
+
+        let result = delete_source(Path(Uuid::new_v4()), State(state.clone())).await;
+        assert!(matches!(result, Err(TamsError::SourceNotFound { .. })));
+
+        let result = delete_flow(Path(Uuid::new_v4()), State(state.clone())).await;
+        assert!(matches!(result, Err(TamsError::FlowNotFound { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_delete_existing_flow_returns_no_content() {
+        let (state, _temp_dir) = create_test_state().await;
+
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+
+        let status = delete_flow(Path(flow.id), State(state.clone())).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(state.database.get_flow(&flow.id).await.unwrap().is_none());
+    }
+
+    async fn create_flow_with_pending_deletion(state: &AppState) -> (Flow, String) {
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        let whole_flow = CreateDeletionRequest { flow_id: flow.id, timerange: None };
+        let request = request_flow_deletion(State(state.clone()), None, Json(whole_flow))
+            .await
+            .unwrap();
+        (flow, request.0.id)
+    }
+
+    #[tokio::test]
+    async fn test_delete_flow_cancels_active_deletion_requests() {
+        let (state, _temp_dir) = create_test_state().await;
+        let (flow, request_id) = create_flow_with_pending_deletion(&state).await;
+
+        let status = delete_flow(Path(flow.id), State(state.clone())).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let request = state.database.get_deletion_request_required(&request_id).await.unwrap();
+        assert_eq!(request.status, DeletionRequest::CANCELLED);
+        assert!(!request.is_active());
+    }
+
+    #[tokio::test]
+    async fn test_delete_flow_rejected_while_deletion_requests_active() {
+        let (state, _temp_dir) = create_test_state_with(|config| {
+            config.deletion.on_flow_delete_with_active_requests = ActiveDeletionRequestPolicy::Reject;
+        })
+        .await;
+        let (flow, request_id) = create_flow_with_pending_deletion(&state).await;
+
+        let result = delete_flow(Path(flow.id), State(state.clone())).await;
+        assert!(matches!(result, Err(TamsError::Conflict(msg)) if msg.contains(&request_id)));
+        assert!(state.database.get_flow(&flow.id).await.unwrap().is_some());
+        assert_eq!(
+            state.database.get_deletion_requests_for_flow(&flow.id).await.unwrap().items.len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_deletion_request_timerange_only_while_pending() {
+        let (state, _temp_dir) = create_test_state().await;
+        let (flow, request_id) = create_flow_with_pending_deletion(&state).await;
+
+        for invalid in [json!({"start": "20:0", "end": "10:0"}), json!("[20:0_10:0)"), json!("[10:0_)"), Value::Null] {
+            let invalid = UpdateDeletionRequest { timerange: invalid };
+            let result = update_deletion_request(Path(request_id.clone()), State(state.clone()), None, Json(invalid)).await;
+            assert!(matches!(result, Err(TamsError::Validation(_))), "{:?}", result);
+        }
+
+        // TAMS notation is taken as on creation
+        let in_notation = UpdateDeletionRequest { timerange: json!("[5:0_15:0)") };
+        let updated = update_deletion_request(Path(request_id.clone()), State(state.clone()), None, Json(in_notation))
+            .await
+            .unwrap();
+        let stored = deletion::parse_deletion_timerange(updated.0.timerange.as_deref().unwrap()).unwrap().unwrap();
+        assert_eq!((stored.start.as_str(), stored.end.as_deref().unwrap()), ("5:0", "15:0"));
+
+        let amended = UpdateDeletionRequest { timerange: json!({"start": "10:0", "end": "20:0"}) };
+        let updated = update_deletion_request(Path(request_id.clone()), State(state.clone()), None, Json(amended.clone()))
+            .await
+            .unwrap();
+        let stored: TimeRange = serde_json::from_str(updated.0.timerange.as_deref().unwrap()).unwrap();
+        assert_eq!((stored.start.as_str(), stored.end.as_deref().unwrap()), ("10:0", "20:0"));
+
+        // Deleting the flow cancels the request, after which it can't be amended
+        delete_flow(Path(flow.id), State(state.clone())).await.unwrap();
+        let result = update_deletion_request(Path(request_id), State(state.clone()), None, Json(amended.clone())).await;
+        assert!(matches!(result, Err(TamsError::Conflict(_))));
+
+        let result = update_deletion_request(Path("missing".to_string()), State(state.clone()), None, Json(amended)).await;
+        assert!(matches!(result, Err(TamsError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_paused_ingest_returns_service_unavailable() {
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+
+        let paused = pause_flow_ingest(Path(flow.id), State(state.clone())).await.unwrap();
+        assert_eq!(paused.0.paused_flows, vec![flow.id]);
+        let err = allocate_storage(Path(flow.id), Query(HashMap::new()), State(state.clone()), Warnings::default(), None)
+            .await
+            .unwrap_err();
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "60");
+
+        // Reads continue while paused
+        assert!(get_flow(Path(flow.id), State(state.clone()), None).await.is_ok());
+
+        let resumed = resume_flow_ingest(Path(flow.id), State(state.clone())).await.unwrap();
+        assert!(resumed.0.paused_flows.is_empty());
+        assert!(allocate_storage(Path(flow.id), Query(HashMap::new()), State(state.clone()), Warnings::default(), None).await.is_ok());
+
+        // The service-wide pause also covers flows that are not paused themselves
+        let service = set_service_ingest_paused(State(state.clone()), Json(IngestPauseRequest { paused: true }))
+            .await
+            .unwrap();
+        assert!(service.0.paused);
+        let mut params = HashMap::new();
+        params.insert("flow_id".to_string(), flow.id.to_string());
+        let result = put_media_object(
+            Path("paused-object".to_string()),
+            Query(params),
+            State(state.clone()),
+            HeaderMap::new(),
+            axum::body::Body::from("data"),
+        )
+        .await;
+        assert!(matches!(result, Err(TamsError::IngestPaused(_))));
+        assert!(get_service_info(State(state.clone())).await.unwrap().0.ingest_paused);
+    }
+
+    #[tokio::test]
+    async fn test_list_flow_ids_streams_json_array() {
+        let (state, _temp_dir) = create_test_state().await;
+
+        let read_ids = |response: Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<Vec<Uuid>>(&bytes).unwrap()
+        };
+
+        let response = list_flow_ids(Query(HashMap::new()), State(state.clone()), None).await.unwrap();
+        assert!(read_ids(response).await.is_empty());
+
+        let source = Source::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_source(&source).await.unwrap();
+        let source_id = source.id;
+        let mut owned = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        owned.source_id = Some(source_id);
+        let other = Flow::new(Uuid::new_v4(), ContentFormat::Audio);
+        state.database.create_flow(&owned).await.unwrap();
+        state.database.create_flow(&other).await.unwrap();
+
+        let response = list_flow_ids(Query(HashMap::new()), State(state.clone()), None).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json; charset=utf-8");
+        let mut all = read_ids(response).await;
+        all.sort();
+        let mut expected = vec![owned.id, other.id];
+        expected.sort();
+        assert_eq!(all, expected);
+
+        let mut params = HashMap::new();
+        params.insert("source_id".to_string(), source_id.to_string());
+        let response = list_flow_ids(Query(params), State(state.clone()), None).await.unwrap();
+        assert_eq!(read_ids(response).await, vec![owned.id]);
+    }
+
+    #[tokio::test]
+    async fn test_list_sources_embeds_bounded_flows() {
+        let (state, _temp_dir) = create_test_state().await;
+        let busy = Source::new(Uuid::new_v4(), ContentFormat::Video);
+        let empty = Source::new(Uuid::new_v4(), ContentFormat::Audio);
+        state.database.create_source(&busy).await.unwrap();
+        state.database.create_source(&empty).await.unwrap();
+        let base = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut flow_ids = Vec::new();
+        for i in 0..4 {
+            let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+            flow.source_id = Some(busy.id);
+            flow.created_at = base + chrono::Duration::seconds(i);
+            state.database.create_flow(&flow).await.unwrap();
+            flow_ids.push(flow.id.to_string());
+        }
+        state.database.create_flow(&Flow::new(Uuid::new_v4(), ContentFormat::Video)).await.unwrap();
+
+        let list = |pairs: &[(&str, &str)]| list_sources(listing_query(pairs), State(state.clone()), None);
+        let Json(listed) = list(&[("include", "flows"), ("flows_limit", "3")]).await.unwrap();
+        let sources = listed["sources"].as_array().unwrap();
+        let by_id = |id: Uuid| sources.iter().find(|s| s["id"] == id.to_string()).unwrap();
+
+        let busy_listed = by_id(busy.id);
+        let embedded: Vec<&str> = busy_listed["flows"].as_array().unwrap().iter().map(|f| f["id"].as_str().unwrap()).collect();
+        assert_eq!(embedded, flow_ids[..3].iter().map(String::as_str).collect::<Vec<_>>());
+        assert_eq!(busy_listed["flows_has_more"], true);
+        assert_eq!(busy_listed["label"], Value::Null);
+
+        let empty_listed = by_id(empty.id);
+        assert!(empty_listed["flows"].as_array().unwrap().is_empty());
+        assert_eq!(empty_listed["flows_has_more"], false);
+
+        // The cap is bounded, and without include=flows nothing is embedded
+        let Json(listed) = list(&[("include", "flows"), ("flows_limit", "100000")]).await.unwrap();
+        assert_eq!(listed["flows_limit"], EMBEDDED_FLOWS_MAX);
+        let Json(listed) = list(&[]).await.unwrap();
+        assert!(listed["sources"][0].get("flows").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_listings_count_total_only_when_asked() {
+        let (state, _temp_dir) = create_test_state().await;
+        state.database.create_source(&Source::new(Uuid::new_v4(), ContentFormat::Video)).await.unwrap();
+        let video = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&video).await.unwrap();
+        state.database.create_flow(&Flow::new(Uuid::new_v4(), ContentFormat::Audio)).await.unwrap();
+        for (start, end) in [("0:0", "10:0"), ("10:0", "20:0"), ("20:0", "30:0")] {
+            let segment = CreateSegmentRequest::new(&format!("obj-{}", start), start, end);
+            state.database.add_flow_segment(&segment.into_segment(video.id)).await.unwrap();
+        }
+        let params = |pairs: &[(&str, &str)]| Query(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect());
+
+        let Json(sources) = list_sources(listing_query(&[]), State(state.clone()), None).await.unwrap();
+        assert!(sources["pagination"]["count"].is_null());
+        let total = listing_query(&[("include_total", "true")]);
+        let Json(sources) = list_sources(total, State(state.clone()), None).await.unwrap();
+        assert_eq!(sources["pagination"]["count"], 1);
+
+        let total = listing_query(&[("include_total", "true")]);
+        let Json(flows) = list_flows(total, State(state.clone()), None).await.unwrap();
+        assert_eq!(flows["pagination"]["count"], 2);
+        let total_video = listing_query(&[("include_total", "true"), ("format", "video")]);
+        let Json(flows) = list_flows(total_video, State(state.clone()), None).await.unwrap();
+        assert_eq!(flows["pagination"]["count"], 1);
+
+        // Segment listings count every match without being asked, not just the page
+        let Json(segments) = list_flow_segments(Path(video.id), params(&[("limit", "1")]), State(state.clone()), Head::default())
+            .await
+            .unwrap();
+        assert_eq!(segments["pagination"]["count"], 3);
+        assert_eq!(segments["segments"].as_array().unwrap().len(), 1);
+        let windowed = params(&[("start", "5:0"), ("end", "15:0"), ("limit", "1")]);
+        let Json(segments) = list_flow_segments(Path(video.id), windowed, State(state.clone()), Head::default()).await.unwrap();
+        assert_eq!(segments["pagination"]["count"], 2);
+        assert_eq!(segments["segments"].as_array().unwrap().len(), 1);
+        assert!(segments["pagination"]["next_key"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_delete_segments_only_within_timerange() {
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        for (start, end) in [("0:0", "10:0"), ("10:0", "20:0"), ("20:0", "30:0")] {
+            let segment: CreateSegmentRequest =
+                serde_json::from_value(json!({"object_id": format!("obj-{}", start), "timerange": {"start": start, "end": end}}))
+                    .unwrap();
+            state.database.add_flow_segment(&segment.into_segment(flow.id)).await.unwrap();
+        }
+        let delete = |pairs: &[(&str, &str)]| {
+            let params = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            delete_flow_segments(Path(flow.id), Query(params), State(state.clone()))
+        };
+        let remaining = || async { state.database.get_flow_segments(&flow.id).await.unwrap().items.len() };
+
+        assert!(matches!(delete(&[]).await, Err(TamsError::Validation(_))));
+        let err = delete(&[("start", "0:0"), ("end", "15:0"), ("strict", "true")]).await.unwrap_err();
+        assert!(matches!(err, TamsError::SegmentOverlap(ref message) if message.contains("[10:0_20:0)")), "{}", err);
+        assert_eq!(remaining().await, 3);
+
+        // Only the segment wholly inside goes; those straddling the ends stay
+        let Json(deleted) = delete(&[("start", "5:0"), ("end", "25:0")]).await.unwrap();
+        assert_eq!(deleted["deleted"], 1);
+        assert_eq!(remaining().await, 2);
+        let Json(deleted) = delete(&[("timerange", "[20:0_)")]).await.unwrap();
+        assert_eq!(deleted["deleted"], 1);
+        assert_eq!(deleted["available_timerange"]["end"], "10:0");
+
+        let Json(deleted) = delete(&[("all", "true")]).await.unwrap();
+        assert_eq!(deleted["deleted"], 1);
+        assert!(deleted["available_timerange"].is_null());
+        assert_eq!(remaining().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_segment_listing_refuses_invalid_timeranges() {
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        for (start, end) in [("0:0", "10:0"), ("10:0", "20:0")] {
+            let segment: CreateSegmentRequest =
+                serde_json::from_value(json!({"object_id": format!("obj-{}", start), "timerange": {"start": start, "end": end}}))
+                    .unwrap();
+            state.database.add_flow_segment(&segment.into_segment(flow.id)).await.unwrap();
+        }
+        let list = |pairs: &[(&str, &str)]| {
+            let params = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            list_flow_segments(Path(flow.id), Query(params), State(state.clone()), Head::default())
+        };
+
+        // Partial overlap on either side still counts
+        let Json(listed) = list(&[("start", "9:500000000"), ("end", "10:1")]).await.unwrap();
+        assert_eq!(listed["segments"].as_array().unwrap().len(), 2);
+        let Json(listed) = list(&[("timerange", "[15:0_)")]).await.unwrap();
+        assert_eq!(listed["segments"][0]["object_id"], "obj-10:0");
+        let Json(listed) = list(&[("limit", "1")]).await.unwrap();
+        assert_eq!(listed["segments"].as_array().unwrap().len(), 1);
+        assert!(listed["pagination"]["next_key"].is_string());
+
+        for invalid in [
+            vec![("start", "20:0"), ("end", "10:0")],
+            vec![("start", "ten seconds")],
+            vec![("end", "10")],
+            vec![("timerange", "[10:0,20:0)")],
+        ] {
+            let result = list(&invalid).await;
+            assert!(matches!(result, Err(TamsError::InvalidTimerange(_))), "{:?} was accepted", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_image_flow_with_mixed_resolution_segments() {
+        let (state, _temp_dir) = create_test_state().await;
+
+        let request: CreateFlowRequest = serde_json::from_value(json!({
+            "format": "urn:x-tam:format:image",
+            "container": "image/jpeg",
+            "frame_width": 1920,
+            "frame_height": 1080,
+            "tags": {}
+        }))
+        .unwrap();
+        let still = create_flow(State(state.clone()), Warnings::default(), None, Json(request)).await.unwrap().body;
+        state.database.create_flow(&Flow::new(Uuid::new_v4(), ContentFormat::Video)).await.unwrap();
+
+        for (object_id, start, end, dimensions) in [
+            ("still-1", "0:0", "1:0", None),
+            ("still-2", "1:0", "2:0", Some(json!({"frame_width": 3840, "frame_height": 2160}))),
+        ] {
+            let segment: CreateSegmentRequest = serde_json::from_value(json!({
+                "object_id": object_id,
+                "timerange": {"start": start, "end": end},
+                "ts_offset": start,
+                "essence_parameters": dimensions
+            }))
+            .unwrap();
+            let Created { body: stored, .. } = add_flow_segment(Path(still.id), State(state.clone()), Warnings::default(), Json(segment)).await.unwrap();
+            assert_eq!(stored.object_id, object_id);
+        }
+
+        let Json(listing) = list_flow_segments(Path(still.id), Query(HashMap::new()), State(state.clone()), Head::default())
+            .await
+            .unwrap();
+        let segments = listing["segments"].as_array().unwrap();
+        assert_eq!(segments.len(), 2);
+        assert!(segments[0].get("essence_parameters").is_none());
+        assert_eq!(segments[1]["essence_parameters"], json!({"frame_width": 3840, "frame_height": 2160}));
+
+        let Json(flows) = list_flows(listing_query(&[("format", "Image")]), State(state.clone()), None).await.unwrap();
+        let flows = flows["flows"].as_array().unwrap();
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0]["id"], json!(still.id));
+
+        let err = list_flows(listing_query(&[("format", "stills")]), State(state.clone()), None).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_stream_flow_concatenates_segments_in_order() {
+        let (state, _temp_dir) = create_test_state().await;
+        let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        flow.container = Some("video/mp2t".to_string());
+        state.database.create_flow(&flow).await.unwrap();
+
+        // Added out of order; the stream follows the timeranges
+        for (object_id, data, start, end) in [("obj-late", "LATE", "10:0", "20:0"), ("obj-early", "early-", "0:0", "10:0")] {
+            let context = ObjectContext { flow_id: Some(flow.id), ..Default::default() };
+            state.storage.store_object(object_id, None, data.as_bytes().to_vec().into(), &context).await.unwrap();
+            let segment = CreateSegmentRequest {
+                ts_offset: Some(start.to_string()),
+                ..CreateSegmentRequest::new(object_id, start, end)
+            };
+            state.database.add_flow_segment(&segment.into_segment(flow.id)).await.unwrap();
+        }
+
+        let read_body = |response: Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+
+        let response = stream_flow(Path(flow.id), Query(HashMap::new()), State(state.clone()), HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "video/mp2t");
+        assert_eq!(read_body(response).await, "early-LATE");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, "bytes=4-7".parse().unwrap());
+        let response = stream_flow(Path(flow.id), Query(HashMap::new()), State(state.clone()), headers)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 4-7/10");
+        assert_eq!(read_body(response).await, "y-LA");
+
+        let mut params = HashMap::new();
+        params.insert("start".to_string(), "12:0".to_string());
+        params.insert("end".to_string(), "15:0".to_string());
+        let response = stream_flow(Path(flow.id), Query(params), State(state.clone()), HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(read_body(response).await, "LATE");
+
+        // Either bound alone narrows the window; one that doesn't parse is refused
+        let stream = |window: &[(&str, &str)]| {
+            let params = window.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            stream_flow(Path(flow.id), Query(params), State(state.clone()), HeaderMap::new())
+        };
+        assert_eq!(read_body(stream(&[("start", "12:0")]).await.unwrap()).await, "LATE");
+        assert_eq!(read_body(stream(&[("end", "5:0")]).await.unwrap()).await, "early-");
+        for window in [[("start", "soon")], [("timerange", "[20:0_10:0)")]] {
+            let status = stream(&window).await.unwrap_err().into_response().status();
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{:?}", window);
+        }
+
+        // With a maximum query duration, no window means the whole flow's extent
+        let (limited, _limited_dir) =
+            create_test_state_with(|config| config.validation.max_query_duration_seconds = Some(15)).await;
+        limited.database.create_flow(&flow).await.unwrap();
+        for segment in state.database.get_flow_segments(&flow.id).await.unwrap().items {
+            let context = ObjectContext { flow_id: Some(flow.id), ..Default::default() };
+            let data = state.storage.get_object(&segment.object_id, &context).await.unwrap();
+            limited.storage.store_object(&segment.object_id, None, data.into(), &context).await.unwrap();
+            limited.database.add_flow_segment(&segment).await.unwrap();
+        }
+        let stream_limited = |window: &[(&str, &str)]| {
+            let params = window.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            stream_flow(Path(flow.id), Query(params), State(limited.clone()), HeaderMap::new())
+        };
+        for window in [&[][..], &[("start", "12:0")][..]] {
+            let status = stream_limited(window).await.unwrap_err().into_response().status();
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{:?}", window);
+        }
+        assert_eq!(read_body(stream_limited(&[("timerange", "[5:0_15:0)")]).await.unwrap()).await, "early-LATE");
+
+        let mut audio = MediaObject {
+            object_id: "obj-early".to_string(),
+            size_bytes: Some(6),
+            mime_type: Some("video/mp2t".to_string()),
+            flow_references: vec![FlowReference::new(flow.id)],
+            created_at: chrono::Utc::now(),
+            metadata: HashMap::new(),
+            storage_class: None,
+            format: None,
+            content_hash: None,
+        };
+        state.database.create_media_object(&audio).await.unwrap();
+        audio.object_id = "obj-late".to_string();
+        audio.mime_type = Some("audio/aac".to_string());
+        state.database.create_media_object(&audio).await.unwrap();
+        let result = stream_flow(Path(flow.id), Query(HashMap::new()), State(state.clone()), HeaderMap::new()).await;
+        assert_eq!(result.unwrap_err().into_response().status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_archive_flow_segments_writes_manifest_and_objects() {
+        use std::io::Read;
+
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        let context = ObjectContext { flow_id: Some(flow.id), ..Default::default() };
+        for (object_id, start, end) in [("obj-late", "10:0", "20:0"), ("obj-early", "0:0", "10:0"), ("obj-gone", "20:0", "30:0")] {
+            if object_id != "obj-gone" {
+                state.storage.store_object(object_id, None, object_id.as_bytes().to_vec().into(), &context).await.unwrap();
+            }
+            let segment = CreateSegmentRequest {
+                ts_offset: Some(start.to_string()),
+                ..CreateSegmentRequest::new(object_id, start, end)
+            };
+            state.database.add_flow_segment(&segment.into_segment(flow.id)).await.unwrap();
+        }
+        let archive = |params: &[(&str, &str)]| {
+            let params = params.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            archive_flow_segments(Path(flow.id), Query(params), State(state.clone()))
+        };
+
+        // A missing object fails the whole archive unless allowed
+        let error = archive(&[]).await.unwrap_err();
+        assert!(matches!(error, TamsError::ObjectNotFound { .. }));
+
+        let response = archive(&[("timerange", "[0:0_20:0)")]).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/x-tar");
+        let length: usize = response.headers()[header::CONTENT_LENGTH].to_str().unwrap().parse().unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(bytes.len(), length);
+        let entries = crate::archive::tests::read_entries(&bytes);
+        let names: Vec<_> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["manifest.json", "0:0_obj-early", "10:0_obj-late"]);
+        assert_eq!(entries[2].1, b"obj-late");
+        let manifest: serde_json::Value = serde_json::from_slice(&entries[0].1).unwrap();
+        assert_eq!(manifest["segments"][1]["ts_offset"], "10:0");
+        assert_eq!(manifest["segments"][1]["entry"], "10:0_obj-late");
+
+        let response = archive(&[("allow_missing", "true"), ("format", "tar.gz")]).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/gzip");
+        let compressed = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mut bytes = Vec::new();
+        flate2::read::GzDecoder::new(&compressed[..]).read_to_end(&mut bytes).unwrap();
+        let entries = crate::archive::tests::read_entries(&bytes);
+        assert_eq!(entries.len(), 3);
+        let manifest: serde_json::Value = serde_json::from_slice(&entries[0].1).unwrap();
+        assert_eq!(manifest["segments"][2]["missing"], true);
+        assert!(manifest["segments"][2]["entry"].is_null());
+
+        assert!(matches!(archive(&[("format", "zip")]).await.unwrap_err(), TamsError::Validation(_)));
+        let error = archive(&[("timerange", "[20:0_10:0)")]).await.unwrap_err();
+        assert_eq!(error.into_response().status(), StatusCode::BAD_REQUEST);
+        let response = archive(&[("start", "10:0"), ("allow_missing", "true")]).await.unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(crate::archive::tests::read_entries(&bytes).len(), 2);
+        let (state, _temp_dir) = create_test_state_with(|config| config.media_storage.max_archive_bytes = 2048).await;
+        state.database.create_flow(&flow).await.unwrap();
+        state.storage.store_object("obj-early", None, vec![0; 1024].into(), &context).await.unwrap();
+        let segment = CreateSegmentRequest::new("obj-early", "0:0", "10:0");
+        state.database.add_flow_segment(&segment.into_segment(flow.id)).await.unwrap();
+        let error = archive_flow_segments(Path(flow.id), Query(HashMap::new()), State(state.clone())).await.unwrap_err();
+        assert_eq!(error.into_response().status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_allocation_returns_existing_and_upload_is_immutable() {
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+
+        let mut params = HashMap::new();
+        params.insert("object_ids".to_string(), "dup-object".to_string());
+        let allocate = || allocate_storage(Path(flow.id), Query(params.clone()), State(state.clone()), Warnings::default(), None);
+
+        let Json(first) = allocate().await.unwrap();
+        let Json(second) = allocate().await.unwrap();
+        assert!(!first.objects[0].already_allocated);
+        assert!(second.objects[0].already_allocated);
+        assert_eq!(first.objects[0].put_url, second.objects[0].put_url);
+        assert!(serde_json::to_value(&first.objects[0]).unwrap().get("already_allocated").is_none());
+
+        let mut upload_params = HashMap::new();
+        upload_params.insert("flow_id".to_string(), flow.id.to_string());
+        let upload = |data: &'static [u8]| {
+            put_media_object(
+                Path("dup-object".to_string()),
+                Query(upload_params.clone()),
+                State(state.clone()),
+                HeaderMap::new(),
+                axum::body::Body::from(data),
+            )
+        };
+        assert_eq!(upload(b"first").await.unwrap(), StatusCode::CREATED);
+        assert_eq!(upload(b"first").await.unwrap(), StatusCode::OK);
+        assert!(matches!(upload(b"second").await, Err(TamsError::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn test_add_segment_rejects_malformed_ts_offset() {
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+
+        let segment = |ts_offset: Option<&str>| CreateSegmentRequest {
+            ts_offset: ts_offset.map(str::to_string),
+            ..CreateSegmentRequest::new("obj", "0:0", "10:0")
+        };
+
+        let err = add_flow_segment(Path(flow.id), State(state.clone()), Warnings::default(), Json(segment(Some("12.5s"))))
+            .await
+            .unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+        assert!(state.database.get_flow_segments(&flow.id).await.unwrap().items.is_empty());
+
+        let Created { body: stored, .. } = add_flow_segment(Path(flow.id), State(state.clone()), Warnings::default(), Json(segment(None))).await.unwrap();
+        assert!(stored.ts_offset.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_add_segment_rejects_malformed_and_inverted_timeranges() {
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+
+        for (start, end) in [("ten seconds", "20:0"), ("0:0", "later"), ("20:0", "10:0"), ("10:0", "10:0")] {
+            let segment = CreateSegmentRequest::new("obj", start, end);
+            let err = add_flow_segment(Path(flow.id), State(state.clone()), Warnings::default(), Json(segment))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, TamsError::InvalidTimerange(_)), "{}:{} gave {:?}", start, end, err);
+            assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+        }
+        assert!(state.database.get_flow_segments(&flow.id).await.unwrap().items.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_add_segment_to_unknown_flow_returns_not_found() {
+        let (state, _temp_dir) = create_test_state().await;
+        let segment = CreateSegmentRequest::new("obj", "0:0", "10:0");
+        let err = add_flow_segment(Path(Uuid::new_v4()), State(state.clone()), Warnings::default(), Json(segment))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TamsError::FlowNotFound { .. }));
+        assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_segment_post_stores_valid_segments_and_reports_failures() {
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        let post = |payload: Value| post_flow_segments(Path(flow.id), State(state.clone()), Warnings::default(), Json(payload));
+        let segment = |object_id: &str, start: &str, end: &str| serde_json::to_value(CreateSegmentRequest::new(object_id, start, end)).unwrap();
+
+        let response = post(json!([segment("obj-0", "0:0", "10:0"), segment("obj-1", "10:0", "20:0")])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        // A duplicate and an inverted timerange fail; the segment between them is kept
+        let response = post(json!([segment("obj-1", "10:0", "20:0"), segment("obj-2", "20:0", "30:0"), segment("obj-3", "40:0", "30:0")]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let failures: FlowSegmentBulkFailure = serde_json::from_slice(&body).unwrap();
+        let failed: Vec<&str> = failures.failed_segments.iter().map(|f| f.segment.object_id.as_str()).collect();
+        assert_eq!(failed, ["obj-3", "obj-1"]);
+        let stored = state.database.get_flow_segments(&flow.id).await.unwrap().items;
+        assert_eq!(stored.len(), 3);
+
+        // A single segment still takes the one-segment path
+        let response = post(segment("obj-4", "30:0", "40:0")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let err = post(json!([])).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+        let err = post_flow_segments(Path(Uuid::new_v4()), State(state.clone()), Warnings::default(), Json(json!([segment("obj-5", "0:0", "1:0")])))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TamsError::FlowNotFound { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_missing_segment_object_error_carries_allocation_guidance() {
+        let (state, _temp_dir) = create_test_state_with(|config| config.validation.require_segment_objects = true).await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        let mut tx = state.database.begin_transaction().await.unwrap();
+        tx.claim_storage_allocation(
+            Some(&flow.id),
+            &StorageObject {
+                object_id: "expired-object".to_string(),
+                put_url: "http://localhost/expired-object".to_string(),
+                put_headers: None,
+                expires_at: Some(chrono::Utc::now() - chrono::Duration::hours(1)),
+                media_store: None,
+                storage_class: None,
+                already_allocated: false,
+            },
+            chrono::Utc::now(),
+        )
+        .await
+        .unwrap();
+        tx.commit().await.unwrap();
+
+        let error_body = |object_id: &str| {
+            let request = CreateSegmentRequest::new(object_id, "0:0", "10:0");
+            let state = state.clone();
+            async move {
+                let response = add_flow_segment(Path(flow.id), State(state), Warnings::default(), Json(request))
+                    .await
+                    .unwrap_err()
+                    .into_response();
+                assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<Value>(&bytes).unwrap()
+            }
+        };
+
+        let body = error_body("missing-object").await;
+        assert_eq!(body["code"], "object_not_uploaded");
+        let extensions = &body["extensions"];
+        assert_eq!(extensions["object_id"], "missing-object");
+        assert_eq!(extensions["expired_allocation"], false);
+        assert!(extensions.get("allocation_expires_at").is_none());
+        assert_eq!(
+            extensions["allocation_hint"],
+            json!({
+                "method": "POST",
+                "path": format!("/x-tams/v6.0/flows/{}/storage", flow.id),
+                "body": { "limit": 1, "object_ids": ["missing-object"] }
+            })
+        );
+
+        let body = error_body("expired-object").await;
+        assert_eq!(body["extensions"]["expired_allocation"], true);
+        assert!(body["extensions"]["allocation_expires_at"].is_string());
+        assert!(state.database.get_flow_segments(&flow.id).await.unwrap().items.is_empty());
+
+        // The hint's body is accepted by the allocation endpoint as-is
+        let hint: FlowStorageRequest = serde_json::from_value(body["extensions"]["allocation_hint"]["body"].clone()).unwrap();
+        let Json(allocated) = allocate_storage(Path(flow.id), Query(HashMap::new()), State(state.clone()), Warnings::default(), Some(Json(hint)))
+            .await
+            .unwrap();
+        assert_eq!(allocated.objects.len(), 1);
+        assert_eq!(allocated.objects[0].object_id, "expired-object");
+    }
+
+    #[tokio::test]
+    async fn test_deletion_requests_are_processed_through_the_job_queue() {
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        for (object_id, start, end) in [("a", "0:0", "5:0"), ("b", "5:0", "10:0"), ("c", "10:0", "20:0")] {
+            let request = CreateSegmentRequest::new(object_id, start, end);
+            state.database.add_flow_segment(&request.into_segment(flow.id)).await.unwrap();
+        }
+
+        let payload = CreateDeletionRequest { flow_id: flow.id, timerange: Some(json!("[0:0_10:0)")) };
+        let Json(request) = request_flow_deletion(State(state.clone()), None, Json(payload)).await.unwrap();
+        let stored: TimeRange = serde_json::from_str(request.timerange.as_deref().unwrap()).unwrap();
+        assert_eq!((stored.start.as_str(), stored.end.as_deref().unwrap()), ("0:0", "10:0"));
+        for bad in [json!("yesterday"), json!("[10:0_0:0)"), json!(42), json!({"start": "0:0"})] {
+            let bad_payload = CreateDeletionRequest { flow_id: flow.id, timerange: Some(bad) };
+            let result = request_flow_deletion(State(state.clone()), None, Json(bad_payload)).await;
+            assert!(matches!(result, Err(TamsError::Validation(_))));
+        }
+
+        let Json(jobs) = list_jobs(Query(HashMap::new()), State(state.clone())).await.unwrap();
+        assert_eq!(jobs["jobs"][0]["kind"], deletion::FLOW_DELETION_JOB);
+        assert_eq!(jobs["jobs"][0]["target"], request.id.as_str());
+        assert_eq!(jobs["jobs"][0]["status"], Job::PENDING);
+        assert_eq!(jobs["jobs"][0]["attempts"], 0);
+        assert!(jobs["jobs"][0]["run_at"].is_string());
+
+        assert_eq!(deletion::run_due_deletions(&state).await.unwrap(), 1);
+        assert_eq!(deletion::run_due_deletions(&state).await.unwrap(), 0);
+
+        let segments = state.database.get_flow_segments(&flow.id).await.unwrap().items;
+        assert_eq!(segments.iter().map(|s| s.object_id.as_str()).collect::<Vec<_>>(), vec!["c"]);
+        let available = state.database.get_flow_required(&flow.id).await.unwrap().available_timerange.unwrap();
+        assert_eq!((available.start.as_str(), available.end.as_deref().unwrap()), ("10:0", "20:0"));
+
+        let request = state.database.get_deletion_request_required(&request.id).await.unwrap();
+        assert_eq!((request.status.as_str(), request.progress), (DeletionRequest::DONE, Some(100)));
+        let list = |pairs: &[(&str, &str)]| {
+            let params = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            list_jobs(Query(params), State(state.clone()))
+        };
+        // Completed jobs are hidden unless asked for
+        let Json(jobs) = list(&[]).await.unwrap();
+        assert_eq!(jobs["pagination"]["count"], 0);
+        assert_eq!(jobs["counts"][Job::COMPLETED], 1);
+        let Json(jobs) = list(&[("status", "completed,failed"), ("type", deletion::FLOW_DELETION_JOB)]).await.unwrap();
+        assert_eq!(jobs["pagination"]["count"], 1);
+        let Json(jobs) = list(&[("status", "all"), ("type", "webhook_delivery")]).await.unwrap();
+        assert_eq!(jobs["pagination"]["count"], 0);
+        assert!(matches!(list(&[("status", "stuck")]).await, Err(TamsError::Validation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_deletions_run_in_batches_inside_the_active_window() {
+        // 22:13 UTC, outside the window
+        let clock = Arc::new(FakeClock::new(chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap()));
+        let (state, _temp_dir) = create_test_state_with_clock(
+            |config| {
+                config.deletion.worker.batch_size = 2;
+                config.deletion.worker.batch_pause_ms = 0;
+                config.deletion.worker.active_window = Some("01:00-05:00".to_string());
+            },
+            clock.clone(),
+        )
+        .await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        for i in 0..5 {
+            let request = CreateSegmentRequest::new(&format!("obj-{}", i), &format!("{}:0", i), &format!("{}:0", i + 1));
+            state.database.add_flow_segment(&request.into_segment(flow.id)).await.unwrap();
+        }
+        let whole_flow = CreateDeletionRequest { flow_id: flow.id, timerange: None };
+        let Json(request) = request_flow_deletion(State(state.clone()), None, Json(whole_flow))
+            .await
+            .unwrap();
+
+        // Outside the window the request is taken up but left idle until it opens
+        assert_eq!(deletion::run_due_deletions(&state).await.unwrap(), 1);
+        let pending = state.database.get_deletion_request_required(&request.id).await.unwrap();
+        assert_eq!(pending.status, DeletionRequest::IN_PROGRESS);
+        assert_eq!(state.database.get_flow_segments(&flow.id).await.unwrap().items.len(), 5);
+        let Json(jobs) = list_jobs(Query(HashMap::new()), State(state.clone())).await.unwrap();
+        assert_eq!(jobs["jobs"][0]["status"], Job::PENDING);
+        assert_eq!(jobs["jobs"][0]["attempts"], 0);
+        assert_eq!(deletion::run_due_deletions(&state).await.unwrap(), 0);
+        let Json(worker) = get_deletion_worker_config(State(state.clone())).await.unwrap();
+        assert!(!worker.in_active_window);
+
+        clock.advance(chrono::Duration::hours(3));
+        assert_eq!(deletion::run_due_deletions(&state).await.unwrap(), 1);
+        assert!(state.database.get_flow_segments(&flow.id).await.unwrap().items.is_empty());
+        let done = state.database.get_deletion_request_required(&request.id).await.unwrap();
+        assert_eq!((done.status.as_str(), done.progress), (DeletionRequest::DONE, Some(100)));
+
+        // Settings can be changed live and are validated
+        let Json(worker) = update_deletion_worker_config(State(state.clone()), Json(json!({ "active_window": null })))
+            .await
+            .unwrap();
+        assert!(worker.overridden);
+        assert_eq!(worker.settings.active_window, None);
+        assert_eq!(worker.settings.batch_size, 2);
+        let err = update_deletion_worker_config(State(state.clone()), Json(json!({ "batch_size": 0 })))
+            .await
+            .unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// State whose clock is pinned to a whole second, with orphaned objects
+    /// reclaimed as soon as retention finds them
+    async fn retention_test_state() -> (AppState, TempDir, Arc<FakeClock>) {
+        let now = chrono::DateTime::from_timestamp(chrono::Utc::now().timestamp(), 0).unwrap();
+        let clock = Arc::new(FakeClock::new(now));
+        let (state, temp_dir) = create_test_state_with_clock(
+            |config| {
+                config.retention.batch_size = 1;
+                config.cleanup.orphaned_object_retention_days = 0;
+            },
+            clock.clone(),
+        )
+        .await;
+        (state, temp_dir, clock)
+    }
+
+    /// Create a flow keeping 100 seconds of segments, with one segment per
+    /// `(object_id, started, ended)`, given in seconds before `now`
+    async fn create_retained_flow(state: &AppState, read_only: bool, segments: &[(&str, i64, i64)]) -> Flow {
+        let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        flow.retention_seconds = Some(100);
+        flow.read_only = read_only.then_some(true);
+        state.database.create_flow(&flow).await.unwrap();
+        let now = state.clock.now().timestamp();
+        for (object_id, started, ended) in segments {
+            let request = CreateSegmentRequest::new(object_id, &format!("{}:0", now - started), &format!("{}:0", now - ended));
+            state.database.add_flow_segment(&request.into_segment(flow.id)).await.unwrap();
+        }
+        flow
+    }
+
+    #[tokio::test]
+    async fn test_retention_expires_segments_of_writable_flows() {
+        let (state, _temp_dir, _clock) = retention_test_state().await;
+        // Ended 150s and 110s ago, then one straddling the cutoff and a recent one
+        let segments = [("old-1", 200, 150), ("old-2", 150, 110), ("straddling", 110, 90), ("recent", 90, 10)];
+        let flow = create_retained_flow(&state, false, &segments).await;
+        let frozen = create_retained_flow(&state, true, &segments[..1]).await;
+
+        assert_eq!(crate::retention::run_due_retention(&state).await.unwrap(), 2);
+        let left = state.database.get_flow_segments(&flow.id).await.unwrap().items;
+        assert_eq!(left.iter().map(|s| s.object_id.as_str()).collect::<Vec<_>>(), vec!["straddling", "recent"]);
+        let available = state.database.get_flow_required(&flow.id).await.unwrap().available_timerange.unwrap();
+        let now = state.clock.now().timestamp();
+        assert_eq!((available.start, available.end), (format!("{}:0", now - 110), Some(format!("{}:0", now - 10))));
+        // Read-only flows keep everything
+        assert_eq!(state.database.get_flow_segments(&frozen.id).await.unwrap().items.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retention_reclaims_only_unreferenced_objects() {
+        let (state, _temp_dir, _clock) = retention_test_state().await;
+        let flow = create_retained_flow(&state, false, &[("expired", 200, 150), ("recent", 90, 10)]).await;
+        let context = ObjectContext { flow_id: Some(flow.id), ..Default::default() };
+        for object_id in ["expired", "recent"] {
+            state.storage.store_object(object_id, None, b"bytes".to_vec().into(), &context).await.unwrap();
+            let object = MediaObject {
+                object_id: object_id.to_string(),
+                size_bytes: Some(5),
+                mime_type: None,
+                flow_references: vec![FlowReference::new(flow.id)],
+                created_at: state.clock.now() - chrono::Duration::hours(1),
+                metadata: HashMap::new(),
+                storage_class: None,
+                format: None,
+                content_hash: None,
+            };
+            state.database.record_uploaded_object(&object).await.unwrap();
+        }
+
+        crate::retention::run_due_retention(&state).await.unwrap();
+        assert!(state.database.get_media_object("expired").await.unwrap().is_none());
+        assert!(!state.storage.object_exists("expired", &context).await);
+        assert!(state.database.get_media_object("recent").await.unwrap().is_some());
+        assert!(state.storage.object_exists("recent", &context).await);
+    }
+
+    #[tokio::test]
+    async fn test_retention_jobs_recur_until_the_policy_is_removed() {
+        use crate::retention;
+
+        let (state, _temp_dir, clock) = retention_test_state().await;
+        let flow = create_retained_flow(&state, false, &[("recent", 90, 10)]).await;
+        create_retained_flow(&state, true, &[]).await;
+        let sweep_interval = chrono::Duration::seconds(state.config.retention.sweep_interval_seconds as i64);
+
+        // Each flow keeps one job, due again at the next sweep
+        assert_eq!(retention::run_due_retention(&state).await.unwrap(), 2);
+        assert_eq!(retention::run_due_retention(&state).await.unwrap(), 0);
+        let Json(jobs) = list_jobs(Query(HashMap::new()), State(state.clone())).await.unwrap();
+        assert_eq!(jobs["pagination"]["count"], 2);
+        assert_eq!(jobs["jobs"][0]["kind"], retention::FLOW_RETENTION_JOB);
+        assert_eq!(jobs["jobs"][0]["attempts"], 0);
+
+        clock.advance(sweep_interval);
+        assert_eq!(retention::run_due_retention(&state).await.unwrap(), 2);
+        assert!(state.database.get_flow_segments(&flow.id).await.unwrap().items.is_empty());
+        assert!(state.database.get_flow_required(&flow.id).await.unwrap().available_timerange.is_none());
+
+        // Removing the policy retires the flow's job
+        let update = UpdateFlowRequest {
+            retention_seconds: Some(0),
+            ..Default::default()
+        };
+        let updated = update.apply_to_flow(state.database.get_flow_required(&flow.id).await.unwrap());
+        assert_eq!(updated.retention_seconds, None);
+        let mut tx = state.database.begin_transaction().await.unwrap();
+        tx.update_flow(&updated).await.unwrap();
+        tx.commit().await.unwrap();
+        clock.advance(sweep_interval);
+        assert_eq!(retention::run_due_retention(&state).await.unwrap(), 2);
+        let Json(jobs) = list_jobs(Query(HashMap::new()), State(state.clone())).await.unwrap();
+        assert_eq!(jobs["pagination"]["count"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_future_segments_are_checked_against_the_injected_clock() {
+        let now = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let clock = Arc::new(FakeClock::new(now));
+        let (state, _temp_dir) = create_test_state_with_clock(|_| {}, clock.clone()).await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+
+        let segment = |object_id: &str, start_seconds: i64| {
+            CreateSegmentRequest::new(object_id, &format!("{}:0", start_seconds), &format!("{}:0", start_seconds + 10))
+        };
+
+        // Ten minutes ahead of the (fake) server time is beyond the default five
+        let err = add_flow_segment(Path(flow.id), State(state.clone()), Warnings::default(), Json(segment("ahead", 1_700_000_600)))
+            .await
+            .unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("1700000600:0"), "{}", message);
+        assert!(message.contains("1700000000:000000000"), "{}", message);
+        assert!(message.contains("600s ahead"), "{}", message);
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+
+        let warnings = Warnings::default();
+        add_flow_segment(Path(flow.id), State(state.clone()), warnings.clone(), Json(segment("near", 1_700_000_200)))
+            .await
+            .unwrap();
+        assert!(warnings.take().is_empty());
+
+        // Once the clock catches up the same segment is accepted
+        clock.advance(chrono::Duration::minutes(6));
+        assert!(add_flow_segment(Path(flow.id), State(state.clone()), Warnings::default(), Json(segment("ahead", 1_700_000_600))).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_future_segments_warn_under_permissive_policy() {
+        use tower::ServiceExt;
+
+        let now = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let post_segment = |policy: crate::config::FutureSkewPolicy| async move {
+            let (state, temp_dir) = create_test_state_with_clock(
+                |config| config.validation.future_skew_policy = policy,
+                Arc::new(FakeClock::new(now)),
+            )
+            .await;
+            let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+            state.database.create_flow(&flow).await.unwrap();
+            let app = axum::Router::new()
+                .route("/flows/:flow_id/segments", axum::routing::post(add_flow_segment))
+                .with_state(state.clone())
+                .layer(axum::middleware::from_fn(crate::warnings::warnings_middleware));
+            let body = json!({"object_id": "ahead", "timerange": {"start": "1700003600:0", "end": "1700003610:0"}});
+            let request = axum::http::Request::post(format!("/flows/{}/segments", flow.id))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(axum::body::Body::from(body.to_string()))
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let (parts, body) = response.into_parts();
+            let body: Value = serde_json::from_slice(&axum::body::to_bytes(body, usize::MAX).await.unwrap()).unwrap();
+            let stored = state.database.get_flow_segments(&flow.id).await.unwrap().items.len();
+            drop(temp_dir);
+            (parts, body, stored)
+        };
+
+        let (parts, body, stored) = post_segment(crate::config::FutureSkewPolicy::Warn).await;
+        assert_eq!(parts.status, StatusCode::CREATED);
+        assert_eq!(parts.headers[crate::warnings::WARNINGS_COUNT_HEADER], "1");
+        let warning = parts.headers[header::WARNING].to_str().unwrap();
+        assert!(warning.starts_with("199 tams \"Segment starts at 1700003600:0"), "{}", warning);
+        assert_eq!(body["object_id"], "ahead");
+        assert_eq!(body["warnings"][0]["code"], "future_segment");
+        assert_eq!(body["warnings"][0]["field"], "timerange.start");
+        assert_eq!(stored, 1);
+
+        // The strict policy turns the same condition into an error
+        let (parts, body, stored) = post_segment(crate::config::FutureSkewPolicy::Reject).await;
+        assert_eq!(parts.status, StatusCode::BAD_REQUEST);
+        assert!(parts.headers.get(crate::warnings::WARNINGS_COUNT_HEADER).is_none());
+        assert!(body.get("warnings").is_none());
+        assert_eq!(stored, 0);
+    }
+
+    #[tokio::test]
+    async fn test_creation_returns_created_with_location() {
+        let (state, _temp_dir) = create_test_state().await;
+        let location = |response: &Response| response.headers()[header::LOCATION].to_str().unwrap().to_string();
+
+        let source_id = Uuid::new_v4();
+        let source: CreateSourceRequest =
+            serde_json::from_value(json!({"id": source_id, "format": "urn:x-nmos:format:video", "tags": {}})).unwrap();
+        let response = create_source(State(state.clone()), None, Json(source)).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(location(&response), format!("/x-tams/v6.0/sources/{}", source_id));
+
+        let flow: CreateFlowRequest = serde_json::from_value(json!({"format": "urn:x-nmos:format:video", "tags": {}})).unwrap();
+        let response = create_flow(State(state.clone()), Warnings::default(), None, Json(flow)).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let flow_id = serde_json::from_slice::<Value>(&bytes).unwrap()["id"].as_str().unwrap().to_string();
+        let flow_id = Uuid::parse_str(&flow_id).unwrap();
+        assert!(state.database.get_flow(&flow_id).await.unwrap().is_some());
+
+        let segment: CreateSegmentRequest =
+            serde_json::from_value(json!({"object_id": "obj", "timerange": {"start": "0:0", "end": "10:0"}})).unwrap();
+        let response = add_flow_segment(Path(flow_id), State(state.clone()), Warnings::default(), Json(segment)).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(location(&response), format!("/x-tams/v6.0/flows/{}/segments?start=0%3A0&end=10%3A0", flow_id));
+    }
+
+    #[tokio::test]
+    async fn test_json_bodies_are_normalized_to_utf8() {
+        use tower::ServiceExt;
+
+        let (state, _temp_dir) = create_test_state().await;
+        let app = axum::Router::new()
+            .route("/sources", axum::routing::post(create_source))
+            .route("/sources/:source_id", axum::routing::get(get_source))
+            .with_state(state.clone())
+            .layer(axum::middleware::from_fn(crate::encoding::json_encoding_middleware));
+        let send = |method: &str, uri: String, body: Vec<u8>| {
+            let request = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(axum::body::Body::from(body))
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        // A byte order mark is dropped and a lone surrogate escape stored as U+FFFD
+        let id = Uuid::new_v4();
+        let body = format!(r#"{{"id":"{}","format":"urn:x-nmos:format:video","label":"cam \ud800 1","tags":{{}}}}"#, id);
+        let response = send("POST", "/sources".to_string(), [b"\xEF\xBB\xBF".as_slice(), body.as_bytes()].concat()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json; charset=utf-8");
+        let stored = state.database.get_source_required(&id).await.unwrap();
+        assert_eq!(stored.label.as_deref(), Some("cam \u{FFFD} 1"));
+
+        let response = send("GET", format!("/sources/{}", id), Vec::new()).await.unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let text = std::str::from_utf8(&bytes).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(text).unwrap()["label"], "cam \u{FFFD} 1");
+
+        let body = [br#"{"format":"urn:x-nmos:format:video","label":""#.as_slice(), b"\xFF\xFE", br#"","tags":{}}"#].concat();
+        let response = send("POST", "/sources".to_string(), body).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json; charset=utf-8");
+    }
+
+    #[tokio::test]
+    async fn test_validate_flow_never_writes() {
+        use tower::ServiceExt;
+
+        let (state, _temp_dir) = create_test_state().await;
+        let app = axum::Router::new()
+            .route("/flows/validate", axum::routing::post(validate_flow))
+            .with_state(state.clone())
+            .layer(axum::middleware::from_fn(crate::warnings::warnings_middleware));
+        let validate = |body: Value| {
+            let request = axum::http::Request::post("/flows/validate")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(axum::body::Body::from(body.to_string()))
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice::<Value>(&bytes).unwrap())
+            }
+        };
+
+        let existing = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&existing).await.unwrap();
+        let (status, body) = validate(json!({
+            "id": existing.id,
+            "format": "urn:x-nmos:format:video",
+            "container": " video/mp2t",
+            "tags": {}
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["valid"], true);
+        assert_eq!(body["flow"]["container"], "video/mp2t");
+        let codes: Vec<&str> = body["warnings"].as_array().unwrap().iter().map(|w| w["code"].as_str().unwrap()).collect();
+        assert_eq!(codes, vec!["normalized", "id_in_use"]);
+
+        let (status, body) = validate(json!({"format": "urn:x-nmos:format:audio", "tags": {}})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["warnings"], json!([]));
+
+        // Every problem is reported, each with its field
+        let id = Uuid::new_v4();
+        let (status, body) = validate(json!({
+            "id": id,
+            "format": "urn:x-tam:format:image",
+            "container": "video/mp2t",
+            "tags": {},
+            "flow_collection": {"flows": [{"flow_id": id, "role": "self"}]}
+        }))
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "validation_failed");
+        let fields: Vec<&str> = body["errors"].as_array().unwrap().iter().map(|e| e["field"].as_str().unwrap()).collect();
+        assert_eq!(fields, vec!["container", "flow_collection"]);
+
+        assert_eq!(state.database.list_flows(false, None).await.unwrap().items.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_flow_notify_url_receives_only_its_own_events() {
+        let (state, _temp_dir) = create_test_state_with(|config| config.validation.allow_private_notify_urls = true).await;
+        let (url, received) = crate::webhooks::tests::spawn_webhook_receiver().await;
+        let request = |body: Value| Json(serde_json::from_value::<CreateFlowRequest>(body).unwrap());
+
+        let Created { body: flow, .. } = create_flow(
+            State(state.clone()),
+            Warnings::default(),
+            None,
+            request(json!({"format": "urn:x-nmos:format:video", "tags": {}, "notify_url": url, "notify_secret": "s3cret"})),
+        )
+        .await
+        .unwrap();
+        assert!(serde_json::to_value(&flow).unwrap().get("notify_secret").is_none());
+        let Created { body: other, .. } =
+            create_flow(State(state.clone()), Warnings::default(), None, request(json!({"format": "urn:x-nmos:format:video", "tags": {}}))).await.unwrap();
+
+        for flow_id in [other.id, flow.id] {
+            let segment = CreateSegmentRequest::new(&format!("obj-{}", flow_id), "0:0", "1:0");
+            add_flow_segment(Path(flow_id), State(state.clone()), Warnings::default(), Json(segment)).await.unwrap();
+        }
+        delete_flow(Path(other.id), State(state.clone())).await.unwrap();
+        delete_flow(Path(flow.id), State(state.clone())).await.unwrap();
+
+        // Deliveries run in the background
+        for _ in 0..50 {
+            if received.lock().unwrap().len() >= 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let events: Vec<(String, String)> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| (e["event_type"].as_str().unwrap().to_string(), e["event"]["flow_id"].as_str().unwrap().to_string()))
+            .collect();
+        let flow_id = flow.id.to_string();
+        assert_eq!(
+            events,
+            vec![("flows/segments_added".to_string(), flow_id.clone()), ("flows/deleted".to_string(), flow_id)]
+        );
+        assert_eq!(state.webhook_manager.get_webhook_count().await, 0);
+
+        // Private targets and unsigned hooks are refused by default
+        let (strict, _temp_dir) = create_test_state().await;
+        for body in [
+            json!({"format": "urn:x-nmos:format:video", "tags": {}, "notify_url": "http://127.0.0.1:9/hook", "notify_secret": "s"}),
+            json!({"format": "urn:x-nmos:format:video", "tags": {}, "notify_url": "http://[fd00::1]/hook", "notify_secret": "s"}),
+            json!({"format": "urn:x-nmos:format:video", "tags": {}, "notify_url": "https://hooks.example.com/tams"}),
+            json!({"format": "urn:x-nmos:format:video", "tags": {}, "notify_url": "ftp://hooks.example.com/", "notify_secret": "s"}),
+        ] {
+            let result = create_flow(State(strict.clone()), Warnings::default(), None, request(body)).await;
+            assert!(matches!(result, Err(TamsError::Validation(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_missing_flow_format_warns_or_is_refused() {
+        let request = || Json(serde_json::from_value::<CreateFlowRequest>(json!({"tags": {}})).unwrap());
+
+        let (state, _temp_dir) = create_test_state().await;
+        let warnings = Warnings::default();
+        let Created { body: flow, .. } = create_flow(State(state.clone()), warnings.clone(), None, request()).await.unwrap();
+        assert_eq!(flow.format, ContentFormat::Data);
+        let collected = warnings.take();
+        assert_eq!(collected.len(), 1);
+        assert_eq!((collected[0].code.as_str(), collected[0].field.as_deref()), ("format_defaulted", Some("format")));
+
+        let (strict, _temp_dir) = create_test_state_with(|config| config.validation.require_flow_format = true).await;
+        let warnings = Warnings::default();
+        let result = create_flow(State(strict.clone()), warnings.clone(), None, request()).await;
+        assert!(matches!(result, Err(TamsError::Validation(msg)) if msg.contains("format")));
+        assert!(warnings.take().is_empty());
+        assert!(strict.database.list_flows(false, None).await.unwrap().items.is_empty());
+        let Json(validation) = validate_flow(
+            State(state.clone()),
+            Warnings::default(),
+            Json(serde_json::from_value(json!({"tags": {}, "format": "urn:x-nmos:format:audio"})).unwrap()),
+        )
+        .await
+        .unwrap();
+        assert!(validation.warnings.is_empty());
+        let result = validate_flow(State(strict), Warnings::default(), request()).await;
+        assert!(matches!(result, Err(TamsError::InvalidFields(errors)) if errors[0].field == "format"));
+    }
+
+    #[tokio::test]
+    async fn test_service_default_and_required_tags() {
+        let (state, _temp_dir) = create_test_state_with(|config| {
+            config.service.default_tags = HashMap::from([
+                ("facility".to_string(), "london".to_string()),
+                ("environment".to_string(), "prod".to_string()),
+            ]);
+            config.service.required_tags = vec!["facility".to_string(), "show".to_string()];
+        })
+        .await;
+
+        let request = |tags: serde_json::Value| -> CreateFlowRequest {
+            serde_json::from_value(json!({"format": "urn:x-nmos:format:video", "tags": tags})).unwrap()
+        };
+
+        let flow = create_flow(State(state.clone()), Warnings::default(), None, Json(request(json!({"show": "news", "environment": "staging"}))))
+            .await
+            .unwrap()
+            .body;
+        assert_eq!(flow.tags["facility"], "london");
+        assert_eq!(flow.tags["environment"], "staging");
+        assert_eq!(state.database.get_flow_required(&flow.id).await.unwrap().tags["facility"], "london");
+
+        let err = create_flow(State(state.clone()), Warnings::default(), None, Json(request(json!({}))))
+            .await
+            .unwrap_err();
+        assert!(matches!(&err, TamsError::Validation(msg) if msg.contains("show") && !msg.contains("facility")));
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+
+        let source: CreateSourceRequest =
+            serde_json::from_value(json!({"id": Uuid::new_v4(), "format": "urn:x-nmos:format:video", "tags": {"show": "news"}}))
+                .unwrap();
+        let source = create_source(State(state.clone()), None, Json(source)).await.unwrap().body;
+        assert_eq!(source.tags["environment"], "prod");
+
+        let Json(info) = get_service_info(State(state.clone())).await.unwrap();
+        assert_eq!(info.capabilities.required_tags, vec!["facility", "show"]);
+        assert_eq!(info.capabilities.default_tags.len(), 2);
+        assert_eq!(info.schema_version, Some(crate::database::SCHEMA_VERSION));
+        assert_eq!(info.binary_version, env!("CARGO_PKG_VERSION"));
+    }
+
+    #[tokio::test]
+    async fn test_service_info_has_spec_fields() {
+        let (state, _temp_dir) = create_test_state_with(|config| {
+            config.service.public_url_base = "https://tams.example.com/".to_string();
+        })
+        .await;
+        let Json(info) = get_service_info(State(state.clone())).await.unwrap();
+        let info = serde_json::to_value(info).unwrap();
+        assert_eq!(info["type"], "urn:x-tams:service.tams-rust");
+        assert_eq!(info["api_version"], "6.0");
+        assert_eq!(info["service_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(info["name"], state.config.service.name.as_str());
+        assert_eq!(info["media_store"], json!({"type": "http_object_store"}));
+        assert_eq!(
+            info["event_stream_mechanisms"],
+            json!([{"name": "webhooks", "url": "https://tams.example.com/x-tams/v6.0/service/webhooks"}])
+        );
+        // Fields older clients read are still there
+        assert_eq!(info["media_store_type"], "http_object_store");
+        assert_eq!(info["media_stores"][0]["primary"], true);
+    }
+
+    #[tokio::test]
+    async fn test_time_format_query_selects_tams_timestamps() {
+        use tower::ServiceExt;
+
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        let segment = CreateSegmentRequest::new("obj", "0:0", "10:0");
+        state.database.add_flow_segment(&segment.into_segment(flow.id)).await.unwrap();
+
+        let app = axum::Router::new()
+            .route("/flows/:flow_id", axum::routing::get(get_flow))
+            .route("/flows/:flow_id/segments", axum::routing::get(list_flow_segments))
+            .with_state(state.clone())
+            .layer(axum::middleware::from_fn(time_format_middleware));
+        let get = |uri: String| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(axum::http::Request::get(uri).body(axum::body::Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice::<Value>(&bytes).unwrap())
+            }
+        };
+        let is_tams = |value: &Value| time_utils::parse_tams_timestamp(value.as_str().unwrap()).is_ok();
+
+        let (_, body) = get(format!("/flows/{}", flow.id)).await;
+        assert!(!is_tams(&body["created_at"]));
+
+        let (_, body) = get(format!("/flows/{}?time_format=tams", flow.id)).await;
+        assert!(is_tams(&body["created_at"]) && is_tams(&body["updated_at"]));
+        assert_eq!(body["created_at"], time_utils::format_tams_timestamp(&flow.created_at));
+
+        let (_, body) = get(format!("/flows/{}/segments?time_format=tams", flow.id)).await;
+        assert!(is_tams(&body["segments"][0]["created_at"]));
+
+        let (status, _) = get(format!("/flows/{}?time_format=epoch", flow.id)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_source_coverage_diff() {
+        let (state, _temp_dir) =
+            create_test_state_with(|config| config.validation.max_query_duration_seconds = Some(100)).await;
+        let source = Source::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_source(&source).await.unwrap();
+
+        let mut flows = Vec::new();
+        for ranges in [vec![("0:0", "60:0"), ("60:0", "120:0")], vec![("0:0", "50:0"), ("100:0", "130:0")]] {
+            let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+            flow.source_id = Some(source.id);
+            state.database.create_flow(&flow).await.unwrap();
+            for (i, (start, end)) in ranges.into_iter().enumerate() {
+                let segment = CreateSegmentRequest {
+                    ts_offset: Some(start.to_string()),
+                    ..CreateSegmentRequest::new(&format!("obj-{}", i), start, end)
+                };
+                state.database.add_flow_segment(&segment.into_segment(flow.id)).await.unwrap();
+            }
+            flows.push(flow.id);
+        }
+        let params = |extra: &[(&str, &str)]| {
+            let mut params = HashMap::from([
+                ("flow_a".to_string(), flows[0].to_string()),
+                ("flow_b".to_string(), flows[1].to_string()),
+            ]);
+            params.extend(extra.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+            Query(params)
+        };
+
+        let Json(diff) = get_source_coverage_diff(Path(source.id), params(&[]), State(state.clone()), None)
+            .await
+            .unwrap();
+        assert_eq!(diff.only_a.total_duration, "50:000000000");
+        assert_eq!(diff.only_b.total_duration, "10:000000000");
+        assert_eq!(diff.both.total_duration, "70:000000000");
+        assert_eq!(diff.both.count, 2);
+
+        let Json(bounded) = get_source_coverage_diff(
+            Path(source.id),
+            params(&[("timerange", "[40:0_110:0)"), ("limit", "1")]),
+            State(state.clone()),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(bounded.only_a.total_duration, "50:000000000");
+        assert_eq!(bounded.both.total_duration, "20:000000000");
+        assert!(bounded.both.truncated);
+        assert_eq!(bounded.both.timeranges.len(), 1);
+        // A window wider than the maximum query duration is refused
+        let wide = params(&[("timerange", "[0:0_200:0)")]);
+        let err = get_source_coverage_diff(Path(source.id), wide, State(state.clone()), None).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+
+        let stray = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&stray).await.unwrap();
+        let mut query = params(&[]);
+        query.0.insert("flow_b".to_string(), stray.id.to_string());
+        let err = get_source_coverage_diff(Path(source.id), query, State(state.clone()), None).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_flow_gaps_within_window() {
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        let gaps = |pairs: &[(&str, &str)]| {
+            let params = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            get_flow_gaps(Path(flow.id), Query(params), State(state.clone()))
+        };
+        let ranges = |gaps: &FlowGaps| -> Vec<(String, String)> {
+            gaps.gaps.timeranges.iter().map(|r| (r.start.clone(), r.end.clone().unwrap())).collect()
+        };
+        let pair = |start: &str, end: &str| (start.to_string(), end.to_string());
+
+        let Json(empty) = gaps(&[]).await.unwrap();
+        assert!(empty.timerange.is_none() && empty.gaps.timeranges.is_empty());
+
+        for (object_id, start, end) in [("a", "0:0", "10:0"), ("b", "10:0", "20:0"), ("c", "25:0", "30:0"), ("d", "27:0", "35:0")] {
+            let segment = CreateSegmentRequest::new(object_id, start, end);
+            state.database.add_flow_segment(&segment.into_segment(flow.id)).await.unwrap();
+        }
+
+        let Json(extent) = gaps(&[]).await.unwrap();
+        assert_eq!(ranges(&extent), vec![pair("20:000000000", "25:000000000")]);
+        assert_eq!(extent.gaps.total_duration, "5:000000000");
+
+        let Json(wide) = gaps(&[("start", "0:0"), ("end", "40:0")]).await.unwrap();
+        assert_eq!(
+            ranges(&wide),
+            vec![pair("20:000000000", "25:000000000"), pair("35:000000000", "40:000000000")]
+        );
+
+        let Json(covered) = gaps(&[("start", "2:0"), ("end", "20:0")]).await.unwrap();
+        assert!(covered.gaps.timeranges.is_empty());
+
+        let err = gaps(&[("start", "40:0"), ("end", "0:0")]).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+
+        // A window wider than the maximum query duration is refused, the flow's extent included
+        let (limited, _limited_dir) =
+            create_test_state_with(|config| config.validation.max_query_duration_seconds = Some(20)).await;
+        limited.database.create_flow(&flow).await.unwrap();
+        for segment in state.database.get_flow_segments(&flow.id).await.unwrap().items {
+            limited.database.add_flow_segment(&segment).await.unwrap();
+        }
+        let limited_gaps = |pairs: &[(&str, &str)]| {
+            let params = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            get_flow_gaps(Path(flow.id), Query(params), State(limited.clone()))
+        };
+        for window in [&[][..], &[("start", "0:0"), ("end", "40:0")][..]] {
+            let status = limited_gaps(window).await.unwrap_err().into_response().status();
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{:?}", window);
+        }
+        assert!(limited_gaps(&[("start", "2:0"), ("end", "20:0")]).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_segment_rollup_buckets() {
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        for (object_id, start, end) in [("obj-a", "0:0", "45:0"), ("obj-b", "45:0", "90:0"), ("obj-c", "150:0", "160:0")] {
+            let segment = CreateSegmentRequest::new(object_id, start, end);
+            state.database.add_flow_segment(&segment.into_segment(flow.id)).await.unwrap();
+        }
+        let object = MediaObject {
+            object_id: "obj-a".to_string(),
+            size_bytes: Some(90),
+            mime_type: None,
+            flow_references: vec![FlowReference::new(flow.id)],
+            created_at: chrono::Utc::now(),
+            metadata: HashMap::new(),
+            storage_class: None,
+            format: None,
+            content_hash: None,
+        };
+        state.database.create_media_object(&object).await.unwrap();
+        let rollup = |pairs: &[(&str, &str)]| {
+            let params = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            get_flow_segment_rollup(Path(flow.id), Query(params), State(state.clone()))
+        };
+
+        let Json(minutes) = rollup(&[("bucket", "60:0")]).await.unwrap();
+        let summary: Vec<_> = minutes
+            .buckets
+            .iter()
+            .map(|b| (b.start.as_str(), b.covered_duration.as_str(), b.segment_count, b.byte_estimate))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("0:000000000", "60:000000000", 2, 90),
+                ("60:000000000", "30:000000000", 1, 0),
+                ("120:000000000", "10:000000000", 1, 0),
+            ]
+        );
+
+        // A window clips the segments at its edges and can include empty buckets
+        let Json(bounded) = rollup(&[("bucket", "60:0"), ("timerange", "[30:0_200:0)"), ("include_empty", "true")])
+            .await
+            .unwrap();
+        assert_eq!(bounded.buckets.len(), 4);
+        assert_eq!(bounded.buckets[0].covered_duration, "30:000000000");
+        assert_eq!(bounded.buckets[0].byte_estimate, 30);
+        assert_eq!(bounded.buckets[3].segment_count, 0);
+        assert_eq!(bounded.timerange.unwrap().end.as_deref(), Some("240:000000000"));
+
+        for bad in [vec![("bucket", "0:0")], vec![("bucket", "0:1")], vec![]] {
+            let err = rollup(&bad).await.unwrap_err();
+            assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_segment_rollup_refuses_windows_it_cannot_count() {
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        let mut open = CreateSegmentRequest::new("obj-open", "10:0", "20:0");
+        open.timerange.end = None;
+        state.database.add_flow_segment(&open.into_segment(flow.id)).await.unwrap();
+        let rollup = |pairs: &[(&str, &str)]| {
+            let params = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            get_flow_segment_rollup(Path(flow.id), Query(params), State(state.clone()))
+        };
+
+        // The open-ended segment's extent runs to the end of time
+        let err = rollup(&[("bucket", "9223372036:0")]).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+
+        // A bucket near i64::MAX nanoseconds would overflow the bucket arithmetic
+        let err = rollup(&[("bucket", "9223372036:0"), ("timerange", "[10:0_9223372036:0)")]).await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+
+        let Json(bounded) = rollup(&[("bucket", "60:0"), ("timerange", "[0:0_120:0)")]).await.unwrap();
+        let covered: Vec<_> = bounded.buckets.iter().map(|b| b.covered_duration.as_str()).collect();
+        assert_eq!(covered, ["50:000000000", "60:000000000"]);
+    }
+
+    #[tokio::test]
+    async fn test_update_flow_rejects_available_timerange_hiding_segments() {
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+
+        let update = |start: &str, end: &str| UpdateFlowRequest {
+            available_timerange: Some(TimeRange::new(start, Some(end))),
+            ..Default::default()
+        };
+
+        // Without segments any range is accepted
+        assert!(call_update_flow(&state, flow.id, HashMap::new(), update("5:0", "6:0"))
+            .await
+            .is_ok());
+
+        let segment = CreateSegmentRequest::new("obj", "10:0", "20:0");
+        state.database.add_flow_segment(&segment.into_segment(flow.id)).await.unwrap();
+
+        let result = call_update_flow(&state, flow.id, HashMap::new(), update("12:0", "20:0")).await;
+        assert!(matches!(result, Err(TamsError::Conflict(msg)) if msg.contains("[10:000000000_12:000000000)")));
+
+        assert!(call_update_flow(&state, flow.id, HashMap::new(), update("0:0", "30:0"))
+            .await
+            .is_ok());
+
+        // Only an admin may force a range that hides segments, or anyone without auth
+        let mut force = HashMap::new();
+        force.insert("force".to_string(), "true".to_string());
+        let user = Principal { name: "user".to_string(), admin: false };
+        let result = call_update_flow_as(&state, flow.id, force.clone(), update("12:0", "20:0"), Some(user)).await;
+        assert!(matches!(result, Err(TamsError::Forbidden(_))));
+        let admin = Principal { name: "admin".to_string(), admin: true };
+        assert!(call_update_flow_as(&state, flow.id, force.clone(), update("12:0", "20:0"), Some(admin))
+            .await
+            .is_ok());
+        assert!(call_update_flow(&state, flow.id, force, update("12:0", "20:0")).await.is_ok());
+
+        // A deletion in progress only lets through a range excluding what it deletes
+        let head = CreateDeletionRequest { flow_id: flow.id, timerange: Some(json!("[10:0_12:0)")) };
+        assert!(request_flow_deletion(State(state.clone()), None, Json(head)).await.unwrap().0.is_active());
+        assert!(call_update_flow(&state, flow.id, HashMap::new(), update("12:0", "20:0"))
+            .await
+            .is_ok());
+        let result = call_update_flow(&state, flow.id, HashMap::new(), update("15:0", "20:0")).await;
+        assert!(matches!(result, Err(TamsError::Conflict(msg)) if msg.contains("[12:000000000_15:000000000)")));
+
+        let whole_flow = CreateDeletionRequest { flow_id: flow.id, timerange: None };
+        let request = request_flow_deletion(State(state.clone()), None, Json(whole_flow))
+            .await
+            .unwrap();
+        assert!(request.0.is_active());
+        assert!(call_update_flow(&state, flow.id, HashMap::new(), update("15:0", "20:0"))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_writes_wait_for_active_deletion_requests() {
+        use crate::config::WritesDuringDeletionPolicy;
+
+        let segment = |object_id: &str, start: &str, end: &str| Json(CreateSegmentRequest::new(object_id, start, end));
+        let request_deletion = |state: &AppState, flow_id: Uuid, timerange: &str| {
+            let payload = CreateDeletionRequest { flow_id, timerange: Some(json!(timerange)) };
+            request_flow_deletion(State(state.clone()), None, Json(payload))
+        };
+
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        add_flow_segment(Path(flow.id), State(state.clone()), Warnings::default(), segment("a", "0:0", "5:0")).await.unwrap();
+
+        let Json(request) = request_deletion(&state, flow.id, "[0:0_5:0)").await.unwrap();
+        let result = add_flow_segment(Path(flow.id), State(state.clone()), Warnings::default(), segment("b", "10:0", "15:0")).await;
+        assert!(matches!(result, Err(TamsError::Conflict(msg)) if msg.contains(&request.id)));
+        let result = allocate_storage(Path(flow.id), Query(HashMap::new()), State(state.clone()), Warnings::default(), None).await;
+        assert!(matches!(result, Err(TamsError::Conflict(_))));
+
+        assert_eq!(deletion::run_due_deletions(&state).await.unwrap(), 1);
+        add_flow_segment(Path(flow.id), State(state.clone()), Warnings::default(), segment("b", "10:0", "15:0")).await.unwrap();
+        let objects = state.database.get_flow_segments(&flow.id).await.unwrap().items;
+        assert_eq!(objects.iter().map(|s| s.object_id.as_str()).collect::<Vec<_>>(), vec!["b"]);
+
+        // Writes clear of the range being deleted may go ahead when configured
+        let (lenient, _temp_dir) =
+            create_test_state_with(|config| config.deletion.writes_during_deletion = WritesDuringDeletionPolicy::OutsideTimerange).await;
+        lenient.database.create_flow(&flow).await.unwrap();
+        let _ = request_deletion(&lenient, flow.id, "[0:0_10:0)").await.unwrap();
+        add_flow_segment(Path(flow.id), State(lenient.clone()), Warnings::default(), segment("c", "10:0", "15:0")).await.unwrap();
+        let result = add_flow_segment(Path(flow.id), State(lenient.clone()), Warnings::default(), segment("d", "8:0", "12:0")).await;
+        assert!(matches!(result, Err(TamsError::Conflict(_))));
+        assert!(allocate_storage(Path(flow.id), Query(HashMap::new()), State(lenient.clone()), Warnings::default(), None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_update_flow_keeps_format_and_source_once_segments_exist() {
+        let (state, _temp_dir) = create_test_state().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_flow(&flow).await.unwrap();
+        let source = Source::new(Uuid::new_v4(), ContentFormat::Audio);
+        state.database.create_source(&source).await.unwrap();
+
+        let change_format = |format: ContentFormat| UpdateFlowRequest {
+            format: Some(format),
+            ..Default::default()
+        };
+        let move_to_source = UpdateFlowRequest {
+            source_id: Some(source.id),
+            ..Default::default()
+        };
+
+        // An empty flow may still be redefined
+        let updated = call_update_flow(&state, flow.id, HashMap::new(), change_format(ContentFormat::Audio)).await.unwrap();
+        assert_eq!(updated.0.format, ContentFormat::Audio);
+
+        let segment = CreateSegmentRequest::new("obj", "0:0", "1:0");
+        state.database.add_flow_segment(&segment.clone().into_segment(flow.id)).await.unwrap();
+
+        let result = call_update_flow(&state, flow.id, HashMap::new(), change_format(ContentFormat::Video)).await;
+        assert!(matches!(result, Err(TamsError::Conflict(msg)) if msg.contains("format")));
+        let result = call_update_flow(&state, flow.id, HashMap::new(), move_to_source.clone()).await;
+        assert!(matches!(result, Err(TamsError::Conflict(msg)) if msg.contains("source_id")));
+        // Restating the current value is not a change
+        assert!(call_update_flow(&state, flow.id, HashMap::new(), change_format(ContentFormat::Audio)).await.is_ok());
+        assert_eq!(state.database.get_flow_required(&flow.id).await.unwrap().format, ContentFormat::Audio);
+
+        let (lenient, _temp_dir) = create_test_state_with(|config| config.validation.immutable_fields_with_segments = false).await;
+        lenient.database.create_flow(&flow).await.unwrap();
+        lenient.database.create_source(&source).await.unwrap();
+        lenient.database.add_flow_segment(&segment.into_segment(flow.id)).await.unwrap();
+        let updated = call_update_flow(&lenient, flow.id, HashMap::new(), move_to_source).await.unwrap();
+        assert_eq!(updated.0.source_id, Some(source.id));
+    }
+
+    #[tokio::test]
+    async fn test_object_metadata_from_headers_and_companion_post() {
+        let (state, _temp_dir) = create_test_state().await;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Object-Meta-Encoder", "ffmpeg 6.1".parse().unwrap());
+        headers.insert("x-object-meta-checksum-algorithm", "sha256".parse().unwrap());
+        headers.insert(header::CONTENT_TYPE, "video/mp2t".parse().unwrap());
+        put_media_object(
+            Path("meta-object".to_string()),
+            Query(HashMap::new()),
+            State(state.clone()),
+            headers,
+            axum::body::Body::from("ts bytes"),
+        )
+        .await
+        .unwrap();
+
+        let object = state.database.get_media_object_required("meta-object").await.unwrap();
+        assert_eq!(object.metadata.len(), 2);
+        assert_eq!(object.metadata["encoder"], "ffmpeg 6.1");
+        assert_eq!(object.metadata["checksum-algorithm"], "sha256");
+
+        let mut entries = HashMap::new();
+        entries.insert("origin".to_string(), "camera-3".to_string());
+        entries.insert("encoder".to_string(), "x264".to_string());
+        let updated = update_media_object_metadata(Path("meta-object".to_string()), State(state.clone()), Json(entries.clone()))
+            .await
+            .unwrap();
+        assert_eq!(updated.0.metadata["origin"], "camera-3");
+        assert_eq!(updated.0.metadata["encoder"], "x264");
+        assert_eq!(updated.0.metadata["checksum-algorithm"], "sha256");
+
+        let result = update_media_object_metadata(Path("missing".to_string()), State(state.clone()), Json(entries)).await;
+        assert!(matches!(result, Err(TamsError::ObjectNotFound { .. })));
+    }
+}
@@ -0,0 +1,91 @@
+//! HEAD for every GET route.
+//!
+//! axum answers a HEAD request with the matching GET route and strips the
+//! body on the way out, after every middleware has had its say, so auth,
+//! ownership, warnings and each handler's own headers (ETag, Cache-Control,
+//! ...) apply exactly as they would to the GET, and Content-Length is taken
+//! from the body the GET would have sent. Routes must not add a separate
+//! `.head()` handler, which would drift from the GET.
+//!
+//! What that leaves is the cost of building a body nobody reads. Handlers
+//! whose bodies are costly take a [`Head`] and ask [`Head::skip_body`] before
+//! doing body-only work such as generating get_urls. A body left incomplete
+//! would report the wrong length, so `head_middleware` sends those HEAD
+//! responses without a Content-Length. Object downloads instead answer HEAD
+//! from the file's metadata with its own Content-Length, never reading the
+//! object.
+
+use axum::{
+    async_trait,
+    body::{Body, Bytes},
+    extract::{FromRequestParts, Request},
+    http::{header, request::Parts, Method},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    convert::Infallible,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// Whether this request is a HEAD, whose response body is thrown away
+#[derive(Debug, Clone, Default)]
+pub struct Head {
+    is_head: bool,
+    body_skipped: Arc<AtomicBool>,
+}
+
+impl Head {
+    pub fn new(is_head: bool) -> Self {
+        Head {
+            is_head,
+            body_skipped: Arc::default(),
+        }
+    }
+
+    pub fn is_head(&self) -> bool {
+        self.is_head
+    }
+
+    /// True when the handler may leave out work that only shapes the body.
+    /// Asking on a HEAD request marks the body incomplete.
+    pub fn skip_body(&self) -> bool {
+        if self.is_head {
+            self.body_skipped.store(true, Ordering::Relaxed);
+        }
+        self.is_head
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Head {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let head = parts.extensions.get::<Head>().cloned();
+        Ok(head.unwrap_or_else(|| Head::new(parts.method == Method::HEAD)))
+    }
+}
+
+/// Give HEAD requests a [`Head`] to consult, and drop the Content-Length of
+/// responses whose handler skipped part of the body
+pub async fn head_middleware(mut request: Request, next: Next) -> Response {
+    if request.method() != Method::HEAD {
+        return next.run(request).await;
+    }
+    let head = Head::new(true);
+    request.extensions_mut().insert(head.clone());
+    let response = next.run(request).await;
+    if !head.body_skipped.load(Ordering::Relaxed) {
+        return response;
+    }
+
+    // A body of unknown length, so the router doesn't derive one before stripping it
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let unsized_body = futures_util::stream::empty::<Result<Bytes, Infallible>>();
+    Response::from_parts(parts, Body::from_stream(unsized_body))
+}
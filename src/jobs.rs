@@ -0,0 +1,212 @@
+use crate::{
+    clock::{system_clock, SharedClock},
+    config::JobsConfig,
+    database::Database,
+    error::TamsResult,
+    models::Job,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+
+/// Durable, at-least-once background work backed by the `jobs` table.
+///
+/// Workers claim due jobs of their kind, which leases them for `lease_seconds`,
+/// then complete or fail each one. A job that is neither, because its worker
+/// died, is claimed again when the lease runs out, so job handlers must
+/// tolerate running more than once.
+#[derive(Clone)]
+pub struct JobQueue {
+    database: Database,
+    config: Arc<RwLock<JobsConfig>>,
+    clock: SharedClock,
+}
+
+impl JobQueue {
+    pub fn new(database: Database, config: JobsConfig) -> Self {
+        Self {
+            database,
+            config: Arc::new(RwLock::new(config)),
+            clock: system_clock(),
+        }
+    }
+
+    /// Read due times and backoff deadlines from `clock`
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn config(&self) -> JobsConfig {
+        self.config.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Take up new retry settings from a config reload; jobs already scheduled
+    /// keep their run times
+    pub fn set_retry_policy(&self, config: &JobsConfig) {
+        let mut current = self.config.write().unwrap_or_else(|e| e.into_inner());
+        current.retry_base_seconds = config.retry_base_seconds;
+        current.retry_max_seconds = config.retry_max_seconds;
+        current.max_attempts = config.max_attempts;
+    }
+
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.config().poll_interval_seconds.max(1))
+    }
+
+    /// Queue a job that is due immediately
+    pub async fn enqueue(&self, kind: &str, target: Option<&str>, payload: &impl Serialize) -> TamsResult<Job> {
+        let job = Job::new(kind, target, serde_json::to_value(payload)?, self.clock.now());
+        self.database.create_job(&job).await?;
+        Ok(job)
+    }
+
+    pub async fn claim_due(&self, kind: &str, limit: u32) -> TamsResult<Vec<Job>> {
+        let now = self.clock.now();
+        let lease_until = now + self.lease();
+        self.database.claim_due_jobs(kind, now, lease_until, limit).await
+    }
+
+    pub async fn complete(&self, job: &Job) -> TamsResult<()> {
+        self.database.finish_job_attempt(&job.id, Job::COMPLETED, self.clock.now(), None).await
+    }
+
+    /// Record a failed attempt and schedule a retry after an exponential backoff,
+    /// or mark the job failed once its attempts are used up. Returns whether the
+    /// job will be retried.
+    pub async fn fail_with_backoff(&self, job: &Job, error: &str) -> TamsResult<bool> {
+        if job.attempts >= self.config().max_attempts {
+            self.database.finish_job_attempt(&job.id, Job::FAILED, self.clock.now(), Some(error)).await?;
+            return Ok(false);
+        }
+        let run_at = self.clock.now() + self.backoff(job.attempts);
+        self.database.finish_job_attempt(&job.id, Job::PENDING, run_at, Some(error)).await?;
+        Ok(true)
+    }
+
+    /// Put a claimed job back to run at `run_at`, for work that is paused rather
+    /// than failed; the attempt isn't counted towards `max_attempts`
+    pub async fn defer(&self, job: &Job, run_at: DateTime<Utc>) -> TamsResult<()> {
+        self.database.defer_job(&job.id, run_at, None).await
+    }
+
+    /// Defer a claimed job as [`Self::defer`] does, replacing its payload with
+    /// `payload`, for work done in steps that records how far it has got
+    pub async fn defer_with_payload(&self, job: &Job, run_at: DateTime<Utc>, payload: &impl Serialize) -> TamsResult<()> {
+        let payload = serde_json::to_value(payload)?;
+        self.database.defer_job(&job.id, run_at, Some(&payload)).await
+    }
+
+    /// How long a claimed job stays reserved for its worker
+    pub fn lease(&self) -> Duration {
+        Duration::seconds(self.config().lease_seconds as i64)
+    }
+
+    /// Delay before the next attempt of a job that has failed `attempts` times
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let config = self.config();
+        let doublings = attempts.saturating_sub(1).min(31);
+        let seconds = config
+            .retry_base_seconds
+            .saturating_mul(1u64 << doublings)
+            .min(config.retry_max_seconds);
+        Duration::seconds(seconds as i64)
+    }
+
+    pub async fn list(&self, kind: Option<&str>, statuses: Option<&[&str]>, limit: u32) -> TamsResult<Vec<Job>> {
+        self.database.list_jobs(kind, statuses, limit).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::{Clock, FakeClock},
+        database::tests::create_test_database,
+    };
+
+    fn queue(database: &Database, lease_seconds: u64) -> JobQueue {
+        JobQueue::new(
+            database.clone(),
+            JobsConfig {
+                lease_seconds,
+                max_attempts: 2,
+                ..JobsConfig::default()
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_claimed_jobs_are_leased_and_reclaimed_after_expiry() {
+        let (database, _temp_dir) = create_test_database().await;
+        let leased = queue(&database, 300);
+        let job = leased.enqueue("test", Some("one"), &serde_json::json!({ "n": 1 })).await.unwrap();
+        leased.enqueue("other", None, &serde_json::json!({})).await.unwrap();
+
+        let claimed = leased.claim_due("test", 10).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!((claimed[0].id.as_str(), claimed[0].status.as_str(), claimed[0].attempts), (job.id.as_str(), Job::RUNNING, 1));
+        assert_eq!(claimed[0].payload["n"], 1);
+        assert!(leased.claim_due("test", 10).await.unwrap().is_empty());
+
+        // A worker that never reports back loses its lease
+        let unleased = queue(&database, 0);
+        let reclaimed = unleased.claim_due("other", 10).await.unwrap();
+        assert_eq!(reclaimed.len(), 1);
+        let reclaimed = unleased.claim_due("other", 10).await.unwrap();
+        assert_eq!(reclaimed[0].attempts, 2);
+
+        leased.complete(&claimed[0]).await.unwrap();
+        let completed = leased.list(Some("test"), Some(&[Job::COMPLETED]), 10).await.unwrap();
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0].target.as_deref(), Some("one"));
+    }
+
+    #[tokio::test]
+    async fn test_failures_back_off_then_give_up() {
+        let (database, _temp_dir) = create_test_database().await;
+        let queue = queue(&database, 300);
+        queue.enqueue("test", None, &serde_json::json!({})).await.unwrap();
+
+        let job = queue.claim_due("test", 1).await.unwrap().remove(0);
+        assert!(queue.fail_with_backoff(&job, "first").await.unwrap());
+        // Not due again until the backoff has passed
+        assert!(queue.claim_due("test", 1).await.unwrap().is_empty());
+        let pending = queue.list(Some("test"), Some(&[Job::PENDING]), 10).await.unwrap();
+        assert_eq!(pending[0].last_error.as_deref(), Some("first"));
+
+        let retried = Job { attempts: 2, ..job };
+        assert!(!queue.fail_with_backoff(&retried, "second").await.unwrap());
+        assert_eq!(queue.list(None, Some(&[Job::FAILED]), 10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_job_timestamps_follow_the_injected_clock() {
+        let (database, _temp_dir) = create_test_database().await;
+        let clock = Arc::new(FakeClock::new(DateTime::from_timestamp(1_700_000_000, 0).unwrap()));
+        let database = database.with_clock(clock.clone());
+        let queue = queue(&database, 300).with_clock(clock.clone());
+        queue.enqueue("test", None, &serde_json::json!({})).await.unwrap();
+        let job = queue.claim_due("test", 1).await.unwrap().remove(0);
+
+        clock.advance(Duration::minutes(5));
+        queue.complete(&job).await.unwrap();
+        let completed = queue.list(Some("test"), Some(&[Job::COMPLETED]), 1).await.unwrap().remove(0);
+        assert_eq!(completed.updated_at, clock.now());
+        assert_eq!(completed.created_at, clock.now() - Duration::minutes(5));
+    }
+
+    #[tokio::test]
+    async fn test_backoff_doubles_up_to_the_cap() {
+        let (database, _temp_dir) = create_test_database().await;
+        let config = JobsConfig {
+            retry_base_seconds: 10,
+            retry_max_seconds: 60,
+            ..JobsConfig::default()
+        };
+        let queue = JobQueue::new(database, config);
+        let seconds: Vec<i64> = (1..=5).map(|attempts| queue.backoff(attempts).num_seconds()).collect();
+        assert_eq!(seconds, vec![10, 20, 40, 60, 60]);
+    }
+}
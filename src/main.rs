@@ -1,170 +1,282 @@
+mod archive;
 mod auth;
+mod behaviours;
+mod clock;
+mod concat;
 mod config;
+mod crypto;
 mod database;
+mod deletion;
+mod encoding;
 mod error;
+mod events;
 mod handlers;
+mod head;
+mod ingest;
+mod jobs;
+mod maintenance;
+mod metrics;
 mod models;
+mod normalize;
+mod ownership;
+mod query;
+mod reload;
+mod retention;
+mod routes;
+mod startup;
 mod storage;
+mod summary;
 mod time_utils;
+mod transaction;
+mod validation;
+mod warnings;
 mod webhooks;
 
 use crate::{
-    auth::{auth_middleware, AuthState},
     config::AppConfig,
+    crypto::SecretCipher,
     database::Database,
-    handlers::{*, AppState, AppStateInner},
+    error::TamsError,
+    handlers::{AppState, AppStateInner},
+    ingest::IngestControl,
+    jobs::JobQueue,
+    normalize::trailing_slash_middleware,
+    reload::{ConfigReloader, LogFilterHandle},
+    startup::{StartupContext, StartupError, StartupPhase},
     storage::MediaStorage,
-    webhooks::WebhookManager,
-};
-use axum::{
-    http::Method,
-    middleware,
-    routing::{delete, get, head, post, put},
-    Router,
+    webhooks::{seal_stored_webhook_keys, WebhookManager},
 };
+use axum::{extract::Request, middleware, ServiceExt};
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, process::ExitCode, sync::Arc};
 use tokio::signal;
-use tower::ServiceBuilder;
-use tower_http::{
-    cors::{Any, CorsLayer},
-    trace::TraceLayer,
-};
+use tower::Layer;
 use tracing::{info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use uuid::Uuid;
 
 // AppState is defined in handlers.rs
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            e.exit_code()
+        }
+    }
+}
+
+async fn run() -> Result<(), StartupError> {
     // Initialize configuration
-    let config = AppConfig::new().map_err(|e| {
-        eprintln!("Failed to load configuration: {}", e);
-        e
-    })?;
+    let config = AppConfig::new().phase(StartupPhase::Config)?;
 
     // Initialize logging
-    init_logging(&config.logging.level, &config.logging.format)?;
+    let log_filter = init_logging(&config.logging.level, &config.logging.format).phase(StartupPhase::Logging)?;
     info!("Starting TAMS Rust server...");
 
     // Initialize database
     info!("Initializing database...");
-    let database = Arc::new(Database::new(&config.database.url, config.database.max_connections).await?);
-    database.migrate().await?;
+    let clock = clock::system_clock();
+    let database = Arc::new(
+        Database::new(&config.database)
+            .await
+            .phase(StartupPhase::Database)?
+            .with_clock(clock.clone()),
+    );
+    database.migrate().await.phase(StartupPhase::Migration)?;
     info!("Database initialized successfully");
 
     // Initialize media storage
     info!("Initializing media storage...");
     let storage = Arc::new(MediaStorage::new(
         config.media_storage.clone(),
-        config.service.public_url_base.clone(),
-    )?);
-    storage.ensure_directories().await?;
+        config.service.api_url(""),
+    ).phase(StartupPhase::Storage)?.with_media_stores(&config.service).with_clock(clock.clone()));
+    storage.ensure_directories().await.phase(StartupPhase::Storage)?;
+
+    // Keep the storage stats cache warm
+    {
+        let storage = storage.clone();
+        let period = std::time::Duration::from_secs(config.media_storage.stats_refresh_interval_seconds.max(1));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                storage.spawn_storage_stats_refresh();
+            }
+        });
+    }
     info!("Media storage initialized successfully");
 
     // Initialize webhook manager
     info!("Initializing webhook manager...");
-    let webhook_manager = Arc::new(WebhookManager::new());
+    let cipher = SecretCipher::from_config(&config.webhooks, &config.auth.jwt_secret).phase(StartupPhase::Config)?;
+    seal_stored_webhook_keys(&database, cipher.as_ref()).await.phase(StartupPhase::Webhooks)?;
+    let webhook_manager = Arc::new(
+        WebhookManager::new()
+            .with_cipher(cipher)
+            .with_max_body_bytes(config.webhooks.max_body_bytes)
+            .with_event_buffer(config.events.buffer_events)
+            .with_delivery_timeout(std::time::Duration::from_secs(config.webhooks.delivery_timeout_seconds.max(1)))
+            .with_clock(clock.clone()),
+    );
     
     // Load existing webhooks from database
-    let _webhooks = database.get_webhooks_list().await?;
-    // Note: WebhookManager::new() doesn't need pre-loaded webhooks
+    database.purge_expired_webhook_keys(clock.now()).await.phase(StartupPhase::Webhooks)?;
+    let stored_webhooks = database.get_stored_webhooks().await.phase(StartupPhase::Webhooks)?;
+    webhook_manager.load_stored_webhooks(stored_webhooks).await;
+    for (flow_id, url, secret) in database.get_flow_webhooks().await.phase(StartupPhase::Webhooks)? {
+        webhook_manager.set_flow_webhook(flow_id, url, secret).await;
+    }
     info!("Webhook manager initialized");
 
+    // `tams-rust seed [sources] [flows_per_source] [segments_per_flow] [format]` fills the
+    // database with synthetic content and exits without serving
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("seed") {
+        let spec = maintenance::SeedSpec::from_args(&args[1..]).phase(StartupPhase::Config)?;
+        let report = maintenance::seed_database(&database, &webhook_manager, &clock, &spec)
+            .await
+            .phase(StartupPhase::Database)?;
+        info!(
+            "Seeded {} sources, {} flows and {} segments",
+            report.sources_created, report.flows_created, report.segments_created
+        );
+        return Ok(());
+    }
+    // `tams-rust move-storage-class <flow_id>` moves a flow's objects into the
+    // storage class its storage_class tag names and exits without serving
+    if args.first().map(String::as_str) == Some("move-storage-class") {
+        let flow_id = args
+            .get(1)
+            .ok_or_else(|| TamsError::Validation("move-storage-class takes a flow id".to_string()))
+            .and_then(|id| Ok(uuid::Uuid::parse_str(id)?))
+            .phase(StartupPhase::Config)?;
+        webhook_manager
+            .suppress_events("move_storage_class", maintenance::move_flow_storage_class(&database, &storage, &flow_id))
+            .await
+            .phase(StartupPhase::Storage)?;
+        return Ok(());
+    }
+
+    // Purge rotated-out webhook keys once their overlap window ends
+    {
+        let database = database.clone();
+        let webhook_manager = webhook_manager.clone();
+        let clock = clock.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                let now = clock.now();
+                match database.purge_expired_webhook_keys(now).await {
+                    Ok(purged) => {
+                        if purged > 0 {
+                            info!("Purged {} expired previous webhook keys", purged);
+                        }
+                        metrics::metrics().record_task_run("webhook_key_purge", now);
+                    }
+                    Err(e) => warn!("Failed to purge expired webhook keys: {}", e),
+                }
+                webhook_manager.purge_expired_keys(now).await;
+            }
+        });
+    }
+
+    // Correct per-flow segment stats that have drifted from the segments
+    if config.database.flow_stats_reconcile_interval_seconds > 0 {
+        let database = database.clone();
+        let clock = clock.clone();
+        let period = std::time::Duration::from_secs(config.database.flow_stats_reconcile_interval_seconds);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            // The first tick is immediate, and migrate has just filled in any missing stats
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match maintenance::reconcile_flow_stats(&database, maintenance::FLOW_STATS_BATCH_SIZE).await {
+                    Ok(_) => metrics::metrics().record_task_run("flow_stats_reconcile", clock.now()),
+                    Err(e) => warn!("Failed to reconcile flow stats: {}", e),
+                }
+            }
+        });
+    }
+
+    let ingest = Arc::new(IngestControl::load(&database).await.phase(StartupPhase::Database)?);
+    if ingest.is_service_paused() {
+        warn!("Ingest is paused service-wide; POST /admin/pause-ingest to resume");
+    }
+
+    let jobs = JobQueue::new((*database).clone(), config.jobs.clone()).with_clock(clock.clone());
+    let deletion_worker = Arc::new(
+        deletion::DeletionWorkerControl::load(&database, config.deletion.worker.clone())
+            .await
+            .phase(StartupPhase::Config)?,
+    );
+
     // Create application state
+    let reloader = Arc::new(ConfigReloader::new("config", config.clone(), Some(log_filter)));
     let app_state = Arc::new(AppStateInner {
         config,
         database: (*database).clone(),
         storage,
         webhook_manager,
+        ingest,
+        jobs,
+        deletion_worker,
+        reloader,
+        clock,
+        summary: Default::default(),
     });
+    spawn_reload_on_sighup(app_state.clone());
+    match deletion::enqueue_unqueued_requests(&app_state).await.phase(StartupPhase::Database)? {
+        0 => {}
+        queued => info!("Queued {} deletion requests that had no job", queued),
+    }
+    deletion::spawn_deletion_worker(app_state.clone());
+    retention::spawn_retention_worker(app_state.clone());
+
+    // Give segments stored before their bounds were kept as numbers those bounds
+    if maintenance::enqueue_segment_bounds_backfill(&app_state).await.phase(StartupPhase::Database)? {
+        let bounds = maintenance::segment_bounds_progress(&app_state).await.phase(StartupPhase::Database)?;
+        info!(
+            "Backfilling numeric bounds of {} segments in the background",
+            bounds.segments_total - bounds.segments_backfilled - bounds.segments_unparseable
+        );
+    }
+    maintenance::spawn_segment_bounds_backfill_worker(app_state.clone());
 
-    // Create auth state  
-    let auth_state = Arc::new(AuthState::new(app_state.config.auth.clone()));
-
-    // Build CORS layer
-    let cors = CorsLayer::new()
-        .allow_methods([
-            Method::GET,
-            Method::POST,
-            Method::PUT,
-            Method::DELETE,
-            Method::HEAD,
-            Method::OPTIONS,
-        ])
-        .allow_origin(Any)
-        .allow_headers(Any);
+    // Forget storage allocations once they have been expired for the temp file
+    // retention, which leaves that long to report an upload as too late
+    {
+        let state = app_state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+            loop {
+                interval.tick().await;
+                let now = state.clock.now();
+                let retention_hours = state.reloader.effective().cleanup.temp_file_retention_hours;
+                let expired_before = now - chrono::Duration::hours(retention_hours as i64);
+                match state.database.purge_expired_storage_allocations(expired_before).await {
+                    Ok(purged) => {
+                        if purged > 0 {
+                            info!("Purged {} expired storage allocations", purged);
+                        }
+                        metrics::metrics().record_task_run("storage_allocation_purge", now);
+                    }
+                    Err(e) => warn!("Failed to purge expired storage allocations: {}", e),
+                }
+            }
+        });
+    }
 
     // Build the application routes
-    let app = Router::new()
-        // Root endpoints
-        .route("/", get(get_root))
-        .route("/service", get(get_service_info))
-        .route("/test", get(get_test_page))
-        
-        // Sources endpoints
-        .route("/sources", get(list_sources).post(create_source))
-        .route("/sources/:source_id", 
-            get(get_source)
-                .put(update_source)
-                .delete(delete_source)
-        )
-        
-        // Flows endpoints
-        .route("/flows", get(list_flows).post(create_flow))
-        .route("/flows/:flow_id", 
-            get(get_flow)
-                .put(update_flow)
-                .delete(delete_flow)
-        )
-        
-        // Flow segments endpoints
-        .route("/flows/:flow_id/segments", 
-            get(list_flow_segments)
-                .post(add_flow_segment)
-                .delete(delete_flow_segments)
-        )
-        
-        // Flow storage endpoints
-        .route("/flows/:flow_id/storage", get(allocate_storage))
-        
-        // Media objects endpoints
-        .route("/objects/:object_id", 
-            get(get_media_object)
-                .put(put_media_object)
-        )
-        
-        // Webhook endpoints
-        .route("/service/webhooks", 
-            get(list_webhooks)
-                .post(create_webhook)
-        )
-        
-        // Flow delete request endpoints
-        .route("/flow-delete-requests", 
-            get(list_deletion_requests)
-                .post(request_flow_deletion)
-        )
-        .route("/flow-delete-requests/:request_id", get(get_deletion_request))
-        
-        // Add application state
-        .with_state(app_state.clone())
-        
-        // Add middleware layers
-        .layer(
-            ServiceBuilder::new()
-                .layer(TraceLayer::new_for_http())
-                .layer(cors)
-                .layer(middleware::from_fn_with_state(
-                    auth_state.clone(),
-                    auth_middleware,
-                ))
-        );
+    let app = routes::build_router(app_state.clone()).phase(StartupPhase::Config)?;
 
     // Create server address
     let addr = SocketAddr::from((
-        app_state.config.server.host.parse::<std::net::IpAddr>()?,
+        app_state.config.server.host.parse::<std::net::IpAddr>().phase(StartupPhase::Config)?,
         app_state.config.server.port,
     ));
 
@@ -175,22 +287,28 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("Database: {}", app_state.config.database.url);
 
     // Start the server
-    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let listener = tokio::net::TcpListener::bind(addr).await.phase(StartupPhase::Bind)?;
     
     info!("TAMS server starting on {}", addr);
     info!("API Documentation: {}/", addr);
     
-    axum::serve(listener, app)
+    // Wraps the router so that rewritten paths are routed
+    let app = middleware::from_fn_with_state(app_state.config.server.trailing_slash_equivalent, trailing_slash_middleware)
+        .layer(app);
+    axum::serve(listener, ServiceExt::<Request>::into_make_service(app))
         .with_graceful_shutdown(shutdown_signal())
-        .await?;
+        .await
+        .phase(StartupPhase::Serve)?;
 
     info!("TAMS server stopped");
     Ok(())
 }
 
-fn init_logging(level: &str, format: &str) -> Result<(), Box<dyn std::error::Error>> {
+/// Install the subscriber, returning a handle that lets a config reload change the level
+fn init_logging(level: &str, format: &str) -> Result<LogFilterHandle, Box<dyn std::error::Error>> {
     let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(level));
+    let (env_filter, handle) = tracing_subscriber::reload::Layer::new(env_filter);
 
     match format {
         "json" => {
@@ -213,7 +331,29 @@ fn init_logging(level: &str, format: &str) -> Result<(), Box<dyn std::error::Err
         }
     }
 
-    Ok(())
+    Ok(handle)
+}
+
+/// Reload the config file's hot-reloadable settings on every SIGHUP
+fn spawn_reload_on_sighup(state: AppState) {
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        let mut hangups = match signal::unix::signal(signal::unix::SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(e) => {
+                warn!("Config reload on SIGHUP is unavailable: {}", e);
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            info!("Received SIGHUP, reloading config...");
+            if let Err(e) = state.reloader.reload(&state.jobs, state.clock.now()) {
+                warn!("Config reload failed, keeping the current settings: {}", e);
+            }
+        }
+    });
+    #[cfg(not(unix))]
+    let _ = state;
 }
 
 async fn shutdown_signal() {
@@ -0,0 +1,573 @@
+use crate::{
+    clock::SharedClock,
+    config::ValidationConfig,
+    database::Database,
+    error::{TamsError, TamsResult},
+    handlers::AppState,
+    metrics::metrics,
+    models::{
+        ContentFormat, CreateSegmentRequest, EventNotification, EventType, Flow, FlowCreatedEvent, Job, SeedReport,
+        FlowStatsReconcileReport, SegmentBoundsProgress, SegmentsAddedEvent, Source, TimeRange, TimeRangeRecomputeReport,
+        VocabularyRepairReport,
+    },
+    storage::{MediaStorage, ObjectContext},
+    time_utils::{compare_tams_timestamps, covering_timerange, parse_segment_timerange},
+    validation::normalize_vocabulary,
+    webhooks::WebhookManager,
+};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, time::Duration};
+use uuid::Uuid;
+
+/// Flows examined per batch by [`recompute_available_timeranges`]
+pub const RECOMPUTE_BATCH_SIZE: u32 = 200;
+
+/// Recompute every flow's available_timerange from its segments and rewrite the
+/// ones that have drifted.
+///
+/// Flows are paged by id so the scan never holds a large result set, and the
+/// range arithmetic for each batch runs on the blocking pool.
+pub async fn recompute_available_timeranges(database: &Database, batch_size: u32) -> TamsResult<TimeRangeRecomputeReport> {
+    let mut report = TimeRangeRecomputeReport::default();
+    let mut after_id: Option<String> = None;
+
+    loop {
+        let page = database.get_flow_timerange_page(after_id.as_deref(), batch_size).await?;
+        let Some((last_id, _)) = page.last() else {
+            break;
+        };
+        after_id = Some(last_id.clone());
+
+        let mut batch = Vec::with_capacity(page.len());
+        for (flow_id, stored) in page {
+            let segments = database.get_segment_timeranges(&flow_id).await?;
+            batch.push((flow_id, stored, segments));
+        }
+
+        report.flows_checked += batch.len() as u64;
+        let corrections = tokio::task::spawn_blocking(move || {
+            batch
+                .into_iter()
+                .filter_map(|(flow_id, stored, segments)| {
+                    let ranges: Vec<TimeRange> = segments
+                        .iter()
+                        .filter_map(|s| parse_segment_timerange(s).ok())
+                        .collect();
+                    let computed = covering_timerange(&ranges).ok()?;
+                    let stored: Option<TimeRange> = stored.as_deref().and_then(|s| serde_json::from_str(s).ok());
+                    (!same_timerange(stored.as_ref(), computed.as_ref())).then_some((flow_id, computed))
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .map_err(|e| TamsError::Internal(format!("Timerange recompute task failed: {}", e)))?;
+
+        for (flow_id, computed) in corrections {
+            database.set_flow_available_timerange(&flow_id, computed.as_ref()).await?;
+            report.flows_corrected += 1;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Flows examined per batch by [`normalize_flow_vocabularies`]
+pub const VOCABULARY_BATCH_SIZE: u32 = 500;
+
+/// Rewrite stored flow containers and codecs the way flow writes now store
+/// them: trimmed, and in the casing of `validation.allowed_containers` and
+/// `allowed_codecs` when those are set. A value outside a configured list is
+/// only trimmed, and its flow counted as unmatched.
+pub async fn normalize_flow_vocabularies(
+    database: &Database,
+    config: &ValidationConfig,
+    batch_size: u32,
+) -> TamsResult<VocabularyRepairReport> {
+    let mut report = VocabularyRepairReport::default();
+    let mut after_id: Option<String> = None;
+
+    loop {
+        let page = database.get_flow_vocabulary_page(after_id.as_deref(), batch_size).await?;
+        let Some((last_id, _, _)) = page.last() else {
+            break;
+        };
+        after_id = Some(last_id.clone());
+        report.flows_checked += page.len() as u64;
+
+        for (flow_id, container, codec) in page {
+            let mut unmatched = false;
+            let mut normalize = |field, value: &Option<String>, allowed: Option<&[String]>| {
+                value.as_deref().map(|value| {
+                    normalize_vocabulary(field, value, allowed).unwrap_or_else(|_| {
+                        unmatched = true;
+                        value.trim().to_string()
+                    })
+                })
+            };
+            let normalized_container = normalize("container", &container, config.allowed_containers.as_deref());
+            let normalized_codec = normalize("codec", &codec, config.allowed_codecs.as_deref());
+            if unmatched {
+                tracing::warn!("Flow {} has a container or codec outside the configured vocabularies", flow_id);
+                report.flows_unmatched += 1;
+            }
+            if (&normalized_container, &normalized_codec) != (&container, &codec) {
+                database
+                    .set_flow_vocabulary(&flow_id, normalized_container.as_deref(), normalized_codec.as_deref())
+                    .await?;
+                report.flows_normalized += 1;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Flows reconciled per statement by [`reconcile_flow_stats`]
+pub const FLOW_STATS_BATCH_SIZE: u32 = 500;
+
+/// Recompute every flow's segment stats and rewrite the ones that have drifted
+/// from what the triggers kept, e.g. after the database was edited by hand
+/// with the triggers missing.
+pub async fn reconcile_flow_stats(database: &Database, batch_size: u32) -> TamsResult<FlowStatsReconcileReport> {
+    let mut report = FlowStatsReconcileReport::default();
+    let mut after_id = String::new();
+    while let Some((last_id, checked, corrected)) = database.reconcile_flow_stats(&after_id, batch_size).await? {
+        after_id = last_id;
+        report.flows_checked += checked;
+        report.flows_corrected += corrected;
+    }
+    if report.flows_corrected > 0 {
+        tracing::warn!(
+            "Corrected drifted segment stats of {} of {} flows",
+            report.flows_corrected,
+            report.flows_checked
+        );
+    }
+    Ok(report)
+}
+
+/// Job kind that gives segments stored without numeric bounds their
+/// start_ns/end_ns, one batch per run. There is at most one, queued at startup
+/// while range queries read through the legacy bounds view; its payload
+/// records how far it has got, and it retires the view when it finishes.
+pub const SEGMENT_BOUNDS_BACKFILL_JOB: &str = "segment_bounds_backfill";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SegmentBoundsBackfillJob {
+    /// Last segment rowid examined
+    pub after_rowid: i64,
+}
+
+/// Last segment rowid examined by the most recent backfill job, or 0 if none has run
+async fn backfilled_through(state: &AppState) -> TamsResult<i64> {
+    let latest = state.jobs.list(Some(SEGMENT_BOUNDS_BACKFILL_JOB), None, 1).await?;
+    Ok(latest
+        .first()
+        .and_then(|job| serde_json::from_value::<SegmentBoundsBackfillJob>(job.payload.clone()).ok())
+        .map_or(0, |payload| payload.after_rowid))
+}
+
+/// How far the segment bounds backfill has got
+pub async fn segment_bounds_progress(state: &AppState) -> TamsResult<SegmentBoundsProgress> {
+    state.database.get_segment_bounds_progress(backfilled_through(state).await?).await
+}
+
+/// Queue the segment bounds backfill while the legacy bounds view is in use
+/// and no backfill job is pending or running, carrying on from where the last
+/// one got to. A backfill that had already examined every segment retires the
+/// view instead. Returns whether a job was queued.
+pub async fn enqueue_segment_bounds_backfill(state: &AppState) -> TamsResult<bool> {
+    if !state.database.reads_legacy_bounds() {
+        return Ok(false);
+    }
+    let active = state
+        .jobs
+        .list(Some(SEGMENT_BOUNDS_BACKFILL_JOB), Some(&[Job::PENDING, Job::RUNNING]), 1)
+        .await?;
+    if !active.is_empty() {
+        return Ok(false);
+    }
+    let after_rowid = backfilled_through(state).await?;
+    if state.database.get_segment_bounds_progress(after_rowid).await?.complete {
+        state.database.retire_segment_bounds_view(after_rowid).await?;
+        return Ok(false);
+    }
+    state
+        .jobs
+        .enqueue(SEGMENT_BOUNDS_BACKFILL_JOB, None, &SegmentBoundsBackfillJob { after_rowid })
+        .await?;
+    Ok(true)
+}
+
+/// Run the segment bounds backfill job, one batch of
+/// `bounds_backfill_batch_size` segments every `bounds_backfill_interval_ms`
+/// so that other writers get the database in between, until the legacy
+/// bounds view is retired
+pub fn spawn_segment_bounds_backfill_worker(state: AppState) {
+    tokio::spawn(async move {
+        let pause = Duration::from_millis(state.config.database.bounds_backfill_interval_ms.max(1));
+        let mut interval = tokio::time::interval(pause);
+        while state.database.reads_legacy_bounds() {
+            interval.tick().await;
+            match run_segment_bounds_backfill(&state).await {
+                Ok(_) => metrics().record_task_run("segment_bounds_backfill", state.clock.now()),
+                Err(e) => tracing::warn!("Segment bounds backfill poll failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Claim the backfill job if it is due and fill in the bounds of its next
+/// batch of segments, retiring the legacy bounds view once none are left.
+/// Returns how many segments were given bounds.
+pub async fn run_segment_bounds_backfill(state: &AppState) -> TamsResult<u64> {
+    let Some(job) = state.jobs.claim_due(SEGMENT_BOUNDS_BACKFILL_JOB, 1).await?.pop() else {
+        return Ok(0);
+    };
+    let payload: SegmentBoundsBackfillJob = match serde_json::from_value(job.payload.clone()) {
+        Ok(payload) => payload,
+        Err(e) => {
+            state.jobs.fail_with_backoff(&job, &format!("Invalid job payload: {}", e)).await?;
+            return Ok(0);
+        }
+    };
+
+    let batch_size = state.config.database.bounds_backfill_batch_size.max(1);
+    let batch = match state.database.backfill_segment_bounds(payload.after_rowid, batch_size).await {
+        Ok(batch) => batch,
+        Err(e) => {
+            tracing::warn!("Segment bounds backfill attempt {} failed: {}", job.attempts, e);
+            state.jobs.fail_with_backoff(&job, &e.to_string()).await?;
+            return Ok(0);
+        }
+    };
+    match batch {
+        Some((after_rowid, filled)) => {
+            state
+                .jobs
+                .defer_with_payload(&job, state.clock.now(), &SegmentBoundsBackfillJob { after_rowid })
+                .await?;
+            Ok(filled)
+        }
+        None => {
+            state.database.retire_segment_bounds_view(payload.after_rowid).await?;
+            state.jobs.complete(&job).await?;
+            let progress = state.database.get_segment_bounds_progress(payload.after_rowid).await?;
+            tracing::info!(
+                "Backfilled numeric bounds of every segment; {} of {} don't parse and have none",
+                progress.segments_unparseable, progress.segments_total
+            );
+            Ok(0)
+        }
+    }
+}
+
+/// Objects moved per batch by [`move_flow_storage_class`]
+const STORAGE_CLASS_MOVE_BATCH_SIZE: u32 = 100;
+
+/// Move the objects of a flow's segments into the storage class its
+/// `storage_class` tag names now, or the default storage when it names none
+/// (or one that isn't configured), so a flow can be retagged and then
+/// migrated. Each object is copied, recorded at its new place, then deleted
+/// from the old one, so downloads find it throughout. Returns how many
+/// objects were moved.
+pub async fn move_flow_storage_class(database: &Database, storage: &MediaStorage, flow_id: &Uuid) -> TamsResult<u64> {
+    let flow = database.get_flow_required(flow_id).await?;
+    let target_class = storage.storage_class_for(&flow, |_| {});
+    let mut moved = 0;
+    loop {
+        let object_ids = database
+            .get_flow_objects_outside_class(flow_id, target_class.as_deref(), STORAGE_CLASS_MOVE_BATCH_SIZE)
+            .await?;
+        if object_ids.is_empty() {
+            break;
+        }
+        for object_id in object_ids {
+            let object = database.get_media_object(&object_id).await?;
+            let from = ObjectContext::for_object(object.as_ref(), Some(*flow_id));
+            let to = ObjectContext {
+                storage_class: target_class.clone(),
+                ..from.clone()
+            };
+            storage.copy_object(&object_id, &from, &to).await?;
+            database.set_media_object_storage_class(&object_id, target_class.as_deref()).await?;
+            match storage.delete_object(&object_id, &from).await {
+                Ok(()) | Err(TamsError::ObjectNotFound { .. }) => {}
+                Err(e) => return Err(e),
+            }
+            moved += 1;
+        }
+    }
+    tracing::info!(
+        "Moved {} objects of flow {} to storage class {}",
+        moved,
+        flow_id,
+        target_class.as_deref().unwrap_or("(default)")
+    );
+    Ok(moved)
+}
+
+/// Shape of the synthetic content written by [`seed_database`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeedSpec {
+    pub sources: u32,
+    pub flows_per_source: u32,
+    pub segments_per_flow: u32,
+    pub format: ContentFormat,
+}
+
+impl Default for SeedSpec {
+    fn default() -> Self {
+        Self {
+            sources: 1,
+            flows_per_source: 2,
+            segments_per_flow: 10,
+            format: ContentFormat::Video,
+        }
+    }
+}
+
+impl SeedSpec {
+    /// Parse `[sources] [flows_per_source] [segments_per_flow] [format]`,
+    /// defaulting anything omitted. The format takes a URN or short alias.
+    pub fn from_args(args: &[String]) -> TamsResult<Self> {
+        let mut spec = SeedSpec::default();
+        if args.len() > 4 {
+            return Err(TamsError::Validation(
+                "usage: seed [sources] [flows_per_source] [segments_per_flow] [format]".to_string(),
+            ));
+        }
+        let (counts, format) = match args.split_at_checked(3) {
+            Some((counts, [format])) => (counts, Some(format)),
+            _ => (args, None),
+        };
+        let fields = [
+            ("sources", &mut spec.sources),
+            ("flows_per_source", &mut spec.flows_per_source),
+            ("segments_per_flow", &mut spec.segments_per_flow),
+        ];
+        for ((name, field), arg) in fields.into_iter().zip(counts) {
+            *field = arg
+                .parse()
+                .map_err(|_| TamsError::Validation(format!("{} must be a non-negative integer, got '{}'", name, arg)))?;
+        }
+        if let Some(format) = format {
+            spec.format = format.parse()?;
+        }
+        Ok(spec)
+    }
+}
+
+/// Fill the database with synthetic sources, flows and one-second segments of
+/// the spec's format.
+///
+/// The per-flow notifications are suppressed; subscribers get a single
+/// `service/bulk_import_completed` summary instead.
+pub async fn seed_database(
+    database: &Database,
+    webhooks: &WebhookManager,
+    clock: &SharedClock,
+    spec: &SeedSpec,
+) -> TamsResult<SeedReport> {
+    webhooks
+        .suppress_events("seed", async {
+            let mut report = SeedReport::default();
+            for _ in 0..spec.sources {
+                let source = Source::new(Uuid::new_v4(), spec.format.clone());
+                database.create_source(&source).await?;
+                report.sources_created += 1;
+
+                for _ in 0..spec.flows_per_source {
+                    let mut flow = Flow::new(Uuid::new_v4(), spec.format.clone());
+                    flow.source_id = Some(source.id);
+                    flow.container = Some("video/mp2t".to_string());
+                    if spec.segments_per_flow > 0 {
+                        let end = format!("{}:0", spec.segments_per_flow);
+                        flow.available_timerange = Some(TimeRange::new("0:0", Some(&end)));
+                    }
+                    database.create_flow(&flow).await?;
+                    report.flows_created += 1;
+                    webhooks.send_flow_notification(flow.owner.as_deref(), EventNotification {
+                        event_timestamp: clock.now(),
+                        event_type: EventType::FlowsCreated,
+                        event: FlowCreatedEvent { flow: flow.clone() },
+                    }).await;
+
+                    let mut segments = Vec::with_capacity(spec.segments_per_flow as usize);
+                    for second in 0..spec.segments_per_flow {
+                        let (start, end) = (format!("{}:0", second), format!("{}:0", second + 1));
+                        let segment = CreateSegmentRequest::new(&format!("seed-{}-{}", flow.id, second), &start, &end);
+                        segments.push(segment.into_segment(flow.id));
+                    }
+                    if let Some(failure) = database.add_flow_segments(&segments).await?.first() {
+                        return Err(TamsError::Internal(format!(
+                            "Failed to seed segment {} of flow {}: {}",
+                            failure.segment.timerange, flow.id, failure.error
+                        )));
+                    }
+                    report.segments_created += segments.len() as u64;
+                    if !segments.is_empty() {
+                        webhooks.send_flow_notification(flow.owner.as_deref(), EventNotification {
+                            event_timestamp: clock.now(),
+                            event_type: EventType::FlowsSegmentsAdded,
+                            event: SegmentsAddedEvent { flow_id: flow.id, segments },
+                        }).await;
+                    }
+                }
+            }
+            Ok(report)
+        })
+        .await
+}
+
+fn same_timerange(a: Option<&TimeRange>, b: Option<&TimeRange>) -> bool {
+    let same_instant = |x: &str, y: &str| matches!(compare_tams_timestamps(x, y), Ok(Ordering::Equal));
+    match (a, b) {
+        (None, None) => true,
+        (Some(a), Some(b)) => {
+            same_instant(&a.start, &b.start)
+                && match (&a.end, &b.end) {
+                    (Some(x), Some(y)) => same_instant(x, y),
+                    (x, y) => x == y,
+                }
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::create_test_database;
+    use crate::models::Webhook;
+    use crate::webhooks::tests::spawn_webhook_receiver;
+    use axum::http::{Method, StatusCode};
+
+    #[tokio::test]
+    async fn test_recompute_corrects_drifted_flows() {
+        let (database, _temp_dir) = create_test_database().await;
+
+        let mut drifted = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        drifted.available_timerange = Some(TimeRange::new("0:0", Some("1:0")));
+        database.create_flow(&drifted).await.unwrap();
+        let request = CreateSegmentRequest::new("obj", "5:0", "9:0");
+        database.add_flow_segment(&request.into_segment(drifted.id)).await.unwrap();
+
+        let mut correct = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        correct.available_timerange = None;
+        database.create_flow(&correct).await.unwrap();
+
+        let report = recompute_available_timeranges(&database, 1).await.unwrap();
+        assert_eq!(report.flows_checked, 2);
+        assert_eq!(report.flows_corrected, 1);
+
+        let stored = database.get_flow_required(&drifted.id).await.unwrap();
+        let available = stored.available_timerange.unwrap();
+        assert_eq!((available.start.as_str(), available.end.as_deref().unwrap()), ("5:0", "9:0"));
+
+        let report = recompute_available_timeranges(&database, RECOMPUTE_BATCH_SIZE).await.unwrap();
+        assert_eq!(report.flows_corrected, 0);
+    }
+
+    #[tokio::test]
+    async fn test_vocabulary_repair_normalizes_stored_codecs_for_the_filter() {
+        let app = crate::routes::tests::TestApp::with_config(|config| {
+            config.validation.allowed_codecs = Some(vec!["video/H264".to_string()]);
+        })
+        .await;
+        // Stored before the vocabulary was configured
+        let mut flows = Vec::new();
+        for codec in [" VIDEO/h264", "video/H264", "avc1 "] {
+            let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+            flow.codec = Some(codec.to_string());
+            app.state.database.create_flow(&flow).await.unwrap();
+            flows.push(flow.id);
+        }
+
+        let (status, report) = app.call(Method::POST, "/service/maintenance/normalize-vocabularies", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((report["flows_checked"].as_u64(), report["flows_normalized"].as_u64()), (Some(3), Some(2)));
+        assert_eq!(report["flows_unmatched"], 1);
+        let stored = app.state.database.get_flow_required(&flows[2]).await.unwrap();
+        assert_eq!(stored.codec.as_deref(), Some("avc1"));
+
+        let (_, listed) = app.call(Method::GET, "/flows?codec=video/h264", None).await;
+        let mut ids: Vec<_> = listed["flows"].as_array().unwrap().iter().map(|flow| flow["id"].as_str().unwrap()).collect();
+        ids.sort();
+        let mut expected: Vec<_> = flows[..2].iter().map(Uuid::to_string).collect();
+        expected.sort();
+        assert_eq!(ids, expected);
+    }
+
+    #[tokio::test]
+    async fn test_repairs_send_one_summary_event() {
+        let (state, _temp_dir) = crate::handlers::tests::create_test_state().await;
+        let (url, received) = spawn_webhook_receiver().await;
+        let webhook = Webhook {
+            id: None,
+            url,
+            api_key_name: None,
+            api_key_value: None,
+            events: vec!["*".to_string()],
+        };
+        state.webhook_manager.add_webhook(webhook, "secret".to_string()).await;
+
+        let recomputed = crate::handlers::recompute_timeranges(axum::extract::State(state.clone())).await.unwrap();
+        let reconciled = crate::handlers::reconcile_flow_stats(axum::extract::State(state.clone())).await.unwrap();
+        assert_eq!((recomputed.flows_corrected, reconciled.flows_corrected), (0, 0));
+
+        let received = received.lock().unwrap();
+        let operations: Vec<_> = received.iter().map(|event| event["event"]["operation"].as_str().unwrap()).collect();
+        assert_eq!(operations, ["recompute_timeranges", "reconcile_flow_stats"]);
+        assert!(received.iter().all(|event| event["event_type"] == "service/bulk_import_completed"));
+    }
+
+    #[test]
+    fn test_seed_spec_from_args() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(SeedSpec::from_args(&[]).unwrap(), SeedSpec::default());
+        let spec = SeedSpec::from_args(&args(&["3", "4"])).unwrap();
+        assert_eq!((spec.sources, spec.flows_per_source, spec.segments_per_flow), (3, 4, 10));
+        assert!(SeedSpec::from_args(&args(&["x"])).is_err());
+        assert!(SeedSpec::from_args(&args(&["1", "1", "1", "1"])).is_err());
+        assert!(SeedSpec::from_args(&args(&["1", "1", "1", "audio", "x"])).is_err());
+        let spec = SeedSpec::from_args(&args(&["1", "1", "1", "urn:x-nmos:format:audio"])).unwrap();
+        assert_eq!(spec.format, ContentFormat::Audio);
+    }
+
+    #[tokio::test]
+    async fn test_seeding_sends_one_summary_event() {
+        let (database, _temp_dir) = create_test_database().await;
+
+        let (url, received) = spawn_webhook_receiver().await;
+
+        let webhooks = WebhookManager::new();
+        let webhook = Webhook {
+            id: None,
+            url,
+            api_key_name: None,
+            api_key_value: None,
+            events: vec!["*".to_string()],
+        };
+        webhooks.add_webhook(webhook, "secret".to_string()).await;
+
+        let spec = SeedSpec {
+            sources: 2,
+            flows_per_source: 3,
+            segments_per_flow: 4,
+            format: ContentFormat::Video,
+        };
+        let report = seed_database(&database, &webhooks, &crate::clock::system_clock(), &spec).await.unwrap();
+        assert_eq!((report.sources_created, report.flows_created, report.segments_created), (2, 6, 24));
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0]["event_type"], "service/bulk_import_completed");
+        let event = &received[0]["event"];
+        assert_eq!(event["operation"], "seed");
+        assert_eq!(event["succeeded"], true);
+        assert_eq!(event["total_suppressed"], 12);
+        assert_eq!(event["suppressed_events"]["flows/created"], 6);
+        assert_eq!(event["suppressed_events"]["flows/segments_added"], 6);
+    }
+}
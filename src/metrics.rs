@@ -0,0 +1,144 @@
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// How far back the ingest rate looks
+pub const INGEST_WINDOW_SECONDS: i64 = 5 * 60;
+/// How far back webhook failures are counted
+pub const WEBHOOK_FAILURE_WINDOW_SECONDS: i64 = 60 * 60;
+
+/// Process-wide operational counters
+pub struct Metrics {
+    /// Database rows skipped by listings because they could not be parsed
+    pub corrupt_rows_skipped: AtomicU64,
+    /// Deliveries sent in summary form because the payload was too large, by webhook URL
+    webhook_truncations: Mutex<BTreeMap<String, u64>>,
+    /// Webhook deliveries queued and not yet attempted
+    pub webhook_deliveries_pending: AtomicU64,
+    /// Webhook deliveries that failed or were refused by the receiver
+    webhook_failures: Mutex<RecentEvents>,
+    /// Clients connected to `GET /service/events`
+    pub event_stream_subscribers: AtomicU64,
+    /// Event stream clients disconnected for falling more than the buffer behind
+    pub event_stream_drops: AtomicU64,
+    /// Segments registered through the API
+    segments_ingested: Mutex<RecentEvents>,
+    /// When each background task last completed a run
+    task_runs: Mutex<BTreeMap<&'static str, DateTime<Utc>>>,
+}
+
+static METRICS: Metrics = Metrics {
+    corrupt_rows_skipped: AtomicU64::new(0),
+    webhook_truncations: Mutex::new(BTreeMap::new()),
+    webhook_deliveries_pending: AtomicU64::new(0),
+    webhook_failures: Mutex::new(RecentEvents::new(WEBHOOK_FAILURE_WINDOW_SECONDS)),
+    event_stream_subscribers: AtomicU64::new(0),
+    event_stream_drops: AtomicU64::new(0),
+    segments_ingested: Mutex::new(RecentEvents::new(INGEST_WINDOW_SECONDS)),
+    task_runs: Mutex::new(BTreeMap::new()),
+};
+
+/// Event counts per second over a trailing window, for rates without a time series store
+struct RecentEvents {
+    window_seconds: i64,
+    /// (unix second, events in it), oldest first
+    seconds: VecDeque<(i64, u64)>,
+}
+
+impl RecentEvents {
+    const fn new(window_seconds: i64) -> Self {
+        RecentEvents { window_seconds, seconds: VecDeque::new() }
+    }
+
+    fn record(&mut self, at: DateTime<Utc>, count: u64) {
+        let second = at.timestamp();
+        match self.seconds.back_mut() {
+            Some((last, total)) if *last == second => *total += count,
+            _ => self.seconds.push_back((second, count)),
+        }
+        self.prune(at);
+    }
+
+    fn count(&mut self, now: DateTime<Utc>) -> u64 {
+        self.prune(now);
+        self.seconds.iter().map(|(_, count)| count).sum()
+    }
+
+    fn prune(&mut self, now: DateTime<Utc>) {
+        let cutoff = now.timestamp() - self.window_seconds;
+        while self.seconds.front().is_some_and(|(second, _)| *second <= cutoff) {
+            self.seconds.pop_front();
+        }
+    }
+}
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    pub fn record_corrupt_row(&self) {
+        self.corrupt_rows_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a truncated delivery to `url`, returning its running total
+    pub fn record_webhook_truncation(&self, url: &str) -> u64 {
+        let mut truncations = self.webhook_truncations.lock().unwrap_or_else(|e| e.into_inner());
+        let count = truncations.entry(url.to_string()).or_default();
+        *count += 1;
+        *count
+    }
+
+    pub fn record_segments_ingested(&self, at: DateTime<Utc>, count: u64) {
+        self.segments_ingested.lock().unwrap_or_else(|e| e.into_inner()).record(at, count);
+    }
+
+    /// Segments registered in the [`INGEST_WINDOW_SECONDS`] before `now`
+    pub fn segments_ingested_recently(&self, now: DateTime<Utc>) -> u64 {
+        self.segments_ingested.lock().unwrap_or_else(|e| e.into_inner()).count(now)
+    }
+
+    pub fn record_webhook_failure(&self, at: DateTime<Utc>) {
+        self.webhook_failures.lock().unwrap_or_else(|e| e.into_inner()).record(at, 1);
+    }
+
+    /// Webhook failures in the [`WEBHOOK_FAILURE_WINDOW_SECONDS`] before `now`
+    pub fn webhook_failures_recently(&self, now: DateTime<Utc>) -> u64 {
+        self.webhook_failures.lock().unwrap_or_else(|e| e.into_inner()).count(now)
+    }
+
+    /// Note that background task `task` completed a run at `at`
+    pub fn record_task_run(&self, task: &'static str, at: DateTime<Utc>) {
+        self.task_runs.lock().unwrap_or_else(|e| e.into_inner()).insert(task, at);
+    }
+
+    pub fn task_runs(&self) -> BTreeMap<&'static str, DateTime<Utc>> {
+        self.task_runs.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    #[cfg(test)]
+    pub fn webhook_truncations(&self, url: &str) -> u64 {
+        let truncations = self.webhook_truncations.lock().unwrap_or_else(|e| e.into_inner());
+        truncations.get(url).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_recent_events_forget_what_left_the_window() {
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut events = RecentEvents::new(60);
+        events.record(start, 2);
+        events.record(start, 1);
+        events.record(start + Duration::seconds(30), 4);
+        assert_eq!(events.count(start + Duration::seconds(30)), 7);
+        assert_eq!(events.count(start + Duration::seconds(60)), 4);
+        assert_eq!(events.count(start + Duration::seconds(90)), 0);
+        assert!(events.seconds.is_empty());
+    }
+}
@@ -1,12 +1,14 @@
+use crate::error::{TamsError, TamsResult};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
 use uuid::Uuid;
 use validator::Validate;
 
 // Core TAMS data types
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum ContentFormat {
     #[serde(rename = "urn:x-nmos:format:video")]
     Video,
@@ -20,17 +22,76 @@ pub enum ContentFormat {
     Multi,
 }
 
+impl ContentFormat {
+    pub const ALL: [ContentFormat; 5] = [
+        ContentFormat::Video,
+        ContentFormat::Audio,
+        ContentFormat::Data,
+        ContentFormat::Image,
+        ContentFormat::Multi,
+    ];
+
+    /// Canonical URN, which is always what gets serialized back to clients
+    pub fn urn(&self) -> &'static str {
+        match self {
+            ContentFormat::Video => "urn:x-nmos:format:video",
+            ContentFormat::Image => "urn:x-tam:format:image",
+            ContentFormat::Audio => "urn:x-nmos:format:audio",
+            ContentFormat::Data => "urn:x-nmos:format:data",
+            ContentFormat::Multi => "urn:x-nmos:format:multi",
+        }
+    }
+
+    /// Short alias accepted wherever the URN is
+    pub fn alias(&self) -> &'static str {
+        match self {
+            ContentFormat::Video => "video",
+            ContentFormat::Image => "image",
+            ContentFormat::Audio => "audio",
+            ContentFormat::Data => "data",
+            ContentFormat::Multi => "multi",
+        }
+    }
+}
+
+/// Parse a format from a query parameter or command line, accepting the URN or
+/// its short alias in any case
+impl FromStr for ContentFormat {
+    type Err = TamsError;
+
+    fn from_str(value: &str) -> TamsResult<Self> {
+        let value = value.trim();
+        ContentFormat::ALL
+            .into_iter()
+            .find(|format| value.eq_ignore_ascii_case(format.urn()) || value.eq_ignore_ascii_case(format.alias()))
+            .ok_or_else(|| TamsError::InvalidFormat {
+                expected: format!(
+                    "one of {}",
+                    ContentFormat::ALL
+                        .iter()
+                        .map(|format| format!("{} ({})", format.alias(), format.urn()))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                actual: format!("'{}'", value),
+            })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TimeRange {
     pub start: String,  // Timestamp format: "seconds:nanoseconds"
-    pub end: String, // Changed from Option<String> to String to match handlers
+    /// None for a range that runs on without end, such as a recording in
+    /// progress; serialized by leaving `end` out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<String>,
 }
 
 impl Default for TimeRange {
     fn default() -> Self {
         TimeRange {
             start: "0:0".to_string(),
-            end: "0:0".to_string(),
+            end: Some("0:0".to_string()),
         }
     }
 }
@@ -42,8 +103,43 @@ pub struct Source {
     pub label: Option<String>,
     pub description: Option<String>,
     pub tags: HashMap<String, String>,
+    #[serde(serialize_with = "crate::time_utils::serialize_timestamp")]
     pub created_at: DateTime<Utc>,
+    #[serde(serialize_with = "crate::time_utils::serialize_timestamp")]
     pub updated_at: DateTime<Utc>,
+    /// Principal that created the source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+/// A source listed with `?include=flows`: its first flows by created_at
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceWithFlows {
+    #[serde(flatten)]
+    pub source: Source,
+    pub flows: Vec<Flow>,
+    /// Whether the source has flows beyond those embedded
+    pub flows_has_more: bool,
+}
+
+/// A flow's segment statistics, kept up to date as segments are written
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FlowStats {
+    pub segment_count: u64,
+    /// Sizes of the segments' objects added up; objects not yet uploaded count as 0
+    pub total_bytes: u64,
+    /// Start of the earliest segment
+    pub first_timestamp: Option<String>,
+    /// End of the latest segment
+    pub last_timestamp: Option<String>,
+}
+
+/// `GET /flows/{id}`: the flow with its segment stats
+#[derive(Debug, Clone, Serialize)]
+pub struct FlowWithStats {
+    #[serde(flatten)]
+    pub flow: Flow,
+    pub stats: FlowStats,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Validate)]
@@ -65,7 +161,21 @@ pub struct Flow {
     pub channels: Option<u32>,
     pub flow_collection: Option<FlowCollection>,
     pub available_timerange: Option<TimeRange>,
+    /// Segments ending longer ago than this are expired by the retention worker
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_seconds: Option<u64>,
+    /// Callback told of this flow's own flow and segment events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify_url: Option<String>,
+    /// Signs notify_url deliveries; held sealed like webhook keys and never returned
+    #[serde(skip)]
+    pub notify_secret: Option<String>,
+    /// Principal the flow belongs to; only changed through `PUT /flows/:id/owner`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(serialize_with = "crate::time_utils::serialize_timestamp")]
     pub created_at: DateTime<Utc>,
+    #[serde(serialize_with = "crate::time_utils::serialize_timestamp")]
     pub updated_at: DateTime<Utc>,
 }
 
@@ -106,7 +216,20 @@ pub struct FlowSegment {
     pub sample_count: Option<u64>,
     pub key_frame_count: Option<u32>, // Changed from u64 to u32 to match database usage
     pub get_urls: HashMap<String, String>, // Changed from Option<Vec<GetUrl>> to HashMap to match database usage
+    #[serde(serialize_with = "crate::time_utils::serialize_timestamp")]
     pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub essence_parameters: Option<SegmentEssenceParameters>,
+}
+
+/// Per-segment overrides of the flow's essence parameters, e.g. for image
+/// flows whose stills do not all share one resolution
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SegmentEssenceParameters {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_height: Option<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -118,6 +241,8 @@ pub struct GetUrl {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FlowStorage {
+    /// Deadline for uploading the objects: the earliest of their expiries
+    pub expires_at: Option<DateTime<Utc>>,
     pub objects: Vec<StorageObject>,
 }
 
@@ -127,6 +252,11 @@ pub struct StorageObject {
     pub put_url: String,
     pub put_headers: Option<HashMap<String, String>>,
     pub expires_at: Option<DateTime<Utc>>,
+    pub media_store: Option<String>, // Name of the backend the put_url targets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_class: Option<String>, // Storage class the object will be placed in, when not the default
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub already_allocated: bool, // Set when another caller allocated (or uploaded) this object first
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -135,19 +265,64 @@ pub struct FlowStorageRequest {
     pub object_ids: Option<Vec<String>>,
 }
 
+/// The request a client can send to allocate an object it has not uploaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AllocationHint {
+    pub method: String,
+    pub path: String,
+    pub body: FlowStorageRequest,
+}
+
+/// Error extensions for a segment whose object has not been uploaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MissingObjectGuidance {
+    pub object_id: String,
+    pub allocation_hint: AllocationHint,
+    /// An allocation for this id was made but its put_url has expired
+    pub expired_allocation: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allocation_expires_at: Option<DateTime<Utc>>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MediaObject {
     pub object_id: String,
     pub size_bytes: Option<u64>,
     pub mime_type: Option<String>,
-    pub flow_references: Vec<Uuid>, // Changed from Vec<FlowReference> to Vec<Uuid> to match database usage
+    pub flow_references: Vec<FlowReference>,
     pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>, // User metadata, like S3 x-amz-meta-*
+    /// Storage class the bytes were placed in; None for the default location
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_class: Option<String>,
+    /// Format of the flow the bytes were uploaded for, which picks their base path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<ContentFormat>,
+    /// Hex SHA-256 of the stored bytes, served as their ETag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FlowReference {
     pub flow_id: Uuid,
-    pub timerange: TimeRange,
+    /// Span of the flow's segments that use the object; absent until one does
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timerange: Option<TimeRange>,
+}
+
+impl FlowReference {
+    pub fn new(flow_id: Uuid) -> Self {
+        FlowReference { flow_id, timerange: None }
+    }
+}
+
+impl MediaObject {
+    /// The flow the object was first referenced by, which decides its storage path
+    pub fn primary_flow_id(&self) -> Option<Uuid> {
+        self.flow_references.first().map(|reference| reference.flow_id)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -161,14 +336,275 @@ pub struct DeletionRequest {
     pub updated_at: DateTime<Utc>,
 }
 
+/// Body of `POST /flow-delete-requests`. The timerange is parsed by
+/// `deletion::parse_requested_timerange`; without one the whole flow goes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateDeletionRequest {
+    pub flow_id: Uuid,
+    #[serde(default)]
+    pub timerange: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateDeletionRequest {
+    /// A TimeRange object or a `[start_end)` string, as on creation
+    pub timerange: serde_json::Value,
+}
+
+impl DeletionRequest {
+    pub const PENDING: &'static str = "pending";
+    pub const IN_PROGRESS: &'static str = "in_progress";
+    pub const DONE: &'static str = "done";
+    pub const ERROR: &'static str = "error";
+    pub const CANCELLED: &'static str = "cancelled";
+
+    /// Whether the request may still be acted on
+    pub fn is_active(&self) -> bool {
+        matches!(self.status.as_str(), "pending" | "in_progress")
+    }
+}
+
+/// A unit of durable background work, see [`crate::jobs::JobQueue`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    pub kind: String,
+    /// What the job acts on, e.g. a deletion request id
+    pub target: Option<String>,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub attempts: u32,
+    pub run_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Job {
+    pub const PENDING: &'static str = "pending";
+    pub const RUNNING: &'static str = "running";
+    pub const COMPLETED: &'static str = "completed";
+    pub const FAILED: &'static str = "failed";
+
+    /// A pending job of the given kind, due at `now`
+    pub fn new(kind: &str, target: Option<&str>, payload: serde_json::Value, now: DateTime<Utc>) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            kind: kind.to_string(),
+            target: target.map(str::to_string),
+            payload,
+            status: Job::PENDING.to_string(),
+            attempts: 0,
+            run_at: now,
+            last_error: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceInfo {
+    #[serde(rename = "type")]
+    pub service_type: String,
     pub name: String,
     pub description: String,
-    pub version: String,
-    pub media_store_type: String,
-    pub event_stream_mechanisms: Vec<String>,
+    /// Version of the TAMS API implemented, `TAMS_API_VERSION`
+    pub api_version: String,
+    /// Version of this service implementation
+    pub service_version: String,
+    /// Path the API is served under, e.g. `/x-tams/v6.0`; absent when it is served at the root
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_prefix: Option<String>,
+    pub media_store: MediaStoreSummary,
+    pub event_stream_mechanisms: Vec<EventStreamMechanism>,
+    pub version: String, // Configured service.version, kept for older clients
+    pub media_store_type: String, // Type of the primary store, kept for older clients
+    pub media_stores: Vec<MediaStoreInfo>,
     pub capabilities: ServiceCapabilities,
+    /// Each configurable behaviour and how it is set; see `behaviours`
+    pub behaviours: crate::behaviours::Behaviours,
+    pub ingest_paused: bool,
+    /// Version of the running server binary
+    pub binary_version: String,
+    /// Newest schema version the database has been migrated to
+    pub schema_version: Option<i64>,
+}
+
+/// Timeline comparison of two flows of one source
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoverageDiff {
+    pub source_id: Uuid,
+    pub flow_a: Uuid,
+    pub flow_b: Uuid,
+    pub timerange: Option<TimeRange>, // Bound the comparison was scoped to, if any
+    pub only_a: CoverageSet,
+    pub only_b: CoverageSet,
+    pub both: CoverageSet,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoverageSet {
+    pub timeranges: Vec<TimeRange>,
+    pub count: usize,            // Number of ranges before truncation
+    pub truncated: bool,
+    pub total_duration: String,  // "seconds:nanoseconds" across all ranges
+}
+
+/// Parts of a window that no segment of a flow covers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowGaps {
+    pub flow_id: Uuid,
+    pub timerange: Option<TimeRange>,  // Window searched; None if the flow has no segments to bound it
+    pub gaps: CoverageSet,
+}
+
+/// Segment coverage of a flow summarised into fixed-size time buckets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SegmentRollup {
+    pub flow_id: Uuid,
+    pub bucket: String,                // Bucket size as "seconds:nanoseconds"
+    pub timerange: Option<TimeRange>,  // Span the buckets cover; None if the flow has no segments
+    pub buckets: Vec<RollupBucket>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RollupBucket {
+    pub start: String,
+    pub covered_duration: String,  // "seconds:nanoseconds" of the bucket covered by segments
+    pub segment_count: u64,        // Segments overlapping the bucket
+    pub byte_estimate: u64,        // Object sizes prorated by the share of each segment in the bucket
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IngestPauseState {
+    pub paused: bool,
+    pub paused_flows: Vec<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IngestPauseRequest {
+    pub paused: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IngestPauseEvent {
+    pub paused: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimeRangeRecomputeReport {
+    pub flows_checked: u64,
+    pub flows_corrected: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VocabularyRepairReport {
+    pub flows_checked: u64,
+    pub flows_normalized: u64,
+    /// Flows whose container or codec is outside a configured vocabulary; left as stored
+    pub flows_unmatched: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FlowStatsReconcileReport {
+    pub flows_checked: u64,
+    pub flows_corrected: u64,
+}
+
+/// How far the backfill of numeric segment bounds has got; until it is
+/// complete, range queries also parse the bounds of the segments it hasn't reached.
+/// Segments whose stored timerange doesn't parse are done once examined.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SegmentBoundsProgress {
+    pub segments_backfilled: u64,
+    pub segments_unparseable: u64,
+    pub segments_total: u64,
+    pub complete: bool,
+}
+
+/// Headline numbers for operator dashboards, from `GET /service/summary`.
+/// Fields are only ever added within a `version`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceSummary {
+    pub version: u32,
+    pub generated_at: DateTime<Utc>,
+    pub counts: SummaryCounts,
+    pub storage: SummaryStorage,
+    pub ingest: SummaryIngest,
+    pub webhooks: SummaryWebhooks,
+    pub event_stream: SummaryEventStream,
+    /// Deletion requests pending or in progress
+    pub active_deletion_requests: u64,
+    /// When each background task last completed a run; tasks yet to run are absent
+    pub tasks_last_run: BTreeMap<String, DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SummaryCounts {
+    pub sources: u64,
+    pub flows: u64,
+    pub segments: u64,
+    pub objects: u64,
+}
+
+/// From the cached storage stats; all None until they are first computed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SummaryStorage {
+    pub stored_bytes: Option<u64>,
+    pub available_bytes: Option<u64>,
+    pub computed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SummaryIngest {
+    pub window_seconds: i64,
+    pub segments: u64,
+    pub segments_per_second: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SummaryWebhooks {
+    /// Deliveries queued and not yet attempted
+    pub queue_depth: u64,
+    pub failures_window_seconds: i64,
+    pub failures: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SummaryEventStream {
+    /// Clients connected to `GET /service/events`
+    pub subscribers: u64,
+    /// Clients disconnected since startup for falling too far behind
+    pub dropped: u64,
+}
+
+/// Version of the TAMS API this server implements
+pub const TAMS_API_VERSION: &str = "6.0";
+
+/// The primary media store, in the shape the TAMS spec gives `media_store`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaStoreSummary {
+    #[serde(rename = "type")]
+    pub store_type: String,
+}
+
+/// A way clients can be told about changes, and where to subscribe to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventStreamMechanism {
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaStoreInfo {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub store_type: String,
+    pub presigned_urls: bool,
+    pub url_base: String,
+    pub role: String,
+    pub primary: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -178,16 +614,56 @@ pub struct ServiceCapabilities {
     pub supports_segment_deletion: bool,
     pub supports_read_only_flows: bool,
     pub max_file_size: u64,
+    pub default_tags: HashMap<String, String>,
+    pub required_tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DependencyStatus {
+    pub name: String,
+    pub status: String, // "ok" or "error"
+    pub latency_ms: f64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DependenciesReport {
+    pub status: String, // "ok" when every dependency is healthy, otherwise "degraded"
+    pub dependencies: Vec<DependencyStatus>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Webhook {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
     pub url: String,
     pub api_key_name: Option<String>,
     pub api_key_value: Option<String>, // Only for requests, omitted in responses
     pub events: Vec<String>,
 }
 
+/// A webhook together with its delivery secrets; never returned by the API
+#[derive(Debug, Clone)]
+pub struct StoredWebhook {
+    pub webhook: Webhook,
+    pub api_key_value: String,
+    pub previous_key: Option<PreviousWebhookKey>,
+    /// Principal whose flows' events it is limited to, if any
+    pub owner: Option<String>,
+}
+
+/// A rotated-out key that still signs deliveries until `expires_at`
+#[derive(Debug, Clone)]
+pub struct PreviousWebhookKey {
+    pub api_key_value: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookSecretRequest {
+    pub api_key_value: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WebhookRequest {
     pub url: String,
@@ -217,6 +693,7 @@ impl CreateSourceRequest {
             tags: self.tags,
             created_at: now,
             updated_at: now,
+            owner: None,
         }
     }
 }
@@ -240,6 +717,9 @@ pub struct CreateFlowRequest {
     pub channels: Option<u32>,
     pub flow_collection: Option<FlowCollection>,
     pub available_timerange: Option<TimeRange>,
+    pub retention_seconds: Option<u64>,
+    pub notify_url: Option<String>,
+    pub notify_secret: Option<String>,
 }
 
 impl CreateFlowRequest {
@@ -263,6 +743,10 @@ impl CreateFlowRequest {
             channels: self.channels,
             flow_collection: self.flow_collection,
             available_timerange: self.available_timerange,
+            retention_seconds: self.retention_seconds.filter(|seconds| *seconds > 0),
+            notify_url: self.notify_url,
+            notify_secret: self.notify_secret,
+            owner: None,
             created_at: now,
             updated_at: now,
         }
@@ -296,7 +780,7 @@ impl UpdateSourceRequest {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct UpdateFlowRequest {
     pub source_id: Option<Uuid>,
     pub format: Option<ContentFormat>,
@@ -314,6 +798,17 @@ pub struct UpdateFlowRequest {
     pub channels: Option<u32>,
     pub flow_collection: Option<FlowCollection>,
     pub available_timerange: Option<TimeRange>,
+    /// 0 removes the flow's retention policy
+    pub retention_seconds: Option<u64>,
+    /// An empty string removes the flow's callback along with its secret
+    pub notify_url: Option<String>,
+    pub notify_secret: Option<String>,
+}
+
+/// Body of `PUT /flows/:id/owner`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetFlowOwnerRequest {
+    pub owner: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -324,12 +819,49 @@ pub struct CreateSegmentRequest {
     pub sample_offset: Option<u64>,
     pub sample_count: Option<u64>,
     pub key_frame_count: Option<u32>,
+    #[serde(default)]
+    pub essence_parameters: Option<SegmentEssenceParameters>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteSegmentsByObjectRequest {
+    pub object_ids: Vec<String>,
+}
+
+/// Objects whose get_urls should be generated afresh: one `object_id`, a list
+/// of `object_ids`, or both
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshGetUrlsRequest {
+    #[serde(default)]
+    pub object_id: Option<String>,
+    #[serde(default)]
+    pub object_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectGetUrls {
+    pub object_id: String,
+    pub get_urls: Vec<GetUrl>,
 }
 
 impl CreateSegmentRequest {
+    /// A segment of `object_id` from `start` to `end` with no optional fields
+    pub fn new(object_id: &str, start: &str, end: &str) -> Self {
+        Self {
+            object_id: object_id.to_string(),
+            timerange: TimeRange::new(start, Some(end)),
+            ts_offset: None,
+            sample_offset: None,
+            sample_count: None,
+            key_frame_count: None,
+            essence_parameters: None,
+        }
+    }
+
     pub fn into_segment(self, flow_id: Uuid) -> FlowSegment {
         let now = Utc::now();
-        let timerange_str = format!("{}:{}", self.timerange.start, self.timerange.end);
+        // Segments always end; one without is refused when its bounds are read from this
+        let timerange_str = format!("{}:{}", self.timerange.start, self.timerange.end.as_deref().unwrap_or_default());
         
         FlowSegment {
             flow_id,
@@ -341,11 +873,56 @@ impl CreateSegmentRequest {
             key_frame_count: self.key_frame_count,
             get_urls: HashMap::new(),
             created_at: now,
+            essence_parameters: self.essence_parameters,
         }
     }
 }
 
 impl UpdateFlowRequest {
+    /// Whether the request changes nothing but read_only, the one field a
+    /// read-only flow takes
+    pub fn sets_only_read_only(&self) -> bool {
+        let UpdateFlowRequest {
+            source_id,
+            format,
+            label,
+            description,
+            tags,
+            read_only: _,
+            max_bit_rate,
+            avg_bit_rate,
+            container,
+            codec,
+            frame_width,
+            frame_height,
+            sample_rate,
+            channels,
+            flow_collection,
+            available_timerange,
+            retention_seconds,
+            notify_url,
+            notify_secret,
+        } = self;
+        source_id.is_none()
+            && format.is_none()
+            && label.is_none()
+            && description.is_none()
+            && tags.is_none()
+            && max_bit_rate.is_none()
+            && avg_bit_rate.is_none()
+            && container.is_none()
+            && codec.is_none()
+            && frame_width.is_none()
+            && frame_height.is_none()
+            && sample_rate.is_none()
+            && channels.is_none()
+            && flow_collection.is_none()
+            && available_timerange.is_none()
+            && retention_seconds.is_none()
+            && notify_url.is_none()
+            && notify_secret.is_none()
+    }
+
     pub fn apply_to_flow(self, mut flow: Flow) -> Flow {
         if let Some(source_id) = self.source_id {
             flow.source_id = Some(source_id);
@@ -395,6 +972,20 @@ impl UpdateFlowRequest {
         if let Some(available_timerange) = self.available_timerange {
             flow.available_timerange = Some(available_timerange);
         }
+        if let Some(retention_seconds) = self.retention_seconds {
+            flow.retention_seconds = Some(retention_seconds).filter(|seconds| *seconds > 0);
+        }
+        if let Some(notify_secret) = self.notify_secret {
+            flow.notify_secret = Some(notify_secret);
+        }
+        if let Some(notify_url) = self.notify_url {
+            if notify_url.is_empty() {
+                flow.notify_url = None;
+                flow.notify_secret = None;
+            } else {
+                flow.notify_url = Some(notify_url);
+            }
+        }
         flow.updated_at = Utc::now();
         flow
     }
@@ -407,20 +998,151 @@ pub struct PaginationParams {
     pub page: Option<String>,
 }
 
+impl PaginationParams {
+    /// The page size asked for, or `default` when none was; between 1 and `max`
+    pub fn limit_or(&self, default: u32, max: u32) -> u32 {
+        self.limit.unwrap_or(default).clamp(1, max.max(1))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PaginationInfo {
     pub limit: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub next_key: Option<String>,
+    /// Total matching items, not just this page. Flow, source and storage
+    /// listings only give it with `?include_total=true`, since counting runs an
+    /// extra `COUNT(*)` over everything the filters match; segment listings
+    /// always do, from the flow's kept stats when unfiltered. Rows skipped as
+    /// corrupt are included.
     pub count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub timerange: Option<TimeRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub reverse_order: Option<bool>,
+    /// Rows left out of this listing because they could not be parsed
+    #[serde(default)]
+    pub skipped_corrupt: u64,
+}
+
+impl PaginationInfo {
+    pub fn new(limit: u32, count: Option<u64>, skipped_corrupt: u64) -> Self {
+        PaginationInfo {
+            limit,
+            next_key: None,
+            count,
+            timerange: None,
+            reverse_order: None,
+            skipped_corrupt,
+        }
+    }
 }
 
 // Event notifications for webhooks
+
+/// Subscription that matches every event type
+pub const ALL_EVENTS_SUBSCRIPTION: &str = "*";
+
+/// The canonical names of the events the service sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum EventType {
+    #[serde(rename = "sources/created")]
+    SourcesCreated,
+    #[serde(rename = "sources/updated")]
+    SourcesUpdated,
+    #[serde(rename = "sources/deleted")]
+    SourcesDeleted,
+    #[serde(rename = "flows/created")]
+    FlowsCreated,
+    #[serde(rename = "flows/updated")]
+    FlowsUpdated,
+    #[serde(rename = "flows/deleted")]
+    FlowsDeleted,
+    #[serde(rename = "flows/segments_added")]
+    FlowsSegmentsAdded,
+    #[serde(rename = "flows/segments_deleted")]
+    FlowsSegmentsDeleted,
+    #[serde(rename = "service/ingest_paused")]
+    ServiceIngestPaused,
+    #[serde(rename = "service/ingest_resumed")]
+    ServiceIngestResumed,
+    #[serde(rename = "service/bulk_import_completed")]
+    ServiceBulkImportCompleted,
+}
+
+impl EventType {
+    pub const ALL: [EventType; 11] = [
+        EventType::SourcesCreated,
+        EventType::SourcesUpdated,
+        EventType::SourcesDeleted,
+        EventType::FlowsCreated,
+        EventType::FlowsUpdated,
+        EventType::FlowsDeleted,
+        EventType::FlowsSegmentsAdded,
+        EventType::FlowsSegmentsDeleted,
+        EventType::ServiceIngestPaused,
+        EventType::ServiceIngestResumed,
+        EventType::ServiceBulkImportCompleted,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventType::SourcesCreated => "sources/created",
+            EventType::SourcesUpdated => "sources/updated",
+            EventType::SourcesDeleted => "sources/deleted",
+            EventType::FlowsCreated => "flows/created",
+            EventType::FlowsUpdated => "flows/updated",
+            EventType::FlowsDeleted => "flows/deleted",
+            EventType::FlowsSegmentsAdded => "flows/segments_added",
+            EventType::FlowsSegmentsDeleted => "flows/segments_deleted",
+            EventType::ServiceIngestPaused => "service/ingest_paused",
+            EventType::ServiceIngestResumed => "service/ingest_resumed",
+            EventType::ServiceBulkImportCompleted => "service/bulk_import_completed",
+        }
+    }
+
+    /// Whether a webhook subscription entry, canonical or legacy, selects this event
+    pub fn matches_subscription(self, subscription: &str) -> bool {
+        subscription == ALL_EVENTS_SUBSCRIPTION || subscription.parse().is_ok_and(|event: EventType| event == self)
+    }
+}
+
+impl std::fmt::Display for EventType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for EventType {
+    type Err = TamsError;
+
+    /// Accepts the canonical `flows/created` form and the legacy dotted
+    /// `flow.created` form that older webhooks were stored with
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let canonical = match value.split_once('.') {
+            Some(("flow" | "flows", action)) => format!("flows/{}", action),
+            Some(("source" | "sources", action)) => format!("sources/{}", action),
+            Some(("service", action)) => format!("service/{}", action),
+            _ => value.to_string(),
+        };
+        EventType::ALL
+            .into_iter()
+            .find(|event| event.as_str() == canonical)
+            .ok_or_else(|| {
+                TamsError::Validation(format!(
+                    "Unknown event type '{}'; expected '{}' or one of: {}",
+                    value,
+                    ALL_EVENTS_SUBSCRIPTION,
+                    EventType::ALL.map(EventType::as_str).join(", ")
+                ))
+            })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EventNotification<T> {
     pub event_timestamp: DateTime<Utc>,
-    pub event_type: String,
+    pub event_type: EventType,
     pub event: T,
 }
 
@@ -439,6 +1161,11 @@ pub struct FlowDeletedEvent {
     pub flow_id: Uuid,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceDeletedEvent {
+    pub source_id: Uuid,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SegmentsAddedEvent {
     pub flow_id: Uuid,
@@ -451,6 +1178,23 @@ pub struct SegmentsDeletedEvent {
     pub timerange: TimeRange,
 }
 
+/// Sent once when an administrative bulk operation finishes, in place of the
+/// per-item notifications it suppressed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkOperationCompletedEvent {
+    pub operation: String,
+    pub succeeded: bool,
+    pub total_suppressed: u64,
+    pub suppressed_events: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SeedReport {
+    pub sources_created: u64,
+    pub flows_created: u64,
+    pub segments_created: u64,
+}
+
 // Bulk operations support
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FlowSegmentBulkFailure {
@@ -468,13 +1212,15 @@ impl TimeRange {
     pub fn new(start: &str, end: Option<&str>) -> Self {
         Self {
             start: start.to_string(),
-            end: end.map(|s| s.to_string()).unwrap_or_default(),
+            end: end.map(|s| s.to_string()),
         }
     }
 
-    pub fn is_valid(&self) -> bool {
-        // Basic validation - should be extended with proper timestamp parsing
-        !self.start.is_empty() && !self.end.is_empty()
+    /// The end of a range that must have one, such as a segment's
+    pub fn bounded_end(&self) -> Result<&str, crate::error::TamsError> {
+        self.end.as_deref().ok_or_else(|| {
+            crate::error::TamsError::InvalidTimerange(format!("Timerange starting at {} must have an end", self.start))
+        })
     }
 
     pub fn overlaps(&self, _other: &TimeRange) -> bool {
@@ -505,6 +1251,10 @@ impl Flow {
             channels: None,
             flow_collection: None,
             available_timerange: None,
+            retention_seconds: None,
+            notify_url: None,
+            notify_secret: None,
+            owner: None,
             created_at: now,
             updated_at: now,
         }
@@ -526,6 +1276,37 @@ impl Source {
             tags: HashMap::new(),
             created_at: now,
             updated_at: now,
+            owner: None,
         }
     }
-} 
\ No newline at end of file
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_format_parses_urns_and_aliases() {
+        for format in ContentFormat::ALL {
+            assert_eq!(format.urn().parse::<ContentFormat>().unwrap(), format);
+            assert_eq!(format.alias().parse::<ContentFormat>().unwrap(), format);
+            assert_eq!(format.alias().to_uppercase().parse::<ContentFormat>().unwrap(), format);
+            assert_eq!(serde_json::to_value(&format).unwrap(), format.urn());
+        }
+        assert_eq!(" Image ".parse::<ContentFormat>().unwrap(), ContentFormat::Image);
+    }
+
+    #[test]
+    fn test_content_format_typo_lists_accepted_forms() {
+        let err = "vidoe".parse::<ContentFormat>().unwrap_err();
+        let message = err.to_string();
+        assert!(matches!(err, TamsError::InvalidFormat { .. }));
+        assert!(message.contains("'vidoe'"));
+        for format in ContentFormat::ALL {
+            assert!(message.contains(format.alias()) && message.contains(format.urn()));
+        }
+
+        // The short name only counts under its own namespace
+        assert!("urn:x-nmos:format:image".parse::<ContentFormat>().is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//! Request normalization applied before routing.
+//!
+//! With `server.trailing_slash_equivalent` set, `trailing_slash_middleware`
+//! serves `/flows/` as `/flows`. The path is rewritten rather than redirected
+//! so that POST and PUT bodies aren't lost on the way; the middleware wraps the
+//! router, as a layer added to it runs only after a route has been picked.
+//!
+//! `duplicate_query_middleware` refuses scalar query parameters given more
+//! than once (`?limit=10&limit=50`) with 400 naming the parameter, instead of
+//! one value being picked silently. Only tag filters may repeat.
+
+use crate::error::TamsError;
+use axum::{
+    extract::{Request, State},
+    http::Uri,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashSet;
+
+/// Query parameters that may legitimately appear more than once
+const REPEATABLE_QUERY_PREFIXES: &[&str] = &["tag.", "tag_exists."];
+
+/// The first non-repeatable query parameter given more than once
+fn duplicate_query_param(query: &str) -> Option<String> {
+    let mut seen = HashSet::new();
+    url::form_urlencoded::parse(query.as_bytes())
+        .map(|(name, _)| name.into_owned())
+        .filter(|name| !REPEATABLE_QUERY_PREFIXES.iter().any(|prefix| name.starts_with(prefix)))
+        .find(|name| !seen.insert(name.clone()))
+}
+
+/// `uri` without trailing slashes on its path, or None if it has none to drop
+fn trim_trailing_slash(uri: &Uri) -> Option<Uri> {
+    let path = uri.path();
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.len() == path.len() || trimmed.is_empty() {
+        return None;
+    }
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{}", trimmed, query),
+        None => trimmed.to_string(),
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse().ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+/// Route `/path/` as `/path` when `trailing_slash_equivalent` is the state
+pub async fn trailing_slash_middleware(
+    State(trailing_slash_equivalent): State<bool>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if trailing_slash_equivalent {
+        if let Some(uri) = trim_trailing_slash(request.uri()) {
+            *request.uri_mut() = uri;
+        }
+    }
+    next.run(request).await
+}
+
+/// Refuse scalar query parameters given more than once
+pub async fn duplicate_query_middleware(request: Request, next: Next) -> Response {
+    if let Some(name) = request.uri().query().and_then(duplicate_query_param) {
+        return TamsError::BadRequest(format!("Query parameter '{}' may only be given once", name)).into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        http::StatusCode,
+        routing::get,
+        Router,
+    };
+    use tower::{Layer, ServiceExt};
+
+    async fn send(trailing_slash_equivalent: bool, request: Request) -> (StatusCode, String) {
+        let app = Router::new()
+            .route("/flows", get(|| async { "list" }).post(|body: String| async move { format!("created {}", body) }))
+            .route("/", get(|| async { "root" }))
+            .layer(axum::middleware::from_fn(duplicate_query_middleware));
+        let app = axum::middleware::from_fn_with_state(trailing_slash_equivalent, trailing_slash_middleware).layer(app);
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn get_request(uri: &str) -> Request {
+        Request::get(uri).body(axum::body::Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slashes_are_equivalent_when_enabled() {
+        assert_eq!(send(true, get_request("/flows/?limit=5")).await, (StatusCode::OK, "list".to_string()));
+        assert_eq!(send(true, get_request("/")).await, (StatusCode::OK, "root".to_string()));
+        let post_request = Request::post("/flows/").body(axum::body::Body::from("flow")).unwrap();
+        assert_eq!(send(true, post_request).await, (StatusCode::OK, "created flow".to_string()));
+
+        assert_eq!(send(false, get_request("/flows/")).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(send(false, get_request("/flows")).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_query_params_are_refused() {
+        let (status, body) = send(false, get_request("/flows?limit=10&limit=50")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("'limit'"));
+
+        let (status, _) = send(false, get_request("/flows?tag.genre=news&tag.genre=sport&limit=10")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(duplicate_query_param("a=1&b=2&a%3D=3"), None);
+        assert_eq!(duplicate_query_param("a=1&b=2&b=3").as_deref(), Some("b"));
+    }
+}
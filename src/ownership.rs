@@ -0,0 +1,385 @@
+//! Ownership of sources and flows, for instances shared between teams.
+//!
+//! Sources and flows record the principal that created them as their
+//! `owner`, and `PUT /flows/:id/owner` hands a flow to another. With
+//! `auth.enforce_ownership` set, source and flow listings only include the
+//! caller's own, and `ownership_middleware` answers any request under
+//! `/sources/:id` or `/flows/:id` for one the caller doesn't own with 404, as
+//! if it didn't exist, so reads and changes alike are refused without
+//! revealing it. Handlers addressing flows some other way, such as by a body
+//! or query parameter, check them with [`hidden_from`]. Principals in
+//! `auth.admin_principals` see and change everything.
+//!
+//! Deletion requests go with their flow: creation, listings, reads and
+//! amendments are limited the same way, and a request keeps its owner once
+//! the flow is gone. A webhook registered by a principal under
+//! `auth.enforce_ownership` can only have its secret rotated by that
+//! principal, and it, like a principal's `/service/events` stream, only hears
+//! of that principal's flows, though events about no flow reach them.
+//!
+//! Objects and the webhook list stay shared. Requests without credentials
+//! (`auth.require_auth` unset) carry no principal and are not restricted.
+
+use crate::{
+    auth::Principal,
+    config::AuthConfig,
+    error::TamsError,
+    handlers::AppState,
+    models::Flow,
+};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use uuid::Uuid;
+
+/// The owner a flow listing is limited to for this caller, if any
+pub fn listing_owner<'a>(config: &AuthConfig, principal: Option<&'a Principal>) -> Option<&'a str> {
+    principal
+        .filter(|principal| config.enforce_ownership && !principal.admin)
+        .map(|principal| principal.name.as_str())
+}
+
+/// Whether a resource owned by `owner` is hidden from this caller, who is
+/// then answered as if it didn't exist
+pub fn hidden_from(config: &AuthConfig, principal: Option<&Principal>, owner: Option<&str>) -> bool {
+    listing_owner(config, principal).is_some_and(|caller| owner != Some(caller))
+}
+
+/// Hide a flow's notify_url from a principal that isn't its owner or an
+/// admin; the callback is only shown to those who may change it
+pub fn hide_notify_url(flow: &mut Flow, principal: Option<&Principal>) {
+    if principal.is_some_and(|principal| !principal.can_access(flow.owner.as_deref())) {
+        flow.notify_url = None;
+    }
+}
+
+/// An owned resource addressed by a request path
+#[derive(Debug, PartialEq)]
+enum OwnedResource {
+    Source(Uuid),
+    Flow(Uuid),
+}
+
+/// The source or flow a `/sources/:id...` or `/flows/:id...` path addresses.
+/// The id is percent-decoded first, as the handler's `Path` extractor does, so
+/// an encoded id can't address a resource without it being checked.
+fn resource_in_path(path: &str) -> Option<OwnedResource> {
+    let id_after = |prefix: &str| {
+        let segment = path.strip_prefix(prefix)?.split('/').next()?;
+        Uuid::parse_str(&percent_encoding::percent_decode_str(segment).decode_utf8().ok()?).ok()
+    };
+    id_after("/sources/").map(OwnedResource::Source).or_else(|| id_after("/flows/").map(OwnedResource::Flow))
+}
+
+/// Refuse requests for sources and flows owned by another principal with 404
+pub async fn ownership_middleware(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let principal = request.extensions().get::<Principal>();
+    let Some(caller) = listing_owner(&state.config.auth, principal).map(str::to_string) else {
+        return next.run(request).await;
+    };
+    let found = match resource_in_path(request.uri().path()) {
+        Some(OwnedResource::Source(id)) => {
+            state.database.get_source(&id).await.map(|source| source.map(|source| (source.owner, "Source")))
+        }
+        Some(OwnedResource::Flow(id)) => state.database.get_flow(&id).await.map(|flow| flow.map(|flow| (flow.owner, "Flow"))),
+        None => return next.run(request).await,
+    };
+    match found {
+        Ok(Some((owner, kind))) if owner.as_deref() != Some(caller.as_str()) => {
+            tracing::debug!("Hiding {} owned by {:?} from {}", request.uri().path(), owner, caller);
+            TamsError::NotFound(format!("{} not found", kind)).into_response()
+        }
+        Ok(_) => next.run(request).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{auth_middleware, create_jwt_token, AuthState},
+        handlers::{create_flow, get_flow, list_flows, set_flow_owner, tests::create_test_state_with},
+    };
+    use axum::{
+        body::Body,
+        http::{header, Method, StatusCode},
+        routing::{get, put},
+        Router,
+    };
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_resource_in_path() {
+        let id = Uuid::new_v4();
+        assert_eq!(resource_in_path(&format!("/flows/{}", id)), Some(OwnedResource::Flow(id)));
+        assert_eq!(resource_in_path(&format!("/flows/{}/segments", id)), Some(OwnedResource::Flow(id)));
+        assert_eq!(resource_in_path("/flows/ids"), None);
+        assert_eq!(resource_in_path(&format!("/sources/{}/coverage-diff", id)), Some(OwnedResource::Source(id)));
+        assert_eq!(resource_in_path(&format!("/objects/{}", id)), None);
+        assert_eq!(resource_in_path(&format!("/flows/{}", percent_encode_first(&id))), Some(OwnedResource::Flow(id)));
+    }
+
+    /// The id with its first character percent-encoded, as a client may send it
+    fn percent_encode_first(id: &Uuid) -> String {
+        let id = id.to_string();
+        format!("%{:02X}{}", id.as_bytes()[0], &id[1..])
+    }
+
+    #[tokio::test]
+    async fn test_percent_encoded_ids_are_checked_like_plain_ones() {
+        let app = crate::routes::tests::TestApp::with_config(|config| config.auth.enforce_ownership = true).await;
+        let mut flow = Flow::new(Uuid::new_v4(), crate::models::ContentFormat::Video);
+        flow.owner = Some("team-a".to_string());
+        app.state.database.create_flow(&flow).await.unwrap();
+        let encoded = format!("/x-tams/v6.0/flows/{}", percent_encode_first(&flow.id));
+        let status = |principal: &str, method: Method| {
+            let response = app.send(request_as(principal, method, &encoded, None));
+            async move { response.await.status() }
+        };
+
+        assert_eq!(status("team-b", Method::GET).await, StatusCode::NOT_FOUND);
+        assert_eq!(status("team-b", Method::DELETE).await, StatusCode::NOT_FOUND);
+        assert!(app.state.database.get_flow(&flow.id).await.unwrap().is_some());
+        assert_eq!(status("team-a", Method::GET).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_principals_only_see_their_own_flows() {
+        let (state, _temp_dir) = create_test_state_with(|config| {
+            config.auth.require_auth = true;
+            config.auth.enforce_ownership = true;
+            config.auth.admin_principals = vec!["ops".to_string()];
+        })
+        .await;
+        let auth_state = Arc::new(AuthState::new(state.config.auth.clone()));
+        let app = Router::new()
+            .route("/flows", get(list_flows).post(create_flow))
+            .route("/flows/:flow_id", get(get_flow))
+            .route("/flows/:flow_id/owner", put(set_flow_owner))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), ownership_middleware))
+            .with_state(state.clone())
+            .layer(axum::middleware::from_fn_with_state(auth_state, auth_middleware));
+
+        let call = |principal: &str, method: Method, uri: String, body: Option<Value>| {
+            let token = create_jwt_token(principal, &state.config.auth.jwt_secret, state.clock.now()).unwrap();
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body.map(|body| Body::from(body.to_string())).unwrap_or_default())
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice::<Value>(&bytes).unwrap_or(Value::Null))
+            }
+        };
+        let create = json!({"format": "urn:x-nmos:format:video", "tags": {}});
+        let (status, flow_a) = call("team-a", Method::POST, "/flows".to_string(), Some(create.clone())).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(flow_a["owner"], "team-a");
+        let (_, flow_b) = call("team-b", Method::POST, "/flows".to_string(), Some(create)).await;
+        let flow_a = format!("/flows/{}", flow_a["id"].as_str().unwrap());
+        let flow_b_id = flow_b["id"].as_str().unwrap().to_string();
+
+        let listed = |flows: &Value| -> Vec<String> {
+            flows["flows"].as_array().unwrap().iter().map(|flow| flow["owner"].as_str().unwrap().to_string()).collect()
+        };
+        let (_, flows) = call("team-b", Method::GET, "/flows".to_string(), None).await;
+        assert_eq!(listed(&flows), ["team-b"]);
+        assert_eq!(call("team-b", Method::GET, flow_a.clone(), None).await.0, StatusCode::NOT_FOUND);
+        let transfer = json!({"owner": "team-b"});
+        let (status, _) = call("team-b", Method::PUT, format!("{}/owner", flow_a), Some(transfer.clone())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (_, flows) = call("ops", Method::GET, "/flows".to_string(), None).await;
+        let mut owners = listed(&flows);
+        owners.sort();
+        assert_eq!(owners, ["team-a", "team-b"]);
+        assert_eq!(call("ops", Method::GET, format!("/flows/{}", flow_b_id), None).await.0, StatusCode::OK);
+
+        let (status, flow) = call("team-a", Method::PUT, format!("{}/owner", flow_a), Some(transfer)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(flow["owner"], "team-b");
+        assert_eq!(call("team-a", Method::GET, flow_a.clone(), None).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(call("team-b", Method::GET, flow_a, None).await.0, StatusCode::OK);
+        let (_, flows) = call("team-a", Method::GET, "/flows".to_string(), None).await;
+        assert!(listed(&flows).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_notify_url_is_only_shown_to_owners_and_admins() {
+        let app = crate::routes::tests::TestApp::with_config(|config| config.validation.allow_private_notify_urls = true).await;
+        let call = |principal: Principal, method: Method, uri: String, body: Option<Value>| {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .extension(principal)
+                .body(body.map(|body| Body::from(body.to_string())).unwrap_or_default())
+                .unwrap();
+            let response = app.send(request);
+            async move {
+                let response = response.await;
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<Value>(&bytes).unwrap()
+            }
+        };
+        let owner = || Principal { name: "team-a".to_string(), admin: false };
+        let other = || Principal { name: "team-b".to_string(), admin: false };
+        let admin = || Principal { name: "ops".to_string(), admin: true };
+
+        let create = json!({"format": "urn:x-nmos:format:video", "tags": {}, "notify_url": "http://127.0.0.1:9/hook", "notify_secret": "s"});
+        let flow = call(owner(), Method::POST, "/flows".to_string(), Some(create)).await;
+        let uri = format!("/flows/{}", flow["id"].as_str().unwrap());
+        assert_eq!(call(owner(), Method::GET, uri.clone(), None).await["notify_url"], "http://127.0.0.1:9/hook");
+        assert_eq!(call(admin(), Method::GET, uri.clone(), None).await["notify_url"], "http://127.0.0.1:9/hook");
+        assert!(call(other(), Method::GET, uri, None).await.get("notify_url").is_none());
+        let flows = call(other(), Method::GET, "/flows".to_string(), None).await;
+        assert!(flows["flows"][0].get("notify_url").is_none());
+    }
+
+    fn request_as(principal: &str, method: Method, uri: &str, body: Option<Value>) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .extension(Principal { name: principal.to_string(), admin: false })
+            .body(body.map(|body| Body::from(body.to_string())).unwrap_or_default())
+            .unwrap()
+    }
+
+    async fn json_body(response: Response) -> Value {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_embedded_flows_are_limited_before_the_per_source_limit() {
+        let app = crate::routes::tests::TestApp::with_config(|config| config.auth.enforce_ownership = true).await;
+        let mut source = crate::models::Source::new(Uuid::new_v4(), crate::models::ContentFormat::Video);
+        source.owner = Some("team-b".to_string());
+        app.state.database.create_source(&source).await.unwrap();
+        for owner in ["team-a", "team-a", "team-b"] {
+            let mut flow = Flow::new(Uuid::new_v4(), crate::models::ContentFormat::Video);
+            flow.source_id = Some(source.id);
+            flow.owner = Some(owner.to_string());
+            app.state.database.create_flow(&flow).await.unwrap();
+        }
+
+        let response = app.send(request_as("team-b", Method::GET, "/sources?include=flows&flows_limit=1", None)).await;
+        let listing = json_body(response).await;
+        let flows = listing["sources"][0]["flows"].as_array().unwrap();
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0]["owner"], "team-b");
+        assert_eq!(listing["sources"][0]["flows_has_more"], false);
+    }
+
+    #[tokio::test]
+    async fn test_deletion_requests_are_scoped_to_the_flow_owner() {
+        let app = crate::routes::tests::TestApp::with_config(|config| config.auth.enforce_ownership = true).await;
+        let mut flow = Flow::new(Uuid::new_v4(), crate::models::ContentFormat::Video);
+        flow.owner = Some("team-a".to_string());
+        app.state.database.create_flow(&flow).await.unwrap();
+        let now = chrono::Utc::now();
+        let request = crate::models::DeletionRequest {
+            id: Uuid::new_v4().to_string(),
+            flow_id: flow.id,
+            timerange: None,
+            status: crate::models::DeletionRequest::PENDING.to_string(),
+            progress: None,
+            created_at: now,
+            updated_at: now,
+        };
+        let job = crate::models::Job::new("test", Some(&request.id), json!({}), now);
+        app.state.database.create_deletion_request_with_job(&request, &job).await.unwrap();
+        let uri = format!("/flow-delete-requests/{}", request.id);
+        let amend = json!({"timerange": {"start": "0:0", "end": "10:0"}});
+
+        let listed = json_body(app.send(request_as("team-b", Method::GET, "/flow-delete-requests", None)).await).await;
+        assert!(listed["deletion_requests"].as_array().unwrap().is_empty());
+        assert_eq!(app.send(request_as("team-b", Method::GET, &uri, None)).await.status(), StatusCode::NOT_FOUND);
+        let response = app.send(request_as("team-b", Method::PATCH, &uri, Some(amend.clone()))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let listed = json_body(app.send(request_as("team-a", Method::GET, "/flow-delete-requests", None)).await).await;
+        assert_eq!(listed["deletion_requests"][0]["id"], request.id);
+        assert_eq!(app.send(request_as("team-a", Method::PATCH, &uri, Some(amend))).await.status(), StatusCode::OK);
+
+        // The requests follow the flow to its new owner, and outlive it
+        app.state.database.set_flow_owner(&flow.id, "team-b", now).await.unwrap();
+        app.state.database.delete_flow(&flow.id).await.unwrap();
+        assert_eq!(app.send(request_as("team-b", Method::GET, &uri, None)).await.status(), StatusCode::OK);
+        assert_eq!(app.send(request_as("team-a", Method::GET, &uri, None)).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_sources_and_flows_named_outside_the_path_are_scoped_to_their_owner() {
+        let app = crate::routes::tests::TestApp::with_config(|config| config.auth.enforce_ownership = true).await;
+        let call = |principal: &str, method: Method, uri: &str, body: Option<Value>| {
+            let response = app.send(request_as(principal, method, uri, body));
+            async move { response.await.status() }
+        };
+
+        let source_id = Uuid::new_v4();
+        let create = json!({"id": source_id, "format": "urn:x-nmos:format:video", "tags": {}});
+        assert_eq!(call("team-a", Method::POST, "/sources", Some(create)).await, StatusCode::CREATED);
+        let source = format!("/sources/{}", source_id);
+        assert_eq!(call("team-b", Method::GET, &source, None).await, StatusCode::NOT_FOUND);
+        assert_eq!(call("team-b", Method::DELETE, &source, None).await, StatusCode::NOT_FOUND);
+        let listed = json_body(app.send(request_as("team-b", Method::GET, "/sources", None)).await).await;
+        assert!(listed["sources"].as_array().unwrap().is_empty());
+        let listed = json_body(app.send(request_as("team-a", Method::GET, "/sources", None)).await).await;
+        assert_eq!(listed["sources"][0]["owner"], "team-a");
+
+        // A flow's source_id is named in the body, so is checked by the handler
+        let under_source = json!({"source_id": source_id, "format": "urn:x-nmos:format:video", "tags": {}});
+        assert_eq!(call("team-b", Method::POST, "/flows", Some(under_source.clone())).await, StatusCode::NOT_FOUND);
+        assert_eq!(call("team-a", Method::POST, "/flows", Some(under_source)).await, StatusCode::CREATED);
+        let own = json_body(
+            app.send(request_as("team-b", Method::POST, "/flows", Some(json!({"format": "urn:x-nmos:format:video", "tags": {}}))))
+                .await,
+        )
+        .await;
+        let own = format!("/flows/{}", own["id"].as_str().unwrap());
+        let moved = json!({"source_id": source_id});
+        assert_eq!(call("team-b", Method::PUT, &own, Some(moved)).await, StatusCode::NOT_FOUND);
+        assert_eq!(call("team-b", Method::PUT, &own, Some(json!({"label": "kept"}))).await, StatusCode::OK);
+
+        let mut flows = Vec::new();
+        for owner in ["team-a", "team-b"] {
+            let mut flow = Flow::new(Uuid::new_v4(), crate::models::ContentFormat::Video);
+            flow.source_id = Some(source_id);
+            flow.owner = Some(owner.to_string());
+            app.state.database.create_flow(&flow).await.unwrap();
+            flows.push(flow.id);
+        }
+        let diff = format!("{}/coverage-diff?flow_a={}&flow_b={}", source, flows[0], flows[1]);
+        assert_eq!(call("team-a", Method::GET, &diff, None).await, StatusCode::NOT_FOUND);
+        let admin = Request::builder()
+            .uri(&diff)
+            .extension(Principal { name: "ops".to_string(), admin: true })
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(app.send(admin).await.status(), StatusCode::OK);
+
+        let deletion = |flow_id: Uuid| Some(json!({"flow_id": flow_id}));
+        assert_eq!(call("team-b", Method::POST, "/flow-delete-requests", deletion(flows[0])).await, StatusCode::NOT_FOUND);
+        assert_eq!(call("team-a", Method::POST, "/flow-delete-requests", deletion(flows[0])).await, StatusCode::OK);
+
+        let register = json!({"url": "https://hooks.example.com/a", "api_key_value": "k1", "events": ["*"]});
+        let webhook = json_body(app.send(request_as("team-a", Method::POST, "/service/webhooks", Some(register))).await).await;
+        let rotate = format!("/service/webhooks/{}/secret", webhook["id"].as_str().unwrap());
+        let secret = || Some(json!({"api_key_value": "k2"}));
+        assert_eq!(call("team-b", Method::PUT, &rotate, secret()).await, StatusCode::NOT_FOUND);
+        assert_eq!(call("team-a", Method::PUT, &rotate, secret()).await, StatusCode::NO_CONTENT);
+    }
+}
@@ -0,0 +1,139 @@
+//! Typed query parameters of the source and flow listings.
+//!
+//! `GET /sources` and `GET /flows` take their paging, `include` options and
+//! filters as [`SourceQueryParams`] and [`FlowQueryParams`], which turn into
+//! the database's [`SourceFilters`] and [`FlowFilters`]. Every filter given
+//! must match, so filters that contradict each other (say a video format and
+//! an audio codec) give an empty listing rather than an error. A value that
+//! doesn't parse is refused with 400. Which filter combinations are served by
+//! an index is noted on the filter structs.
+
+use crate::{
+    config::ValidationConfig,
+    database::{FlowFilters, SourceFilters},
+    error::{TamsError, TamsResult},
+    models::{ContentFormat, PaginationParams},
+    time_utils, validation,
+};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::request::Parts,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use uuid::Uuid;
+
+/// `GET /sources?format=&label=&include=flows&flows_limit=&include_total=&limit=&page=`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SourceQueryParams {
+    pub limit: Option<u32>,
+    pub page: Option<String>,
+    /// Comma-separated extras; `flows` embeds each source's first flows
+    pub include: Option<String>,
+    pub flows_limit: Option<u32>,
+    #[serde(default)]
+    pub include_total: bool,
+    /// Format URN or short alias, as accepted by [`ContentFormat`]'s `FromStr`
+    pub format: Option<String>,
+    pub label: Option<String>,
+}
+
+impl SourceQueryParams {
+    pub fn paging(&self) -> PaginationParams {
+        PaginationParams { limit: self.limit, page: self.page.clone() }
+    }
+
+    pub fn includes(&self, field: &str) -> bool {
+        includes(self.include.as_deref(), field)
+    }
+
+    pub fn filters(&self) -> TamsResult<SourceFilters> {
+        Ok(SourceFilters {
+            format: parse_format(self.format.as_deref())?,
+            label: self.label.clone(),
+        })
+    }
+}
+
+/// `GET /flows?source_id=&format=&label=&codec=&frame_width=&frame_height=&timerange=`,
+/// plus `include=flow_collection`, `include_total` and paging
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FlowQueryParams {
+    pub limit: Option<u32>,
+    pub page: Option<String>,
+    pub include: Option<String>,
+    #[serde(default)]
+    pub include_total: bool,
+    pub source_id: Option<Uuid>,
+    pub format: Option<String>,
+    pub label: Option<String>,
+    pub codec: Option<String>,
+    pub frame_width: Option<u32>,
+    pub frame_height: Option<u32>,
+    /// Only flows with segments in this range, in TAMS notation; either bound may be open
+    pub timerange: Option<String>,
+}
+
+impl FlowQueryParams {
+    pub fn paging(&self) -> PaginationParams {
+        PaginationParams { limit: self.limit, page: self.page.clone() }
+    }
+
+    pub fn includes(&self, field: &str) -> bool {
+        includes(self.include.as_deref(), field)
+    }
+
+    /// The timerange filter is held to the configured maximum query duration
+    pub fn filters(&self, validation: &ValidationConfig) -> TamsResult<FlowFilters> {
+        let (start_ns, end_ns) = self
+            .timerange
+            .as_deref()
+            .map(time_utils::parse_timerange_bounds)
+            .transpose()?
+            .unwrap_or_default();
+        validation::check_query_bounds(start_ns, end_ns, validation)?;
+        Ok(FlowFilters {
+            source_id: self.source_id,
+            format: parse_format(self.format.as_deref())?,
+            label: self.label.clone(),
+            codec: self.codec.clone(),
+            frame_width: self.frame_width,
+            frame_height: self.frame_height,
+            start_ns,
+            end_ns,
+        })
+    }
+}
+
+fn includes(include: Option<&str>, field: &str) -> bool {
+    include.is_some_and(|include| include.split(',').any(|included| included.trim() == field))
+}
+
+fn parse_format(format: Option<&str>) -> TamsResult<Option<ContentFormat>> {
+    format.map(str::parse).transpose()
+}
+
+/// Deserialize the query string, refusing one that doesn't fit with 400
+fn from_query<T: DeserializeOwned>(parts: &Parts) -> TamsResult<T> {
+    Query::try_from_uri(&parts.uri)
+        .map(|Query(params)| params)
+        .map_err(|rejection| TamsError::BadRequest(rejection.body_text()))
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for SourceQueryParams {
+    type Rejection = TamsError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        from_query(parts)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for FlowQueryParams {
+    type Rejection = TamsError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        from_query(parts)
+    }
+}
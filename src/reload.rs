@@ -0,0 +1,240 @@
+use crate::{
+    config::{AppConfig, PaginationConfig},
+    error::{TamsError, TamsResult},
+    jobs::JobQueue,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use tracing::{info, warn};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// Swaps the log filter installed at startup
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Settings a reload applies to the running server
+const RELOADABLE: &[&str] = &[
+    "logging.level",
+    "pagination.default_limit",
+    "pagination.max_limit",
+    "cleanup.temp_file_retention_hours",
+    "cleanup.orphaned_object_retention_days",
+    "jobs.retry_base_seconds",
+    "jobs.retry_max_seconds",
+    "jobs.max_attempts",
+];
+
+/// Settings a reload never touches, whatever the file says: the listener,
+/// the database, where media lives and the secrets. A trailing `.` covers a
+/// whole section.
+const REFUSED: &[&str] = &[
+    "server.",
+    "database.url",
+    "media_storage.base_path",
+    "media_storage.temp_path",
+    "auth.jwt_secret",
+    "auth.basic_auth_username",
+    "auth.basic_auth_password",
+    "webhooks.encryption_key",
+    "webhooks.derive_encryption_key_from_jwt_secret",
+];
+
+/// Shown in place of secrets by the effective config
+const SECRETS: &[&str] = &["auth.jwt_secret", "auth.basic_auth_password", "webhooks.encryption_key"];
+
+/// Which changed settings a reload applied and which it left alone
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigReload {
+    pub reloaded_at: DateTime<Utc>,
+    pub applied: Vec<String>,
+    /// Changed in the file but never reloadable
+    pub refused: Vec<String>,
+    /// Changed in the file but only read at startup
+    pub requires_restart: Vec<String>,
+}
+
+/// Re-reads the config file on SIGHUP or `POST /admin/reload-config` and
+/// applies the hot-reloadable settings in [`RELOADABLE`]; the rest keep their
+/// startup values until the next restart.
+pub struct ConfigReloader {
+    path: String,
+    effective: RwLock<AppConfig>,
+    reloaded_at: RwLock<Option<DateTime<Utc>>>,
+    log_filter: Option<LogFilterHandle>,
+}
+
+impl ConfigReloader {
+    pub fn new(path: &str, config: AppConfig, log_filter: Option<LogFilterHandle>) -> Self {
+        Self {
+            path: path.to_string(),
+            effective: RwLock::new(config),
+            reloaded_at: RwLock::new(None),
+            log_filter,
+        }
+    }
+
+    /// The config in force: the startup values with any reloads applied
+    pub fn effective(&self) -> AppConfig {
+        self.effective.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn reloaded_at(&self) -> Option<DateTime<Utc>> {
+        *self.reloaded_at.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn pagination(&self) -> PaginationConfig {
+        self.effective.read().unwrap_or_else(|e| e.into_inner()).pagination.clone()
+    }
+
+    /// The effective config as JSON with its secrets redacted
+    pub fn effective_json(&self) -> TamsResult<Value> {
+        let mut config = serde_json::to_value(self.effective())?;
+        for key in SECRETS {
+            if let Some(value) = pointer_mut(&mut config, key).filter(|value| !value.is_null()) {
+                *value = Value::String("[redacted]".to_string());
+            }
+        }
+        Ok(config)
+    }
+
+    /// Re-read the config file and apply it
+    pub fn reload(&self, jobs: &JobQueue, now: DateTime<Utc>) -> TamsResult<ConfigReload> {
+        let config = AppConfig::from_file(&self.path)?;
+        self.apply(config, jobs, now)
+    }
+
+    /// Apply the reloadable settings that differ in `config`. Nothing is
+    /// applied if any of them is invalid.
+    pub fn apply(&self, config: AppConfig, jobs: &JobQueue, now: DateTime<Utc>) -> TamsResult<ConfigReload> {
+        let mut effective = serde_json::to_value(self.effective())?;
+        let mut current = BTreeMap::new();
+        flatten("", &effective, &mut current);
+        let mut reloaded = BTreeMap::new();
+        flatten("", &serde_json::to_value(&config)?, &mut reloaded);
+
+        let mut report = ConfigReload {
+            reloaded_at: now,
+            applied: Vec::new(),
+            refused: Vec::new(),
+            requires_restart: Vec::new(),
+        };
+        for (key, value) in &reloaded {
+            if current.get(key) == Some(value) {
+                continue;
+            }
+            if RELOADABLE.contains(&key.as_str()) {
+                if let Some(slot) = pointer_mut(&mut effective, key) {
+                    *slot = value.clone();
+                    report.applied.push(key.clone());
+                }
+            } else if REFUSED.iter().any(|refused| key == refused || (refused.ends_with('.') && key.starts_with(refused))) {
+                report.refused.push(key.clone());
+            } else {
+                report.requires_restart.push(key.clone());
+            }
+        }
+
+        let effective: AppConfig = serde_json::from_value(effective)
+            .map_err(|e| TamsError::Validation(format!("Invalid reloaded config: {}", e)))?;
+        let log_filter = EnvFilter::try_new(&effective.logging.level).map_err(|e| {
+            TamsError::Validation(format!("Invalid logging.level '{}': {}", effective.logging.level, e))
+        })?;
+
+        if report.applied.iter().any(|key| key == "logging.level") {
+            if let Some(handle) = &self.log_filter {
+                handle
+                    .reload(log_filter)
+                    .map_err(|e| TamsError::Internal(format!("Failed to change the log level: {}", e)))?;
+            }
+        }
+        jobs.set_retry_policy(&effective.jobs);
+        *self.effective.write().unwrap_or_else(|e| e.into_inner()) = effective;
+        *self.reloaded_at.write().unwrap_or_else(|e| e.into_inner()) = Some(now);
+
+        info!("Config reloaded; applied: {:?}", report.applied);
+        if !report.refused.is_empty() {
+            warn!("Config reload refused to change: {:?}", report.refused);
+        }
+        if !report.requires_restart.is_empty() {
+            warn!("Config reload skipped settings that need a restart: {:?}", report.requires_restart);
+        }
+        Ok(report)
+    }
+}
+
+/// Leaf values of a JSON document keyed by their dotted path; arrays are leaves
+fn flatten(prefix: &str, value: &Value, out: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(&path, value, out);
+            }
+        }
+        _ => {
+            out.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
+fn pointer_mut<'a>(value: &'a mut Value, dotted: &str) -> Option<&'a mut Value> {
+    dotted
+        .split('.')
+        .try_fold(value, |value, key| value.as_object_mut().and_then(|fields: &mut Map<String, Value>| fields.get_mut(key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::create_test_database;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn startup_config() -> AppConfig {
+        AppConfig::from_file("config").unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reload_changes_log_level_and_tunables() {
+        let (database, _temp_dir) = create_test_database().await;
+        let jobs = JobQueue::new(database, startup_config().jobs);
+        let (filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let _subscriber = tracing_subscriber::registry().with(filter);
+        let reloader = ConfigReloader::new("config", startup_config(), Some(handle.clone()));
+
+        let mut config = startup_config();
+        config.logging.level = "debug".to_string();
+        config.pagination.max_limit = 7;
+        config.jobs.retry_base_seconds = 1;
+        let now = Utc::now();
+        let report = reloader.apply(config, &jobs, now).unwrap();
+
+        assert_eq!(report.applied, vec!["jobs.retry_base_seconds", "logging.level", "pagination.max_limit"]);
+        assert!(report.refused.is_empty() && report.requires_restart.is_empty());
+        assert_eq!(handle.with_current(|filter| filter.to_string()).unwrap(), "debug");
+        assert_eq!(reloader.pagination().max_limit, 7);
+        assert_eq!(jobs.backoff(1), chrono::Duration::seconds(1));
+        assert_eq!(reloader.reloaded_at(), Some(now));
+        assert_eq!(reloader.effective_json().unwrap()["logging"]["level"], "debug");
+    }
+
+    #[tokio::test]
+    async fn test_reload_refuses_database_url_change() {
+        let (database, _temp_dir) = create_test_database().await;
+        let jobs = JobQueue::new(database, startup_config().jobs);
+        let reloader = ConfigReloader::new("config", startup_config(), None);
+
+        let mut config = startup_config();
+        config.database.url = "sqlite:/elsewhere/tams.db".to_string();
+        config.server.port += 1;
+        config.media_storage.stats_refresh_interval_seconds += 1;
+        let report = reloader.apply(config, &jobs, Utc::now()).unwrap();
+
+        assert!(report.applied.is_empty());
+        assert_eq!(report.refused, vec!["database.url", "server.port"]);
+        assert_eq!(report.requires_restart, vec!["media_storage.stats_refresh_interval_seconds"]);
+        assert_eq!(reloader.effective().database.url, startup_config().database.url);
+        assert_eq!(reloader.effective_json().unwrap()["auth"]["jwt_secret"], "[redacted]");
+    }
+}
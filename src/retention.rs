@@ -0,0 +1,169 @@
+use crate::{
+    error::{TamsError, TamsResult},
+    handlers::AppState,
+    metrics::metrics,
+    models::{EventNotification, EventType, SegmentsDeletedEvent},
+    storage::ObjectContext,
+};
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Job kind that expires one flow's segments. Each flow with a retention
+/// policy has one such job, deferred to the next sweep after every run.
+pub const FLOW_RETENTION_JOB: &str = "flow_retention";
+/// Retention jobs claimed per poll
+const RETENTION_JOBS_PER_POLL: u32 = 10;
+/// Orphaned objects reclaimed per flow per sweep
+const ORPHANED_OBJECTS_PER_SWEEP: u32 = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowRetentionJob {
+    pub flow_id: Uuid,
+}
+
+/// What a sweep of one flow removed
+#[derive(Debug, Default, PartialEq)]
+pub struct RetentionSweep {
+    pub segments_deleted: u64,
+    pub objects_reclaimed: u64,
+}
+
+/// Queue a retention job for every flow with a retention policy that has
+/// none. Returns how many were queued.
+pub async fn enqueue_unqueued_flows(state: &AppState) -> TamsResult<usize> {
+    let ids = state.database.get_unqueued_retention_flow_ids(FLOW_RETENTION_JOB).await?;
+    for id in &ids {
+        let payload = FlowRetentionJob {
+            flow_id: Uuid::parse_str(id)?,
+        };
+        state.jobs.enqueue(FLOW_RETENTION_JOB, Some(id), &payload).await?;
+    }
+    Ok(ids.len())
+}
+
+/// Queue and run retention jobs until the process exits
+pub fn spawn_retention_worker(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(state.jobs.poll_interval());
+        loop {
+            interval.tick().await;
+            match run_due_retention(&state).await {
+                Ok(_) => metrics().record_task_run("retention", state.clock.now()),
+                Err(e) => warn!("Retention worker poll failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Queue jobs for newly retained flows, then claim due retention jobs and
+/// sweep each flow, returning how many jobs were claimed
+pub async fn run_due_retention(state: &AppState) -> TamsResult<usize> {
+    enqueue_unqueued_flows(state).await?;
+    let jobs = state.jobs.claim_due(FLOW_RETENTION_JOB, RETENTION_JOBS_PER_POLL).await?;
+    let sweep_interval = Duration::seconds(state.config.retention.sweep_interval_seconds as i64);
+    for job in &jobs {
+        let payload: FlowRetentionJob = match serde_json::from_value(job.payload.clone()) {
+            Ok(payload) => payload,
+            Err(e) => {
+                state.jobs.fail_with_backoff(job, &format!("Invalid job payload: {}", e)).await?;
+                continue;
+            }
+        };
+
+        match sweep_flow(state, &payload.flow_id).await {
+            Ok(Some(_)) => state.jobs.defer(job, state.clock.now() + sweep_interval).await?,
+            // The flow is gone or no longer retains; a new policy queues a new job
+            Ok(None) => state.jobs.complete(job).await?,
+            Err(e) => {
+                warn!("Retention sweep of flow {} attempt {} failed: {}", payload.flow_id, job.attempts, e);
+                state.jobs.fail_with_backoff(job, &e.to_string()).await?;
+            }
+        }
+    }
+    Ok(jobs.len())
+}
+
+/// Delete the flow's segments that ended more than its `retention_seconds`
+/// ago, announcing each batch as a segments-deleted event, then reclaim the
+/// flow's objects left without segments for longer than the orphaned object
+/// retention. Read-only flows are left alone. Returns None when the flow no
+/// longer exists or has no retention policy.
+pub async fn sweep_flow(state: &AppState, flow_id: &Uuid) -> TamsResult<Option<RetentionSweep>> {
+    let Some(flow) = state.database.get_flow(flow_id).await? else {
+        return Ok(None);
+    };
+    let Some(retention_seconds) = flow.retention_seconds else {
+        return Ok(None);
+    };
+    let mut sweep = RetentionSweep::default();
+    if flow.is_read_only() {
+        return Ok(Some(sweep));
+    }
+
+    let retention = i64::try_from(retention_seconds)
+        .ok()
+        .and_then(Duration::try_seconds)
+        .ok_or_else(|| TamsError::Validation(format!("retention_seconds {} is out of range", retention_seconds)))?;
+    let cutoff = state.clock.now() - retention;
+    let batch_size = state.config.retention.batch_size.max(1);
+    loop {
+        let deletion = state.database.delete_flow_segments_ending_before(flow_id, cutoff, batch_size).await?;
+        let count: u64 = deletion.counts.iter().map(|(_, count)| count).sum();
+        sweep.segments_deleted += count;
+
+        if let Some(timerange) = deletion.deleted_timerange {
+            state.webhook_manager.send_flow_notification(flow.owner.as_deref(), EventNotification {
+                event_timestamp: state.clock.now(),
+                event_type: EventType::FlowsSegmentsDeleted,
+                event: SegmentsDeletedEvent {
+                    flow_id: *flow_id,
+                    timerange,
+                },
+            }).await;
+        }
+        if count < batch_size as u64 {
+            break;
+        }
+    }
+
+    let orphan_retention_days = state.reloader.effective().cleanup.orphaned_object_retention_days;
+    let created_before = state.clock.now() - Duration::days(orphan_retention_days as i64);
+    let orphans = state
+        .database
+        .list_orphaned_flow_objects(flow_id, created_before, ORPHANED_OBJECTS_PER_SWEEP)
+        .await?;
+    for (object_id, storage_class, format) in orphans {
+        let context = ObjectContext {
+            flow_id: Some(*flow_id),
+            storage_class,
+            format,
+        };
+        // Re-checked and removed under the write lock, which segment writes
+        // take too, so no segment can pick the object up until it is gone
+        let mut tx = state.database.begin_transaction().await?;
+        if !tx.delete_orphaned_media_object(&object_id).await? {
+            tx.rollback().await?;
+            continue;
+        }
+        match state.storage.delete_object(&object_id, &context).await {
+            Ok(()) | Err(TamsError::ObjectNotFound { .. }) => {
+                tx.commit().await?;
+                sweep.objects_reclaimed += 1;
+            }
+            Err(e) => {
+                tx.rollback().await?;
+                warn!("Failed to remove orphaned object {} of flow {}: {}", object_id, flow_id, e);
+            }
+        }
+    }
+
+    if sweep != RetentionSweep::default() {
+        info!(
+            "Retention removed {} segments and {} orphaned objects from flow {}",
+            sweep.segments_deleted, sweep.objects_reclaimed, flow_id
+        );
+    }
+    Ok(Some(sweep))
+}
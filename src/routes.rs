@@ -0,0 +1,792 @@
+//! The application router: every route, and the middleware stack they share.
+//!
+//! `main` serves it wrapped in `trailing_slash_middleware`, which has to run
+//! before routing; the tests below drive the same router end to end.
+//!
+//! The API is mounted under `service.api_prefix` (`/x-tams/v6.0` unless
+//! configured otherwise), so a later API version can be served alongside it.
+//! With `service.serve_unprefixed` the same routes also answer at the root,
+//! marked `Deprecation: true`, until clients have moved to the prefix.
+
+use crate::{
+    auth::{auth_middleware, cors_layer, require_admin_middleware, AuthState},
+    encoding::json_encoding_middleware,
+    error::{TamsError, TamsResult},
+    events::stream_events,
+    handlers::*,
+    head::head_middleware,
+    normalize::duplicate_query_middleware,
+    ownership::ownership_middleware,
+    summary::get_service_summary,
+    transaction::transaction_middleware,
+    warnings::warnings_middleware,
+};
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::{self, Next},
+    response::Response,
+    routing::{get, post, put},
+    Router,
+};
+use std::sync::Arc;
+use tower::ServiceBuilder;
+use tower_http::trace::TraceLayer;
+
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+
+/// Mark responses from the unprefixed routes, which clients should stop using
+async fn deprecated_route_middleware(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(DEPRECATION, HeaderValue::from_static("true"));
+    response
+}
+
+/// Every route of the API over `app_state`, with auth, CORS and the other
+/// middleware applied, mounted under the configured prefix
+pub fn build_router(app_state: AppState) -> TamsResult<Router> {
+    let prefix = app_state.config.service.api_prefix.clone();
+    if !prefix.is_empty() && (!prefix.starts_with('/') || prefix.ends_with('/')) {
+        return Err(TamsError::Internal(format!(
+            "Invalid service.api_prefix '{}': it must start with '/' and not end with one",
+            prefix
+        )));
+    }
+    let serve_unprefixed = app_state.config.service.serve_unprefixed;
+    let api = build_api(app_state)?;
+    if prefix.is_empty() {
+        return Ok(api);
+    }
+    let router = Router::new().nest(&prefix, api.clone());
+    if !serve_unprefixed {
+        return Ok(router);
+    }
+    Ok(router.merge(api.layer(middleware::from_fn(deprecated_route_middleware))))
+}
+
+/// Operator endpoints, refused to principals that aren't admins
+fn admin_routes() -> Router<AppState> {
+    Router::new()
+        .route("/service/jobs", get(list_jobs))
+        .route("/flows/:flow_id/pause", post(pause_flow_ingest))
+        .route("/flows/:flow_id/resume", post(resume_flow_ingest))
+        .route("/admin/segment-bounds-backfill", get(get_segment_bounds_progress))
+        .route("/admin/pause-ingest",
+            get(get_ingest_pause_state)
+                .post(set_service_ingest_paused)
+        )
+        .route("/admin/deletion-worker/config",
+            get(get_deletion_worker_config)
+                .put(update_deletion_worker_config)
+        )
+        .route("/admin/config", get(get_effective_config))
+        .route("/admin/reload-config", post(reload_config))
+        // Repairs that rewrite every principal's flows
+        .route("/service/maintenance/recompute-timeranges", post(recompute_timeranges))
+        .route("/service/maintenance/reconcile-flow-stats", post(reconcile_flow_stats))
+        .route("/service/maintenance/normalize-vocabularies", post(normalize_vocabularies))
+        // Runs after auth_middleware has identified the caller
+        .route_layer(middleware::from_fn(require_admin_middleware))
+}
+
+/// The API's routes, relative to wherever they are mounted
+fn build_api(app_state: AppState) -> TamsResult<Router> {
+    // Create auth state
+    let auth_state = Arc::new(AuthState::new(app_state.config.auth.clone()));
+
+    // Build CORS layer
+    let cors = cors_layer(&app_state.config.cors)?;
+
+    Ok(Router::new()
+        // Root endpoints
+        .route("/", get(get_root))
+        .route("/service", get(get_service_info))
+        .route("/service/health/dependencies", get(get_dependency_health))
+        .route("/service/storage-stats", get(get_storage_stats))
+        .route("/service/summary", get(get_service_summary))
+        .route("/service/events", get(stream_events))
+        .route("/test", get(get_test_page))
+        
+        // Sources endpoints
+        .route("/sources", get(list_sources).post(create_source))
+        .route("/sources/:source_id", 
+            get(get_source)
+                .put(update_source)
+                .delete(delete_source)
+        )
+        .route("/sources/:source_id/coverage-diff", get(get_source_coverage_diff))
+        
+        // Flows endpoints
+        .route("/flows", get(list_flows).post(create_flow))
+        .route("/flows/ids", get(list_flow_ids))
+        .route("/flows/validate", post(validate_flow))
+        .route("/flows/:flow_id", 
+            get(get_flow)
+                .put(update_flow)
+                .delete(delete_flow)
+        )
+        .route("/flows/:flow_id/owner", put(set_flow_owner))
+        .route("/flows/:flow_id/finalize", post(finalize_flow))
+        
+        // Flow segments endpoints
+        .route("/flows/:flow_id/segments", 
+            get(list_flow_segments)
+                .post(post_flow_segments)
+                .delete(delete_flow_segments)
+        )
+        .route("/flows/:flow_id/segments/delete", post(delete_flow_segments_by_object))
+        .route("/flows/:flow_id/segments/rollup", get(get_flow_segment_rollup))
+        .route("/flows/:flow_id/gaps", get(get_flow_gaps))
+        .route("/flows/:flow_id/segments/get_urls", post(refresh_segment_get_urls))
+        .route("/flows/:flow_id/stream", get(stream_flow))
+        .route("/flows/:flow_id/segments/archive", get(archive_flow_segments))
+        
+        // Flow storage endpoints
+        .route("/flows/:flow_id/storage", get(list_flow_storage).post(allocate_storage))
+        
+        // Media objects endpoints
+        .route("/objects/:object_id", 
+            get(get_media_object)
+                .put(put_media_object)
+        )
+        .route("/objects/:object_id/download", get(download_media_object))
+        .route("/objects/:object_id/metadata", post(update_media_object_metadata))
+        
+        // Webhook endpoints
+        .route("/service/webhooks", 
+            get(list_webhooks)
+                .post(create_webhook)
+        )
+        .route("/service/webhooks/:webhook_id/secret", put(rotate_webhook_secret))
+        
+        // Admin endpoints
+        .merge(admin_routes())
+        
+        // Flow delete request endpoints
+        .route("/flow-delete-requests", 
+            get(list_deletion_requests)
+                .post(request_flow_deletion)
+        )
+        .route("/flow-delete-requests/:request_id",
+            get(get_deletion_request)
+                .patch(update_deletion_request)
+        )
+        
+        // Hides other principals' sources and flows; runs after auth_middleware identifies the caller
+        .layer(middleware::from_fn_with_state(app_state.clone(), ownership_middleware))
+
+        // Add application state
+        .with_state(app_state.clone())
+        
+        // Add middleware layers
+        .layer(
+            ServiceBuilder::new()
+                .layer(TraceLayer::new_for_http())
+                .layer(cors)
+                .layer(middleware::from_fn(head_middleware))
+                .layer(middleware::from_fn(json_encoding_middleware))
+                .layer(middleware::from_fn(duplicate_query_middleware))
+                .layer(middleware::from_fn_with_state(
+                    auth_state.clone(),
+                    auth_middleware,
+                ))
+                .layer(middleware::from_fn(time_format_middleware))
+                .layer(middleware::from_fn(warnings_middleware))
+                .layer(middleware::from_fn(transaction_middleware))
+        ))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        auth::Principal,
+        config::{AppConfig, MediaStoreConfig},
+        handlers::tests::create_test_state_with,
+        models::{ContentFormat, CreateSegmentRequest, Flow, Source, TimeRange},
+    };
+    use axum::{
+        body::Body,
+        http::{header, Method, Request, StatusCode},
+        response::Response,
+    };
+    use serde_json::{json, Value};
+    use tempfile::TempDir;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    /// The real router over a fresh database and storage in a temp directory
+    pub(crate) struct TestApp {
+        pub(crate) state: AppState,
+        router: Router,
+        _temp_dir: TempDir,
+    }
+
+    impl TestApp {
+        pub(crate) async fn new() -> Self {
+            Self::with_config(|_| {}).await
+        }
+
+        pub(crate) async fn with_config(configure: impl FnOnce(&mut AppConfig)) -> Self {
+            let (state, temp_dir) = create_test_state_with(configure).await;
+            let router = build_router(state.clone()).unwrap();
+            TestApp { state, router, _temp_dir: temp_dir }
+        }
+
+        pub(crate) async fn send(&self, request: Request<Body>) -> Response {
+            self.router.clone().oneshot(request).await.unwrap()
+        }
+
+        /// Send `body` as JSON, returning the status and the JSON response (Null if not JSON)
+        pub(crate) async fn call(&self, method: Method, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+            let request = Request::builder().method(method).uri(uri);
+            let request = match body {
+                Some(body) => request.header(header::CONTENT_TYPE, "application/json").body(Body::from(body.to_string())),
+                None => request.body(Body::empty()),
+            };
+            let response = self.send(request.unwrap()).await;
+            let status = response.status();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_source_flow_segment_lifecycle() {
+        let app = TestApp::new().await;
+
+        let source_id = Uuid::new_v4();
+        let (status, _) = app
+            .call(Method::POST, "/sources", Some(json!({"id": source_id, "format": "urn:x-nmos:format:video", "tags": {}})))
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, flow) = app
+            .call(
+                Method::POST,
+                "/flows",
+                Some(json!({"source_id": source_id, "format": "urn:x-nmos:format:video", "tags": {}})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let flow_id = flow["id"].as_str().unwrap().to_string();
+
+        // Upload an object through its allocation's put_url, then reference it
+        let (status, storage) = app.call(Method::POST, &format!("/flows/{}/storage", flow_id), Some(json!({"limit": 1}))).await;
+        assert_eq!(status, StatusCode::OK);
+        let object_id = storage["objects"][0]["object_id"].as_str().unwrap().to_string();
+        let put_url = storage["objects"][0]["put_url"].as_str().unwrap();
+        let put_path = &put_url[put_url.find("/objects/").unwrap()..];
+        let upload = Request::builder().method(Method::PUT).uri(put_path).body(Body::from("segment bytes")).unwrap();
+        assert_eq!(app.send(upload).await.status(), StatusCode::CREATED);
+        let probe = Request::builder()
+            .method(Method::HEAD)
+            .uri(format!("/objects/{}/download", object_id))
+            .body(Body::empty())
+            .unwrap();
+        let probe = app.send(probe).await;
+        assert_eq!(probe.status(), StatusCode::OK);
+        assert_eq!(probe.headers()[header::CONTENT_LENGTH], "13");
+
+        let stored = app.state.database.get_media_object_required(&object_id).await.unwrap();
+        assert_eq!(stored.size_bytes, Some(13));
+
+        let segment = json!({"object_id": object_id, "timerange": {"start": "0:0", "end": "10:0"}});
+        let (status, _) = app.call(Method::POST, &format!("/flows/{}/segments", flow_id), Some(segment)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, segments) = app.call(Method::GET, &format!("/flows/{}/segments", flow_id), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(segments["segments"][0]["object_id"], object_id.as_str());
+        assert_eq!(segments["segments"][0]["timerange"], "0:0:10:0");
+        let (status, flows) = app.call(Method::GET, "/flows", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(flows["flows"][0]["id"], flow_id.as_str());
+        assert_eq!(flows["flows"][0]["source_id"], source_id.to_string());
+
+        let (status, _) = app.call(Method::DELETE, &format!("/flows/{}", flow_id), None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = app.call(Method::GET, &format!("/flows/{}", flow_id), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let flow_id = Uuid::parse_str(&flow_id).unwrap();
+        assert!(app.state.database.get_flow_segments(&flow_id).await.unwrap().items.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_deletion_requests_are_created_through_the_router() {
+        let app = TestApp::new().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        app.state.database.create_flow(&flow).await.unwrap();
+
+        let (status, request) = app
+            .call(Method::POST, "/flow-delete-requests", Some(json!({"flow_id": flow.id, "timerange": "[0:0_10:0)"})))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(request["flow_id"], flow.id.to_string());
+        assert_eq!(request["status"], "pending");
+        let (status, _) = app.call(Method::POST, "/flow-delete-requests", Some(json!({"flow_id": flow.id, "timerange": "soon"}))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = app.call(Method::POST, "/flow-delete-requests", Some(json!({"flow_id": Uuid::new_v4()}))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_deletion_request_lifecycle() {
+        let app = TestApp::new().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        app.state.database.create_flow(&flow).await.unwrap();
+        for (object_id, start, end) in [("a", "0:0", "5:0"), ("b", "5:0", "10:0")] {
+            let segment = CreateSegmentRequest::new(object_id, start, end);
+            app.state.database.add_flow_segment(&segment.into_segment(flow.id)).await.unwrap();
+        }
+
+        let (status, request) = app.call(Method::POST, "/flow-delete-requests", Some(json!({"flow_id": flow.id}))).await;
+        assert_eq!(status, StatusCode::OK);
+        let uri = format!("/flow-delete-requests/{}", request["id"].as_str().unwrap());
+        let (status, listed) = app.call(Method::GET, "/flow-delete-requests", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(listed["deletion_requests"][0]["id"], request["id"]);
+
+        let amend = json!({"timerange": {"start": "0:0", "end": "5:0"}});
+        let (status, amended) = app.call(Method::PATCH, &uri, Some(amend.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        let stored: TimeRange = serde_json::from_str(amended["timerange"].as_str().unwrap()).unwrap();
+        assert_eq!((stored.start.as_str(), stored.end.as_deref()), ("0:0", Some("5:0")));
+
+        assert_eq!(crate::deletion::run_due_deletions(&app.state).await.unwrap(), 1);
+        let (status, done) = app.call(Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(done["status"], "done");
+        let (_, segments) = app.call(Method::GET, &format!("/flows/{}/segments", flow.id), None).await;
+        assert_eq!(segments["segments"].as_array().unwrap().len(), 1);
+        assert_eq!(app.call(Method::PATCH, &uri, Some(amend)).await.0, StatusCode::CONFLICT);
+        let (status, _) = app.call(Method::GET, &format!("/flow-delete-requests/{}", Uuid::new_v4()), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_repairs() {
+        let app = TestApp::new().await;
+        let mut drifted = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        drifted.available_timerange = Some(TimeRange::new("0:0", Some("1:0")));
+        app.state.database.create_flow(&drifted).await.unwrap();
+        let segment = CreateSegmentRequest::new("obj", "5:0", "9:0");
+        app.state.database.add_flow_segment(&segment.into_segment(drifted.id)).await.unwrap();
+
+        let (status, report) = app.call(Method::POST, "/service/maintenance/recompute-timeranges", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((report["flows_checked"].as_u64(), report["flows_corrected"].as_u64()), (Some(1), Some(1)));
+        let (_, flow) = app.call(Method::GET, &format!("/flows/{}", drifted.id), None).await;
+        assert_eq!(flow["available_timerange"]["start"], "5:0");
+
+        let (status, report) = app.call(Method::POST, "/service/maintenance/reconcile-flow-stats", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((report["flows_checked"].as_u64(), report["flows_corrected"].as_u64()), (Some(1), Some(0)));
+        let (status, report) = app.call(Method::POST, "/service/maintenance/normalize-vocabularies", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["flows_checked"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_source_and_flow_listings_page_without_repeats() {
+        let app = TestApp::new().await;
+        // Rows share creation times in batches, so pages have to break ties by id
+        let base = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        for i in 0..300 {
+            let mut source = Source::new(Uuid::new_v4(), ContentFormat::Video);
+            source.created_at = base + chrono::Duration::seconds(i / 7);
+            app.state.database.create_source(&source).await.unwrap();
+            let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+            flow.created_at = source.created_at;
+            app.state.database.create_flow(&flow).await.unwrap();
+        }
+
+        for (collection, uri) in [("sources", "/sources?limit=50"), ("flows", "/flows?limit=50&format=urn:x-nmos:format:video")] {
+            let mut seen = std::collections::HashSet::new();
+            let mut pages = 0;
+            let mut next = uri.to_string();
+            loop {
+                let (status, listing) = app.call(Method::GET, &next, None).await;
+                assert_eq!(status, StatusCode::OK);
+                let items = listing[collection].as_array().unwrap();
+                assert_eq!(items.len(), 50);
+                seen.extend(items.iter().map(|item| item["id"].as_str().unwrap().to_string()));
+                pages += 1;
+                match listing["pagination"]["next_key"].as_str() {
+                    Some(key) => next = format!("{}&page={}", uri, key),
+                    None => break,
+                }
+            }
+            assert_eq!((pages, seen.len()), (6, 300), "{}", collection);
+        }
+
+        let (status, _) = app.call(Method::GET, "/flows?page=not-a-key", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (_, capped) = app.call(Method::GET, "/sources?limit=100000", None).await;
+        assert_eq!(capped["pagination"]["limit"], app.state.config.pagination.max_limit);
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        app.state.database.create_flow(&flow).await.unwrap();
+        let (_, capped) = app.call(Method::GET, &format!("/flows/{}/segments?limit=100000", flow.id), None).await;
+        assert_eq!(capped["pagination"]["limit"], app.state.config.pagination.max_limit);
+    }
+
+    #[tokio::test]
+    async fn test_in_progress_flow_has_open_ended_available_timerange() {
+        let app = TestApp::new().await;
+        let (status, flow) = app
+            .call(
+                Method::POST,
+                "/flows",
+                Some(json!({"format": "urn:x-nmos:format:video", "tags": {}, "available_timerange": {"start": "100:0"}})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (_, flow) = app.call(Method::GET, &format!("/flows/{}", flow["id"].as_str().unwrap()), None).await;
+        assert_eq!(flow["available_timerange"], json!({"start": "100:0"}));
+
+        let stored = app.state.database.get_flow_required(&flow["id"].as_str().unwrap().parse().unwrap()).await.unwrap();
+        let available = stored.available_timerange.unwrap();
+        assert_eq!((available.start.as_str(), available.end), ("100:0", None));
+    }
+
+    #[tokio::test]
+    async fn test_read_only_flow_refuses_changes_until_an_admin_unlocks_it() {
+        let app = TestApp::new().await;
+        let (_, flow) = app
+            .call(Method::POST, "/flows", Some(json!({"format": "urn:x-nmos:format:video", "tags": {}})))
+            .await;
+        let flow_uri = format!("/flows/{}", flow["id"].as_str().unwrap());
+        let (status, _) = app.call(Method::PUT, &flow_uri, Some(json!({"read_only": true}))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let segment = json!({"object_id": "late", "timerange": {"start": "0:0", "end": "1:0"}});
+        let (status, _) = app.call(Method::POST, &format!("{}/segments", flow_uri), Some(segment.clone())).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = app.call(Method::PUT, &flow_uri, Some(json!({"label": "renamed"}))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        // Clearing read_only alongside another change is refused too
+        let (status, _) = app.call(Method::PUT, &flow_uri, Some(json!({"read_only": false, "label": "renamed"}))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = app.call(Method::PUT, &flow_uri, Some(json!({"read_only": false}))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (_, stored) = app.call(Method::GET, &flow_uri, None).await;
+        assert_eq!((stored["read_only"].clone(), stored["label"].clone()), (json!(true), Value::Null));
+
+        let unlock = |principal: Principal| {
+            Request::builder()
+                .method(Method::PUT)
+                .uri(&flow_uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .extension(principal)
+                .body(Body::from(json!({"read_only": false}).to_string()))
+                .unwrap()
+        };
+        let user = Principal { name: "editor".to_string(), admin: false };
+        assert_eq!(app.send(unlock(user)).await.status(), StatusCode::FORBIDDEN);
+        let admin = Principal { name: "ops".to_string(), admin: true };
+        assert_eq!(app.send(unlock(admin)).await.status(), StatusCode::OK);
+        let (status, _) = app.call(Method::POST, &format!("{}/segments", flow_uri), Some(segment)).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        // Or anyone, when the deployment allows it
+        let app = TestApp::with_config(|config| config.auth.allow_read_only_unlock = true).await;
+        let mut frozen = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        frozen.read_only = Some(true);
+        app.state.database.create_flow(&frozen).await.unwrap();
+        let (status, _) = app.call(Method::PUT, &format!("/flows/{}", frozen.id), Some(json!({"read_only": false}))).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_listings_filter_on_every_given_field() {
+        let app = TestApp::new().await;
+        let db = &app.state.database;
+        let mut camera = Source::new(Uuid::new_v4(), ContentFormat::Video);
+        camera.label = Some("camera".to_string());
+        let mut mic = Source::new(Uuid::new_v4(), ContentFormat::Audio);
+        mic.label = Some("mic".to_string());
+        db.create_source(&camera).await.unwrap();
+        db.create_source(&mic).await.unwrap();
+
+        let flow = |source: Option<&Source>, format: ContentFormat, codec: &str, size: Option<(u32, u32)>| {
+            let mut flow = Flow::new(Uuid::new_v4(), format);
+            flow.source_id = source.map(|source| source.id);
+            flow.codec = Some(codec.to_string());
+            flow.frame_width = size.map(|(width, _)| width);
+            flow.frame_height = size.map(|(_, height)| height);
+            flow
+        };
+        let hd = flow(Some(&camera), ContentFormat::Video, "video/h264", Some((1920, 1080)));
+        let proxy = flow(Some(&camera), ContentFormat::Video, "video/h264", Some((1280, 720)));
+        let audio = flow(Some(&mic), ContentFormat::Audio, "audio/aac", None);
+        let orphan = flow(None, ContentFormat::Video, "video/h264", Some((1920, 1080)));
+        for (flow, start, end) in [(&hd, "20:0", "30:0"), (&proxy, "0:0", "10:0"), (&audio, "0:0", "10:0"), (&orphan, "0:0", "10:0")] {
+            db.create_flow(flow).await.unwrap();
+            let segment = CreateSegmentRequest::new(&format!("obj-{}", flow.id), start, end);
+            db.add_flow_segment(&segment.into_segment(flow.id)).await.unwrap();
+        }
+
+        let ids = |listing: &Value, collection: &str| -> Vec<String> {
+            let mut ids: Vec<String> =
+                listing[collection].as_array().unwrap().iter().map(|item| item["id"].as_str().unwrap().to_string()).collect();
+            ids.sort();
+            ids
+        };
+        let expect = |items: &[Uuid]| -> Vec<String> {
+            let mut ids: Vec<String> = items.iter().map(Uuid::to_string).collect();
+            ids.sort();
+            ids
+        };
+        let cases = [
+            // Single fields
+            ("/sources?label=mic", "sources", expect(&[mic.id])),
+            ("/sources?format=video", "sources", expect(&[camera.id])),
+            (&format!("/flows?source_id={}", camera.id) as &str, "flows", expect(&[hd.id, proxy.id])),
+            ("/flows?codec=audio/aac", "flows", expect(&[audio.id])),
+            ("/flows?frame_width=1920", "flows", expect(&[hd.id, orphan.id])),
+            ("/flows?timerange=[15:0_", "flows", expect(&[hd.id])),
+            // Several fields, all of which must match
+            (&format!("/flows?source_id={}&frame_height=1080", camera.id), "flows", expect(&[hd.id])),
+            ("/flows?codec=video/h264&frame_width=1920&timerange=[0:0_15:0)", "flows", expect(&[orphan.id])),
+            ("/sources?format=audio&label=mic", "sources", expect(&[mic.id])),
+            // Filters that contradict each other match nothing
+            ("/flows?format=audio&codec=video/h264", "flows", expect(&[])),
+            (&format!("/flows?source_id={}&format=video", mic.id), "flows", expect(&[])),
+            ("/sources?format=audio&label=camera", "sources", expect(&[])),
+        ];
+        for (uri, collection, expected) in cases {
+            let (status, listing) = app.call(Method::GET, &format!("{}&include_total=true", uri), None).await;
+            assert_eq!(status, StatusCode::OK, "{}", uri);
+            assert_eq!(ids(&listing, collection), expected, "{}", uri);
+            assert_eq!(listing["pagination"]["count"], expected.len(), "{}", uri);
+        }
+
+        for uri in ["/flows?frame_width=wide", "/flows?source_id=nope", "/flows?timerange=[20:0_10:0)", "/sources?format=stills"] {
+            let (status, body) = app.call(Method::GET, uri, None).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+            assert!(body.is_object(), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flows_timerange_filter_is_held_to_the_maximum_query_duration() {
+        let app = TestApp::with_config(|config| config.validation.max_query_duration_seconds = Some(60)).await;
+
+        let (status, _) = app.call(Method::GET, "/flows?timerange=[0:0_60:0)", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = app.call(Method::GET, "/flows?timerange=[0:0_61:0)", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.is_object());
+
+        // An open end would span the whole flow, however long it has grown
+        let (_, flow) = app.call(Method::POST, "/flows", Some(json!({"format": "urn:x-nmos:format:video", "tags": {}}))).await;
+        let segments = format!("/flows/{}/segments", flow["id"].as_str().unwrap());
+        for window in ["?timerange=[0:0_", "?start=0:0", "?end=60:0"] {
+            let (status, _) = app.call(Method::GET, &format!("{}{}", segments, window), None).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", window);
+        }
+        let (status, _) = app.call(Method::GET, "/flows?timerange=[0:0_", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = app.call(Method::GET, &segments, None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_dependency_health_probes_configured_media_stores() {
+        let app = TestApp::new().await;
+        let (status, report) = app.call(Method::GET, "/service/health/dependencies", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["status"], "ok");
+        assert_eq!(report["dependencies"].as_array().unwrap().len(), 2);
+
+        // Any answer from a store will do; nothing listening is an error
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let live = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, Router::new()).await });
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dead = format!("http://{}", closed.local_addr().unwrap());
+        drop(closed);
+        let stores = |urls: Vec<(&'static str, String)>| {
+            move |config: &mut AppConfig| {
+                let template = config.service.effective_media_stores().remove(0);
+                config.service.media_stores = urls
+                    .into_iter()
+                    .map(|(name, url_base)| MediaStoreConfig { name: name.to_string(), url_base, ..template.clone() })
+                    .collect();
+            }
+        };
+
+        let app = TestApp::with_config(stores(vec![("live", live.clone())])).await;
+        let (status, report) = app.call(Method::GET, "/service/health/dependencies", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["dependencies"][2]["name"], "media_store:live");
+        assert_eq!(report["dependencies"][2]["status"], "ok");
+
+        let app = TestApp::with_config(stores(vec![("live", live), ("dead", dead)])).await;
+        let (status, report) = app.call(Method::GET, "/service/health/dependencies", None).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(report["status"], "degraded");
+        assert_eq!(report["dependencies"][2]["status"], "ok");
+        assert_eq!(report["dependencies"][3]["name"], "media_store:dead");
+        assert_eq!(report["dependencies"][3]["status"], "error");
+        assert!(report["dependencies"][3]["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_api_is_served_under_its_version_prefix() {
+        let app = TestApp::new().await;
+        let (status, flow) = app
+            .call(Method::POST, "/x-tams/v6.0/flows", Some(json!({"format": "urn:x-nmos:format:video", "tags": {}})))
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let segments = format!("/flows/{}/segments", flow["id"].as_str().unwrap());
+
+        let request = |uri: String| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let prefixed = app.send(request(format!("/x-tams/v6.0{}", segments))).await;
+        assert_eq!(prefixed.status(), StatusCode::OK);
+        assert!(!prefixed.headers().contains_key("deprecation"));
+        let legacy = app.send(request(segments.clone())).await;
+        assert_eq!(legacy.status(), StatusCode::OK);
+        assert_eq!(legacy.headers()["deprecation"], "true");
+        let (_, info) = app.call(Method::GET, "/x-tams/v6.0/service", None).await;
+        assert_eq!(info["api_prefix"], "/x-tams/v6.0");
+
+        let app = TestApp::with_config(|config| config.service.serve_unprefixed = false).await;
+        assert_eq!(app.call(Method::GET, "/flows", None).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(app.call(Method::GET, "/x-tams/v6.0/flows", None).await.0, StatusCode::OK);
+
+        let (state, _temp_dir) = create_test_state_with(|config| config.service.api_prefix = "/x-tams/v6.0/".to_string()).await;
+        assert!(build_router(state).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_generated_urls_work_without_unprefixed_routes() {
+        let app = TestApp::with_config(|config| config.service.serve_unprefixed = false).await;
+        let base = app.state.config.service.public_url_base.trim_end_matches('/').to_string();
+        let send = |method: Method, uri: &str, body: Body| {
+            app.send(Request::builder().method(method).uri(uri).body(body).unwrap())
+        };
+        let location = |response: &Response| response.headers()[header::LOCATION].to_str().unwrap().to_string();
+
+        let created = app
+            .send(
+                Request::post("/x-tams/v6.0/flows")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(json!({"format": "urn:x-nmos:format:video", "tags": {}}).to_string()))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(created.status(), StatusCode::CREATED);
+        let flow_url = location(&created);
+        assert_eq!(send(Method::GET, &flow_url, Body::empty()).await.status(), StatusCode::OK);
+
+        let (_, storage) = app.call(Method::POST, &format!("{}/storage", flow_url), Some(json!({"limit": 1}))).await;
+        let object_id = storage["objects"][0]["object_id"].as_str().unwrap().to_string();
+        let put_url = storage["objects"][0]["put_url"].as_str().unwrap();
+        let put_path = put_url.strip_prefix(&base).unwrap();
+        assert_eq!(send(Method::PUT, put_path, Body::from("bytes")).await.status(), StatusCode::CREATED);
+
+        let segment = json!({"object_id": object_id, "timerange": {"start": "0:0", "end": "10:0"}});
+        let (status, _) = app.call(Method::POST, &format!("{}/segments", flow_url), Some(segment)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (_, refreshed) = app
+            .call(Method::POST, &format!("{}/segments/get_urls", flow_url), Some(json!({"object_id": object_id})))
+            .await;
+        let get_url = refreshed["objects"][0]["get_urls"][0]["url"].as_str().unwrap();
+        let get_path = get_url.strip_prefix(&base).unwrap();
+        assert_eq!(send(Method::GET, get_path, Body::empty()).await.status(), StatusCode::OK);
+
+        let (_, info) = app.call(Method::GET, "/x-tams/v6.0/service", None).await;
+        let webhooks_url = info["event_stream_mechanisms"][0]["url"].as_str().unwrap();
+        let webhooks_path = webhooks_url.strip_prefix(&base).unwrap();
+        assert_eq!(send(Method::GET, webhooks_path, Body::empty()).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_admin_endpoints_refuse_other_principals() {
+        let app = TestApp::new().await;
+        let request = |method: Method, uri: &str, principal: Option<Principal>| {
+            let request = Request::builder().method(method).uri(uri);
+            let request = match principal {
+                Some(principal) => request.extension(principal),
+                None => request,
+            };
+            request.body(Body::empty()).unwrap()
+        };
+        let user = || Some(Principal { name: "editor".to_string(), admin: false });
+        let admin = || Some(Principal { name: "ops".to_string(), admin: true });
+
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        app.state.database.create_flow(&flow).await.unwrap();
+        for (method, uri) in [
+            (Method::GET, "/admin/config".to_string()),
+            (Method::POST, "/admin/pause-ingest".to_string()),
+            (Method::GET, "/admin/deletion-worker/config".to_string()),
+            (Method::POST, "/admin/reload-config".to_string()),
+            (Method::GET, "/service/jobs".to_string()),
+            (Method::POST, format!("/flows/{}/pause", flow.id)),
+            (Method::POST, "/service/maintenance/recompute-timeranges".to_string()),
+            (Method::POST, "/service/maintenance/reconcile-flow-stats".to_string()),
+            (Method::POST, "/service/maintenance/normalize-vocabularies".to_string()),
+        ] {
+            let response = app.send(request(method.clone(), &uri, user())).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{} {}", method, uri);
+        }
+        assert!(!app.state.ingest.is_service_paused());
+
+        assert_eq!(app.send(request(Method::GET, "/admin/config", admin())).await.status(), StatusCode::OK);
+        assert_eq!(app.send(request(Method::GET, "/service/jobs", admin())).await.status(), StatusCode::OK);
+        // Without auth there is no principal, and nothing is restricted
+        assert_eq!(app.send(request(Method::GET, "/service/jobs", None)).await.status(), StatusCode::OK);
+        assert_eq!(app.send(request(Method::GET, "/flows", user())).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_head_matches_get() {
+        let app = TestApp::new().await;
+        let (_, flow) = app
+            .call(Method::POST, "/flows", Some(json!({"format": "urn:x-nmos:format:video", "tags": {}})))
+            .await;
+        let flow_id = flow["id"].as_str().unwrap().to_string();
+        let (_, storage) = app.call(Method::POST, &format!("/flows/{}/storage", flow_id), Some(json!({"limit": 1}))).await;
+        let object_id = storage["objects"][0]["object_id"].as_str().unwrap().to_string();
+        let upload = Request::builder()
+            .method(Method::PUT)
+            .uri(format!("/objects/{}", object_id))
+            .body(Body::from("segment bytes"))
+            .unwrap();
+        assert_eq!(app.send(upload).await.status(), StatusCode::CREATED);
+        let segment = json!({"object_id": object_id, "timerange": {"start": "0:0", "end": "10:0"}});
+        app.call(Method::POST, &format!("/flows/{}/segments", flow_id), Some(segment)).await;
+
+        let request = |method: Method, uri: &str| Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+        for uri in [
+            "/flows".to_string(),
+            format!("/flows/{}", flow_id),
+            format!("/flows/{}/segments", flow_id),
+            format!("/objects/{}/download", object_id),
+            format!("/flows/{}", Uuid::new_v4()),
+        ] {
+            let get = app.send(request(Method::GET, &uri)).await;
+            let head = app.send(request(Method::HEAD, &uri)).await;
+            assert_eq!(head.status(), get.status(), "{}", uri);
+            let without_length = |response: &Response| {
+                let mut headers = response.headers().clone();
+                headers.remove(header::CONTENT_LENGTH);
+                headers
+            };
+            assert_eq!(without_length(&head), without_length(&get), "{}", uri);
+
+            let head_length = head.headers().get(header::CONTENT_LENGTH).cloned();
+            let get_body = axum::body::to_bytes(get.into_body(), usize::MAX).await.unwrap();
+            assert!(axum::body::to_bytes(head.into_body(), usize::MAX).await.unwrap().is_empty());
+            if uri.ends_with("/segments") {
+                // The listing skips its get_urls for HEAD, so its length is unknown
+                assert_eq!(head_length, None);
+            } else {
+                assert_eq!(head_length.unwrap(), get_body.len().to_string().as_str(), "{}", uri);
+            }
+        }
+
+        let head = app.send(request(Method::HEAD, &format!("/objects/{}/download", object_id))).await;
+        assert!(head.headers().contains_key(header::ETAG));
+    }
+}
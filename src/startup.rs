@@ -0,0 +1,133 @@
+use std::fmt;
+use std::process::ExitCode;
+
+/// The startup step that failed, used to pick the operator hint and exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupPhase {
+    Config,
+    Logging,
+    Database,
+    Migration,
+    Storage,
+    Webhooks,
+    Bind,
+    Serve,
+}
+
+impl StartupPhase {
+    /// Exit code for scripting; follows sysexits(3) where one fits
+    pub fn exit_code(self) -> u8 {
+        match self {
+            StartupPhase::Config => 78,    // EX_CONFIG
+            StartupPhase::Logging => 70,   // EX_SOFTWARE
+            StartupPhase::Database => 69,  // EX_UNAVAILABLE
+            StartupPhase::Migration => 65, // EX_DATAERR
+            StartupPhase::Storage => 73,   // EX_CANTCREAT
+            StartupPhase::Webhooks => 75,  // EX_TEMPFAIL
+            StartupPhase::Bind => 71,      // EX_OSERR
+            StartupPhase::Serve => 1,
+        }
+    }
+
+    fn subsystem(self) -> &'static str {
+        match self {
+            StartupPhase::Config => "configuration",
+            StartupPhase::Logging => "logging",
+            StartupPhase::Database => "database connection",
+            StartupPhase::Migration => "database migration",
+            StartupPhase::Storage => "media storage",
+            StartupPhase::Webhooks => "webhooks",
+            StartupPhase::Bind => "network listener",
+            StartupPhase::Serve => "server",
+        }
+    }
+
+    fn hint(self) -> &'static str {
+        match self {
+            StartupPhase::Config => "check config.toml for missing or mistyped settings",
+            StartupPhase::Logging => "check the [logging] level and RUST_LOG filter",
+            StartupPhase::Database => "check database.url and that its directory exists and is writable",
+            StartupPhase::Migration => {
+                "check create_db.sql is present, the database file is not corrupt, and no newer server version has migrated it"
+            }
+            StartupPhase::Storage => "check media_storage paths exist or can be created, and object_path_template",
+            StartupPhase::Webhooks => "check the [webhooks] encryption settings match the stored webhook keys",
+            StartupPhase::Bind => "check server.host/server.port are valid and the port is free",
+            StartupPhase::Serve => "see the log output above for the cause",
+        }
+    }
+}
+
+/// A startup failure annotated with the phase it happened in
+#[derive(Debug)]
+pub struct StartupError {
+    pub phase: StartupPhase,
+    pub message: String,
+}
+
+impl StartupError {
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(self.phase.exit_code())
+    }
+}
+
+impl fmt::Display for StartupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TAMS server failed to start: {} error: {}\n  hint: {}",
+            self.phase.subsystem(),
+            self.message,
+            self.phase.hint()
+        )
+    }
+}
+
+impl std::error::Error for StartupError {}
+
+/// Attach a startup phase to any displayable error
+pub trait StartupContext<T> {
+    fn phase(self, phase: StartupPhase) -> Result<T, StartupError>;
+}
+
+impl<T, E: fmt::Display> StartupContext<T> for Result<T, E> {
+    fn phase(self, phase: StartupPhase) -> Result<T, StartupError> {
+        self.map_err(|e| StartupError {
+            phase,
+            message: e.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_phases_have_distinct_exit_codes() {
+        let phases = [
+            StartupPhase::Config,
+            StartupPhase::Logging,
+            StartupPhase::Database,
+            StartupPhase::Migration,
+            StartupPhase::Storage,
+            StartupPhase::Webhooks,
+            StartupPhase::Bind,
+            StartupPhase::Serve,
+        ];
+        let codes: HashSet<u8> = phases.iter().map(|p| p.exit_code()).collect();
+        assert_eq!(codes.len(), phases.len());
+        assert!(!codes.contains(&0));
+    }
+
+    #[test]
+    fn test_startup_error_summary() {
+        let err = Err::<(), _>("unable to open database file")
+            .phase(StartupPhase::Database)
+            .unwrap_err();
+        let summary = err.to_string();
+        assert!(summary.contains("database connection error: unable to open database file"));
+        assert!(summary.contains("hint: check database.url"));
+    }
+}
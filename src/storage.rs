@@ -1,26 +1,462 @@
-use crate::config::MediaStorageConfig;
+use crate::clock::{system_clock, SharedClock};
+use crate::config::{ConcurrentUploadPolicy, DownloadMode, MediaStorageConfig, MediaStoreConfig, ObjectOverwritePolicy, ServiceConfig};
+#[cfg(test)]
+use crate::config::{
+    default_allocation_expiry_seconds, default_max_allocation_objects, default_max_archive_bytes, default_max_compression_ratio,
+    default_api_prefix, default_object_path_template, default_service_type, default_stats_refresh_interval_seconds, default_summary_cache_seconds,
+};
 use crate::error::{TamsError, TamsResult};
-use crate::models::{GetUrl, StorageObject};
+use crate::metrics::metrics;
+use crate::models::{ContentFormat, Flow, GetUrl, MediaObject, StorageObject};
+use axum::body::Bytes;
+use axum::http::HeaderValue;
 use chrono::{DateTime, Duration, Utc};
+use futures_util::{Stream, StreamExt};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 use tokio::fs;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{Mutex, OwnedMutexGuard, RwLock};
 use uuid::Uuid;
 
+/// What storing an object did to the bytes under its id
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StoreOutcome {
+    /// Nothing was stored under the id before
+    Created,
+    /// Identical bytes were already stored
+    Unchanged,
+    /// Different bytes were replaced, as `object_overwrite_policy = "allow"` permits
+    Overwritten,
+}
+
+/// What is known about an object when resolving where it lives on disk
+#[derive(Debug, Clone, Default)]
+pub struct ObjectContext {
+    /// Flow the object was allocated for, if known
+    pub flow_id: Option<Uuid>,
+    /// Storage class the object was placed in; None for `base_path`
+    pub storage_class: Option<String>,
+    /// Format of the object's flow, for `format_base_paths`
+    pub format: Option<ContentFormat>,
+}
+
+impl ObjectContext {
+    /// Where a recorded object lives, falling back to `flow_id` for objects
+    /// not referenced by any flow
+    pub fn for_object(object: Option<&MediaObject>, flow_id: Option<Uuid>) -> Self {
+        ObjectContext {
+            flow_id: object.and_then(|o| o.primary_flow_id()).or(flow_id),
+            storage_class: object.and_then(|o| o.storage_class.clone()),
+            format: object.and_then(|o| o.format.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TemplateToken {
+    Literal(String),
+    Id,
+    Shard2,
+    FlowId,
+}
+
+/// Parsed `object_path_template`, e.g. `{shard2}/{shard2}/{id}` or `{flow_id}/{id}`.
+///
+/// Supported placeholders:
+/// - `{id}`: the object id (required)
+/// - `{shard2}`: the next two characters of the object id; each occurrence takes the following pair
+/// - `{flow_id}`: the flow the object was allocated for, or `unassigned` when unknown
+#[derive(Debug, Clone)]
+pub struct ObjectPathTemplate {
+    components: Vec<Vec<TemplateToken>>,
+    shard_count: usize,
+}
+
+impl ObjectPathTemplate {
+    pub fn parse(template: &str) -> TamsResult<Self> {
+        let invalid = |reason: &str| {
+            TamsError::MediaStorage(format!("Invalid object_path_template '{}': {}", template, reason))
+        };
+
+        if template.starts_with('/') || template.contains('\\') {
+            return Err(invalid("must be a relative path using '/' separators"));
+        }
+
+        let mut components = Vec::new();
+        let mut shard_count = 0;
+        let mut has_id = false;
+
+        for component in template.split('/') {
+            if component.is_empty() || component == "." || component == ".." {
+                return Err(invalid("path components must be non-empty and must not be '.' or '..'"));
+            }
+
+            let mut tokens = Vec::new();
+            let mut rest = component;
+            while !rest.is_empty() {
+                if let Some(after_brace) = rest.strip_prefix('{') {
+                    let end = after_brace.find('}').ok_or_else(|| invalid("unterminated placeholder"))?;
+                    let token = match &after_brace[..end] {
+                        "id" => {
+                            has_id = true;
+                            TemplateToken::Id
+                        }
+                        "shard2" => {
+                            shard_count += 1;
+                            TemplateToken::Shard2
+                        }
+                        "flow_id" => TemplateToken::FlowId,
+                        other => return Err(invalid(&format!("unknown placeholder '{{{}}}'", other))),
+                    };
+                    tokens.push(token);
+                    rest = &after_brace[end + 1..];
+                } else {
+                    let end = rest.find('{').unwrap_or(rest.len());
+                    let literal = &rest[..end];
+                    if literal.contains('}') {
+                        return Err(invalid("unbalanced '}'"));
+                    }
+                    tokens.push(TemplateToken::Literal(literal.to_string()));
+                    rest = &rest[end..];
+                }
+            }
+            components.push(tokens);
+        }
+
+        if !has_id {
+            return Err(invalid("must contain the {id} placeholder"));
+        }
+
+        Ok(ObjectPathTemplate {
+            components,
+            shard_count,
+        })
+    }
+
+    /// Resolve the template to a relative path. Object ids too short to fill every
+    /// shard are placed under a single `misc` directory instead of the shard directories.
+    pub fn resolve(&self, object_id: &str, context: &ObjectContext) -> PathBuf {
+        let id_chars: Vec<char> = object_id.chars().collect();
+        let shardable = id_chars.len() >= self.shard_count * 2;
+
+        let mut path = PathBuf::new();
+        let mut shard_index = 0;
+        let mut misc_added = false;
+
+        for component in &self.components {
+            let has_shard = component.contains(&TemplateToken::Shard2);
+            if has_shard && !shardable {
+                if !misc_added {
+                    path.push("misc");
+                    misc_added = true;
+                }
+                continue;
+            }
+
+            let mut resolved = String::new();
+            for token in component {
+                match token {
+                    TemplateToken::Literal(literal) => resolved.push_str(literal),
+                    TemplateToken::Id => resolved.push_str(object_id),
+                    TemplateToken::Shard2 => {
+                        resolved.extend(&id_chars[shard_index * 2..shard_index * 2 + 2]);
+                        shard_index += 1;
+                    }
+                    TemplateToken::FlowId => match &context.flow_id {
+                        Some(flow_id) => resolved.push_str(&flow_id.to_string()),
+                        None => resolved.push_str("unassigned"),
+                    },
+                }
+            }
+            path.push(resolved);
+        }
+
+        path
+    }
+}
+
+/// Flow tag naming an external store that serves the flow's media
+pub const GET_URL_TEMPLATE_TAG: &str = "get_url_template";
+
+/// Flow tag naming the storage class (see `media_storage.storage_classes`)
+/// the flow's objects are placed in
+pub const STORAGE_CLASS_TAG: &str = "storage_class";
+
+/// Label of the get_urls generated from a flow's `get_url_template`
+pub const EXTERNAL_GET_URL_LABEL: &str = "external";
+
+#[derive(Debug, Clone, PartialEq)]
+enum GetUrlToken {
+    Literal(String),
+    ObjectId { encoded: bool },
+    FlowId { encoded: bool },
+}
+
+/// Parsed `get_url_template` flow tag, for flows whose media lives in an
+/// external store, e.g. `https://store.example.com/{flow_id}/{object_id_urlencoded}`.
+///
+/// Supported placeholders:
+/// - `{object_id}` / `{object_id_urlencoded}`: the object id, as stored or percent-encoded
+/// - `{flow_id}` / `{flow_id_urlencoded}`: the flow the segment belongs to
+#[derive(Debug, Clone)]
+pub struct GetUrlTemplate {
+    tokens: Vec<GetUrlToken>,
+}
+
+impl GetUrlTemplate {
+    pub fn parse(template: &str) -> TamsResult<Self> {
+        let invalid = |reason: &str| TamsError::Validation(format!("Invalid get_url_template '{}': {}", template, reason));
+
+        let mut tokens = Vec::new();
+        let mut rest = template;
+        while !rest.is_empty() {
+            if let Some(after_brace) = rest.strip_prefix('{') {
+                let end = after_brace.find('}').ok_or_else(|| invalid("unterminated placeholder"))?;
+                let token = match &after_brace[..end] {
+                    "object_id" => GetUrlToken::ObjectId { encoded: false },
+                    "object_id_urlencoded" => GetUrlToken::ObjectId { encoded: true },
+                    "flow_id" => GetUrlToken::FlowId { encoded: false },
+                    "flow_id_urlencoded" => GetUrlToken::FlowId { encoded: true },
+                    other => return Err(invalid(&format!("unknown placeholder '{{{}}}'", other))),
+                };
+                tokens.push(token);
+                rest = &after_brace[end + 1..];
+            } else {
+                let end = rest.find('{').unwrap_or(rest.len());
+                let literal = &rest[..end];
+                if literal.contains('}') {
+                    return Err(invalid("unbalanced '}'"));
+                }
+                tokens.push(GetUrlToken::Literal(literal.to_string()));
+                rest = &rest[end..];
+            }
+        }
+
+        if !tokens.iter().any(|token| matches!(token, GetUrlToken::ObjectId { .. })) {
+            return Err(invalid("must contain {object_id} or {object_id_urlencoded}"));
+        }
+        Ok(GetUrlTemplate { tokens })
+    }
+
+    /// The flow's template, if it has the tag. Its get_urls come from the
+    /// template and its bytes are never stored here.
+    pub fn for_flow(flow: &Flow) -> TamsResult<Option<Self>> {
+        flow.tags.get(GET_URL_TEMPLATE_TAG).map(|template| Self::parse(template)).transpose()
+    }
+
+    pub fn expand(&self, object_id: &str, flow_id: &Uuid) -> String {
+        let flow_id = flow_id.to_string();
+        let mut url = String::new();
+        for token in &self.tokens {
+            match token {
+                GetUrlToken::Literal(literal) => url.push_str(literal),
+                GetUrlToken::ObjectId { encoded: false } => url.push_str(object_id),
+                GetUrlToken::ObjectId { encoded: true } => url.push_str(&percent_encode(object_id)),
+                GetUrlToken::FlowId { encoded: false } => url.push_str(&flow_id),
+                GetUrlToken::FlowId { encoded: true } => url.push_str(&percent_encode(&flow_id)),
+            }
+        }
+        url
+    }
+}
+
+/// Percent-encode everything but RFC 3986 unreserved characters, so the
+/// result is safe in a path segment or a query value
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
 #[derive(Clone)]
 pub struct MediaStorage {
     config: MediaStorageConfig,
+    /// Base URL of the API, prefix included, that put_url and get_urls point into
     public_base_url: String,
+    path_template: ObjectPathTemplate,
+    /// Name of the store uploads are written to
+    write_store: Option<String>,
+    /// Readable stores in get_urls preference order
+    read_stores: Vec<MediaStoreConfig>,
+    stats_cache: Arc<StorageStatsCache>,
+    upload_locks: Arc<UploadLocks>,
+    clock: SharedClock,
+}
+
+/// Per-object-id locks held for the duration of an upload
+#[derive(Default)]
+struct UploadLocks {
+    locks: std::sync::Mutex<HashMap<String, Arc<Mutex<()>>>>,
+}
+
+impl UploadLocks {
+    async fn acquire(self: &Arc<Self>, object_id: &str, policy: ConcurrentUploadPolicy) -> TamsResult<UploadGuard> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+            locks.entry(object_id.to_string()).or_default().clone()
+        };
+        let guard = match policy {
+            ConcurrentUploadPolicy::Wait => lock.lock_owned().await,
+            ConcurrentUploadPolicy::Reject => lock.try_lock_owned().map_err(|_| {
+                TamsError::Conflict(format!("An upload of object {} is already in progress", object_id))
+            })?,
+        };
+        Ok(UploadGuard {
+            locks: self.clone(),
+            object_id: object_id.to_string(),
+            guard: Some(guard),
+        })
+    }
+}
+
+struct UploadGuard {
+    locks: Arc<UploadLocks>,
+    object_id: String,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for UploadGuard {
+    fn drop(&mut self) {
+        self.guard.take();
+        // Forget the lock once nobody holds or waits on it
+        let mut locks = self.locks.locks.lock().unwrap_or_else(|e| e.into_inner());
+        if locks.get(&self.object_id).is_some_and(|lock| Arc::strong_count(lock) == 1) {
+            locks.remove(&self.object_id);
+        }
+    }
+}
+
+/// Last computed storage stats, refreshed in the background
+#[derive(Default)]
+struct StorageStatsCache {
+    stats: RwLock<Option<StorageStats>>,
+    refreshing: AtomicBool,
+}
+
+/// Refusal of an upload body that broke off or doesn't match its Content-Length
+fn incomplete_upload(received: u64, declared_len: Option<u64>) -> TamsError {
+    TamsError::BadRequest(match declared_len {
+        Some(declared) => format!(
+            "Incomplete upload: received {} bytes where Content-Length declared {}",
+            received, declared
+        ),
+        None => format!("Incomplete upload: the body broke off after {} bytes", received),
+    })
+}
+
+/// Bytes compared at a time when checking a re-upload against the stored object
+const COMPARE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Whether two files hold the same bytes, comparing sizes first and then
+/// reading both a chunk at a time, so large objects needn't fit in memory
+async fn same_contents(a: &Path, b: &Path) -> std::io::Result<bool> {
+    if fs::metadata(a).await?.len() != fs::metadata(b).await?.len() {
+        return Ok(false);
+    }
+    let mut a = tokio::io::BufReader::with_capacity(COMPARE_CHUNK_SIZE, fs::File::open(a).await?);
+    let mut b = tokio::io::BufReader::with_capacity(COMPARE_CHUNK_SIZE, fs::File::open(b).await?);
+    let (mut chunk_a, mut chunk_b) = (vec![0; COMPARE_CHUNK_SIZE], vec![0; COMPARE_CHUNK_SIZE]);
+    loop {
+        let read = a.read(&mut chunk_a).await?;
+        if read == 0 {
+            return Ok(true);
+        }
+        b.read_exact(&mut chunk_b[..read]).await?;
+        if chunk_a[..read] != chunk_b[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+/// How an upload's body is encoded, from its `Content-Encoding`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UploadEncoding {
+    Identity,
+    Gzip,
+}
+
+impl UploadEncoding {
+    fn from_header(content_encoding: Option<&HeaderValue>) -> TamsResult<Self> {
+        let Some(content_encoding) = content_encoding else {
+            return Ok(UploadEncoding::Identity);
+        };
+        let encodings: Vec<String> = content_encoding
+            .to_str()
+            .map_err(|_| TamsError::BadRequest("Content-Encoding is not valid text".to_string()))?
+            .split(',')
+            .map(|encoding| encoding.trim().to_ascii_lowercase())
+            .filter(|encoding| !encoding.is_empty() && encoding != "identity")
+            .collect();
+        match encodings.as_slice() {
+            [] => Ok(UploadEncoding::Identity),
+            [gzip] if gzip == "gzip" || gzip == "x-gzip" => Ok(UploadEncoding::Gzip),
+            _ => Err(TamsError::UnsupportedMediaType(format!(
+                "Content-Encoding '{}' is not supported; send gzip or identity",
+                encodings.join(", ")
+            ))),
+        }
+    }
 }
 
 impl MediaStorage {
     pub fn new(config: MediaStorageConfig, public_base_url: String) -> TamsResult<Self> {
+        let path_template = ObjectPathTemplate::parse(&config.object_path_template)?;
         Ok(MediaStorage {
             config,
             public_base_url,
+            path_template,
+            write_store: None,
+            read_stores: Vec::new(),
+            stats_cache: Arc::new(StorageStatsCache::default()),
+            upload_locks: Arc::new(UploadLocks::default()),
+            clock: system_clock(),
         })
     }
 
+    /// Read the time for URL expiry and temp file cleanup from `clock`
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Describe the configured media store backends for allocations and get_urls
+    pub fn with_media_stores(mut self, service: &ServiceConfig) -> Self {
+        self.write_store = service.primary_media_store().map(|store| store.name);
+        self.read_stores = service.media_stores_by_read_priority();
+        self
+    }
+
+    /// The preferred read store, if it serves downloads by redirect
+    pub fn download_redirect_store(&self) -> Option<&MediaStoreConfig> {
+        self.read_stores
+            .first()
+            .filter(|store| store.download_mode == DownloadMode::Redirect)
+    }
+
+    /// URL of an object under a store's redirect base, laid out like the object path on disk
+    pub fn redirect_url(&self, store: &MediaStoreConfig, object_id: &str, context: &ObjectContext) -> TamsResult<String> {
+        self.validate_object_id(object_id)?;
+        let base = store.redirect_url_base.as_deref().unwrap_or(&store.url_base);
+        let path = self.path_template.resolve(object_id, context);
+        let path = path
+            .iter()
+            .map(|component| component.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        Ok(format!("{}/{}", base.trim_end_matches('/'), path))
+    }
+
     pub async fn ensure_directories(&self) -> TamsResult<()> {
         fs::create_dir_all(&self.config.base_path).await?;
         fs::create_dir_all(&self.config.temp_path).await?;
@@ -51,9 +487,12 @@ impl MediaStorage {
 
     pub async fn delete_file(&self, object_id: &str) -> TamsResult<()> {
         let file_path = self.get_file_path(object_id).await;
-        if file_path.exists() {
-            fs::remove_file(file_path).await?;
+        if !file_path.exists() {
+            return Err(TamsError::ObjectNotFound {
+                object_id: object_id.to_string(),
+            });
         }
+        fs::remove_file(file_path).await?;
         Ok(())
     }
 
@@ -65,22 +504,35 @@ impl MediaStorage {
         format!("{}/media/{}", self.public_base_url, object_id)
     }
 
-    /// Generate storage objects for new media uploads
-    pub async fn allocate_storage(&self, count: u32, object_ids: Option<Vec<String>>) -> TamsResult<Vec<StorageObject>> {
+    /// Generate storage objects for new media uploads, at most
+    /// `max_allocation_objects` of them
+    pub async fn allocate_storage(&self, count: u32, object_ids: Option<Vec<String>>, context: &ObjectContext) -> TamsResult<Vec<StorageObject>> {
+        let max = self.config.max_allocation_objects;
+        let requested = object_ids.as_ref().map_or(count as usize, Vec::len);
+        if requested > max as usize {
+            return Err(TamsError::BadRequest(format!(
+                "At most {} objects can be allocated per request, {} were asked for",
+                max, requested
+            )));
+        }
         let mut objects = Vec::new();
 
         if let Some(ids) = object_ids {
+            let mut seen = HashSet::new();
+            if let Some(duplicate) = ids.iter().find(|object_id| !seen.insert(object_id.as_str())) {
+                return Err(TamsError::BadRequest(format!("Object id {} is listed more than once", duplicate)));
+            }
             // Use provided object IDs
             for object_id in ids {
                 self.validate_object_id(&object_id)?;
-                let storage_obj = self.create_storage_object(object_id).await?;
+                let storage_obj = self.create_storage_object(object_id, context).await?;
                 objects.push(storage_obj);
             }
         } else {
             // Generate new object IDs
             for _ in 0..count {
                 let object_id = self.generate_object_id();
-                let storage_obj = self.create_storage_object(object_id).await?;
+                let storage_obj = self.create_storage_object(object_id, context).await?;
                 objects.push(storage_obj);
             }
         }
@@ -88,32 +540,32 @@ impl MediaStorage {
         Ok(objects)
     }
 
-    /// Create a storage object with presigned upload URL
-    async fn create_storage_object(&self, object_id: String) -> TamsResult<StorageObject> {
-        let file_path = self.get_object_path(&object_id);
-        
-        // Ensure the parent directory exists
-        if let Some(parent) = file_path.parent() {
-            fs::create_dir_all(parent).await?;
+    /// Create a storage object with presigned upload URL. Its directory is
+    /// left for `store_object` to create, so unused allocations leave nothing on disk.
+    async fn create_storage_object(&self, object_id: String, context: &ObjectContext) -> TamsResult<StorageObject> {
+        // Generate a presigned PUT URL (for our local implementation, this points to our PUT endpoint).
+        // The flow id is carried along so the upload lands where the path template expects it.
+        let mut put_url = format!("{}/objects/{}", self.public_base_url.replace("/media", ""), object_id);
+        if let Some(flow_id) = &context.flow_id {
+            put_url.push_str(&format!("?flow_id={}", flow_id));
         }
-
-        // Generate a presigned PUT URL (for our local implementation, this points to our PUT endpoint)
-        let put_url = format!("{}/objects/{}", self.public_base_url.replace("/media", ""), object_id);
         
-        // URL expires in 1 hour
-        let expires_at = Utc::now() + Duration::hours(1);
+        let expires_at = self.clock.now() + Duration::seconds(self.config.allocation_expiry_seconds as i64);
 
         Ok(StorageObject {
             object_id,
             put_url,
             put_headers: None,
             expires_at: Some(expires_at),
+            media_store: self.write_store.clone(),
+            storage_class: context.storage_class.clone(),
+            already_allocated: false,
         })
     }
 
     /// Generate download URLs for existing objects
-    pub async fn generate_get_urls(&self, object_id: &str, labels: Option<Vec<String>>) -> TamsResult<Vec<GetUrl>> {
-        let file_path = self.get_object_path(object_id);
+    pub async fn generate_get_urls(&self, object_id: &str, labels: Option<Vec<String>>, context: &ObjectContext) -> TamsResult<Vec<GetUrl>> {
+        let file_path = self.get_object_path(object_id, context);
         
         if !file_path.exists() {
             return Err(TamsError::ObjectNotFound {
@@ -122,16 +574,26 @@ impl MediaStorage {
         }
 
         let mut urls = Vec::new();
-        
-        // Generate primary download URL
-        let url = format!("{}/objects/{}/download", self.public_base_url, object_id);
-        let expires_at = Utc::now() + Duration::hours(24); // URLs expire in 24 hours
-        
-        urls.push(GetUrl {
-            url,
-            label: None,
-            expires_at: Some(expires_at),
-        });
+        let expires_at = self.clock.now() + Duration::hours(24); // URLs expire in 24 hours
+
+        if self.read_stores.is_empty() {
+            // Generate primary download URL
+            let url = format!("{}/objects/{}/download", self.public_base_url, object_id);
+            urls.push(GetUrl {
+                url,
+                label: None,
+                expires_at: Some(expires_at),
+            });
+        } else {
+            // One URL per backend, in configured read priority order
+            for store in &self.read_stores {
+                urls.push(GetUrl {
+                    url: format!("{}/objects/{}/download", store.url_base, object_id),
+                    label: Some(store.name.clone()),
+                    expires_at: Some(expires_at),
+                });
+            }
+        }
 
         // If specific labels are requested, generate labeled URLs
         if let Some(labels) = labels {
@@ -148,42 +610,203 @@ impl MediaStorage {
         Ok(urls)
     }
 
-    /// Store media data for an object
-    pub async fn store_object(&self, object_id: &str, data: Vec<u8>) -> TamsResult<()> {
-        if data.len() as u64 > self.config.max_file_size {
+    /// Store media data for an object held in memory; see [`Self::store_object_stream`]
+    pub async fn store_object(
+        &self,
+        object_id: &str,
+        content_encoding: Option<&HeaderValue>,
+        body: Bytes,
+        context: &ObjectContext,
+    ) -> TamsResult<(StoreOutcome, u64, String)> {
+        let declared_len = Some(body.len() as u64);
+        let body = futures_util::stream::once(async move { Ok(body) });
+        self.store_object_stream(object_id, content_encoding, declared_len, body, context).await
+    }
+
+    /// Store media data for an object as it arrives, undoing the upload's
+    /// Content-Encoding first: objects are always stored decompressed. gzip is
+    /// inflated into the temporary file a chunk at a time, and abandoned as soon
+    /// as it outgrows max_file_size or max_compression_ratio times the encoded
+    /// bytes received, so a small bomb never expands in full. A body that breaks
+    /// off, or whose length doesn't match `declared_len` (its Content-Length), is
+    /// refused as an incomplete upload and nothing is stored. Whether an object
+    /// already stored under the id may be written again follows
+    /// `object_overwrite_policy`; a refused write fails with `Conflict`. Returns
+    /// the outcome, the stored size and the hex SHA-256 of the stored bytes.
+    pub async fn store_object_stream<S>(
+        &self,
+        object_id: &str,
+        content_encoding: Option<&HeaderValue>,
+        declared_len: Option<u64>,
+        body: S,
+        context: &ObjectContext,
+    ) -> TamsResult<(StoreOutcome, u64, String)>
+    where
+        S: Stream<Item = std::io::Result<Bytes>> + Send,
+    {
+        let encoding = UploadEncoding::from_header(content_encoding)?;
+        if encoding == UploadEncoding::Identity && declared_len.is_some_and(|len| len > self.config.max_file_size) {
             return Err(TamsError::FileTooLarge {
                 max_size: self.config.max_file_size,
             });
         }
 
         self.validate_object_id(object_id)?;
+        let _upload = self
+            .upload_locks
+            .acquire(object_id, self.config.concurrent_upload_policy)
+            .await?;
         
-        let file_path = self.get_object_path(object_id);
+        let file_path = self.get_object_path(object_id, context);
         
-        // Ensure the parent directory exists
+        // Ensure the parent and temp directories exist
         if let Some(parent) = file_path.parent() {
             fs::create_dir_all(parent).await?;
         }
+        fs::create_dir_all(&self.config.temp_path).await?;
 
-        // Write to a temporary file first, then rename for atomicity
-        let temp_path = self.get_temp_path(&format!("{}.tmp", object_id));
-        let mut temp_file = fs::File::create(&temp_path).await?;
-        temp_file.write_all(&data).await?;
-        temp_file.sync_all().await?;
-        drop(temp_file);
+        // Write to a uniquely named temporary file so concurrent uploads don't share one
+        let temp_path = self.get_temp_path(&format!("{}.{}.tmp", object_id, Uuid::new_v4()));
+        let (size, content_hash) = match self.write_upload(&temp_path, encoding, declared_len, body).await {
+            Ok(written) => written,
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path).await;
+                return Err(e);
+            }
+        };
 
-        // Atomic rename
-        fs::rename(&temp_path, &file_path).await?;
+        // Move the data next to its final path, then hard link it into place, which
+        // fails rather than overwrites if the object exists
+        let file_name = file_path.file_name().and_then(|n| n.to_str()).unwrap_or(object_id);
+        let staging_path = file_path.with_file_name(format!(".{}.{}.staging", file_name, Uuid::new_v4()));
+        fs::rename(&temp_path, &staging_path).await?;
+        let outcome = match fs::hard_link(&staging_path, &file_path).await {
+            Ok(()) => Ok(StoreOutcome::Created),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                self.store_over_existing(object_id, &staging_path, &file_path).await
+            }
+            Err(e) => Err(e.into()),
+        };
+        // An overwrite renames the staging file into place, leaving nothing to remove
+        match fs::remove_file(&staging_path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
 
-        tracing::info!("Stored object {} ({} bytes)", object_id, data.len());
-        Ok(())
+        let outcome = outcome?;
+        match outcome {
+            StoreOutcome::Created => tracing::info!("Stored object {} ({} bytes)", object_id, size),
+            StoreOutcome::Unchanged => tracing::debug!("Object {} re-uploaded with identical content", object_id),
+            StoreOutcome::Overwritten => tracing::info!("Overwrote object {} ({} bytes)", object_id, size),
+        }
+        Ok((outcome, size, content_hash))
+    }
+
+    /// Write an upload's body to `temp_path` as it arrives, decoded, returning its
+    /// decoded size and SHA-256. The encoded bytes are counted against
+    /// `declared_len` on the way.
+    async fn write_upload<S>(
+        &self,
+        temp_path: &Path,
+        encoding: UploadEncoding,
+        declared_len: Option<u64>,
+        body: S,
+    ) -> TamsResult<(u64, String)>
+    where
+        S: Stream<Item = std::io::Result<Bytes>> + Send,
+    {
+        let mut temp_file = fs::File::create(temp_path).await?;
+        let received = Arc::new(AtomicU64::new(0));
+        let incomplete = |received: &AtomicU64| incomplete_upload(received.load(Ordering::Relaxed), declared_len);
+        // Each chunk is counted as it is read; one past the declared length, or a
+        // body that breaks off, ends the upload as a connection error
+        let body = body.map({
+            let received = received.clone();
+            move |chunk| {
+                let chunk = chunk.map_err(|e| std::io::Error::new(std::io::ErrorKind::ConnectionAborted, e))?;
+                let total = received.fetch_add(chunk.len() as u64, Ordering::Relaxed) + chunk.len() as u64;
+                if declared_len.is_some_and(|declared| total > declared) {
+                    return Err(std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "body longer than declared"));
+                }
+                Ok(chunk)
+            }
+        });
+        let mut body = tokio_util::io::StreamReader::new(Box::pin(body));
+        let read_error = |e: std::io::Error, received: &AtomicU64| match e.kind() {
+            std::io::ErrorKind::ConnectionAborted => incomplete(received),
+            _ => TamsError::BadRequest(format!("Invalid gzip upload body: {}", e)),
+        };
+
+        let mut chunk = vec![0u8; 64 * 1024];
+        let mut size = 0u64;
+        let mut hasher = Sha256::new();
+        match encoding {
+            UploadEncoding::Identity => loop {
+                let read = body.read(&mut chunk).await.map_err(|_| incomplete(&received))?;
+                if read == 0 {
+                    break;
+                }
+                size += read as u64;
+                if size > self.config.max_file_size {
+                    return Err(TamsError::FileTooLarge { max_size: self.config.max_file_size });
+                }
+                hasher.update(&chunk[..read]);
+                temp_file.write_all(&chunk[..read]).await?;
+            },
+            UploadEncoding::Gzip => {
+                let mut decoder = async_compression::tokio::bufread::GzipDecoder::new(&mut body);
+                loop {
+                    let read = decoder.read(&mut chunk).await.map_err(|e| read_error(e, &received))?;
+                    if read == 0 {
+                        break;
+                    }
+                    size += read as u64;
+                    let limit = self
+                        .config
+                        .max_file_size
+                        .min(received.load(Ordering::Relaxed).saturating_mul(self.config.max_compression_ratio));
+                    if size > limit {
+                        return Err(TamsError::FileTooLarge { max_size: limit });
+                    }
+                    hasher.update(&chunk[..read]);
+                    temp_file.write_all(&chunk[..read]).await?;
+                }
+                // Anything after the gzip stream still counts towards the declared length
+                tokio::io::copy(&mut body, &mut tokio::io::sink()).await.map_err(|_| incomplete(&received))?;
+            }
+        }
+        if declared_len.is_some_and(|declared| declared != received.load(Ordering::Relaxed)) {
+            return Err(incomplete(&received));
+        }
+        temp_file.sync_all().await?;
+        Ok((size, hex::encode(hasher.finalize())))
+    }
+
+    /// Apply `object_overwrite_policy` to an upload whose object id is already stored
+    async fn store_over_existing(&self, object_id: &str, staging_path: &Path, file_path: &Path) -> TamsResult<StoreOutcome> {
+        if self.config.object_overwrite_policy == ObjectOverwritePolicy::Fail {
+            return Err(TamsError::Conflict(format!("Object {} already exists and objects are write-once", object_id)));
+        }
+        if same_contents(file_path, staging_path).await? {
+            return Ok(StoreOutcome::Unchanged);
+        }
+        match self.config.object_overwrite_policy {
+            ObjectOverwritePolicy::Allow => {
+                fs::rename(staging_path, file_path).await?;
+                Ok(StoreOutcome::Overwritten)
+            }
+            _ => Err(TamsError::Conflict(format!(
+                "Object {} already exists with different content",
+                object_id
+            ))),
+        }
     }
 
     /// Retrieve media data for an object
-    pub async fn get_object(&self, object_id: &str) -> TamsResult<Vec<u8>> {
+    pub async fn get_object(&self, object_id: &str, context: &ObjectContext) -> TamsResult<Vec<u8>> {
         self.validate_object_id(object_id)?;
         
-        let file_path = self.get_object_path(object_id);
+        let file_path = self.get_object_path(object_id, context);
         
         if !file_path.exists() {
             return Err(TamsError::ObjectNotFound {
@@ -198,11 +821,64 @@ impl MediaStorage {
         Ok(data)
     }
 
+    /// Open `len` bytes of an object starting at `offset`, for streaming
+    pub async fn open_object_range(
+        &self,
+        object_id: &str,
+        context: &ObjectContext,
+        offset: u64,
+        len: u64,
+    ) -> TamsResult<tokio::io::Take<fs::File>> {
+        self.validate_object_id(object_id)?;
+
+        let file_path = self.get_object_path(object_id, context);
+        if !file_path.exists() {
+            return Err(TamsError::ObjectNotFound {
+                object_id: object_id.to_string(),
+            });
+        }
+
+        let mut file = fs::File::open(&file_path).await?;
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        Ok(file.take(len))
+    }
+
+    /// Size and ETag of an object, without reading it. The ETag is the
+    /// `content_hash` recorded at upload; objects recorded without one, such
+    /// as those uploaded before hashes were kept, get the file's size and
+    /// modification time instead, which change whenever the bytes are rewritten.
+    pub async fn get_object_etag(
+        &self,
+        object_id: &str,
+        context: &ObjectContext,
+        content_hash: Option<&str>,
+    ) -> TamsResult<(u64, String)> {
+        self.validate_object_id(object_id)?;
+        let metadata = match fs::metadata(self.get_object_path(object_id, context)).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(TamsError::ObjectNotFound {
+                    object_id: object_id.to_string(),
+                })
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(hash) = content_hash {
+            return Ok((metadata.len(), format!("\"{}\"", hash)));
+        }
+        let modified = metadata
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        Ok((metadata.len(), format!("\"{:x}-{:x}\"", metadata.len(), modified)))
+    }
+
     /// Get object metadata (size, MIME type)
-    pub async fn get_object_metadata(&self, object_id: &str) -> TamsResult<(u64, Option<String>)> {
+    pub async fn get_object_metadata(&self, object_id: &str, context: &ObjectContext) -> TamsResult<(u64, Option<String>)> {
         self.validate_object_id(object_id)?;
         
-        let file_path = self.get_object_path(object_id);
+        let file_path = self.get_object_path(object_id, context);
         
         if !file_path.exists() {
             return Err(TamsError::ObjectNotFound {
@@ -222,16 +898,47 @@ impl MediaStorage {
     }
 
     /// Delete an object
-    pub async fn delete_object(&self, object_id: &str) -> TamsResult<()> {
+    pub async fn delete_object(&self, object_id: &str, context: &ObjectContext) -> TamsResult<()> {
         self.validate_object_id(object_id)?;
         
-        let file_path = self.get_object_path(object_id);
+        let file_path = self.get_object_path(object_id, context);
         
-        if file_path.exists() {
-            fs::remove_file(&file_path).await?;
-            tracing::info!("Deleted object {}", object_id);
+        if !file_path.exists() {
+            return Err(TamsError::ObjectNotFound {
+                object_id: object_id.to_string(),
+            });
         }
 
+        fs::remove_file(&file_path).await?;
+        tracing::info!("Deleted object {}", object_id);
+
+        Ok(())
+    }
+
+    /// Copy an object from where `from` places it to where `to` does, through a
+    /// staging file so that the copy only appears once complete. The original
+    /// is left for the caller to delete once the object is recorded at its new
+    /// place. An object already at its new place is left as it is.
+    pub async fn copy_object(&self, object_id: &str, from: &ObjectContext, to: &ObjectContext) -> TamsResult<()> {
+        self.validate_object_id(object_id)?;
+        let (source, target) = (self.get_object_path(object_id, from), self.get_object_path(object_id, to));
+        if source == target || target.exists() {
+            return Ok(());
+        }
+        if !source.exists() {
+            return Err(TamsError::ObjectNotFound {
+                object_id: object_id.to_string(),
+            });
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let file_name = target.file_name().and_then(|n| n.to_str()).unwrap_or(object_id);
+        let staging_path = target.with_file_name(format!(".{}.{}.staging", file_name, Uuid::new_v4()));
+        fs::copy(&source, &staging_path).await?;
+        fs::File::open(&staging_path).await?.sync_all().await?;
+        fs::rename(&staging_path, &target).await?;
+        tracing::info!("Copied object {} to {}", object_id, target.display());
         Ok(())
     }
 
@@ -253,7 +960,7 @@ impl MediaStorage {
 
     /// Clean up temporary files older than the retention period
     pub async fn cleanup_temp_files(&self) -> TamsResult<u64> {
-        let cutoff = Utc::now() - Duration::hours(self.config.temp_path.to_string_lossy().parse::<i64>().unwrap_or(24));
+        let cutoff = self.clock.now() - Duration::hours(self.config.temp_path.to_string_lossy().parse::<i64>().unwrap_or(24));
         let mut cleaned = 0u64;
 
         let mut entries = fs::read_dir(&self.config.temp_path).await?;
@@ -278,10 +985,18 @@ impl MediaStorage {
         Ok(cleaned)
     }
 
+    /// Write and remove a small probe file to confirm the storage paths are writable
+    pub async fn probe_write(&self) -> TamsResult<()> {
+        let probe_path = self.get_temp_path(&format!(".probe-{}", Uuid::new_v4().simple()));
+        fs::write(&probe_path, b"probe").await?;
+        fs::remove_file(&probe_path).await?;
+        Ok(())
+    }
+
     /// Generate a new object ID
     pub fn generate_object_id(&self) -> String {
         // Generate a UUID-based object ID with timestamp prefix for better locality
-        let timestamp = Utc::now().timestamp();
+        let timestamp = self.clock.now().timestamp();
         let uuid = Uuid::new_v4();
         format!("{:x}-{}", timestamp, uuid.simple())
     }
@@ -301,19 +1016,50 @@ impl MediaStorage {
         Ok(())
     }
 
-    /// Get the filesystem path for an object
-    fn get_object_path(&self, object_id: &str) -> PathBuf {
-        // Use a two-level directory structure for better performance
-        // e.g., objects/ab/cd/abcd1234-5678-...
-        let prefix = if object_id.len() >= 4 {
-            format!("{}/{}", &object_id[0..2], &object_id[2..4])
-        } else {
-            "misc".to_string()
-        };
+    /// Get the filesystem path for an object, laid out by the configured path template
+    /// (by default a two-level directory structure, e.g. objects/ab/cd/abcd1234-5678-...)
+    /// under the base path of the object's storage class, or failing that its format
+    fn get_object_path(&self, object_id: &str, context: &ObjectContext) -> PathBuf {
+        let base_path = context
+            .storage_class
+            .as_ref()
+            .and_then(|class| self.config.storage_classes.get(class))
+            .or_else(|| context.format.as_ref().and_then(|format| self.config.format_base_paths.get(format)))
+            .unwrap_or(&self.config.base_path);
+        base_path.join(self.path_template.resolve(object_id, context))
+    }
 
-        self.config.base_path
-            .join(prefix)
-            .join(object_id)
+    /// The configured storage class a flow's `storage_class` tag names. A flow
+    /// without the tag, or naming a class that isn't configured, is placed
+    /// under `base_path`; the latter is reported through `on_unknown`.
+    pub fn storage_class_for(&self, flow: &Flow, on_unknown: impl FnOnce(&str)) -> Option<String> {
+        let class = flow.tags.get(STORAGE_CLASS_TAG)?;
+        if self.config.storage_classes.contains_key(class) {
+            return Some(class.clone());
+        }
+        tracing::warn!("Flow {} names unknown storage class '{}'; using the default", flow.id, class);
+        on_unknown(class);
+        None
+    }
+
+    /// Every directory objects are placed under, base_path first. A root lying
+    /// within another is left out, as walking the outer one covers it
+    fn object_roots(&self) -> Vec<PathBuf> {
+        let configured: Vec<&PathBuf> = std::iter::once(&self.config.base_path)
+            .chain(self.config.storage_classes.values())
+            .chain(self.config.format_base_paths.values())
+            .collect();
+        let mut roots: Vec<PathBuf> = Vec::with_capacity(configured.len());
+        for (i, root) in configured.iter().enumerate() {
+            let covered = configured
+                .iter()
+                .enumerate()
+                .any(|(j, other)| i != j && root.starts_with(other) && (root != other || j < i));
+            if !covered {
+                roots.push(root.to_path_buf());
+            }
+        }
+        roots
     }
 
     /// Get the filesystem path for a temporary file
@@ -322,54 +1068,114 @@ impl MediaStorage {
     }
 
     /// Check if an object exists
-    pub async fn object_exists(&self, object_id: &str) -> bool {
-        let file_path = self.get_object_path(object_id);
+    pub async fn object_exists(&self, object_id: &str, context: &ObjectContext) -> bool {
+        let file_path = self.get_object_path(object_id, context);
         file_path.exists()
     }
 
-    /// Get storage statistics
-    pub async fn get_storage_stats(&self) -> TamsResult<StorageStats> {
-        let mut total_size = 0u64;
-        let mut object_count = 0u64;
+    /// Walk the object tree and recompute storage statistics on the blocking pool,
+    /// updating the cache
+    pub async fn refresh_storage_stats(&self) -> TamsResult<StorageStats> {
+        let roots = self.object_roots();
+        let clock = self.clock.clone();
+        let stats = tokio::task::spawn_blocking(move || compute_storage_stats(&roots, &clock))
+            .await
+            .map_err(|e| TamsError::Internal(format!("Storage stats task failed: {}", e)))?;
+        *self.stats_cache.stats.write().await = Some(stats.clone());
+        metrics().record_task_run("storage_stats", stats.computed_at);
+        Ok(stats)
+    }
 
-        fn visit_dir(dir: &Path, total_size: &mut u64, count: &mut u64) -> std::io::Result<()> {
-            if dir.is_dir() {
-                for entry in std::fs::read_dir(dir)? {
-                    let entry = entry?;
-                    let path = entry.path();
-                    if path.is_dir() {
-                        visit_dir(&path, total_size, count)?;
-                    } else {
-                        *total_size += entry.metadata()?.len();
-                        *count += 1;
-                    }
+    /// The most recently computed stats, if any
+    pub async fn cached_storage_stats(&self) -> Option<StorageStats> {
+        self.stats_cache.stats.read().await.clone()
+    }
+
+    /// Start a background refresh unless one is already running; returns whether
+    /// a refresh was started
+    pub fn spawn_storage_stats_refresh(self: &Arc<Self>) -> bool {
+        if self.stats_cache.refreshing.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        let storage = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = storage.refresh_storage_stats().await {
+                tracing::warn!("Failed to refresh storage stats: {}", e);
+            }
+            storage.stats_cache.refreshing.store(false, Ordering::SeqCst);
+        });
+        true
+    }
+}
+
+/// Synchronously walk each of `roots`, the first of which is base_path; call
+/// via `spawn_blocking`. Available space is that of base_path's filesystem
+fn compute_storage_stats(roots: &[PathBuf], clock: &SharedClock) -> StorageStats {
+    let mut total_size = 0u64;
+    let mut object_count = 0u64;
+
+    fn visit_dir(dir: &Path, total_size: &mut u64, count: &mut u64) -> std::io::Result<()> {
+        if dir.is_dir() {
+            for entry in std::fs::read_dir(dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                if path.is_dir() {
+                    visit_dir(&path, total_size, count)?;
+                } else {
+                    *total_size += entry.metadata()?.len();
+                    *count += 1;
                 }
             }
-            Ok(())
         }
+        Ok(())
+    }
 
-        if let Err(e) = visit_dir(&self.config.base_path, &mut total_size, &mut object_count) {
-            tracing::warn!("Error calculating storage stats: {}", e);
+    for root in roots {
+        if let Err(e) = visit_dir(root, &mut total_size, &mut object_count) {
+            tracing::warn!("Error calculating storage stats under {}: {}", root.display(), e);
         }
+    }
 
-        Ok(StorageStats {
-            total_size_bytes: total_size,
-            object_count,
-            available_space_bytes: None, // TODO: Implement disk space checking
-        })
+    StorageStats {
+        total_size_bytes: total_size,
+        object_count,
+        available_space_bytes: roots.first().and_then(|base_path| available_space(base_path)),
+        computed_at: clock.now(),
     }
 }
 
-#[derive(Debug, Clone)]
+/// Bytes available to unprivileged users on the filesystem holding `path`
+#[cfg(unix)]
+fn available_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is a valid NUL-terminated string and stat is a writable statvfs
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        tracing::warn!("statvfs failed for {}: {}", path.display(), std::io::Error::last_os_error());
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct StorageStats {
     pub total_size_bytes: u64,
     pub object_count: u64,
     pub available_space_bytes: Option<u64>,
+    pub computed_at: DateTime<Utc>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::MediaStoreRole;
     use tempfile::TempDir;
 
     fn create_test_storage() -> (MediaStorage, TempDir) {
@@ -380,6 +1186,16 @@ mod tests {
             base_path: temp_path.join("objects"),
             max_file_size: 1024 * 1024, // 1MB
             temp_path: temp_path.join("temp"),
+            object_path_template: default_object_path_template(),
+            stats_refresh_interval_seconds: default_stats_refresh_interval_seconds(),
+            concurrent_upload_policy: ConcurrentUploadPolicy::default(),
+            object_overwrite_policy: ObjectOverwritePolicy::default(),
+            max_compression_ratio: default_max_compression_ratio(),
+            max_archive_bytes: default_max_archive_bytes(),
+            max_allocation_objects: default_max_allocation_objects(),
+            allocation_expiry_seconds: default_allocation_expiry_seconds(),
+            storage_classes: HashMap::new(),
+            format_base_paths: HashMap::new(),
         };
 
         let storage = MediaStorage::new(config, "http://localhost:8080".to_string()).unwrap();
@@ -394,22 +1210,40 @@ mod tests {
         let data = b"Hello, TAMS!".to_vec();
 
         // Store object
-        storage.store_object(object_id, data.clone()).await.unwrap();
+        storage.store_object(object_id, None, data.clone().into(), &ObjectContext::default()).await.unwrap();
 
         // Retrieve object
-        let retrieved_data = storage.get_object(object_id).await.unwrap();
+        let retrieved_data = storage.get_object(object_id, &ObjectContext::default()).await.unwrap();
         assert_eq!(data, retrieved_data);
 
         // Check metadata
-        let (size, _mime_type) = storage.get_object_metadata(object_id).await.unwrap();
+        let (size, _mime_type) = storage.get_object_metadata(object_id, &ObjectContext::default()).await.unwrap();
         assert_eq!(size, data.len() as u64);
     }
 
+    #[tokio::test]
+    async fn test_reupload_is_compared_past_the_first_chunk() {
+        let (storage, _temp_dir) = create_test_storage();
+        let context = ObjectContext::default();
+        let data = vec![7u8; COMPARE_CHUNK_SIZE * 2 + 10];
+        let (outcome, _, _) = storage.store_object("big", None, data.clone().into(), &context).await.unwrap();
+        assert_eq!(outcome, StoreOutcome::Created);
+        let (outcome, _, _) = storage.store_object("big", None, data.clone().into(), &context).await.unwrap();
+        assert_eq!(outcome, StoreOutcome::Unchanged);
+
+        // Same size, differing only in the last chunk
+        let mut changed = data.clone();
+        *changed.last_mut().unwrap() = 8;
+        let err = storage.store_object("big", None, changed.into(), &context).await.unwrap_err();
+        assert!(matches!(err, TamsError::Conflict(_)));
+        assert_eq!(storage.get_object("big", &context).await.unwrap(), data);
+    }
+
     #[tokio::test]
     async fn test_object_not_found() {
         let (storage, _temp_dir) = create_test_storage();
         
-        let result = storage.get_object("nonexistent").await;
+        let result = storage.get_object("nonexistent", &ObjectContext::default()).await;
         assert!(matches!(result, Err(TamsError::ObjectNotFound { .. })));
     }
 
@@ -417,7 +1251,244 @@ mod tests {
     async fn test_invalid_object_id() {
         let (storage, _temp_dir) = create_test_storage();
         
-        let result = storage.store_object("../../../etc/passwd", b"hack".to_vec()).await;
+        let result = storage.store_object("../../../etc/passwd", None, b"hack".to_vec().into(), &ObjectContext::default()).await;
         assert!(matches!(result, Err(TamsError::BadRequest(_))));
     }
-} 
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_delete_missing_object() {
+        let (storage, _temp_dir) = create_test_storage();
+        let context = ObjectContext::default();
+
+        let result = storage.delete_object("never-stored", &context).await;
+        assert!(matches!(result, Err(TamsError::ObjectNotFound { .. })));
+
+        storage.store_object("stored-object", None, b"data".to_vec().into(), &context).await.unwrap();
+        storage.delete_object("stored-object", &context).await.unwrap();
+        assert!(!storage.object_exists("stored-object", &context).await);
+    }
+
+    #[test]
+    fn test_default_path_template_layout() {
+        let template = ObjectPathTemplate::parse(&default_object_path_template()).unwrap();
+        let context = ObjectContext::default();
+
+        assert_eq!(template.resolve("abcd1234", &context), PathBuf::from("ab/cd/abcd1234"));
+        assert_eq!(template.resolve("abc", &context), PathBuf::from("misc/abc"));
+    }
+
+    #[test]
+    fn test_flow_id_path_template() {
+        let template = ObjectPathTemplate::parse("flows/{flow_id}/{shard2}/{id}.bin").unwrap();
+        let flow_id = Uuid::new_v4();
+        let context = ObjectContext { flow_id: Some(flow_id), ..Default::default() };
+
+        assert_eq!(
+            template.resolve("abcd", &context),
+            PathBuf::from(format!("flows/{}/ab/abcd.bin", flow_id))
+        );
+        assert_eq!(
+            template.resolve("abcd", &ObjectContext::default()),
+            PathBuf::from("flows/unassigned/ab/abcd.bin")
+        );
+    }
+
+    #[test]
+    fn test_invalid_path_templates_rejected() {
+        for template in ["/{id}", "../{id}", "{shard2}/./{id}", "a//{id}", "{shard2}", "{nope}/{id}", "{id", "a}/{id}", "a\\{id}"] {
+            assert!(
+                matches!(ObjectPathTemplate::parse(template), Err(TamsError::MediaStorage(_))),
+                "template {:?} should be rejected",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_url_template_expansion() {
+        let flow_id = Uuid::parse_str("6fd1e2a4-0c5b-4f43-9b53-4d2f6a7c8e90").unwrap();
+        let template = GetUrlTemplate::parse("https://store.example.com/{flow_id}/{object_id}?key={object_id_urlencoded}").unwrap();
+        assert_eq!(
+            template.expand("seg-001", &flow_id),
+            "https://store.example.com/6fd1e2a4-0c5b-4f43-9b53-4d2f6a7c8e90/seg-001?key=seg-001"
+        );
+
+        let encoded = GetUrlTemplate::parse("https://store.example.com/{object_id_urlencoded}").unwrap();
+        assert_eq!(
+            encoded.expand("clips/a b+c?&#%é~_.", &flow_id),
+            "https://store.example.com/clips%2Fa%20b%2Bc%3F%26%23%25%C3%A9~_."
+        );
+
+        for template in ["https://store.example.com/{flow_id}", "https://x/{object}", "https://x/{object_id", "https://x/}{object_id}"] {
+            assert!(
+                matches!(GetUrlTemplate::parse(template), Err(TamsError::Validation(_))),
+                "template {:?} should be rejected",
+                template
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_urls_follow_read_priority() {
+        let (storage, _temp_dir) = create_test_storage();
+        let store = |name: &str, role: MediaStoreRole| MediaStoreConfig {
+            name: name.to_string(),
+            store_type: "http_object_store".to_string(),
+            url_base: format!("http://{}.example.com", name),
+            presigned_urls: false,
+            role,
+            download_mode: DownloadMode::Proxy,
+            redirect_url_base: None,
+            allow_proxy_override: false,
+        };
+        let service = ServiceConfig {
+            name: "test".to_string(),
+            description: "test".to_string(),
+            version: "0.1.0".to_string(),
+            service_type: default_service_type(),
+            api_prefix: default_api_prefix(),
+            serve_unprefixed: true,
+            media_store_type: "http_object_store".to_string(),
+            public_url_base: "http://localhost:8080".to_string(),
+            media_stores: vec![
+                store("local", MediaStoreRole::ReadWrite),
+                store("archive", MediaStoreRole::ReadOnly),
+            ],
+            media_store_read_priority: vec!["archive".to_string()],
+            default_tags: Default::default(),
+            required_tags: Vec::new(),
+            summary_cache_seconds: default_summary_cache_seconds(),
+        };
+        let storage = storage.with_media_stores(&service);
+        let context = ObjectContext::default();
+        storage.store_object("abcd", None, b"data".to_vec().into(), &context).await.unwrap();
+
+        let urls = storage.generate_get_urls("abcd", None, &context).await.unwrap();
+        let labels: Vec<_> = urls.iter().map(|u| u.label.as_deref()).collect();
+        assert_eq!(labels, vec![Some("archive"), Some("local")]);
+        assert!(urls[0].url.starts_with("http://archive.example.com/"));
+
+        let allocated = storage.allocate_storage(1, None, &context).await.unwrap();
+        assert_eq!(allocated[0].media_store.as_deref(), Some("local"));
+    }
+
+    #[tokio::test]
+    async fn test_allocations_are_capped_and_leave_no_directories() {
+        let (storage, _temp_dir) = create_test_storage();
+        let now = Utc::now();
+        let storage = storage.with_clock(Arc::new(crate::clock::FakeClock::new(now)));
+        let context = ObjectContext { flow_id: Some(Uuid::new_v4()), ..Default::default() };
+        let max = default_max_allocation_objects();
+
+        let allocated = storage.allocate_storage(max, None, &context).await.unwrap();
+        assert_eq!(allocated.len(), max as usize);
+        let deadline = now + Duration::seconds(default_allocation_expiry_seconds() as i64);
+        assert!(allocated.iter().all(|object| object.expires_at == Some(deadline)));
+        let on_disk = std::fs::read_dir(&storage.config.base_path).map(|entries| entries.count()).unwrap_or(0);
+        assert_eq!(on_disk, 0);
+
+        let too_many = storage.allocate_storage(max + 1, None, &context).await;
+        assert!(matches!(too_many, Err(TamsError::BadRequest(_))));
+        let ids: Vec<String> = (0..=max).map(|i| format!("obj-{}", i)).collect();
+        let too_many = storage.allocate_storage(1, Some(ids), &context).await;
+        assert!(matches!(too_many, Err(TamsError::BadRequest(_))));
+        let duplicated = vec!["obj-a".to_string(), "obj-b".to_string(), "obj-a".to_string()];
+        match storage.allocate_storage(1, Some(duplicated), &context).await {
+            Err(TamsError::BadRequest(message)) => assert!(message.contains("obj-a")),
+            other => panic!("expected BadRequest, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_storage_stats_cache_updates_after_refresh() {
+        let (storage, _temp_dir) = create_test_storage();
+        let storage = Arc::new(storage);
+        let context = ObjectContext::default();
+        assert!(storage.cached_storage_stats().await.is_none());
+
+        storage.store_object("stats-one", None, b"12345".to_vec().into(), &context).await.unwrap();
+        let first = storage.refresh_storage_stats().await.unwrap();
+        assert_eq!((first.object_count, first.total_size_bytes), (1, 5));
+        #[cfg(unix)]
+        assert!(first.available_space_bytes.is_some());
+
+        // The cache keeps the old value until the next refresh
+        storage.store_object("stats-two", None, b"678".to_vec().into(), &context).await.unwrap();
+        assert_eq!(storage.cached_storage_stats().await.unwrap().object_count, 1);
+
+        assert!(storage.spawn_storage_stats_refresh());
+        for _ in 0..100 {
+            if storage.cached_storage_stats().await.unwrap().object_count == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let refreshed = storage.cached_storage_stats().await.unwrap();
+        assert_eq!((refreshed.object_count, refreshed.total_size_bytes), (2, 8));
+        assert!(refreshed.computed_at >= first.computed_at);
+    }
+
+    #[tokio::test]
+    async fn test_storage_stats_count_every_object_root_once() {
+        let (mut storage, temp_dir) = create_test_storage();
+        let nearline = temp_dir.path().join("nearline");
+        storage.config.storage_classes.insert("nearline".to_string(), nearline.clone());
+        // Sharing a root, or lying within base_path, doesn't count objects twice
+        storage.config.storage_classes.insert("archive".to_string(), nearline);
+        storage.config.storage_classes.insert("inner".to_string(), storage.config.base_path.join("inner"));
+
+        for (object_id, class, data) in [("default", None, "12345"), ("cold", Some("nearline"), "678"), ("colder", Some("archive"), "9")] {
+            let context = ObjectContext { storage_class: class.map(str::to_string), ..Default::default() };
+            storage.store_object(object_id, None, data.into(), &context).await.unwrap();
+        }
+        let inner = ObjectContext { storage_class: Some("inner".to_string()), ..Default::default() };
+        storage.store_object("nested", None, "00".into(), &inner).await.unwrap();
+
+        let stats = storage.refresh_storage_stats().await.unwrap();
+        assert_eq!((stats.object_count, stats.total_size_bytes), (4, 11));
+
+        // As are the objects placed by their flow's format
+        storage.config.format_base_paths.insert(ContentFormat::Audio, temp_dir.path().join("audio"));
+        let audio = ObjectContext { format: Some(ContentFormat::Audio), ..Default::default() };
+        storage.store_object("sound", None, "abc".into(), &audio).await.unwrap();
+        let stats = storage.refresh_storage_stats().await.unwrap();
+        assert_eq!((stats.object_count, stats.total_size_bytes), (5, 14));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_uploads_of_one_object_id() {
+        for policy in [ConcurrentUploadPolicy::Wait, ConcurrentUploadPolicy::Reject] {
+            let (mut storage, _temp_dir) = create_test_storage();
+            storage.config.concurrent_upload_policy = policy;
+            let storage = Arc::new(storage);
+            let context = ObjectContext::default();
+
+            let uploads: Vec<_> = (0..16u8)
+                .map(|i| {
+                    let storage = storage.clone();
+                    let context = context.clone();
+                    tokio::spawn(async move {
+                        let data = vec![i; 64 * 1024];
+                        storage.store_object("contended", None, data.into(), &context).await
+                    })
+                })
+                .collect();
+            let mut stored = 0;
+            for upload in uploads {
+                match upload.await.unwrap() {
+                    Ok((StoreOutcome::Created, _, _)) => stored += 1,
+                    Err(TamsError::Conflict(_)) => {}
+                    Ok((outcome, _, _)) => panic!("unexpected outcome: {:?}", outcome),
+                    Err(e) => panic!("unexpected error: {:?}", e),
+                }
+            }
+
+            // Every payload differs, so exactly one write may land, and intact
+            assert_eq!(stored, 1, "policy {:?}", policy);
+            let data = storage.get_object("contended", &context).await.unwrap();
+            assert_eq!(data.len(), 64 * 1024);
+            assert!(data.iter().all(|b| *b == data[0]));
+            assert!(storage.upload_locks.locks.lock().unwrap().is_empty());
+        }
+    }
+}
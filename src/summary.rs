@@ -0,0 +1,122 @@
+//! `GET /service/summary`: the headline numbers of an instance in one document,
+//! for dashboards that would rather not scrape metrics.
+//!
+//! Everything in it is cheap to gather: row counts in a single query, the
+//! cached storage stats, and the process-wide counters in `metrics`. The
+//! document is still kept for `service.summary_cache_seconds`, so any number of
+//! dashboards polling every few seconds cost one computation per interval.
+
+use crate::{
+    error::{TamsError, TamsResult},
+    handlers::AppState,
+    metrics::{metrics, INGEST_WINDOW_SECONDS, WEBHOOK_FAILURE_WINDOW_SECONDS},
+    models::{ServiceSummary, SummaryEventStream, SummaryIngest, SummaryStorage, SummaryWebhooks},
+};
+use axum::{extract::State, Json};
+use chrono::Duration;
+use std::sync::atomic::Ordering;
+use tokio::sync::Mutex;
+
+/// Version of the summary document; raised only when a field changes meaning or goes away
+pub const SUMMARY_VERSION: u32 = 1;
+
+/// The most recent summary. Held across computation, so concurrent requests
+/// for an expired summary wait for one computation instead of each running one.
+#[derive(Default)]
+pub struct SummaryCache(Mutex<Option<ServiceSummary>>);
+
+pub async fn get_service_summary(State(state): State<AppState>) -> Result<Json<ServiceSummary>, TamsError> {
+    Ok(Json(service_summary(&state).await?))
+}
+
+/// The cached summary, recomputed once it is older than the configured TTL
+pub async fn service_summary(state: &AppState) -> TamsResult<ServiceSummary> {
+    let mut cached = state.summary.0.lock().await;
+    let now = state.clock.now();
+    let ttl = Duration::seconds(state.config.service.summary_cache_seconds as i64);
+    if let Some(summary) = cached.as_ref().filter(|summary| now - summary.generated_at < ttl) {
+        return Ok(summary.clone());
+    }
+    let summary = compute_summary(state).await?;
+    *cached = Some(summary.clone());
+    Ok(summary)
+}
+
+async fn compute_summary(state: &AppState) -> TamsResult<ServiceSummary> {
+    let now = state.clock.now();
+    let (counts, active_deletion_requests) = state.database.get_summary_counts().await?;
+    let stats = state.storage.cached_storage_stats().await;
+    let ingested = metrics().segments_ingested_recently(now);
+    Ok(ServiceSummary {
+        version: SUMMARY_VERSION,
+        generated_at: now,
+        counts,
+        storage: SummaryStorage {
+            stored_bytes: stats.as_ref().map(|stats| stats.total_size_bytes),
+            available_bytes: stats.as_ref().and_then(|stats| stats.available_space_bytes),
+            computed_at: stats.as_ref().map(|stats| stats.computed_at),
+        },
+        ingest: SummaryIngest {
+            window_seconds: INGEST_WINDOW_SECONDS,
+            segments: ingested,
+            segments_per_second: ingested as f64 / INGEST_WINDOW_SECONDS as f64,
+        },
+        webhooks: SummaryWebhooks {
+            queue_depth: metrics().webhook_deliveries_pending.load(Ordering::Relaxed),
+            failures_window_seconds: WEBHOOK_FAILURE_WINDOW_SECONDS,
+            failures: metrics().webhook_failures_recently(now),
+        },
+        event_stream: SummaryEventStream {
+            subscribers: metrics().event_stream_subscribers.load(Ordering::Relaxed),
+            dropped: metrics().event_stream_drops.load(Ordering::Relaxed),
+        },
+        active_deletion_requests,
+        tasks_last_run: metrics().task_runs().into_iter().map(|(task, at)| (task.to_string(), at)).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::FakeClock,
+        handlers::tests::create_test_state_with_clock,
+        models::{ContentFormat, Source},
+    };
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_summary_is_cached_for_its_ttl() {
+        let clock = Arc::new(FakeClock::new(chrono::Utc::now()));
+        let (state, _temp_dir) = create_test_state_with_clock(|config| config.service.summary_cache_seconds = 10, clock.clone()).await;
+
+        let Json(first) = get_service_summary(State(state.clone())).await.unwrap();
+        let document = serde_json::to_value(&first).unwrap();
+        assert_eq!(document["version"], SUMMARY_VERSION);
+        for field in ["counts", "storage", "ingest", "webhooks", "event_stream", "active_deletion_requests", "tasks_last_run"] {
+            assert!(document.get(field).is_some(), "summary lacks {}", field);
+        }
+        for field in ["sources", "flows", "segments", "objects"] {
+            assert_eq!(document["counts"][field], 0);
+        }
+        for field in ["stored_bytes", "available_bytes"] {
+            assert!(document["storage"].get(field).is_some(), "summary lacks storage.{}", field);
+        }
+        assert!(document["ingest"]["segments_per_second"].is_number());
+        assert!(document["webhooks"]["queue_depth"].is_number());
+
+        // Within the TTL the counts aren't queried again
+        let source = Source::new(Uuid::new_v4(), ContentFormat::Video);
+        state.database.create_source(&source).await.unwrap();
+        clock.advance(Duration::seconds(9));
+        let Json(cached) = get_service_summary(State(state.clone())).await.unwrap();
+        assert_eq!(cached.generated_at, first.generated_at);
+        assert_eq!(cached.counts.sources, 0);
+
+        clock.advance(Duration::seconds(1));
+        let Json(fresh) = get_service_summary(State(state.clone())).await.unwrap();
+        assert_eq!(fresh.counts.sources, 1);
+        assert!(fresh.generated_at > first.generated_at);
+    }
+}
@@ -1,6 +1,8 @@
 use crate::{error::TamsError, models::TimeRange};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Serialize, Serializer};
 use std::cmp::Ordering;
+use std::future::Future;
 
 /// Parse a TAMS timestamp string in the format "seconds:nanoseconds"
 /// where seconds is Unix timestamp and nanoseconds is the fractional part
@@ -49,56 +51,66 @@ pub fn compare_tams_timestamps(a: &str, b: &str) -> Result<Ordering, TamsError>
     Ok(dt_a.cmp(&dt_b))
 }
 
-/// Validate a TimeRange
+/// Validate a TimeRange; one without an end runs on indefinitely
 pub fn validate_timerange(timerange: &TimeRange) -> Result<(), TamsError> {
     // Parse start timestamp
     let start_dt = parse_tams_timestamp(&timerange.start)?;
-    
-    // Parse end timestamp (now always required)
-    let end_dt = parse_tams_timestamp(&timerange.end)?;
-    
+
+    let Some(end) = &timerange.end else {
+        return Ok(());
+    };
+    let end_dt = parse_tams_timestamp(end)?;
+
     // End must be after start
     if end_dt <= start_dt {
         return Err(TamsError::InvalidTimerange(format!(
             "End timestamp ({}) must be after start timestamp ({})",
-            timerange.end, timerange.start
+            end, timerange.start
         )));
     }
-    
+
     Ok(())
 }
 
+/// End of a TimeRange, or None for one without an end
+fn parse_end(timerange: &TimeRange) -> Result<Option<DateTime<Utc>>, TamsError> {
+    timerange.end.as_deref().map(parse_tams_timestamp).transpose()
+}
+
+/// Whether `instant` comes before the (exclusive) end, which is never the case
+/// for a range without an end
+fn before_end(instant: DateTime<Utc>, end: Option<DateTime<Utc>>) -> bool {
+    end.is_none_or(|end| instant < end)
+}
+
 /// Check if two TimeRanges overlap
 pub fn timeranges_overlap(a: &TimeRange, b: &TimeRange) -> Result<bool, TamsError> {
     validate_timerange(a)?;
     validate_timerange(b)?;
-    
+
     let a_start = parse_tams_timestamp(&a.start)?;
     let b_start = parse_tams_timestamp(&b.start)?;
-    let a_end = parse_tams_timestamp(&a.end)?;
-    let b_end = parse_tams_timestamp(&b.end)?;
-    
-    // Check for overlap - both ranges are now always bounded
-    Ok(a_start < b_end && b_start < a_end)
+
+    // A missing end lies after every start
+    Ok(before_end(a_start, parse_end(b)?) && before_end(b_start, parse_end(a)?))
 }
 
 /// Check if a timestamp falls within a TimeRange
 pub fn timestamp_in_range(timestamp: &str, range: &TimeRange) -> Result<bool, TamsError> {
     validate_timerange(range)?;
-    
+
     let ts = parse_tams_timestamp(timestamp)?;
     let range_start = parse_tams_timestamp(&range.start)?;
-    let range_end = parse_tams_timestamp(&range.end)?;
-    
+
     // Must be at or after start and before end (exclusive end)
-    Ok(ts >= range_start && ts < range_end)
+    Ok(ts >= range_start && before_end(ts, parse_end(range)?))
 }
 
 /// Create a TimeRange from start and end timestamps
 pub fn create_timerange(start: &str, end: &str) -> Result<TimeRange, TamsError> {
     let timerange = TimeRange {
         start: start.to_string(),
-        end: end.to_string(),
+        end: Some(end.to_string()),
     };
     
     validate_timerange(&timerange)?;
@@ -120,10 +132,21 @@ pub fn iso8601_to_tams(iso_timestamp: &str) -> Result<String, TamsError> {
     Ok(format_tams_timestamp(&dt.with_timezone(&Utc)))
 }
 
-/// Convert TAMS timestamp to ISO 8601 format
+/// Convert TAMS timestamp to ISO 8601 format.
+///
+/// Fractional seconds are written with as many digits as needed (none, 3, 6 or 9)
+/// so every nanosecond survives a round trip through `iso8601_to_tams`.
 pub fn tams_to_iso8601(tams_timestamp: &str) -> Result<String, TamsError> {
     let dt = parse_tams_timestamp(tams_timestamp)?;
-    Ok(dt.to_rfc3339())
+    Ok(dt.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+}
+
+/// Format a timestamp for storage: RFC 3339 in UTC with all nine fractional digits.
+///
+/// The fixed width keeps stored values lexicographically ordered, which the
+/// `<=` comparisons in SQL rely on, and never drops sub-microsecond precision.
+pub fn format_rfc3339(datetime: &DateTime<Utc>) -> String {
+    datetime.to_rfc3339_opts(SecondsFormat::Nanos, true)
 }
 
 /// Calculate duration between two TAMS timestamps in nanoseconds
@@ -141,10 +164,340 @@ pub fn calculate_duration_nanos(start: &str, end: &str) -> Result<i64, TamsError
     Ok(duration.num_nanoseconds().unwrap_or(i64::MAX))
 }
 
+/// Parse a stored segment timerange of the form "start_s:start_ns:end_s:end_ns",
+/// or "start_s:start_ns:" for a segment written without an end
+pub fn parse_segment_timerange(stored: &str) -> Result<TimeRange, TamsError> {
+    let parts: Vec<&str> = stored.split(':').collect();
+    if let [start_s, start_ns, ""] = parts[..] {
+        let start = format!("{}:{}", start_s, start_ns);
+        parse_tams_timestamp(&start)?;
+        return Ok(TimeRange { start, end: None });
+    }
+    if parts.len() != 4 {
+        return Err(TamsError::InvalidTimerange(format!(
+            "Invalid segment timerange: expected 'start_s:start_ns:end_s:end_ns', got '{}'",
+            stored
+        )));
+    }
+
+    let start = format!("{}:{}", parts[0], parts[1]);
+    let end = format!("{}:{}", parts[2], parts[3]);
+    parse_tams_timestamp(&start)?;
+    parse_tams_timestamp(&end)?;
+    Ok(TimeRange { start, end: Some(end) })
+}
+
+/// Order of two range ends, where a missing end comes after every other
+fn compare_ends(a: Option<&str>, b: Option<&str>) -> Result<Ordering, TamsError> {
+    match (a, b) {
+        (Some(a), Some(b)) => compare_tams_timestamps(a, b),
+        (a, b) => Ok(a.is_some().cmp(&b.is_some()).reverse()),
+    }
+}
+
+/// Smallest TimeRange covering all of the given ranges, or None if there are none
+pub fn covering_timerange<'a>(ranges: impl IntoIterator<Item = &'a TimeRange>) -> Result<Option<TimeRange>, TamsError> {
+    let mut covering: Option<TimeRange> = None;
+    for range in ranges {
+        covering = Some(match covering {
+            None => range.clone(),
+            Some(mut current) => {
+                if compare_tams_timestamps(&range.start, &current.start)? == Ordering::Less {
+                    current.start = range.start.clone();
+                }
+                if compare_ends(range.end.as_deref(), current.end.as_deref())? == Ordering::Greater {
+                    current.end = range.end.clone();
+                }
+                current
+            }
+        });
+    }
+    Ok(covering)
+}
+
+/// Parse a timerange in TAMS notation, e.g. `[0:0_10:500000000)`, into the
+/// half-open range it denotes. `[` and `]` include their bound, `(` and `)`
+/// exclude it, and a missing bracket counts as `[` or `)`. Timestamps are whole
+/// nanoseconds, so an excluded start or an included end moves that bound on by
+/// one nanosecond. Either end may be left open (`_10:0`, `0:0_`, or `_` for all
+/// of time); an open start is left empty and an open end is None.
+pub fn parse_tams_timerange(value: &str) -> Result<TimeRange, TamsError> {
+    let text = value.trim();
+    let (start_excluded, text) = match text.strip_prefix('(') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('[').unwrap_or(text)),
+    };
+    let (end_included, text) = match text.strip_suffix(']') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_suffix(')').unwrap_or(text)),
+    };
+    let (start, end) = text.split_once('_').ok_or_else(|| {
+        TamsError::InvalidTimerange(format!("Invalid timerange '{}': expected 'start_end'", value))
+    })?;
+
+    // Each bound as given, or moved on a nanosecond, with its value in nanoseconds
+    let bound = |timestamp: &str, shift: bool| -> Result<Option<(String, i64)>, TamsError> {
+        let timestamp = timestamp.trim();
+        if timestamp.is_empty() {
+            return Ok(None);
+        }
+        let nanos = timestamp_to_nanos(timestamp)?;
+        if !shift {
+            return Ok(Some((timestamp.to_string(), nanos)));
+        }
+        let shifted = nanos
+            .checked_add(1)
+            .ok_or_else(|| TamsError::InvalidTimerange(format!("Timestamp '{}' is out of range", timestamp)))?;
+        Ok(Some((nanos_to_timestamp(shifted), shifted)))
+    };
+    let start = bound(start, start_excluded)?;
+    let end = bound(end, end_included)?;
+    if let (Some((_, start)), Some((_, end))) = (&start, &end) {
+        if start > end {
+            return Err(TamsError::InvalidTimerange(format!("Invalid timerange '{}': start is after end", value)));
+        }
+    }
+    Ok(TimeRange {
+        start: start.map(|(timestamp, _)| timestamp).unwrap_or_default(),
+        end: end.map(|(timestamp, _)| timestamp),
+    })
+}
+
+/// A half-open TimeRange in TAMS notation, e.g. `[0:0_10:0)`; an empty start
+/// or missing end is left open, so an unbounded range is `_`
+pub fn format_tams_timerange(range: &TimeRange) -> String {
+    let start = if range.start.is_empty() { String::new() } else { format!("[{}", range.start) };
+    let end = range.end.as_ref().map(|end| format!("{})", end)).unwrap_or_default();
+    format!("{}_{}", start, end)
+}
+
+/// Parse a bounded `timerange` query value in TAMS notation, e.g. `[10:0_20:0)`,
+/// as [`parse_tams_timerange`] does
+pub fn parse_timerange_param(value: &str) -> Result<TimeRange, TamsError> {
+    let range = parse_tams_timerange(value)?;
+    if range.start.is_empty() || range.end.is_none() {
+        return Err(TamsError::InvalidTimerange(format!("Invalid timerange '{}': both ends must be given", value)));
+    }
+    validate_timerange(&range)?;
+    Ok(range)
+}
+
+/// Bounds of a `timerange` query value in nanoseconds since the epoch, either
+/// of which may be left open, e.g. `[10:0_` for everything from 10s on
+pub fn parse_timerange_bounds(value: &str) -> Result<(Option<i64>, Option<i64>), TamsError> {
+    let range = parse_tams_timerange(value)?;
+    let bound = |timestamp: &str| (!timestamp.is_empty()).then(|| timestamp_to_nanos(timestamp)).transpose();
+    let end = range.end.as_deref().map(timestamp_to_nanos).transpose()?;
+    Ok((bound(&range.start)?, end))
+}
+
+/// Half-open interval in nanoseconds since the epoch
+type NanoInterval = (i128, i128);
+
+/// End of an interval whose range has no end
+const OPEN_END: i128 = i128::MAX;
+
+fn timestamp_nanos(timestamp: &str) -> Result<i128, TamsError> {
+    let datetime = parse_tams_timestamp(timestamp)?;
+    Ok(datetime.timestamp() as i128 * 1_000_000_000 + datetime.timestamp_subsec_nanos() as i128)
+}
+
+fn nanos_timestamp(nanos: i128) -> String {
+    format!("{}:{:09}", nanos.div_euclid(1_000_000_000), nanos.rem_euclid(1_000_000_000))
+}
+
+/// Sorted, non-overlapping intervals covering the same instants; touching intervals merge
+fn normalize_intervals(ranges: &[TimeRange]) -> Result<Vec<NanoInterval>, TamsError> {
+    let mut intervals = Vec::new();
+    for range in ranges {
+        let end = range.end.as_deref().map(timestamp_nanos).transpose()?.unwrap_or(OPEN_END);
+        let interval = (timestamp_nanos(&range.start)?, end);
+        if interval.0 < interval.1 {
+            intervals.push(interval);
+        }
+    }
+    intervals.sort();
+
+    let mut merged: Vec<NanoInterval> = Vec::with_capacity(intervals.len());
+    for (start, end) in intervals {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    Ok(merged)
+}
+
+fn to_timeranges(intervals: &[NanoInterval]) -> Vec<TimeRange> {
+    intervals
+        .iter()
+        .map(|(start, end)| TimeRange {
+            start: nanos_timestamp(*start),
+            end: (*end != OPEN_END).then(|| nanos_timestamp(*end)),
+        })
+        .collect()
+}
+
+fn intersect_intervals(a: &[NanoInterval], b: &[NanoInterval]) -> Vec<NanoInterval> {
+    let mut result = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        let start = a[i].0.max(b[j].0);
+        let end = a[i].1.min(b[j].1);
+        if start < end {
+            result.push((start, end));
+        }
+        if a[i].1 < b[j].1 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    result
+}
+
+fn subtract_intervals(a: &[NanoInterval], b: &[NanoInterval]) -> Vec<NanoInterval> {
+    let mut result = Vec::new();
+    let mut j = 0;
+    for &(start, end) in a {
+        let mut cursor = start;
+        while j < b.len() && b[j].1 <= cursor {
+            j += 1;
+        }
+        let mut k = j;
+        while k < b.len() && b[k].0 < end {
+            if b[k].0 > cursor {
+                result.push((cursor, b[k].0));
+            }
+            cursor = cursor.max(b[k].1);
+            k += 1;
+        }
+        if cursor < end {
+            result.push((cursor, end));
+        }
+    }
+    result
+}
+
+/// Instants covered by both sets of ranges
+pub fn intersect_timeranges(a: &[TimeRange], b: &[TimeRange]) -> Result<Vec<TimeRange>, TamsError> {
+    Ok(to_timeranges(&intersect_intervals(&normalize_intervals(a)?, &normalize_intervals(b)?)))
+}
+
+/// Instants covered by `a` but not by `b`
+pub fn subtract_timeranges(a: &[TimeRange], b: &[TimeRange]) -> Result<Vec<TimeRange>, TamsError> {
+    Ok(to_timeranges(&subtract_intervals(&normalize_intervals(a)?, &normalize_intervals(b)?)))
+}
+
+/// Holes in `window` not covered by any of `ranges`, in order. Overlapping and
+/// touching ranges merge first, so only instants no range covers are reported.
+pub fn find_gaps(window: &TimeRange, ranges: &[TimeRange]) -> Result<Vec<TimeRange>, TamsError> {
+    subtract_timeranges(std::slice::from_ref(window), ranges)
+}
+
+/// Total duration of the instants covered by the ranges, as a TAMS `seconds:nanoseconds` value
+pub fn total_duration(ranges: &[TimeRange]) -> Result<String, TamsError> {
+    let intervals = normalize_intervals(ranges)?;
+    if intervals.last().is_some_and(|(_, end)| *end == OPEN_END) {
+        return Err(TamsError::InvalidTimerange("A timerange without an end has no total duration".to_string()));
+    }
+    let nanos: i128 = intervals.iter().map(|(start, end)| end - start).sum();
+    Ok(nanos_timestamp(nanos))
+}
+
+/// A TAMS timestamp as nanoseconds since the epoch
+pub fn timestamp_to_nanos(timestamp: &str) -> Result<i64, TamsError> {
+    i64::try_from(timestamp_nanos(timestamp)?)
+        .map_err(|_| TamsError::InvalidTimerange(format!("Timestamp '{}' is out of range", timestamp)))
+}
+
+/// Nanoseconds since the epoch as a TAMS `seconds:nanoseconds` timestamp
+pub fn nanos_to_timestamp(nanos: i64) -> String {
+    nanos_timestamp(nanos as i128)
+}
+
+/// end_ns stored for a segment without an end, after every other instant
+pub const OPEN_END_NANOS: i64 = i64::MAX;
+
+/// Start and end of a stored segment timerange in nanoseconds since the epoch
+pub fn segment_bounds_nanos(stored: &str) -> Result<(i64, i64), TamsError> {
+    let range = parse_segment_timerange(stored)?;
+    let end_ns = range.end.as_deref().map(timestamp_to_nanos).transpose()?.unwrap_or(OPEN_END_NANOS);
+    Ok((timestamp_to_nanos(&range.start)?, end_ns))
+}
+
+/// Parse a TAMS duration such as `60:0` into nanoseconds, rejecting zero and negative durations
+pub fn parse_duration_nanos(duration: &str) -> Result<i64, TamsError> {
+    let nanos = timestamp_to_nanos(duration.trim())
+        .map_err(|_| TamsError::Validation(format!("Invalid duration '{}': expected 'seconds:nanoseconds'", duration)))?;
+    if nanos <= 0 {
+        return Err(TamsError::Validation(format!("Duration '{}' must be positive", duration)));
+    }
+    Ok(nanos)
+}
+
+/// How `created_at`/`updated_at` fields are written in responses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeFormat {
+    #[default]
+    Rfc3339,
+    Tams,
+}
+
+impl TimeFormat {
+    /// Parse the `time_format` query parameter
+    pub fn from_query(value: &str) -> Result<Self, TamsError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "rfc3339" => Ok(TimeFormat::Rfc3339),
+            "tams" => Ok(TimeFormat::Tams),
+            other => Err(TamsError::BadRequest(format!(
+                "Unknown time_format '{}': expected 'rfc3339' or 'tams'",
+                other
+            ))),
+        }
+    }
+}
+
+tokio::task_local! {
+    static RESPONSE_TIME_FORMAT: TimeFormat;
+}
+
+/// Run a request with the given response time format in effect
+pub async fn with_time_format<F: Future>(format: TimeFormat, future: F) -> F::Output {
+    RESPONSE_TIME_FORMAT.scope(format, future).await
+}
+
+/// Run a synchronous serialization with the given time format in effect,
+/// e.g. to keep webhook payloads RFC 3339 whatever the triggering request asked for
+pub fn with_time_format_sync<R>(format: TimeFormat, f: impl FnOnce() -> R) -> R {
+    RESPONSE_TIME_FORMAT.sync_scope(format, f)
+}
+
+/// `serialize_with` for resource timestamps: RFC 3339 unless the current
+/// request selected `time_format=tams`
+pub fn serialize_timestamp<S: Serializer>(datetime: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+    match RESPONSE_TIME_FORMAT.try_with(|format| *format).unwrap_or_default() {
+        TimeFormat::Tams => serializer.serialize_str(&format_tams_timestamp(datetime)),
+        TimeFormat::Rfc3339 => datetime.serialize(serializer),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_duration_nanos() {
+        assert_eq!(parse_duration_nanos("60:0").unwrap(), 60_000_000_000);
+        assert_eq!(parse_duration_nanos("0:500000000").unwrap(), 500_000_000);
+        assert!(matches!(parse_duration_nanos("0:0"), Err(TamsError::Validation(_))));
+        assert!(matches!(parse_duration_nanos("-1:0"), Err(TamsError::Validation(_))));
+        assert!(matches!(parse_duration_nanos("60"), Err(TamsError::Validation(_))));
+        assert_eq!(segment_bounds_nanos("10:5:12:0").unwrap(), (10_000_000_005, 12_000_000_000));
+        assert_eq!(segment_bounds_nanos("10:5:").unwrap(), (10_000_000_005, OPEN_END_NANOS));
+        assert_eq!(nanos_to_timestamp(10_000_000_005), "10:000000005");
+    }
+
     #[test]
     fn test_parse_tams_timestamp() {
         // Valid timestamp
@@ -172,14 +525,14 @@ mod tests {
         // Valid range
         let valid_range = TimeRange {
             start: "1609459200:000000000".to_string(),
-            end: "1609459260:000000000".to_string(),
+            end: Some("1609459260:000000000".to_string()),
         };
         assert!(validate_timerange(&valid_range).is_ok());
         
         // Invalid range (end before start)
         let invalid_range = TimeRange {
             start: "1609459260:000000000".to_string(),
-            end: "1609459200:000000000".to_string(),
+            end: Some("1609459200:000000000".to_string()),
         };
         assert!(validate_timerange(&invalid_range).is_err());
     }
@@ -188,12 +541,12 @@ mod tests {
     fn test_timerange_overlap() {
         let range1 = TimeRange {
             start: "1609459200:000000000".to_string(),
-            end: "1609459260:000000000".to_string(),
+            end: Some("1609459260:000000000".to_string()),
         };
         
         let range2 = TimeRange {
             start: "1609459230:000000000".to_string(),
-            end: "1609459290:000000000".to_string(),
+            end: Some("1609459290:000000000".to_string()),
         };
         
         // These ranges should overlap
@@ -201,18 +554,44 @@ mod tests {
         
         let range3 = TimeRange {
             start: "1609459300:000000000".to_string(),
-            end: "1609459360:000000000".to_string(),
+            end: Some("1609459360:000000000".to_string()),
         };
         
         // range1 and range3 should not overlap
         assert!(!timeranges_overlap(&range1, &range3).unwrap());
     }
 
+    #[test]
+    fn test_timerange_without_end_runs_on() {
+        let live = TimeRange::new("100:0", None);
+        assert!(validate_timerange(&live).is_ok());
+        assert!(timestamp_in_range("100:0", &live).unwrap());
+        assert!(timestamp_in_range("9999999999:0", &live).unwrap());
+        assert!(!timestamp_in_range("99:999999999", &live).unwrap());
+        assert!(timeranges_overlap(&live, &TimeRange::new("0:0", Some("100:000000001"))).unwrap());
+        assert!(!timeranges_overlap(&live, &TimeRange::new("0:0", Some("100:0"))).unwrap());
+        assert!(timeranges_overlap(&live, &TimeRange::new("200:0", None)).unwrap());
+
+        let finished = TimeRange::new("100:0", Some("200:0"));
+        let covering = covering_timerange([&finished, &TimeRange::new("150:0", None)]).unwrap().unwrap();
+        assert_eq!((covering.start.as_str(), covering.end), ("100:0", None));
+        let rest = subtract_timeranges(std::slice::from_ref(&live), std::slice::from_ref(&finished)).unwrap();
+        assert_eq!((rest[0].start.as_str(), rest[0].end.as_deref()), ("200:000000000", None));
+        assert!(total_duration(std::slice::from_ref(&live)).is_err());
+        assert_eq!(format_tams_timerange(&live), "[100:0_");
+
+        // Serialized without an end, and read back the same
+        let json = serde_json::to_string(&live).unwrap();
+        assert_eq!(json, r#"{"start":"100:0"}"#);
+        let parsed: TimeRange = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.end, None);
+    }
+
     #[test]
     fn test_timestamp_in_range() {
         let range = TimeRange {
             start: "1609459200:000000000".to_string(),
-            end: "1609459260:000000000".to_string(),
+            end: Some("1609459260:000000000".to_string()),
         };
         
         // Inside range
@@ -240,4 +619,188 @@ mod tests {
         let duration = calculate_duration_nanos(start, end).unwrap();
         assert_eq!(duration, 60_000_000_000); // 60 seconds in nanoseconds
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_segment_timerange_and_covering_range() {
+        let a = parse_segment_timerange("10:0:20:500").unwrap();
+        assert_eq!(a.start, "10:0");
+        assert_eq!(a.end.as_deref(), Some("20:500"));
+        assert!(parse_segment_timerange("10:0:20").is_err());
+
+        let b = parse_segment_timerange("5:0:12:0").unwrap();
+        let covering = covering_timerange([&a, &b]).unwrap().unwrap();
+        assert_eq!(covering.start, "5:0");
+        assert_eq!(covering.end.as_deref(), Some("20:500"));
+        assert!(covering_timerange(std::iter::empty()).unwrap().is_none());
+    }
+
+    /// Deterministic pseudo-random (seconds, nanoseconds) pairs spanning the
+    /// representable range, including negative seconds and edge nanoseconds
+    fn sample_timestamps() -> Vec<(i64, u32)> {
+        let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let mut samples = vec![(0, 0), (0, 999_999_999), (-1, 1), (1_609_459_200, 1), (253_402_300_799, 999_999_999)];
+        for _ in 0..2_000 {
+            let seconds = (next() % 500_000_000_000) as i64 - 250_000_000_000;
+            let nanos = (next() % 1_000_000_000) as u32;
+            samples.push((seconds, nanos));
+        }
+        samples
+    }
+
+    #[test]
+    fn test_tams_timestamp_round_trip_is_lossless() {
+        for (seconds, nanos) in sample_timestamps() {
+            let Some(dt) = DateTime::from_timestamp(seconds, nanos) else {
+                continue;
+            };
+            let formatted = format_tams_timestamp(&dt);
+            assert_eq!(parse_tams_timestamp(&formatted).unwrap(), dt, "{}", formatted);
+        }
+    }
+
+    #[test]
+    fn test_iso8601_round_trip_preserves_nanoseconds() {
+        for (seconds, nanos) in sample_timestamps() {
+            // RFC 3339 only covers years 0000-9999
+            let seconds = seconds.rem_euclid(253_402_300_800);
+            let tams = format!("{}:{:09}", seconds, nanos);
+            let iso = tams_to_iso8601(&tams).unwrap();
+            assert_eq!(iso8601_to_tams(&iso).unwrap(), tams, "{}", iso);
+        }
+
+        assert_eq!(tams_to_iso8601("1609459200:000000001").unwrap(), "2021-01-01T00:00:00.000000001Z");
+    }
+
+    #[test]
+    fn test_storage_format_keeps_nanoseconds_and_sorts() {
+        let earlier = DateTime::from_timestamp(1_609_459_200, 1).unwrap();
+        let later = DateTime::from_timestamp(1_609_459_200, 10).unwrap();
+        let stored = format_rfc3339(&earlier);
+
+        assert_eq!(stored, "2021-01-01T00:00:00.000000001Z");
+        assert_eq!(DateTime::parse_from_rfc3339(&stored).unwrap(), earlier);
+        assert!(stored < format_rfc3339(&later));
+    }
+
+    #[tokio::test]
+    async fn test_time_format_selects_timestamp_serialization() {
+        use crate::models::{ContentFormat, Source};
+
+        let mut source = Source::new(uuid::Uuid::new_v4(), ContentFormat::Video);
+        source.created_at = DateTime::from_timestamp(1_700_000_000, 5).unwrap();
+
+        let default = serde_json::to_value(&source).unwrap();
+        assert_eq!(default["created_at"], "2023-11-14T22:13:20.000000005Z");
+
+        let tams = with_time_format(TimeFormat::Tams, async { serde_json::to_value(&source).unwrap() }).await;
+        assert_eq!(tams["created_at"], "1700000000:000000005");
+
+        let nested = with_time_format(TimeFormat::Tams, async {
+            with_time_format_sync(TimeFormat::Rfc3339, || serde_json::to_value(&source).unwrap())
+        })
+        .await;
+        assert_eq!(nested["created_at"], default["created_at"]);
+
+        assert_eq!(TimeFormat::from_query("TAMS").unwrap(), TimeFormat::Tams);
+        assert!(TimeFormat::from_query("unix").is_err());
+    }
+
+    #[test]
+    fn test_find_gaps() {
+        let window = TimeRange::new("0:0", Some("30:0"));
+        let gaps = |ranges: &[(&str, &str)]| -> Vec<(String, String)> {
+            let ranges: Vec<TimeRange> = ranges.iter().map(|(start, end)| TimeRange::new(start, Some(end))).collect();
+            find_gaps(&window, &ranges).unwrap().into_iter().map(|r| (r.start, r.end.unwrap())).collect()
+        };
+        let pair = |start: &str, end: &str| (start.to_string(), end.to_string());
+
+        assert_eq!(gaps(&[]), vec![pair("0:000000000", "30:000000000")]);
+        // Adjacent segments leave no gap between them
+        assert_eq!(gaps(&[("0:0", "10:0"), ("10:0", "20:0"), ("20:0", "30:0")]), vec![]);
+        // Overlapping and out-of-order segments merge before holes are found
+        assert_eq!(
+            gaps(&[("12:0", "18:0"), ("5:0", "10:0"), ("8:0", "11:0"), ("15:0", "20:0")]),
+            vec![pair("0:000000000", "5:000000000"), pair("11:000000000", "12:000000000"), pair("20:000000000", "30:000000000")]
+        );
+        // Segments reaching past the window are clipped to it
+        assert_eq!(gaps(&[("-5:0", "10:0"), ("25:0", "40:0")]), vec![pair("10:000000000", "25:000000000")]);
+        assert_eq!(gaps(&[("10:0", "10:500000000")])[1], pair("10:500000000", "30:000000000"));
+    }
+
+    #[test]
+    fn test_timerange_set_operations() {
+        let a = vec![
+            TimeRange::new("0:0", Some("10:0")),
+            TimeRange::new("20:0", Some("30:0")),
+            TimeRange::new("5:0", Some("12:0")),
+        ];
+        let b = vec![TimeRange::new("8:0", Some("25:0")), TimeRange::new("40:0", Some("41:500000000"))];
+
+        let pairs = |ranges: Vec<TimeRange>| -> Vec<(String, String)> {
+            ranges.into_iter().map(|r| (r.start, r.end.unwrap())).collect()
+        };
+        let pair = |start: &str, end: &str| (start.to_string(), end.to_string());
+
+        assert_eq!(
+            pairs(subtract_timeranges(&a, &b).unwrap()),
+            vec![pair("0:000000000", "8:000000000"), pair("25:000000000", "30:000000000")]
+        );
+        assert_eq!(
+            pairs(subtract_timeranges(&b, &a).unwrap()),
+            vec![pair("12:000000000", "20:000000000"), pair("40:000000000", "41:500000000")]
+        );
+        assert_eq!(
+            pairs(intersect_timeranges(&a, &b).unwrap()),
+            vec![pair("8:000000000", "12:000000000"), pair("20:000000000", "25:000000000")]
+        );
+        assert_eq!(total_duration(&b).unwrap(), "18:500000000");
+
+        let bound = parse_timerange_param("[10:0_20:0)").unwrap();
+        assert_eq!((bound.start.as_str(), bound.end.as_deref().unwrap()), ("10:0", "20:0"));
+        assert!(parse_timerange_param("10:0").is_err());
+    }
+
+    #[test]
+    fn test_tams_timerange_notation() {
+        let parsed = |value: &str| {
+            let range = parse_tams_timerange(value).unwrap();
+            (range.start, range.end)
+        };
+        let range = |start: &str, end: &str| (start.to_string(), (!end.is_empty()).then(|| end.to_string()));
+
+        // Bounds are normalized to a half-open range; brackets default to [ and )
+        assert_eq!(parsed("[0:0_10:500000000)"), range("0:0", "10:500000000"));
+        assert_eq!(parsed("0:0_10:0"), range("0:0", "10:0"));
+        assert_eq!(parsed("(0:0_10:0]"), range("0:000000001", "10:000000001"));
+        assert_eq!(parsed("[5:999999999_6:0]"), range("5:999999999", "6:000000001"));
+        assert_eq!(parsed("[10:0_10:0]"), range("10:0", "10:000000001"));
+        // Open ends, and all of time
+        assert_eq!(parsed("_10:0)"), range("", "10:0"));
+        assert_eq!(parsed("(0:0_"), range("0:000000001", ""));
+        assert_eq!(parsed("_"), range("", ""));
+
+        // At the boundaries: ) leaves 10:0 out, ] takes it in
+        assert_eq!(parse_timerange_bounds("[0:0_10:0)").unwrap(), (Some(0), Some(10_000_000_000)));
+        assert_eq!(parse_timerange_bounds("[0:0_10:0]").unwrap(), (Some(0), Some(10_000_000_001)));
+        assert_eq!(parse_timerange_bounds("(0:0_").unwrap(), (Some(1), None));
+        assert_eq!(parse_timerange_bounds("_").unwrap(), (None, None));
+
+        for malformed in ["", "10:0", "[0:0-10:0)", "[x_10:0)", "[0:0_10)", "[0:1000000000_1:0)", "[10:0_5:0)", "((0:0_1:0)", "(10:0_10:0)"] {
+            assert!(
+                matches!(parse_tams_timerange(malformed), Err(TamsError::InvalidTimerange(_))),
+                "{:?} should be refused",
+                malformed
+            );
+        }
+
+        for text in ["[0:0_10:500000000)", "_10:0)", "[0:0_", "_"] {
+            assert_eq!(format_tams_timerange(&parse_tams_timerange(text).unwrap()), text);
+        }
+    }
+}
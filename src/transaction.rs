@@ -0,0 +1,211 @@
+//! Request-scoped database transactions.
+//!
+//! `transaction_middleware` gives every request an empty slot; a handler that
+//! takes a [`Tx`] argument opens a [`DatabaseTransaction`] in it and does its
+//! reads and writes through that. Once the handler returns, the middleware
+//! commits on a 2xx response and rolls back on anything else, so a handler
+//! that fails halfway leaves nothing behind. Requests whose handlers don't ask
+//! for a transaction never touch the database here.
+//!
+//! A handler that also takes a JSON body, or has slow checks such as DNS
+//! lookups to make, takes the request's [`TransactionSlot`] instead and calls
+//! [`TransactionSlot::begin`] once the body is read and the checks are done,
+//! so the write lock is never held while a client is still uploading or a
+//! lookup is pending.
+//!
+//! Isolation per backend: SQLite, the only backend, opens the transaction with
+//! `BEGIN IMMEDIATE` and so runs it serializable; see [`DatabaseTransaction`].
+
+use crate::{
+    database::{Database, DatabaseTransaction},
+    error::{TamsError, TamsResult},
+    handlers::AppState,
+};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request},
+    http::{request::Parts, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+/// Where a request's transaction lives between the handler and the middleware
+#[derive(Clone, Default)]
+pub struct TransactionSlot(Arc<Mutex<Option<DatabaseTransaction>>>);
+
+impl TransactionSlot {
+    /// Open a transaction in the slot and hand it out for the handler to use
+    pub async fn begin(&self, database: &Database) -> TamsResult<Tx> {
+        let mut guard = self.0.clone().lock_owned().await;
+        *guard = Some(database.begin_transaction().await?);
+        Ok(Tx(guard))
+    }
+
+    /// Commit the slot's transaction if the response succeeded, roll it back
+    /// otherwise. Does nothing when no transaction was opened.
+    pub async fn finish(&self, status: StatusCode) -> TamsResult<()> {
+        let Some(transaction) = self.0.lock().await.take() else {
+            return Ok(());
+        };
+        if status.is_success() {
+            transaction.commit().await
+        } else {
+            transaction.rollback().await
+        }
+    }
+}
+
+/// Extractor for the request's transaction. Handlers call the transaction's
+/// query methods through it instead of `state.database`.
+pub struct Tx(OwnedMutexGuard<Option<DatabaseTransaction>>);
+
+impl Deref for Tx {
+    type Target = DatabaseTransaction;
+
+    fn deref(&self) -> &DatabaseTransaction {
+        self.0.as_ref().expect("transaction stays open while the handler holds it")
+    }
+}
+
+impl DerefMut for Tx {
+    fn deref_mut(&mut self) -> &mut DatabaseTransaction {
+        self.0.as_mut().expect("transaction stays open while the handler holds it")
+    }
+}
+
+impl Tx {
+    /// Commit before the response is ready, for a handler with follow-up work
+    /// that must only happen once its writes are stored. The middleware then
+    /// finds nothing left to settle.
+    pub async fn commit(mut self) -> TamsResult<()> {
+        let transaction = self.0.take().expect("transaction stays open while the handler holds it");
+        transaction.commit().await
+    }
+}
+
+fn request_slot(extensions: &axum::http::Extensions) -> TamsResult<TransactionSlot> {
+    extensions
+        .get::<TransactionSlot>()
+        .cloned()
+        .ok_or_else(|| TamsError::Internal("transaction_middleware is not installed".to_string()))
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Tx {
+    type Rejection = TamsError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        request_slot(&parts.extensions)?.begin(&state.database).await
+    }
+}
+
+/// Settle the transaction a handler opened once its response is ready
+pub async fn transaction_middleware(mut request: Request, next: Next) -> Response {
+    let slot = TransactionSlot::default();
+    request.extensions_mut().insert(slot.clone());
+    let response = next.run(request).await;
+    match slot.finish(response.status()).await {
+        Ok(()) => response,
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{ContentFormat, CreateSegmentRequest, Flow},
+        routes::tests::TestApp,
+    };
+    use axum::{body::Body, extract::State, http::Method, middleware, routing::post, Json, Router};
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    /// Adds the segment in the body, then answers with the body's status
+    async fn add_segment_then_answer(
+        State(state): State<AppState>,
+        axum::Extension(slot): axum::Extension<TransactionSlot>,
+        Json(body): Json<Value>,
+    ) -> StatusCode {
+        let mut tx = slot.begin(&state.database).await.unwrap();
+        let flow_id = Uuid::parse_str(body["flow_id"].as_str().unwrap()).unwrap();
+        let segment: CreateSegmentRequest = serde_json::from_value(body["segment"].clone()).unwrap();
+        tx.add_flow_segment(&segment.into_segment(flow_id)).await.unwrap();
+        StatusCode::from_u16(body["status"].as_u64().unwrap() as u16).unwrap()
+    }
+
+    async fn router_with_flow() -> (TestApp, Router, Uuid) {
+        let app = TestApp::new().await;
+        let flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        app.state.database.create_flow(&flow).await.unwrap();
+        let router = Router::new()
+            .route("/write", post(add_segment_then_answer))
+            .layer(middleware::from_fn(transaction_middleware))
+            .with_state(app.state.clone());
+        (app, router, flow.id)
+    }
+
+    fn write_request(flow_id: Uuid, object_id: &str, status: u16) -> Request<Body> {
+        let body = json!({
+            "flow_id": flow_id,
+            "segment": {"object_id": object_id, "timerange": {"start": "0:0", "end": "10:0"}},
+            "status": status,
+        });
+        Request::builder()
+            .method(Method::POST)
+            .uri("/write")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_success_commits_and_failure_rolls_back() {
+        let (app, router, flow_id) = router_with_flow().await;
+
+        let response = router.clone().oneshot(write_request(flow_id, "kept", 201)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = router.oneshot(write_request(flow_id, "discarded", 409)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let segments = app.state.database.get_flow_segments(&flow_id).await.unwrap().items;
+        let object_ids: Vec<&str> = segments.iter().map(|segment| segment.object_id.as_str()).collect();
+        assert_eq!(object_ids, vec!["kept"]);
+    }
+
+    #[tokio::test]
+    async fn test_transaction_opens_after_the_body_arrives() {
+        let (app, router, flow_id) = router_with_flow().await;
+        let (send_body, body_ready) = tokio::sync::oneshot::channel::<Request<Body>>();
+        let slow_body = futures_util::stream::once(async move {
+            let request = body_ready.await.unwrap();
+            axum::body::to_bytes(request.into_body(), usize::MAX).await
+        });
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/write")
+            .header("content-type", "application/json")
+            .body(Body::from_stream(slow_body))
+            .unwrap();
+        let pending = tokio::spawn(router.oneshot(request));
+
+        // Another writer gets the write lock while the body is still on its way
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let other = tokio::time::timeout(Duration::from_secs(1), app.state.database.begin_transaction())
+            .await
+            .expect("the write lock is free while the body uploads")
+            .unwrap();
+        other.rollback().await.unwrap();
+
+        send_body.send(write_request(flow_id, "late", 201)).unwrap();
+        assert_eq!(pending.await.unwrap().unwrap().status(), StatusCode::CREATED);
+        assert_eq!(app.state.database.get_flow_segments(&flow_id).await.unwrap().items.len(), 1);
+    }
+}
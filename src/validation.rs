@@ -0,0 +1,667 @@
+use crate::{
+    config::{CodecInferencePolicy, FutureSkewPolicy, ServiceConfig, ValidationConfig},
+    error::{TamsError, TamsResult},
+    models::{ContentFormat, CreateSegmentRequest, EventType, Flow, TimeRange, ALL_EVENTS_SUBSCRIPTION},
+    storage::GetUrlTemplate,
+    time_utils::{
+        calculate_duration_nanos, format_rfc3339, format_tams_timestamp, nanos_to_timestamp, parse_tams_timestamp,
+    },
+    warnings::{ResponseWarning, Warnings},
+};
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+};
+
+/// Number of allowed values quoted back in a vocabulary mismatch error
+const ALLOWED_EXCERPT_LEN: usize = 10;
+
+/// Normalize a free-text vocabulary value against an optional allow list.
+///
+/// Surrounding whitespace is always trimmed. When an allow list is configured the
+/// value must match one of its entries case-insensitively and is rewritten to the
+/// casing used in the list.
+pub fn normalize_vocabulary(field: &str, value: &str, allowed: Option<&[String]>) -> TamsResult<String> {
+    let trimmed = value.trim();
+
+    let Some(allowed) = allowed else {
+        return Ok(trimmed.to_string());
+    };
+
+    if let Some(canonical) = allowed.iter().find(|a| a.eq_ignore_ascii_case(trimmed)) {
+        return Ok(canonical.clone());
+    }
+
+    let mut excerpt = allowed
+        .iter()
+        .take(ALLOWED_EXCERPT_LEN)
+        .map(|a| a.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if allowed.len() > ALLOWED_EXCERPT_LEN {
+        excerpt.push_str(&format!(", ... ({} more)", allowed.len() - ALLOWED_EXCERPT_LEN));
+    }
+
+    Err(TamsError::Validation(format!(
+        "Unsupported {} '{}': allowed values are {}",
+        field, trimmed, excerpt
+    )))
+}
+
+/// Normalize a flow's container and codec in place before it is stored
+pub fn normalize_flow_vocabularies(flow: &mut Flow, config: &ValidationConfig) -> TamsResult<()> {
+    if let Some(container) = &flow.container {
+        flow.container = Some(normalize_vocabulary(
+            "container",
+            container,
+            config.allowed_containers.as_deref(),
+        )?);
+    }
+    if let Some(codec) = &flow.codec {
+        flow.codec = Some(normalize_vocabulary(
+            "codec",
+            codec,
+            config.allowed_codecs.as_deref(),
+        )?);
+    }
+    Ok(())
+}
+
+/// Fill in a flow's missing container or codec from `container_codecs`, and
+/// check a pair given together against it, as `codec_inference` says. A
+/// missing codec becomes the container's first codec of the flow's media type;
+/// a missing container becomes the first one listing the codec. Each inference
+/// is reported as an `inferred` warning. A codec its container isn't listed
+/// with is a `codec_mismatch` warning, or refused under the strict policy.
+/// Containers not in the table are never judged.
+pub fn infer_container_codec(flow: &mut Flow, config: &ValidationConfig, warnings: &Warnings) -> TamsResult<()> {
+    if config.codec_inference == CodecInferencePolicy::Off {
+        return Ok(());
+    }
+    let table = &config.container_codecs;
+    let lists = |codecs: &[String], codec: &str| codecs.iter().any(|listed| listed.eq_ignore_ascii_case(codec.trim()));
+    let row_for = |container: &str| table.iter().find(|row| row.container.eq_ignore_ascii_case(container.trim()));
+
+    match (&flow.container, &flow.codec) {
+        (Some(container), Some(codec)) => {
+            let Some(row) = row_for(container) else {
+                return Ok(());
+            };
+            if lists(&row.codecs, codec) {
+                return Ok(());
+            }
+            let message = format!(
+                "Codec '{}' is not expected in container '{}', which usually carries {}",
+                codec,
+                container,
+                row.codecs.join(", ")
+            );
+            if config.codec_inference == CodecInferencePolicy::Strict {
+                return Err(TamsError::Validation(message));
+            }
+            warnings.push(ResponseWarning::new("codec_mismatch", message).with_field("codec"));
+        }
+        (Some(container), None) => {
+            let media_type = match flow.format {
+                ContentFormat::Video => Some("video/"),
+                ContentFormat::Audio => Some("audio/"),
+                _ => None,
+            };
+            let codec = row_for(container).and_then(|row| {
+                row.codecs
+                    .iter()
+                    .find(|codec| media_type.is_none_or(|media_type| codec.starts_with(media_type)))
+            });
+            if let Some(codec) = codec {
+                warnings.push(
+                    ResponseWarning::new("inferred", format!("codec '{}' was inferred from container '{}'", codec, container))
+                        .with_field("codec"),
+                );
+                flow.codec = Some(codec.clone());
+            }
+        }
+        (None, Some(codec)) => {
+            if let Some(row) = table.iter().find(|row| lists(&row.codecs, codec)) {
+                warnings.push(
+                    ResponseWarning::new("inferred", format!("container '{}' was inferred from codec '{}'", row.container, codec))
+                        .with_field("container"),
+                );
+                flow.container = Some(row.container.clone());
+            }
+        }
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+/// Check the container suits the flow's format: image flows (stills) must use
+/// an `image/*` media type, and only image flows may. Stills carry no frame rate,
+/// so none is required of them.
+pub fn check_flow_format(flow: &Flow) -> TamsResult<()> {
+    let Some(container) = &flow.container else {
+        return Ok(());
+    };
+
+    let is_image_container = container.trim().to_ascii_lowercase().starts_with("image/");
+    match (&flow.format, is_image_container) {
+        (ContentFormat::Image, false) => Err(TamsError::Validation(format!(
+            "Image flows must use an image/* container, got '{}'",
+            container
+        ))),
+        (ContentFormat::Image, true) | (_, false) => Ok(()),
+        (_, true) => Err(TamsError::Validation(format!(
+            "Container '{}' is only valid for image flows",
+            container
+        ))),
+    }
+}
+
+/// Reject a flow_collection that lists the flow itself or the same flow twice
+pub fn check_flow_collection(flow: &Flow) -> TamsResult<()> {
+    let Some(collection) = &flow.flow_collection else {
+        return Ok(());
+    };
+    let mut seen = HashSet::new();
+    for item in &collection.flows {
+        if item.flow_id == flow.id {
+            return Err(TamsError::Validation(format!(
+                "Flow {} cannot be a member of its own flow_collection",
+                flow.id
+            )));
+        }
+        if !seen.insert(item.flow_id) {
+            return Err(TamsError::Validation(format!(
+                "flow_collection lists flow {} more than once",
+                item.flow_id
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Require a flow's notify_url to come with a secret and to be an http(s) URL
+/// that, unless private targets are allowed, doesn't reach into the server's
+/// own network by address. A hostname is only checked by name here; see
+/// [`check_notify_url_resolves`] for where it points.
+pub fn check_flow_notify(flow: &Flow, config: &ValidationConfig) -> TamsResult<()> {
+    let Some(notify_url) = &flow.notify_url else {
+        return Ok(());
+    };
+    if flow.notify_secret.as_deref().is_none_or(str::is_empty) {
+        return Err(TamsError::Validation("notify_url requires a notify_secret".to_string()));
+    }
+    let url = parse_notify_url(notify_url)?;
+    if config.allow_private_notify_urls {
+        return Ok(());
+    }
+    let private = match url.host() {
+        None => return Err(invalid_notify_url(notify_url, "a host is required")),
+        Some(url::Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            domain == "localhost" || domain.ends_with(".localhost")
+        }
+        Some(url::Host::Ipv4(ip)) => is_private_address(IpAddr::V4(ip)),
+        Some(url::Host::Ipv6(ip)) => is_private_address(IpAddr::V6(ip)),
+    };
+    if private {
+        return Err(invalid_notify_url(notify_url, "loopback, private and link-local addresses are not allowed"));
+    }
+    Ok(())
+}
+
+/// How long a notify_url hostname may take to resolve
+const NOTIFY_URL_RESOLVE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Resolve a notify_url hostname and refuse it, unless private targets are
+/// allowed, when it doesn't resolve or any of its addresses is private, so a
+/// public-looking name can't point the dispatcher into the server's network
+pub async fn check_notify_url_resolves(notify_url: &str, config: &ValidationConfig) -> TamsResult<()> {
+    if config.allow_private_notify_urls {
+        return Ok(());
+    }
+    let url = parse_notify_url(notify_url)?;
+    let (Some(url::Host::Domain(domain)), Some(port)) = (url.host(), url.port_or_known_default()) else {
+        return Ok(());
+    };
+    let lookup = tokio::time::timeout(NOTIFY_URL_RESOLVE_TIMEOUT, tokio::net::lookup_host((domain, port)))
+        .await
+        .map_err(|_| invalid_notify_url(notify_url, "host didn't resolve in time"))?;
+    let addresses: Vec<SocketAddr> =
+        lookup.map_err(|e| invalid_notify_url(notify_url, &format!("host doesn't resolve: {}", e)))?.collect();
+    if addresses.is_empty() {
+        return Err(invalid_notify_url(notify_url, "host doesn't resolve"));
+    }
+    if addresses.iter().any(|address| is_private_address(address.ip())) {
+        return Err(invalid_notify_url(notify_url, "host resolves to a loopback, private or link-local address"));
+    }
+    Ok(())
+}
+
+fn invalid_notify_url(notify_url: &str, reason: &str) -> TamsError {
+    TamsError::Validation(format!("Invalid notify_url '{}': {}", notify_url, reason))
+}
+
+fn parse_notify_url(notify_url: &str) -> TamsResult<url::Url> {
+    let url = url::Url::parse(notify_url).map_err(|e| invalid_notify_url(notify_url, &e.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(invalid_notify_url(notify_url, "only http and https are supported"));
+    }
+    Ok(url)
+}
+
+/// Loopback, private, link-local and unspecified addresses, IPv4-mapped ones included
+fn is_private_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast()
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || (segments[0] & 0xfe00) == 0xfc00 // unique local
+                || (segments[0] & 0xffc0) == 0xfe80 // link local
+                || ip.to_ipv4_mapped().is_some_and(|ip| is_private_address(IpAddr::V4(ip)))
+        }
+    }
+}
+
+/// Run every create-time check on a new flow, normalizing it in place, and
+/// return each failure with the field it concerns. Later checks still run
+/// after one fails, so a caller can report all of them at once.
+pub fn check_new_flow(
+    flow: &mut Flow,
+    service: &ServiceConfig,
+    config: &ValidationConfig,
+    warnings: &Warnings,
+) -> Vec<(&'static str, TamsError)> {
+    let mut failures = Vec::new();
+    let mut check = |field: &'static str, result: TamsResult<()>| {
+        if let Err(e) = result {
+            failures.push((field, e));
+        }
+    };
+    check("tags", apply_service_tag_policy(&mut flow.tags, service));
+    if let Some(max_bytes) = config.max_tags_bytes {
+        check("tags", check_serialized_size("tags", &flow.tags, max_bytes));
+    }
+    if let (Some(max_bytes), Some(collection)) = (config.max_flow_collection_bytes, &flow.flow_collection) {
+        check("flow_collection", check_serialized_size("flow_collection", collection, max_bytes));
+    }
+    check("codec", infer_container_codec(flow, config, warnings));
+    if let Some(container) = &flow.container {
+        let normalized = normalize_vocabulary("container", container, config.allowed_containers.as_deref());
+        check("container", normalized.map(|container| flow.container = Some(container)));
+    }
+    if let Some(codec) = &flow.codec {
+        let normalized = normalize_vocabulary("codec", codec, config.allowed_codecs.as_deref());
+        check("codec", normalized.map(|codec| flow.codec = Some(codec)));
+    }
+    check("container", check_flow_format(flow));
+    check("flow_collection", check_flow_collection(flow));
+    check("tags", GetUrlTemplate::for_flow(flow).map(|_| ()));
+    check("notify_url", check_flow_notify(flow, config));
+    failures
+}
+
+/// Merge the service's default tags into a new resource's tags, keeping any
+/// value the client sent, then require the configured tag keys
+pub fn apply_service_tag_policy(tags: &mut HashMap<String, String>, service: &ServiceConfig) -> TamsResult<()> {
+    for (key, value) in &service.default_tags {
+        tags.entry(key.clone()).or_insert_with(|| value.clone());
+    }
+
+    let missing: Vec<&str> = service
+        .required_tags
+        .iter()
+        .filter(|key| !tags.contains_key(key.as_str()))
+        .map(|key| key.as_str())
+        .collect();
+    if !missing.is_empty() {
+        return Err(TamsError::Validation(format!("Missing required tags: {}", missing.join(", "))));
+    }
+    Ok(())
+}
+
+/// Rewrite a webhook's event subscriptions to their canonical names, rejecting
+/// unknown events and dropping duplicates
+pub fn normalize_webhook_events(events: &[String]) -> TamsResult<Vec<String>> {
+    let mut normalized: Vec<String> = Vec::with_capacity(events.len());
+    for event in events {
+        let event = if event == ALL_EVENTS_SUBSCRIPTION {
+            event.clone()
+        } else {
+            event.parse::<EventType>()?.to_string()
+        };
+        if !normalized.contains(&event) {
+            normalized.push(event);
+        }
+    }
+    Ok(normalized)
+}
+
+/// A flow created without a format is stored as a data flow, which usually
+/// means the client forgot to say video or audio. Under `require_flow_format`
+/// that is refused; otherwise it is accepted with a `format_defaulted` warning.
+pub fn check_flow_format_given(format: Option<&ContentFormat>, config: &ValidationConfig, warnings: &Warnings) -> TamsResult<()> {
+    if format.is_some() {
+        return Ok(());
+    }
+    if config.require_flow_format {
+        return Err(TamsError::Validation("format is required".to_string()));
+    }
+    warnings.push(
+        ResponseWarning::new("format_defaulted", format!("No format given; the flow is stored as {}", ContentFormat::Data.urn()))
+            .with_field("format"),
+    );
+    Ok(())
+}
+
+/// Catch segments from ingest nodes whose clocks run ahead: a segment starting
+/// more than the allowed skew after `now` is rejected, or under the `warn`
+/// policy accepted with a `future_segment` warning. Both timestamps are
+/// reported so the drift is visible.
+pub fn check_segment_clock_skew(
+    segment: &CreateSegmentRequest,
+    now: DateTime<Utc>,
+    config: &ValidationConfig,
+    warnings: &Warnings,
+) -> TamsResult<()> {
+    // Callers validate the timerange first; a start that doesn't parse has nothing to compare
+    let Ok(start) = parse_tams_timestamp(&segment.timerange.start) else {
+        return Ok(());
+    };
+    let ahead = start - now;
+    if ahead <= chrono::Duration::seconds(config.max_future_skew_seconds as i64) {
+        return Ok(());
+    }
+
+    let message = format!(
+        "Segment starts at {} ({}), {}s ahead of server time {} ({}); the allowed skew is {}s",
+        segment.timerange.start,
+        format_rfc3339(&start),
+        ahead.num_seconds(),
+        format_tams_timestamp(&now),
+        format_rfc3339(&now),
+        config.max_future_skew_seconds
+    );
+    match config.future_skew_policy {
+        FutureSkewPolicy::Reject => Err(TamsError::Validation(message)),
+        FutureSkewPolicy::Warn => {
+            warnings.push(ResponseWarning::new("future_segment", message).with_field("timerange.start"));
+            Ok(())
+        }
+    }
+}
+
+/// Reject flows whose serialized tags or flow_collection exceed the configured limits
+pub fn check_flow_field_sizes(flow: &Flow, config: &ValidationConfig) -> TamsResult<()> {
+    if let (Some(max_bytes), Some(collection)) = (config.max_flow_collection_bytes, &flow.flow_collection) {
+        check_serialized_size("flow_collection", collection, max_bytes)?;
+    }
+    if let Some(max_bytes) = config.max_tags_bytes {
+        check_serialized_size("tags", &flow.tags, max_bytes)?;
+    }
+    Ok(())
+}
+
+fn check_serialized_size(field: &str, value: &impl serde::Serialize, max_bytes: usize) -> TamsResult<()> {
+    let size = serde_json::to_vec(value)?.len();
+    if size > max_bytes {
+        return Err(TamsError::Validation(format!(
+            "Serialized {} is {} bytes, exceeding the limit of {} bytes",
+            field, size, max_bytes
+        )));
+    }
+    Ok(())
+}
+
+/// Reject segments whose ts_offset, if given, is not a TAMS timestamp; segments
+/// are ordered by it
+pub fn check_segment_ts_offset(segment: &CreateSegmentRequest) -> TamsResult<()> {
+    if let Some(ts_offset) = &segment.ts_offset {
+        parse_tams_timestamp(ts_offset)
+            .map_err(|e| TamsError::Validation(format!("Invalid ts_offset '{}': {}", ts_offset, e)))?;
+    }
+    Ok(())
+}
+
+/// Reject query timeranges whose span exceeds the configured maximum. An open
+/// end spans everything after the start, so it is refused while a maximum is set
+pub fn check_query_duration(timerange: &TimeRange, config: &ValidationConfig) -> TamsResult<()> {
+    let Some(max_seconds) = config.max_query_duration_seconds else {
+        return Ok(());
+    };
+
+    let Some(end) = &timerange.end else {
+        return Err(open_ended_query(max_seconds));
+    };
+    let span_nanos = calculate_duration_nanos(&timerange.start, end)?;
+    let max_nanos = (max_seconds as i64).saturating_mul(1_000_000_000);
+    if span_nanos > max_nanos {
+        return Err(TamsError::InvalidTimerange(format!(
+            "Requested timerange spans {:.3} seconds, exceeding the maximum query duration of {} seconds; narrow the query window",
+            span_nanos as f64 / 1_000_000_000.0,
+            max_seconds
+        )));
+    }
+
+    Ok(())
+}
+
+/// [`check_query_duration`] for a window already parsed into nanosecond bounds;
+/// with neither bound there is no window, and the caller decides what that spans
+pub fn check_query_bounds(start_ns: Option<i64>, end_ns: Option<i64>, config: &ValidationConfig) -> TamsResult<()> {
+    match (start_ns, end_ns, config.max_query_duration_seconds) {
+        (None, None, _) | (_, _, None) => Ok(()),
+        (Some(start), Some(end), _) => check_query_duration(
+            &TimeRange { start: nanos_to_timestamp(start), end: Some(nanos_to_timestamp(end)) },
+            config,
+        ),
+        (_, _, Some(max_seconds)) => Err(open_ended_query(max_seconds)),
+    }
+}
+
+fn open_ended_query(max_seconds: u64) -> TamsError {
+    TamsError::InvalidTimerange(format!(
+        "Open-ended timeranges are refused while the maximum query duration is {} seconds; give both a start and an end",
+        max_seconds
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ContentFormat, FlowCollection, FlowCollectionItem};
+    use uuid::Uuid;
+
+    fn codecs() -> Vec<String> {
+        vec!["video/h264".to_string(), "video/H265".to_string()]
+    }
+
+    #[test]
+    fn test_unset_vocabulary_only_trims() {
+        let value = normalize_vocabulary("codec", "  H.264 ", None).unwrap();
+        assert_eq!(value, "H.264");
+    }
+
+    #[test]
+    fn test_vocabulary_match_is_case_insensitive_and_canonicalized() {
+        let allowed = codecs();
+        assert_eq!(normalize_vocabulary("codec", "VIDEO/H264", Some(&allowed)).unwrap(), "video/h264");
+        assert_eq!(normalize_vocabulary("codec", " video/h265", Some(&allowed)).unwrap(), "video/H265");
+    }
+
+    #[test]
+    fn test_vocabulary_mismatch_lists_allowed_values() {
+        let allowed = codecs();
+        let err = normalize_vocabulary("codec", "avc1", Some(&allowed)).unwrap_err();
+        match err {
+            TamsError::Validation(msg) => {
+                assert!(msg.contains("avc1"));
+                assert!(msg.contains("video/h264, video/H265"));
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_normalize_flow_vocabularies() {
+        let config = ValidationConfig {
+            allowed_containers: Some(vec!["video/mp2t".to_string()]),
+            ..Default::default()
+        };
+
+        let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        flow.container = Some("Video/MP2T".to_string());
+        flow.codec = Some(" h264 ".to_string());
+        normalize_flow_vocabularies(&mut flow, &config).unwrap();
+        assert_eq!(flow.container.as_deref(), Some("video/mp2t"));
+        assert_eq!(flow.codec.as_deref(), Some("h264"));
+
+        flow.container = Some("video/mp4".to_string());
+        assert!(normalize_flow_vocabularies(&mut flow, &config).is_err());
+    }
+
+    #[test]
+    fn test_container_codec_inference() {
+        let config = ValidationConfig {
+            codec_inference: CodecInferencePolicy::Infer,
+            ..Default::default()
+        };
+        let flow_with = |format: ContentFormat, container: Option<&str>, codec: Option<&str>| {
+            let mut flow = Flow::new(Uuid::new_v4(), format);
+            flow.container = container.map(str::to_string);
+            flow.codec = codec.map(str::to_string);
+            flow
+        };
+
+        let warnings = Warnings::default();
+        let mut audio = flow_with(ContentFormat::Audio, Some("video/mp2t"), None);
+        infer_container_codec(&mut audio, &config, &warnings).unwrap();
+        assert_eq!(audio.codec.as_deref(), Some("audio/aac"));
+        let mut video = flow_with(ContentFormat::Video, None, Some("video/VP9"));
+        infer_container_codec(&mut video, &config, &warnings).unwrap();
+        assert_eq!(video.container.as_deref(), Some("video/webm"));
+        let inferred = warnings.take();
+        assert_eq!(inferred.iter().map(|w| (w.code.as_str(), w.field.as_deref())).collect::<Vec<_>>(), [
+            ("inferred", Some("codec")),
+            ("inferred", Some("container"))
+        ]);
+
+        // Pairs are judged against the table only when it knows the container
+        let mut mismatched = flow_with(ContentFormat::Video, Some("video/webm"), Some("video/h264"));
+        infer_container_codec(&mut mismatched, &config, &warnings).unwrap();
+        let mut unknown = flow_with(ContentFormat::Video, Some("video/x-custom"), Some("video/h264"));
+        infer_container_codec(&mut unknown, &config, &warnings).unwrap();
+        assert_eq!(warnings.take().iter().map(|w| w.code.as_str()).collect::<Vec<_>>(), ["codec_mismatch"]);
+
+        let strict = ValidationConfig {
+            codec_inference: CodecInferencePolicy::Strict,
+            ..Default::default()
+        };
+        assert!(matches!(
+            infer_container_codec(&mut mismatched, &strict, &warnings),
+            Err(TamsError::Validation(_))
+        ));
+        let service = crate::config::AppConfig::from_file("config").unwrap().service;
+        let failures = check_new_flow(&mut mismatched, &service, &strict, &warnings);
+        assert_eq!(failures.iter().map(|(field, _)| *field).collect::<Vec<_>>(), ["codec"]);
+
+        let mut untouched = flow_with(ContentFormat::Video, Some("video/mp2t"), None);
+        infer_container_codec(&mut untouched, &ValidationConfig::default(), &warnings).unwrap();
+        assert_eq!(untouched.codec, None);
+    }
+
+    #[test]
+    fn test_image_flow_containers() {
+        let mut still = Flow::new(Uuid::new_v4(), ContentFormat::Image);
+        assert!(check_flow_format(&still).is_ok());
+        still.container = Some("image/jpeg".to_string());
+        assert!(check_flow_format(&still).is_ok());
+        still.container = Some("video/mp2t".to_string());
+        assert!(check_flow_format(&still).is_err());
+
+        let mut video = Flow::new(Uuid::new_v4(), ContentFormat::Video);
+        video.container = Some("Image/PNG".to_string());
+        assert!(check_flow_format(&video).is_err());
+    }
+
+    #[test]
+    fn test_flow_field_size_limits() {
+        let config = ValidationConfig {
+            max_flow_collection_bytes: Some(64),
+            max_tags_bytes: Some(32),
+            ..Default::default()
+        };
+
+        let mut flow = Flow::new(Uuid::new_v4(), ContentFormat::Multi);
+        flow.tags.insert("a".to_string(), "b".to_string());
+        assert!(check_flow_field_sizes(&flow, &config).is_ok());
+
+        flow.tags.insert("long".to_string(), "x".repeat(64));
+        assert!(check_flow_field_sizes(&flow, &config).is_err());
+
+        flow.tags.clear();
+        flow.flow_collection = Some(FlowCollection {
+            flows: vec![FlowCollectionItem {
+                flow_id: Uuid::new_v4(),
+                role: Some("video".to_string()),
+                container_map: None,
+            }],
+        });
+        assert!(check_flow_field_sizes(&flow, &config).is_err());
+    }
+
+    #[test]
+    fn test_query_duration_limit() {
+        let config = ValidationConfig {
+            max_query_duration_seconds: Some(60),
+            ..Default::default()
+        };
+        let within = TimeRange::new("100:0", Some("160:0"));
+        let beyond = TimeRange::new("100:0", Some("160:1"));
+
+        assert!(check_query_duration(&within, &config).is_ok());
+        assert!(matches!(
+            check_query_duration(&beyond, &config),
+            Err(TamsError::InvalidTimerange(_))
+        ));
+        assert!(check_query_duration(&beyond, &ValidationConfig::default()).is_ok());
+
+        let open_ended = TimeRange::new("100:0", None);
+        assert!(check_query_duration(&open_ended, &config).is_err());
+        assert!(check_query_duration(&open_ended, &ValidationConfig::default()).is_ok());
+        assert!(check_query_bounds(None, None, &config).is_ok());
+        assert!(check_query_bounds(Some(100_000_000_000), None, &config).is_err());
+        assert!(check_query_bounds(None, Some(100_000_000_000), &config).is_err());
+    }
+
+    #[test]
+    fn test_normalize_webhook_events() {
+        let events = |e: &[&str]| e.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            normalize_webhook_events(&events(&["flow.created", "flows/created", "*", "sources/deleted"])).unwrap(),
+            events(&["flows/created", "*", "sources/deleted"])
+        );
+        assert!(matches!(
+            normalize_webhook_events(&events(&["flows/renamed"])),
+            Err(TamsError::Validation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_notify_hostname_is_checked_where_it_resolves() {
+        let config = ValidationConfig::default();
+        // The literal check only catches localhost by name; resolving catches any alias of it
+        let result = check_notify_url_resolves("http://localhost:9/hook", &config).await;
+        assert!(matches!(result, Err(TamsError::Validation(msg)) if msg.contains("resolves to")));
+        let result = check_notify_url_resolves("http://does-not-exist.invalid/hook", &config).await;
+        assert!(matches!(result, Err(TamsError::Validation(msg)) if msg.contains("doesn't resolve")));
+        assert!(check_notify_url_resolves("http://203.0.113.7/hook", &config).await.is_ok());
+
+        let permissive = ValidationConfig { allow_private_notify_urls: true, ..ValidationConfig::default() };
+        assert!(check_notify_url_resolves("http://localhost:9/hook", &permissive).await.is_ok());
+    }
+}
@@ -0,0 +1,132 @@
+//! Soft validation warnings returned alongside successful responses.
+//!
+//! `warnings_middleware` gives every request an empty [`Warnings`] collector;
+//! handlers take it as an argument (and pass it on to validators) to report
+//! something that was accepted but looks wrong, e.g. a segment from an ingest
+//! node whose clock runs ahead under the permissive skew policy. When the
+//! handler succeeds with warnings collected, the middleware adds them to a JSON
+//! object body as a `warnings` array, sets `X-TAMS-Warnings-Count`, and emits
+//! one `Warning: 199` header per warning. Clients should log these rather
+//! than ignore them: the same conditions are errors under the strict policies.
+//!
+//! Responses without warnings pass through untouched; an unused collector
+//! costs one small allocation per request.
+
+use axum::{
+    async_trait,
+    body::Body,
+    extract::{FromRequestParts, Request},
+    http::{header, request::Parts, HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex},
+};
+
+pub const WARNINGS_COUNT_HEADER: HeaderName = HeaderName::from_static("x-tams-warnings-count");
+
+/// Something the server accepted but wants the client to know about; the
+/// fields mirror those of an error body
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResponseWarning {
+    pub code: String,
+    pub message: String,
+    /// Path of the request field the warning is about, e.g. `timerange.start`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+impl ResponseWarning {
+    pub fn new(code: &str, message: impl Into<String>) -> Self {
+        ResponseWarning {
+            code: code.to_string(),
+            message: message.into(),
+            field: None,
+        }
+    }
+
+    pub fn with_field(mut self, field: &str) -> Self {
+        self.field = Some(field.to_string());
+        self
+    }
+}
+
+/// Per-request warning collector. Extracting it outside `warnings_middleware`
+/// gives a detached collector whose warnings are never sent.
+#[derive(Debug, Clone, Default)]
+pub struct Warnings(Arc<Mutex<Vec<ResponseWarning>>>);
+
+impl Warnings {
+    pub fn push(&self, warning: ResponseWarning) {
+        tracing::warn!("Request accepted with warning {}: {}", warning.code, warning.message);
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).push(warning);
+    }
+
+    /// The warnings collected so far, left in place for the middleware
+    pub fn snapshot(&self) -> Vec<ResponseWarning> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn take(&self) -> Vec<ResponseWarning> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Warnings {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts.extensions.get::<Warnings>().cloned().unwrap_or_default())
+    }
+}
+
+/// Attach the warnings a handler collected to its successful response
+pub async fn warnings_middleware(mut request: Request, next: Next) -> Response {
+    let warnings = Warnings::default();
+    request.extensions_mut().insert(warnings.clone());
+    let response = next.run(request).await;
+
+    let collected = warnings.take();
+    if collected.is_empty() || !response.status().is_success() {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    parts.headers.insert(WARNINGS_COUNT_HEADER, HeaderValue::from(collected.len()));
+    for warning in &collected {
+        // 199 is the miscellaneous warn-code; quotes can't appear in the warn-text
+        let value = format!("199 tams \"{}\"", warning.message.replace('"', "'"));
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            parts.headers.append(header::WARNING, value);
+        }
+    }
+
+    let is_json = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    if !is_json {
+        return Response::from_parts(parts, body);
+    }
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("Failed to buffer response body for warnings: {}", e);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    let body = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(Value::Object(mut fields)) => {
+            fields.insert("warnings".to_string(), serde_json::to_value(&collected).unwrap_or_default());
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Body::from(Value::Object(fields).to_string())
+        }
+        _ => Body::from(bytes),
+    };
+    Response::from_parts(parts, body)
+}
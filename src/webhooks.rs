@@ -1,34 +1,310 @@
-use crate::{error::TamsResult, models::*};
+use crate::{
+    clock::{system_clock, SharedClock},
+    config::{EventStreamConfig, WebhookConfig},
+    crypto::{is_sealed, SecretCipher},
+    database::Database,
+    error::{TamsError, TamsResult},
+    events::{EventAudience, EventStream},
+    metrics::metrics,
+    models::*,
+    time_utils::{self, TimeFormat},
+};
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
 use reqwest::Client;
-use serde_json::json;
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::RwLock;
+use serde_json::{json, Map, Value};
+use sha2::Sha256;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    sync::{atomic::Ordering, Arc, Mutex},
+};
+use tokio::sync::{mpsc, oneshot, RwLock};
 use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// HMAC-SHA256 of the request body under the current api_key_value
+pub const SIGNATURE_HEADER: &str = "X-TAMS-Signature";
+/// The same signature under the rotated-out key, sent only during the overlap window
+pub const PREVIOUS_SIGNATURE_HEADER: &str = "X-TAMS-Signature-Previous";
+
+/// Most of a receiver's response body read; the rest is discarded unread
+const MAX_RESPONSE_BODY_BYTES: usize = 4096;
+/// How long a receiver may take to accept a connection
+const CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Events estimated at least this large are serialized on a blocking thread
+const BLOCKING_SERIALIZE_BYTES: usize = 256 * 1024;
+
+/// Rough serialized size of an event, so large notifications can be serialized
+/// off the async workers before anything is known about their real size
+pub trait EstimatedSize {
+    fn estimated_size(&self) -> usize {
+        0
+    }
+}
+
+fn estimated_flow_size(flow: &Flow) -> usize {
+    let tags: usize = flow.tags.iter().map(|(key, value)| key.len() + value.len() + 8).sum();
+    let collection = flow.flow_collection.as_ref().map_or(0, |collection| collection.flows.len() * 128);
+    1024 + tags + collection + flow.description.as_ref().map_or(0, String::len)
+}
+
+impl EstimatedSize for FlowCreatedEvent {
+    fn estimated_size(&self) -> usize {
+        estimated_flow_size(&self.flow)
+    }
+}
+
+impl EstimatedSize for FlowUpdatedEvent {
+    fn estimated_size(&self) -> usize {
+        estimated_flow_size(&self.flow)
+    }
+}
+
+impl EstimatedSize for SegmentsAddedEvent {
+    fn estimated_size(&self) -> usize {
+        self.segments.iter().map(|segment| 512 + segment.get_urls.len() * 256).sum()
+    }
+}
+
+impl EstimatedSize for FlowDeletedEvent {}
+impl EstimatedSize for SourceDeletedEvent {}
+impl EstimatedSize for SegmentsDeletedEvent {}
+impl EstimatedSize for IngestPauseEvent {}
+impl EstimatedSize for BulkOperationCompletedEvent {}
+
+/// The flow an event concerns, if any; a flow's own webhook hears only of its flow
+pub trait EventSubject {
+    fn flow_id(&self) -> Option<Uuid> {
+        None
+    }
+}
+
+impl EventSubject for FlowCreatedEvent {
+    fn flow_id(&self) -> Option<Uuid> {
+        Some(self.flow.id)
+    }
+}
+
+impl EventSubject for FlowUpdatedEvent {
+    fn flow_id(&self) -> Option<Uuid> {
+        Some(self.flow.id)
+    }
+}
+
+impl EventSubject for FlowDeletedEvent {
+    fn flow_id(&self) -> Option<Uuid> {
+        Some(self.flow_id)
+    }
+}
+
+impl EventSubject for SegmentsAddedEvent {
+    fn flow_id(&self) -> Option<Uuid> {
+        Some(self.flow_id)
+    }
+}
+
+impl EventSubject for SegmentsDeletedEvent {
+    fn flow_id(&self) -> Option<Uuid> {
+        Some(self.flow_id)
+    }
+}
+
+impl EventSubject for SourceDeletedEvent {}
+impl EventSubject for IngestPauseEvent {}
+impl EventSubject for BulkOperationCompletedEvent {}
+
+/// Who may hear of an event, given the owner of the flow it concerns, if any
+fn event_audience<T: EventSubject>(event: &T, flow_owner: Option<&str>) -> EventAudience {
+    match event.flow_id() {
+        Some(_) => EventAudience::FlowOwner(flow_owner.map(str::to_string)),
+        None => EventAudience::Everyone,
+    }
+}
+
+/// Events a flow's own webhook (its `notify_url`) is subscribed to
+pub const FLOW_WEBHOOK_EVENTS: [EventType; 4] = [
+    EventType::FlowsUpdated,
+    EventType::FlowsDeleted,
+    EventType::FlowsSegmentsAdded,
+    EventType::FlowsSegmentsDeleted,
+];
+
+/// Where a flow's own webhook is kept, apart from global webhooks keyed by url
+fn flow_webhook_key(flow_id: &Uuid) -> String {
+    format!("flow:{}", flow_id)
+}
+
+tokio::task_local! {
+    /// Per event type counts of notifications withheld from the bulk operation
+    /// running on this task
+    static SUPPRESSED_EVENTS: RefCell<BTreeMap<String, u64>>;
+}
 
 #[derive(Clone)]
 pub struct WebhookInfo {
     pub webhook: Webhook,
     pub api_key_value: String,
+    pub previous_key: Option<PreviousWebhookKey>,
+    /// Set for a flow's own webhook, which only hears of events about that flow
+    pub flow_id: Option<Uuid>,
+    /// Set for a webhook a principal registered under `auth.enforce_ownership`,
+    /// which only hears of events about that principal's flows
+    pub owner: Option<String>,
+}
+
+impl WebhookInfo {
+    /// Signature headers for a delivery body at time `now`
+    pub fn signature_headers(&self, body: &[u8], now: DateTime<Utc>) -> Vec<(&'static str, String)> {
+        let mut headers = vec![(SIGNATURE_HEADER, sign_payload(&self.api_key_value, body))];
+        if let Some(previous) = &self.previous_key {
+            if previous.expires_at > now {
+                headers.push((PREVIOUS_SIGNATURE_HEADER, sign_payload(&previous.api_key_value, body)));
+            }
+        }
+        headers
+    }
+}
+
+fn sign_payload(key: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Deliveries to one webhook about one flow, or about no flow at all, are made
+/// one at a time in the order their events were sent, so a receiver never sees
+/// a flow's segments deleted before they were added. Other keys go out
+/// concurrently.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct DeliveryKey {
+    url: String,
+    flow_id: Option<Uuid>,
+}
+
+struct Delivery {
+    webhook_info: WebhookInfo,
+    body: Bytes,
+    done: oneshot::Sender<()>,
 }
 
+/// Resolves once a delivery has been attempted
+pub type DeliveryHandle = oneshot::Receiver<()>;
+
+/// The queue of each key with deliveries pending; a key's worker removes its
+/// queue once it has drained it
+type DeliveryQueues = Arc<Mutex<HashMap<DeliveryKey, mpsc::UnboundedSender<Delivery>>>>;
+
+/// Dispatches event notifications. Key values are held in their stored (possibly
+/// sealed) form and only decrypted when a delivery is built.
 pub struct WebhookManager {
     client: Client,
     webhooks: Arc<RwLock<HashMap<String, WebhookInfo>>>,
+    cipher: Option<SecretCipher>,
+    max_body_bytes: u64,
+    queues: DeliveryQueues,
+    /// Subscribers to `GET /service/events`, who get every notification too
+    events: EventStream,
+    clock: SharedClock,
 }
 
 impl WebhookManager {
     pub fn new() -> Self {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(30))
-            .build()
-            .expect("Failed to create HTTP client");
-
         Self {
-            client,
+            client: Self::delivery_client(std::time::Duration::from_secs(WebhookConfig::default().delivery_timeout_seconds)),
             webhooks: Arc::new(RwLock::new(HashMap::new())),
+            cipher: None,
+            max_body_bytes: WebhookConfig::default().max_body_bytes,
+            queues: DeliveryQueues::default(),
+            events: EventStream::new(EventStreamConfig::default().buffer_events),
+            clock: system_clock(),
+        }
+    }
+
+    /// Read the time for event timestamps, signatures and failure metrics from `clock`
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Client whose timeout bounds a whole delivery, from connecting until the
+    /// response body has been read
+    fn delivery_client(timeout: std::time::Duration) -> Client {
+        Client::builder()
+            .timeout(timeout)
+            .connect_timeout(CONNECT_TIMEOUT.min(timeout))
+            .build()
+            .expect("Failed to create HTTP client")
+    }
+
+    /// Longest a single delivery may take, response included
+    pub fn with_delivery_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.client = Self::delivery_client(timeout);
+        self
+    }
+
+    /// Largest delivery body; bigger notifications are sent in summary form.
+    /// Zero disables the limit.
+    pub fn with_max_body_bytes(mut self, max_body_bytes: u64) -> Self {
+        self.max_body_bytes = max_body_bytes;
+        self
+    }
+
+    /// Notifications held for event stream subscribers that haven't read them yet
+    pub fn with_event_buffer(mut self, buffer_events: usize) -> Self {
+        self.events = EventStream::new(buffer_events);
+        self
+    }
+
+    pub fn events(&self) -> &EventStream {
+        &self.events
+    }
+
+    /// Encrypt api key values at rest with the given cipher
+    pub fn with_cipher(mut self, cipher: Option<SecretCipher>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Convert a plaintext key into the form it is stored in
+    pub fn seal_key(&self, api_key_value: &str) -> TamsResult<String> {
+        match &self.cipher {
+            Some(cipher) => cipher.seal(api_key_value),
+            None => Ok(api_key_value.to_string()),
+        }
+    }
+
+    fn open_key(&self, stored: &str) -> TamsResult<String> {
+        match &self.cipher {
+            Some(cipher) if is_sealed(stored) => cipher.open(stored),
+            None if is_sealed(stored) => Err(TamsError::Internal(
+                "Webhook api key is encrypted but no encryption key is configured".to_string(),
+            )),
+            _ => Ok(stored.to_string()),
         }
     }
 
+    /// Copy of a webhook with its keys decrypted, for a single delivery
+    fn delivery_info(&self, info: &WebhookInfo) -> TamsResult<WebhookInfo> {
+        let previous_key = match &info.previous_key {
+            Some(previous) => Some(PreviousWebhookKey {
+                api_key_value: self.open_key(&previous.api_key_value)?,
+                expires_at: previous.expires_at,
+            }),
+            None => None,
+        };
+        Ok(WebhookInfo {
+            webhook: info.webhook.clone(),
+            api_key_value: self.open_key(&info.api_key_value)?,
+            previous_key,
+            flow_id: info.flow_id,
+            owner: info.owner.clone(),
+        })
+    }
+
     pub async fn add_webhook(&self, webhook: Webhook, api_key_value: String) {
         let mut webhooks = self.webhooks.write().await;
         webhooks.insert(
@@ -36,11 +312,75 @@ impl WebhookManager {
             WebhookInfo {
                 webhook,
                 api_key_value,
+                previous_key: None,
+                flow_id: None,
+                owner: None,
             },
         );
         info!("Added webhook: {}", webhooks.len());
     }
 
+    /// Insert or replace a webhook, including any previous key still in its overlap window
+    pub async fn upsert_stored_webhook(&self, stored: StoredWebhook) {
+        let mut webhooks = self.webhooks.write().await;
+        webhooks.insert(
+            stored.webhook.url.clone(),
+            WebhookInfo {
+                webhook: stored.webhook,
+                api_key_value: stored.api_key_value,
+                previous_key: stored.previous_key,
+                flow_id: None,
+                owner: stored.owner,
+            },
+        );
+    }
+
+    /// Attach or replace a flow's own webhook. `secret` is in its stored form
+    /// and signs the deliveries.
+    pub async fn set_flow_webhook(&self, flow_id: Uuid, url: String, secret: String) {
+        let webhook = Webhook {
+            id: None,
+            url,
+            api_key_name: None,
+            api_key_value: None,
+            events: FLOW_WEBHOOK_EVENTS.iter().map(|event| event.to_string()).collect(),
+        };
+        self.webhooks.write().await.insert(
+            flow_webhook_key(&flow_id),
+            WebhookInfo {
+                webhook,
+                api_key_value: secret,
+                previous_key: None,
+                flow_id: Some(flow_id),
+                owner: None,
+            },
+        );
+    }
+
+    pub async fn remove_flow_webhook(&self, flow_id: &Uuid) {
+        if self.webhooks.write().await.remove(&flow_webhook_key(flow_id)).is_some() {
+            info!("Removed webhook of flow {}", flow_id);
+        }
+    }
+
+    /// Make the flow's own webhook match its notify_url
+    pub async fn sync_flow_webhook(&self, flow: &Flow) {
+        match (&flow.notify_url, &flow.notify_secret) {
+            (Some(url), Some(secret)) => self.set_flow_webhook(flow.id, url.clone(), secret.clone()).await,
+            _ => self.remove_flow_webhook(&flow.id).await,
+        }
+    }
+
+    /// Forget previous keys whose overlap window has ended
+    pub async fn purge_expired_keys(&self, now: DateTime<Utc>) {
+        let mut webhooks = self.webhooks.write().await;
+        for info in webhooks.values_mut() {
+            if info.previous_key.as_ref().is_some_and(|key| key.expires_at <= now) {
+                info.previous_key = None;
+            }
+        }
+    }
+
     pub async fn remove_webhook(&self, url: &str) {
         let mut webhooks = self.webhooks.write().await;
         if webhooks.remove(url).is_some() {
@@ -48,66 +388,250 @@ impl WebhookManager {
         }
     }
 
+    /// Send an event that concerns no owned flow, or a flow without an owner
     pub async fn send_notification<T>(&self, notification: EventNotification<T>)
     where
-        T: serde::Serialize + Send + Sync,
+        T: serde::Serialize + EstimatedSize + EventSubject + Send + Sync + 'static,
     {
-        let webhooks = self.webhooks.read().await;
-        
-        for webhook_info in webhooks.values() {
-            if webhook_info.webhook.events.contains(&notification.event_type)
-                || webhook_info.webhook.events.contains(&"*".to_string())
-            {
-                let webhook_info = webhook_info.clone();
-                let notification_json = match serde_json::to_value(&notification) {
-                    Ok(json) => json,
-                    Err(e) => {
-                        error!("Failed to serialize notification: {}", e);
-                        continue;
-                    }
-                };
-                
-                let client = self.client.clone();
-                tokio::spawn(async move {
-                    if let Err(e) = Self::send_webhook_request(
-                        &client,
-                        &webhook_info,
-                        notification_json,
-                    ).await {
-                        error!("Failed to send webhook notification to {}: {}", 
-                               webhook_info.webhook.url, e);
+        self.send_flow_notification(None, notification).await
+    }
+
+    /// Send an event about a flow owned by `flow_owner`. Webhooks limited to an
+    /// owner only hear of that owner's flows.
+    pub async fn send_flow_notification<T>(&self, flow_owner: Option<&str>, notification: EventNotification<T>)
+    where
+        T: serde::Serialize + EstimatedSize + EventSubject + Send + Sync + 'static,
+    {
+        let suppressed = SUPPRESSED_EVENTS.try_with(|counts| {
+            *counts.borrow_mut().entry(notification.event_type.to_string()).or_default() += 1;
+        });
+        if suppressed.is_ok() {
+            return;
+        }
+        if self.events.has_subscribers() {
+            match serde_json::to_string(&notification) {
+                Ok(data) => {
+                    let audience = event_audience(&notification.event, flow_owner);
+                    self.events.publish(notification.event_type.as_str(), data, audience);
+                }
+                Err(e) => error!("Failed to serialize {} notification for the event stream: {}", notification.event_type, e),
+            }
+        }
+        self.dispatch(flow_owner, notification).await;
+    }
+
+    /// Run an administrative bulk operation (seeding, the maintenance repairs,
+    /// storage class moves) with its notifications suppressed.
+    ///
+    /// Events sent from the operation's task are only counted, and a single
+    /// `service/bulk_import_completed` summary is delivered once it finishes.
+    /// Nothing in a request can enter this scope; only code that calls it
+    /// directly is suppressed.
+    pub async fn suppress_events<F, T>(&self, operation: &str, fut: F) -> TamsResult<T>
+    where
+        F: Future<Output = TamsResult<T>>,
+    {
+        let (result, suppressed_events) = SUPPRESSED_EVENTS
+            .scope(RefCell::new(BTreeMap::new()), async {
+                let result = fut.await;
+                (result, SUPPRESSED_EVENTS.with(|counts| counts.take()))
+            })
+            .await;
+        let total_suppressed: u64 = suppressed_events.values().sum();
+
+        info!(
+            target: "audit",
+            operation,
+            succeeded = result.is_ok(),
+            events_suppressed = true,
+            total_suppressed,
+            "bulk operation completed"
+        );
+
+        let deliveries = self
+            .dispatch(None, EventNotification {
+                event_timestamp: self.clock.now(),
+                event_type: EventType::ServiceBulkImportCompleted,
+                event: BulkOperationCompletedEvent {
+                    operation: operation.to_string(),
+                    succeeded: result.is_ok(),
+                    total_suppressed,
+                    suppressed_events,
+                },
+            })
+            .await;
+        // Administrative commands may exit right after; let the summary go out first
+        for delivery in deliveries {
+            let _ = delivery.await;
+        }
+
+        result
+    }
+
+    /// Queue a delivery to every webhook subscribed to the event, behind any
+    /// earlier ones with the same [`DeliveryKey`]. The payload is serialized
+    /// once and shared by all of them. `flow_owner` owns the flow the event
+    /// concerns, if it concerns one.
+    async fn dispatch<T>(&self, flow_owner: Option<&str>, notification: EventNotification<T>) -> Vec<DeliveryHandle>
+    where
+        T: serde::Serialize + EstimatedSize + EventSubject + Send + Sync + 'static,
+    {
+        let audience = event_audience(&notification.event, flow_owner);
+        let subscribers: Vec<WebhookInfo> = self
+            .webhooks
+            .read()
+            .await
+            .values()
+            .filter(|info| {
+                info.webhook
+                    .events
+                    .iter()
+                    .any(|subscription| notification.event_type.matches_subscription(subscription))
+                    && info.flow_id.is_none_or(|flow_id| notification.event.flow_id() == Some(flow_id))
+                    && audience.includes(info.owner.as_deref())
+            })
+            .cloned()
+            .collect();
+        if subscribers.is_empty() {
+            return Vec::new();
+        }
+
+        let event_type = notification.event_type;
+        let flow_id = notification.event.flow_id();
+        let max_body_bytes = self.max_body_bytes;
+        let encoded = if notification.event.estimated_size() >= BLOCKING_SERIALIZE_BYTES {
+            tokio::task::spawn_blocking(move || encode_notification(&notification, max_body_bytes))
+                .await
+                .unwrap_or_else(|e| Err(TamsError::Internal(format!("Notification serialization panicked: {}", e))))
+        } else {
+            encode_notification(&notification, max_body_bytes)
+        };
+        let (body, truncated) = match encoded {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                error!("Failed to serialize {} notification: {}", event_type, e);
+                return Vec::new();
+            }
+        };
+
+        let mut deliveries = Vec::new();
+        for webhook_info in subscribers {
+            let webhook_info = match self.delivery_info(&webhook_info) {
+                Ok(info) => info,
+                Err(e) => {
+                    error!("Skipping webhook {}: {}", webhook_info.webhook.url, e);
+                    continue;
+                }
+            };
+            if truncated {
+                let total = metrics().record_webhook_truncation(&webhook_info.webhook.url);
+                warn!(
+                    "{} notification exceeded {} bytes; sending its summary to {} ({} truncated so far)",
+                    event_type, max_body_bytes, webhook_info.webhook.url, total
+                );
+            }
+
+            let key = DeliveryKey {
+                url: webhook_info.webhook.url.clone(),
+                flow_id,
+            };
+            let (done, handle) = oneshot::channel();
+            self.enqueue(key, Delivery { webhook_info, body: body.clone(), done });
+            deliveries.push(handle);
+        }
+
+        deliveries
+    }
+
+    /// Add a delivery to its key's queue, starting a worker for the key if it
+    /// has none
+    fn enqueue(&self, key: DeliveryKey, delivery: Delivery) {
+        metrics().webhook_deliveries_pending.fetch_add(1, Ordering::Relaxed);
+        let mut queues = self.queues.lock().unwrap_or_else(|e| e.into_inner());
+        let delivery = match queues.get(&key) {
+            Some(queue) => match queue.send(delivery) {
+                Ok(()) => return,
+                Err(mpsc::error::SendError(delivery)) => delivery,
+            },
+            None => delivery,
+        };
+        let (queue, pending) = mpsc::unbounded_channel();
+        let _ = queue.send(delivery);
+        queues.insert(key.clone(), queue);
+        tokio::spawn(Self::drain_queue(self.client.clone(), self.clock.clone(), self.queues.clone(), key, pending));
+    }
+
+    /// Make a key's deliveries one after another until its queue is empty
+    async fn drain_queue(
+        client: Client,
+        clock: SharedClock,
+        queues: DeliveryQueues,
+        key: DeliveryKey,
+        mut pending: mpsc::UnboundedReceiver<Delivery>,
+    ) {
+        loop {
+            let delivery = match pending.try_recv() {
+                Ok(delivery) => delivery,
+                Err(_) => {
+                    // enqueue sends under this lock, so nothing can slip in between
+                    let mut queues = queues.lock().unwrap_or_else(|e| e.into_inner());
+                    match pending.try_recv() {
+                        Ok(delivery) => delivery,
+                        Err(_) => {
+                            queues.remove(&key);
+                            return;
+                        }
                     }
-                });
+                }
+            };
+            if let Err(e) = Self::send_webhook_request(&client, &clock, &delivery.webhook_info, delivery.body).await {
+                error!("Failed to send webhook notification to {}: {}", delivery.webhook_info.webhook.url, e);
+                metrics().record_webhook_failure(clock.now());
             }
+            metrics().webhook_deliveries_pending.fetch_sub(1, Ordering::Relaxed);
+            let _ = delivery.done.send(());
         }
     }
 
     async fn send_webhook_request(
         client: &Client,
+        clock: &SharedClock,
         webhook_info: &WebhookInfo,
-        payload: serde_json::Value,
+        body: Bytes,
     ) -> TamsResult<()> {
         let mut request_builder = client
             .post(&webhook_info.webhook.url)
-            .json(&payload)
             .header("Content-Type", "application/json")
             .header("User-Agent", "TAMS-Rust/6.0");
 
+        for (name, value) in webhook_info.signature_headers(&body, clock.now()) {
+            request_builder = request_builder.header(name, value);
+        }
+        request_builder = request_builder.body(body);
+
         // Add API key header if specified
         if let Some(api_key_name) = &webhook_info.webhook.api_key_name {
             request_builder = request_builder.header(api_key_name, &webhook_info.api_key_value);
         }
 
-        let response = request_builder.send().await?;
+        let mut response = request_builder.send().await?;
+        let status = response.status();
+
+        // The body is never used, but reading a little of it lets the connection
+        // be reused; a receiver streaming more is cut off, one stalling times out
+        let mut read = 0;
+        while read < MAX_RESPONSE_BODY_BYTES {
+            match response.chunk().await? {
+                Some(chunk) => read += chunk.len(),
+                None => break,
+            }
+        }
 
-        if response.status().is_success() {
+        if status.is_success() {
             info!("Successfully sent webhook notification to {}", webhook_info.webhook.url);
         } else {
-            warn!(
-                "Webhook returned non-success status {}: {}",
-                response.status(),
-                webhook_info.webhook.url
-            );
+            warn!("Webhook returned non-success status {}: {}", status, webhook_info.webhook.url);
+            metrics().record_webhook_failure(clock.now());
         }
 
         Ok(())
@@ -128,20 +652,439 @@ impl WebhookManager {
                 WebhookInfo {
                     webhook,
                     api_key_value,
+                    previous_key: None,
+                    flow_id: None,
+                    owner: None,
                 },
             );
         }
         
         info!("Loaded {} webhooks from database", webhook_map.len());
     }
+
+    pub async fn load_stored_webhooks(&self, stored: Vec<StoredWebhook>) {
+        let count = stored.len();
+        self.webhooks.write().await.clear();
+        for webhook in stored {
+            self.upsert_stored_webhook(webhook).await;
+        }
+        info!("Loaded {} webhooks from database", count);
+    }
+}
+
+/// Serialize a notification body with RFC 3339 timestamps. Bodies over
+/// `max_body_bytes` are replaced by the summary form; the flag says whether that
+/// happened.
+fn encode_notification<T: serde::Serialize>(
+    notification: &EventNotification<T>,
+    max_body_bytes: u64,
+) -> TamsResult<(Bytes, bool)> {
+    time_utils::with_time_format_sync(TimeFormat::Rfc3339, || {
+        let body = serde_json::to_vec(notification)?;
+        if max_body_bytes == 0 || body.len() as u64 <= max_body_bytes {
+            return Ok((Bytes::from(body), false));
+        }
+        let mut summary = serde_json::to_value(notification)?;
+        if let Some(event) = summary.get_mut("event") {
+            *event = summarize_event(event);
+        }
+        summary["truncated"] = json!(true);
+        summary["original_size_bytes"] = json!(body.len());
+        Ok((Bytes::from(serde_json::to_vec(&summary)?), true))
+    })
+}
+
+/// Reduce an event to its ids, timeranges and scalar flags. Arrays become a
+/// `<name>_count`, plus a `<name>_timerange` covering their elements' timeranges
+/// when they have them; nested objects are reduced the same way.
+fn summarize_event(event: &Value) -> Value {
+    let Value::Object(fields) = event else {
+        return event.clone();
+    };
+    let mut summary = Map::new();
+    for (key, value) in fields {
+        match value {
+            Value::Array(items) => {
+                summary.insert(format!("{}_count", key), json!(items.len()));
+                let timeranges: Vec<TimeRange> = items
+                    .iter()
+                    .filter_map(|item| item.get("timerange")?.as_str())
+                    .filter_map(|stored| time_utils::parse_segment_timerange(stored).ok())
+                    .collect();
+                if let Ok(Some(covering)) = time_utils::covering_timerange(&timeranges) {
+                    summary.insert(format!("{}_timerange", key), json!(covering));
+                }
+            }
+            Value::Object(_) if key.ends_with("timerange") => {
+                summary.insert(key.clone(), value.clone());
+            }
+            Value::Object(_) => {
+                let nested = summarize_event(value);
+                if nested.as_object().is_some_and(|nested| !nested.is_empty()) {
+                    summary.insert(key.clone(), nested);
+                }
+            }
+            Value::String(_) if key == "id" || key.ends_with("_id") || key.ends_with("timerange") => {
+                summary.insert(key.clone(), value.clone());
+            }
+            Value::Bool(_) | Value::Number(_) => {
+                summary.insert(key.clone(), value.clone());
+            }
+            _ => {}
+        }
+    }
+    Value::Object(summary)
+}
+
+/// Startup migration for stored webhook keys: encrypts any plaintext values when a
+/// cipher is configured, and refuses to start when sealed values exist without one.
+/// Returns the number of webhooks rewritten.
+pub async fn seal_stored_webhook_keys(database: &Database, cipher: Option<&SecretCipher>) -> TamsResult<usize> {
+    let rows = database.get_webhook_key_values().await?;
+
+    let Some(cipher) = cipher else {
+        if rows.iter().any(|(_, current, previous)| {
+            current.as_deref().is_some_and(is_sealed) || previous.as_deref().is_some_and(is_sealed)
+        }) {
+            return Err(TamsError::Config(config::ConfigError::Message(
+                "Encrypted webhook api keys exist but webhooks.encryption_key is not configured".to_string(),
+            )));
+        }
+        if rows.iter().any(|(_, current, _)| current.is_some()) {
+            warn!("Webhook api keys are stored in plaintext; configure webhooks.encryption_key to encrypt them");
+        }
+        return Ok(0);
+    };
+
+    let seal = |value: Option<String>| -> TamsResult<(Option<String>, bool)> {
+        match value {
+            Some(v) if !is_sealed(&v) => Ok((Some(cipher.seal(&v)?), true)),
+            other => Ok((other, false)),
+        }
+    };
+
+    let mut rewritten = 0;
+    for (url, current, previous) in rows {
+        let (current, current_changed) = seal(current)?;
+        let (previous, previous_changed) = seal(previous)?;
+        if current_changed || previous_changed {
+            database
+                .set_webhook_key_values(&url, current.as_deref(), previous.as_deref())
+                .await?;
+            rewritten += 1;
+        }
+    }
+    if rewritten > 0 {
+        info!("Encrypted api keys for {} webhooks", rewritten);
+    }
+    Ok(rewritten)
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use axum::{extract::State, routing::post, Json, Router};
     use chrono::Utc;
+    use std::sync::Mutex;
     use uuid::Uuid;
 
+    pub(crate) type ReceivedEvents = Arc<Mutex<Vec<Value>>>;
+
+    /// Start a local HTTP endpoint that records every delivery posted to it,
+    /// returning its URL and the recorded bodies
+    pub(crate) async fn spawn_webhook_receiver() -> (String, ReceivedEvents) {
+        let received = ReceivedEvents::default();
+        let receiver = Router::new()
+            .route(
+                "/hook",
+                post(|State(received): State<ReceivedEvents>, Json(body): Json<Value>| async move {
+                    received.lock().unwrap().push(body);
+                }),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, receiver).await });
+        (url, received)
+    }
+
+    fn subscriber(url: String, events: &[&str]) -> Webhook {
+        Webhook {
+            id: None,
+            url,
+            api_key_name: None,
+            api_key_value: None,
+            events: events.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_canonical_and_legacy_subscriptions_receive_events() {
+        let (canonical_url, canonical) = spawn_webhook_receiver().await;
+        let (legacy_url, legacy) = spawn_webhook_receiver().await;
+        let (other_url, other) = spawn_webhook_receiver().await;
+        let manager = WebhookManager::new();
+        manager.add_webhook(subscriber(canonical_url, &["flows/deleted"]), "key".to_string()).await;
+        manager.add_webhook(subscriber(legacy_url, &["flow.deleted"]), "key".to_string()).await;
+        manager.add_webhook(subscriber(other_url, &["flows/created"]), "key".to_string()).await;
+
+        let flow_id = Uuid::new_v4();
+        let deliveries = manager
+            .dispatch(None, EventNotification {
+                event_timestamp: Utc::now(),
+                event_type: EventType::FlowsDeleted,
+                event: FlowDeletedEvent { flow_id },
+            })
+            .await;
+        assert_eq!(deliveries.len(), 2);
+        for delivery in deliveries {
+            delivery.await.unwrap();
+        }
+
+        for received in [canonical, legacy] {
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 1);
+            assert_eq!(received[0]["event_type"], "flows/deleted");
+            assert_eq!(received[0]["event"]["flow_id"], flow_id.to_string());
+        }
+        assert!(other.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_owned_webhooks_only_hear_of_their_owners_flows() {
+        let (owned_url, owned) = spawn_webhook_receiver().await;
+        let (global_url, global) = spawn_webhook_receiver().await;
+        let manager = WebhookManager::new();
+        manager.upsert_stored_webhook(StoredWebhook {
+            webhook: subscriber(owned_url, &["*"]),
+            api_key_value: "key".to_string(),
+            previous_key: None,
+            owner: Some("team-a".to_string()),
+        }).await;
+        manager.add_webhook(subscriber(global_url, &["*"]), "key".to_string()).await;
+
+        let (own_flow, other_flow) = (Uuid::new_v4(), Uuid::new_v4());
+        for (owner, flow_id) in [(Some("team-b"), other_flow), (None, other_flow), (Some("team-a"), own_flow)] {
+            let deliveries = manager
+                .dispatch(owner, EventNotification {
+                    event_timestamp: Utc::now(),
+                    event_type: EventType::FlowsDeleted,
+                    event: FlowDeletedEvent { flow_id },
+                })
+                .await;
+            for delivery in deliveries {
+                delivery.await.unwrap();
+            }
+        }
+        // Events about no flow reach every webhook
+        let deliveries = manager
+            .dispatch(None, EventNotification {
+                event_timestamp: Utc::now(),
+                event_type: EventType::SourcesDeleted,
+                event: SourceDeletedEvent { source_id: Uuid::new_v4() },
+            })
+            .await;
+        assert_eq!(deliveries.len(), 2);
+        for delivery in deliveries {
+            delivery.await.unwrap();
+        }
+
+        let owned = owned.lock().unwrap();
+        let types: Vec<&str> = owned.iter().map(|body| body["event_type"].as_str().unwrap()).collect();
+        assert_eq!(types, ["flows/deleted", "sources/deleted"]);
+        assert_eq!(owned[0]["event"]["flow_id"], own_flow.to_string());
+        assert_eq!(global.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_abusive_receivers_cannot_hold_deliveries() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // One receiver stalls after its headers, the other streams without end
+        let spawn_receiver = |endless: bool| async move {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/hook", listener.local_addr().unwrap());
+            tokio::spawn(async move {
+                while let Ok((mut socket, _)) = listener.accept().await {
+                    tokio::spawn(async move {
+                        let mut request = [0u8; 8192];
+                        let _ = socket.read(&mut request).await;
+                        let _ = socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1073741824\r\n\r\n").await;
+                        let chunk = [b'x'; 16384];
+                        while endless && socket.write_all(&chunk).await.is_ok() {}
+                        tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+                    });
+                }
+            });
+            url
+        };
+        let manager = WebhookManager::new().with_delivery_timeout(std::time::Duration::from_millis(500));
+        manager.add_webhook(subscriber(spawn_receiver(false).await, &["*"]), "key".to_string()).await;
+        manager.add_webhook(subscriber(spawn_receiver(true).await, &["*"]), "key".to_string()).await;
+
+        let started = std::time::Instant::now();
+        let deliveries = manager
+            .dispatch(None, EventNotification {
+                event_timestamp: Utc::now(),
+                event_type: EventType::FlowsDeleted,
+                event: FlowDeletedEvent { flow_id: Uuid::new_v4() },
+            })
+            .await;
+        assert_eq!(deliveries.len(), 2);
+        for delivery in deliveries {
+            delivery.await.unwrap();
+        }
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_deliveries_keep_event_order_per_flow() {
+        // Additions take the receiver longer, so unordered deliveries would let
+        // a deletion overtake the addition sent before it
+        let received = ReceivedEvents::default();
+        let receiver = Router::new()
+            .route(
+                "/hook",
+                post(|State(received): State<ReceivedEvents>, Json(body): Json<Value>| async move {
+                    if body["event_type"] == "flows/segments_added" {
+                        tokio::time::sleep(std::time::Duration::from_millis(40)).await;
+                    }
+                    received.lock().unwrap().push(body);
+                }),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, receiver).await });
+
+        let manager = WebhookManager::new();
+        manager.add_webhook(subscriber(url, &["*"]), "key".to_string()).await;
+        let flows = [Uuid::new_v4(), Uuid::new_v4()];
+        let mut deliveries = Vec::new();
+        for round in 0..3 {
+            for flow_id in flows {
+                let timerange = TimeRange::new(&format!("{}:0", round), Some(&format!("{}:0", round + 1)));
+                deliveries.extend(
+                    manager
+                        .dispatch(None, EventNotification {
+                            event_timestamp: Utc::now(),
+                            event_type: EventType::FlowsSegmentsAdded,
+                            event: SegmentsAddedEvent { flow_id, segments: Vec::new() },
+                        })
+                        .await,
+                );
+                deliveries.extend(
+                    manager
+                        .dispatch(None, EventNotification {
+                            event_timestamp: Utc::now(),
+                            event_type: EventType::FlowsSegmentsDeleted,
+                            event: SegmentsDeletedEvent { flow_id, timerange },
+                        })
+                        .await,
+                );
+            }
+        }
+        for delivery in deliveries {
+            delivery.await.unwrap();
+        }
+
+        // Each flow's queue is dropped once its worker has drained it
+        for _ in 0..100 {
+            if manager.queues.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(manager.queues.lock().unwrap().is_empty());
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 12);
+        for flow_id in flows {
+            let events: Vec<&str> = received
+                .iter()
+                .filter(|body| body["event"]["flow_id"] == flow_id.to_string())
+                .map(|body| body["event_type"].as_str().unwrap())
+                .collect();
+            assert_eq!(events, ["flows/segments_added", "flows/segments_deleted"].repeat(3));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oversized_payloads_are_sent_as_summaries() {
+        let (url, received) = spawn_webhook_receiver().await;
+        let manager = WebhookManager::new().with_max_body_bytes(4096);
+        manager.add_webhook(subscriber(url.clone(), &["*"]), "key".to_string()).await;
+
+        let flow_id = Uuid::new_v4();
+        let segments: Vec<FlowSegment> = (0..1000)
+            .map(|i| {
+                CreateSegmentRequest::new(&format!("object-{}", i), &format!("{}:0", i + 10), &format!("{}:0", i + 11))
+                .into_segment(flow_id)
+            })
+            .collect();
+        let mut flow = Flow::new(flow_id, ContentFormat::Video);
+        flow.tags = (0..200).map(|i| (format!("tag-{}", i), "x".repeat(64))).collect();
+
+        let deliveries = [
+            manager
+                .dispatch(None, EventNotification {
+                    event_timestamp: Utc::now(),
+                    event_type: EventType::FlowsSegmentsAdded,
+                    event: SegmentsAddedEvent { flow_id, segments },
+                })
+                .await,
+            manager
+                .dispatch(None, EventNotification {
+                    event_timestamp: Utc::now(),
+                    event_type: EventType::FlowsCreated,
+                    event: FlowCreatedEvent { flow },
+                })
+                .await,
+            manager
+                .dispatch(None, EventNotification {
+                    event_timestamp: Utc::now(),
+                    event_type: EventType::FlowsDeleted,
+                    event: FlowDeletedEvent { flow_id },
+                })
+                .await,
+        ];
+        for delivery in deliveries.into_iter().flatten() {
+            delivery.await.unwrap();
+        }
+
+        let received = received.lock().unwrap();
+        let by_type = |event_type: &str| received.iter().find(|body| body["event_type"] == event_type).unwrap();
+
+        let segments_added = by_type("flows/segments_added");
+        assert_eq!(segments_added["truncated"], true);
+        assert!(segments_added["original_size_bytes"].as_u64().unwrap() > 4096);
+        assert_eq!(segments_added["event"]["flow_id"], flow_id.to_string());
+        assert_eq!(segments_added["event"]["segments_count"], 1000);
+        assert_eq!(segments_added["event"]["segments_timerange"]["start"], "10:0");
+        assert_eq!(segments_added["event"]["segments_timerange"]["end"], "1010:0");
+
+        let created = by_type("flows/created");
+        assert_eq!(created["truncated"], true);
+        assert_eq!(created["event"]["flow"]["id"], flow_id.to_string());
+        assert!(created["event"]["flow"].get("tags").is_none());
+
+        assert!(by_type("flows/deleted").get("truncated").is_none());
+        assert_eq!(metrics().webhook_truncations(&url), 2);
+    }
+
+    #[test]
+    fn test_event_type_names() {
+        for event in EventType::ALL {
+            assert_eq!(event.to_string().parse::<EventType>().unwrap(), event);
+            assert_eq!(serde_json::to_value(event).unwrap(), event.as_str());
+        }
+        assert_eq!("flow.created".parse::<EventType>().unwrap(), EventType::FlowsCreated);
+        assert_eq!("source.deleted".parse::<EventType>().unwrap(), EventType::SourcesDeleted);
+        assert!("flows/renamed".parse::<EventType>().is_err());
+        assert!(EventType::FlowsUpdated.matches_subscription("*"));
+        assert!(!EventType::FlowsUpdated.matches_subscription("flows/created"));
+    }
+
     #[tokio::test]
     async fn test_webhook_manager_creation() {
         let manager = WebhookManager::new();
@@ -153,6 +1096,7 @@ mod tests {
         let manager = WebhookManager::new();
         
         let webhook = Webhook {
+            id: None,
             url: "https://example.com/webhook".to_string(),
             api_key_name: Some("X-API-Key".to_string()),
             api_key_value: None,
@@ -171,6 +1115,7 @@ mod tests {
         let manager = WebhookManager::new();
         
         let webhook1 = Webhook {
+            id: None,
             url: "https://example.com/webhook1".to_string(),
             api_key_name: None,
             api_key_value: None,
@@ -178,6 +1123,7 @@ mod tests {
         };
         
         let webhook2 = Webhook {
+            id: None,
             url: "https://example.com/webhook2".to_string(),
             api_key_name: Some("Authorization".to_string()),
             api_key_value: None,
@@ -192,4 +1138,101 @@ mod tests {
         manager.load_webhooks_from_database(webhooks).await;
         assert_eq!(manager.get_webhook_count().await, 2);
     }
-} 
\ No newline at end of file
+
+    fn rotated_info(expires_at: DateTime<Utc>) -> WebhookInfo {
+        WebhookInfo {
+            webhook: Webhook {
+                id: Some(Uuid::new_v4()),
+                url: "https://example.com/rotating".to_string(),
+                api_key_name: None,
+                api_key_value: None,
+                events: vec!["*".to_string()],
+            },
+            api_key_value: "new-key".to_string(),
+            previous_key: Some(PreviousWebhookKey {
+                api_key_value: "old-key".to_string(),
+                expires_at,
+            }),
+            flow_id: None,
+            owner: None,
+        }
+    }
+
+    #[test]
+    fn test_signatures_during_overlap_window() {
+        let now = Utc::now();
+        let info = rotated_info(now + chrono::Duration::hours(1));
+        let body = br#"{"event_type":"flows/deleted"}"#;
+
+        let headers = info.signature_headers(body, now);
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[0], (SIGNATURE_HEADER, sign_payload("new-key", body)));
+        assert_eq!(headers[1], (PREVIOUS_SIGNATURE_HEADER, sign_payload("old-key", body)));
+        assert_ne!(headers[0].1, headers[1].1);
+        assert!(headers[0].1.starts_with("sha256="));
+    }
+
+    #[tokio::test]
+    async fn test_signatures_after_overlap_window() {
+        let now = Utc::now();
+        let info = rotated_info(now - chrono::Duration::seconds(1));
+        let body = b"{}";
+
+        let headers = info.signature_headers(body, now);
+        assert_eq!(headers, vec![(SIGNATURE_HEADER, sign_payload("new-key", body))]);
+
+        let manager = WebhookManager::new();
+        manager.upsert_stored_webhook(StoredWebhook {
+            webhook: info.webhook.clone(),
+            api_key_value: info.api_key_value.clone(),
+            previous_key: info.previous_key.clone(),
+            owner: None,
+        }).await;
+        manager.purge_expired_keys(now).await;
+        let webhooks = manager.webhooks.read().await;
+        assert!(webhooks[&info.webhook.url].previous_key.is_none());
+    }
+
+    #[test]
+    fn test_delivery_info_decrypts_sealed_keys() {
+        let manager = WebhookManager::new().with_cipher(Some(SecretCipher::new(&[9u8; 32])));
+        let mut info = rotated_info(Utc::now() + chrono::Duration::hours(1));
+        info.api_key_value = manager.seal_key("new-key").unwrap();
+        info.previous_key.as_mut().unwrap().api_key_value = manager.seal_key("old-key").unwrap();
+        assert!(is_sealed(&info.api_key_value));
+
+        let delivery = manager.delivery_info(&info).unwrap();
+        assert_eq!(delivery.api_key_value, "new-key");
+        assert_eq!(delivery.previous_key.unwrap().api_key_value, "old-key");
+
+        // Sealed keys cannot be used without the cipher
+        assert!(WebhookManager::new().delivery_info(&info).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_seal_stored_webhook_keys_migration() {
+        let (database, _temp_dir) = crate::database::tests::create_test_database().await;
+        database.create_webhook(&Webhook {
+            id: Some(Uuid::new_v4()),
+            url: "https://example.com/plain".to_string(),
+            api_key_name: Some("X-Api-Key".to_string()),
+            api_key_value: Some("plaintext-key".to_string()),
+            events: vec!["*".to_string()],
+        }, None).await.unwrap();
+
+        // Plaintext rows are tolerated without a cipher
+        assert_eq!(seal_stored_webhook_keys(&database, None).await.unwrap(), 0);
+
+        let cipher = SecretCipher::new(&[3u8; 32]);
+        assert_eq!(seal_stored_webhook_keys(&database, Some(&cipher)).await.unwrap(), 1);
+        assert_eq!(seal_stored_webhook_keys(&database, Some(&cipher)).await.unwrap(), 0);
+
+        let (_, stored, _) = database.get_webhook_key_values().await.unwrap().remove(0);
+        let stored = stored.unwrap();
+        assert!(is_sealed(&stored));
+        assert_eq!(cipher.open(&stored).unwrap(), "plaintext-key");
+
+        // Sealed rows without a configured key are a startup error
+        assert!(seal_stored_webhook_keys(&database, None).await.is_err());
+    }
+}